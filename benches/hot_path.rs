@@ -0,0 +1,112 @@
+/* Hot Path Benchmark
+ *
+ * Times loop iterations dispatching 64 ready fds and a timer on a mock
+ * backend, first on a plain loop, then with every instrumentation knob
+ * turned on: rusage sampled each iteration, a flight recorder and a
+ * timer budget. Rounds of both alternate and the best of each is kept.
+ *
+ * Without the `instrumentation` feature the knobs are inert and their
+ * hooks compile to nothing, so both loops must run the same hot path:
+ * the benchmark fails if the instrumented one is measurably slower.
+ *
+ *     cargo bench --bench hot_path --no-default-features
+ */
+
+use rae::test_util::{MockBackend, MockControl};
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_READABLE, AeEventLoop, AeEventLoopBuilder, AeOverrunPolicy,
+    ae_create_file_event, ae_create_time_event, ae_delete_event_loop, ae_process_events,
+    ae_set_flight_recorder, ae_set_time_event_budget,
+};
+use std::ffi::c_void;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const FDS: i32 = 64;
+const ITERATIONS: u32 = 20_000;
+const ROUNDS: u32 = 25;
+/* Slack for scheduling noise between rounds. */
+const TOLERANCE: f64 = 1.25;
+
+fn noop_read(_event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    black_box((fd, client_data));
+}
+
+/* Due again at every iteration. */
+fn tick(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    0
+}
+
+fn build(instrumented: bool) -> (Box<AeEventLoop>, MockControl) {
+    let (backend, control) = MockBackend::new();
+    let mut builder = AeEventLoopBuilder::new(1024).backend(backend);
+    if instrumented {
+        builder = builder.rusage_sample_interval(1);
+    }
+    let mut event_loop = builder.build().expect("Failed to create event loop");
+    for fd in 0..FDS {
+        ae_create_file_event(
+            &mut event_loop,
+            fd,
+            AE_READABLE,
+            noop_read,
+            std::ptr::null_mut(),
+        );
+        control.set_ready(fd, AE_READABLE);
+    }
+    let id = ae_create_time_event(&mut event_loop, 0, tick, std::ptr::null_mut(), None);
+    if instrumented {
+        /* Refused without the feature, which is the point. */
+        ae_set_flight_recorder(&mut event_loop, 1024);
+        ae_set_time_event_budget(&mut event_loop, id, 1_000_000, AeOverrunPolicy::Record);
+    }
+    (event_loop, control)
+}
+
+fn round(event_loop: &mut AeEventLoop) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT));
+    }
+    start.elapsed()
+}
+
+fn per_iteration(elapsed: Duration) -> f64 {
+    elapsed.as_nanos() as f64 / ITERATIONS as f64
+}
+
+fn main() {
+    let (mut plain, _plain_control) = build(false);
+    let (mut instrumented, _instrumented_control) = build(true);
+    /* Warm up caches and the fired buffers. */
+    round(&mut plain);
+    round(&mut instrumented);
+
+    let mut best_plain = Duration::MAX;
+    let mut best_instrumented = Duration::MAX;
+    for _ in 0..ROUNDS {
+        best_plain = best_plain.min(round(&mut plain));
+        best_instrumented = best_instrumented.min(round(&mut instrumented));
+    }
+    ae_delete_event_loop(plain);
+    ae_delete_event_loop(instrumented);
+
+    let plain_ns = per_iteration(best_plain);
+    let instrumented_ns = per_iteration(best_instrumented);
+    let ratio = instrumented_ns / plain_ns;
+    println!(
+        "hot_path: {} fds + 1 timer, plain {:.0} ns/iteration, instrumented {:.0} ns/iteration ({:+.1}%)",
+        FDS,
+        plain_ns,
+        instrumented_ns,
+        (ratio - 1.0) * 100.0
+    );
+
+    if !cfg!(feature = "instrumentation") {
+        assert!(
+            ratio <= TOLERANCE,
+            "Instrumentation is compiled out, yet the knobs cost {:+.1}%",
+            (ratio - 1.0) * 100.0
+        );
+    }
+}
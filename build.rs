@@ -0,0 +1,46 @@
+/* Build script.
+ *
+ * Only does something with the ffi-compat-tests feature: the original
+ * Redis ae.c is compiled (with the select backend) so that
+ * tests/ffi_compat_tests.rs can run the same scenarios against both
+ * implementations. RAE_REDIS_SRC must point to the src/ directory of a
+ * Redis 6.2 or later checkout.
+ */
+
+fn main() {
+    #[cfg(feature = "ffi-compat-tests")]
+    build_redis_ae();
+}
+
+#[cfg(feature = "ffi-compat-tests")]
+fn build_redis_ae() {
+    use std::path::PathBuf;
+
+    println!("cargo:rerun-if-env-changed=RAE_REDIS_SRC");
+    println!("cargo:rerun-if-changed=tests/ffi_compat/shim");
+    let src = match std::env::var_os("RAE_REDIS_SRC") {
+        Some(src) => PathBuf::from(src),
+        None => panic!("ffi-compat-tests: set RAE_REDIS_SRC to the src/ directory of Redis"),
+    };
+
+    /* ae.c includes zmalloc.h, config.h, ... with quotes, which are looked
+     * up next to it first: copy it away from the Redis headers so the
+     * shims are picked instead. */
+    let out = PathBuf::from(std::env::var_os("OUT_DIR").unwrap()).join("redis-ae");
+    std::fs::create_dir_all(&out).unwrap();
+    for file in ["ae.c", "ae.h", "ae_select.c"] {
+        let from = src.join(file);
+        println!("cargo:rerun-if-changed={}", from.display());
+        if let Err(err) = std::fs::copy(&from, out.join(file)) {
+            panic!("ffi-compat-tests: cannot copy {}: {err}", from.display());
+        }
+    }
+
+    cc::Build::new()
+        .file(out.join("ae.c"))
+        .file("tests/ffi_compat/shim/monotonic.c")
+        .include("tests/ffi_compat/shim")
+        .include(&out)
+        .warnings(false)
+        .compile("redis_ae");
+}
@@ -0,0 +1,172 @@
+/* Registration state machine fuzzer.
+ *
+ * Drives random sequences of file event creations, deletions, set size
+ * changes and loop iterations against a loop on the mock backend, with
+ * callbacks themselves creating and deleting events while the fired
+ * array is being walked. After every step the loop bookkeeping must be
+ * consistent:
+ *
+ * - no fd above maxfd, or beyond the set size, has a non-empty mask;
+ * - the backend watches exactly the AE_READABLE / AE_WRITABLE bits the
+ *   loop has registered (nothing leaks after a delete or a failed add);
+ * - a successful create / delete is reflected in the mask of the fd.
+ *
+ *     cargo +nightly fuzz run registration
+ */
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rae::test_util::{MockBackend, MockControl};
+use rae::{
+    AE_BARRIER, AE_DONT_WAIT, AE_ERR, AE_FILE_EVENTS, AE_NONE, AE_OK, AE_READABLE,
+    AE_TIME_EVENTS, AE_WRITABLE, AeEventLoop, AeEventLoopBuilder, ae_create_file_event,
+    ae_delete_file_event, ae_get_file_events, ae_get_set_size, ae_process_events,
+    ae_resize_set_size,
+};
+use std::collections::VecDeque;
+use std::ffi::c_void;
+
+/* fds stay small so that operations keep hitting the same slots and the
+ * set size boundary. */
+const MAX_FD: u8 = 80;
+
+#[derive(Arbitrary, Debug, Clone)]
+enum Op {
+    Create { fd: u8, mask: u8 },
+    Delete { fd: u8, mask: u8 },
+    Resize { setsize: u8 },
+    Ready { fd: u8, mask: u8 },
+    Unready { fd: u8, mask: u8 },
+    FailNextAdd,
+    Interrupt,
+    Process { time_events: bool },
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    initial_setsize: u8,
+    ops: Vec<Op>,
+    /* Run one by one by the file procs, as they get called. */
+    callback_ops: Vec<Op>,
+}
+
+struct Context {
+    control: MockControl,
+    callback_ops: VecDeque<Op>,
+}
+
+fn fd_of(fd: u8) -> i32 {
+    (fd % (MAX_FD + 1)) as i32
+}
+
+fn mask_of(mask: u8) -> i32 {
+    mask as i32 & (AE_READABLE | AE_WRITABLE | AE_BARRIER)
+}
+
+fn file_proc(event_loop: &mut AeEventLoop, _fd: i32, client_data: *mut c_void, _mask: i32) {
+    let ctx = unsafe { &mut *(client_data as *mut Context) };
+    if let Some(op) = ctx.callback_ops.pop_front() {
+        /* No nested iterations. */
+        if !matches!(op, Op::Process { .. }) {
+            apply(event_loop, ctx, op);
+        }
+    }
+}
+
+fn apply(event_loop: &mut AeEventLoop, ctx: &mut Context, op: Op) {
+    let ctx_ptr = ctx as *mut Context as *mut c_void;
+    match op {
+        Op::Create { fd, mask } => {
+            let (fd, mask) = (fd_of(fd), mask_of(mask));
+            let before = ae_get_file_events(event_loop, fd);
+            let ret = ae_create_file_event(event_loop, fd, mask, file_proc, ctx_ptr);
+            let after = ae_get_file_events(event_loop, fd);
+            if ret == AE_OK {
+                assert_eq!(after, before | mask, "create fd {fd} mask {mask}");
+            } else {
+                assert_eq!(ret, AE_ERR);
+                assert_eq!(after, before, "failed create changed fd {fd}");
+            }
+        }
+        Op::Delete { fd, mask } => {
+            let (fd, mask) = (fd_of(fd), mask_of(mask));
+            ae_delete_file_event(event_loop, fd, mask);
+            let after = ae_get_file_events(event_loop, fd);
+            assert_eq!(after & mask, AE_NONE, "delete fd {fd} mask {mask}");
+            if mask & AE_WRITABLE != 0 {
+                assert_eq!(after & AE_BARRIER, AE_NONE, "barrier kept on fd {fd}");
+            }
+        }
+        Op::Resize { setsize } => {
+            let setsize = setsize as i32;
+            let maxfd = event_loop.maxfd;
+            let old = ae_get_set_size(event_loop);
+            let ret = ae_resize_set_size(event_loop, setsize);
+            if setsize != old && maxfd >= setsize {
+                assert_eq!(ret, AE_ERR, "shrunk below maxfd {maxfd}");
+                assert_eq!(ae_get_set_size(event_loop), old);
+            } else {
+                assert_eq!(ret, AE_OK);
+                assert_eq!(ae_get_set_size(event_loop), setsize);
+            }
+        }
+        Op::Ready { fd, mask } => ctx.control.set_ready(fd_of(fd), mask_of(mask)),
+        Op::Unready { fd, mask } => ctx.control.clear_ready(fd_of(fd), mask_of(mask)),
+        Op::FailNextAdd => ctx.control.fail_next_add(),
+        Op::Interrupt => ctx.control.interrupt_next_poll(),
+        Op::Process { time_events } => {
+            /* The mock never blocks, AE_DONT_WAIT keeps the loop from
+             * asking it to with no fd registered. */
+            let mut flags = AE_FILE_EVENTS | AE_DONT_WAIT;
+            if time_events {
+                flags |= AE_TIME_EVENTS;
+            }
+            assert!(ae_process_events(event_loop, flags) >= 0);
+        }
+    }
+}
+
+fn check_invariants(event_loop: &AeEventLoop, control: &MockControl) {
+    let setsize = ae_get_set_size(event_loop);
+    let mut highest = -1;
+    for fd in 0..=MAX_FD as i32 {
+        let mask = ae_get_file_events(event_loop, fd);
+        if fd >= setsize {
+            assert_eq!(mask, AE_NONE, "fd {fd} registered beyond set size {setsize}");
+        }
+        if mask != AE_NONE {
+            highest = fd;
+        }
+        assert_eq!(
+            control.interest(fd),
+            mask & (AE_READABLE | AE_WRITABLE),
+            "backend interest of fd {fd} does not match the loop"
+        );
+    }
+    /* Creating an event with an empty mask raises maxfd without
+     * registering anything, as in ae.c: maxfd is only an upper bound
+     * until a delete recomputes it. */
+    assert!(event_loop.maxfd >= highest, "maxfd {} below fd {highest}", event_loop.maxfd);
+    assert!(event_loop.maxfd < setsize.max(0), "maxfd {} beyond set size", event_loop.maxfd);
+}
+
+fuzz_target!(|input: Input| {
+    let (backend, control) = MockBackend::new();
+    let setsize = (input.initial_setsize % (MAX_FD + 8)) as i32 + 1;
+    let mut event_loop = match AeEventLoopBuilder::new(setsize).backend(backend).build() {
+        Some(event_loop) => event_loop,
+        None => return,
+    };
+    let mut ctx = Box::new(Context {
+        control: control.clone(),
+        callback_ops: input.callback_ops.into(),
+    });
+
+    for op in input.ops {
+        apply(&mut event_loop, &mut ctx, op);
+        check_invariants(&event_loop, &control);
+    }
+    drop(event_loop);
+});
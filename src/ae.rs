@@ -8,18 +8,122 @@
  * Rust port of Redis ae.c
  */
 
+pub mod admin;
+pub mod backoff;
+pub mod bufpool;
+pub mod builder;
+pub mod child;
+pub mod config;
+pub mod conn;
+pub mod context;
+pub mod cron;
+pub mod custom;
+pub mod differential;
+pub mod dispatch;
+pub mod doctor;
+pub mod drain;
+pub mod external;
+pub mod fileio;
+pub mod flags;
+pub mod flight;
+pub mod fork;
+pub mod framing;
+#[cfg(feature = "glib")]
+pub mod glib;
+pub mod group;
+pub mod group_commit;
+pub mod handle;
+pub mod heartbeat;
+pub mod idle;
+pub mod invariants;
+pub mod job;
+pub mod keepalive;
+pub mod lifecycle;
+pub mod limits;
+pub mod memory;
+pub mod migrate;
+pub mod module;
+pub mod net;
+#[cfg(target_os = "linux")]
+pub mod netlink;
+pub mod panic;
+pub mod probe;
+pub mod proxy;
+pub mod proxy_header;
+#[cfg(feature = "async-io")]
+pub mod reactor;
+pub mod rearm;
+pub mod reconfigure;
+pub mod registry;
+pub mod reload;
+#[cfg(feature = "resp")]
+pub mod resp;
+pub mod runtime;
+pub mod scope;
+pub mod selftest;
+pub mod shared;
+pub mod shutdown;
+pub mod signal;
+pub mod snapshot;
+pub mod stats;
+pub mod stream;
+pub mod sync;
+pub mod timer_batch;
+pub mod timer_group;
+pub mod timer_loop;
+pub mod timer_ref;
+pub mod typed;
+pub mod udp;
+pub mod wallclock;
+pub mod watchdog;
+
 use crate::ae_select;
 use crate::ae_select::FiredEvent;
+use crate::anet::set_errno;
 use crate::constants::*;
+use crate::monotonic::{AeClockSource, get_monotonic_us};
 use crate::traits::*;
-use std::time::{Duration, Instant};
+use context::AeDispatchSource;
+use lifecycle::AeLifecycleEvent;
+use stats::AeStatsDelta;
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub struct AeFileEvent {
     pub mask: i32,
     pub rfile_proc: Option<FileProc>,
     pub wfile_proc: Option<FileProc>,
+    /* Handler of AE_ERRQUEUE, called with the data of the read handler. */
+    pub efile_proc: Option<FileProc>,
+    /* Data of the read handler, and of the write handler unless it was
+     * given its own (see AeFileEventOptions::split_client_data). */
     pub client_data: *mut std::ffi::c_void,
+    pub wclient_data: *mut std::ffi::c_void,
+    /* AE_WRITABLE was registered before AE_READABLE on this slot, used by
+     * AeDispatchOrder::RegistrationOrder. */
+    pub(crate) write_first: bool,
+    /* User defined routing tag, see AeFileEventOptions. */
+    pub tag: u32,
+    /* Registered with AeFileEventOptions::exclusive. */
+    pub(crate) exclusive: bool,
+    /* See AeFileEventOptions::priority. */
+    pub(crate) priority: u8,
+    /* See AeFileEventOptions::read_coalescing. The read handler is not
+     * called again up to this iteration (see AeEventLoop::iteration). */
+    pub(crate) read_coalescing: AeReadCoalescing,
+    pub(crate) read_suppressed_until: u64,
+    /* Re-armed since the read handler was last called, see ae_rearm(). */
+    pub(crate) read_rearmed: bool,
+    /* Bumped every time the slot is released, so events fired for a
+     * previous user of the fd number can be told apart. */
+    pub(crate) generation: u64,
+    /* Times the handlers of the fd were dispatched since it was
+     * registered, see ae_get_file_dispatches(). */
+    pub(crate) dispatches: u64,
 }
 
 impl Default for AeFileEvent {
@@ -28,11 +132,175 @@ impl Default for AeFileEvent {
             mask: AE_NONE,
             rfile_proc: None,
             wfile_proc: None,
+            efile_proc: None,
             client_data: std::ptr::null_mut(),
+            wclient_data: std::ptr::null_mut(),
+            write_first: false,
+            tag: 0,
+            exclusive: false,
+            priority: 0,
+            read_coalescing: AeReadCoalescing::Off,
+            read_suppressed_until: 0,
+            read_rearmed: false,
+            generation: 0,
+            dispatches: 0,
         }
     }
 }
 
+/* Extra per-fd settings for ae_create_file_event_ex(). */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AeFileEventOptions {
+    /* Small user defined value kept next to the registration, separate
+     * from client_data. Callbacks read it back with ae_get_file_tag(),
+     * which lets a multiplexed dispatcher route an event without
+     * dereferencing client_data. */
+    pub tag: u32,
+    /* Give client_data only to the handlers registered by this call
+     * instead of to both directions of the fd, so the read and write
+     * handlers of one fd can belong to different components, each with
+     * its own data. */
+    pub split_client_data: bool,
+    /* Read-only registration of an fd shared with other loops, typically
+     * a listening socket registered by every loop of a pool: when it
+     * becomes readable only one of the loops is woken up instead of all
+     * of them (EPOLLEXCLUSIVE on epoll, Linux 4.5+, see
+     * AeKernelFeatures::epoll_exclusive). kqueue and select have no such
+     * mode and wake every loop, the losers see EAGAIN from accept(). The
+     * mask must be AE_READABLE on an fd not registered yet, and the fd
+     * cannot be registered for writing afterwards. */
+    pub exclusive: bool,
+    /* Dispatch class of the fd. Among the fds fired by one poll, those
+     * with a higher priority are dispatched first, fds of the same
+     * priority in the loop's AeFdOrder. Priority only
+     * orders fds against each other: within an fd the handlers still run
+     * in the order set by AE_BARRIER and the loop's AeDispatchOrder, so a
+     * barrier fd flushes before it reads whatever its priority. */
+    pub priority: u8,
+    /* What to do when the fd is still readable after its read handler
+     * ran, see AeReadCoalescing. */
+    pub read_coalescing: AeReadCoalescing,
+}
+
+/* Level-triggered backends report an fd readable for as long as unread
+ * data is left, so a handler that leaves part of its input for later (a
+ * client over its per-iteration quota, say) is called again at every
+ * iteration, and the loop spins. With coalescing the read handler is not
+ * called again for a while after it ran: readable events fired meanwhile
+ * are dropped and counted in AeStats::coalesced_reads. The write handler
+ * is not affected. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AeReadCoalescing {
+    /* Call the read handler whenever the fd is readable. */
+    #[default]
+    Off,
+    /* Skip the next `n` iterations. */
+    Iterations(u32),
+    /* Skip until the fd is re-armed with ae_rearm() (or AE_READABLE is
+     * registered again on it), typically by the code that consumes the
+     * rest of the input. */
+    UntilRearmed,
+}
+
+/* Order in which the read and write handlers of an fd that is both
+ * readable and writable are called. AE_BARRIER on an fd always means
+ * writes first, whatever the loop policy. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AeDispatchOrder {
+    /* Read handler first, the Redis behaviour. */
+    #[default]
+    ReadsFirst,
+    /* Write handler first, as if every fd had AE_BARRIER set. */
+    WritesFirst,
+    /* Whichever of AE_READABLE / AE_WRITABLE was registered first. */
+    RegistrationOrder,
+}
+
+/* Order in which the fds fired by one poll are dispatched, before
+ * AeFileEventOptions::priority is applied on top of it. Backends report
+ * fds in their own order: select by ascending fd, epoll and kqueue in
+ * the order the kernel queued them. The loop normalizes it, so that an
+ * application works the same on every backend. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AeFdOrder {
+    /* Ascending fd number, whatever the backend. */
+    #[default]
+    Ascending,
+    /* The order the backend reported them, saving a sort per iteration.
+     * Differs between backends and from one poll to another. */
+    Backend,
+}
+
+/* What the loop does when a signal interrupts the backend poll (EINTR). */
+#[derive(Debug, Clone, Copy, Default)]
+pub enum AeEintrPolicy {
+    /* End the wait and go on with the iteration as if nothing fired, the
+     * Redis behaviour. */
+    #[default]
+    ReturnEarly,
+    /* Poll again, with the timeout recomputed from the next timer. */
+    Retry,
+    /* Call the hook (to handle the signal, typically), then poll again if
+     * it returns true or end the wait if it returns false. */
+    InvokeHook(EintrProc),
+}
+
+/* What ae_create_file_event() does with fds 0, 1 and 2. A CLI watches
+ * stdin or stdout on purpose. A daemon closed them at startup, and the
+ * next file it opens takes the number: a registration there usually is a
+ * handler meant for stdio talking to an unrelated socket, or the other
+ * way around. Applies to every path registering an fd, ae_register_stdin()
+ * and the typed API included. */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AeStdioPolicy {
+    #[default]
+    Allow,
+    /* Register, and raise an AeFindingKind::StdioRegistration finding
+     * through the diagnostic proc (see ae_set_diagnostic_proc()). */
+    Warn,
+    /* Refuse with AE_ERR. */
+    Deny,
+}
+
+/* What a timer whose callback ran past its budget gets, on top of the
+ * slowlog entry, see ae_set_time_event_budget(). */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AeOverrunPolicy {
+    /* Keep the schedule. */
+    #[default]
+    Record,
+    /* Skip the firings the overrun ate into: a callback that took three
+     * times the delay it returned skips the next three, so a periodic job
+     * gone slow stays within its share of the loop until it is fast
+     * again. */
+    Skip,
+}
+
+/* Polls AeFiredOverflowPolicy::PollAgain makes in one iteration, the
+ * first one included. */
+pub const AE_FIRED_OVERFLOW_MAX_POLLS: usize = 4;
+
+/* What the loop does when a poll fills the fired buffer: more fds may be
+ * ready than it could report. Events left over are reported again by the
+ * next poll (all backends are level triggered), but they wait for the
+ * next iteration, behind the timers. Every overflow is counted in
+ * AeStats::fired_saturated whatever the policy. */
+#[derive(Debug, Clone, Copy, Default)]
+pub enum AeFiredOverflowPolicy {
+    /* Leave the remaining events to the next iteration. */
+    #[default]
+    Defer,
+    /* Double the fired buffer, up to the number of fds the loop tracks,
+     * so that the next polls report more. */
+    Grow,
+    /* Once the events are dispatched, poll again without waiting and
+     * dispatch what is still ready, up to AE_FIRED_OVERFLOW_MAX_POLLS
+     * polls per iteration. */
+    PollAgain,
+    /* Call the hook with the number of events reported, then defer. */
+    InvokeHook(FiredOverflowProc),
+}
+
 impl AeFileEvent {
     pub fn new() -> Self {
         Self::default()
@@ -48,9 +316,47 @@ pub struct AeTimeEvent {
     pub finalizer_proc: Option<EventFinalizerProc>,
     pub client_data: *mut std::ffi::c_void,
     pub refcount: i32,
+    /* Set by ae_delete_time_event(). The id is kept so that a timer
+     * deleting itself from its own callback is still found (and released)
+     * once the callback returns. */
+    pub(crate) deleted: bool,
+    /* The finalizer has been called, it must never run twice. */
+    pub(crate) finalized: bool,
+    /* Shared with the TimeEventRef handles of this timer, cleared once
+     * the timer is deleted. */
+    pub(crate) liveness: Option<std::sync::Arc<std::sync::atomic::AtomicBool>>,
+    /* Random spread applied to each rescheduling, in percent of the
+     * period, see ae_set_time_event_jitter(). */
+    pub(crate) jitter_pct: u32,
+    /* Soft time limit of the callback in microseconds, 0 for none, and
+     * what happens past it, see ae_set_time_event_budget(). */
+    pub(crate) budget_us: u64,
+    pub(crate) overrun_policy: AeOverrunPolicy,
+    /* Set for the members of a timer batch, which have no time_proc of
+     * their own, see ae_create_batched_time_event(). */
+    pub(crate) batch: Option<u64>,
+    /* A call of ae_call_soon() or ae_yield_and_continue(), subject to
+     * the deferred limit, see ae_set_deferred_limit(). */
+    pub(crate) deferred: bool,
+    /* See ae_set_time_event_group(), 0 for none. */
+    pub(crate) group: u64,
+    /* Next run in loop time set by the callback itself, used instead of
+     * its millisecond return value, see periodic_timer_proc(). */
+    pub(crate) rearm_at: Option<u64>,
 }
 
 impl AeTimeEvent {
+    /* Schedule the event for removal and tell its handles. Returns false
+     * if it already was. */
+    pub(crate) fn mark_deleted(&mut self) -> bool {
+        let was_live = !self.deleted;
+        self.deleted = true;
+        if let Some(liveness) = &self.liveness {
+            liveness.store(false, std::sync::atomic::Ordering::Release);
+        }
+        was_live
+    }
+
     pub fn new(
         id: i64,
         when: u64,
@@ -65,6 +371,16 @@ impl AeTimeEvent {
             finalizer_proc,
             client_data,
             refcount: 0,
+            deleted: false,
+            finalized: false,
+            liveness: None,
+            jitter_pct: 0,
+            budget_us: 0,
+            overrun_policy: AeOverrunPolicy::Record,
+            batch: None,
+            deferred: false,
+            group: 0,
+            rearm_at: None,
         }
     }
 }
@@ -81,12 +397,38 @@ impl TimeEventNode {
     }
 }
 
+/* One fired event as seen when poll returned. The registration it fired
+ * for is the one holding the fd's slot at that generation: a callback of
+ * the same iteration may delete the fd and another one reuse it, and the
+ * readiness belongs to the old file. */
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DispatchEntry {
+    fd: i32,
+    mask: i32,
+    generation: u64,
+}
+
 /* Fields ordered for optimal memory alignment */
 pub struct AeEventLoop {
     pub time_event_next_id: i64,
     pub apidata: Box<dyn EventBackend>,
     pub events: Vec<AeFileEvent>,
     pub fired: Vec<FiredEvent>,
+    /* Events of the current iteration, snapshotted right after the poll
+     * and dispatched from here. */
+    pub(crate) dispatch_list: Vec<DispatchEntry>,
+    /* What the last poll fired, see ae_fired_events(). */
+    pub(crate) fired_batch: Vec<FiredEvent>,
+    /* Queued by ae_fire_event() for the next iteration. */
+    pub(crate) injected: Vec<DispatchEntry>,
+    /* Number of ae_process_events() calls, never reset. */
+    pub(crate) iteration: u64,
+    /* See ae_set_rearm_check(). */
+    pub(crate) rearm_check: bool,
+    /* See ae_create_custom_event(). */
+    pub(crate) custom: custom::CustomEvents,
+    /* Waiters of the primitives of ae::sync woken up. */
+    pub(crate) sync_ready: sync::ReadyQueue,
     pub time_event_head: Option<Box<TimeEventNode>>,
     pub beforesleep: Option<BeforeSleepProc>,
     pub aftersleep: Option<AfterSleepProc>,
@@ -94,12 +436,120 @@ pub struct AeEventLoop {
     pub maxfd: i32,
     pub setsize: i32,
     pub nevents: u32,
-    pub flags: i32,
+    /* Global flags (AE_DONT_WAIT), shared with the AeHandles of the loop
+     * so other threads can toggle them, see AeHandle::set_dont_wait(). */
+    pub(crate) flags: Arc<AtomicI32>,
     pub stop: bool,
+    pub(crate) wakeup: Option<handle::Wakeup>,
+    pub(crate) modules: Vec<std::sync::Arc<dyn AeModule>>,
+    /* Monotonic time ae_pause() was called at, None while running. */
+    pub(crate) paused_at: Option<u64>,
+    /* Spare fds given up by ae_accept() on EMFILE, see
+     * AeEventLoopBuilder::reserved_fds(). */
+    pub(crate) reserved_fds: Vec<i32>,
+    /* See ae_set_peer_filter(). */
+    pub(crate) peer_filter: Option<PeerFilterProc>,
+    pub(crate) stats: stats::StatsState,
+    pub(crate) lifecycle: Option<LifecycleProc>,
+    /* Set by ae_dont_wait_next(), cleared by the next poll. */
+    pub(crate) dont_wait_once: bool,
+    pub(crate) dispatch_order: AeDispatchOrder,
+    pub(crate) fd_order: AeFdOrder,
+    /* Longest poll, see AeEventLoopBuilder::max_poll_timeout(). */
+    pub(crate) max_poll_timeout: Option<Duration>,
+    /* Changes applied at the next iteration, see ae_reconfigure(). */
+    pub(crate) pending_reconfig: Option<reconfigure::AeLoopConfigDelta>,
+    pub(crate) eintr_policy: AeEintrPolicy,
+    pub(crate) fired_overflow: AeFiredOverflowPolicy,
+    pub(crate) stdio_policy: AeStdioPolicy,
+    /* See ae_group_commit_enable(). */
+    pub(crate) group_commit: Option<group_commit::GroupCommit>,
+    /* See ae_drain(). */
+    pub(crate) drain: Option<drain::DrainState>,
+    /* Config snapshots, see ae_set_config(). */
+    pub(crate) configs: config::ConfigSlots,
+    /* Write end of the status pipe in a child of ae_spawn_child_loop(),
+     * -1 otherwise. */
+    pub(crate) child_status_fd: i32,
+    /* What an AeTimerLoop waits on, None for other loops. */
+    pub(crate) parker: Option<Arc<timer_loop::Parker>>,
+    /* Most slots of the fired buffer, see
+     * AeEventLoopBuilder::fired_capacity(). */
+    pub(crate) fired_capacity: usize,
+    pub(crate) clock: AeClockSource,
+    /* Clock timing callbacks, see AeEventLoopBuilder::latency_clock(). */
+    pub(crate) latency_clock: Option<AeClockSource>,
+    /* Time of AeClockSource::Manual, see ae_advance_clock(). */
+    pub(crate) manual_now_us: u64,
+    /* Loop time of the current iteration, see ae_loop_now(). */
+    pub(crate) cached_now_us: u64,
+    pub(crate) wallclock: wallclock::WallClockState,
+    /* State of the xorshift generator used for timer jitter. */
+    pub(crate) jitter_seed: u64,
+    /* I/O threads serving ae_file_read(), started on first use. */
+    pub(crate) fileio: Option<fileio::FileIoPool>,
+    pub(crate) io_threads: usize,
+    /* Write end of the heartbeat pipe, see ae_enable_heartbeat(). */
+    pub(crate) heartbeat: Option<heartbeat::HeartbeatState>,
+    /* Connections probed by ae_keepalive_enable(). */
+    pub(crate) keepalive: Option<keepalive::KeepaliveState>,
+    /* Connections with an idle timeout, see ae_conn_set_idle_timeout(). */
+    pub(crate) idle: idle::IdleWheel,
+    /* Fds waiting to be writable, see ae_set_write_stall_watchdog(). */
+    pub(crate) write_watchdog: watchdog::WriteWatchdog,
+    /* See ae_set_soft_limits(). */
+    pub(crate) soft_limits: limits::SoftLimits,
+    /* Buffered connections, see ae_conn_create(). */
+    pub(crate) conns: HashMap<i32, *mut conn::ConnState>,
+    /* Bytes held by buffered connections, see ae_memory_usage(). */
+    pub(crate) conn_memory: usize,
+    /* Free read buffers, see ae_buf_acquire(). */
+    pub(crate) buf_pool: bufpool::BufPool,
+    /* Both sides of each proxy, see ae_proxy_create(). */
+    pub(crate) proxies: HashMap<i32, *mut proxy::ProxyState>,
+    /* See ae_set_iteration_budget(), 0 for none. */
+    pub(crate) iteration_budget_us: u64,
+    /* See ae_set_deferred_limit(), 0 for none. */
+    pub(crate) deferred_limit: usize,
+    pub(crate) iteration_deadline_us: Option<u64>,
+    /* Job slices that overran, see ae_slowlog_get(). */
+    pub(crate) slowlog: job::Slowlog,
+    /* See ae_pending_time_events() and ae_registered_file_events(). */
+    pub(crate) live_time_events: usize,
+    pub(crate) registered_fds: usize,
+    /* See ae_dispatch_stack(). */
+    pub(crate) dispatch_stack: context::DispatchStack,
+    /* See ae_set_flight_recorder(). */
+    pub(crate) flight: flight::FlightRecorder,
+    /* See ae_set_panic_policy() and ae_set_crash_reporter(). */
+    pub(crate) panic: panic::PanicState,
+    /* See ae_shutdown_token(). */
+    pub(crate) shutdown: shutdown::ShutdownState,
+    /* See ae_register_group(). */
+    pub(crate) groups: group::Groups,
+    /* See ae_scope(). */
+    pub(crate) scopes: scope::Scopes,
+    /* See ae_create_timer_batch(). */
+    pub(crate) timer_batches: timer_batch::TimerBatches,
+    /* See AeEventLoopBuilder::name(). */
+    pub(crate) name: Option<String>,
+    /* See ae_create_shared_file_event(). */
+    pub(crate) shared: shared::SharedFds,
+    /* See ae_set_diagnostic_proc(). */
+    pub(crate) diagnostics: doctor::Diagnostics,
+    /* See ae_add_external_source(). */
+    pub(crate) external: external::ExternalSources,
+    pub(crate) backoff: backoff::Backoffs,
+    pub(crate) admin: Option<admin::AdminState>,
+    /* See AeEventLoopBuilder::differential(). */
+    pub(crate) differential: Option<differential::DivergenceLog>,
+    /* What the backend was asked to watch, see ae::invariants. */
+    #[cfg_attr(not(feature = "debug-invariants"), allow(dead_code))]
+    pub(crate) shadow: invariants::Shadow,
 }
 
 impl AeEventLoop {
-    fn new(setsize: i32, backend: Box<dyn EventBackend>) -> Self {
+    pub(crate) fn new(setsize: i32, backend: Box<dyn EventBackend>) -> Self {
         let nevents = if setsize < INITIAL_EVENT as i32 {
             setsize as u32
         } else {
@@ -107,7 +557,8 @@ impl AeEventLoop {
         };
 
         let mut events = Vec::with_capacity(nevents as usize);
-        let mut fired = Vec::with_capacity(nevents as usize);
+        let nfired = poll_batch(nevents as usize);
+        let mut fired = Vec::with_capacity(nfired);
 
         /* Events with mask == AE_NONE are not set. So let's initialize the
          * vector with it. */
@@ -115,15 +566,24 @@ impl AeEventLoop {
             events.push(AeFileEvent::new());
         }
 
-        for _ in 0..nevents {
+        for _ in 0..nfired {
             fired.push(FiredEvent { fd: 0, mask: 0 });
         }
+        let shadow = invariants::Shadow::default();
 
         Self {
-            time_event_next_id: 0,
-            apidata: backend,
+            /* Ids start at 1 so that 0 is never a valid timer id. */
+            time_event_next_id: 1,
+            apidata: invariants::wrap(backend, &shadow),
             events,
             fired,
+            dispatch_list: Vec::new(),
+            fired_batch: Vec::new(),
+            injected: Vec::new(),
+            iteration: 0,
+            rearm_check: false,
+            custom: custom::CustomEvents::default(),
+            sync_ready: sync::ReadyQueue::default(),
             time_event_head: None,
             beforesleep: None,
             aftersleep: None,
@@ -131,41 +591,209 @@ impl AeEventLoop {
             maxfd: -1,
             setsize,
             nevents,
-            flags: 0,
+            flags: Arc::new(AtomicI32::new(0)),
             stop: false,
+            wakeup: None,
+            modules: Vec::new(),
+            paused_at: None,
+            reserved_fds: Vec::new(),
+            peer_filter: None,
+            stats: stats::StatsState::default(),
+            lifecycle: None,
+            dont_wait_once: false,
+            dispatch_order: AeDispatchOrder::ReadsFirst,
+            fd_order: AeFdOrder::Ascending,
+            max_poll_timeout: None,
+            pending_reconfig: None,
+            eintr_policy: AeEintrPolicy::ReturnEarly,
+            fired_overflow: AeFiredOverflowPolicy::Defer,
+            stdio_policy: AeStdioPolicy::Allow,
+            group_commit: None,
+            drain: None,
+            configs: config::ConfigSlots::new(),
+            child_status_fd: -1,
+            parker: None,
+            fired_capacity: AE_POLL_BATCH,
+            clock: AeClockSource::Instant,
+            latency_clock: None,
+            manual_now_us: 0,
+            cached_now_us: get_monotonic_us(AeClockSource::Instant),
+            wallclock: wallclock::WallClockState::default(),
+            jitter_seed: random_seed(),
+            fileio: None,
+            io_threads: fileio::AE_IO_THREADS_DEFAULT,
+            heartbeat: None,
+            keepalive: None,
+            idle: idle::IdleWheel::default(),
+            write_watchdog: watchdog::WriteWatchdog::default(),
+            soft_limits: limits::SoftLimits::default(),
+            conns: HashMap::new(),
+            conn_memory: 0,
+            buf_pool: bufpool::BufPool::default(),
+            proxies: HashMap::new(),
+            iteration_budget_us: 0,
+            deferred_limit: dispatch::AE_DEFERRED_LIMIT_DEFAULT,
+            iteration_deadline_us: None,
+            slowlog: job::Slowlog::default(),
+            live_time_events: 0,
+            registered_fds: 0,
+            dispatch_stack: context::DispatchStack::default(),
+            flight: flight::FlightRecorder::default(),
+            panic: panic::PanicState::default(),
+            shutdown: shutdown::ShutdownState::default(),
+            groups: group::Groups::default(),
+            scopes: scope::Scopes::default(),
+            timer_batches: timer_batch::TimerBatches::default(),
+            name: None,
+            shared: shared::SharedFds::default(),
+            diagnostics: doctor::Diagnostics::default(),
+            external: external::ExternalSources::default(),
+            backoff: backoff::Backoffs::default(),
+            admin: None,
+            differential: None,
+            shadow,
+        }
+    }
+}
+
+impl AeEventLoop {
+    /* Name of the backend this loop polls with: "select", "epoll" or
+     * "kqueue", whatever the loop was built with. */
+    pub fn api_name(&self) -> &'static str {
+        self.apidata.name()
+    }
+
+    /* Current time of the loop clock, see AeEventLoopBuilder::clock_source(). */
+    #[inline]
+    pub(crate) fn now_us(&self) -> u64 {
+        match self.clock {
+            AeClockSource::Manual => self.manual_now_us,
+            clock => get_monotonic_us(clock),
         }
     }
+
+    /* Current time of the clock timing callbacks, see
+     * AeEventLoopBuilder::latency_clock(). */
+    #[inline]
+    pub(crate) fn latency_now_us(&self) -> u64 {
+        match self.latency_clock {
+            None | Some(AeClockSource::Manual) => self.now_us(),
+            Some(clock) => get_monotonic_us(clock),
+        }
+    }
+
+    /* Next value of the jitter generator (xorshift64*). */
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.jitter_seed;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.jitter_seed = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
 }
 
-unsafe impl Send for AeEventLoop {}
+/* Per-loop random seed, so that processes started together do not pick
+ * the same jitter. Never 0, which xorshift cannot leave. */
+fn random_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+        | 1
+}
 
-fn get_monotonic_us() -> u64 {
-    static START_TIME: std::sync::OnceLock<Instant> = std::sync::OnceLock::new();
-    let start = START_TIME.get_or_init(Instant::now);
-    start.elapsed().as_micros() as u64
+/* Spread `delay_us` uniformly over +/- `pct` percent of itself. */
+fn jittered_delay_us(delay_us: u64, pct: u32, random: u64) -> u64 {
+    if pct == 0 || delay_us == 0 {
+        return delay_us;
+    }
+    let spread = (delay_us as u128 * pct as u128 / 100) as u64;
+    let offset = random % (spread.saturating_mul(2).saturating_add(1));
+    (delay_us - spread).saturating_add(offset)
 }
 
 impl Drop for AeEventLoop {
     fn drop(&mut self) {
-        /* Free the time events list. */
-        while let Some(mut node) = self.time_event_head.take() {
-            if let Some(finalizer) = node.event.finalizer_proc {
-                finalizer(self, node.event.client_data);
+        module::run_hooks(self, |m, el| m.on_shutdown(el));
+        admin::ae_admin_close(self);
+
+        wallclock::free_all(self);
+        heartbeat::ae_disable_heartbeat(self);
+
+        /* Free the time events list. The list is unlinked before calling
+         * the finalizers, which may create or delete timers: keep going
+         * until no timer is left. */
+        while let Some(head) = self.time_event_head.take() {
+            let mut list = Some(head);
+            while let Some(mut node) = list {
+                list = node.next.take();
+                finalize_time_event(self, &mut node.event);
             }
-            self.time_event_head = node.next.take();
         }
+
+        for &fd in &self.reserved_fds {
+            unsafe { libc::close(fd) };
+        }
+        shared::close_all(self);
     }
 }
 
+/* Create a loop tracking fds below `setsize`. None for a negative size.
+ *
+ * A setsize of 0 makes a timers-only loop, for pure schedulers: every
+ * file registration fails with AE_ERR and errno ERANGE (so do loop
+ * handles and signal stops, which need a wakeup fd), the backend is never
+ * polled and the wait for the next timer is a plain sleep. ae_main()
+ * works as usual, and like any loop with nothing left to wait for it
+ * sleeps forever once the last timer is gone. */
 pub fn ae_create_event_loop(setsize: i32) -> Option<Box<AeEventLoop>> {
-    // Create the platform-specific backend
-    let backend = match create_select_backend() {
-        Ok(backend) => backend,
-        Err(_) => return None,
-    };
+    builder::AeEventLoopBuilder::new(setsize).build()
+}
+
+/* Create an event loop on top of an explicitly chosen backend, e.g. a
+ * kqueue state for a loop that has to be pollable itself (see
+ * ae::child). The backend is sized for `setsize` before use. */
+pub fn ae_create_event_loop_with_backend(
+    setsize: i32,
+    backend: Box<dyn EventBackend>,
+) -> Option<Box<AeEventLoop>> {
+    builder::AeEventLoopBuilder::new(setsize)
+        .backend(backend)
+        .build()
+}
+
+/* Change what the loop does when a poll fills the fired buffer. */
+pub fn ae_set_fired_overflow_policy(event_loop: &mut AeEventLoop, policy: AeFiredOverflowPolicy) {
+    event_loop.fired_overflow = policy;
+}
 
-    let event_loop = AeEventLoop::new(setsize, backend);
-    Some(Box::new(event_loop))
+/* Change what the loop does when a signal interrupts the poll. */
+pub fn ae_set_eintr_policy(event_loop: &mut AeEventLoop, policy: AeEintrPolicy) {
+    event_loop.eintr_policy = policy;
+}
+
+/* Change what registering fds 0, 1 and 2 does, see AeStdioPolicy. Fds
+ * registered already stay. */
+pub fn ae_set_stdio_policy(event_loop: &mut AeEventLoop, policy: AeStdioPolicy) {
+    event_loop.stdio_policy = policy;
+}
+
+pub fn ae_get_stdio_policy(event_loop: &AeEventLoop) -> AeStdioPolicy {
+    event_loop.stdio_policy
+}
+
+/* Don't block in the next poll only, e.g. from a beforesleep handler that
+ * still has pending jobs. The request is consumed by the next iteration
+ * that polls, including the current one when called from beforesleep. */
+pub fn ae_dont_wait_next(event_loop: &mut AeEventLoop) {
+    event_loop.dont_wait_once = true;
+}
+
+/* Process pending events without blocking, whatever the global
+ * AE_DONT_WAIT setting is. Same as passing AE_DONT_WAIT in `flags`. */
+pub fn ae_process_events_nowait(event_loop: &mut AeEventLoop, flags: i32) -> i32 {
+    ae_process_events(event_loop, flags | AE_DONT_WAIT)
 }
 
 /* Return the current set size. */
@@ -173,16 +801,59 @@ pub fn ae_get_set_size(event_loop: &AeEventLoop) -> i32 {
     event_loop.setsize
 }
 
+/* Timers waiting to fire, i.e. created and not deleted yet (by
+ * ae_delete_time_event() or by returning AE_NOMORE). Kept as a counter, so
+ * health checks can call it as often as they like. */
+pub fn ae_pending_time_events(event_loop: &AeEventLoop) -> usize {
+    event_loop.live_time_events
+}
+
+/* File descriptors with at least one event registered. */
+pub fn ae_registered_file_events(event_loop: &AeEventLoop) -> usize {
+    event_loop.registered_fds
+}
+
+/* Monotonic time in microseconds, as sampled by the loop at the start of
+ * the iteration and again when poll returns. Callbacks that need "now"
+ * (rate limiting, timestamps) can use it instead of reading the clock, and
+ * all of them see the same value within one batch of fired events. */
+pub fn ae_loop_now(event_loop: &AeEventLoop) -> u64 {
+    event_loop.cached_now_us
+}
+
+/* Move the clock of a loop built with AeClockSource::Manual forward by
+ * `us` microseconds. Timers that became due fire on the next iteration.
+ * Returns AE_ERR if the loop uses a real clock. */
+pub fn ae_advance_clock(event_loop: &mut AeEventLoop, us: u64) -> i32 {
+    if event_loop.clock != AeClockSource::Manual {
+        return AE_ERR;
+    }
+    event_loop.manual_now_us = event_loop.manual_now_us.saturating_add(us);
+    AE_OK
+}
+
 /*
  * Tell the event processing to change the wait timeout as soon as possible.
  *
- * Note: it just means you turn on/off the global AE_DONT_WAIT.
+ * Note: it just means you turn on/off the global AE_DONT_WAIT. The flag is
+ * sticky until turned off again: to skip a single sleep use
+ * ae_dont_wait_next() instead. From another thread use
+ * AeHandle::set_dont_wait(), which also wakes up a loop already asleep.
  */
 pub fn ae_set_dont_wait(event_loop: &mut AeEventLoop, no_wait: bool) {
+    set_dont_wait(&event_loop.flags, no_wait);
+}
+
+/* True while the global AE_DONT_WAIT is on. */
+pub fn ae_get_dont_wait(event_loop: &AeEventLoop) -> bool {
+    event_loop.flags.load(Ordering::Acquire) & AE_DONT_WAIT != 0
+}
+
+pub(crate) fn set_dont_wait(flags: &AtomicI32, no_wait: bool) {
     if no_wait {
-        event_loop.flags |= AE_DONT_WAIT;
+        flags.fetch_or(AE_DONT_WAIT, Ordering::AcqRel);
     } else {
-        event_loop.flags &= !AE_DONT_WAIT;
+        flags.fetch_and(!AE_DONT_WAIT, Ordering::AcqRel);
     }
 }
 
@@ -194,6 +865,9 @@ pub fn ae_set_dont_wait(event_loop: &mut AeEventLoop, no_wait: bool) {
  *
  * Otherwise AE_OK is returned and the operation is successful. */
 pub fn ae_resize_set_size(event_loop: &mut AeEventLoop, setsize: i32) -> i32 {
+    if setsize < 0 {
+        return AE_ERR;
+    }
     if setsize == event_loop.setsize {
         return AE_OK;
     }
@@ -216,9 +890,185 @@ pub fn ae_resize_set_size(event_loop: &mut AeEventLoop, setsize: i32) -> i32 {
         event_loop.nevents = setsize as u32;
     }
 
+    invariants::after(event_loop, "ae_resize_set_size");
+    AE_OK
+}
+
+/* Like ae_resize_set_size(), but registered fds that do not fit in the new
+ * size are moved below it first instead of failing: each one is
+ * duplicated to the lowest free fd number, its registration moved over,
+ * the old fd closed and `relocated` called so the application can update
+ * whatever it keys by fd. Buffered connections, their keepalive probes
+ * and idle timeouts follow on their own. The memory of the per-fd tables is released too,
+ * for servers shrinking back after a connection spike.
+ *
+ * Returns AE_ERR if no free fd is left below `setsize`, with the fds moved
+ * so far staying moved (and reported). */
+pub fn ae_resize_set_size_compact(
+    event_loop: &mut AeEventLoop,
+    setsize: i32,
+    relocated: RelocateProc,
+) -> i32 {
+    if setsize <= 0 {
+        return AE_ERR;
+    }
+    for fd in (setsize..=event_loop.maxfd).rev() {
+        if event_loop.events[fd as usize].mask != AE_NONE
+            && relocate_file_event(event_loop, fd, setsize, relocated) == AE_ERR
+        {
+            return AE_ERR;
+        }
+    }
+    if ae_resize_set_size(event_loop, setsize) == AE_ERR {
+        return AE_ERR;
+    }
+    event_loop.events.shrink_to_fit();
+    event_loop.fired.shrink_to_fit();
+    invariants::after(event_loop, "ae_resize_set_size_compact");
+    AE_OK
+}
+
+fn relocate_file_event(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    limit: i32,
+    relocated: RelocateProc,
+) -> i32 {
+    let new_fd = unsafe { libc::fcntl(fd, libc::F_DUPFD, 0) };
+    if new_fd == -1 {
+        return AE_ERR;
+    }
+    if new_fd >= limit {
+        unsafe { libc::close(new_fd) };
+        return AE_ERR;
+    }
+    let fd_flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if fd_flags != -1 {
+        unsafe { libc::fcntl(new_fd, libc::F_SETFD, fd_flags) };
+    }
+
+    let fe = event_loop.events[fd as usize].clone();
+    let registered = [
+        (fe.rfile_proc, AE_READABLE, fe.client_data),
+        (
+            fe.wfile_proc,
+            AE_WRITABLE | (fe.mask & AE_BARRIER),
+            fe.wclient_data,
+        ),
+        (fe.efile_proc, AE_ERRQUEUE, fe.client_data),
+    ];
+    let options = AeFileEventOptions {
+        tag: fe.tag,
+        split_client_data: true,
+        exclusive: fe.exclusive,
+        priority: fe.priority,
+        read_coalescing: fe.read_coalescing,
+    };
+    for (proc, mask, client_data) in registered {
+        if let Some(proc) = proc
+            && ae_create_file_event_ex(event_loop, new_fd, mask, proc, client_data, options)
+                == AE_ERR
+        {
+            ae_delete_file_event(event_loop, new_fd, AE_ALL_MASK);
+            unsafe { libc::close(new_fd) };
+            return AE_ERR;
+        }
+    }
+    event_loop.events[new_fd as usize].write_first = fe.write_first;
+
+    ae_delete_file_event(event_loop, fd, AE_ALL_MASK);
+    unsafe { libc::close(fd) };
+    conn::relocate(event_loop, fd, new_fd);
+    keepalive::relocate(event_loop, fd, new_fd);
+    idle::relocate(event_loop, fd, new_fd);
+    watchdog::relocate(event_loop, fd, new_fd);
+    proxy::relocate(event_loop, fd, new_fd);
+    relocated(event_loop, fd, new_fd, fe.client_data);
     AE_OK
 }
 
+/* Sort the dispatch list by descending AeFileEventOptions::priority,
+ * then by `order`. With AeFdOrder::Backend, loops that use no priority
+ * pay one pass over the fired events. */
+fn order_dispatch_list(
+    events: &[AeFileEvent],
+    order: AeFdOrder,
+    dispatch_list: &mut [DispatchEntry],
+) {
+    let priority =
+        |entry: &DispatchEntry| events.get(entry.fd as usize).map_or(0, |fe| fe.priority);
+    match order {
+        AeFdOrder::Ascending => {
+            dispatch_list.sort_by_key(|entry| (std::cmp::Reverse(priority(entry)), entry.fd))
+        }
+        AeFdOrder::Backend => {
+            if dispatch_list.iter().all(|entry| priority(entry) == 0) {
+                return;
+            }
+            dispatch_list.sort_by_key(|entry| std::cmp::Reverse(priority(entry)));
+        }
+    }
+}
+
+/* The registration `entry` fired for, None once it was deleted. */
+fn fired_registration<'a>(
+    event_loop: &'a AeEventLoop,
+    entry: &DispatchEntry,
+) -> Option<&'a AeFileEvent> {
+    event_loop
+        .events
+        .get(entry.fd as usize)
+        .filter(|fe| fe.generation == entry.generation && fe.mask != AE_NONE)
+}
+
+/* Call the read handler `entry` fired for, then hold further reads back
+ * as its AeReadCoalescing says, unless it re-armed the fd meanwhile. */
+fn call_read_proc(
+    event_loop: &mut AeEventLoop,
+    entry: &DispatchEntry,
+    proc: FileProc,
+    client_data: *mut std::ffi::c_void,
+) {
+    event_loop.events[entry.fd as usize].read_rearmed = false;
+    call_file_proc(event_loop, proc, entry.fd, client_data, entry.mask);
+
+    let iteration = event_loop.iteration;
+    if fired_registration(event_loop, entry).is_none() {
+        return;
+    }
+    let fe = &mut event_loop.events[entry.fd as usize];
+    if fe.read_rearmed {
+        return;
+    }
+    fe.read_suppressed_until = match fe.read_coalescing {
+        AeReadCoalescing::Off => return,
+        AeReadCoalescing::Iterations(n) => iteration + n as u64,
+        AeReadCoalescing::UntilRearmed => u64::MAX,
+    };
+    if fe.read_coalescing == AeReadCoalescing::UntilRearmed {
+        rearm::check(event_loop, entry.fd);
+    }
+}
+
+/* Handler and data registered right now for `direction` (AE_READABLE or
+ * AE_WRITABLE) of the registration `entry` fired for, if that direction
+ * fired and is still registered. */
+fn fired_handler(
+    event_loop: &AeEventLoop,
+    entry: &DispatchEntry,
+    direction: i32,
+) -> Option<(FileProc, *mut std::ffi::c_void)> {
+    let fe = fired_registration(event_loop, entry)?;
+    if fe.mask & entry.mask & direction == 0 {
+        return None;
+    }
+    match direction {
+        AE_READABLE => fe.rfile_proc.map(|proc| (proc, fe.client_data)),
+        AE_ERRQUEUE => fe.efile_proc.map(|proc| (proc, fe.client_data)),
+        _ => fe.wfile_proc.map(|proc| (proc, fe.wclient_data)),
+    }
+}
+
 pub fn ae_delete_event_loop(event_loop: Box<AeEventLoop>) {
     // Drop will handle cleanup automatically
     drop(event_loop);
@@ -228,6 +1078,105 @@ pub fn ae_stop(event_loop: &mut AeEventLoop) {
     event_loop.stop = true;
 }
 
+/* Pause the loop: all file event interest is removed from the backend
+ * (registrations themselves are kept) and timers are frozen, so nothing
+ * fires until ae_resume(). The wakeup pipe of the loop handle stays armed,
+ * which lets another thread resume the loop through AeHandle::post().
+ *
+ * Useful around configuration reloads or fork-for-snapshot windows. */
+pub fn ae_pause(event_loop: &mut AeEventLoop) -> i32 {
+    if event_loop.paused_at.is_some() {
+        return AE_OK;
+    }
+
+    let wakeup_fd = event_loop.wakeup.as_ref().map_or(-1, |w| w.rfd);
+    for fd in 0..=event_loop.maxfd {
+        let mask = event_loop.events[fd as usize].mask;
+        if mask != AE_NONE && fd != wakeup_fd {
+            event_loop.apidata.del_event(fd, mask);
+        }
+    }
+
+    event_loop.paused_at = Some(event_loop.now_us());
+    invariants::after(event_loop, "ae_pause");
+    AE_OK
+}
+
+/* Resume a loop paused with ae_pause(): file event interest is restored
+ * and timers are shifted by the time spent paused, so a timer due in 10ms
+ * when the loop was paused is still due in 10ms now. */
+pub fn ae_resume(event_loop: &mut AeEventLoop) -> i32 {
+    let paused_at = match event_loop.paused_at.take() {
+        Some(paused_at) => paused_at,
+        None => return AE_OK,
+    };
+
+    let mut retval = AE_OK;
+    let wakeup_fd = event_loop.wakeup.as_ref().map_or(-1, |w| w.rfd);
+    for fd in 0..=event_loop.maxfd {
+        if fd != wakeup_fd && add_backend_interest(event_loop, fd) == -1 {
+            retval = AE_ERR;
+        }
+    }
+
+    let paused_for = event_loop.now_us().saturating_sub(paused_at);
+    let mut current = &mut event_loop.time_event_head;
+    while let Some(node) = current {
+        node.event.when += paused_for;
+        current = &mut node.next;
+    }
+
+    invariants::after(event_loop, "ae_resume");
+    retval
+}
+
+pub fn ae_is_paused(event_loop: &AeEventLoop) -> bool {
+    event_loop.paused_at.is_some()
+}
+
+/* To be called in the child after fork(). Kernel polling objects (kqueue,
+ * epoll) do not survive a fork usably, so the backend is recreated and
+ * every registered file event is handed to it again. The wakeup pipe used
+ * by loop handles is shared with the parent and gets dropped: handles
+ * copied across the fork report is_closed() and a new one can be obtained
+ * with ae_get_handle(). Timers are kept as they are. */
+pub fn ae_reinit_after_fork(event_loop: &mut AeEventLoop) -> i32 {
+    /* Backend first: the resets below delete file events, which must not
+     * reach an epoll instance still shared with the parent. They run once
+     * the private one watches the registered fds again, so the loop and
+     * its backend agree whenever a file event is touched. */
+    let reinit = event_loop.apidata.reinit();
+    let mut retval = if reinit == -1 { AE_ERR } else { AE_OK };
+
+    /* A paused loop has no interest registered, ae_resume() adds it. */
+    if reinit != -1 && event_loop.paused_at.is_none() {
+        for fd in 0..=event_loop.maxfd {
+            if add_backend_interest(event_loop, fd) == -1 {
+                retval = AE_ERR;
+            }
+        }
+    }
+    handle::reset_wakeup_after_fork(event_loop);
+    fileio::reset_after_fork(event_loop);
+    heartbeat::reset_after_fork(event_loop);
+    if reinit == -1 {
+        return AE_ERR;
+    }
+    invariants::after(event_loop, "ae_reinit_after_fork");
+    retval
+}
+
+/* Hand the registered interest of `fd` to the backend again, the way it
+ * was first registered. */
+fn add_backend_interest(event_loop: &mut AeEventLoop, fd: i32) -> i32 {
+    let fe = &event_loop.events[fd as usize];
+    match (fe.mask, fe.exclusive) {
+        (AE_NONE, _) => 0,
+        (mask, true) => event_loop.apidata.add_event_exclusive(fd, mask),
+        (mask, false) => event_loop.apidata.add_event(fd, mask),
+    }
+}
+
 pub fn ae_set_before_sleep_proc(
     event_loop: &mut AeEventLoop,
     beforesleep: Option<BeforeSleepProc>,
@@ -239,32 +1188,102 @@ pub fn ae_set_after_sleep_proc(event_loop: &mut AeEventLoop, aftersleep: Option<
     event_loop.aftersleep = aftersleep;
 }
 
+/* The file events of the current iteration, in the order they are about
+ * to be dispatched, injected ones (ae_fire_event()) included. Filled when
+ * the poll returns, so aftersleep sees the whole batch before any handler
+ * runs: a batching layer can decide to fsync once, or to gather replies,
+ * up front. Stays valid until the next poll, a nested
+ * ae_process_events() included. */
+pub fn ae_fired_events(event_loop: &AeEventLoop) -> &[FiredEvent] {
+    &event_loop.fired_batch
+}
+
+/* Name of the backend ae_create_event_loop() picks by default, which is
+ * not necessarily the one a given loop uses. */
+#[deprecated(note = "use AeEventLoop::api_name(), which reports the backend of the loop")]
 pub fn ae_get_api_name() -> &'static str {
     ae_select::ae_api_name()
 }
 
+/* Name given with AeEventLoopBuilder::name(), None for an unnamed loop. */
+pub fn ae_get_loop_name(event_loop: &AeEventLoop) -> Option<&str> {
+    event_loop.name.as_deref()
+}
+
 pub fn ae_main(event_loop: &mut AeEventLoop) {
     event_loop.stop = false;
+    lifecycle::emit(event_loop, AeLifecycleEvent::LoopStarted);
+    while !event_loop.stop {
+        ae_process_events(
+            event_loop,
+            AE_ALL_EVENTS | AE_CALL_BEFORE_SLEEP | AE_CALL_AFTER_SLEEP,
+        );
+    }
+    lifecycle::emit(event_loop, AeLifecycleEvent::LoopStopped);
+}
+
+/* Like ae_main(), calling `driver` before every iteration. The loop exits
+ * when the driver breaks or ae_stop() is called. */
+pub fn ae_run_with_driver(event_loop: &mut AeEventLoop, driver: &mut dyn LoopDriver) {
+    event_loop.stop = false;
+    lifecycle::emit(event_loop, AeLifecycleEvent::LoopStarted);
+    while !event_loop.stop {
+        if driver.tick(event_loop).is_break() {
+            break;
+        }
+        ae_process_events(
+            event_loop,
+            AE_ALL_EVENTS | AE_CALL_BEFORE_SLEEP | AE_CALL_AFTER_SLEEP,
+        );
+    }
+    lifecycle::emit(event_loop, AeLifecycleEvent::LoopStopped);
+}
+
+/* Like ae_main(), calling `on_iteration` after every iteration with what
+ * it did: the events it processed, the bytes the connections moved, the
+ * time it took. The loop exits when `on_iteration` breaks or ae_stop() is
+ * called, for progress reports, external stop conditions or tests that
+ * want the loop to run until something happened. */
+pub fn ae_main_with<F>(event_loop: &mut AeEventLoop, mut on_iteration: F)
+where
+    F: FnMut(&AeStatsDelta) -> ControlFlow<()>,
+{
+    event_loop.stop = false;
+    lifecycle::emit(event_loop, AeLifecycleEvent::LoopStarted);
     while !event_loop.stop {
+        let before = AeStatsDelta::totals(&event_loop.stats.stats);
+        let started_us = event_loop.now_us();
         ae_process_events(
             event_loop,
             AE_ALL_EVENTS | AE_CALL_BEFORE_SLEEP | AE_CALL_AFTER_SLEEP,
         );
+        let delta = AeStatsDelta {
+            interval_us: event_loop.now_us().saturating_sub(started_us),
+            ..AeStatsDelta::totals(&event_loop.stats.stats).since(&before)
+        };
+        if on_iteration(&delta).is_break() {
+            break;
+        }
     }
+    lifecycle::emit(event_loop, AeLifecycleEvent::LoopStopped);
 }
 
-fn create_select_backend() -> Result<Box<dyn EventBackend>, i32> {
+pub(crate) fn create_select_backend() -> Result<Box<dyn EventBackend>, i32> {
     SelectBackend::create().map(|backend| backend as Box<dyn EventBackend>)
 }
 
 struct SelectBackend {
     state: Box<ae_select::aeApiState>,
+    stats: stats::AeBackendStats,
 }
 
 impl EventBackend for SelectBackend {
     fn create() -> Result<Box<Self>, i32> {
         match ae_select::ae_api_create() {
-            Ok(state) => Ok(Box::new(SelectBackend { state })),
+            Ok(state) => Ok(Box::new(SelectBackend {
+                state,
+                stats: stats::AeBackendStats::default(),
+            })),
             Err(e) => Err(e),
         }
     }
@@ -292,12 +1311,25 @@ impl EventBackend for SelectBackend {
         maxfd: i32,
         timeout: Option<Duration>,
     ) -> Result<i32, i32> {
-        ae_select::ae_api_poll(&mut self.state, events, fired, maxfd, timeout)
+        let result = ae_select::ae_api_poll(&mut self.state, events, fired, maxfd, timeout);
+        match result {
+            Ok(numevents) => self.stats.record_poll(numevents, 0),
+            Err(err) => self.stats.record_poll(-1, err),
+        }
+        result
     }
 
     fn name(&self) -> &'static str {
         ae_select::ae_api_name()
     }
+
+    fn stats(&self) -> stats::AeBackendStats {
+        self.stats
+    }
+
+    fn reset_stats(&mut self) {
+        self.stats = stats::AeBackendStats::default();
+    }
 }
 
 pub fn ae_create_file_event(
@@ -307,7 +1339,44 @@ pub fn ae_create_file_event(
     proc: FileProc,
     client_data: *mut std::ffi::c_void,
 ) -> i32 {
+    let retval = create_file_event(event_loop, fd, mask, proc, client_data, false);
+    invariants::after(event_loop, "ae_create_file_event");
+    retval
+}
+
+fn create_file_event(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    mask: i32,
+    proc: FileProc,
+    client_data: *mut std::ffi::c_void,
+    exclusive: bool,
+) -> i32 {
+    if fd < 0 {
+        set_errno(libc::EBADF);
+        return AE_ERR;
+    }
     if fd >= event_loop.setsize {
+        /* Always the case for a timers-only loop, see ae_create_event_loop(). */
+        set_errno(libc::ERANGE);
+        return AE_ERR;
+    }
+    if let Some(fe) = event_loop.events.get(fd as usize)
+        && (fe.exclusive || exclusive)
+        && (fe.mask != AE_NONE || mask & !AE_READABLE != 0)
+    {
+        /* See AeFileEventOptions::exclusive. */
+        return AE_ERR;
+    }
+    if exclusive && mask != AE_READABLE {
+        return AE_ERR;
+    }
+    if mask & AE_ERRQUEUE != 0 && !event_loop.apidata.supports_errqueue() {
+        set_errno(libc::ENOTSUP);
+        return AE_ERR;
+    }
+    let stdio = (0..=2).contains(&fd) && mask != AE_NONE;
+    if stdio && event_loop.stdio_policy == AeStdioPolicy::Deny {
         return AE_ERR;
     }
 
@@ -329,44 +1398,125 @@ pub fn ae_create_file_event(
             event_loop.events.push(AeFileEvent::new());
         }
 
-        while event_loop.fired.len() < (new_nevents as usize) {
+        while event_loop.fired.len()
+            < poll_batch(new_nevents as usize).min(event_loop.fired_capacity)
+        {
             event_loop.fired.push(FiredEvent { fd: 0, mask: 0 });
         }
 
         event_loop.nevents = new_nevents;
     }
 
-    if event_loop.apidata.add_event(fd, mask) == -1 {
-        return AE_ERR;
+    /* While paused the interest is only recorded, ae_resume() hands it
+     * to the backend. */
+    if event_loop.paused_at.is_none() {
+        let added = if exclusive {
+            event_loop.apidata.add_event_exclusive(fd, mask)
+        } else {
+            event_loop.apidata.add_event(fd, mask)
+        };
+        if added == -1 {
+            return AE_ERR;
+        }
     }
     let fe = &mut event_loop.events[fd as usize];
+    fe.exclusive = exclusive;
+    if fe.mask & (AE_READABLE | AE_WRITABLE) == AE_NONE {
+        fe.write_first = mask & AE_WRITABLE != 0 && mask & AE_READABLE == 0;
+    }
+    let new_registration = fe.mask == AE_NONE && mask != AE_NONE;
+    if new_registration {
+        event_loop.registered_fds += 1;
+    }
+    let arming_write = mask & AE_WRITABLE != 0 && fe.mask & AE_WRITABLE == 0;
     fe.mask |= mask;
 
     if mask & AE_READABLE != 0 {
         fe.rfile_proc = Some(proc);
+        /* Registering AE_READABLE re-arms a coalesced fd. */
+        fe.read_suppressed_until = 0;
+        fe.read_rearmed = true;
     }
     if mask & AE_WRITABLE != 0 {
         fe.wfile_proc = Some(proc);
     }
+    if mask & AE_ERRQUEUE != 0 {
+        fe.efile_proc = Some(proc);
+    }
 
     fe.client_data = client_data;
+    fe.wclient_data = client_data;
 
-    if fd > event_loop.maxfd {
+    /* An empty mask registers nothing, the poll need not reach the fd. */
+    if fd > event_loop.maxfd && fe.mask != AE_NONE {
         event_loop.maxfd = fd;
     }
 
+    if arming_write {
+        watchdog::armed(event_loop, fd);
+    }
+    if new_registration {
+        limits::check_fds(event_loop);
+    }
+    if stdio && new_registration && event_loop.stdio_policy == AeStdioPolicy::Warn {
+        doctor::raise(event_loop, doctor::stdio_finding(fd, mask));
+    }
+    module::run_hooks(event_loop, |m, el| m.on_fd_register(el, fd, mask));
+
+    AE_OK
+}
+
+/* Like ae_create_file_event(), applying `options` to the fd. The options
+ * replace the ones of a previous registration of the same fd. */
+pub fn ae_create_file_event_ex(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    mask: i32,
+    proc: FileProc,
+    client_data: *mut std::ffi::c_void,
+    options: AeFileEventOptions,
+) -> i32 {
+    /* The data of directions not registered here, null for a slot that
+     * is free (and may hold the data of its previous user). */
+    let previous = match event_loop.events.get(fd as usize) {
+        Some(fe) if fe.mask != AE_NONE => (fe.client_data, fe.wclient_data),
+        _ => (std::ptr::null_mut(), std::ptr::null_mut()),
+    };
+    if create_file_event(event_loop, fd, mask, proc, client_data, options.exclusive) == AE_ERR {
+        invariants::after(event_loop, "ae_create_file_event_ex");
+        return AE_ERR;
+    }
+    let fe = &mut event_loop.events[fd as usize];
+    fe.tag = options.tag;
+    fe.priority = options.priority;
+    fe.read_coalescing = options.read_coalescing;
+    if options.split_client_data {
+        /* The error queue handler shares the data of the read one. */
+        if mask & (AE_READABLE | AE_ERRQUEUE) == 0 {
+            fe.client_data = previous.0;
+        }
+        if mask & AE_WRITABLE == 0 {
+            fe.wclient_data = previous.1;
+        }
+    }
+    invariants::after(event_loop, "ae_create_file_event_ex");
     AE_OK
 }
 
 pub fn ae_delete_file_event(event_loop: &mut AeEventLoop, fd: i32, mask: i32) {
+    delete_file_event(event_loop, fd, mask);
+    invariants::after(event_loop, "ae_delete_file_event");
+}
+
+fn delete_file_event(event_loop: &mut AeEventLoop, fd: i32, mask: i32) {
     if fd >= event_loop.setsize {
         return;
     }
 
-    let fe = &mut event_loop.events[fd as usize];
-    if fe.mask == AE_NONE {
-        return;
-    }
+    let fe = match event_loop.events.get_mut(fd as usize) {
+        Some(fe) if fe.mask != AE_NONE => fe,
+        _ => return,
+    };
 
     /* We want to always remove AE_BARRIER if set when AE_WRITABLE
      * is removed. */
@@ -375,16 +1525,33 @@ pub fn ae_delete_file_event(event_loop: &mut AeEventLoop, fd: i32, mask: i32) {
         mask_to_remove |= AE_BARRIER;
     }
 
-    event_loop.apidata.del_event(fd, mask_to_remove);
+    if event_loop.paused_at.is_none() {
+        event_loop.apidata.del_event(fd, mask_to_remove);
+    }
     fe.mask &= !mask_to_remove;
 
     if mask_to_remove & AE_READABLE != 0 {
         fe.rfile_proc = None;
     }
+    if mask_to_remove & AE_ERRQUEUE != 0 {
+        fe.efile_proc = None;
+    }
+    let disarming_write = mask_to_remove & AE_WRITABLE != 0 && fe.wfile_proc.is_some();
     if mask_to_remove & AE_WRITABLE != 0 {
         fe.wfile_proc = None;
     }
 
+    if fe.mask == AE_NONE {
+        fe.tag = 0;
+        fe.exclusive = false;
+        fe.priority = 0;
+        fe.read_coalescing = AeReadCoalescing::Off;
+        fe.read_suppressed_until = 0;
+        fe.generation += 1;
+        fe.dispatches = 0;
+        event_loop.registered_fds -= 1;
+    }
+
     if fd == event_loop.maxfd && fe.mask == AE_NONE {
         /* Update the max fd */
         let mut j = event_loop.maxfd - 1;
@@ -396,6 +1563,9 @@ pub fn ae_delete_file_event(event_loop: &mut AeEventLoop, fd: i32, mask: i32) {
         }
         event_loop.maxfd = j;
     }
+    if disarming_write {
+        watchdog::disarmed(event_loop, fd);
+    }
 }
 
 pub fn ae_get_file_client_data(event_loop: &AeEventLoop, fd: i32) -> *mut std::ffi::c_void {
@@ -403,12 +1573,24 @@ pub fn ae_get_file_client_data(event_loop: &AeEventLoop, fd: i32) -> *mut std::f
         return std::ptr::null_mut();
     }
 
-    let fe = &event_loop.events[fd as usize];
-    if fe.mask == AE_NONE {
+    match event_loop.events.get(fd as usize) {
+        Some(fe) if fe.mask != AE_NONE => fe.client_data,
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/* Data given to the write handler of the fd. Same as
+ * ae_get_file_client_data() unless the fd was registered with
+ * AeFileEventOptions::split_client_data. */
+pub fn ae_get_file_write_client_data(event_loop: &AeEventLoop, fd: i32) -> *mut std::ffi::c_void {
+    if fd >= event_loop.setsize {
         return std::ptr::null_mut();
     }
 
-    fe.client_data
+    match event_loop.events.get(fd as usize) {
+        Some(fe) if fe.mask != AE_NONE => fe.wclient_data,
+        _ => std::ptr::null_mut(),
+    }
 }
 
 pub fn ae_get_file_events(event_loop: &AeEventLoop, fd: i32) -> i32 {
@@ -416,35 +1598,312 @@ pub fn ae_get_file_events(event_loop: &AeEventLoop, fd: i32) -> i32 {
         return 0;
     }
 
-    event_loop.events[fd as usize].mask
+    /* The events array only grows up to the highest fd ever registered. */
+    event_loop
+        .events
+        .get(fd as usize)
+        .map_or(AE_NONE, |fe| fe.mask)
+}
+
+/* Generation of the fd slot: it changes every time all the events of the
+ * fd are deleted, i.e. when the fd number may be handed to a different
+ * file. Callbacks acting on behalf of another fd can compare it with the
+ * value seen at registration time to detect a stale fd. */
+pub fn ae_get_file_generation(event_loop: &AeEventLoop, fd: i32) -> u64 {
+    if fd < 0 {
+        return 0;
+    }
+    event_loop
+        .events
+        .get(fd as usize)
+        .map_or(0, |fe| fe.generation)
+}
+
+/* Times the loop dispatched the handlers of the fd since it was
+ * registered: once per poll it fired in, whatever the directions. 0 if
+ * the fd is not registered. */
+pub fn ae_get_file_dispatches(event_loop: &AeEventLoop, fd: i32) -> u64 {
+    if fd < 0 {
+        return 0;
+    }
+    event_loop
+        .events
+        .get(fd as usize)
+        .filter(|fe| fe.mask != AE_NONE)
+        .map_or(0, |fe| fe.dispatches)
+}
+
+/* Tag given to ae_create_file_event_ex() for this fd, 0 if the fd is not
+ * registered or was registered without one. */
+pub fn ae_get_file_tag(event_loop: &AeEventLoop, fd: i32) -> u32 {
+    if fd < 0 {
+        return 0;
+    }
+    match event_loop.events.get(fd as usize) {
+        Some(fe) if fe.mask != AE_NONE => fe.tag,
+        _ => 0,
+    }
 }
 
+/* Have the next iteration dispatch `mask` (AE_READABLE, AE_WRITABLE or
+ * both) on `fd` as if the backend had reported it, whether the fd is
+ * ready or not, e.g. to get back to input a handler left buffered in user
+ * space. The next poll does not block. The event is merged with what the
+ * backend reports for the fd, and dropped if the fd is deleted before.
+ * Returns AE_ERR if the fd is not registered for every direction of
+ * `mask`. */
+pub fn ae_fire_event(event_loop: &mut AeEventLoop, fd: i32, mask: i32) -> i32 {
+    let mask = mask & (AE_READABLE | AE_WRITABLE);
+    if fd < 0 || mask == AE_NONE {
+        return AE_ERR;
+    }
+    let generation = match event_loop.events.get(fd as usize) {
+        Some(fe) if fe.mask & mask == mask => fe.generation,
+        _ => return AE_ERR,
+    };
+    event_loop.injected.push(DispatchEntry {
+        fd,
+        mask,
+        generation,
+    });
+    AE_OK
+}
+
+/* A negative delay is due right away. See ae_create_time_event_duration()
+ * for delays finer than a millisecond. */
 pub fn ae_create_time_event(
     event_loop: &mut AeEventLoop,
     milliseconds: i64,
     proc: TimeProc,
     client_data: *mut std::ffi::c_void,
     finalizer_proc: Option<EventFinalizerProc>,
+) -> i64 {
+    let delay = ms_duration(milliseconds);
+    ae_create_time_event_duration(event_loop, delay, proc, client_data, finalizer_proc)
+}
+
+/* ae_create_time_event() taking the delay as a Duration, kept to the
+ * microsecond. The delays the proc returns are still milliseconds. */
+pub fn ae_create_time_event_duration(
+    event_loop: &mut AeEventLoop,
+    delay: Duration,
+    proc: TimeProc,
+    client_data: *mut std::ffi::c_void,
+    finalizer_proc: Option<EventFinalizerProc>,
+) -> i64 {
+    create_time_event_us(
+        event_loop,
+        duration_us(delay),
+        proc,
+        client_data,
+        finalizer_proc,
+    )
+}
+
+/* The Duration taking variants of the public API convert with these: to
+ * the microsecond, saturating, or to whole milliseconds, rounded up so
+ * that a short duration is not taken for 0. */
+pub(crate) fn duration_us(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+pub(crate) fn duration_ms(duration: Duration) -> i64 {
+    i64::try_from(duration.as_micros().div_ceil(1000)).unwrap_or(i64::MAX)
+}
+
+/* The integer variants, with `ms` <= 0 as Duration::ZERO. */
+pub(crate) fn ms_duration(ms: i64) -> Duration {
+    Duration::from_millis(ms.max(0) as u64)
+}
+
+/* ae_create_time_event() with a delay in microseconds. */
+pub(crate) fn create_time_event_us(
+    event_loop: &mut AeEventLoop,
+    delay_us: u64,
+    proc: TimeProc,
+    client_data: *mut std::ffi::c_void,
+    finalizer_proc: Option<EventFinalizerProc>,
 ) -> i64 {
     let id = event_loop.time_event_next_id;
     event_loop.time_event_next_id += 1;
 
-    let when = get_monotonic_us() + (milliseconds * 1000) as u64;
+    let when = event_loop.now_us().saturating_add(delay_us);
     let time_event = AeTimeEvent::new(id, when, Some(proc), finalizer_proc, client_data);
 
     let mut new_node = Box::new(TimeEventNode::new(time_event));
     new_node.next = event_loop.time_event_head.take();
     event_loop.time_event_head = Some(new_node);
+    event_loop.live_time_events += 1;
+    limits::check_timers(event_loop);
 
     id
 }
 
+/* State of a timer created with ae_create_time_event_owned(). */
+struct OwnedTimer<T> {
+    proc: OwnedTimeProc<T>,
+    data: Box<T>,
+}
+
+fn owned_timer_proc<T>(
+    event_loop: &mut AeEventLoop,
+    id: i64,
+    client_data: *mut std::ffi::c_void,
+) -> i32 {
+    /* The timer is referenced while its proc runs, so the state cannot be
+     * finalized under our feet even if the proc deletes the timer. */
+    let timer = unsafe { &mut *(client_data as *mut OwnedTimer<T>) };
+    (timer.proc)(event_loop, id, &mut timer.data)
+}
+
+fn owned_timer_finalizer<T>(_event_loop: &mut AeEventLoop, client_data: *mut std::ffi::c_void) {
+    drop(unsafe { Box::from_raw(client_data as *mut OwnedTimer<T>) });
+}
+
+/* Like ae_create_time_event(), but the timer owns `data`: the proc gets
+ * it by reference and it is dropped when the timer is finalized (after
+ * AE_NOMORE, ae_delete_time_event() or when the loop is deleted). */
+pub fn ae_create_time_event_owned<T: 'static>(
+    event_loop: &mut AeEventLoop,
+    milliseconds: i64,
+    proc: OwnedTimeProc<T>,
+    data: Box<T>,
+) -> i64 {
+    let timer = Box::new(OwnedTimer { proc, data });
+    ae_create_time_event(
+        event_loop,
+        milliseconds,
+        owned_timer_proc::<T>,
+        Box::into_raw(timer) as *mut std::ffi::c_void,
+        Some(owned_timer_finalizer::<T>),
+    )
+}
+
+struct PeriodicTimer {
+    proc: PeriodicTimeProc,
+    finalizer_proc: Option<EventFinalizerProc>,
+    client_data: *mut std::ffi::c_void,
+    period_us: u64,
+    /* Scheduled time of the next call. Firings are anchored to it rather
+     * than to the time the previous call returned, so the period does not
+     * drift. */
+    next_us: u64,
+}
+
+fn periodic_timer_proc(
+    event_loop: &mut AeEventLoop,
+    id: i64,
+    client_data: *mut std::ffi::c_void,
+) -> i32 {
+    let timer = unsafe { &mut *(client_data as *mut PeriodicTimer) };
+    let now = event_loop.now_us();
+    let overruns = now.saturating_sub(timer.next_us) / timer.period_us;
+    timer.next_us += (overruns + 1) * timer.period_us;
+
+    if (timer.proc)(event_loop, id, overruns, timer.client_data) == AE_NOMORE {
+        return AE_NOMORE;
+    }
+    /* Rearmed to the microsecond, periods may be shorter than the
+     * millisecond a return value counts in. */
+    let mut current = &mut event_loop.time_event_head;
+    while let Some(node) = current {
+        if node.event.id == id {
+            node.event.rearm_at = Some(timer.next_us);
+            break;
+        }
+        current = &mut node.next;
+    }
+    0
+}
+
+fn periodic_timer_finalizer(event_loop: &mut AeEventLoop, client_data: *mut std::ffi::c_void) {
+    let timer = unsafe { Box::from_raw(client_data as *mut PeriodicTimer) };
+    if let Some(finalizer) = timer.finalizer_proc {
+        finalizer(event_loop, timer.client_data);
+    }
+}
+
+/* Call `proc` every `period_ms` milliseconds (the first time one period
+ * from now) until it returns AE_NOMORE; any other return value keeps the
+ * schedule. When the loop was too busy to run the timer on time, the
+ * missed periods are skipped and reported to the proc as `overruns`, like
+ * the expiration count of a timerfd.
+ *
+ * Returns the timer id, or AE_ERR if period_ms is not positive. */
+pub fn ae_create_periodic_event(
+    event_loop: &mut AeEventLoop,
+    period_ms: i64,
+    proc: PeriodicTimeProc,
+    client_data: *mut std::ffi::c_void,
+    finalizer_proc: Option<EventFinalizerProc>,
+) -> i64 {
+    ae_create_periodic_event_duration(
+        event_loop,
+        ms_duration(period_ms),
+        proc,
+        client_data,
+        finalizer_proc,
+    )
+}
+
+/* ae_create_periodic_event() taking the period as a Duration, kept to
+ * the microsecond. Returns AE_ERR for a zero period. */
+pub fn ae_create_periodic_event_duration(
+    event_loop: &mut AeEventLoop,
+    period: Duration,
+    proc: PeriodicTimeProc,
+    client_data: *mut std::ffi::c_void,
+    finalizer_proc: Option<EventFinalizerProc>,
+) -> i64 {
+    let period_us = duration_us(period);
+    if period_us == 0 {
+        return AE_ERR as i64;
+    }
+    let timer = Box::new(PeriodicTimer {
+        proc,
+        finalizer_proc,
+        client_data,
+        period_us,
+        next_us: event_loop.now_us().saturating_add(period_us),
+    });
+    create_time_event_us(
+        event_loop,
+        period_us,
+        periodic_timer_proc,
+        Box::into_raw(timer) as *mut std::ffi::c_void,
+        Some(periodic_timer_finalizer),
+    )
+}
+
 pub fn ae_delete_time_event(event_loop: &mut AeEventLoop, id: i64) -> i32 {
     let mut current = &mut event_loop.time_event_head;
 
     while let Some(node) = current {
-        if node.event.id == id {
-            node.event.id = AE_DELETED_EVENT_ID;
+        if node.event.id == id && node.event.mark_deleted() {
+            event_loop.live_time_events -= 1;
+            return AE_OK;
+        }
+        current = &mut node.next;
+    }
+
+    AE_ERR
+}
+
+/* Randomize the period of a periodic timer: every time its proc returns a
+ * delay, the next firing is moved by up to `percent` percent of that delay
+ * in either direction, so that timers started in lockstep across many
+ * processes spread out instead of firing together. 0 disables jitter.
+ *
+ * Returns AE_ERR if there is no such timer or percent is over 100. */
+pub fn ae_set_time_event_jitter(event_loop: &mut AeEventLoop, id: i64, percent: u32) -> i32 {
+    if percent > 100 {
+        return AE_ERR;
+    }
+    let mut current = &mut event_loop.time_event_head;
+
+    while let Some(node) = current {
+        if node.event.id == id && !node.event.deleted {
+            node.event.jitter_pct = percent;
             return AE_OK;
         }
         current = &mut node.next;
@@ -453,10 +1912,89 @@ pub fn ae_delete_time_event(event_loop: &mut AeEventLoop, id: i64) -> i32 {
     AE_ERR
 }
 
+/* Give a timer a soft budget of `budget_us` microseconds per call, 0 to
+ * remove it. The loop does not interrupt a callback running past it;
+ * once the callback returns the overrun is counted in
+ * AeStats::timer_overruns, logged in the slowlog (see ae_slowlog_get(),
+ * under the dispatch label, "timer" without one) and `policy` applies.
+ * Members of a timer batch have no budget of their own.
+ *
+ * Returns AE_ERR if there is no such timer. */
+pub fn ae_set_time_event_budget(
+    event_loop: &mut AeEventLoop,
+    id: i64,
+    budget_us: u64,
+    policy: AeOverrunPolicy,
+) -> i32 {
+    ae_set_time_event_budget_duration(event_loop, id, Duration::from_micros(budget_us), policy)
+}
+
+/* ae_set_time_event_budget() taking the budget as a Duration,
+ * Duration::ZERO to remove it. */
+pub fn ae_set_time_event_budget_duration(
+    event_loop: &mut AeEventLoop,
+    id: i64,
+    budget: Duration,
+    policy: AeOverrunPolicy,
+) -> i32 {
+    let budget_us = duration_us(budget);
+    let mut current = &mut event_loop.time_event_head;
+
+    while let Some(node) = current {
+        if node.event.id == id && !node.event.deleted {
+            node.event.budget_us = budget_us;
+            node.event.overrun_policy = policy;
+            return AE_OK;
+        }
+        current = &mut node.next;
+    }
+
+    AE_ERR
+}
+
+/* Budget of a timer callback in microseconds (0 for none) and its
+ * overrun policy, see ae_set_time_event_budget(). */
+type TimerBudget = (u64, AeOverrunPolicy);
+
+/* Check a timer callback that returned `retval` after `took_us` against
+ * its budget. Returns the delay to reschedule it with, longer than
+ * `retval` when AeOverrunPolicy::Skip skips firings. */
+fn check_time_event_budget(
+    event_loop: &mut AeEventLoop,
+    id: i64,
+    (budget_us, policy): TimerBudget,
+    label: Option<&'static str>,
+    started_us: u64,
+    took_us: u64,
+    retval: i32,
+) -> i32 {
+    if took_us <= budget_us {
+        return retval;
+    }
+
+    event_loop.stats.stats.timer_overruns += 1;
+    job::log_slow(
+        event_loop,
+        id,
+        label.unwrap_or("timer"),
+        started_us,
+        budget_us,
+        took_us,
+    );
+    if policy != AeOverrunPolicy::Skip || retval <= 0 {
+        return retval;
+    }
+    let skipped = took_us / (retval as u64 * 1000);
+    event_loop.stats.stats.timer_firings_skipped += skipped;
+    (retval as u64)
+        .saturating_mul(skipped + 1)
+        .min(i32::MAX as u64) as i32
+}
+
 /* How many microseconds until the first timer should fire.
  * If there are no timers, -1 is returned.
  */
-fn us_until_earliest_timer(event_loop: &AeEventLoop) -> i64 {
+pub(crate) fn us_until_earliest_timer(event_loop: &AeEventLoop) -> i64 {
     if event_loop.time_event_head.is_none() {
         return -1;
     }
@@ -466,15 +2004,14 @@ fn us_until_earliest_timer(event_loop: &AeEventLoop) -> i64 {
 
     while let Some(node) = current {
         let te = &node.event;
-        if te.id != AE_DELETED_EVENT_ID && (earliest.is_none() || te.when < earliest.unwrap().when)
-        {
+        if !te.deleted && (earliest.is_none() || te.when < earliest.unwrap().when) {
             earliest = Some(te);
         }
         current = &node.next;
     }
 
     if let Some(earliest_event) = earliest {
-        let now = get_monotonic_us();
+        let now = event_loop.now_us();
         if now >= earliest_event.when {
             0
         } else {
@@ -486,24 +2023,30 @@ fn us_until_earliest_timer(event_loop: &AeEventLoop) -> i64 {
 }
 
 /* Process time events */
+/* Timers due at this iteration: a timer of its own, or the due members
+ * of a batch, dispatched together where the first of them was found. */
+enum DueTimers {
+    One(i64),
+    Batch(u64, Vec<i64>),
+}
+
 fn process_time_events(event_loop: &mut AeEventLoop) -> i32 {
     let mut processed = 0;
     let max_id = event_loop.time_event_next_id - 1;
-    let now = get_monotonic_us();
+    let now = event_loop.now_us();
 
     /* First, collect events that need to be processed */
     let mut events_to_process = Vec::new();
-    let mut events_to_remove = Vec::new();
+    let mut batch_slots: HashMap<u64, usize> = HashMap::new();
+    let mut deferred = Vec::new();
+    let mut earliest: Option<u64> = None;
 
     let mut current = &mut event_loop.time_event_head;
     while let Some(node) = current {
         let te = &mut node.event;
 
-        /* Remove events scheduled for deletion. */
-        if te.id == AE_DELETED_EVENT_ID {
-            if te.refcount == 0 {
-                events_to_remove.push(te.id);
-            }
+        /* Events scheduled for deletion are removed at the end. */
+        if te.deleted {
             current = &mut node.next;
             continue;
         }
@@ -515,56 +2058,238 @@ fn process_time_events(event_loop: &mut AeEventLoop) -> i32 {
         }
 
         if te.when <= now {
-            events_to_process.push(te.id);
+            earliest = Some(earliest.map_or(te.when, |when| when.min(te.when)));
+            if te.deferred {
+                deferred.push(te.id);
+            }
+            match te.batch {
+                None => events_to_process.push(DueTimers::One(te.id)),
+                Some(batch) => match batch_slots.get(&batch) {
+                    Some(&slot) => {
+                        if let DueTimers::Batch(_, ids) = &mut events_to_process[slot] {
+                            ids.push(te.id);
+                        }
+                    }
+                    None => {
+                        batch_slots.insert(batch, events_to_process.len());
+                        events_to_process.push(DueTimers::Batch(batch, vec![te.id]));
+                    }
+                },
+            }
         }
 
         current = &mut node.next;
     }
 
-    for event_id in events_to_process {
-        let mut event_found = false;
-        let mut time_proc_to_call: Option<TimeProc> = None;
-        let mut client_data: *mut std::ffi::c_void = std::ptr::null_mut();
+    /* Past the deferred limit, the newest calls wait for the next
+     * iteration, see ae_set_deferred_limit(). */
+    let limit = event_loop.deferred_limit;
+    if limit > 0 && deferred.len() > limit {
+        deferred.sort_unstable();
+        let postponed: HashSet<i64> = deferred[limit..].iter().copied().collect();
+        events_to_process
+            .retain(|due| !matches!(due, DueTimers::One(id) if postponed.contains(id)));
+        event_loop.stats.stats.deferred_postponed += postponed.len() as u64;
+    }
 
-        let mut current = &mut event_loop.time_event_head;
-        while let Some(node) = current {
-            let te = &mut node.event;
-            if te.id == event_id && te.id != AE_DELETED_EVENT_ID && te.when <= now {
-                te.refcount += 1;
-                time_proc_to_call = te.time_proc;
-                client_data = te.client_data;
-                event_found = true;
-                break;
+    for due in events_to_process {
+        match due {
+            DueTimers::One(event_id) => {
+                let Some((time_proc, client_data, budget)) =
+                    acquire_time_event(event_loop, event_id, now)
+                else {
+                    continue;
+                };
+                let Some(time_proc) = time_proc else {
+                    release_time_event(event_loop, event_id, AE_NOMORE, event_loop.now_us());
+                    continue;
+                };
+                let callback_start = stats::callback_start(event_loop);
+                /* Timed on the loop clock, for budgeted timers only. */
+                let budgeted = budget.0 > 0;
+                let started_us = if budgeted { event_loop.now_us() } else { 0 };
+                context::push(event_loop, AeDispatchSource::Timer { id: event_id });
+                /* A timer that panicked is dropped, see AePanicPolicy. */
+                let retval = panic::guard(event_loop, |el| time_proc(el, event_id, client_data))
+                    .unwrap_or(AE_NOMORE);
+                let label =
+                    context::ae_current_dispatch(event_loop).and_then(|record| record.label);
+                context::pop(event_loop, retval);
+                processed += 1;
+
+                stats::record_callback(event_loop, callback_start);
+                let updated_now = event_loop.now_us();
+                let retval = if budgeted {
+                    check_time_event_budget(
+                        event_loop,
+                        event_id,
+                        budget,
+                        label,
+                        started_us,
+                        updated_now.saturating_sub(started_us),
+                        retval,
+                    )
+                } else {
+                    retval
+                };
+                release_time_event(event_loop, event_id, retval, updated_now);
+            }
+            DueTimers::Batch(batch, ids) => {
+                /* Members deleted by an earlier callback of this iteration
+                 * are left out, a deleted batch is not called at all. */
+                let Some((batch_proc, client_data)) = event_loop.timer_batches.get(batch) else {
+                    continue;
+                };
+                let fired: Vec<i64> = ids
+                    .into_iter()
+                    .filter(|&id| acquire_time_event(event_loop, id, now).is_some())
+                    .collect();
+                if fired.is_empty() {
+                    continue;
+                }
+                let callback_start = stats::callback_start(event_loop);
+                context::push(
+                    event_loop,
+                    AeDispatchSource::TimerBatch {
+                        batch: timer_batch::AeTimerBatchId(batch),
+                        timers: fired.len(),
+                    },
+                );
+                let retval = panic::guard(event_loop, |el| batch_proc(el, &fired, client_data))
+                    .unwrap_or(AE_NOMORE);
+                context::pop(event_loop, retval);
+                processed += fired.len() as i32;
+
+                stats::record_callback(event_loop, callback_start);
+                let updated_now = event_loop.now_us();
+                for id in fired {
+                    release_time_event(event_loop, id, retval, updated_now);
+                }
             }
-            current = &mut node.next;
         }
+    }
 
-        if event_found && let Some(time_proc) = time_proc_to_call {
-            let retval = time_proc(event_loop, event_id, client_data);
-            processed += 1;
+    if let Some(earliest) = earliest {
+        doctor::record_earliest_timer(event_loop, earliest, now);
+    }
+    cleanup_deleted_time_events(event_loop);
 
-            let updated_now = get_monotonic_us();
+    processed
+}
 
-            let mut current = &mut event_loop.time_event_head;
-            while let Some(node) = current {
-                let te = &mut node.event;
-                if te.id == event_id {
-                    te.refcount -= 1;
-                    if retval != AE_NOMORE {
-                        te.when = updated_now + (retval * 1000) as u64;
-                    } else {
-                        te.id = AE_DELETED_EVENT_ID;
-                    }
-                    break;
+/* Reference a live timer due at `now` for its callback, and record its
+ * lag. Returns its proc, client data and budget, None if it is gone or no
+ * longer due. */
+fn acquire_time_event(
+    event_loop: &mut AeEventLoop,
+    event_id: i64,
+    now: u64,
+) -> Option<(Option<TimeProc>, *mut std::ffi::c_void, TimerBudget)> {
+    let mut current = &mut event_loop.time_event_head;
+    while let Some(node) = current {
+        let te = &mut node.event;
+        if te.id == event_id && !te.deleted && te.when <= now {
+            te.refcount += 1;
+            let found = (
+                te.time_proc,
+                te.client_data,
+                (te.budget_us, te.overrun_policy),
+            );
+            let lag = now - te.when;
+            stats::record_timer_lag(event_loop, lag);
+            return Some(found);
+        }
+        current = &mut node.next;
+    }
+    None
+}
+
+/* Release a timer after its callback returned `retval`: delete it on
+ * AE_NOMORE, otherwise schedule it again. */
+fn release_time_event(event_loop: &mut AeEventLoop, event_id: i64, retval: i32, updated_now: u64) {
+    let random = event_loop.next_random();
+    let mut current = &mut event_loop.time_event_head;
+    while let Some(node) = current {
+        let te = &mut node.event;
+        if te.id == event_id {
+            te.refcount -= 1;
+            /* A timer deleted by its own callback stays deleted,
+             * whatever the callback returned. */
+            if retval == AE_NOMORE {
+                if te.mark_deleted() {
+                    event_loop.live_time_events -= 1;
                 }
-                current = &mut node.next;
+            } else if !te.deleted {
+                let delay_us = match te.rearm_at.take() {
+                    Some(at) => at.saturating_sub(updated_now),
+                    None => (retval.max(0) as u64).saturating_mul(1000),
+                };
+                te.when =
+                    updated_now.saturating_add(jittered_delay_us(delay_us, te.jitter_pct, random));
             }
+            break;
         }
+        current = &mut node.next;
     }
+}
 
-    cleanup_deleted_time_events(event_loop);
+/* Call a file proc under the panic policy. The handlers of an fd stop
+ * being called once one of them panicked and the loop carries on, see
+ * AePanicPolicy::Continue. */
+fn call_file_proc(
+    event_loop: &mut AeEventLoop,
+    proc: FileProc,
+    fd: i32,
+    client_data: *mut std::ffi::c_void,
+    mask: i32,
+) {
+    if panic::guard(event_loop, |el| proc(el, fd, client_data, mask)).is_none() {
+        ae_delete_file_event(event_loop, fd, AE_ALL_MASK);
+    }
+}
 
-    processed
+/* How long the next poll may block: None for no limit. */
+fn poll_timeout(event_loop: &AeEventLoop, flags: i32, dont_wait_once: bool) -> Option<Duration> {
+    let timeout = loop_poll_timeout(event_loop, flags, dont_wait_once);
+    /* External sources due before that, see ae_add_external_source(). */
+    let timeout = match event_loop.external.deadline() {
+        Some(deadline) if flags & AE_FILE_EVENTS != 0 => {
+            let due = Duration::from_micros(deadline.saturating_sub(event_loop.now_us()));
+            Some(timeout.map_or(due, |timeout| timeout.min(due)))
+        }
+        _ => timeout,
+    };
+    /* See AeEventLoopBuilder::max_poll_timeout(). */
+    match event_loop.max_poll_timeout {
+        Some(max) => Some(timeout.map_or(max, |timeout| timeout.min(max))),
+        None => timeout,
+    }
+}
+
+/* How long a loop driven from outside (see reactor.rs) may sleep before
+ * its next non-blocking iteration: None for no limit. */
+#[cfg(feature = "async-io")]
+pub(crate) fn idle_timeout(event_loop: &AeEventLoop) -> Option<Duration> {
+    poll_timeout(event_loop, AE_ALL_EVENTS, event_loop.dont_wait_once)
+}
+
+fn loop_poll_timeout(
+    event_loop: &AeEventLoop,
+    flags: i32,
+    dont_wait_once: bool,
+) -> Option<Duration> {
+    if (flags & AE_DONT_WAIT) != 0 || ae_get_dont_wait(event_loop) || dont_wait_once {
+        Some(Duration::from_secs(0)) // No wait
+    } else if (flags & AE_TIME_EVENTS) != 0 && event_loop.paused_at.is_none() {
+        let us_until_timer = us_until_earliest_timer(event_loop);
+        if us_until_timer >= 0 {
+            Some(Duration::from_micros(us_until_timer as u64))
+        } else {
+            None // Infinite wait
+        }
+    } else {
+        None // Infinite wait
+    }
 }
 
 pub fn ae_process_events(event_loop: &mut AeEventLoop, flags: i32) -> i32 {
@@ -575,175 +2300,322 @@ pub fn ae_process_events(event_loop: &mut AeEventLoop, flags: i32) -> i32 {
         return 0;
     }
 
+    reconfigure::apply(event_loop);
+    scope::reap(event_loop);
+    event_loop.cached_now_us = event_loop.now_us();
+    event_loop.iteration += 1;
+    dispatch::start(event_loop);
+    heartbeat::beat(event_loop);
+    let n = event_loop.stats.stats.iterations + 1;
+    lifecycle::emit(event_loop, AeLifecycleEvent::IterationBegin { n });
+    if (flags & AE_FILE_EVENTS) != 0 {
+        external::prepare(event_loop);
+    }
+
     /* Note that we want to call poll() even if there are no file events
      * to process as long as we want to process time events, in order to
-     * sleep until the next time event is ready to fire. */
-    if event_loop.maxfd != -1 || ((flags & AE_TIME_EVENTS) != 0 && (flags & AE_DONT_WAIT) == 0) {
+     * sleep until the next time event is ready to fire. A timer loop
+     * always waits, that is where the closures of its handles run. */
+    if event_loop.maxfd != -1
+        || ((flags & AE_TIME_EVENTS) != 0 && (flags & AE_DONT_WAIT) == 0)
+        || event_loop.parker.is_some()
+    {
         // Call beforesleep callback if present
         if let Some(beforesleep) = event_loop.beforesleep
             && (flags & AE_CALL_BEFORE_SLEEP) != 0
         {
             beforesleep(event_loop);
         }
-
-        // Determine timeout based on flags and time events
-        let timeout = if (flags & AE_DONT_WAIT) != 0 || (event_loop.flags & AE_DONT_WAIT) != 0 {
-            Some(Duration::from_secs(0)) // No wait
-        } else if (flags & AE_TIME_EVENTS) != 0 {
-            let us_until_timer = us_until_earliest_timer(event_loop);
-            if us_until_timer >= 0 {
-                Some(Duration::from_micros(us_until_timer as u64))
-            } else {
-                None // Infinite wait
+        /* Replies waiting for the log, see ae_group_commit_reply(). */
+        group_commit::commit(event_loop);
+
+        /* Events queued by ae_fire_event() are due now. */
+        let dont_wait_once = std::mem::take(&mut event_loop.dont_wait_once)
+            || ((flags & AE_FILE_EVENTS) != 0 && !event_loop.injected.is_empty());
+
+        module::run_hooks(event_loop, |m, el| m.before_poll(el));
+
+        /* See AeFiredOverflowPolicy::PollAgain. */
+        let mut polls = 0;
+        loop {
+            let (dispatched, saturated) =
+                poll_and_dispatch(event_loop, flags, dont_wait_once || polls > 0, polls == 0);
+            processed += dispatched;
+            polls += 1;
+            if !saturated
+                || !matches!(event_loop.fired_overflow, AeFiredOverflowPolicy::PollAgain)
+                || polls >= AE_FIRED_OVERFLOW_MAX_POLLS
+            {
+                break;
             }
-        } else {
-            None // Infinite wait
-        };
+        }
+    }
+
+    if (flags & AE_FILE_EVENTS) != 0 {
+        processed += external::dispatch(event_loop);
+    }
+
+    /* Check time events (frozen while the loop is paused) */
+    let file_processed = processed;
+    if (flags & AE_TIME_EVENTS) != 0 && event_loop.paused_at.is_none() {
+        processed += process_time_events(event_loop);
+    }
 
-        // Call the multiplexing API, will return only on timeout or when some event fires
-        let numevents = event_loop
-            .apidata
-            .poll(
+    stats::record_iteration(event_loop, file_processed, processed - file_processed);
+    module::run_hooks(event_loop, |m, el| m.after_dispatch(el, processed));
+    invariants::after(event_loop, "ae_process_events");
+
+    processed /* return the number of processed file/time events */
+}
+
+/* Poll the backend and dispatch the file events that fired. Returns the
+ * number of events dispatched and whether the poll filled the fired
+ * buffer, in which case more fds may be ready. */
+fn poll_and_dispatch(
+    event_loop: &mut AeEventLoop,
+    flags: i32,
+    dont_wait_once: bool,
+    first_poll: bool,
+) -> (i32, bool) {
+    let mut processed = 0;
+    // Call the multiplexing API, will return only on timeout or when some event fires
+    let poll_start = event_loop.now_us();
+    let numevents = loop {
+        /* Recomputed on every attempt, so that a retried poll does not
+         * sleep past the next timer. */
+        let timeout = poll_timeout(event_loop, flags, dont_wait_once);
+        let polled = if event_loop.setsize == 0 {
+            /* Timers-only loop: nothing to poll, the wait is a sleep. */
+            timer_loop::sleep(event_loop, timeout);
+            Ok(0)
+        } else if timeout == Some(Duration::ZERO) {
+            event_loop.apidata.poll_nowait(
                 &event_loop.events,
                 &mut event_loop.fired,
                 event_loop.maxfd,
-                timeout,
             )
-            .unwrap_or(0); // Error in polling, continue with 0 events
-
-        // Don't process file events if not requested
-        let numevents = if (flags & AE_FILE_EVENTS) != 0 {
-            numevents
         } else {
-            0
+            event_loop.apidata.poll(
+                &event_loop.events,
+                &mut event_loop.fired,
+                event_loop.maxfd,
+                timeout,
+            )
         };
-
-        // Call aftersleep callback if present
-        if let Some(aftersleep) = event_loop.aftersleep
-            && (flags & AE_CALL_AFTER_SLEEP) != 0
-        {
-            aftersleep(event_loop);
-        }
-
-        // Process file events
-        for j in 0..(numevents as usize) {
-            if j >= event_loop.fired.len() {
-                break;
-            }
-
-            let fd = event_loop.fired[j].fd;
-            let mask = event_loop.fired[j].mask;
-
-            if (fd as usize) >= event_loop.events.len() {
-                continue;
+        match polled {
+            Ok(numevents) => break numevents,
+            Err(libc::EINTR) => {
+                event_loop.stats.stats.interrupted_polls += 1;
+                let retry = match event_loop.eintr_policy {
+                    AeEintrPolicy::ReturnEarly => false,
+                    AeEintrPolicy::Retry => true,
+                    AeEintrPolicy::InvokeHook(hook) => hook(event_loop),
+                };
+                if !retry {
+                    break 0;
+                }
             }
+            Err(_) => break 0, // Error in polling, continue with 0 events
+        }
+    };
+    event_loop.cached_now_us = event_loop.now_us();
+    dispatch::start(event_loop);
+    let waited_us = event_loop.cached_now_us.saturating_sub(poll_start);
+    stats::record_poll(event_loop, waited_us);
+    differential::report(event_loop);
+    lifecycle::emit(
+        event_loop,
+        AeLifecycleEvent::PollReturned {
+            nfired: numevents,
+            waited_us,
+        },
+    );
+    let saturated = numevents > 0 && numevents as usize >= event_loop.fired.len();
+    if saturated {
+        fired_overflow(event_loop, numevents);
+    }
 
-            // Extract event info to avoid borrowing issues during callbacks
-            let fe_mask = event_loop.events[fd as usize].mask;
-            let rfile_proc = event_loop.events[fd as usize].rfile_proc;
-            let wfile_proc = event_loop.events[fd as usize].wfile_proc;
-            let client_data = event_loop.events[fd as usize].client_data;
-
-            let mut fired = 0; // Number of events fired for current fd
-
-            // Check if we should invert the calls (AE_BARRIER flag)
-            let invert = (fe_mask & AE_BARRIER) != 0;
+    // Don't process file events if not requested
+    let numevents = if (flags & AE_FILE_EVENTS) != 0 {
+        numevents
+    } else {
+        0
+    };
 
-            // Fire the readable event if the call sequence is not inverted
-            if !invert
-                && (fe_mask & mask & AE_READABLE) != 0
-                && let Some(rfile_proc) = rfile_proc
+    /* Snapshot what fired and for which registration, before any
+     * callback gets a chance to delete and reuse an fd, or to poll
+     * again from a nested ae_process_events(). */
+    let nfired = (numevents.max(0) as usize).min(event_loop.fired.len());
+    let mut dispatch_list = std::mem::take(&mut event_loop.dispatch_list);
+    dispatch_list.clear();
+    dispatch_list.extend(event_loop.fired[..nfired].iter().map(|fired| {
+        DispatchEntry {
+            fd: fired.fd,
+            mask: fired.mask,
+            generation: event_loop
+                .events
+                .get(fired.fd as usize)
+                .map_or(0, |fe| fe.generation),
+        }
+    }));
+    if (flags & AE_FILE_EVENTS) != 0 {
+        for injected in event_loop.injected.drain(..) {
+            match dispatch_list
+                .iter_mut()
+                .find(|entry| entry.fd == injected.fd && entry.generation == injected.generation)
             {
-                rfile_proc(event_loop, fd, client_data, mask);
-                fired += 1;
+                Some(entry) => entry.mask |= injected.mask,
+                None => dispatch_list.push(injected),
             }
+        }
+    }
+    order_dispatch_list(&event_loop.events, event_loop.fd_order, &mut dispatch_list);
+    event_loop.fired_batch.clear();
+    event_loop
+        .fired_batch
+        .extend(dispatch_list.iter().map(|entry| FiredEvent {
+            fd: entry.fd,
+            mask: entry.mask,
+        }));
+
+    // Call aftersleep callback if present
+    if let Some(aftersleep) = event_loop.aftersleep
+        && first_poll
+        && (flags & AE_CALL_AFTER_SLEEP) != 0
+    {
+        aftersleep(event_loop);
+    }
 
-            // Fire the writable event
-            if (fe_mask & mask & AE_WRITABLE) != 0 {
-                // Refresh event info in case of resize during callback
-                let current_fe_mask = if (fd as usize) < event_loop.events.len() {
-                    event_loop.events[fd as usize].mask
-                } else {
-                    0
-                };
+    // Process file events
+    for entry in &dispatch_list {
+        let mut entry = *entry;
+        /* Deleted by a previous callback of this iteration (or by the
+         * aftersleep callback). If the fd was registered again and the
+         * new file is ready, it fires on the next iteration. */
+        let (fe_mask, write_first, rfile_proc, wfile_proc, read_suppressed_until) =
+            match fired_registration(event_loop, &entry) {
+                Some(fe) => (
+                    fe.mask,
+                    fe.write_first,
+                    fe.rfile_proc,
+                    fe.wfile_proc,
+                    fe.read_suppressed_until,
+                ),
+                None => continue,
+            };
+        /* See AeReadCoalescing. */
+        if entry.mask & AE_READABLE != 0 && event_loop.iteration <= read_suppressed_until {
+            event_loop.stats.stats.coalesced_reads += 1;
+            entry.mask &= !AE_READABLE;
+            if entry.mask & (AE_WRITABLE | AE_ERRQUEUE) == 0 {
+                continue;
+            }
+        }
+        let entry = &entry;
+        let (fd, mask) = (entry.fd, entry.mask);
+        event_loop.events[fd as usize].dispatches += 1;
+        /* One handler registered for both directions is called once. */
+        let distinct_procs = match (rfile_proc, wfile_proc) {
+            (Some(r), Some(w)) => r as *const FileProc != w as *const FileProc,
+            _ => false,
+        };
 
-                let current_wfile_proc = if (fd as usize) < event_loop.events.len() {
-                    event_loop.events[fd as usize].wfile_proc
-                } else {
-                    None
-                };
+        let mut fired = 0; // Number of events fired for current fd
+        let dispatch_start = stats::callback_start(event_loop);
+        context::push(event_loop, AeDispatchSource::File { fd, mask });
 
-                let should_fire_write = fired == 0
-                    || current_wfile_proc.is_none()
-                    || (wfile_proc.is_some()
-                        && rfile_proc.is_some()
-                        && wfile_proc.unwrap() as *const FileProc
-                            != rfile_proc.unwrap() as *const FileProc);
-
-                if should_fire_write
-                    && (current_fe_mask & mask & AE_WRITABLE) != 0
-                    && let Some(wfile_proc) = current_wfile_proc
-                {
-                    let current_client_data = if (fd as usize) < event_loop.events.len() {
-                        event_loop.events[fd as usize].client_data
-                    } else {
-                        client_data
-                    };
-                    wfile_proc(event_loop, fd, current_client_data, mask);
-                    fired += 1;
-                }
-            }
+        // Check if we should invert the calls (AE_BARRIER flag or loop policy)
+        let policy_invert = match event_loop.dispatch_order {
+            AeDispatchOrder::ReadsFirst => false,
+            AeDispatchOrder::WritesFirst => true,
+            AeDispatchOrder::RegistrationOrder => write_first,
+        };
+        let invert = (fe_mask & AE_BARRIER) != 0 || policy_invert;
+        /* The order was changed by the barrier alone, and it matters
+         * because both handlers are due. */
+        let barrier_inverted = (fe_mask & AE_BARRIER) != 0
+            && !policy_invert
+            && (fe_mask & mask & (AE_READABLE | AE_WRITABLE)) == AE_READABLE | AE_WRITABLE;
+        if barrier_inverted {
+            event_loop.stats.stats.barrier_inversions += 1;
+        }
+        let mut wrote = false;
 
-            // If we have to invert the call, fire the readable event now after the writable one
-            if invert {
-                // Refresh event info in case of resize during callback
-                let current_fe_mask = if (fd as usize) < event_loop.events.len() {
-                    event_loop.events[fd as usize].mask
-                } else {
-                    0
-                };
+        // Fire the readable event if the call sequence is not inverted
+        if !invert && let Some((proc, client_data)) = fired_handler(event_loop, entry, AE_READABLE)
+        {
+            call_read_proc(event_loop, entry, proc, client_data);
+            fired += 1;
+        }
 
-                let current_rfile_proc = if (fd as usize) < event_loop.events.len() {
-                    event_loop.events[fd as usize].rfile_proc
-                } else {
-                    None
-                };
+        /* Fire the writable event, looking the handler up again: the
+         * read handler may have changed or deleted it. */
+        if (fired == 0 || distinct_procs)
+            && let Some((proc, client_data)) = fired_handler(event_loop, entry, AE_WRITABLE)
+        {
+            call_file_proc(event_loop, proc, fd, client_data, mask);
+            fired += 1;
+            wrote = true;
+        }
 
-                let should_fire_read = (current_fe_mask & mask & AE_READABLE) != 0
-                    && (fired == 0
-                        || current_rfile_proc.is_none()
-                        || (rfile_proc.is_some()
-                            && wfile_proc.is_some()
-                            && rfile_proc.unwrap() as *const FileProc
-                                != wfile_proc.unwrap() as *const FileProc));
-
-                if should_fire_read && let Some(rfile_proc) = current_rfile_proc {
-                    let current_client_data = if (fd as usize) < event_loop.events.len() {
-                        event_loop.events[fd as usize].client_data
-                    } else {
-                        client_data
-                    };
-                    rfile_proc(event_loop, fd, current_client_data, mask);
-                }
+        // If we have to invert the call, fire the readable event now after the writable one
+        if invert
+            && (fired == 0 || distinct_procs)
+            && let Some((proc, client_data)) = fired_handler(event_loop, entry, AE_READABLE)
+        {
+            call_read_proc(event_loop, entry, proc, client_data);
+            if barrier_inverted && wrote {
+                event_loop.stats.stats.barrier_writes_before_reads += 1;
             }
+        }
 
-            processed += 1;
+        /* The error queue comes last, whatever the order of the others. */
+        if let Some((proc, client_data)) = fired_handler(event_loop, entry, AE_ERRQUEUE) {
+            call_file_proc(event_loop, proc, fd, client_data, AE_ERRQUEUE);
         }
-    }
 
-    /* Check time events */
-    if (flags & AE_TIME_EVENTS) != 0 {
-        processed += process_time_events(event_loop);
+        context::pop(event_loop, AE_OK);
+        stats::record_callback(event_loop, dispatch_start);
+        processed += 1;
     }
+    event_loop.dispatch_list = dispatch_list;
+    (processed, saturated && (flags & AE_FILE_EVENTS) != 0)
+}
 
-    processed /* return the number of processed file/time events */
+/* The poll filled the fired buffer, see AeFiredOverflowPolicy. */
+fn fired_overflow(event_loop: &mut AeEventLoop, nfired: i32) {
+    event_loop.stats.stats.fired_saturated += 1;
+    match event_loop.fired_overflow {
+        AeFiredOverflowPolicy::Grow => {
+            let slots = (event_loop.fired.len() * 2).min(event_loop.nevents as usize);
+            event_loop.fired_capacity = event_loop.fired_capacity.max(slots);
+            event_loop
+                .fired
+                .resize(slots, FiredEvent { fd: 0, mask: 0 });
+        }
+        AeFiredOverflowPolicy::InvokeHook(hook) => hook(event_loop, nfired),
+        AeFiredOverflowPolicy::Defer | AeFiredOverflowPolicy::PollAgain => {}
+    }
 }
 
 /* Wait for milliseconds until the given file descriptor becomes
- * writable/readable/exception */
+ * writable/readable/exception. A negative timeout waits forever. */
 pub fn ae_wait(fd: i32, mask: i32, milliseconds: i64) -> i32 {
+    let timeout = u64::try_from(milliseconds).ok().map(Duration::from_millis);
+    ae_wait_duration(fd, mask, timeout)
+}
+
+/* ae_wait() taking the timeout as a Duration, None to wait forever. The
+ * wait is in whole milliseconds, rounded up so that a short timeout does
+ * not turn into a busy poll. */
+pub fn ae_wait_duration(fd: i32, mask: i32, timeout: Option<Duration>) -> i32 {
     use libc::{POLLERR, POLLHUP, POLLIN, POLLOUT, poll, pollfd};
 
+    let milliseconds = match timeout {
+        Some(timeout) => duration_ms(timeout).min(i32::MAX as i64) as i32,
+        None => -1,
+    };
+
     let mut pfd = pollfd {
         fd,
         events: 0,
@@ -757,7 +2629,7 @@ pub fn ae_wait(fd: i32, mask: i32, milliseconds: i64) -> i32 {
         pfd.events |= POLLOUT;
     }
 
-    let retval = unsafe { poll(&mut pfd, 1, milliseconds as i32) };
+    let retval = unsafe { poll(&mut pfd, 1, milliseconds) };
 
     if retval == 1 {
         let mut retmask = 0;
@@ -780,37 +2652,36 @@ pub fn ae_wait(fd: i32, mask: i32, milliseconds: i64) -> i32 {
 }
 
 fn cleanup_deleted_time_events(event_loop: &mut AeEventLoop) {
-    let mut nodes_to_remove = Vec::new();
-
-    let mut current = &event_loop.time_event_head;
-    while let Some(node) = current {
-        if node.event.id == AE_DELETED_EVENT_ID && node.event.refcount == 0 {
-            nodes_to_remove.push((
-                node.event.id,
-                node.event.finalizer_proc,
-                node.event.client_data,
-            ));
+    /* Unlink the deleted events first, then call their finalizers: a
+     * finalizer may create or delete other timers. Events it deletes are
+     * released on the next iteration. */
+    let mut removed = Vec::new();
+    let mut list = event_loop.time_event_head.take();
+    let mut tail = &mut event_loop.time_event_head;
+    while let Some(mut node) = list {
+        list = node.next.take();
+        if node.event.deleted && node.event.refcount == 0 {
+            removed.push(node);
+        } else {
+            tail = &mut tail.insert(node).next;
         }
-        current = &node.next;
     }
 
-    for (_id, finalizer_proc, client_data) in &nodes_to_remove {
-        if let Some(finalizer) = finalizer_proc {
-            finalizer(event_loop, *client_data);
-        }
+    for mut node in removed {
+        finalize_time_event(event_loop, &mut node.event);
     }
+}
 
-    fn remove_deleted_nodes(current: &mut Option<Box<TimeEventNode>>) {
-        if let Some(mut node) = current.take() {
-            if node.event.id == AE_DELETED_EVENT_ID && node.event.refcount == 0 {
-                *current = node.next.take();
-                remove_deleted_nodes(current);
-            } else {
-                remove_deleted_nodes(&mut node.next);
-                *current = Some(node);
-            }
-        }
+/* Call the finalizer of an unlinked time event, at most once. */
+fn finalize_time_event(event_loop: &mut AeEventLoop, te: &mut AeTimeEvent) {
+    if te.finalized {
+        return;
+    }
+    if te.mark_deleted() {
+        event_loop.live_time_events -= 1;
+    }
+    te.finalized = true;
+    if let Some(finalizer) = te.finalizer_proc {
+        finalizer(event_loop, te.client_data);
     }
-
-    remove_deleted_nodes(&mut event_loop.time_event_head);
 }
@@ -0,0 +1,294 @@
+/* Admin endpoint.
+ *
+ * Every daemon ends up wanting a way to ask a running process what its
+ * loop is doing. ae_admin_listen() opens a Unix socket served by the loop
+ * itself, with buffered connections, speaking a line protocol simple
+ * enough for nc or socat:
+ *
+ *   PING              PONG
+ *   INFO              loop statistics, one "key:value" per line
+ *   DOCTOR            ae_doctor() findings, "severity message"
+ *   FDS               registered fds, one per line, with the times
+ *                     they were dispatched
+ *   SLOWLOG [count]   latest job slices that overran their budget
+ *   FLIGHT            the flight recorder, oldest dispatch first
+ *   HELP              the commands
+ *   QUIT              close the connection
+ *
+ * Each reply is zero or more lines followed by an empty line. Errors are
+ * a single "ERR ..." line, followed by the empty line as well.
+ *
+ * The endpoint only reads the loop state, and each command runs in a
+ * single callback: a slow client costs the loop its output buffer, not
+ * its time.
+ */
+
+use crate::ae::backoff::{ae_backoff, ae_backoff_cancel, ae_backoff_reset};
+use crate::ae::conn::{
+    ae_conn_close, ae_conn_close_after_write, ae_conn_close_with_error, ae_conn_closing,
+    ae_conn_create, ae_conn_stats, ae_conn_write_owned,
+};
+use crate::ae::doctor::{AeFindingSeverity, ae_doctor};
+use crate::ae::flight::{self, ae_flight_entries};
+use crate::ae::job::ae_slowlog_get;
+use crate::ae::memory::ae_memory_usage;
+use crate::ae::net::ae_accept;
+use crate::ae::stats::ae_get_stats;
+use crate::ae::{
+    AeEventLoop, ae_create_file_event, ae_delete_file_event, ae_pending_time_events,
+    ae_registered_file_events,
+};
+use crate::constants::{AE_ALL_MASK, AE_ERR, AE_OK, AE_READABLE, AE_WRITABLE};
+use std::ffi::c_void;
+use std::fmt::Write;
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+
+/* Longest command line, a client sending more is disconnected. */
+pub const AE_ADMIN_MAX_LINE: usize = 4096;
+/* Slowlog entries listed by SLOWLOG without a count. */
+pub const AE_ADMIN_SLOWLOG_DEFAULT: usize = 10;
+
+pub(crate) struct AdminState {
+    fd: i32,
+    path: PathBuf,
+    /* Connected clients, closed with the endpoint. */
+    pub(crate) clients: Vec<i32>,
+}
+
+/* Serve the admin protocol on a Unix socket bound at `path`, which must
+ * not exist. Returns the errno of the failed bind, or EEXIST if the loop
+ * already listens: a loop has a single endpoint. */
+pub fn ae_admin_listen(event_loop: &mut AeEventLoop, path: impl AsRef<Path>) -> Result<(), i32> {
+    if event_loop.admin.is_some() {
+        return Err(libc::EEXIST);
+    }
+    let path = path.as_ref();
+    let listener = UnixListener::bind(path).map_err(|e| e.raw_os_error().unwrap_or(libc::EIO))?;
+    if let Err(e) = listener.set_nonblocking(true) {
+        let _ = std::fs::remove_file(path);
+        return Err(e.raw_os_error().unwrap_or(libc::EIO));
+    }
+    let fd = listener.into_raw_fd();
+    if ae_create_file_event(
+        event_loop,
+        fd,
+        AE_READABLE,
+        admin_accept_handler,
+        std::ptr::null_mut(),
+    ) == AE_ERR
+    {
+        unsafe { libc::close(fd) };
+        let _ = std::fs::remove_file(path);
+        return Err(libc::ERANGE);
+    }
+    event_loop.admin = Some(AdminState {
+        fd,
+        path: path.to_path_buf(),
+        clients: Vec::new(),
+    });
+    Ok(())
+}
+
+/* Stop serving: the listening socket is closed and removed, connected
+ * clients are disconnected. Done when the loop is deleted otherwise. */
+pub fn ae_admin_close(event_loop: &mut AeEventLoop) {
+    let Some(state) = event_loop.admin.take() else {
+        return;
+    };
+    ae_backoff_cancel(event_loop, state.fd);
+    ae_delete_file_event(event_loop, state.fd, AE_ALL_MASK);
+    unsafe { libc::close(state.fd) };
+    let _ = std::fs::remove_file(&state.path);
+    for fd in state.clients {
+        ae_conn_close(event_loop, fd);
+    }
+}
+
+/* Path of the admin socket of the loop, if it listens. */
+pub fn ae_admin_path(event_loop: &AeEventLoop) -> Option<&Path> {
+    event_loop.admin.as_ref().map(|state| state.path.as_path())
+}
+
+fn admin_accept_handler(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    _client_data: *mut c_void,
+    _mask: i32,
+) {
+    let cfd = match ae_accept(event_loop, fd, b"ERR too many clients\n\n") {
+        Ok((cfd, _)) => cfd,
+        Err(err) => {
+            /* Nothing a client could do about it, the endpoint is gone. */
+            if ae_backoff(event_loop, fd, AE_READABLE, err) == AE_ERR as i64 {
+                ae_admin_close(event_loop);
+            }
+            return;
+        }
+    };
+    ae_backoff_reset(event_loop, fd);
+    if ae_conn_create(
+        event_loop,
+        cfd,
+        admin_read_proc,
+        Some(admin_close_proc),
+        std::ptr::null_mut(),
+    ) == AE_OK
+    {
+        if let Some(state) = event_loop.admin.as_mut() {
+            state.clients.push(cfd);
+        }
+    } else {
+        unsafe { libc::close(cfd) };
+    }
+}
+
+fn admin_close_proc(event_loop: &mut AeEventLoop, fd: i32, _err: i32, _client_data: *mut c_void) {
+    if let Some(state) = event_loop.admin.as_mut() {
+        state.clients.retain(|&client| client != fd);
+    }
+}
+
+/* Answer every complete line of `input`. */
+fn admin_read_proc(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    input: &[u8],
+    _client_data: *mut c_void,
+) -> usize {
+    let mut consumed = 0;
+    while !ae_conn_closing(event_loop, fd) {
+        let Some(newline) = input[consumed..].iter().position(|&c| c == b'\n') else {
+            break;
+        };
+        let line = String::from_utf8_lossy(&input[consumed..consumed + newline]).into_owned();
+        consumed += newline + 1;
+        let mut reply = String::new();
+        let quit = command(event_loop, line.trim(), &mut reply);
+        reply.push('\n');
+        ae_conn_write_owned(event_loop, fd, reply.into_bytes());
+        if quit {
+            ae_conn_close_after_write(event_loop, fd);
+        }
+    }
+    if input.len() - consumed > AE_ADMIN_MAX_LINE {
+        ae_conn_close_with_error(event_loop, fd, libc::EMSGSIZE);
+    }
+    consumed
+}
+
+/* Append the reply lines of `line` to `reply`. True for QUIT. */
+fn command(event_loop: &AeEventLoop, line: &str, reply: &mut String) -> bool {
+    let mut args = line.split_whitespace();
+    let Some(name) = args.next() else {
+        return false;
+    };
+    match name.to_ascii_uppercase().as_str() {
+        "PING" => reply.push_str("PONG\n"),
+        "INFO" => info(event_loop, reply),
+        "DOCTOR" => {
+            for finding in ae_doctor(event_loop) {
+                let severity = match finding.severity {
+                    AeFindingSeverity::Warning => "warning",
+                    AeFindingSeverity::Critical => "critical",
+                };
+                let _ = writeln!(reply, "{} {}", severity, finding.message);
+            }
+        }
+        "FDS" => fds(event_loop, reply),
+        "SLOWLOG" => {
+            let count = match args.next().map(str::parse::<usize>) {
+                None => AE_ADMIN_SLOWLOG_DEFAULT,
+                Some(Ok(count)) => count,
+                Some(Err(_)) => {
+                    reply.push_str("ERR count is not a number\n");
+                    return false;
+                }
+            };
+            for entry in ae_slowlog_get(event_loop, count) {
+                let _ = writeln!(
+                    reply,
+                    "{} {} took_us={} budget_us={}",
+                    entry.id, entry.name, entry.took_us, entry.budget_us
+                );
+            }
+        }
+        "FLIGHT" => {
+            for entry in ae_flight_entries(event_loop) {
+                let _ = flight::write_entry(reply, &entry);
+                reply.push('\n');
+            }
+        }
+        "HELP" => reply.push_str("PING\nINFO\nDOCTOR\nFDS\nSLOWLOG [count]\nFLIGHT\nHELP\nQUIT\n"),
+        "QUIT" => return true,
+        _ => {
+            let _ = writeln!(reply, "ERR unknown command '{}'", name);
+        }
+    }
+    false
+}
+
+fn info(event_loop: &AeEventLoop, reply: &mut String) {
+    let stats = ae_get_stats(event_loop);
+    let memory = ae_memory_usage(event_loop);
+    let fields: [(&str, String); 25] = [
+        ("name", stats.loop_name.clone().unwrap_or_default()),
+        ("backend", event_loop.api_name().to_string()),
+        ("iterations", stats.iterations.to_string()),
+        ("file_events", stats.file_events.to_string()),
+        ("time_events", stats.time_events.to_string()),
+        (
+            "registered_fds",
+            ae_registered_file_events(event_loop).to_string(),
+        ),
+        (
+            "pending_timers",
+            ae_pending_time_events(event_loop).to_string(),
+        ),
+        ("utilization", format!("{:.3}", stats.utilization)),
+        ("poll_us_p50", stats.poll_us.p50().to_string()),
+        ("poll_us_p99", stats.poll_us.p99().to_string()),
+        ("callback_us_p50", stats.callback_us.p50().to_string()),
+        ("callback_us_p99", stats.callback_us.p99().to_string()),
+        ("callback_us_max", stats.callback_us.max().to_string()),
+        ("timer_lag_us_p99", stats.timer_lag_us.p99().to_string()),
+        ("conns_open", stats.conns_open.to_string()),
+        ("conn_input_bps", stats.conn_input_bps.to_string()),
+        ("conn_output_bps", stats.conn_output_bps.to_string()),
+        ("callback_panics", stats.callback_panics.to_string()),
+        ("backoffs", stats.backoffs.to_string()),
+        ("peers_filtered", stats.peers_filtered.to_string()),
+        ("backend_divergences", stats.backend_divergences.to_string()),
+        ("job_overruns", stats.job_overruns.to_string()),
+        ("timer_overruns", stats.timer_overruns.to_string()),
+        ("deferred_backlog", stats.deferred_backlog.to_string()),
+        ("memory_total", memory.total.to_string()),
+    ];
+    for (key, value) in fields {
+        let _ = writeln!(reply, "{}:{}", key, value);
+    }
+}
+
+/* "fd r|w|rw tag=.. priority=.. events=..", with the traffic of
+ * connections. */
+fn fds(event_loop: &AeEventLoop, reply: &mut String) {
+    for fd in 0..=event_loop.maxfd {
+        let fe = &event_loop.events[fd as usize];
+        let mask = match (fe.mask & AE_READABLE != 0, fe.mask & AE_WRITABLE != 0) {
+            (true, true) => "rw",
+            (true, false) => "r",
+            (false, true) => "w",
+            (false, false) => continue,
+        };
+        let _ = write!(
+            reply,
+            "{} {} tag={} priority={} events={}",
+            fd, mask, fe.tag, fe.priority, fe.dispatches
+        );
+        if let Some(conn) = ae_conn_stats(event_loop, fd) {
+            let _ = write!(reply, " in={} out={}", conn.bytes_in, conn.bytes_out);
+        }
+        reply.push('\n');
+    }
+}
@@ -0,0 +1,250 @@
+/* Backing off transient syscall errors.
+ *
+ * Some errors mean "not now" rather than "never": accept() out of
+ * descriptors (EMFILE, ENFILE) or kernel memory (ENOBUFS, ENOMEM), a
+ * write refused for lack of buffer space (ENOBUFS). Retrying at the next
+ * iteration does not help, and as the backends are level-triggered the
+ * fd keeps firing and the loop spins. ae_backoff() takes the fd out of
+ * the poll for the directions that failed and registers them again from
+ * a timer, after a delay that doubles with every failure in a row up to
+ * a maximum. Past a bounded number of failures it gives up, and the
+ * caller closes the fd. The first call that succeeds ends the streak,
+ * with ae_backoff_reset().
+ *
+ * EAGAIN and EINTR are transient too but need no delay: the fd is
+ * reported again once it is ready.
+ *
+ * Buffered connections (ae_conn_create()) back off their writes on their
+ * own. Around ae_accept(), the caller does it:
+ *
+ *     match ae_accept(event_loop, listen_fd, b"-ERR busy\r\n") {
+ *         Ok((fd, _)) => ae_backoff_reset(event_loop, listen_fd),
+ *         Err(err) if ae_backoff(event_loop, listen_fd, AE_READABLE, err) >= 0 => {}
+ *         Err(err) => ... the listener is broken
+ *     }
+ */
+
+use crate::ae::{
+    AeEventLoop, AeFileEventOptions, ae_create_file_event_ex, ae_create_time_event,
+    ae_delete_file_event, ae_delete_time_event,
+};
+use crate::constants::{AE_ERR, AE_NOMORE, AE_READABLE, AE_WRITABLE};
+use crate::traits::FileProc;
+use std::collections::HashMap;
+use std::ffi::c_void;
+
+/* Default delay after the first failure, cap of the delay and failures
+ * in a row before giving up, see AeEventLoopBuilder::backoff(). */
+pub const AE_BACKOFF_INITIAL_MS: i64 = 10;
+pub const AE_BACKOFF_MAX_MS: i64 = 1000;
+pub const AE_BACKOFF_MAX_RETRIES: u32 = 16;
+
+pub(crate) struct Backoffs {
+    pub(crate) initial_ms: i64,
+    pub(crate) max_ms: i64,
+    pub(crate) max_retries: u32,
+    streaks: HashMap<i32, Streak>,
+}
+
+impl Default for Backoffs {
+    fn default() -> Self {
+        Backoffs {
+            initial_ms: AE_BACKOFF_INITIAL_MS,
+            max_ms: AE_BACKOFF_MAX_MS,
+            max_retries: AE_BACKOFF_MAX_RETRIES,
+            streaks: HashMap::new(),
+        }
+    }
+}
+
+struct Streak {
+    failures: u32,
+    /* Timer registering the suspended directions again. */
+    timer_id: Option<i64>,
+    suspended: Vec<Suspended>,
+}
+
+/* One direction taken out of the poll, with what it was registered with. */
+struct Suspended {
+    mask: i32,
+    proc: FileProc,
+    client_data: *mut c_void,
+    options: AeFileEventOptions,
+}
+
+/* Whether `err` may go away by itself: EAGAIN and EINTR, and the errors
+ * ae_backoff() delays the fd for. */
+pub fn ae_is_transient_error(err: i32) -> bool {
+    err == libc::EAGAIN || err == libc::EINTR || needs_delay(err)
+}
+
+fn needs_delay(err: i32) -> bool {
+    matches!(
+        err,
+        libc::ENOBUFS | libc::ENOMEM | libc::ENFILE | libc::EMFILE
+    )
+}
+
+/* Handle the error `err` of a call on `fd` made for the directions in
+ * `mask`. For an error worth a delay the directions are unregistered,
+ * and registered again with the same handler, data and options once the
+ * delay passed; the delay in milliseconds is returned. Returns 0 for
+ * EAGAIN and EINTR, which need nothing. Returns AE_ERR if `err` is not
+ * transient, if `fd` is not registered for `mask`, or once `fd` failed
+ * more than max_retries times in a row: the directions are then left
+ * unregistered for good, and the caller gives up on the fd.
+ *
+ * A suspended fd must not be closed without ae_backoff_cancel(). */
+pub fn ae_backoff(event_loop: &mut AeEventLoop, fd: i32, mask: i32, err: i32) -> i64 {
+    if err == libc::EAGAIN || err == libc::EINTR {
+        return 0;
+    }
+    let mask = mask & (AE_READABLE | AE_WRITABLE);
+    if !needs_delay(err) || mask == 0 || fd < 0 || fd as usize >= event_loop.events.len() {
+        return AE_ERR as i64;
+    }
+    let suspended = ae_backoff_suspended(event_loop, fd);
+    let registered = event_loop.events[fd as usize].mask;
+    if mask & !(registered | suspended) != 0 {
+        return AE_ERR as i64;
+    }
+
+    let backoffs = &mut event_loop.backoff;
+    let streak = backoffs.streaks.entry(fd).or_insert(Streak {
+        failures: 0,
+        timer_id: None,
+        suspended: Vec::new(),
+    });
+    streak.failures += 1;
+    if streak.failures > backoffs.max_retries {
+        let timer_id = backoffs
+            .streaks
+            .remove(&fd)
+            .and_then(|streak| streak.timer_id);
+        if let Some(timer_id) = timer_id {
+            ae_delete_time_event(event_loop, timer_id);
+        }
+        ae_delete_file_event(event_loop, fd, mask);
+        event_loop.stats.stats.backoffs_exhausted += 1;
+        return AE_ERR as i64;
+    }
+    let shift = (streak.failures - 1).min(32);
+    let delay = backoffs
+        .initial_ms
+        .saturating_mul(1i64 << shift)
+        .min(backoffs.max_ms)
+        .max(1);
+    let previous_timer = streak.timer_id.take();
+
+    for direction in [AE_READABLE, AE_WRITABLE] {
+        if mask & direction != 0 && suspended & direction == 0 {
+            suspend(event_loop, fd, direction);
+        }
+    }
+    if let Some(timer_id) = previous_timer {
+        ae_delete_time_event(event_loop, timer_id);
+    }
+    let timer_id = ae_create_time_event(
+        event_loop,
+        delay,
+        backoff_timer,
+        fd as usize as *mut c_void,
+        None,
+    );
+    if let Some(streak) = event_loop.backoff.streaks.get_mut(&fd) {
+        streak.timer_id = Some(timer_id);
+    }
+    event_loop.stats.stats.backoffs += 1;
+    delay
+}
+
+/* End the streak of `fd` after a call that succeeded. Directions still
+ * suspended are registered again right away. */
+pub fn ae_backoff_reset(event_loop: &mut AeEventLoop, fd: i32) {
+    if let Some(streak) = forget(event_loop, fd) {
+        restore(event_loop, fd, streak.suspended);
+    }
+}
+
+/* Drop the streak of `fd` without registering anything again, before
+ * closing it. */
+pub fn ae_backoff_cancel(event_loop: &mut AeEventLoop, fd: i32) {
+    forget(event_loop, fd);
+}
+
+/* Failures of `fd` in a row so far, None if it has no streak. */
+pub fn ae_backoff_failures(event_loop: &AeEventLoop, fd: i32) -> Option<u32> {
+    event_loop
+        .backoff
+        .streaks
+        .get(&fd)
+        .map(|streak| streak.failures)
+}
+
+/* Directions of `fd` waiting for their backoff delay. */
+pub fn ae_backoff_suspended(event_loop: &AeEventLoop, fd: i32) -> i32 {
+    event_loop.backoff.streaks.get(&fd).map_or(0, |streak| {
+        streak.suspended.iter().fold(0, |mask, s| mask | s.mask)
+    })
+}
+
+fn forget(event_loop: &mut AeEventLoop, fd: i32) -> Option<Streak> {
+    if event_loop.backoff.streaks.is_empty() {
+        return None;
+    }
+    let streak = event_loop.backoff.streaks.remove(&fd)?;
+    if let Some(timer_id) = streak.timer_id {
+        ae_delete_time_event(event_loop, timer_id);
+    }
+    Some(streak)
+}
+
+fn suspend(event_loop: &mut AeEventLoop, fd: i32, direction: i32) {
+    let fe = &event_loop.events[fd as usize];
+    let (proc, client_data) = if direction == AE_READABLE {
+        (fe.rfile_proc, fe.client_data)
+    } else {
+        (fe.wfile_proc, fe.wclient_data)
+    };
+    let Some(proc) = proc else {
+        return;
+    };
+    let options = AeFileEventOptions {
+        tag: fe.tag,
+        split_client_data: true,
+        exclusive: fe.exclusive,
+        priority: fe.priority,
+        read_coalescing: fe.read_coalescing,
+    };
+    ae_delete_file_event(event_loop, fd, direction);
+    if let Some(streak) = event_loop.backoff.streaks.get_mut(&fd) {
+        streak.suspended.push(Suspended {
+            mask: direction,
+            proc,
+            client_data,
+            options,
+        });
+    }
+}
+
+fn restore(event_loop: &mut AeEventLoop, fd: i32, suspended: Vec<Suspended>) {
+    for s in suspended {
+        ae_create_file_event_ex(event_loop, fd, s.mask, s.proc, s.client_data, s.options);
+    }
+}
+
+/* The delay passed: register the suspended directions again. The streak
+ * goes on until ae_backoff_reset(). */
+fn backoff_timer(event_loop: &mut AeEventLoop, id: i64, client_data: *mut c_void) -> i32 {
+    let fd = client_data as usize as i32;
+    let Some(streak) = event_loop.backoff.streaks.get_mut(&fd) else {
+        return AE_NOMORE;
+    };
+    if streak.timer_id != Some(id) {
+        return AE_NOMORE;
+    }
+    streak.timer_id = None;
+    let suspended = std::mem::take(&mut streak.suspended);
+    restore(event_loop, fd, suspended);
+    AE_NOMORE
+}
@@ -0,0 +1,129 @@
+/* Read buffer pool.
+ *
+ * A server reading from thousands of sockets allocates a buffer for every
+ * read, or keeps one per connection that sits idle between requests. The
+ * pool keeps released buffers per size class instead, so that a read
+ * takes a warm buffer and gives it back once its input is consumed, like
+ * the reusable query buffer of Redis.
+ *
+ * Buffered connections (ae::conn) take their input buffer from the pool
+ * on the first read and give it back as soon as the read proc consumed
+ * all of it, so only connections holding a partial frame keep one. Read
+ * handlers of their own use ae_buf_acquire() and ae_buf_release().
+ */
+
+use crate::ae::AeEventLoop;
+
+/* Default size classes, in bytes. */
+pub const AE_BUF_POOL_CLASSES: [usize; 3] = [4 * 1024, 16 * 1024, 64 * 1024];
+
+/* Default number of free buffers kept per class. */
+pub const AE_BUF_POOL_MAX_PER_CLASS: usize = 64;
+
+/* See ae_buf_pool_stats(). */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AeBufPoolStats {
+    /* Buffers handed out from the pool, and allocated because the class
+     * had none or the request was larger than every class. */
+    pub hits: u64,
+    pub misses: u64,
+    /* Buffers given back and kept, and given back and freed because the
+     * class was full or the buffer fits no class. */
+    pub released: u64,
+    pub dropped: u64,
+    /* Free buffers held right now, and their size. */
+    pub cached: usize,
+    pub cached_bytes: usize,
+}
+
+pub(crate) struct BufPool {
+    /* Ascending. */
+    classes: Vec<usize>,
+    max_per_class: usize,
+    free: Vec<Vec<Vec<u8>>>,
+    stats: AeBufPoolStats,
+}
+
+impl Default for BufPool {
+    fn default() -> Self {
+        Self::new(&AE_BUF_POOL_CLASSES, AE_BUF_POOL_MAX_PER_CLASS)
+    }
+}
+
+impl BufPool {
+    pub(crate) fn new(classes: &[usize], max_per_class: usize) -> Self {
+        let mut classes: Vec<usize> = classes.iter().copied().filter(|&size| size > 0).collect();
+        classes.sort_unstable();
+        classes.dedup();
+        BufPool {
+            free: vec![Vec::new(); classes.len()],
+            classes,
+            max_per_class,
+            stats: AeBufPoolStats::default(),
+        }
+    }
+
+    pub(crate) fn cached_bytes(&self) -> usize {
+        self.stats.cached_bytes
+    }
+}
+
+/* An empty buffer with room for at least `len` bytes: a free one of the
+ * smallest class that fits, or a new one of that class size. Requests
+ * larger than every class get a buffer of their own size. */
+pub fn ae_buf_acquire(event_loop: &mut AeEventLoop, len: usize) -> Vec<u8> {
+    let pool = &mut event_loop.buf_pool;
+    let class = match pool.classes.iter().position(|&size| size >= len) {
+        Some(class) => class,
+        None => {
+            pool.stats.misses += 1;
+            return Vec::with_capacity(len);
+        }
+    };
+    match pool.free[class].pop() {
+        Some(buf) => {
+            pool.stats.hits += 1;
+            pool.stats.cached -= 1;
+            pool.stats.cached_bytes -= buf.capacity();
+            buf
+        }
+        None => {
+            pool.stats.misses += 1;
+            Vec::with_capacity(pool.classes[class])
+        }
+    }
+}
+
+/* Give a buffer back to the pool. It is kept in the largest class it
+ * holds, or freed if it is smaller than every class or that class is
+ * full. */
+pub fn ae_buf_release(event_loop: &mut AeEventLoop, mut buf: Vec<u8>) {
+    let pool = &mut event_loop.buf_pool;
+    let class = pool
+        .classes
+        .iter()
+        .rposition(|&size| size <= buf.capacity());
+    match class {
+        Some(class) if pool.free[class].len() < pool.max_per_class => {
+            buf.clear();
+            pool.stats.released += 1;
+            pool.stats.cached += 1;
+            pool.stats.cached_bytes += buf.capacity();
+            pool.free[class].push(buf);
+        }
+        _ => pool.stats.dropped += 1,
+    }
+}
+
+/* Counters of the pool since the loop was created. */
+pub fn ae_buf_pool_stats(event_loop: &AeEventLoop) -> AeBufPoolStats {
+    event_loop.buf_pool.stats
+}
+
+/* Free every cached buffer, e.g. after a traffic spike. */
+pub fn ae_buf_pool_trim(event_loop: &mut AeEventLoop) {
+    let pool = &mut event_loop.buf_pool;
+    pool.free.iter_mut().for_each(Vec::clear);
+    pool.stats.cached = 0;
+    pool.stats.cached_bytes = 0;
+}
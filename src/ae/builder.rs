@@ -0,0 +1,306 @@
+/* Event loop builder.
+ *
+ * ae_create_event_loop() only takes the set size. Loops that need more
+ * than that (a specific backend, reserved fds, ...) are configured through
+ * AeEventLoopBuilder, which ends up in the same constructor.
+ */
+
+use crate::ae::signal::{AeSigpipePolicy, ae_ignore_sigpipe};
+use crate::ae::timer_loop::{AeTimerLoop, NoBackend};
+use crate::ae::{
+    AeDispatchOrder, AeEintrPolicy, AeEventLoop, AeFdOrder, AeFiredOverflowPolicy, AeStdioPolicy,
+    bufpool, create_select_backend, differential, fileio, module, probe,
+};
+use crate::anet::anet_cloexec;
+use crate::constants::{AE_ERR, AE_POLL_BATCH};
+use crate::monotonic::AeClockSource;
+use crate::traits::EventBackend;
+use std::time::Duration;
+
+pub struct AeEventLoopBuilder {
+    setsize: i32,
+    backend: Option<Box<dyn EventBackend>>,
+    reserved_fds: usize,
+    backoff: Option<(i64, i64, u32)>,
+    rusage_interval: u64,
+    dispatch_order: AeDispatchOrder,
+    fd_order: AeFdOrder,
+    max_poll_timeout: Option<Duration>,
+    eintr_policy: AeEintrPolicy,
+    fired_overflow: AeFiredOverflowPolicy,
+    stdio_policy: AeStdioPolicy,
+    sigpipe: AeSigpipePolicy,
+    fired_capacity: usize,
+    buf_pool: bufpool::BufPool,
+    clock: AeClockSource,
+    latency_clock: Option<AeClockSource>,
+    io_threads: usize,
+    name: Option<String>,
+    best_backend: bool,
+    differential: bool,
+}
+
+impl AeEventLoopBuilder {
+    pub fn new(setsize: i32) -> Self {
+        Self {
+            setsize,
+            backend: None,
+            reserved_fds: 0,
+            backoff: None,
+            rusage_interval: 0,
+            dispatch_order: AeDispatchOrder::ReadsFirst,
+            fd_order: AeFdOrder::Ascending,
+            max_poll_timeout: None,
+            eintr_policy: AeEintrPolicy::ReturnEarly,
+            fired_overflow: AeFiredOverflowPolicy::Defer,
+            stdio_policy: AeStdioPolicy::Allow,
+            sigpipe: AeSigpipePolicy::Ignore,
+            fired_capacity: AE_POLL_BATCH,
+            buf_pool: bufpool::BufPool::default(),
+            clock: AeClockSource::Instant,
+            latency_clock: None,
+            io_threads: fileio::AE_IO_THREADS_DEFAULT,
+            name: None,
+            best_backend: false,
+            differential: false,
+        }
+    }
+
+    /* Use the given backend instead of the default one. It is sized for
+     * the set size when the loop is built. */
+    pub fn backend(mut self, backend: Box<dyn EventBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /* Use epoll or kqueue when the running kernel supports it (see
+     * ae_kernel_features()), select otherwise. Ignored if backend() is
+     * given. */
+    pub fn best_backend(mut self) -> Self {
+        self.best_backend = true;
+        self
+    }
+
+    /* Run a second backend next to the loop backend and report where
+     * their answers differ, see ae::differential. Select is paired with
+     * epoll or kqueue, those with select. For tests of the backends. */
+    pub fn differential(mut self) -> Self {
+        self.differential = true;
+        self
+    }
+
+    /* Keep `count` spare fds open for the lifetime of the loop, so that
+     * ae_accept() can still accept and reject a client once the process
+     * hits its fd limit (EMFILE). */
+    pub fn reserved_fds(mut self, count: usize) -> Self {
+        self.reserved_fds = count;
+        self
+    }
+
+    /* Sample getrusage() every `iterations` loop iterations and expose it
+     * in ae_get_stats(). 0 (the default) disables sampling. */
+    pub fn rusage_sample_interval(mut self, iterations: u64) -> Self {
+        self.rusage_interval = iterations;
+        self
+    }
+
+    /* Order of the read and write handlers of an fd that fired both ways,
+     * see AeDispatchOrder. */
+    pub fn dispatch_order(mut self, order: AeDispatchOrder) -> Self {
+        self.dispatch_order = order;
+        self
+    }
+
+    /* Order of the fds fired by one poll, see AeFdOrder. */
+    pub fn fd_order(mut self, order: AeFdOrder) -> Self {
+        self.fd_order = order;
+        self
+    }
+
+    /* What to do when a signal interrupts the poll, see AeEintrPolicy. */
+    pub fn eintr_policy(mut self, policy: AeEintrPolicy) -> Self {
+        self.eintr_policy = policy;
+        self
+    }
+
+    /* What to do when a poll fills the fired buffer, see
+     * AeFiredOverflowPolicy. */
+    pub fn fired_overflow(mut self, policy: AeFiredOverflowPolicy) -> Self {
+        self.fired_overflow = policy;
+        self
+    }
+
+    /* What registering fds 0, 1 and 2 does, see AeStdioPolicy. Daemons
+     * usually want Deny. */
+    pub fn stdio_policy(mut self, policy: AeStdioPolicy) -> Self {
+        self.stdio_policy = policy;
+        self
+    }
+
+    /* What building the loop does with SIGPIPE, see AeSigpipePolicy.
+     * Applications managing their signals themselves want Keep. */
+    pub fn sigpipe(mut self, policy: AeSigpipePolicy) -> Self {
+        self.sigpipe = policy;
+        self
+    }
+
+    /* Report at most `events` fired events per poll (at least 1), like
+     * AE_POLL_BATCH for this loop only. The buffer otherwise grows with
+     * the highest fd. */
+    pub fn fired_capacity(mut self, events: usize) -> Self {
+        self.fired_capacity = events.max(1);
+        self
+    }
+
+    /* Size classes of the read buffer pool, in bytes, and how many free
+     * buffers each keeps. Defaults to AE_BUF_POOL_CLASSES and
+     * AE_BUF_POOL_MAX_PER_CLASS; no classes disables the pool. */
+    pub fn buffer_pool(mut self, classes: &[usize], max_per_class: usize) -> Self {
+        self.buf_pool = bufpool::BufPool::new(classes, max_per_class);
+        self
+    }
+
+    /* Clock used for timers and loop statistics, see AeClockSource. The
+     * loop reads it several times per iteration. Building fails if the
+     * clock is not available on this system. */
+    pub fn clock_source(mut self, clock: AeClockSource) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /* Delays of ae_backoff(): `initial_ms` after the first failure of an
+     * fd, doubling with each failure in a row up to `max_ms`, and giving
+     * up after `max_retries`. Defaults to AE_BACKOFF_INITIAL_MS,
+     * AE_BACKOFF_MAX_MS and AE_BACKOFF_MAX_RETRIES. */
+    pub fn backoff(mut self, initial_ms: i64, max_ms: i64, max_retries: u32) -> Self {
+        let initial_ms = initial_ms.max(1);
+        self.backoff = Some((initial_ms, max_ms.max(initial_ms), max_retries));
+        self
+    }
+
+    /* Clock used to time callbacks for the callback_us histogram, the
+     * loop clock by default. AeClockSource::Cycles reads the cycle counter
+     * instead of calling clock_gettime() twice per callback, which shows
+     * in microbenchmarks; timers keep the loop clock. Building fails if
+     * the clock is not available on this system. */
+    pub fn latency_clock(mut self, clock: AeClockSource) -> Self {
+        self.latency_clock = Some(clock);
+        self
+    }
+
+    /* Never sleep in the poll for longer than `max`, timers or not: the
+     * loop goes through beforesleep at least that often, so conditions
+     * it checks there (a flag in shared memory set by another process,
+     * ...) are seen within a bounded delay. Unbounded by default. */
+    pub fn max_poll_timeout(mut self, max: Duration) -> Self {
+        self.max_poll_timeout = Some(max);
+        self
+    }
+
+    /* Number of threads serving ae_file_read(), started on first use. */
+    pub fn io_threads(mut self, threads: usize) -> Self {
+        self.io_threads = threads.max(1);
+        self
+    }
+
+    /* Name of the loop, to tell loops apart in a process running several:
+     * it shows in ae_get_stats(), ae_doctor() findings, crash reports and
+     * the name of the threads the loop starts. */
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /* Create a timers-only loop (see ae/timer_loop.rs) with the other
+     * options: the size is forced to 0 and no backend is created, so the
+     * loop holds no fd. None on failure. */
+    pub fn build_timer_loop(mut self) -> Option<AeTimerLoop> {
+        self.setsize = 0;
+        self.backend = Some(Box::new(NoBackend));
+        self.best_backend = false;
+        self.differential = false;
+        /* No sockets to write to. */
+        self.sigpipe = AeSigpipePolicy::Keep;
+        self.build().map(AeTimerLoop::from_loop)
+    }
+
+    /* Create the loop, None on failure. */
+    pub fn build(self) -> Option<Box<AeEventLoop>> {
+        if self.setsize < 0 || !self.clock.is_supported() {
+            return None;
+        }
+        if self
+            .latency_clock
+            .is_some_and(|clock| !clock.is_supported())
+        {
+            return None;
+        }
+        if self.sigpipe == AeSigpipePolicy::Ignore && ae_ignore_sigpipe() == AE_ERR {
+            return None;
+        }
+        let backend = match self.backend {
+            Some(mut backend) => {
+                if backend.resize(self.setsize) == -1 {
+                    return None;
+                }
+                backend
+            }
+            None if self.best_backend => {
+                let mut backend = probe::create_best_backend().ok()?;
+                if backend.resize(self.setsize) == -1 {
+                    return None;
+                }
+                backend
+            }
+            None => create_select_backend().ok()?,
+        };
+        let (backend, divergences) = if self.differential {
+            let (backend, log) = differential::pair(backend, self.setsize).ok()?;
+            (backend, Some(log))
+        } else {
+            (backend, None)
+        };
+
+        let mut event_loop = Box::new(AeEventLoop::new(self.setsize, backend));
+        event_loop.differential = divergences;
+        event_loop.stats.rusage_interval = self.rusage_interval;
+        event_loop.dispatch_order = self.dispatch_order;
+        event_loop.fd_order = self.fd_order;
+        event_loop.max_poll_timeout = self.max_poll_timeout;
+        event_loop.eintr_policy = self.eintr_policy;
+        event_loop.fired_overflow = self.fired_overflow;
+        event_loop.stdio_policy = self.stdio_policy;
+        event_loop.fired_capacity = self.fired_capacity;
+        event_loop.fired.truncate(event_loop.fired_capacity);
+        event_loop.buf_pool = self.buf_pool;
+        event_loop.clock = self.clock;
+        event_loop.latency_clock = self.latency_clock;
+        if let Some((initial_ms, max_ms, max_retries)) = self.backoff {
+            event_loop.backoff.initial_ms = initial_ms;
+            event_loop.backoff.max_ms = max_ms;
+            event_loop.backoff.max_retries = max_retries;
+        }
+        event_loop.io_threads = self.io_threads;
+        event_loop.name = self.name;
+        event_loop.cached_now_us = event_loop.now_us();
+        for _ in 0..self.reserved_fds {
+            /* Dropping the loop closes the ones opened so far. */
+            event_loop.reserved_fds.push(open_reserved_fd()?);
+        }
+        Some(module::attach_modules(event_loop))
+    }
+}
+
+/* Open a placeholder fd to be given up when the process runs out of
+ * descriptors. */
+pub(crate) fn open_reserved_fd() -> Option<i32> {
+    let fd = unsafe { libc::open(c"/dev/null".as_ptr(), libc::O_RDONLY) };
+    if fd == -1 {
+        return None;
+    }
+    if anet_cloexec(fd) == AE_ERR {
+        unsafe { libc::close(fd) };
+        return None;
+    }
+    Some(fd)
+}
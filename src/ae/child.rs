@@ -0,0 +1,123 @@
+/* Child loops pumped by a parent loop.
+ *
+ * A subsystem (metrics, admin console, ...) can own a full AeEventLoop of
+ * its own without dedicating a thread to it: the child's backend fd is
+ * registered on the parent, and whenever it becomes readable (or one of
+ * the child's timers is due) the parent runs one non-blocking iteration of
+ * the child.
+ *
+ * This requires a backend that is itself pollable (kqueue, epoll). The
+ * select backend has no such fd and cannot be attached.
+ */
+
+use crate::ae::{
+    AeEventLoop, ae_create_file_event, ae_create_time_event, ae_delete_file_event,
+    ae_delete_time_event, ae_get_file_client_data, ae_process_events, us_until_earliest_timer,
+};
+use crate::constants::{
+    AE_ALL_EVENTS, AE_CALL_AFTER_SLEEP, AE_CALL_BEFORE_SLEEP, AE_DONT_WAIT, AE_ERR, AE_NOMORE,
+    AE_READABLE,
+};
+use std::ffi::c_void;
+
+struct ChildLoop {
+    child: Box<AeEventLoop>,
+    /* Parent timer waking us up for the child's next timer, -1 if none. */
+    timer_id: i64,
+}
+
+/* Attach `child` to `parent`. The parent takes ownership of the child
+ * until it is detached (or the child fd unregistered and the parent
+ * dropped, in which case the child leaks).
+ *
+ * Returns the fd identifying the child on the parent (its backend fd), to
+ * be passed to ae_detach_child_loop(), or AE_ERR if the child backend is
+ * not pollable or the fd cannot be registered. */
+pub fn ae_attach_child_loop(parent: &mut AeEventLoop, child: Box<AeEventLoop>) -> i32 {
+    let fd = child.apidata.fd();
+    if fd == -1 {
+        return AE_ERR;
+    }
+
+    let state = Box::into_raw(Box::new(ChildLoop {
+        child,
+        timer_id: -1,
+    }));
+    if ae_create_file_event(
+        parent,
+        fd,
+        AE_READABLE,
+        child_readable_handler,
+        state as *mut c_void,
+    ) == AE_ERR
+    {
+        drop(unsafe { Box::from_raw(state) });
+        return AE_ERR;
+    }
+
+    schedule_child_timer(parent, unsafe { &mut *state });
+    fd
+}
+
+/* Detach the child registered under `fd` and give it back. */
+pub fn ae_detach_child_loop(parent: &mut AeEventLoop, fd: i32) -> Option<Box<AeEventLoop>> {
+    let client_data = ae_get_file_client_data(parent, fd);
+    if client_data.is_null() {
+        return None;
+    }
+    let state = unsafe { Box::from_raw(client_data as *mut ChildLoop) };
+    ae_delete_file_event(parent, fd, AE_READABLE);
+    if state.timer_id != -1 {
+        ae_delete_time_event(parent, state.timer_id);
+    }
+    Some(state.child)
+}
+
+/* Run one non-blocking iteration of the child. */
+fn pump_child(state: &mut ChildLoop) {
+    ae_process_events(
+        &mut state.child,
+        AE_ALL_EVENTS | AE_DONT_WAIT | AE_CALL_BEFORE_SLEEP | AE_CALL_AFTER_SLEEP,
+    );
+}
+
+/* (Re)arm the parent timer so that it fires when the child's earliest
+ * timer is due. */
+fn schedule_child_timer(parent: &mut AeEventLoop, state: &mut ChildLoop) {
+    if state.timer_id != -1 {
+        ae_delete_time_event(parent, state.timer_id);
+        state.timer_id = -1;
+    }
+
+    let us = us_until_earliest_timer(&state.child);
+    if us >= 0 {
+        let ms = (us + 999) / 1000;
+        state.timer_id = ae_create_time_event(
+            parent,
+            ms,
+            child_timer_handler,
+            state as *mut ChildLoop as *mut c_void,
+            None,
+        );
+    }
+}
+
+fn child_readable_handler(
+    parent: &mut AeEventLoop,
+    _fd: i32,
+    client_data: *mut c_void,
+    _mask: i32,
+) {
+    let state = unsafe { &mut *(client_data as *mut ChildLoop) };
+    pump_child(state);
+    schedule_child_timer(parent, state);
+}
+
+fn child_timer_handler(parent: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    let state = unsafe { &mut *(client_data as *mut ChildLoop) };
+    /* This timer is done, schedule_child_timer() must not delete it. */
+    state.timer_id = -1;
+    pump_child(state);
+    schedule_child_timer(parent, state);
+    AE_NOMORE
+}
@@ -0,0 +1,42 @@
+/* Shared configuration snapshots.
+ *
+ * Settings reloaded at runtime (SIGHUP, an admin command, a watcher
+ * thread) must reach every callback of the loop. Instead of threading a
+ * pointer through each client_data, the loop keeps one slot per config
+ * type holding an Arc<T>. ae_config() hands out the current snapshot,
+ * which a callback can keep using for as long as it needs: a reload swaps
+ * the slot, snapshots already taken stay valid and unchanged.
+ *
+ * The slots belong to the loop thread, so reading them takes no lock.
+ * Another thread publishes a new config with AeHandle::set_config(), which
+ * swaps it in on the loop thread.
+ */
+
+use crate::ae::AeEventLoop;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub(crate) type ConfigSlots = HashMap<TypeId, Arc<dyn Any + Send + Sync>>;
+
+/* Publish `config` as the current T of the loop, returning the previous
+ * one. Callbacks see it from their next ae_config() call on. */
+pub fn ae_set_config<T: Send + Sync + 'static>(
+    event_loop: &mut AeEventLoop,
+    config: Arc<T>,
+) -> Option<Arc<T>> {
+    let previous = event_loop.configs.insert(TypeId::of::<T>(), config)?;
+    previous.downcast::<T>().ok()
+}
+
+/* Current T of the loop, None if none was set. */
+pub fn ae_config<T: Send + Sync + 'static>(event_loop: &AeEventLoop) -> Option<Arc<T>> {
+    let config = event_loop.configs.get(&TypeId::of::<T>())?;
+    Arc::clone(config).downcast::<T>().ok()
+}
+
+/* Remove the T of the loop, returning it. */
+pub fn ae_clear_config<T: Send + Sync + 'static>(event_loop: &mut AeEventLoop) -> Option<Arc<T>> {
+    let previous = event_loop.configs.remove(&TypeId::of::<T>())?;
+    previous.downcast::<T>().ok()
+}
@@ -0,0 +1,756 @@
+/* Buffered connections.
+ *
+ * Protocol code does not want readable events, it wants "here is all the
+ * input so far" and "send this". A connection owns a socket fd, reads
+ * what arrives into an input buffer handed to a ConnReadProc (which
+ * consumes whole frames and leaves partial ones for later), and queues
+ * output in a BufChain, written right away as far as the socket takes it
+ * and then whenever it becomes writable again.
+ *
+ * TCP connections close one direction at a time. By default end of file
+ * from the peer closes the connection, as most request/response
+ * protocols want. With an eof proc (ae_conn_set_eof_proc()) the read side
+ * is closed alone and the connection can still answer, and
+ * ae_conn_shutdown_write() sends end of file once the queued output is
+ * written while input keeps being read. ae_conn_half_state() tells where
+ * a connection is.
+ *
+ * Protocols change on the way: an HTTP request upgrading to WebSocket,
+ * a STARTTLS command. ae_conn_replace_handlers() swaps the procs of a
+ * connection, buffers kept, and the new read proc gets the input the old
+ * one left. ae_conn_handover() gives the fd itself, with that input, to
+ * code that does its own I/O once the queued output is written, and
+ * ae_conn_migrate() (see migrate.rs) gives the whole connection to another
+ * loop the same way.
+ *
+ * The functions below take the fd of a connection created with
+ * ae_conn_create(), which must be closed with ae_conn_close() before the
+ * loop is deleted.
+ */
+
+use crate::ae::backoff::{
+    ae_backoff, ae_backoff_cancel, ae_backoff_reset, ae_backoff_suspended, ae_is_transient_error,
+};
+use crate::ae::watchdog::ae_note_write_progress;
+use crate::ae::{AeEventLoop, ae_create_file_event, ae_delete_file_event, ae_get_file_events};
+use crate::ae::{bufpool, drain, idle, keepalive, stats};
+use crate::anet::{BufChain, errno};
+use crate::constants::{AE_ALL_MASK, AE_ERR, AE_OK, AE_READABLE, AE_WRITABLE};
+use crate::traits::{ConnCloseProc, ConnEofProc, ConnHandoverProc, ConnReadProc};
+use std::ffi::c_void;
+
+/* Bytes requested from the socket on each readable event. */
+const AE_CONN_READ_LEN: usize = 16 * 1024;
+
+/* Input a read proc may leave unconsumed before the connection is closed
+ * with ENOBUFS, like Redis client-query-buffer-limit. */
+pub const AE_CONN_MAX_INPUT: usize = 1024 * 1024 * 1024;
+
+pub(crate) struct ConnState {
+    proc: ConnReadProc,
+    close_proc: Option<ConnCloseProc>,
+    eof_proc: Option<ConnEofProc>,
+    client_data: *mut c_void,
+    input: Vec<u8>,
+    output: BufChain,
+    /* The read proc is running: closing is deferred until it returns, so
+     * that the state it is called from stays valid. */
+    in_proc: bool,
+    /* Close requested (with this errno) while in_proc was set. */
+    pending_close: Option<i32>,
+    /* The procs were replaced while in_proc was set: the new read proc
+     * gets the input left once the old one returns. */
+    replaced: bool,
+    /* Hand the fd over once the output is written, see
+     * ae_conn_handover(). */
+    handover: Option<Handover>,
+    /* Close once the output is flushed, see ae_conn_close_after_write(). */
+    close_after_write: bool,
+    /* The peer sent end of file, see ae_conn_set_eof_proc(). */
+    read_eof: bool,
+    /* Shut the write side down once the output is flushed, and done, see
+     * ae_conn_shutdown_write(). */
+    shutdown_write: bool,
+    write_shut: bool,
+    /* Traffic so far, see ae_conn_stats(). Keepalive probes also read
+     * bytes_in to tell whether the peer is alive. */
+    bytes_in: u64,
+    bytes_out: u64,
+    created_us: u64,
+    last_interaction_us: u64,
+    /* Footprint last added to the loop total, see ae_memory_usage(). */
+    accounted: usize,
+}
+
+/* Where the fd of a connection being handed over goes. */
+enum Handover {
+    Proc(ConnHandoverProc, *mut c_void),
+    /* To another loop, with the procs and counters, see migrate.rs.
+     * Dropped with the connection if it closes first. */
+    Migrate(MigrateProc),
+}
+
+pub(crate) type MigrateProc = Box<dyn FnOnce(&mut AeEventLoop, i32, Vec<u8>, ConnProcs)>;
+
+/* What a connection is besides its fd and buffers, taken along by a
+ * migration. */
+#[derive(Clone, Copy)]
+pub(crate) struct ConnProcs {
+    pub(crate) proc: ConnReadProc,
+    pub(crate) close_proc: Option<ConnCloseProc>,
+    pub(crate) eof_proc: Option<ConnEofProc>,
+    pub(crate) client_data: *mut c_void,
+    pub(crate) bytes_in: u64,
+    pub(crate) bytes_out: u64,
+}
+
+/* Traffic of one connection, see ae_conn_stats(). Times are in the clock
+ * of ae_loop_now(). */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AeConnStats {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub created_us: u64,
+    /* Last time bytes were read or written. */
+    pub last_interaction_us: u64,
+}
+
+/* Which directions of a connection are still open, see
+ * ae_conn_half_state(). */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeConnHalf {
+    Open,
+    /* The peer sent end of file: nothing more is read, output still
+     * goes out. */
+    ReadClosed,
+    /* ae_conn_shutdown_write() was called and the queued output is being
+     * written before the write side is shut down. */
+    WriteDraining,
+    /* The write side is shut down, input is still read. */
+    WriteClosed,
+}
+
+/* Turn the connected socket `fd` into a buffered connection calling
+ * `proc` with its input. The connection owns the fd from now on: it is
+ * closed, and `close_proc` called, when the peer hangs up, on an I/O
+ * error or on ae_conn_close(). Returns AE_OK or AE_ERR. */
+pub fn ae_conn_create(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    proc: ConnReadProc,
+    close_proc: Option<ConnCloseProc>,
+    client_data: *mut c_void,
+) -> i32 {
+    let state = Box::into_raw(Box::new(ConnState {
+        proc,
+        close_proc,
+        eof_proc: None,
+        client_data,
+        input: Vec::new(),
+        output: BufChain::new(),
+        in_proc: false,
+        pending_close: None,
+        replaced: false,
+        handover: None,
+        close_after_write: false,
+        read_eof: false,
+        shutdown_write: false,
+        write_shut: false,
+        bytes_in: 0,
+        bytes_out: 0,
+        created_us: event_loop.cached_now_us,
+        last_interaction_us: event_loop.cached_now_us,
+        accounted: 0,
+    }));
+    if ae_create_file_event(
+        event_loop,
+        fd,
+        AE_READABLE,
+        conn_readable_handler,
+        state as *mut c_void,
+    ) == AE_ERR
+    {
+        drop(unsafe { Box::from_raw(state) });
+        return AE_ERR;
+    }
+    event_loop.conns.insert(fd, state);
+    stats::record_conn_open(event_loop);
+    account(event_loop, state);
+    AE_OK
+}
+
+/* Make `fd`, migrated from another loop, a connection of this one with
+ * `procs`, keeping the `input` it had not consumed for resume(). Returns
+ * AE_ERR, with errno set, if it cannot be registered. */
+pub(crate) fn adopt(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    procs: &ConnProcs,
+    input: Vec<u8>,
+) -> i32 {
+    if ae_conn_create(
+        event_loop,
+        fd,
+        procs.proc,
+        procs.close_proc,
+        procs.client_data,
+    ) == AE_ERR
+    {
+        return AE_ERR;
+    }
+    let state = conn_state(event_loop, fd).expect("connection just created");
+    unsafe {
+        (*state).eof_proc = procs.eof_proc;
+        (*state).bytes_in = procs.bytes_in;
+        (*state).bytes_out = procs.bytes_out;
+        (*state).input = input;
+    }
+    account(event_loop, state);
+    AE_OK
+}
+
+/* Hand the read proc of an adopted connection the input it came with. */
+pub(crate) fn resume(event_loop: &mut AeEventLoop, fd: i32) {
+    let state = match conn_state(event_loop, fd) {
+        Some(state) => state,
+        None => return,
+    };
+    if unsafe { (*state).in_proc || (*state).input.is_empty() } {
+        return;
+    }
+    let input = unsafe {
+        (*state).in_proc = true;
+        std::mem::take(&mut (*state).input)
+    };
+    feed(event_loop, fd, state, input);
+}
+
+/* Looked up in the loop rather than from the registration: a half closed
+ * connection may have no file event left. */
+fn conn_state(event_loop: &AeEventLoop, fd: i32) -> Option<*mut ConnState> {
+    event_loop.conns.get(&fd).copied()
+}
+
+/* Queue `data` on the connection and write as much of it as the socket
+ * takes right now. Returns AE_ERR if `fd` is not an open connection. */
+pub fn ae_conn_write(event_loop: &mut AeEventLoop, fd: i32, data: &[u8]) -> i32 {
+    ae_conn_write_owned(event_loop, fd, data.to_vec())
+}
+
+/* ae_conn_write() taking ownership of the buffer, which is queued as is. */
+pub fn ae_conn_write_owned(event_loop: &mut AeEventLoop, fd: i32, data: Vec<u8>) -> i32 {
+    let state = match conn_state(event_loop, fd) {
+        Some(state) => state,
+        None => return AE_ERR,
+    };
+    unsafe {
+        /* Like Redis after CLIENT_CLOSE_AFTER_REPLY, nothing more is
+         * sent once the connection is on its way out. */
+        if (*state).pending_close.is_some() || (*state).close_after_write || (*state).shutdown_write
+        {
+            return AE_ERR;
+        }
+        (*state).output.push(data);
+        account(event_loop, state);
+        /* Replies to pipelined input go out in one write once the read
+         * proc returns. */
+        if (*state).in_proc {
+            return AE_OK;
+        }
+    }
+    /* Output already waiting for the socket goes out in order. */
+    if ae_get_file_events(event_loop, fd) & AE_WRITABLE == 0 {
+        flush(event_loop, fd, state);
+    }
+    AE_OK
+}
+
+/* Bytes queued on the connection and not written yet. */
+pub fn ae_conn_pending_output(event_loop: &AeEventLoop, fd: i32) -> usize {
+    conn_state(event_loop, fd).map_or(0, |state| unsafe { (*state).output.len() })
+}
+
+/* Client data given to ae_conn_create(), null if `fd` is not an open
+ * connection. */
+pub fn ae_conn_client_data(event_loop: &AeEventLoop, fd: i32) -> *mut c_void {
+    conn_state(event_loop, fd).map_or(std::ptr::null_mut(), |state| unsafe {
+        (*state).client_data
+    })
+}
+
+/* Whether `fd` is no longer an open connection or is going to be closed
+ * (ae_conn_close() from the read proc, ae_conn_close_after_write()):
+ * input still buffered should not be acted upon. */
+pub fn ae_conn_closing(event_loop: &AeEventLoop, fd: i32) -> bool {
+    conn_state(event_loop, fd).is_none_or(|state| unsafe {
+        (*state).pending_close.is_some() || (*state).close_after_write
+    })
+}
+
+/* Close the connection now, dropping pending output. From the read proc
+ * it takes effect once the proc returns. */
+pub fn ae_conn_close(event_loop: &mut AeEventLoop, fd: i32) {
+    if let Some(state) = conn_state(event_loop, fd) {
+        close_with(event_loop, fd, state, 0);
+    }
+}
+
+/* ae_conn_close() reporting `err` to the close proc, for protocol code
+ * giving up on a peer, e.g. EMSGSIZE for an oversized frame. */
+pub fn ae_conn_close_with_error(event_loop: &mut AeEventLoop, fd: i32, err: i32) {
+    if let Some(state) = conn_state(event_loop, fd) {
+        close_with(event_loop, fd, state, err);
+    }
+}
+
+/* Stop reading and close the connection once its pending output is
+ * written, e.g. after sending an error reply. */
+pub fn ae_conn_close_after_write(event_loop: &mut AeEventLoop, fd: i32) {
+    let state = match conn_state(event_loop, fd) {
+        Some(state) => state,
+        None => return,
+    };
+    if unsafe { (*state).output.is_empty() } {
+        close_with(event_loop, fd, state, 0);
+        return;
+    }
+    unsafe { (*state).close_after_write = true };
+    /* Output queued by the read proc is not being written yet: arm the
+     * writable event first so that the fd stays registered. */
+    if !arm_writable(event_loop, fd, state) {
+        close_with(event_loop, fd, state, libc::ENOMEM);
+        return;
+    }
+    ae_delete_file_event(event_loop, fd, AE_READABLE);
+}
+
+/* Call `eof_proc` when the peer shuts down its write side, instead of
+ * closing the connection. The read side is unregistered and the
+ * connection stays open for output until ae_conn_close(),
+ * ae_conn_close_after_write() or ae_conn_shutdown_write(). None restores
+ * the default. Returns AE_ERR if `fd` is not an open connection. */
+pub fn ae_conn_set_eof_proc(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    eof_proc: Option<ConnEofProc>,
+) -> i32 {
+    match conn_state(event_loop, fd) {
+        Some(state) => {
+            unsafe { (*state).eof_proc = eof_proc };
+            AE_OK
+        }
+        None => AE_ERR,
+    }
+}
+
+/* Send end of file to the peer once the queued output is written, like
+ * shutdown(SHUT_WR), and keep reading: nothing more can be written. The
+ * connection is closed when the peer sends end of file in turn, or right
+ * after the flush if it already did. Returns AE_ERR if `fd` is not an
+ * open connection or is closing. */
+pub fn ae_conn_shutdown_write(event_loop: &mut AeEventLoop, fd: i32) -> i32 {
+    let state = match conn_state(event_loop, fd) {
+        Some(state) => state,
+        None => return AE_ERR,
+    };
+    unsafe {
+        if (*state).pending_close.is_some() || (*state).close_after_write {
+            return AE_ERR;
+        }
+        if (*state).read_eof {
+            ae_conn_close_after_write(event_loop, fd);
+            return AE_OK;
+        }
+        if (*state).shutdown_write {
+            return AE_OK;
+        }
+        (*state).shutdown_write = true;
+        /* The read proc's output goes out, and the shutdown with it, once
+         * the proc returns. */
+        if (*state).in_proc {
+            return AE_OK;
+        }
+    }
+    if ae_get_file_events(event_loop, fd) & AE_WRITABLE == 0 {
+        flush(event_loop, fd, state);
+    }
+    AE_OK
+}
+
+/* Replace the read and close procs of the connection and their client
+ * data, keeping its buffers: input the old read proc did not consume is
+ * handed to `proc` right away, then everything read from now on. From the
+ * read proc, `proc` gets the rest of the input once the old one returns.
+ * The eof proc is kept. Returns AE_ERR if `fd` is not an open connection,
+ * is closing or being handed over. */
+pub fn ae_conn_replace_handlers(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    proc: ConnReadProc,
+    close_proc: Option<ConnCloseProc>,
+    client_data: *mut c_void,
+) -> i32 {
+    if ae_conn_closing(event_loop, fd) {
+        return AE_ERR;
+    }
+    let state = match conn_state(event_loop, fd) {
+        Some(state) => state,
+        None => return AE_ERR,
+    };
+    let in_proc = unsafe {
+        if (*state).handover.is_some() {
+            return AE_ERR;
+        }
+        (*state).proc = proc;
+        (*state).close_proc = close_proc;
+        (*state).client_data = client_data;
+        (*state).replaced = (*state).in_proc;
+        (*state).in_proc
+    };
+    if !in_proc && unsafe { !(*state).input.is_empty() } {
+        let input = unsafe {
+            (*state).in_proc = true;
+            std::mem::take(&mut (*state).input)
+        };
+        feed(event_loop, fd, state, input);
+    }
+    AE_OK
+}
+
+/* Stop reading from the connection and, once its queued output is
+ * written, give its fd to `proc` with the input not consumed yet,
+ * instead of closing it: for a protocol taking over the socket, like TLS
+ * after STARTTLS. The connection is gone by then, its close proc is not
+ * called. A connection closed before that (peer hangup, write error) is
+ * closed as usual. From the read proc, the handover happens once it
+ * returns. Returns AE_ERR if `fd` is not an open connection, is closing,
+ * shutting down its write side or already being handed over. */
+pub fn ae_conn_handover(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    proc: ConnHandoverProc,
+    client_data: *mut c_void,
+) -> i32 {
+    start_handover(event_loop, fd, Handover::Proc(proc, client_data))
+}
+
+/* ae_conn_handover() for ae_conn_migrate(): `proc` also gets the procs
+ * and counters of the connection. */
+pub(crate) fn migrate_out(event_loop: &mut AeEventLoop, fd: i32, proc: MigrateProc) -> i32 {
+    start_handover(event_loop, fd, Handover::Migrate(proc))
+}
+
+fn start_handover(event_loop: &mut AeEventLoop, fd: i32, handover: Handover) -> i32 {
+    if ae_conn_closing(event_loop, fd) {
+        return AE_ERR;
+    }
+    let state = match conn_state(event_loop, fd) {
+        Some(state) => state,
+        None => return AE_ERR,
+    };
+    let in_proc = unsafe {
+        if (*state).shutdown_write || (*state).handover.is_some() {
+            return AE_ERR;
+        }
+        (*state).handover = Some(handover);
+        (*state).in_proc
+    };
+    ae_delete_file_event(event_loop, fd, AE_READABLE);
+    if !in_proc && ae_get_file_events(event_loop, fd) & AE_WRITABLE == 0 {
+        flush(event_loop, fd, state);
+    }
+    AE_OK
+}
+
+/* Which directions of the connection `fd` are open, None if it is not an
+ * open connection. */
+pub fn ae_conn_half_state(event_loop: &AeEventLoop, fd: i32) -> Option<AeConnHalf> {
+    conn_state(event_loop, fd).map(|state| unsafe {
+        if (*state).read_eof {
+            AeConnHalf::ReadClosed
+        } else if (*state).write_shut {
+            AeConnHalf::WriteClosed
+        } else if (*state).shutdown_write {
+            AeConnHalf::WriteDraining
+        } else {
+            AeConnHalf::Open
+        }
+    })
+}
+
+/* Traffic of the connection `fd`, None if it is not an open connection.
+ * The loop wide totals are in ae_get_stats(). */
+pub fn ae_conn_stats(event_loop: &AeEventLoop, fd: i32) -> Option<AeConnStats> {
+    conn_state(event_loop, fd).map(|state| unsafe {
+        AeConnStats {
+            bytes_in: (*state).bytes_in,
+            bytes_out: (*state).bytes_out,
+            created_us: (*state).created_us,
+            last_interaction_us: (*state).last_interaction_us,
+        }
+    })
+}
+
+/* Bytes read from the connection so far, None if `fd` is not an open
+ * connection. */
+pub(crate) fn conn_bytes_in(event_loop: &AeEventLoop, fd: i32) -> Option<u64> {
+    conn_state(event_loop, fd).map(|state| unsafe { (*state).bytes_in })
+}
+
+fn close_with(event_loop: &mut AeEventLoop, fd: i32, state: *mut ConnState, err: i32) {
+    unsafe {
+        if (*state).in_proc {
+            (*state).pending_close.get_or_insert(err);
+            return;
+        }
+    }
+    let state = unsafe { Box::from_raw(state) };
+    event_loop.conns.remove(&fd);
+    event_loop.conn_memory -= state.accounted;
+    ae_delete_file_event(event_loop, fd, AE_ALL_MASK);
+    keepalive::forget(event_loop, fd);
+    ae_backoff_cancel(event_loop, fd);
+    idle::forget(event_loop, fd);
+    stats::record_conn_close(event_loop);
+    unsafe { libc::close(fd) };
+    if let Some(close_proc) = state.close_proc {
+        close_proc(event_loop, fd, err, state.client_data);
+    }
+    drain::check(event_loop);
+}
+
+/* Write pending output, arming or disarming the writable event as
+ * needed. A write failing for lack of buffer space is retried after a
+ * delay, see ae_backoff(). */
+fn flush(event_loop: &mut AeEventLoop, fd: i32, state: *mut ConnState) {
+    if ae_backoff_suspended(event_loop, fd) & AE_WRITABLE != 0 {
+        account(event_loop, state);
+        return;
+    }
+    let output = unsafe { &mut (*state).output };
+    while !output.is_empty() {
+        match output.write_to(fd) {
+            Ok(written) => unsafe {
+                (*state).bytes_out += written as u64;
+                (*state).last_interaction_us = event_loop.cached_now_us;
+                stats::record_conn_io(event_loop, 0, written as u64);
+                ae_note_write_progress(event_loop, fd);
+                ae_backoff_reset(event_loop, fd);
+            },
+            Err(err) if ae_is_transient_error(err) => {
+                account(event_loop, state);
+                if !arm_writable(event_loop, fd, state) {
+                    close_with(event_loop, fd, state, libc::ENOMEM);
+                } else if ae_backoff(event_loop, fd, AE_WRITABLE, err) == AE_ERR as i64 {
+                    close_with(event_loop, fd, state, err);
+                }
+                return;
+            }
+            Err(err) => {
+                close_with(event_loop, fd, state, err);
+                return;
+            }
+        }
+    }
+
+    account(event_loop, state);
+    ae_delete_file_event(event_loop, fd, AE_WRITABLE);
+    unsafe {
+        if (*state).close_after_write {
+            close_with(event_loop, fd, state, 0);
+        } else if (*state).handover.is_some() {
+            finish_handover(event_loop, fd, state);
+        } else if (*state).shutdown_write && !(*state).write_shut {
+            /* The peer may be gone already, reads will tell. */
+            libc::shutdown(fd, libc::SHUT_WR);
+            (*state).write_shut = true;
+        }
+    }
+}
+
+/* The output of a connection being handed over is written: forget it
+ * and give its fd away. */
+fn finish_handover(event_loop: &mut AeEventLoop, fd: i32, state: *mut ConnState) {
+    let mut state = unsafe { Box::from_raw(state) };
+    let handover = state.handover.take().expect("handover pending");
+    event_loop.conns.remove(&fd);
+    event_loop.conn_memory -= state.accounted;
+    ae_delete_file_event(event_loop, fd, AE_ALL_MASK);
+    keepalive::forget(event_loop, fd);
+    idle::forget(event_loop, fd);
+    ae_backoff_cancel(event_loop, fd);
+    stats::record_conn_close(event_loop);
+    let input = std::mem::take(&mut state.input);
+    match handover {
+        Handover::Proc(proc, client_data) => proc(event_loop, fd, input, client_data),
+        Handover::Migrate(proc) => {
+            let procs = ConnProcs {
+                proc: state.proc,
+                close_proc: state.close_proc,
+                eof_proc: state.eof_proc,
+                client_data: state.client_data,
+                bytes_in: state.bytes_in,
+                bytes_out: state.bytes_out,
+            };
+            proc(event_loop, fd, input, procs);
+        }
+    }
+    drain::check(event_loop);
+}
+
+/* Bring the loop total up to date with what the connection holds now:
+ * its state, the input buffer and the queued output. */
+fn account(event_loop: &mut AeEventLoop, state: *mut ConnState) {
+    let footprint = unsafe {
+        std::mem::size_of::<ConnState>() + (*state).input.capacity() + (*state).output.len()
+    };
+    let accounted = unsafe { std::mem::replace(&mut (*state).accounted, footprint) };
+    event_loop.conn_memory = event_loop.conn_memory - accounted + footprint;
+}
+
+fn arm_writable(event_loop: &mut AeEventLoop, fd: i32, state: *mut ConnState) -> bool {
+    ae_get_file_events(event_loop, fd) & AE_WRITABLE != 0
+        || ae_create_file_event(
+            event_loop,
+            fd,
+            AE_WRITABLE,
+            conn_writable_handler,
+            state as *mut c_void,
+        ) == AE_OK
+}
+
+fn conn_writable_handler(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    client_data: *mut c_void,
+    _mask: i32,
+) {
+    flush(event_loop, fd, client_data as *mut ConnState);
+}
+
+fn conn_readable_handler(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    client_data: *mut c_void,
+    _mask: i32,
+) {
+    let state = client_data as *mut ConnState;
+    let mut chunk = [0u8; AE_CONN_READ_LEN];
+    let nread = unsafe { libc::read(fd, chunk.as_mut_ptr() as *mut c_void, chunk.len()) };
+    if nread < 0 {
+        let err = errno();
+        if ae_backoff(event_loop, fd, AE_READABLE, err) == AE_ERR as i64 {
+            close_with(event_loop, fd, state, err);
+        }
+        return;
+    }
+    if nread == 0 {
+        read_eof(event_loop, fd, state);
+        return;
+    }
+
+    /* Take the input out of the state while the proc looks at it: the
+     * proc may write to the connection, which touches the state. */
+    let mut input = unsafe {
+        (*state).in_proc = true;
+        (*state).bytes_in += nread as u64;
+        (*state).last_interaction_us = event_loop.cached_now_us;
+        std::mem::take(&mut (*state).input)
+    };
+    stats::record_conn_io(event_loop, nread as u64, 0);
+    if input.capacity() == 0 {
+        input = bufpool::ae_buf_acquire(event_loop, nread as usize);
+    }
+    input.extend_from_slice(&chunk[..nread as usize]);
+    feed(event_loop, fd, state, input);
+}
+
+/* Call the read proc with `input`, taken out of the state with in_proc
+ * set, then put back what it left. */
+fn feed(event_loop: &mut AeEventLoop, fd: i32, state: *mut ConnState, mut input: Vec<u8>) {
+    loop {
+        let (proc, user_data) = unsafe { ((*state).proc, (*state).client_data) };
+        let consumed = proc(event_loop, fd, &input, user_data).min(input.len());
+        input.drain(..consumed);
+        /* Procs replaced by this one get the rest right away, it may be
+         * all the peer sends for now. */
+        let replaced = unsafe { std::mem::take(&mut (*state).replaced) };
+        if !replaced
+            || input.is_empty()
+            || unsafe { (*state).handover.is_some() }
+            || ae_conn_closing(event_loop, fd)
+        {
+            break;
+        }
+    }
+    /* Nothing left to parse: the buffer goes back to the pool rather than
+     * staying with a connection that may be idle for a while. */
+    if input.is_empty() {
+        bufpool::ae_buf_release(event_loop, std::mem::take(&mut input));
+    }
+
+    unsafe {
+        (*state).in_proc = false;
+        (*state).input = input;
+    }
+    after_proc(event_loop, fd, state);
+}
+
+/* The peer sent end of file. */
+fn read_eof(event_loop: &mut AeEventLoop, fd: i32, state: *mut ConnState) {
+    let (eof_proc, user_data, shutdown_write) = unsafe {
+        (
+            (*state).eof_proc,
+            (*state).client_data,
+            (*state).shutdown_write,
+        )
+    };
+    /* Done in both directions once our own end of file is out. */
+    if shutdown_write {
+        ae_conn_close_after_write(event_loop, fd);
+        return;
+    }
+    let eof_proc = match eof_proc {
+        Some(eof_proc) => eof_proc,
+        None => {
+            close_with(event_loop, fd, state, 0);
+            return;
+        }
+    };
+    ae_delete_file_event(event_loop, fd, AE_READABLE);
+    unsafe {
+        (*state).read_eof = true;
+        (*state).in_proc = true;
+    }
+    eof_proc(event_loop, fd, user_data);
+    unsafe { (*state).in_proc = false };
+    after_proc(event_loop, fd, state);
+}
+
+/* Act on what the read or eof proc asked for while it ran. */
+fn after_proc(event_loop: &mut AeEventLoop, fd: i32, state: *mut ConnState) {
+    account(event_loop, state);
+    let (pending_close, input_len, flush_due) = unsafe {
+        (
+            (*state).pending_close,
+            (*state).input.len(),
+            !(*state).output.is_empty() || ((*state).shutdown_write && !(*state).write_shut),
+        )
+    };
+    let handover = unsafe { (*state).handover.is_some() };
+    if let Some(err) = pending_close {
+        close_with(event_loop, fd, state, err);
+    } else if input_len > AE_CONN_MAX_INPUT && !handover {
+        close_with(event_loop, fd, state, libc::ENOBUFS);
+    } else if flush_due || handover {
+        flush(event_loop, fd, state);
+    }
+}
+
+/* Called when the connection `old_fd` was moved to `new_fd`, see
+ * ae_resize_set_size_compact(). */
+pub(crate) fn relocate(event_loop: &mut AeEventLoop, old_fd: i32, new_fd: i32) {
+    if let Some(state) = event_loop.conns.remove(&old_fd) {
+        event_loop.conns.insert(new_fd, state);
+    }
+}
@@ -0,0 +1,126 @@
+/* Callback context.
+ *
+ * While a file or time event is dispatched the loop keeps a record of it:
+ * what it is, when it started, and an optional label the callback can set.
+ * Callbacks that run the loop again (a nested ae_process_events()) stack a
+ * record on top of their own. Diagnostics use the stack to say exactly
+ * what the loop was doing: ae_doctor() reports dispatches that have been
+ * running for too long, crash reports (see ae_set_crash_reporter()) carry
+ * the stack at the time of the panic, and an application's own latency
+ * reports can include ae_dispatch_stack().
+ */
+
+use crate::ae::AeEventLoop;
+use crate::ae::custom::AeCustomEventId;
+use crate::ae::flight;
+use crate::ae::timer_batch::AeTimerBatchId;
+use crate::constants::{AE_ERR, AE_OK};
+
+/* Records kept beyond this depth are dropped: a loop recursing that deep
+ * is broken anyway, and the stack stays small. */
+pub const AE_DISPATCH_STACK_MAX: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeDispatchSource {
+    /* Read and/or write handlers of an fd, with the mask that fired. */
+    File {
+        fd: i32,
+        mask: i32,
+    },
+    Timer {
+        id: i64,
+    },
+    /* One call of a batch proc, for `timers` due members. */
+    TimerBatch {
+        batch: AeTimerBatchId,
+        timers: usize,
+    },
+    Custom {
+        id: AeCustomEventId,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AeDispatchRecord {
+    pub source: AeDispatchSource,
+    /* Loop clock time (see ae_loop_now()) the dispatch started at. */
+    pub started_us: u64,
+    /* Set by the callback with ae_set_dispatch_label(), jobs use their
+     * name. */
+    pub label: Option<&'static str>,
+}
+
+#[derive(Default)]
+pub(crate) struct DispatchStack {
+    records: Vec<AeDispatchRecord>,
+    /* Pushes beyond AE_DISPATCH_STACK_MAX, popped without a record. */
+    overflow: usize,
+}
+
+pub(crate) fn push(event_loop: &mut AeEventLoop, source: AeDispatchSource) {
+    let started_us = event_loop.now_us();
+    flight::begin(event_loop, source, started_us);
+    let stack = &mut event_loop.dispatch_stack;
+    if stack.records.len() == AE_DISPATCH_STACK_MAX {
+        stack.overflow += 1;
+        return;
+    }
+    stack.records.push(AeDispatchRecord {
+        source,
+        started_us,
+        label: None,
+    });
+}
+
+/* The innermost dispatch returned `result`, see AeFlightOutcome. */
+pub(crate) fn pop(event_loop: &mut AeEventLoop, result: i32) {
+    let stack = &mut event_loop.dispatch_stack;
+    let label = if stack.overflow > 0 {
+        stack.overflow -= 1;
+        None
+    } else {
+        stack.records.pop().and_then(|record| record.label)
+    };
+    flight::end(event_loop, label, result);
+}
+
+/* Number of dispatches in progress, see truncate(). */
+pub(crate) fn depth(event_loop: &AeEventLoop) -> usize {
+    let stack = &event_loop.dispatch_stack;
+    stack.records.len() + stack.overflow
+}
+
+/* Drop the records above `depth`, left behind by dispatches a panic
+ * unwound through. */
+pub(crate) fn truncate(event_loop: &mut AeEventLoop, depth: usize) {
+    flight::truncate(event_loop, depth);
+    let stack = &mut event_loop.dispatch_stack;
+    if depth <= stack.records.len() {
+        stack.records.truncate(depth);
+        stack.overflow = 0;
+    } else {
+        stack.overflow = depth - stack.records.len();
+    }
+}
+
+/* The innermost dispatch, None when no callback is running. */
+pub fn ae_current_dispatch(event_loop: &AeEventLoop) -> Option<AeDispatchRecord> {
+    event_loop.dispatch_stack.records.last().copied()
+}
+
+/* Every dispatch in progress, outermost first. */
+pub fn ae_dispatch_stack(event_loop: &AeEventLoop) -> Vec<AeDispatchRecord> {
+    event_loop.dispatch_stack.records.clone()
+}
+
+/* Label the innermost dispatch, e.g. with the command being served.
+ * Returns AE_ERR if no callback is running. */
+pub fn ae_set_dispatch_label(event_loop: &mut AeEventLoop, label: &'static str) -> i32 {
+    match event_loop.dispatch_stack.records.last_mut() {
+        Some(record) => {
+            record.label = Some(label);
+            AE_OK
+        }
+        None => AE_ERR,
+    }
+}
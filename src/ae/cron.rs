@@ -0,0 +1,220 @@
+/* Cron-style scheduled jobs.
+ *
+ * ae_create_cron_event() runs a proc on the usual five field crontab
+ * schedule ("minute hour day-of-month month day-of-week"), evaluated in
+ * local time on top of the wall-clock timers, so jobs keep their schedule
+ * across clock changes.
+ *
+ * Each field is "*", a number, a range "a-b", any of these followed by a
+ * step "/n", or a comma separated list of them. Month and day names are
+ * not supported; day of week 0 and 7 are both Sunday. As in Vixie cron,
+ * when both day of month and day of week are restricted a day matching
+ * either one is selected.
+ */
+
+use crate::ae::AeEventLoop;
+use crate::ae::wallclock::ae_create_wallclock_event;
+use crate::constants::{AE_ERR, AE_NOMORE};
+use crate::traits::{EventFinalizerProc, TimeProc};
+use std::ffi::c_void;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/* Give up looking for the next match after this many years (e.g. for
+ * "0 0 30 2 *", which never matches). */
+const AE_CRON_MAX_YEARS: i32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AeCronExpr {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    dom_restricted: bool,
+    dow_restricted: bool,
+}
+
+impl AeCronExpr {
+    /* Parse a five field expression, None if it is malformed. */
+    pub fn parse(expr: &str) -> Option<AeCronExpr> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return None;
+        }
+        let mut days_of_week = parse_field(fields[4], 0, 7)?;
+        /* 7 is another name for Sunday. */
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week & !(1 << 7)) | 1;
+        }
+        Some(AeCronExpr {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days_of_month: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            days_of_week,
+            dom_restricted: !fields[2].starts_with('*'),
+            dow_restricted: !fields[4].starts_with('*'),
+        })
+    }
+
+    fn day_matches(&self, tm: &libc::tm) -> bool {
+        let dom = self.days_of_month & (1 << tm.tm_mday) != 0;
+        let dow = self.days_of_week & (1 << tm.tm_wday) != 0;
+        if self.dom_restricted && self.dow_restricted {
+            dom || dow
+        } else {
+            dom && dow
+        }
+    }
+
+    /* First minute strictly after `after` matching the expression, in
+     * local time. None if there is none in the next few years. */
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        let secs = after.duration_since(UNIX_EPOCH).ok()?.as_secs() as libc::time_t;
+        let mut tm = local_time(secs - secs % 60 + 60)?;
+        let last_year = tm.tm_year + AE_CRON_MAX_YEARS;
+
+        /* Walk forward one field at a time, from the coarsest: when a field
+         * does not match, jump to the start of its next value and let
+         * mktime() normalize the overflow. */
+        while tm.tm_year <= last_year {
+            if self.months & (1 << (tm.tm_mon + 1)) == 0 {
+                tm.tm_mon += 1;
+                tm.tm_mday = 1;
+                tm.tm_hour = 0;
+                tm.tm_min = 0;
+            } else if !self.day_matches(&tm) {
+                tm.tm_mday += 1;
+                tm.tm_hour = 0;
+                tm.tm_min = 0;
+            } else if self.hours & (1 << tm.tm_hour) == 0 {
+                tm.tm_hour += 1;
+                tm.tm_min = 0;
+            } else if self.minutes & (1 << tm.tm_min) == 0 {
+                tm.tm_min += 1;
+            } else {
+                let t = unsafe { libc::mktime(&mut tm) };
+                return Some(UNIX_EPOCH + Duration::from_secs(t as u64));
+            }
+            tm.tm_isdst = -1;
+            let t = unsafe { libc::mktime(&mut tm) };
+            if t == -1 {
+                return None;
+            }
+            tm = local_time(t)?;
+        }
+        None
+    }
+}
+
+/* Bitmask of the values in [min, max] selected by one field. */
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return None;
+        }
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some((lo, hi)) = range.split_once('-') {
+            (lo.parse().ok()?, hi.parse().ok()?)
+        } else {
+            let value = range.parse().ok()?;
+            /* "5/15" means from 5 to the end in steps of 15. */
+            (value, if part.contains('/') { max } else { value })
+        };
+        if lo < min || hi > max || lo > hi {
+            return None;
+        }
+        for value in (lo..=hi).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Some(mask)
+}
+
+fn local_time(t: libc::time_t) -> Option<libc::tm> {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+        return None;
+    }
+    Some(tm)
+}
+
+struct CronState {
+    expr: AeCronExpr,
+    proc: TimeProc,
+    client_data: *mut c_void,
+    finalizer_proc: Option<EventFinalizerProc>,
+}
+
+/* Call `proc` at every minute matching the cron expression `expr`. The
+ * proc returns AE_NOMORE to cancel the job, any other value keeps the
+ * schedule.
+ *
+ * Returns a wall-clock timer id (see ae_delete_wallclock_event()), or
+ * AE_ERR if the expression is invalid or never matches. */
+pub fn ae_create_cron_event(
+    event_loop: &mut AeEventLoop,
+    expr: &str,
+    proc: TimeProc,
+    client_data: *mut c_void,
+    finalizer_proc: Option<EventFinalizerProc>,
+) -> i64 {
+    let expr = match AeCronExpr::parse(expr) {
+        Some(expr) => expr,
+        None => return AE_ERR as i64,
+    };
+    let first = match expr.next_after(SystemTime::now()) {
+        Some(first) => first,
+        None => return AE_ERR as i64,
+    };
+
+    let state = Box::into_raw(Box::new(CronState {
+        expr,
+        proc,
+        client_data,
+        finalizer_proc,
+    }));
+    let id = ae_create_wallclock_event(
+        event_loop,
+        first,
+        cron_handler,
+        state as *mut c_void,
+        Some(cron_finalizer),
+    );
+    if id == AE_ERR as i64 {
+        drop(unsafe { Box::from_raw(state) });
+    }
+    id
+}
+
+fn cron_handler(event_loop: &mut AeEventLoop, id: i64, client_data: *mut c_void) -> i32 {
+    let (expr, proc, user_data) = {
+        let state = unsafe { &*(client_data as *const CronState) };
+        (state.expr, state.proc, state.client_data)
+    };
+    if proc(event_loop, id, user_data) == AE_NOMORE {
+        return AE_NOMORE;
+    }
+
+    let now = SystemTime::now();
+    match expr.next_after(now) {
+        /* Round up: waking up early would run the job twice. */
+        Some(next) => next.duration_since(now).map_or(0, |left| {
+            left.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32
+        }),
+        None => AE_NOMORE,
+    }
+}
+
+fn cron_finalizer(event_loop: &mut AeEventLoop, client_data: *mut c_void) {
+    let state = unsafe { Box::from_raw(client_data as *mut CronState) };
+    if let Some(finalizer) = state.finalizer_proc {
+        finalizer(event_loop, state.client_data);
+    }
+}
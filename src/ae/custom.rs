@@ -0,0 +1,82 @@
+/* User defined event sources.
+ *
+ * Telling a loop that something happened elsewhere (a worker finished a
+ * job, a cache was refilled, the configuration changed) usually costs a
+ * pipe per source, or a task posted per occurrence. A custom event is
+ * registered on the loop with a proc and an id instead, and triggered by
+ * id from any thread through an AeHandle. Every source shares the wakeup
+ * pipe of the handle, and triggers coalesce until the loop gets to them:
+ * the proc is called once and told how many there were.
+ */
+
+use crate::ae::context::{self, AeDispatchSource};
+use crate::ae::handle::ae_get_handle;
+use crate::ae::{AeEventLoop, panic, stats};
+use crate::constants::{AE_ERR, AE_OK};
+use crate::traits::CustomEventProc;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::c_void;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AeCustomEventId(pub(crate) u64);
+
+#[derive(Default)]
+pub(crate) struct CustomEvents {
+    procs: HashMap<u64, (CustomEventProc, *mut c_void)>,
+    next_id: u64,
+}
+
+/* Register a custom event calling `proc`. Returns None if the loop has
+ * no handle and none can be created (see ae_get_handle()). */
+pub fn ae_create_custom_event(
+    event_loop: &mut AeEventLoop,
+    proc: CustomEventProc,
+    client_data: *mut c_void,
+) -> Option<AeCustomEventId> {
+    ae_get_handle(event_loop)?;
+    let events = &mut event_loop.custom;
+    let id = events.next_id;
+    events.next_id += 1;
+    events.procs.insert(id, (proc, client_data));
+    Some(AeCustomEventId(id))
+}
+
+/* Delete a custom event. Triggers still pending for it are dropped.
+ * Returns AE_ERR for an unknown id. */
+pub fn ae_delete_custom_event(event_loop: &mut AeEventLoop, id: AeCustomEventId) -> i32 {
+    match event_loop.custom.procs.remove(&id.0) {
+        Some(_) => AE_OK,
+        None => AE_ERR,
+    }
+}
+
+/* Trigger a custom event from the loop thread. Like AeHandle::trigger(),
+ * the proc runs at the next iteration, not from here. Returns AE_ERR for
+ * an unknown id. */
+pub fn ae_trigger_custom_event(event_loop: &mut AeEventLoop, id: AeCustomEventId) -> i32 {
+    if !event_loop.custom.procs.contains_key(&id.0) {
+        return AE_ERR;
+    }
+    match ae_get_handle(event_loop) {
+        Some(handle) => handle.trigger(id),
+        None => AE_ERR,
+    }
+}
+
+/* Run the procs of the events triggered since the last call, in id
+ * order, with the number of triggers of each. Called by the wakeup
+ * handler of the handle. */
+pub(crate) fn dispatch(event_loop: &mut AeEventLoop, triggered: BTreeMap<u64, u64>) {
+    for (id, triggers) in triggered {
+        /* Looked up one at a time: a proc may delete other events. */
+        let Some(&(proc, client_data)) = event_loop.custom.procs.get(&id) else {
+            continue;
+        };
+        let id = AeCustomEventId(id);
+        let start = stats::callback_start(event_loop);
+        context::push(event_loop, AeDispatchSource::Custom { id });
+        panic::guard(event_loop, |el| proc(el, id, client_data, triggers));
+        context::pop(event_loop, AE_OK);
+        stats::record_callback(event_loop, start);
+    }
+}
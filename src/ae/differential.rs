@@ -0,0 +1,388 @@
+/* Differential backend testing.
+ *
+ * A new backend is easiest to trust next to one that already works.
+ * AeEventLoopBuilder::differential() puts the loop backend (the primary)
+ * and a second one side by side: every registration goes to both, the
+ * primary polls and drives the loop as usual, and right after each of
+ * its polls the secondary polls without waiting, so their answers can be
+ * compared. Select is paired with the native backend (epoll, kqueue) and
+ * the native backend with select.
+ *
+ * The backends are level-triggered, and nothing is dispatched between
+ * the two polls, so the secondary must report at least what the primary
+ * did: a direction the primary found ready and the secondary did not is
+ * a divergence (Missed). The secondary may see more, for an fd that got
+ * ready in between; what it reports beyond the primary is only a
+ * divergence (Spurious) if the primary, polled again, still does not see
+ * it. A registration one backend accepted and the other refused is one
+ * too (Registration). Polls that filled the fired buffer are not
+ * compared, the backends may have picked different fds.
+ *
+ * Divergences are counted in AeStats::backend_divergences, the latest are
+ * kept for ae_backend_divergences(), and each one is raised as an
+ * AeFindingKind::BackendDivergence finding through the diagnostic proc.
+ * The secondary poll doubles the syscalls of the loop: a testing tool.
+ * Select only watches fds below FD_SETSIZE, its answers past that are
+ * divergences as any other.
+ */
+
+use crate::ae::doctor::{AeFinding, AeFindingKind, AeFindingSeverity};
+use crate::ae::{AeEventLoop, AeFileEvent, doctor};
+use crate::ae_select::FiredEvent;
+use crate::constants::{AE_NONE, AE_READABLE, AE_WRITABLE};
+use crate::traits::EventBackend;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+
+/* Divergences kept for ae_backend_divergences(), the oldest dropped. */
+pub const AE_DIVERGENCE_LOG_LEN: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeDivergenceKind {
+    /* Directions ready per the primary that the secondary missed. */
+    Missed,
+    /* Directions ready per the secondary only, even polling the primary
+     * again. */
+    Spurious,
+    /* A registration accepted by one backend and refused by the other:
+     * the masks are the one asked for on the side that accepted it, 0 on
+     * the other. */
+    Registration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AeDivergence {
+    pub kind: AeDivergenceKind,
+    pub fd: i32,
+    pub primary_mask: i32,
+    pub secondary_mask: i32,
+    /* Iteration of the loop it was found in (see ae_get_stats()). */
+    pub iteration: u64,
+}
+
+/* Divergences found by the backend, moved to the loop after each poll. */
+pub(crate) struct DivergenceLog {
+    pending: Rc<RefCell<Vec<AeDivergence>>>,
+    recent: VecDeque<AeDivergence>,
+    secondary: &'static str,
+}
+
+/* Pair `primary` with the other kind of backend, sized for `setsize`.
+ * The log goes to the loop, see report(). */
+pub(crate) fn pair(
+    primary: Box<dyn EventBackend>,
+    setsize: i32,
+) -> Result<(Box<dyn EventBackend>, DivergenceLog), i32> {
+    /* Select is left at FD_SETSIZE, as for a loop of its own. */
+    let secondary = if primary.name() == crate::ae_select::ae_api_name() {
+        let mut secondary = crate::ae::probe::create_best_backend()?;
+        if secondary.resize(setsize) == -1 {
+            return Err(libc::ENOMEM);
+        }
+        secondary
+    } else {
+        crate::ae::create_select_backend()?
+    };
+    let pending = Rc::new(RefCell::new(Vec::new()));
+    let secondary_name = secondary.name();
+    let backend = AeDifferentialBackend {
+        primary,
+        secondary,
+        fired: Vec::new(),
+        pending: pending.clone(),
+    };
+    let log = DivergenceLog {
+        pending,
+        recent: VecDeque::new(),
+        secondary: secondary_name,
+    };
+    Ok((Box::new(backend), log))
+}
+
+/* Move the divergences found by the backend to the loop: stats, log and
+ * diagnostic proc. Called after every poll. */
+pub(crate) fn report(event_loop: &mut AeEventLoop) {
+    let Some(log) = event_loop.differential.as_mut() else {
+        return;
+    };
+    if log.pending.borrow().is_empty() {
+        return;
+    }
+    let found = std::mem::take(&mut *log.pending.borrow_mut());
+    let iteration = event_loop.stats.stats.iterations + 1;
+    for mut divergence in found {
+        divergence.iteration = iteration;
+        let Some(log) = event_loop.differential.as_mut() else {
+            return;
+        };
+        if log.recent.len() == AE_DIVERGENCE_LOG_LEN {
+            log.recent.pop_front();
+        }
+        log.recent.push_back(divergence);
+        event_loop.stats.stats.backend_divergences += 1;
+        let total = event_loop.stats.stats.backend_divergences;
+        doctor::raise(event_loop, finding(event_loop, divergence, total));
+    }
+}
+
+/* Latest divergences found, oldest first. Empty unless the loop was built
+ * with AeEventLoopBuilder::differential(). */
+pub fn ae_backend_divergences(event_loop: &AeEventLoop) -> Vec<AeDivergence> {
+    event_loop
+        .differential
+        .as_ref()
+        .map_or(Vec::new(), |log| log.recent.iter().copied().collect())
+}
+
+/* Name of the secondary backend of a differential loop, None for other
+ * loops. ae_get_api_name() gives the primary. */
+pub fn ae_differential_secondary(event_loop: &AeEventLoop) -> Option<&'static str> {
+    event_loop.differential.as_ref().map(|log| log.secondary)
+}
+
+/* The finding of ae_doctor() and of the diagnostic proc for `divergence`,
+ * `total` being the count so far. */
+pub(crate) fn finding(event_loop: &AeEventLoop, divergence: AeDivergence, total: u64) -> AeFinding {
+    let (primary, secondary) = (
+        event_loop.apidata.name(),
+        ae_differential_secondary(event_loop).unwrap_or("?"),
+    );
+    let what = match divergence.kind {
+        AeDivergenceKind::Missed => format!(
+            "{primary} reported fd {} ready for {} but {secondary} only for {}",
+            divergence.fd,
+            mask_name(divergence.primary_mask),
+            mask_name(divergence.secondary_mask)
+        ),
+        AeDivergenceKind::Spurious => format!(
+            "{secondary} reported fd {} ready for {} but {primary} only for {}",
+            divergence.fd,
+            mask_name(divergence.secondary_mask),
+            mask_name(divergence.primary_mask)
+        ),
+        AeDivergenceKind::Registration => format!(
+            "registering fd {} for {} succeeded on {} only",
+            divergence.fd,
+            mask_name(divergence.primary_mask | divergence.secondary_mask),
+            if divergence.primary_mask != AE_NONE {
+                primary
+            } else {
+                secondary
+            }
+        ),
+    };
+    AeFinding {
+        severity: AeFindingSeverity::Critical,
+        kind: AeFindingKind::BackendDivergence { divergence, total },
+        message: format!(
+            "Backends diverged at iteration {} ({} so far): {what}",
+            divergence.iteration, total
+        ),
+    }
+}
+
+fn mask_name(mask: i32) -> &'static str {
+    match (mask & AE_READABLE != 0, mask & AE_WRITABLE != 0) {
+        (true, true) => "reading and writing",
+        (true, false) => "reading",
+        (false, true) => "writing",
+        (false, false) => "nothing",
+    }
+}
+
+/* Both backends behind one, see the top of the file. */
+struct AeDifferentialBackend {
+    primary: Box<dyn EventBackend>,
+    secondary: Box<dyn EventBackend>,
+    /* Fired buffer of the secondary. */
+    fired: Vec<FiredEvent>,
+    pending: Rc<RefCell<Vec<AeDivergence>>>,
+}
+
+impl AeDifferentialBackend {
+    fn diverged(&self, kind: AeDivergenceKind, fd: i32, primary_mask: i32, secondary_mask: i32) {
+        self.pending.borrow_mut().push(AeDivergence {
+            kind,
+            fd,
+            primary_mask,
+            secondary_mask,
+            iteration: 0,
+        });
+    }
+
+    fn registered(&self, fd: i32, mask: i32, primary: i32, secondary: i32) {
+        let mask = mask & (AE_READABLE | AE_WRITABLE);
+        match (primary != -1, secondary != -1) {
+            (true, false) => self.diverged(AeDivergenceKind::Registration, fd, mask, AE_NONE),
+            (false, true) => self.diverged(AeDivergenceKind::Registration, fd, AE_NONE, mask),
+            _ => {}
+        }
+    }
+
+    /* Compare what the secondary reports right after the primary fired
+     * `fired`, out of a buffer of `capacity` events. */
+    fn compare(
+        &mut self,
+        events: &[AeFileEvent],
+        fired: &[FiredEvent],
+        capacity: usize,
+        maxfd: i32,
+    ) {
+        self.fired.resize(capacity, FiredEvent { fd: 0, mask: 0 });
+        let Ok(n) = self.secondary.poll_nowait(events, &mut self.fired, maxfd) else {
+            return;
+        };
+        let n = n.max(0) as usize;
+        if n >= capacity {
+            return;
+        }
+        /* Only the directions the loop registered count: backends report
+         * errors and hangups on different directions. */
+        let interest = |fd: i32| {
+            events
+                .get(fd as usize)
+                .map_or(AE_NONE, |fe| fe.mask & (AE_READABLE | AE_WRITABLE))
+        };
+        let mask_in = |set: &[FiredEvent], fd: i32| {
+            set.iter()
+                .filter(|e| e.fd == fd)
+                .fold(AE_NONE, |mask, e| mask | e.mask)
+                & interest(fd)
+        };
+
+        /* kqueue reports each direction of an fd as an event of its own. */
+        let fds = |set: &[FiredEvent]| {
+            let mut fds: Vec<i32> = set.iter().map(|e| e.fd).collect();
+            fds.sort_unstable();
+            fds.dedup();
+            fds
+        };
+
+        for fd in fds(fired) {
+            let primary = mask_in(fired, fd);
+            let secondary = mask_in(&self.fired[..n], fd);
+            if primary & !secondary != 0 {
+                self.diverged(AeDivergenceKind::Missed, fd, primary, secondary);
+            }
+        }
+
+        let extra: Vec<(i32, i32)> = fds(&self.fired[..n])
+            .into_iter()
+            .map(|fd| (fd, mask_in(&self.fired[..n], fd)))
+            .filter(|&(fd, secondary)| secondary & !mask_in(fired, fd) != 0)
+            .collect();
+        if extra.is_empty() {
+            return;
+        }
+        let mut again = vec![FiredEvent { fd: 0, mask: 0 }; capacity];
+        let Ok(m) = self.primary.poll_nowait(events, &mut again, maxfd) else {
+            return;
+        };
+        let m = m.max(0) as usize;
+        if m >= again.len() {
+            return;
+        }
+        for (fd, secondary) in extra {
+            let primary = mask_in(&again[..m], fd);
+            if secondary & !primary != 0 {
+                self.diverged(AeDivergenceKind::Spurious, fd, primary, secondary);
+            }
+        }
+    }
+}
+
+impl EventBackend for AeDifferentialBackend {
+    fn create() -> Result<Box<Self>, i32> {
+        Err(libc::ENOTSUP)
+    }
+
+    fn free(self: Box<Self>) {
+        let this = *self;
+        this.primary.free();
+        this.secondary.free();
+    }
+
+    /* The loop follows the primary: select refuses any setsize from
+     * FD_SETSIZE up, and only loses the fds past it. */
+    fn resize(&mut self, setsize: i32) -> i32 {
+        let retval = self.primary.resize(setsize);
+        if retval != -1 {
+            self.secondary.resize(setsize);
+        }
+        retval
+    }
+
+    fn add_event(&mut self, fd: i32, mask: i32) -> i32 {
+        let primary = self.primary.add_event(fd, mask);
+        let secondary = self.secondary.add_event(fd, mask);
+        self.registered(fd, mask, primary, secondary);
+        primary
+    }
+
+    fn add_event_exclusive(&mut self, fd: i32, mask: i32) -> i32 {
+        let primary = self.primary.add_event_exclusive(fd, mask);
+        let secondary = self.secondary.add_event_exclusive(fd, mask);
+        self.registered(fd, mask, primary, secondary);
+        primary
+    }
+
+    fn del_event(&mut self, fd: i32, mask: i32) {
+        self.primary.del_event(fd, mask);
+        self.secondary.del_event(fd, mask);
+    }
+
+    fn poll(
+        &mut self,
+        events: &[AeFileEvent],
+        fired: &mut [FiredEvent],
+        maxfd: i32,
+        timeout: Option<Duration>,
+    ) -> Result<i32, i32> {
+        let n = self.primary.poll(events, fired, maxfd, timeout)?;
+        let nfired = n.max(0) as usize;
+        if nfired < fired.len() {
+            self.compare(events, &fired[..nfired], fired.len(), maxfd);
+        }
+        Ok(n)
+    }
+
+    fn poll_nowait(
+        &mut self,
+        events: &[AeFileEvent],
+        fired: &mut [FiredEvent],
+        maxfd: i32,
+    ) -> Result<i32, i32> {
+        let n = self.primary.poll_nowait(events, fired, maxfd)?;
+        let nfired = n.max(0) as usize;
+        if nfired < fired.len() {
+            self.compare(events, &fired[..nfired], fired.len(), maxfd);
+        }
+        Ok(n)
+    }
+
+    fn name(&self) -> &'static str {
+        self.primary.name()
+    }
+
+    fn fd(&self) -> i32 {
+        self.primary.fd()
+    }
+
+    fn reinit(&mut self) -> i32 {
+        if self.primary.reinit() == -1 {
+            return -1;
+        }
+        self.secondary.reinit()
+    }
+
+    fn stats(&self) -> crate::ae::stats::AeBackendStats {
+        self.primary.stats()
+    }
+
+    fn reset_stats(&mut self) {
+        self.primary.reset_stats();
+        self.secondary.reset_stats();
+    }
+}
@@ -0,0 +1,213 @@
+/* Cooperative time slicing.
+ *
+ * A callback working through a big job (rehashing a table, expiring keys,
+ * scanning a file) holds up every other event until it returns. With an
+ * iteration budget set, each iteration gets a deadline, the budget after
+ * poll returned. Callbacks see it through an AeDispatchCtx and, once
+ * should_yield() says so, stop and carry on at the next iteration with
+ * ae_call_soon(), the way Redis bounds incrementallyRehash() to 1ms.
+ * ae_yield_and_continue() is the one-shot form, for work that hands its
+ * state from one step to the next.
+ *
+ * Nothing is preempted: the deadline is only a hint to callbacks that
+ * check it.
+ *
+ * The deferred calls themselves are bounded: a call that keeps asking
+ * for another round, or one spawning more of them each time, would
+ * otherwise fill every iteration. At most AE_DEFERRED_LIMIT_DEFAULT of
+ * them (see ae_set_deferred_limit()) run per iteration, the oldest
+ * ones, the others wait for the next one. AeStats::deferred_backlog and
+ * deferred_postponed show a runaway.
+ */
+
+use crate::ae::{AeEventLoop, ae_create_time_event_owned, duration_us};
+use crate::constants::AE_NOMORE;
+
+/* Deferred calls run per iteration at most, unless changed with
+ * ae_set_deferred_limit(). */
+pub const AE_DEFERRED_LIMIT_DEFAULT: usize = 1024;
+use crate::monotonic::{AeClockSource, get_monotonic_us};
+use crate::traits::{ContinuationProc, SoonProc};
+use std::time::Duration;
+
+/* Deadline of the current iteration, handed to callbacks. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AeDispatchCtx {
+    deadline_us: Option<u64>,
+    clock: AeClockSource,
+    /* Time of a manual clock, which does not move during a callback. */
+    manual_now_us: u64,
+}
+
+impl AeDispatchCtx {
+    /* Loop clock time (see ae_loop_now()) by which the iteration should
+     * be done, None without an iteration budget. */
+    pub fn deadline_us(&self) -> Option<u64> {
+        self.deadline_us
+    }
+
+    /* Microseconds left until the deadline, None without a budget. */
+    pub fn remaining_us(&self) -> Option<u64> {
+        self.deadline_us
+            .map(|deadline| deadline.saturating_sub(self.now_us()))
+    }
+
+    /* True once the deadline has passed: a long running callback should
+     * save its progress and return. Always false without a budget. */
+    pub fn should_yield(&self) -> bool {
+        self.remaining_us() == Some(0)
+    }
+
+    /* Bring the deadline forward to `deadline_us` if that is earlier. */
+    pub(crate) fn shorten_deadline(&mut self, deadline_us: u64) {
+        self.deadline_us = Some(self.deadline_us.map_or(deadline_us, |d| d.min(deadline_us)));
+    }
+
+    fn now_us(&self) -> u64 {
+        match self.clock {
+            AeClockSource::Manual => self.manual_now_us,
+            clock => get_monotonic_us(clock),
+        }
+    }
+}
+
+/* Give each iteration `budget_us` microseconds, counted from the time
+ * poll returned, to dispatch its events. 0, the default, sets no
+ * deadline. Takes effect at the next iteration. */
+pub fn ae_set_iteration_budget(event_loop: &mut AeEventLoop, budget_us: u64) {
+    ae_set_iteration_budget_duration(event_loop, Duration::from_micros(budget_us));
+}
+
+/* ae_set_iteration_budget() taking the budget as a Duration,
+ * Duration::ZERO for no deadline. */
+pub fn ae_set_iteration_budget_duration(event_loop: &mut AeEventLoop, budget: Duration) {
+    event_loop.iteration_budget_us = duration_us(budget);
+}
+
+pub fn ae_get_iteration_budget(event_loop: &AeEventLoop) -> u64 {
+    event_loop.iteration_budget_us
+}
+
+/* Run at most `limit` deferred calls (ae_call_soon(),
+ * ae_yield_and_continue()) per iteration, 0 for no limit. */
+pub fn ae_set_deferred_limit(event_loop: &mut AeEventLoop, limit: usize) {
+    event_loop.deferred_limit = limit;
+}
+
+pub fn ae_get_deferred_limit(event_loop: &AeEventLoop) -> usize {
+    event_loop.deferred_limit
+}
+
+/* Deferred calls not deleted yet, see AeStats::deferred_backlog. */
+pub(crate) fn backlog(event_loop: &AeEventLoop) -> u64 {
+    let mut count = 0;
+    let mut node = event_loop.time_event_head.as_deref();
+    while let Some(current) = node {
+        if current.event.deferred && !current.event.deleted {
+            count += 1;
+        }
+        node = current.next.as_deref();
+    }
+    count
+}
+
+/* Flag timer `id` as a deferred call. */
+fn mark_deferred(event_loop: &mut AeEventLoop, id: i64) -> i64 {
+    let mut current = &mut event_loop.time_event_head;
+    while let Some(node) = current {
+        if node.event.id == id {
+            node.event.deferred = true;
+            break;
+        }
+        current = &mut node.next;
+    }
+    id
+}
+
+/* Context of the iteration being dispatched, for callbacks of the raw
+ * API that want to check the deadline. */
+pub fn ae_dispatch_ctx(event_loop: &AeEventLoop) -> AeDispatchCtx {
+    AeDispatchCtx {
+        deadline_us: event_loop.iteration_deadline_us,
+        clock: event_loop.clock,
+        manual_now_us: event_loop.manual_now_us,
+    }
+}
+
+/* Called by ae_process_events() when it starts dispatching. */
+pub(crate) fn start(event_loop: &mut AeEventLoop) {
+    let budget = event_loop.iteration_budget_us;
+    event_loop.iteration_deadline_us =
+        (budget > 0).then(|| event_loop.cached_now_us.saturating_add(budget));
+}
+
+struct SoonCall<T> {
+    proc: SoonProc<T>,
+    data: Box<T>,
+}
+
+fn soon_timer<T>(event_loop: &mut AeEventLoop, _id: i64, call: &mut SoonCall<T>) -> i32 {
+    let ctx = ae_dispatch_ctx(event_loop);
+    if (call.proc)(event_loop, &ctx, &mut call.data) {
+        0
+    } else {
+        AE_NOMORE
+    }
+}
+
+/* Call `proc` with `data` at the next iteration, and at every iteration
+ * after that for as long as it returns true. `data` is dropped once it
+ * returns false, or when the call is cancelled with
+ * ae_delete_time_event() on the returned id. */
+pub fn ae_call_soon<T: 'static>(
+    event_loop: &mut AeEventLoop,
+    proc: SoonProc<T>,
+    data: Box<T>,
+) -> i64 {
+    let id = ae_create_time_event_owned(
+        event_loop,
+        0,
+        soon_timer::<T>,
+        Box::new(SoonCall { proc, data }),
+    );
+    mark_deferred(event_loop, id)
+}
+
+struct Continuation<T> {
+    proc: ContinuationProc<T>,
+    state: Option<Box<T>>,
+}
+
+fn continuation_timer<T>(
+    event_loop: &mut AeEventLoop,
+    _id: i64,
+    continuation: &mut Continuation<T>,
+) -> i32 {
+    if let Some(state) = continuation.state.take() {
+        let ctx = ae_dispatch_ctx(event_loop);
+        (continuation.proc)(event_loop, &ctx, state);
+    }
+    AE_NOMORE
+}
+
+/* Stop here and call `continuation` with `state` at the next iteration,
+ * letting the other events in. The continuation owns the state: it
+ * yields again by passing it back to ae_yield_and_continue(), or drops it
+ * once the work is done. Cancelling with ae_delete_time_event() on the
+ * returned id drops the state. */
+pub fn ae_yield_and_continue<T: 'static>(
+    event_loop: &mut AeEventLoop,
+    state: Box<T>,
+    continuation: ContinuationProc<T>,
+) -> i64 {
+    let id = ae_create_time_event_owned(
+        event_loop,
+        0,
+        continuation_timer::<T>,
+        Box::new(Continuation {
+            proc: continuation,
+            state: Some(state),
+        }),
+    );
+    mark_deferred(event_loop, id)
+}
@@ -0,0 +1,344 @@
+/* Loop doctor.
+ *
+ * ae_doctor() looks at the loop state and the statistics gathered so far
+ * and reports conditions that usually explain latency problems, in the
+ * spirit of the LATENCY DOCTOR command of Redis. It only reads state, so
+ * it is cheap enough to be called from a periodic timer. Called from a
+ * long running callback, it also reports the dispatches in progress.
+ *
+ * Some conditions are better pushed than polled for. The loop raises
+ * those itself through the diagnostic proc (ae_set_diagnostic_proc()):
+ * with ae_set_timer_starvation_alarm(), a loop whose earliest timer keeps
+ * firing late reports it along with the job slice from the slowlog that
+ * most likely held it up, so "my cron drifted" comes with a culprit.
+ * Soft limits (see limits.rs) and the write stall watchdog (see
+ * watchdog.rs) raise theirs the same way.
+ */
+
+use crate::ae::context::{AeDispatchRecord, AeDispatchSource, ae_dispatch_stack};
+use crate::ae::differential::{self, AeDivergence};
+use crate::ae::job::{AE_SLOWLOG_MAX_LEN, AeSlowlogEntry, ae_slowlog_get};
+use crate::ae::limits::{self, AeSoftLimitResource};
+use crate::ae::watchdog;
+use crate::ae::{AeEventLoop, duration_us};
+use crate::traits::DiagnosticProc;
+use std::time::Duration;
+
+/* select() cannot watch fds >= FD_SETSIZE (1024), warn well before. */
+pub const AE_DOCTOR_SELECT_MAX_FD: i32 = 900;
+/* A timer firing this late after its deadline counts as late. */
+pub const AE_DOCTOR_TIMER_LAG_US: u64 = 10_000;
+/* Callbacks running longer than this block every other client. */
+pub const AE_DOCTOR_CALLBACK_US: u64 = 10_000;
+/* Minimum number of samples before percentiles are considered. */
+const MIN_SAMPLES: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AeFindingSeverity {
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AeFindingKind {
+    /* The select backend is close to its FD_SETSIZE limit. */
+    SelectNearFdLimit {
+        maxfd: i32,
+    },
+    /* Timers keep firing well after their deadline. */
+    LateTimers {
+        count: u64,
+        p99_lag_us: u64,
+    },
+    /* Some callbacks take long enough to stall the loop. */
+    SlowCallbacks {
+        p99_us: u64,
+        max_us: u64,
+    },
+    /* The backend returned as many events as the fired buffer holds, so
+     * ready fds may have been left for the next iteration. */
+    FiredBufferSaturated {
+        times: u64,
+    },
+    /* ae_doctor() was called from within a dispatch that has been
+     * running for too long already. */
+    LongDispatch {
+        record: AeDispatchRecord,
+        running_us: u64,
+    },
+    /* The earliest due timer fired more than the alarm margin late
+     * `misses` iterations in a row (see ae_set_timer_starvation_alarm()).
+     * `offender` is the slowest job slice of the slowlog that was still
+     * running when the first of those deadlines passed, or ran after it. */
+    TimerStarvation {
+        misses: u32,
+        worst_lag_us: u64,
+        offender: Option<AeSlowlogEntry>,
+    },
+    /* The two backends of a differential loop disagreed (see
+     * AeEventLoopBuilder::differential()), `total` times so far. */
+    BackendDivergence {
+        divergence: AeDivergence,
+        total: u64,
+    },
+    /* An fd below 3 was registered under AeStdioPolicy::Warn. */
+    StdioRegistration {
+        fd: i32,
+        mask: i32,
+    },
+    /* `fd` kept AE_WRITABLE registered for `stalled_us` without flushing
+     * anything (see ae_set_write_stall_watchdog()), with `pending` bytes
+     * of connection output waiting. */
+    WriteStall {
+        fd: i32,
+        stalled_us: u64,
+        pending: usize,
+    },
+    /* A registration brought `used` to the soft `limit` set for
+     * `resource` (see ae_set_soft_limits()). */
+    SoftLimit {
+        resource: AeSoftLimitResource,
+        used: usize,
+        limit: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AeFinding {
+    pub severity: AeFindingSeverity,
+    pub kind: AeFindingKind,
+    /* Human readable explanation and advice. */
+    pub message: String,
+}
+
+/* Inspect the loop and return what looks wrong, most severe first. An
+ * empty vector means nothing was found. */
+pub fn ae_doctor(event_loop: &AeEventLoop) -> Vec<AeFinding> {
+    let mut findings = Vec::new();
+    let stats = &event_loop.stats.stats;
+
+    if event_loop.apidata.name() == "select" && event_loop.maxfd > AE_DOCTOR_SELECT_MAX_FD {
+        findings.push(AeFinding {
+            severity: AeFindingSeverity::Critical,
+            kind: AeFindingKind::SelectNearFdLimit {
+                maxfd: event_loop.maxfd,
+            },
+            message: format!(
+                "The select backend is watching fds up to {}, select() cannot go past {}: \
+                 use a kqueue or epoll backend",
+                event_loop.maxfd,
+                libc::FD_SETSIZE
+            ),
+        });
+    }
+
+    let lag = &stats.timer_lag_us;
+    if lag.count() >= MIN_SAMPLES && lag.p99() > AE_DOCTOR_TIMER_LAG_US {
+        findings.push(AeFinding {
+            severity: AeFindingSeverity::Warning,
+            kind: AeFindingKind::LateTimers {
+                count: lag.count(),
+                p99_lag_us: lag.p99(),
+            },
+            message: format!(
+                "Timers fire {}us after their deadline (p99): the loop is kept busy by \
+                 callbacks or starved of CPU",
+                lag.p99()
+            ),
+        });
+    }
+
+    let callbacks = &stats.callback_us;
+    if callbacks.count() >= MIN_SAMPLES && callbacks.p99() > AE_DOCTOR_CALLBACK_US {
+        findings.push(AeFinding {
+            severity: AeFindingSeverity::Warning,
+            kind: AeFindingKind::SlowCallbacks {
+                p99_us: callbacks.p99(),
+                max_us: callbacks.max(),
+            },
+            message: format!(
+                "Callbacks take {}us (p99, max {}us): move slow work out of the loop thread \
+                 or split it across iterations",
+                callbacks.p99(),
+                callbacks.max()
+            ),
+        });
+    }
+
+    if stats.fired_saturated > 0 {
+        findings.push(AeFinding {
+            severity: AeFindingSeverity::Warning,
+            kind: AeFindingKind::FiredBufferSaturated {
+                times: stats.fired_saturated,
+            },
+            message: format!(
+                "The fired events buffer was full {} times: ready fds had to wait for the \
+                 next iteration",
+                stats.fired_saturated
+            ),
+        });
+    }
+
+    let now = event_loop.now_us();
+    for record in ae_dispatch_stack(event_loop) {
+        let running_us = now.saturating_sub(record.started_us);
+        if running_us <= AE_DOCTOR_CALLBACK_US {
+            continue;
+        }
+        let what = match record.source {
+            AeDispatchSource::File { fd, .. } => format!("The handler of fd {fd}"),
+            AeDispatchSource::Timer { id } => format!("Timer {id}"),
+            AeDispatchSource::TimerBatch { batch, timers } => {
+                format!("Timer batch {} ({timers} timers)", batch.0)
+            }
+            AeDispatchSource::Custom { id } => format!("Custom event {}", id.0),
+        };
+        let label = record.label.map(|l| format!(" ({l})")).unwrap_or_default();
+        findings.push(AeFinding {
+            severity: AeFindingSeverity::Warning,
+            kind: AeFindingKind::LongDispatch { record, running_us },
+            message: format!(
+                "{what}{label} has been running for {running_us}us, every other event \
+                 is waiting for it"
+            ),
+        });
+    }
+
+    if let Some(&divergence) = differential::ae_backend_divergences(event_loop).last() {
+        let total = stats.backend_divergences;
+        findings.push(differential::finding(event_loop, divergence, total));
+    }
+    findings.extend(watchdog::findings(event_loop));
+    findings.extend(limits::findings(event_loop));
+
+    for finding in &mut findings {
+        name_finding(event_loop, finding);
+    }
+    findings.sort_by_key(|f| std::cmp::Reverse(f.severity));
+    findings
+}
+
+fn name_finding(event_loop: &AeEventLoop, finding: &mut AeFinding) {
+    if let Some(name) = &event_loop.name {
+        finding.message = format!("Loop {name}: {}", finding.message);
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Diagnostics {
+    proc: Option<DiagnosticProc>,
+    starvation_margin_us: u64,
+    /* Alarm after this many misses in a row, 0 when disabled. */
+    starvation_misses: u32,
+    /* Current run of misses, the worst lag in it, and the deadline of
+     * its first miss. */
+    misses: u32,
+    worst_lag_us: u64,
+    first_missed_us: u64,
+}
+
+/* Set (or clear with None) the proc receiving the findings the loop
+ * raises by itself. */
+pub fn ae_set_diagnostic_proc(event_loop: &mut AeEventLoop, proc: Option<DiagnosticProc>) {
+    event_loop.diagnostics.proc = proc;
+}
+
+/* Raise an AeFindingKind::TimerStarvation finding when the earliest due
+ * timer fires more than `margin_us` after its deadline `misses`
+ * iterations in a row. The count starts over after each alarm and after
+ * a timer fires in time. `misses` 0 disables the alarm. */
+pub fn ae_set_timer_starvation_alarm(event_loop: &mut AeEventLoop, margin_us: u64, misses: u32) {
+    ae_set_timer_starvation_alarm_duration(event_loop, Duration::from_micros(margin_us), misses);
+}
+
+/* ae_set_timer_starvation_alarm() taking the margin as a Duration. */
+pub fn ae_set_timer_starvation_alarm_duration(
+    event_loop: &mut AeEventLoop,
+    margin: Duration,
+    misses: u32,
+) {
+    let diagnostics = &mut event_loop.diagnostics;
+    diagnostics.starvation_margin_us = duration_us(margin);
+    diagnostics.starvation_misses = misses;
+    diagnostics.misses = 0;
+    diagnostics.worst_lag_us = 0;
+}
+
+/* Called once per iteration that fired timers, with the deadline of the
+ * earliest of them and the time they fired at. */
+pub(crate) fn record_earliest_timer(event_loop: &mut AeEventLoop, deadline_us: u64, fired_us: u64) {
+    let lag_us = fired_us.saturating_sub(deadline_us);
+    let diagnostics = &mut event_loop.diagnostics;
+    if diagnostics.starvation_misses == 0 {
+        return;
+    }
+    if lag_us <= diagnostics.starvation_margin_us {
+        diagnostics.misses = 0;
+        diagnostics.worst_lag_us = 0;
+        return;
+    }
+    if diagnostics.misses == 0 {
+        diagnostics.first_missed_us = deadline_us;
+    }
+    diagnostics.misses += 1;
+    diagnostics.worst_lag_us = diagnostics.worst_lag_us.max(lag_us);
+    if diagnostics.misses < diagnostics.starvation_misses {
+        return;
+    }
+
+    let (misses, worst_lag_us, margin_us) = (
+        diagnostics.misses,
+        diagnostics.worst_lag_us,
+        diagnostics.starvation_margin_us,
+    );
+    let first_missed_us = diagnostics.first_missed_us;
+    diagnostics.misses = 0;
+    diagnostics.worst_lag_us = 0;
+
+    let offender = ae_slowlog_get(event_loop, AE_SLOWLOG_MAX_LEN)
+        .into_iter()
+        .filter(|entry| entry.started_us + entry.took_us >= first_missed_us)
+        .max_by_key(|entry| entry.took_us);
+    let culprit = match &offender {
+        Some(entry) => format!(
+            "job {} (id {}) ran for {}us in a {}us slice",
+            entry.name, entry.job_id, entry.took_us, entry.budget_us
+        ),
+        None => "no job slice overran, look for slow callbacks with ae_doctor()".to_string(),
+    };
+    let finding = AeFinding {
+        severity: AeFindingSeverity::Critical,
+        kind: AeFindingKind::TimerStarvation {
+            misses,
+            worst_lag_us,
+            offender,
+        },
+        message: format!(
+            "The earliest timer fired more than {margin_us}us late {misses} iterations in a \
+             row (up to {worst_lag_us}us): {culprit}"
+        ),
+    };
+    raise(event_loop, finding);
+}
+
+/* The finding raised for a registration of stdio `fd` under
+ * AeStdioPolicy::Warn. */
+pub(crate) fn stdio_finding(fd: i32, mask: i32) -> AeFinding {
+    let name = ["stdin", "stdout", "stderr"][fd as usize];
+    AeFinding {
+        severity: AeFindingSeverity::Warning,
+        kind: AeFindingKind::StdioRegistration { fd, mask },
+        message: format!(
+            "fd {fd} ({name}) was registered: if it was closed at startup, the number now \
+             belongs to another file"
+        ),
+    }
+}
+
+/* Hand a finding the loop found by itself to the diagnostic proc. */
+pub(crate) fn raise(event_loop: &mut AeEventLoop, mut finding: AeFinding) {
+    name_finding(event_loop, &mut finding);
+    if let Some(proc) = event_loop.diagnostics.proc {
+        proc(event_loop, &finding);
+    }
+}
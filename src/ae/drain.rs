@@ -0,0 +1,133 @@
+/* Connection draining.
+ *
+ * A rolling deploy takes servers out of a load balancer one at a time:
+ * the server stops accepting, so that health checks fail and new clients
+ * go elsewhere, and exits once the clients it has are done. ae_drain()
+ * is the loop side of it: it closes the listeners, then calls a DrainProc
+ * once the last buffered connection (see ae_conn_create()) is closed, or
+ * when the timeout expires with connections still open.
+ *
+ * Connections of the admin endpoint are not waited for.
+ */
+
+use crate::ae::backoff::ae_backoff_cancel;
+use crate::ae::{
+    AeEventLoop, ae_delete_file_event, ae_delete_time_event, create_time_event_us, duration_us,
+    ms_duration,
+};
+use crate::anet::set_errno;
+use crate::constants::{AE_ALL_MASK, AE_ERR, AE_NOMORE, AE_OK};
+use crate::traits::DrainProc;
+use std::ffi::c_void;
+use std::time::Duration;
+
+pub(crate) struct DrainState {
+    timer_id: i64,
+    proc: DrainProc,
+    client_data: *mut c_void,
+}
+
+/* Stop accepting on `listeners`, which are closed, and call `proc` once
+ * no buffered connection is left open, or after `timeout_ms` with the
+ * number still open (a timeout <= 0 waits as long as it takes). The proc
+ * runs from a later iteration even if nothing was open.
+ *
+ * Returns AE_ERR with errno EBUSY, closing nothing, if the loop is
+ * already draining. */
+pub fn ae_drain(
+    event_loop: &mut AeEventLoop,
+    listeners: &[i32],
+    timeout_ms: i64,
+    proc: DrainProc,
+    client_data: *mut c_void,
+) -> i32 {
+    ae_drain_duration(
+        event_loop,
+        listeners,
+        ms_duration(timeout_ms),
+        proc,
+        client_data,
+    )
+}
+
+/* ae_drain() taking the timeout as a Duration, Duration::ZERO to wait as
+ * long as it takes. */
+pub fn ae_drain_duration(
+    event_loop: &mut AeEventLoop,
+    listeners: &[i32],
+    timeout: Duration,
+    proc: DrainProc,
+    client_data: *mut c_void,
+) -> i32 {
+    if event_loop.drain.is_some() {
+        set_errno(libc::EBUSY);
+        return AE_ERR;
+    }
+    for &fd in listeners {
+        ae_backoff_cancel(event_loop, fd);
+        ae_delete_file_event(event_loop, fd, AE_ALL_MASK);
+        unsafe { libc::close(fd) };
+    }
+
+    let delay = if open_conns(event_loop) == 0 {
+        Some(0)
+    } else {
+        Some(duration_us(timeout)).filter(|&us| us > 0)
+    };
+    let timer_id = match delay {
+        Some(us) => create_time_event_us(
+            event_loop,
+            us,
+            drain_timer_handler,
+            std::ptr::null_mut(),
+            None,
+        ),
+        None => -1,
+    };
+    event_loop.drain = Some(DrainState {
+        timer_id,
+        proc,
+        client_data,
+    });
+    AE_OK
+}
+
+/* True between ae_drain() and the call of its proc. */
+pub fn ae_draining(event_loop: &AeEventLoop) -> bool {
+    event_loop.drain.is_some()
+}
+
+/* Buffered connections being waited for. */
+fn open_conns(event_loop: &AeEventLoop) -> usize {
+    let admin = event_loop
+        .admin
+        .as_ref()
+        .map_or(0, |admin| admin.clients.len());
+    event_loop.conns.len() - admin
+}
+
+/* A buffered connection went away: report if it was the last one. */
+pub(crate) fn check(event_loop: &mut AeEventLoop) {
+    if event_loop.drain.is_some() && open_conns(event_loop) == 0 {
+        finish(event_loop);
+    }
+}
+
+fn drain_timer_handler(event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    if let Some(drain) = event_loop.drain.as_mut() {
+        drain.timer_id = -1;
+    }
+    finish(event_loop);
+    AE_NOMORE
+}
+
+fn finish(event_loop: &mut AeEventLoop) {
+    let Some(drain) = event_loop.drain.take() else {
+        return;
+    };
+    if drain.timer_id != -1 {
+        ae_delete_time_event(event_loop, drain.timer_id);
+    }
+    let open = open_conns(event_loop);
+    (drain.proc)(event_loop, open, drain.client_data);
+}
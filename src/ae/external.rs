@@ -0,0 +1,230 @@
+/* External event sources.
+ *
+ * Libraries such as c-ares, libdbus or libvirt do their own I/O and only
+ * tell their host which fds to watch and how long it may sleep, then
+ * expect a call back once something happened. An ExternalSource wraps
+ * such a library, and ae_add_external_source() drives it from the loop:
+ *
+ *   - before every poll the source is asked for its fds and timeout, fds
+ *     are registered or dropped to match and the poll sleeps no longer
+ *     than the timeout;
+ *   - after the file events of the iteration, dispatch() is called once
+ *     with every fd of the source that fired, or with none if only the
+ *     timeout passed.
+ *
+ * The fds belong to the source: they must not be registered with the
+ * loop by anything else.
+ */
+
+use crate::ae::{AeEventLoop, ae_create_file_event, ae_delete_file_event};
+use crate::constants::{AE_ERR, AE_OK, AE_READABLE, AE_WRITABLE};
+use crate::traits::ExternalSource;
+use std::ffi::c_void;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AeExternalSourceId(pub(crate) u64);
+
+struct Entry {
+    id: u64,
+    /* None while its dispatch() runs. */
+    source: Option<Box<dyn ExternalSource>>,
+    /* Fds registered for the source, and their mask. */
+    watched: Vec<(i32, i32)>,
+    /* Fds that fired since the last dispatch(), and how. */
+    ready: Vec<(i32, i32)>,
+    /* When dispatch() is due even with nothing ready. */
+    deadline: Option<u64>,
+    /* Removed from its own dispatch(). */
+    removed: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct ExternalSources {
+    entries: Vec<Entry>,
+    next_id: u64,
+    /* Reused for the fds a source asks for. */
+    wanted: Vec<(i32, i32)>,
+}
+
+impl ExternalSources {
+    /* Earliest deadline of the sources, see poll_timeout(). */
+    pub(crate) fn deadline(&self) -> Option<u64> {
+        self.entries.iter().filter_map(|entry| entry.deadline).min()
+    }
+}
+
+/* Drive `source` from the loop until ae_remove_external_source(). Its fds
+ * are registered before the next poll. */
+pub fn ae_add_external_source(
+    event_loop: &mut AeEventLoop,
+    source: Box<dyn ExternalSource>,
+) -> AeExternalSourceId {
+    let sources = &mut event_loop.external;
+    let id = sources.next_id;
+    sources.next_id += 1;
+    sources.entries.push(Entry {
+        id,
+        source: Some(source),
+        watched: Vec::new(),
+        ready: Vec::new(),
+        deadline: None,
+        removed: false,
+    });
+    AeExternalSourceId(id)
+}
+
+/* Stop driving a source and unregister its fds, which are not closed.
+ * From its own dispatch() the source is dropped once it returns. Returns
+ * AE_ERR for an unknown id. */
+pub fn ae_remove_external_source(event_loop: &mut AeEventLoop, id: AeExternalSourceId) -> i32 {
+    let Some(index) = find(event_loop, id.0) else {
+        return AE_ERR;
+    };
+    let entry = &mut event_loop.external.entries[index];
+    if entry.removed {
+        return AE_ERR;
+    }
+    let watched = std::mem::take(&mut entry.watched);
+    if entry.source.is_none() {
+        entry.removed = true;
+    } else {
+        event_loop.external.entries.remove(index);
+    }
+    for (fd, mask) in watched {
+        ae_delete_file_event(event_loop, fd, mask);
+    }
+    AE_OK
+}
+
+/* Number of sources driven by the loop. */
+pub fn ae_external_sources(event_loop: &AeEventLoop) -> usize {
+    let entries = &event_loop.external.entries;
+    entries.iter().filter(|entry| !entry.removed).count()
+}
+
+/* Before the poll: register the fds each source asks for, drop the ones
+ * it no longer wants and note its deadline. */
+pub(crate) fn prepare(event_loop: &mut AeEventLoop) {
+    if event_loop.external.entries.is_empty() {
+        return;
+    }
+    let now = event_loop.now_us();
+    let mut wanted = std::mem::take(&mut event_loop.external.wanted);
+    for index in 0..event_loop.external.entries.len() {
+        let entry = &mut event_loop.external.entries[index];
+        let Some(source) = entry.source.as_mut() else {
+            continue;
+        };
+        wanted.clear();
+        source.fds(&mut wanted);
+        entry.deadline = source
+            .timeout()
+            .map(|timeout| now.saturating_add(timeout.as_micros() as u64));
+        let id = entry.id;
+        let old = std::mem::take(&mut entry.watched);
+
+        let mut watched: Vec<(i32, i32)> = Vec::with_capacity(wanted.len());
+        for &(fd, mask) in &wanted {
+            let mask = mask & (AE_READABLE | AE_WRITABLE);
+            match watched.iter_mut().find(|(watched_fd, _)| *watched_fd == fd) {
+                Some((_, merged)) => *merged |= mask,
+                None if mask != 0 => watched.push((fd, mask)),
+                None => {}
+            }
+        }
+        for &(fd, mask) in &old {
+            let kept = watched
+                .iter()
+                .find(|(new_fd, _)| *new_fd == fd)
+                .map_or(0, |&(_, new_mask)| new_mask);
+            if mask & !kept != 0 {
+                ae_delete_file_event(event_loop, fd, mask & !kept);
+            }
+        }
+        for (fd, mask) in watched.iter_mut() {
+            let had = old
+                .iter()
+                .find(|(old_fd, _)| old_fd == fd)
+                .map_or(0, |&(_, old_mask)| old_mask);
+            let added = *mask & !had;
+            if added != 0
+                && ae_create_file_event(
+                    event_loop,
+                    *fd,
+                    added,
+                    external_fd_handler,
+                    id as usize as *mut c_void,
+                ) == AE_ERR
+            {
+                /* Out of range fd: the source keeps its timeout. */
+                *mask = had;
+            }
+        }
+        watched.retain(|&(_, mask)| mask != 0);
+        event_loop.external.entries[index].watched = watched;
+    }
+    event_loop.external.wanted = wanted;
+}
+
+/* After the file events: dispatch the sources with ready fds or past
+ * their deadline. Returns how many were dispatched. */
+pub(crate) fn dispatch(event_loop: &mut AeEventLoop) -> i32 {
+    if event_loop.external.entries.is_empty() {
+        return 0;
+    }
+    let now = event_loop.now_us();
+    let due: Vec<u64> = event_loop
+        .external
+        .entries
+        .iter()
+        .filter(|entry| {
+            entry.source.is_some()
+                && (!entry.ready.is_empty() || entry.deadline.is_some_and(|at| at <= now))
+        })
+        .map(|entry| entry.id)
+        .collect();
+
+    let mut dispatched = 0;
+    for id in due {
+        /* Removed by a source dispatched before it. */
+        let Some(index) = find(event_loop, id) else {
+            continue;
+        };
+        let entry = &mut event_loop.external.entries[index];
+        let Some(mut source) = entry.source.take() else {
+            continue;
+        };
+        let ready = std::mem::take(&mut entry.ready);
+        entry.deadline = None;
+        source.dispatch(event_loop, &ready);
+        dispatched += 1;
+
+        let Some(index) = find(event_loop, id) else {
+            continue;
+        };
+        let entry = &mut event_loop.external.entries[index];
+        if entry.removed {
+            event_loop.external.entries.remove(index);
+        } else {
+            entry.source = Some(source);
+        }
+    }
+    dispatched
+}
+
+fn find(event_loop: &AeEventLoop, id: u64) -> Option<usize> {
+    let entries = &event_loop.external.entries;
+    entries.iter().position(|entry| entry.id == id)
+}
+
+/* Note what fired, dispatch() runs once all file events are done. */
+fn external_fd_handler(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, mask: i32) {
+    let Some(index) = find(event_loop, client_data as usize as u64) else {
+        return;
+    };
+    let ready = &mut event_loop.external.entries[index].ready;
+    match ready.iter_mut().find(|(ready_fd, _)| *ready_fd == fd) {
+        Some((_, fired)) => *fired |= mask,
+        None => ready.push((fd, mask)),
+    }
+}
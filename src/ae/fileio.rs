@@ -0,0 +1,178 @@
+/* Offloaded reads of regular files.
+ *
+ * Regular files are always "readable" for select/kqueue/epoll, so
+ * readiness tells nothing about whether a read is going to block on the
+ * disk. Reads of regular files are instead handed to a small pool of
+ * worker threads doing pread(), and their completion is delivered back on
+ * the loop thread through the loop handle, like any other event. This is
+ * what log tailing and AOF style workloads need to live on the loop.
+ */
+
+use crate::ae::AeEventLoop;
+use crate::ae::handle::{AeHandle, ae_get_handle};
+use crate::anet::errno;
+use crate::constants::{AE_ERR, AE_OK};
+use crate::traits::FileReadProc;
+use std::ffi::c_void;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/* Worker threads started by the first ae_file_read() unless the loop was
+ * built with AeEventLoopBuilder::io_threads(). */
+#[cfg(not(feature = "small"))]
+pub const AE_IO_THREADS_DEFAULT: usize = 2;
+#[cfg(feature = "small")]
+pub const AE_IO_THREADS_DEFAULT: usize = 1;
+
+/* client_data only travels through the worker to be handed back to the
+ * loop thread, the worker never dereferences it. */
+struct ClientData(*mut c_void);
+unsafe impl Send for ClientData {}
+
+struct ReadJob {
+    fd: i32,
+    offset: u64,
+    len: usize,
+    proc: FileReadProc,
+    client_data: ClientData,
+}
+
+pub(crate) struct FileIoPool {
+    jobs: Option<Sender<ReadJob>>,
+    workers: Vec<JoinHandle<()>>,
+    /* Reads submitted whose completion has not run yet. */
+    pending: usize,
+}
+
+impl FileIoPool {
+    fn start(threads: usize, handle: AeHandle, name: Option<&str>) -> Option<FileIoPool> {
+        let (jobs, queue) = channel::<ReadJob>();
+        let queue = Arc::new(Mutex::new(queue));
+        let mut workers = Vec::with_capacity(threads);
+        for i in 0..threads.max(1) {
+            let queue = queue.clone();
+            let handle = handle.clone();
+            let worker = std::thread::Builder::new()
+                .name(match name {
+                    Some(name) => format!("{name}-io-{i}"),
+                    None => format!("rae-io-{i}"),
+                })
+                .spawn(move || worker_main(queue, handle))
+                .ok()?;
+            workers.push(worker);
+        }
+        Some(FileIoPool {
+            jobs: Some(jobs),
+            workers,
+            pending: 0,
+        })
+    }
+}
+
+impl Drop for FileIoPool {
+    fn drop(&mut self) {
+        /* Closing the channel makes the workers exit once the reads in
+         * progress are done. Their completions are dropped unrun, together
+         * with the loop handle queue. */
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn worker_main(queue: Arc<Mutex<Receiver<ReadJob>>>, handle: AeHandle) {
+    loop {
+        let job = match queue.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        let mut buf = vec![0u8; job.len];
+        let n = loop {
+            let n = unsafe {
+                libc::pread(
+                    job.fd,
+                    buf.as_mut_ptr() as *mut c_void,
+                    buf.len(),
+                    job.offset as libc::off_t,
+                )
+            };
+            if n == -1 && errno() == libc::EINTR {
+                continue;
+            }
+            break n;
+        };
+        let result = if n < 0 {
+            Err(errno())
+        } else {
+            buf.truncate(n as usize);
+            Ok(buf)
+        };
+        handle.post(move |event_loop| complete(event_loop, job, result));
+    }
+}
+
+fn complete(event_loop: &mut AeEventLoop, job: ReadJob, result: Result<Vec<u8>, i32>) {
+    if let Some(pool) = event_loop.fileio.as_mut() {
+        pool.pending -= 1;
+    }
+    let result = result.as_deref().map_err(|&err| err);
+    (job.proc)(event_loop, job.fd, job.offset, result, job.client_data.0);
+}
+
+/* Read up to `len` bytes of `fd` at `offset` without blocking the loop.
+ * The read runs on an I/O thread and `proc` is called on the loop thread
+ * with the data (empty at end of file) or the errno of the failed read.
+ * The fd must stay open until then.
+ *
+ * Returns AE_OK, or AE_ERR if the I/O threads cannot be started. */
+pub fn ae_file_read(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    offset: u64,
+    len: usize,
+    proc: FileReadProc,
+    client_data: *mut c_void,
+) -> i32 {
+    if event_loop.fileio.is_none() {
+        let handle = match ae_get_handle(event_loop) {
+            Some(handle) => handle,
+            None => return AE_ERR,
+        };
+        event_loop.fileio =
+            FileIoPool::start(event_loop.io_threads, handle, event_loop.name.as_deref());
+    }
+    let pool = match event_loop.fileio.as_mut() {
+        Some(pool) => pool,
+        None => return AE_ERR,
+    };
+
+    let job = ReadJob {
+        fd,
+        offset,
+        len,
+        proc,
+        client_data: ClientData(client_data),
+    };
+    match pool.jobs.as_ref().map(|jobs| jobs.send(job)) {
+        Some(Ok(())) => {
+            pool.pending += 1;
+            AE_OK
+        }
+        _ => AE_ERR,
+    }
+}
+
+/* Number of ae_file_read() calls whose proc has not been called yet. */
+pub fn ae_file_reads_pending(event_loop: &AeEventLoop) -> usize {
+    event_loop.fileio.as_ref().map_or(0, |pool| pool.pending)
+}
+
+/* The I/O threads do not exist in a forked child, and joining them would
+ * hang: forget the pool, a new one is started on demand. */
+pub(crate) fn reset_after_fork(event_loop: &mut AeEventLoop) {
+    if let Some(pool) = event_loop.fileio.take() {
+        std::mem::forget(pool);
+    }
+}
@@ -0,0 +1,115 @@
+/* Typed flags for ae_process_events().
+ *
+ * The raw i32 flags accept any combination, and the meaningless ones are
+ * silently ignored: without AE_FILE_EVENTS or AE_TIME_EVENTS the call
+ * returns 0 without doing anything, unknown bits are dropped. ProcessFlags
+ * builds the same values from named constructors, and
+ * AeEventLoop::process() refuses the combinations that cannot do any work.
+ */
+
+use crate::ae::{AeEventLoop, ae_process_events};
+use crate::constants::{
+    AE_CALL_AFTER_SLEEP, AE_CALL_BEFORE_SLEEP, AE_DONT_WAIT, AE_FILE_EVENTS, AE_TIME_EVENTS,
+};
+use std::ops::{BitOr, BitOrAssign};
+
+const KNOWN_BITS: i32 =
+    AE_FILE_EVENTS | AE_TIME_EVENTS | AE_DONT_WAIT | AE_CALL_BEFORE_SLEEP | AE_CALL_AFTER_SLEEP;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ProcessFlags(i32);
+
+impl ProcessFlags {
+    pub const FILE_EVENTS: ProcessFlags = ProcessFlags(AE_FILE_EVENTS);
+    pub const TIME_EVENTS: ProcessFlags = ProcessFlags(AE_TIME_EVENTS);
+    pub const DONT_WAIT: ProcessFlags = ProcessFlags(AE_DONT_WAIT);
+    pub const CALL_BEFORE_SLEEP: ProcessFlags = ProcessFlags(AE_CALL_BEFORE_SLEEP);
+    pub const CALL_AFTER_SLEEP: ProcessFlags = ProcessFlags(AE_CALL_AFTER_SLEEP);
+
+    /* File and time events, blocking until one is ready (AE_ALL_EVENTS). */
+    pub const fn all() -> Self {
+        ProcessFlags(AE_FILE_EVENTS | AE_TIME_EVENTS)
+    }
+
+    pub const fn files_only() -> Self {
+        ProcessFlags(AE_FILE_EVENTS)
+    }
+
+    pub const fn timers_only() -> Self {
+        ProcessFlags(AE_TIME_EVENTS)
+    }
+
+    /* File and time events that are ready right now. */
+    pub const fn nowait() -> Self {
+        ProcessFlags(AE_FILE_EVENTS | AE_TIME_EVENTS | AE_DONT_WAIT)
+    }
+
+    /* What ae_main() passes: all events and both sleep hooks. */
+    pub const fn main_loop() -> Self {
+        ProcessFlags(AE_FILE_EVENTS | AE_TIME_EVENTS | AE_CALL_BEFORE_SLEEP | AE_CALL_AFTER_SLEEP)
+    }
+
+    pub const fn dont_wait(self) -> Self {
+        ProcessFlags(self.0 | AE_DONT_WAIT)
+    }
+
+    pub const fn with_sleep_hooks(self) -> Self {
+        ProcessFlags(self.0 | AE_CALL_BEFORE_SLEEP | AE_CALL_AFTER_SLEEP)
+    }
+
+    /* Flags from raw ae_process_events() flags, None if unknown bits are
+     * set. */
+    pub const fn from_bits(bits: i32) -> Option<Self> {
+        if bits & !KNOWN_BITS != 0 {
+            None
+        } else {
+            Some(ProcessFlags(bits))
+        }
+    }
+
+    pub const fn bits(self) -> i32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: ProcessFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /* True if processing with these flags can dispatch anything, i.e.
+     * file or time events are selected. */
+    pub const fn is_valid(self) -> bool {
+        self.0 & (AE_FILE_EVENTS | AE_TIME_EVENTS) != 0
+    }
+}
+
+impl BitOr for ProcessFlags {
+    type Output = ProcessFlags;
+
+    fn bitor(self, other: ProcessFlags) -> ProcessFlags {
+        ProcessFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for ProcessFlags {
+    fn bitor_assign(&mut self, other: ProcessFlags) {
+        self.0 |= other.0;
+    }
+}
+
+impl From<ProcessFlags> for i32 {
+    fn from(flags: ProcessFlags) -> i32 {
+        flags.0
+    }
+}
+
+impl AeEventLoop {
+    /* ae_process_events() with typed flags. Returns the number of events
+     * processed, or Err(EINVAL) without doing anything if the flags
+     * select neither file nor time events. */
+    pub fn process(&mut self, flags: ProcessFlags) -> Result<i32, i32> {
+        if !flags.is_valid() {
+            return Err(libc::EINVAL);
+        }
+        Ok(ae_process_events(self, flags.bits()))
+    }
+}
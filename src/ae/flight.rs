@@ -0,0 +1,257 @@
+/* Flight recorder.
+ *
+ * The dispatch stack (see context.rs) says what the loop is doing now; a
+ * post-mortem also wants what it did just before. Once enabled with
+ * ae_set_flight_recorder(), the loop keeps the last N dispatches in a
+ * ring allocated up front: what ran, when, for how long and what it
+ * returned. A dispatch is written to the ring when it starts and
+ * completed when it returns, so the one that crashed the process shows
+ * as still running, and one whose callback panicked as panicked.
+ *
+ * ae_flight_dump() writes the ring to an fd without allocating or
+ * locking, for a fatal signal handler that holds a pointer to the loop.
+ * The admin endpoint serves it as FLIGHT.
+ */
+
+use crate::ae::AeEventLoop;
+use crate::ae::context::{self, AeDispatchSource};
+use crate::anet::{errno, set_errno};
+use crate::constants::{AE_ERR, AE_OK, AE_READABLE, AE_WRITABLE};
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeFlightOutcome {
+    /* Not returned yet: in progress, or the process died in it. */
+    Running,
+    /* The callback returned: the timer proc's return value for timers,
+     * AE_OK otherwise. */
+    Returned(i32),
+    /* The callback panicked, see AePanicPolicy. */
+    Panicked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AeFlightEntry {
+    /* Increasing across the life of the loop. */
+    pub seq: u64,
+    pub source: AeDispatchSource,
+    /* See ae_set_dispatch_label(), as of the end of the dispatch. */
+    pub label: Option<&'static str>,
+    /* Loop clock time (see ae_loop_now()) the dispatch started at. */
+    pub started_us: u64,
+    /* 0 while running. */
+    pub took_us: u64,
+    pub outcome: AeFlightOutcome,
+}
+
+#[derive(Default)]
+pub(crate) struct FlightRecorder {
+    /* Indexed by seq modulo the capacity, empty when disabled. */
+    ring: Vec<Option<AeFlightEntry>>,
+    next_seq: u64,
+    /* Seq of each dispatch in progress, outermost first. */
+    open: Vec<u64>,
+}
+
+impl FlightRecorder {
+    fn slot(&mut self, seq: u64) -> Option<&mut AeFlightEntry> {
+        if self.ring.is_empty() {
+            return None;
+        }
+        let index = (seq % self.ring.len() as u64) as usize;
+        /* Overwritten by nested dispatches when the ring wrapped. */
+        self.ring[index].as_mut().filter(|entry| entry.seq == seq)
+    }
+}
+
+/* Keep the last `capacity` dispatches, 0 to stop recording. Changing the
+ * capacity clears the ring. Returns AE_ERR from a callback, where the
+ * dispatches in progress would be lost, and with errno set to ENOTSUP
+ * without the `instrumentation` feature. */
+pub fn ae_set_flight_recorder(event_loop: &mut AeEventLoop, capacity: usize) -> i32 {
+    if !cfg!(feature = "instrumentation") {
+        set_errno(libc::ENOTSUP);
+        return AE_ERR;
+    }
+    if context::depth(event_loop) > 0 {
+        return AE_ERR;
+    }
+    event_loop.flight.ring = vec![None; capacity];
+    AE_OK
+}
+
+/* The recorded dispatches, oldest first. */
+pub fn ae_flight_entries(event_loop: &AeEventLoop) -> Vec<AeFlightEntry> {
+    let mut entries: Vec<AeFlightEntry> =
+        event_loop.flight.ring.iter().flatten().copied().collect();
+    entries.sort_by_key(|entry| entry.seq);
+    entries
+}
+
+/* Write the recorded dispatches to `fd`, oldest first, one line each as
+ * served by the admin FLIGHT command. Safe to call from a signal handler:
+ * nothing is allocated and the only syscall is write(2). Returns AE_ERR if
+ * a write fails. */
+pub fn ae_flight_dump(event_loop: &AeEventLoop, fd: i32) -> i32 {
+    let ring = &event_loop.flight.ring;
+    if ring.is_empty() {
+        return AE_OK;
+    }
+    /* The oldest entry follows the newest one, unless the ring has not
+     * wrapped yet. */
+    let start = (event_loop.flight.next_seq % ring.len() as u64) as usize;
+    for i in 0..ring.len() {
+        let Some(entry) = &ring[(start + i) % ring.len()] else {
+            continue;
+        };
+        let mut line = LineBuf {
+            buf: [0; 256],
+            len: 0,
+        };
+        let _ = write_entry(&mut line, entry);
+        let _ = line.write_char('\n');
+        let mut written = 0;
+        while written < line.len {
+            let n = unsafe {
+                libc::write(
+                    fd,
+                    line.buf[written..].as_ptr() as *const libc::c_void,
+                    line.len - written,
+                )
+            };
+            if n <= 0 {
+                if n == -1 && errno() == libc::EINTR {
+                    continue;
+                }
+                return AE_ERR;
+            }
+            written += n as usize;
+        }
+    }
+    AE_OK
+}
+
+/* "seq source started_us=.. took_us=.. outcome [label]", e.g.
+ * "42 file fd=7 mask=r started_us=1200 took_us=35 returned=0 GET". */
+pub(crate) fn write_entry(out: &mut impl Write, entry: &AeFlightEntry) -> fmt::Result {
+    write!(out, "{} ", entry.seq)?;
+    match entry.source {
+        AeDispatchSource::File { fd, mask } => {
+            let mask = match (mask & AE_READABLE != 0, mask & AE_WRITABLE != 0) {
+                (true, true) => "rw",
+                (true, false) => "r",
+                (false, true) => "w",
+                (false, false) => "-",
+            };
+            write!(out, "file fd={} mask={}", fd, mask)?
+        }
+        AeDispatchSource::Timer { id } => write!(out, "timer id={}", id)?,
+        AeDispatchSource::TimerBatch { batch, timers } => {
+            write!(out, "batch id={} timers={}", batch.0, timers)?
+        }
+        AeDispatchSource::Custom { id } => write!(out, "custom id={}", id.0)?,
+    }
+    write!(
+        out,
+        " started_us={} took_us={} ",
+        entry.started_us, entry.took_us
+    )?;
+    match entry.outcome {
+        AeFlightOutcome::Running => out.write_str("running")?,
+        AeFlightOutcome::Returned(result) => write!(out, "returned={}", result)?,
+        AeFlightOutcome::Panicked => out.write_str("panicked")?,
+    }
+    if let Some(label) = entry.label {
+        write!(out, " {}", label)?;
+    }
+    Ok(())
+}
+
+/* A line on the stack, truncated when full. */
+struct LineBuf {
+    buf: [u8; 256],
+    len: usize,
+}
+
+impl Write for LineBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/* A dispatch starts, called by context::push(). */
+pub(crate) fn begin(event_loop: &mut AeEventLoop, source: AeDispatchSource, started_us: u64) {
+    let recorder = &mut event_loop.flight;
+    if !cfg!(feature = "instrumentation") || recorder.ring.is_empty() {
+        return;
+    }
+    let seq = recorder.next_seq;
+    recorder.next_seq += 1;
+    let index = (seq % recorder.ring.len() as u64) as usize;
+    recorder.ring[index] = Some(AeFlightEntry {
+        seq,
+        source,
+        label: None,
+        started_us,
+        took_us: 0,
+        outcome: AeFlightOutcome::Running,
+    });
+    recorder.open.push(seq);
+}
+
+/* The innermost dispatch returned `result`, called by context::pop(). */
+pub(crate) fn end(event_loop: &mut AeEventLoop, label: Option<&'static str>, result: i32) {
+    if !cfg!(feature = "instrumentation") || event_loop.flight.open.is_empty() {
+        return;
+    }
+    let now = event_loop.now_us();
+    let recorder = &mut event_loop.flight;
+    let Some(seq) = recorder.open.pop() else {
+        return;
+    };
+    if let Some(entry) = recorder.slot(seq) {
+        entry.label = label;
+        entry.took_us = now.saturating_sub(entry.started_us);
+        if entry.outcome == AeFlightOutcome::Running {
+            entry.outcome = AeFlightOutcome::Returned(result);
+        }
+    }
+}
+
+/* The innermost dispatch panicked, called by panic::guard() once the
+ * dispatches the panic unwound through are truncated. */
+pub(crate) fn panicked(event_loop: &mut AeEventLoop, label: Option<&'static str>) {
+    if !cfg!(feature = "instrumentation") || event_loop.flight.open.is_empty() {
+        return;
+    }
+    let now = event_loop.now_us();
+    let recorder = &mut event_loop.flight;
+    let Some(&seq) = recorder.open.last() else {
+        return;
+    };
+    if let Some(entry) = recorder.slot(seq) {
+        entry.label = label;
+        entry.took_us = now.saturating_sub(entry.started_us);
+        entry.outcome = AeFlightOutcome::Panicked;
+    }
+}
+
+/* Drop the dispatches above `depth`, unwound by a panic: they are marked
+ * panicked too. */
+pub(crate) fn truncate(event_loop: &mut AeEventLoop, depth: usize) {
+    if !cfg!(feature = "instrumentation") || event_loop.flight.open.len() <= depth {
+        return;
+    }
+    let now = event_loop.now_us();
+    let recorder = &mut event_loop.flight;
+    while recorder.open.len() > depth {
+        let seq = recorder.open.pop().unwrap();
+        if let Some(entry) = recorder.slot(seq) {
+            entry.took_us = now.saturating_sub(entry.started_us);
+            entry.outcome = AeFlightOutcome::Panicked;
+        }
+    }
+}
@@ -0,0 +1,142 @@
+/* Fork workers.
+ *
+ * The pattern Redis uses for BGSAVE: fork, and let the child work on the
+ * copy-on-write image of the parent's memory while the parent keeps
+ * serving. ae_spawn_child_loop() does the loop side of it. In the child
+ * the backend is recreated (see ae_reinit_after_fork()), every file event
+ * and timer inherited from the parent is dropped without calling its
+ * finalizer, so nothing of the parent runs there, and `setup` is called
+ * on the emptied loop. The child then runs ae_main() on it, unless setup
+ * called ae_stop(), and exits with status 0.
+ *
+ * The only channel kept is a status pipe: the child writes to the fd
+ * returned by ae_child_status_fd(), the parent reads AeForkedChild
+ * status_fd, typically registered on its own loop. It reaches EOF once
+ * the child is gone.
+ *
+ * Only the calling thread exists in the child: I/O threads, and locks
+ * other threads held at fork time, are not usable there. Fds the parent
+ * had open stay open, unregistered.
+ */
+
+use crate::ae::{AeEventLoop, ae_delete_file_event, ae_main, ae_reinit_after_fork, sync};
+use crate::anet::{anet_block, anet_pipe};
+use crate::constants::{AE_ALL_MASK, AE_ERR, AE_OK};
+use crate::traits::ChildLoopSetupProc;
+
+/* A child started by ae_spawn_child_loop(), as seen by the parent. */
+#[derive(Debug)]
+pub struct AeForkedChild {
+    pub pid: i32,
+    /* Read end of the status pipe, owned by the parent. Non-blocking. */
+    pub status_fd: i32,
+}
+
+/* Fork a child running `setup` on a copy of `event_loop` stripped of its
+ * registrations. Returns the child in the parent, None if the pipe or the
+ * fork failed (errno is set). Never returns in the child. */
+pub fn ae_spawn_child_loop(
+    event_loop: &mut AeEventLoop,
+    setup: ChildLoopSetupProc,
+) -> Option<AeForkedChild> {
+    let (rfd, wfd) = anet_pipe(true).ok()?;
+    /* The child must not lose status writes to EAGAIN. */
+    if anet_block(wfd) == AE_ERR {
+        close_pair(rfd, wfd);
+        return None;
+    }
+
+    let pid = unsafe { libc::fork() };
+    if pid == -1 {
+        close_pair(rfd, wfd);
+        return None;
+    }
+    if pid != 0 {
+        unsafe { libc::close(wfd) };
+        return Some(AeForkedChild {
+            pid,
+            status_fd: rfd,
+        });
+    }
+
+    unsafe { libc::close(rfd) };
+    let status = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        if strip_registrations(event_loop) == AE_ERR {
+            return 1;
+        }
+        event_loop.child_status_fd = wfd;
+        setup(event_loop);
+        if !event_loop.stop {
+            ae_main(event_loop);
+        }
+        0
+    }))
+    .unwrap_or(1);
+    /* Exit without dropping anything: the parent state copied in the
+     * child must not be torn down (admin socket files, ...). */
+    unsafe { libc::_exit(status) }
+}
+
+/* Write end of the status pipe in a child started by
+ * ae_spawn_child_loop(), -1 in any other loop. */
+pub fn ae_child_status_fd(event_loop: &AeEventLoop) -> i32 {
+    event_loop.child_status_fd
+}
+
+/* Close the status pipe of `child` and wait for it to exit. Unregister
+ * status_fd from the parent loop first. Returns the exit status of the
+ * child, 128 + the signal number if it was killed, -1 on error. */
+pub fn ae_wait_child_loop(child: AeForkedChild) -> i32 {
+    unsafe { libc::close(child.status_fd) };
+    let mut status = 0;
+    loop {
+        if unsafe { libc::waitpid(child.pid, &mut status, 0) } != -1 {
+            break;
+        }
+        if std::io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            return -1;
+        }
+    }
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        -1
+    }
+}
+
+/* Drop, in the child, everything inherited from the parent loop. */
+fn strip_registrations(event_loop: &mut AeEventLoop) -> i32 {
+    /* Recreates the backend first: deleting from the one shared with the
+     * parent would delete in the parent too. */
+    if ae_reinit_after_fork(event_loop) == AE_ERR {
+        return AE_ERR;
+    }
+    event_loop.paused_at = None;
+    for fd in 0..=event_loop.maxfd {
+        ae_delete_file_event(event_loop, fd, AE_ALL_MASK);
+    }
+
+    /* Timers go without their finalizers, which would release state the
+     * parent still owns. */
+    let mut list = event_loop.time_event_head.take();
+    while let Some(mut node) = list {
+        list = node.next.take();
+        node.event.mark_deleted();
+    }
+    event_loop.live_time_events = 0;
+    event_loop.sync_ready = sync::ReadyQueue::default();
+
+    event_loop.beforesleep = None;
+    event_loop.aftersleep = None;
+    event_loop.stop = false;
+    AE_OK
+}
+
+fn close_pair(rfd: i32, wfd: i32) {
+    unsafe {
+        libc::close(rfd);
+        libc::close(wfd);
+    }
+}
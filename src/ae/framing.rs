@@ -0,0 +1,203 @@
+/* Line and length-prefixed framing.
+ *
+ * Most ad hoc protocols are one of two shapes: newline-delimited text
+ * (memcached, SMTP, JSON lines) or binary frames carrying their length in
+ * a header. frame_decode() and frame_encode() implement both over a byte
+ * buffer, and ae_framed_create() runs them on a buffered connection
+ * (ae::conn) so that a FrameProc sees one call per complete frame however
+ * the bytes were split across reads.
+ *
+ * A frame longer than the configured maximum closes the connection with
+ * EMSGSIZE rather than buffering without bound.
+ */
+
+use crate::ae::AeEventLoop;
+use crate::ae::conn::{
+    ae_conn_client_data, ae_conn_close_with_error, ae_conn_closing, ae_conn_create,
+    ae_conn_write_owned,
+};
+use crate::constants::{AE_ERR, AE_OK};
+use crate::traits::{ConnCloseProc, FrameProc};
+use std::ffi::c_void;
+use std::ops::Range;
+
+/* Size of the LengthPrefixed header. */
+pub const AE_FRAME_HEADER_LEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeFraming {
+    /* Frames end with "\n", delivered without it (and without a "\r"
+     * before it). `max_len` bounds a line, terminator excluded. */
+    Lines { max_len: usize },
+    /* Frames start with their length as a big-endian u32, the header
+     * excluded. `max_len` bounds that length. */
+    LengthPrefixed { max_len: usize },
+}
+
+impl AeFraming {
+    fn max_len(self) -> usize {
+        match self {
+            AeFraming::Lines { max_len } | AeFraming::LengthPrefixed { max_len } => max_len,
+        }
+    }
+}
+
+/* The first frame in `buf`: the range of its payload and the bytes it
+ * takes including framing. Ok(None) if `buf` holds only part of it,
+ * Err(EMSGSIZE) if it is over the limit. */
+pub fn frame_decode(framing: AeFraming, buf: &[u8]) -> Result<Option<(Range<usize>, usize)>, i32> {
+    match framing {
+        AeFraming::Lines { max_len } => {
+            /* Only the allowed length (plus "\r\n") is searched, so that a
+             * peer that never sends a newline is caught early. */
+            let window = &buf[..buf.len().min(max_len.saturating_add(2))];
+            match window.iter().position(|&c| c == b'\n') {
+                Some(newline) => {
+                    let end = if newline > 0 && buf[newline - 1] == b'\r' {
+                        newline - 1
+                    } else {
+                        newline
+                    };
+                    if end > max_len {
+                        return Err(libc::EMSGSIZE);
+                    }
+                    Ok(Some((0..end, newline + 1)))
+                }
+                None if buf.len() > max_len.saturating_add(1) => Err(libc::EMSGSIZE),
+                None => Ok(None),
+            }
+        }
+        AeFraming::LengthPrefixed { max_len } => {
+            if buf.len() < AE_FRAME_HEADER_LEN {
+                return Ok(None);
+            }
+            let mut header = [0u8; AE_FRAME_HEADER_LEN];
+            header.copy_from_slice(&buf[..AE_FRAME_HEADER_LEN]);
+            let len = u32::from_be_bytes(header) as usize;
+            if len > max_len {
+                return Err(libc::EMSGSIZE);
+            }
+            let end = AE_FRAME_HEADER_LEN + len;
+            if buf.len() < end {
+                return Ok(None);
+            }
+            Ok(Some((AE_FRAME_HEADER_LEN..end, end)))
+        }
+    }
+}
+
+/* Append `frame` to `out` with its framing. Returns AE_ERR, leaving `out`
+ * alone, if the frame is over the limit or, for Lines, contains a
+ * newline. */
+pub fn frame_encode(framing: AeFraming, frame: &[u8], out: &mut Vec<u8>) -> i32 {
+    if frame.len() > framing.max_len() {
+        return AE_ERR;
+    }
+    match framing {
+        AeFraming::Lines { .. } => {
+            if frame.contains(&b'\n') {
+                return AE_ERR;
+            }
+            out.extend_from_slice(frame);
+            out.push(b'\n');
+        }
+        AeFraming::LengthPrefixed { .. } => {
+            let len = match u32::try_from(frame.len()) {
+                Ok(len) => len,
+                Err(_) => return AE_ERR,
+            };
+            out.extend_from_slice(&len.to_be_bytes());
+            out.extend_from_slice(frame);
+        }
+    }
+    AE_OK
+}
+
+/* Per connection state of a framed connection, the client data of the
+ * underlying buffered connection. */
+struct FramedConn {
+    framing: AeFraming,
+    proc: FrameProc,
+    close_proc: Option<ConnCloseProc>,
+    client_data: *mut c_void,
+}
+
+/* Read frames from the connected socket `fd` and pass each one to `proc`.
+ * Takes ownership of `fd` like ae_conn_create(), and the connection is
+ * closed the same way, with ae_conn_close(). */
+pub fn ae_framed_create(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    framing: AeFraming,
+    proc: FrameProc,
+    close_proc: Option<ConnCloseProc>,
+    client_data: *mut c_void,
+) -> i32 {
+    let conn = Box::into_raw(Box::new(FramedConn {
+        framing,
+        proc,
+        close_proc,
+        client_data,
+    }));
+    if ae_conn_create(
+        event_loop,
+        fd,
+        framed_read,
+        Some(framed_closed),
+        conn as *mut c_void,
+    ) == AE_ERR
+    {
+        drop(unsafe { Box::from_raw(conn) });
+        return AE_ERR;
+    }
+    AE_OK
+}
+
+/* Send `frame` on the framed connection `fd`. Returns AE_ERR if `fd` is
+ * not an open connection or frame_encode() refuses the frame. */
+pub fn ae_framed_write(event_loop: &mut AeEventLoop, fd: i32, frame: &[u8]) -> i32 {
+    let conn = ae_conn_client_data(event_loop, fd) as *mut FramedConn;
+    if conn.is_null() {
+        return AE_ERR;
+    }
+    let mut out = Vec::with_capacity(frame.len() + AE_FRAME_HEADER_LEN);
+    if frame_encode(unsafe { (*conn).framing }, frame, &mut out) == AE_ERR {
+        return AE_ERR;
+    }
+    ae_conn_write_owned(event_loop, fd, out)
+}
+
+fn framed_closed(event_loop: &mut AeEventLoop, fd: i32, err: i32, client_data: *mut c_void) {
+    let conn = unsafe { Box::from_raw(client_data as *mut FramedConn) };
+    if let Some(close_proc) = conn.close_proc {
+        close_proc(event_loop, fd, err, conn.client_data);
+    }
+}
+
+fn framed_read(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    input: &[u8],
+    client_data: *mut c_void,
+) -> usize {
+    let conn = client_data as *mut FramedConn;
+    let (framing, proc, user_data) =
+        unsafe { ((*conn).framing, (*conn).proc, (*conn).client_data) };
+    let mut consumed = 0;
+    /* A frame proc may close the connection: what follows is dropped. */
+    while !ae_conn_closing(event_loop, fd) {
+        match frame_decode(framing, &input[consumed..]) {
+            Ok(Some((payload, len))) => {
+                let frame = &input[consumed..][payload];
+                proc(event_loop, fd, frame, user_data);
+                consumed += len;
+            }
+            Ok(None) => break,
+            Err(err) => {
+                ae_conn_close_with_error(event_loop, fd, err);
+                return input.len();
+            }
+        }
+    }
+    consumed
+}
@@ -0,0 +1,212 @@
+/* Hosting a GLib main context.
+ *
+ * A desktop tool built on rae that also uses a GLib based library (GIO,
+ * D-Bus through GDBus, libsoup) needs the sources of a GMainContext to
+ * run. AeGlibContext is an ExternalSource that iterates the context from
+ * the loop, the way g_main_context_iteration() would:
+ *
+ *   - before the poll: g_main_context_prepare() and g_main_context_query()
+ *     give the GPollFDs to watch and the timeout;
+ *   - once something fired or the timeout passed (or before the next
+ *     prepare otherwise): g_main_context_check() with the revents, then
+ *     g_main_context_dispatch() if a source is ready.
+ *
+ * The loop only watches readability and writability: a GPollFD asking
+ * for G_IO_HUP or G_IO_ERR alone is watched for reading and gets them
+ * back when the fd becomes readable.
+ *
+ * Built with the `glib` feature, which links libglib-2.0.
+ */
+
+use crate::ae::AeEventLoop;
+use crate::ae::external::{AeExternalSourceId, ae_add_external_source};
+use crate::constants::{AE_READABLE, AE_WRITABLE};
+use crate::traits::ExternalSource;
+use std::ffi::c_void;
+use std::time::Duration;
+
+const G_IO_IN: u16 = 1;
+const G_IO_OUT: u16 = 4;
+const G_IO_PRI: u16 = 2;
+const G_IO_ERR: u16 = 8;
+const G_IO_HUP: u16 = 16;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GPollFD {
+    fd: i32,
+    events: u16,
+    revents: u16,
+}
+
+#[link(name = "glib-2.0")]
+unsafe extern "C" {
+    fn g_main_context_default() -> *mut c_void;
+    fn g_main_context_ref(context: *mut c_void) -> *mut c_void;
+    fn g_main_context_unref(context: *mut c_void);
+    fn g_main_context_acquire(context: *mut c_void) -> i32;
+    fn g_main_context_release(context: *mut c_void);
+    fn g_main_context_prepare(context: *mut c_void, priority: *mut i32) -> i32;
+    fn g_main_context_query(
+        context: *mut c_void,
+        max_priority: i32,
+        timeout: *mut i32,
+        fds: *mut GPollFD,
+        n_fds: i32,
+    ) -> i32;
+    fn g_main_context_check(
+        context: *mut c_void,
+        max_priority: i32,
+        fds: *mut GPollFD,
+        n_fds: i32,
+    ) -> i32;
+    fn g_main_context_dispatch(context: *mut c_void);
+}
+
+/* A GMainContext iterated by the loop, see the top of the file. */
+pub struct AeGlibContext {
+    context: *mut c_void,
+    fds: Vec<GPollFD>,
+    priority: i32,
+    /* From the last query, negative for none. */
+    timeout_ms: i32,
+    /* Prepared and queried, not checked yet. */
+    in_cycle: bool,
+}
+
+impl AeGlibContext {
+    /* Take ownership of `context` (a GMainContext, null for the global
+     * default one) for the calling thread. None if another thread owns
+     * it. */
+    pub fn new(context: *mut c_void) -> Option<Self> {
+        let context = unsafe {
+            g_main_context_ref(if context.is_null() {
+                g_main_context_default()
+            } else {
+                context
+            })
+        };
+        if unsafe { g_main_context_acquire(context) } == 0 {
+            unsafe { g_main_context_unref(context) };
+            return None;
+        }
+        Some(AeGlibContext {
+            context,
+            fds: Vec::new(),
+            priority: 0,
+            timeout_ms: -1,
+            in_cycle: false,
+        })
+    }
+
+    /* Check the fds of the current cycle with their revents, and dispatch
+     * the sources that are ready. */
+    fn finish_cycle(&mut self) {
+        if self.check() {
+            unsafe { g_main_context_dispatch(self.context) };
+        }
+    }
+
+    fn check(&mut self) -> bool {
+        if !self.in_cycle {
+            return false;
+        }
+        self.in_cycle = false;
+        let ready = unsafe {
+            g_main_context_check(
+                self.context,
+                self.priority,
+                self.fds.as_mut_ptr(),
+                self.fds.len() as i32,
+            )
+        };
+        ready != 0
+    }
+}
+
+impl ExternalSource for AeGlibContext {
+    fn fds(&mut self, fds: &mut Vec<(i32, i32)>) {
+        /* Nothing fired and the timeout did not pass: still checked, as
+         * after a poll that timed out. */
+        self.finish_cycle();
+        unsafe { g_main_context_prepare(self.context, &mut self.priority) };
+        loop {
+            let needed = unsafe {
+                g_main_context_query(
+                    self.context,
+                    self.priority,
+                    &mut self.timeout_ms,
+                    self.fds.as_mut_ptr(),
+                    self.fds.capacity() as i32,
+                )
+            } as usize;
+            if needed <= self.fds.capacity() {
+                unsafe { self.fds.set_len(needed) };
+                break;
+            }
+            self.fds.clear();
+            self.fds.reserve(needed);
+        }
+        self.in_cycle = true;
+
+        for pollfd in self.fds.iter_mut() {
+            pollfd.revents = 0;
+            let mut mask = 0;
+            if pollfd.events & (G_IO_IN | G_IO_PRI) != 0 {
+                mask |= AE_READABLE;
+            }
+            if pollfd.events & G_IO_OUT != 0 {
+                mask |= AE_WRITABLE;
+            }
+            if mask == 0 && pollfd.events & (G_IO_HUP | G_IO_ERR) != 0 {
+                mask = AE_READABLE;
+            }
+            if mask != 0 {
+                fds.push((pollfd.fd, mask));
+            }
+        }
+    }
+
+    fn timeout(&mut self) -> Option<Duration> {
+        u64::try_from(self.timeout_ms)
+            .ok()
+            .map(Duration::from_millis)
+    }
+
+    fn dispatch(&mut self, _event_loop: &mut AeEventLoop, ready: &[(i32, i32)]) {
+        for pollfd in self.fds.iter_mut() {
+            let Some(&(_, mask)) = ready.iter().find(|(fd, _)| *fd == pollfd.fd) else {
+                continue;
+            };
+            if mask & AE_READABLE != 0 {
+                pollfd.revents |= pollfd.events & (G_IO_IN | G_IO_PRI | G_IO_HUP | G_IO_ERR);
+            }
+            if mask & AE_WRITABLE != 0 {
+                pollfd.revents |= pollfd.events & G_IO_OUT;
+            }
+        }
+        self.finish_cycle();
+    }
+}
+
+impl Drop for AeGlibContext {
+    fn drop(&mut self) {
+        /* Close the cycle, without running GLib callbacks from here. */
+        self.check();
+        unsafe {
+            g_main_context_release(self.context);
+            g_main_context_unref(self.context);
+        }
+    }
+}
+
+/* Run the sources of `context` (null for the default GMainContext) from
+ * the loop until ae_remove_external_source(). None if another thread owns
+ * the context. */
+pub fn ae_glib_host(
+    event_loop: &mut AeEventLoop,
+    context: *mut c_void,
+) -> Option<AeExternalSourceId> {
+    let source = AeGlibContext::new(context)?;
+    Some(ae_add_external_source(event_loop, Box::new(source)))
+}
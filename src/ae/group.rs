@@ -0,0 +1,103 @@
+/* Grouped file events.
+ *
+ * A component owning several fds (a socket, a timerfd, a signal pipe)
+ * registers them as one group: either every registration succeeds or none
+ * is left behind, and ae_delete_group() tears them all down at once. The
+ * group remembers the generation of each fd (see
+ * ae_get_file_generation()), so an fd that was deleted and handed to
+ * someone else in the meantime is left alone.
+ */
+
+use crate::ae::{
+    AeEventLoop, ae_create_file_event, ae_delete_file_event, ae_get_file_events,
+    ae_get_file_generation,
+};
+use crate::constants::{AE_ALL_MASK, AE_ERR, AE_NONE, AE_OK};
+use crate::traits::FileProc;
+use std::collections::HashMap;
+use std::ffi::c_void;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AeGroupId(u64);
+
+#[derive(Default)]
+pub(crate) struct Groups {
+    /* fd and generation of every member. */
+    members: HashMap<u64, Vec<(i32, u64)>>,
+    next_id: u64,
+}
+
+/* Register every (fd, mask, proc, client_data) entry, like
+ * ae_create_file_event(). An fd may appear more than once, e.g. with a
+ * read and a write handler, but must not be registered already: the group
+ * owns its fds. Returns None, with nothing registered, if any entry
+ * fails. */
+pub fn ae_register_group(
+    event_loop: &mut AeEventLoop,
+    entries: &[(i32, i32, FileProc, *mut c_void)],
+) -> Option<AeGroupId> {
+    let mut fds: Vec<i32> = Vec::new();
+    for &(fd, _, _, _) in entries {
+        if fds.contains(&fd) {
+            continue;
+        }
+        if fd < 0 || ae_get_file_events(event_loop, fd) != AE_NONE {
+            return None;
+        }
+        fds.push(fd);
+    }
+
+    for (done, &(fd, mask, proc, client_data)) in entries.iter().enumerate() {
+        if ae_create_file_event(event_loop, fd, mask, proc, client_data) == AE_ERR {
+            for &(fd, _, _, _) in &entries[..done] {
+                ae_delete_file_event(event_loop, fd, AE_ALL_MASK);
+            }
+            return None;
+        }
+    }
+
+    let members = fds
+        .into_iter()
+        .map(|fd| (fd, ae_get_file_generation(event_loop, fd)))
+        .collect();
+    let groups = &mut event_loop.groups;
+    let id = groups.next_id;
+    groups.next_id += 1;
+    groups.members.insert(id, members);
+    Some(AeGroupId(id))
+}
+
+/* Delete every file event of the group. Members already deleted, and
+ * possibly registered again by someone else, are skipped. Returns AE_ERR
+ * for an unknown (or already deleted) group. */
+pub fn ae_delete_group(event_loop: &mut AeEventLoop, id: AeGroupId) -> i32 {
+    let members = match event_loop.groups.members.remove(&id.0) {
+        Some(members) => members,
+        None => return AE_ERR,
+    };
+    for (fd, generation) in members {
+        if ae_get_file_generation(event_loop, fd) == generation {
+            ae_delete_file_event(event_loop, fd, AE_ALL_MASK);
+        }
+    }
+    AE_OK
+}
+
+/* The fds of the group still registered by it. */
+pub fn ae_group_fds(event_loop: &AeEventLoop, id: AeGroupId) -> Vec<i32> {
+    event_loop
+        .groups
+        .members
+        .get(&id.0)
+        .map(|members| {
+            members
+                .iter()
+                .filter(|&&(fd, generation)| {
+                    ae_get_file_generation(event_loop, fd) == generation
+                        && ae_get_file_events(event_loop, fd) != AE_NONE
+                })
+                .map(|&(fd, _)| fd)
+                .collect()
+        })
+        .unwrap_or_default()
+}
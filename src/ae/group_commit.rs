@@ -0,0 +1,177 @@
+/* Group commit.
+ *
+ * A server that promises durability (Redis with appendfsync always) must
+ * not reply before the log holding the write is on disk, and one fsync
+ * per reply would cap it at the disk's fsync rate. The usual way out is
+ * to commit the whole iteration at once: handlers append to the log and
+ * queue their reply, beforesleep runs one fsync, and only then are the
+ * write handlers installed, with AE_BARRIER so that the reply goes out
+ * before the next request of the same client is read.
+ *
+ * ae_group_commit_enable() names the log fd, ae_group_commit_reply()
+ * queues a write handler for a client fd. Right after beforesleep the
+ * loop syncs the log once if any reply is waiting, then registers the
+ * queued handlers. A failed sync keeps them queued, retried on the next
+ * iteration. Replies for an fd deleted in the meantime (the client went
+ * away) are dropped.
+ */
+
+use crate::ae::{AeEventLoop, ae_create_file_event};
+use crate::anet::set_errno;
+use crate::constants::{AE_BARRIER, AE_ERR, AE_NONE, AE_OK, AE_WRITABLE};
+use crate::traits::FileProc;
+use std::ffi::c_void;
+
+/* Counters of a loop's group commit. */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AeGroupCommitStats {
+    /* Syncs of the log, one per iteration with replies waiting. */
+    pub commits: u64,
+    /* Write handlers registered after a sync. */
+    pub replies: u64,
+    /* Syncs that failed, their replies were kept for the next one. */
+    pub failures: u64,
+    /* Replies dropped because their fd was deleted before the sync. */
+    pub dropped: u64,
+}
+
+struct PendingReply {
+    fd: i32,
+    generation: u64,
+    proc: FileProc,
+    client_data: *mut c_void,
+}
+
+pub(crate) struct GroupCommit {
+    log_fd: i32,
+    pending: Vec<PendingReply>,
+    stats: AeGroupCommitStats,
+}
+
+/* Sync `log_fd` once per iteration before releasing queued replies. The
+ * fd stays owned by the caller. Calling it again switches to another
+ * log, replies already queued wait for that one. */
+pub fn ae_group_commit_enable(event_loop: &mut AeEventLoop, log_fd: i32) -> i32 {
+    if log_fd < 0 {
+        set_errno(libc::EBADF);
+        return AE_ERR;
+    }
+    match &mut event_loop.group_commit {
+        Some(group) => group.log_fd = log_fd,
+        None => {
+            event_loop.group_commit = Some(GroupCommit {
+                log_fd,
+                pending: Vec::new(),
+                stats: AeGroupCommitStats::default(),
+            })
+        }
+    }
+    AE_OK
+}
+
+/* Stop group commits. Replies still queued are committed and released
+ * first; AE_ERR if that sync fails, in which case they are dropped. */
+pub fn ae_group_commit_disable(event_loop: &mut AeEventLoop) -> i32 {
+    if event_loop.group_commit.is_none() {
+        return AE_OK;
+    }
+    commit(event_loop);
+    let failed = event_loop
+        .group_commit
+        .take()
+        .is_some_and(|group| !group.pending.is_empty());
+    if failed { AE_ERR } else { AE_OK }
+}
+
+/* Register `proc` as the AE_WRITABLE handler of `fd` once the log is
+ * synced, which happens before the loop sleeps again. `fd` must be
+ * registered already (for reading, typically), so that its deletion can
+ * be told apart from a reuse of the number. AE_ERR with EINVAL if group
+ * commits are not enabled or `fd` is not registered. */
+pub fn ae_group_commit_reply(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    proc: FileProc,
+    client_data: *mut c_void,
+) -> i32 {
+    let generation = match event_loop.events.get(fd.max(0) as usize) {
+        Some(fe) if fd >= 0 && fe.mask != AE_NONE => fe.generation,
+        _ => {
+            set_errno(libc::EINVAL);
+            return AE_ERR;
+        }
+    };
+    let Some(group) = &mut event_loop.group_commit else {
+        set_errno(libc::EINVAL);
+        return AE_ERR;
+    };
+    group.pending.push(PendingReply {
+        fd,
+        generation,
+        proc,
+        client_data,
+    });
+    AE_OK
+}
+
+/* Replies waiting for the next sync. */
+pub fn ae_group_commit_pending(event_loop: &AeEventLoop) -> usize {
+    event_loop
+        .group_commit
+        .as_ref()
+        .map_or(0, |group| group.pending.len())
+}
+
+/* None if group commits were never enabled (or were disabled). */
+pub fn ae_group_commit_stats(event_loop: &AeEventLoop) -> Option<AeGroupCommitStats> {
+    event_loop.group_commit.as_ref().map(|group| group.stats)
+}
+
+/* Called by the loop after beforesleep: sync the log if replies are
+ * waiting, then register their handlers. */
+pub(crate) fn commit(event_loop: &mut AeEventLoop) {
+    let Some(group) = &mut event_loop.group_commit else {
+        return;
+    };
+    if group.pending.is_empty() {
+        return;
+    }
+    if sync_log(group.log_fd) == -1 {
+        group.stats.failures += 1;
+        return;
+    }
+    group.stats.commits += 1;
+
+    let pending = std::mem::take(&mut group.pending);
+    for reply in pending {
+        let live = event_loop
+            .events
+            .get(reply.fd as usize)
+            .is_some_and(|fe| fe.mask != AE_NONE && fe.generation == reply.generation);
+        let released = live
+            && ae_create_file_event(
+                event_loop,
+                reply.fd,
+                AE_WRITABLE | AE_BARRIER,
+                reply.proc,
+                reply.client_data,
+            ) == AE_OK;
+        if let Some(group) = &mut event_loop.group_commit {
+            if released {
+                group.stats.replies += 1;
+            } else {
+                group.stats.dropped += 1;
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn sync_log(fd: i32) -> i32 {
+    unsafe { libc::fdatasync(fd) }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sync_log(fd: i32) -> i32 {
+    unsafe { libc::fsync(fd) }
+}
@@ -0,0 +1,307 @@
+/* Cross-thread handle to an event loop.
+ *
+ * The loop itself is single threaded: every callback gets `&mut
+ * AeEventLoop`. Other threads talk to it through an AeHandle, which queues
+ * closures and wakes the loop up by writing a byte to a pipe whose read
+ * end is registered as a regular file event. The queued closures then run
+ * on the loop thread, in submission order.
+ *
+ * Built with `--cfg loom`, the queue uses loom's Arc and Mutex so that
+ * tests/ae_handle_loom_tests.rs can explore the interleavings of posters
+ * and the loop thread.
+ */
+
+use crate::ae::config;
+use crate::ae::custom::{self, AeCustomEventId};
+use crate::ae::reconfigure;
+use crate::ae::shutdown;
+use crate::ae::signal::{self, AE_WAKE_STOP};
+use crate::ae::{AeEventLoop, ae_create_file_event, ae_delete_file_event, set_dont_wait};
+use crate::anet::anet_pipe;
+use crate::constants::{AE_ERR, AE_OK, AE_READABLE};
+#[cfg(loom)]
+use loom::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, VecDeque};
+use std::ffi::c_void;
+use std::sync::atomic::AtomicI32;
+#[cfg(not(loom))]
+use std::sync::{Arc, Mutex};
+
+pub type AeTask = Box<dyn FnOnce(&mut AeEventLoop) + Send>;
+
+struct HandleQueue {
+    tasks: VecDeque<AeTask>,
+    /* Triggers of each custom event since the loop last looked. */
+    triggered: BTreeMap<u64, u64>,
+    /* Write end of the wakeup pipe, -1 once the loop is gone. Kept under
+     * the lock so posters never write to an fd the loop already closed. */
+    wake_fd: i32,
+    /* A wakeup byte is already in flight, no need to write another. */
+    wakeup_pending: bool,
+}
+
+struct HandleShared {
+    queue: Mutex<HandleQueue>,
+    /* The flag word of the loop, see AeHandle::set_dont_wait(). */
+    flags: std::sync::Arc<AtomicI32>,
+    /* See AeEventLoopBuilder::name(). */
+    name: Option<String>,
+}
+
+/* Clonable, Send + Sync handle used to post work to a loop from any
+ * thread. */
+#[derive(Clone)]
+pub struct AeHandle {
+    shared: Arc<HandleShared>,
+}
+
+impl std::fmt::Debug for AeHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AeHandle")
+            .field("name", &self.shared.name)
+            .field("closed", &self.is_closed())
+            .finish_non_exhaustive()
+    }
+}
+
+impl AeHandle {
+    /* Queue `task` to run on the loop thread and wake the loop up.
+     * Returns AE_ERR if the loop has already been deleted. */
+    pub fn post<F>(&self, task: F) -> i32
+    where
+        F: FnOnce(&mut AeEventLoop) + Send + 'static,
+    {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.wake_fd == -1 {
+            return AE_ERR;
+        }
+        queue.tasks.push_back(Box::new(task));
+        wake_locked(&mut queue);
+        AE_OK
+    }
+
+    /* Wake the loop up without queueing anything, e.g. after changing
+     * some state it checks in beforesleep. */
+    pub fn wakeup(&self) -> i32 {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.wake_fd == -1 {
+            return AE_ERR;
+        }
+        wake_locked(&mut queue);
+        AE_OK
+    }
+
+    /* Publish `config` on the loop, see ae_set_config(). It is swapped in
+     * on the loop thread, by the time the loop wakes up. */
+    pub fn set_config<T: Send + Sync + 'static>(&self, config: std::sync::Arc<T>) -> i32 {
+        self.post(move |event_loop| {
+            config::ae_set_config(event_loop, config);
+        })
+    }
+
+    /* Change loop parameters, see ae_reconfigure(). Posted, so they take
+     * effect at the iteration after the one that runs the post. */
+    pub fn reconfigure(&self, delta: reconfigure::AeLoopConfigDelta) -> i32 {
+        self.post(move |event_loop| {
+            reconfigure::ae_reconfigure(event_loop, delta);
+        })
+    }
+
+    /* Turn the global AE_DONT_WAIT of the loop on or off, like
+     * ae_set_dont_wait(), and wake the loop up so that a poll already
+     * sleeping returns and the next one uses the new setting. */
+    pub fn set_dont_wait(&self, no_wait: bool) -> i32 {
+        set_dont_wait(&self.shared.flags, no_wait);
+        self.wakeup()
+    }
+
+    /* Ask the loop to shut down: its shutdown token is cancelled, the
+     * procs registered with ae_on_shutdown() run and the loop stops. */
+    pub fn stop(&self) -> i32 {
+        self.post(shutdown::request)
+    }
+
+    /* True once the loop this handle points to has been deleted. */
+    pub fn is_closed(&self) -> bool {
+        self.shared.queue.lock().unwrap().wake_fd == -1
+    }
+
+    /* True if both handles point to the same loop. */
+    pub fn same_loop(&self, other: &AeHandle) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+
+    /* Name of the loop, see AeEventLoopBuilder::name(). */
+    pub fn loop_name(&self) -> Option<&str> {
+        self.shared.name.as_deref()
+    }
+
+    /* Trigger the custom event `id` of the loop (see
+     * ae_create_custom_event()). Returns AE_ERR if the loop has already
+     * been deleted. Triggers of an id the loop does not know (any longer)
+     * are dropped there. */
+    pub fn trigger(&self, id: AeCustomEventId) -> i32 {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.wake_fd == -1 {
+            return AE_ERR;
+        }
+        *queue.triggered.entry(id.0).or_insert(0) += 1;
+        wake_locked(&mut queue);
+        AE_OK
+    }
+}
+
+fn wake_locked(queue: &mut HandleQueue) {
+    if queue.wakeup_pending {
+        return;
+    }
+    let byte = 1u8;
+    /* A full pipe (EAGAIN) is fine: the loop is going to wake up anyway. */
+    unsafe { libc::write(queue.wake_fd, &byte as *const u8 as *const c_void, 1) };
+    queue.wakeup_pending = true;
+}
+
+/* Loop side of the handle: owns both pipe ends. */
+pub(crate) struct Wakeup {
+    pub(crate) rfd: i32,
+    /* Copy of the write end, readable without the lock. */
+    wfd: i32,
+    shared: Arc<HandleShared>,
+}
+
+impl Drop for Wakeup {
+    fn drop(&mut self) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        signal::forget_wake_fd(self.wfd);
+        unsafe {
+            libc::close(self.rfd);
+            libc::close(queue.wake_fd);
+        }
+        queue.wake_fd = -1;
+        queue.triggered.clear();
+        /* Tasks that never ran are dropped here, on the loop thread, once
+         * the lock is released: dropping one may post to another loop
+         * (see migrate.rs). */
+        let tasks = std::mem::take(&mut queue.tasks);
+        drop(queue);
+        drop(tasks);
+    }
+}
+
+/* Return a handle to the loop, creating the wakeup pipe on first use.
+ * Returns None if the pipe cannot be created or registered. */
+pub fn ae_get_handle(event_loop: &mut AeEventLoop) -> Option<AeHandle> {
+    if let Some(wakeup) = &event_loop.wakeup {
+        return Some(AeHandle {
+            shared: wakeup.shared.clone(),
+        });
+    }
+
+    let (rfd, wfd) = anet_pipe(true).ok()?;
+    let shared = Arc::new(HandleShared {
+        queue: Mutex::new(HandleQueue {
+            tasks: VecDeque::new(),
+            triggered: BTreeMap::new(),
+            wake_fd: wfd,
+            wakeup_pending: false,
+        }),
+        flags: event_loop.flags.clone(),
+        name: event_loop.name.clone(),
+    });
+    let wakeup = Wakeup {
+        rfd,
+        wfd,
+        shared: shared.clone(),
+    };
+
+    if ae_create_file_event(
+        event_loop,
+        rfd,
+        AE_READABLE,
+        wakeup_readable_handler,
+        std::ptr::null_mut(),
+    ) == AE_ERR
+    {
+        /* Dropping `wakeup` closes both ends. */
+        return None;
+    }
+
+    /* ae_pause() only leaves an already registered wakeup pipe armed. */
+    if event_loop.paused_at.is_some() && event_loop.apidata.add_event(rfd, AE_READABLE) == -1 {
+        ae_delete_file_event(event_loop, rfd, AE_READABLE);
+        return None;
+    }
+
+    event_loop.wakeup = Some(wakeup);
+    Some(AeHandle { shared })
+}
+
+/* Write end of the wakeup pipe, creating it if needed, -1 on failure.
+ * Unlike the handle it can be used from a signal handler. */
+pub(crate) fn wake_fd(event_loop: &mut AeEventLoop) -> i32 {
+    if ae_get_handle(event_loop).is_none() {
+        return -1;
+    }
+    event_loop.wakeup.as_ref().map_or(-1, |wakeup| wakeup.wfd)
+}
+
+/* Drop the wakeup pipe inherited from the parent process. The pipe is
+ * shared with the parent, so the child must not read from it; handles
+ * copied across the fork are closed and ae_get_handle() creates a new
+ * pipe on demand. Signals meant to stop the parent loop no longer reach
+ * the pipe either. */
+pub(crate) fn reset_wakeup_after_fork(event_loop: &mut AeEventLoop) {
+    let wakeup = match event_loop.wakeup.take() {
+        Some(wakeup) => wakeup,
+        None => return,
+    };
+    ae_delete_file_event(event_loop, wakeup.rfd, AE_READABLE);
+
+    /* A thread of the parent may have been holding the queue lock when
+     * fork() was called. That thread does not exist here, so the lock
+     * would never be released: just close our end and leak the rest. */
+    signal::forget_wake_fd(wakeup.wfd);
+    let lockable = wakeup.shared.queue.try_lock().is_ok();
+    if !lockable {
+        unsafe { libc::close(wakeup.rfd) };
+        std::mem::forget(wakeup);
+    }
+}
+
+fn wakeup_readable_handler(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    _client_data: *mut c_void,
+    _mask: i32,
+) {
+    let mut buf = [0u8; 128];
+    let mut stop = false;
+    loop {
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+        if n <= 0 {
+            break;
+        }
+        stop |= buf[..n as usize].contains(&AE_WAKE_STOP);
+    }
+    /* Written by ae_request_stop_from_signal(). */
+    if stop {
+        shutdown::request(event_loop);
+    }
+
+    let (tasks, triggered) = match &event_loop.wakeup {
+        Some(wakeup) => {
+            let mut queue = wakeup.shared.queue.lock().unwrap();
+            queue.wakeup_pending = false;
+            (
+                std::mem::take(&mut queue.tasks),
+                std::mem::take(&mut queue.triggered),
+            )
+        }
+        None => return,
+    };
+
+    for task in tasks {
+        task(event_loop);
+    }
+    custom::dispatch(event_loop, triggered);
+}
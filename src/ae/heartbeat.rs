@@ -0,0 +1,183 @@
+/* Loop freeze detection through a heartbeat pipe.
+ *
+ * Once enabled, the loop writes a byte to a pipe at the start of its
+ * iterations, at most once per interval, and a timer makes sure it wakes
+ * up at least that often even when idle. The read end is watched by a
+ * monitor outside the loop thread: a sibling thread started with
+ * AeHeartbeat::spawn_monitor(), or another process the fd was handed to.
+ * Silence on the pipe means the loop is stuck, whether in a callback or
+ * inside the poll itself.
+ */
+
+use crate::ae::{
+    AeEventLoop, ae_create_time_event, ae_delete_time_event, duration_ms, ms_duration,
+};
+use crate::anet::{anet_pipe, errno};
+use std::ffi::c_void;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+pub(crate) struct HeartbeatState {
+    wfd: i32,
+    interval_us: u64,
+    last_beat_us: u64,
+    timer_id: i64,
+}
+
+/* Monitor side of the heartbeat: owns the read end of the pipe. */
+#[derive(Debug)]
+pub struct AeHeartbeat {
+    rfd: i32,
+    interval: Duration,
+}
+
+impl AeHeartbeat {
+    /* Read end of the pipe, for monitors with their own poll loop. It is
+     * non-blocking, and reaches end of file once the loop is gone. */
+    pub fn fd(&self) -> i32 {
+        self.rfd
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /* Wait up to `timeout` for a beat, consuming the pending ones.
+     * Returns Some(true) on a beat, Some(false) on timeout and None once
+     * the loop has been deleted (or heartbeats disabled). */
+    pub fn wait(&self, timeout: Duration) -> Option<bool> {
+        let mut pfd = libc::pollfd {
+            fd: self.rfd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        let n = unsafe { libc::poll(&mut pfd, 1, ms) };
+        if n == 0 || (n == -1 && errno() == libc::EINTR) {
+            return Some(false);
+        }
+        if n == -1 {
+            return None;
+        }
+
+        let mut buf = [0u8; 64];
+        let mut beat = false;
+        loop {
+            let nread = unsafe { libc::read(self.rfd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+            if nread > 0 {
+                beat = true;
+                continue;
+            }
+            if nread == 0 {
+                /* Writer closed. Report the last beats first. */
+                return if beat { Some(true) } else { None };
+            }
+            return Some(beat);
+        }
+    }
+
+    /* Start a thread calling `alarm` with the time since the last beat
+     * every time `timeout` passes without one. The thread exits once the
+     * loop is deleted. */
+    pub fn spawn_monitor<F>(self, timeout: Duration, alarm: F) -> std::io::Result<JoinHandle<()>>
+    where
+        F: Fn(Duration) + Send + 'static,
+    {
+        std::thread::Builder::new()
+            .name("rae-heartbeat".into())
+            .spawn(move || {
+                let mut last_beat = Instant::now();
+                loop {
+                    match self.wait(timeout) {
+                        Some(true) => last_beat = Instant::now(),
+                        Some(false) => alarm(last_beat.elapsed()),
+                        None => return,
+                    }
+                }
+            })
+    }
+}
+
+impl Drop for AeHeartbeat {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.rfd) };
+    }
+}
+
+/* Start beating at least every `interval_ms` milliseconds and return the
+ * monitor side. Enabling it again replaces the previous pipe, whose
+ * monitor then sees the loop as gone. None if the pipe cannot be
+ * created. */
+pub fn ae_enable_heartbeat(event_loop: &mut AeEventLoop, interval_ms: i64) -> Option<AeHeartbeat> {
+    ae_enable_heartbeat_duration(event_loop, ms_duration(interval_ms))
+}
+
+/* ae_enable_heartbeat() taking the interval as a Duration, in whole
+ * milliseconds rounded up, at least 1. */
+pub fn ae_enable_heartbeat_duration(
+    event_loop: &mut AeEventLoop,
+    interval: Duration,
+) -> Option<AeHeartbeat> {
+    let interval_ms = duration_ms(interval).max(1);
+    ae_disable_heartbeat(event_loop);
+
+    let (rfd, wfd) = anet_pipe(true).ok()?;
+    let timer_id = ae_create_time_event(
+        event_loop,
+        interval_ms,
+        heartbeat_timer,
+        std::ptr::null_mut(),
+        None,
+    );
+    event_loop.heartbeat = Some(HeartbeatState {
+        wfd,
+        interval_us: interval_ms as u64 * 1000,
+        last_beat_us: 0,
+        timer_id,
+    });
+    beat(event_loop);
+    Some(AeHeartbeat {
+        rfd,
+        interval: Duration::from_millis(interval_ms as u64),
+    })
+}
+
+/* Stop beating and close the write end of the pipe. */
+pub fn ae_disable_heartbeat(event_loop: &mut AeEventLoop) {
+    if let Some(state) = event_loop.heartbeat.take() {
+        ae_delete_time_event(event_loop, state.timer_id);
+        unsafe { libc::close(state.wfd) };
+    }
+}
+
+/* Does nothing, only there to bound the poll timeout. */
+fn heartbeat_timer(event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    match &event_loop.heartbeat {
+        Some(state) => (state.interval_us / 1000) as i32,
+        None => crate::constants::AE_NOMORE,
+    }
+}
+
+/* Called at the start of every iteration. */
+pub(crate) fn beat(event_loop: &mut AeEventLoop) {
+    let now = event_loop.cached_now_us;
+    let state = match event_loop.heartbeat.as_mut() {
+        Some(state) => state,
+        None => return,
+    };
+    /* Half the interval keeps a beat in every interval despite the timer
+     * firing slightly late. */
+    if state.last_beat_us != 0 && now.saturating_sub(state.last_beat_us) < state.interval_us / 2 {
+        return;
+    }
+    state.last_beat_us = now;
+    let byte = 1u8;
+    /* A full pipe (EAGAIN) means nobody is reading, which is fine. */
+    unsafe { libc::write(state.wfd, &byte as *const u8 as *const c_void, 1) };
+}
+
+/* The pipe is shared with the parent after fork(): the child must not
+ * beat on its behalf. */
+pub(crate) fn reset_after_fork(event_loop: &mut AeEventLoop) {
+    ae_disable_heartbeat(event_loop);
+}
@@ -0,0 +1,169 @@
+/* Idle timeouts on buffered connections.
+ *
+ * A server with hundreds of thousands of connections does not want one
+ * precise timer per client timeout: the timers would fill the time event
+ * list and be pushed back on every byte received. Idle timeouts live in a
+ * coarse wheel instead, with AE_IDLE_WHEEL_SLOTS slots of one second, and
+ * a single time event turns it once per second while it holds anything.
+ *
+ * Traffic does not move a connection in the wheel. When its slot comes
+ * up, the connection is closed with ETIMEDOUT if it has been idle for its
+ * timeout, and put back in the slot of its new deadline otherwise. A
+ * timeout therefore fires up to one second late, which is the point of
+ * the wheel: precise timers stay in the time event list.
+ */
+
+use crate::ae::conn::{ae_conn_close_with_error, ae_conn_stats};
+use crate::ae::{AeEventLoop, ae_create_time_event, duration_us};
+use crate::constants::{AE_ERR, AE_NOMORE, AE_OK};
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::time::Duration;
+
+/* Seconds covered by one turn of the wheel. Deadlines further away are
+ * checked once per turn until they are due. */
+pub const AE_IDLE_WHEEL_SLOTS: usize = 64;
+
+const TICK_US: u64 = 1_000_000;
+
+#[derive(Default)]
+pub(crate) struct IdleWheel {
+    slots: Vec<Vec<i32>>,
+    /* Connection to its timeout and the tick of the slot it is in. A
+     * connection found in any other slot is a stale entry. */
+    conns: HashMap<i32, (u64, u64)>,
+    /* Next tick to process. */
+    cursor: u64,
+    /* The time event turning the wheel, if any. */
+    timer_id: Option<i64>,
+}
+
+/* Close the connection `fd` (see ae_conn_create()) with ETIMEDOUT once it
+ * has neither read nor written anything for `timeout_ms` milliseconds,
+ * within a second. Setting it again replaces the timeout, 0 removes it.
+ * Returns AE_ERR for a negative timeout or if `fd` is not an open
+ * connection. */
+pub fn ae_conn_set_idle_timeout(event_loop: &mut AeEventLoop, fd: i32, timeout_ms: i64) -> i32 {
+    if timeout_ms < 0 {
+        return AE_ERR;
+    }
+    ae_conn_set_idle_timeout_duration(event_loop, fd, Duration::from_millis(timeout_ms as u64))
+}
+
+/* ae_conn_set_idle_timeout() taking the timeout as a Duration,
+ * Duration::ZERO to remove it. */
+pub fn ae_conn_set_idle_timeout_duration(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    timeout: Duration,
+) -> i32 {
+    let last_interaction_us = match ae_conn_stats(event_loop, fd) {
+        Some(stats) => stats.last_interaction_us,
+        None => return AE_ERR,
+    };
+    let timeout_us = duration_us(timeout);
+    if timeout_us == 0 {
+        forget(event_loop, fd);
+        return AE_OK;
+    }
+    if event_loop.idle.timer_id.is_none() {
+        let timer_id = ae_create_time_event(
+            event_loop,
+            (TICK_US / 1000) as i64,
+            idle_timer,
+            std::ptr::null_mut(),
+            None,
+        );
+        let wheel = &mut event_loop.idle;
+        wheel.timer_id = Some(timer_id);
+        wheel.cursor = event_loop.cached_now_us / TICK_US;
+        if wheel.slots.is_empty() {
+            wheel.slots = vec![Vec::new(); AE_IDLE_WHEEL_SLOTS];
+        }
+    }
+    schedule(
+        &mut event_loop.idle,
+        fd,
+        timeout_us,
+        last_interaction_us.saturating_add(timeout_us),
+    );
+    AE_OK
+}
+
+/* Idle timeout of `fd` in milliseconds, None if it has none. */
+pub fn ae_conn_idle_timeout(event_loop: &AeEventLoop, fd: i32) -> Option<i64> {
+    let &(timeout_us, _) = event_loop.idle.conns.get(&fd)?;
+    Some((timeout_us / 1000) as i64)
+}
+
+/* Connections with an idle timeout. */
+pub fn ae_idle_wheel_len(event_loop: &AeEventLoop) -> usize {
+    event_loop.idle.conns.len()
+}
+
+/* Put `fd` in the slot of `deadline_us`, or in the furthest slot when the
+ * deadline is more than a turn away. */
+fn schedule(wheel: &mut IdleWheel, fd: i32, timeout_us: u64, deadline_us: u64) {
+    let tick = deadline_us
+        .div_ceil(TICK_US)
+        .clamp(wheel.cursor, wheel.cursor + AE_IDLE_WHEEL_SLOTS as u64 - 1);
+    wheel.slots[(tick % AE_IDLE_WHEEL_SLOTS as u64) as usize].push(fd);
+    wheel.conns.insert(fd, (timeout_us, tick));
+}
+
+/* Called by the connection layer when `fd` is closed. The entry left in
+ * its slot is skipped when the slot comes up. */
+pub(crate) fn forget(event_loop: &mut AeEventLoop, fd: i32) {
+    event_loop.idle.conns.remove(&fd);
+}
+
+/* Called when the connection `old_fd` was moved to `new_fd`, see
+ * ae_resize_set_size_compact(). */
+pub(crate) fn relocate(event_loop: &mut AeEventLoop, old_fd: i32, new_fd: i32) {
+    let wheel = &mut event_loop.idle;
+    if let Some((timeout_us, tick)) = wheel.conns.remove(&old_fd) {
+        wheel.slots[(tick % AE_IDLE_WHEEL_SLOTS as u64) as usize].push(new_fd);
+        wheel.conns.insert(new_fd, (timeout_us, tick));
+    }
+}
+
+fn idle_timer(event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    let now_us = event_loop.cached_now_us;
+    /* Catch up on the ticks missed while the loop was busy. */
+    while event_loop.idle.cursor <= now_us / TICK_US {
+        let tick = event_loop.idle.cursor;
+        let slot = (tick % AE_IDLE_WHEEL_SLOTS as u64) as usize;
+        let fds = std::mem::take(&mut event_loop.idle.slots[slot]);
+        event_loop.idle.cursor += 1;
+        for fd in fds {
+            expire(event_loop, fd, tick, now_us);
+        }
+    }
+
+    if event_loop.idle.conns.is_empty() {
+        event_loop.idle.timer_id = None;
+        return AE_NOMORE;
+    }
+    (TICK_US / 1000) as i32
+}
+
+fn expire(event_loop: &mut AeEventLoop, fd: i32, tick: u64, now_us: u64) {
+    /* Closing a connection may close others, which forget() removes. */
+    let timeout_us = match event_loop.idle.conns.get(&fd) {
+        Some(&(timeout_us, scheduled)) if scheduled == tick => timeout_us,
+        _ => return,
+    };
+    let deadline_us = match ae_conn_stats(event_loop, fd) {
+        Some(stats) => stats.last_interaction_us.saturating_add(timeout_us),
+        None => {
+            forget(event_loop, fd);
+            return;
+        }
+    };
+    if deadline_us <= now_us {
+        forget(event_loop, fd);
+        ae_conn_close_with_error(event_loop, fd, libc::ETIMEDOUT);
+    } else {
+        schedule(&mut event_loop.idle, fd, timeout_us, deadline_us);
+    }
+}
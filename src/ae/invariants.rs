@@ -0,0 +1,271 @@
+/* Loop state invariants.
+ *
+ * The loop keeps the same facts in several places: the mask of an fd in
+ * its slot and in the kernel, the highest registered fd in maxfd, the
+ * number of registered fds in a counter. A bug in the loop, or code
+ * poking at its public fields, can get them out of step, and the symptom
+ * shows up much later: an fd that never fires, a poll over a stale maxfd.
+ *
+ * ae_check_invariants() verifies them on demand. With the
+ * `debug-invariants` feature the loop also does it after every public
+ * mutation (file event registration and deletion, resizing, pause,
+ * resume, fork reinit, each ae_process_events() call), and panics with
+ * the operation and the first invariant found broken. The feature also
+ * puts a shadow in front of the backend, recording what the kernel was
+ * asked to watch, so the masks of the loop are checked against it.
+ *
+ * The checks walk every fd slot: meant for tests and debug builds of
+ * downstream code, not for production.
+ */
+
+use crate::ae::AeEventLoop;
+#[cfg(feature = "debug-invariants")]
+use crate::ae_select::FiredEvent;
+#[cfg(feature = "debug-invariants")]
+use crate::constants::AE_ALL_MASK;
+use crate::constants::{AE_BARRIER, AE_ERRQUEUE, AE_NONE, AE_READABLE, AE_WRITABLE};
+use crate::traits::EventBackend;
+#[cfg(feature = "debug-invariants")]
+use std::cell::RefCell;
+#[cfg(feature = "debug-invariants")]
+use std::rc::Rc;
+#[cfg(feature = "debug-invariants")]
+use std::time::Duration;
+
+/* Interest handed to the backend per fd, as seen by ShadowBackend. Empty
+ * without the `debug-invariants` feature. */
+#[derive(Clone, Default)]
+pub(crate) struct Shadow {
+    #[cfg(feature = "debug-invariants")]
+    masks: Rc<RefCell<Vec<i32>>>,
+}
+
+/* Put the shadow in front of `backend`, with the feature. */
+#[cfg(feature = "debug-invariants")]
+pub(crate) fn wrap(backend: Box<dyn EventBackend>, shadow: &Shadow) -> Box<dyn EventBackend> {
+    Box::new(ShadowBackend {
+        inner: backend,
+        masks: shadow.masks.clone(),
+    })
+}
+
+#[cfg(not(feature = "debug-invariants"))]
+pub(crate) fn wrap(backend: Box<dyn EventBackend>, _shadow: &Shadow) -> Box<dyn EventBackend> {
+    backend
+}
+
+/* Check the invariants after `op`, panicking if one is broken. Nothing
+ * without the `debug-invariants` feature. */
+#[inline]
+pub(crate) fn after(event_loop: &AeEventLoop, op: &str) {
+    #[cfg(feature = "debug-invariants")]
+    if let Err(violation) = ae_check_invariants(event_loop) {
+        panic!("rae: loop invariant broken after {}: {}", op, violation);
+    }
+    #[cfg(not(feature = "debug-invariants"))]
+    let _ = (event_loop, op);
+}
+
+/* Verify the loop state, returning the first inconsistency found:
+ *
+ *   - nevents is at most setsize, and the fd table has nevents slots;
+ *   - maxfd is the highest registered fd, -1 if there is none;
+ *   - the count of registered fds matches the slots;
+ *   - each registered direction has a handler, AE_BARRIER goes with
+ *     AE_WRITABLE;
+ *   - with the `debug-invariants` feature, the backend watches what the
+ *     slots say (nothing but the handle wakeup fd while paused).
+ */
+pub fn ae_check_invariants(event_loop: &AeEventLoop) -> Result<(), String> {
+    if event_loop.nevents as i64 > event_loop.setsize as i64 {
+        return Err(format!(
+            "nevents {} exceeds setsize {}",
+            event_loop.nevents, event_loop.setsize
+        ));
+    }
+    if event_loop.events.len() != event_loop.nevents as usize {
+        return Err(format!(
+            "{} fd slots for nevents {}",
+            event_loop.events.len(),
+            event_loop.nevents
+        ));
+    }
+
+    let mut highest = -1;
+    let mut registered = 0;
+    for (fd, fe) in event_loop.events.iter().enumerate() {
+        if fe.mask == AE_NONE {
+            continue;
+        }
+        highest = fd as i32;
+        registered += 1;
+        if (fe.mask & AE_READABLE != 0) != fe.rfile_proc.is_some() {
+            return Err(format!(
+                "fd {} mask {} disagrees with its read handler",
+                fd, fe.mask
+            ));
+        }
+        if (fe.mask & AE_WRITABLE != 0) != fe.wfile_proc.is_some() {
+            return Err(format!(
+                "fd {} mask {} disagrees with its write handler",
+                fd, fe.mask
+            ));
+        }
+        if (fe.mask & AE_ERRQUEUE != 0) != fe.efile_proc.is_some() {
+            return Err(format!(
+                "fd {} mask {} disagrees with its error queue handler",
+                fd, fe.mask
+            ));
+        }
+        if fe.mask & AE_BARRIER != 0 && fe.mask & AE_WRITABLE == 0 {
+            return Err(format!("fd {} has AE_BARRIER without AE_WRITABLE", fd));
+        }
+    }
+    if event_loop.maxfd != highest {
+        return Err(format!(
+            "maxfd is {} but the highest registered fd is {}",
+            event_loop.maxfd, highest
+        ));
+    }
+    if event_loop.registered_fds != registered {
+        return Err(format!(
+            "{} fds counted as registered, {} slots in use",
+            event_loop.registered_fds, registered
+        ));
+    }
+
+    #[cfg(feature = "debug-invariants")]
+    check_shadow(event_loop)?;
+    Ok(())
+}
+
+#[cfg(feature = "debug-invariants")]
+fn check_shadow(event_loop: &AeEventLoop) -> Result<(), String> {
+    let masks = event_loop.shadow.masks.borrow();
+    let wakeup_fd = event_loop.wakeup.as_ref().map_or(-1, |w| w.rfd);
+    let slots = event_loop.events.len().max(masks.len());
+    for fd in 0..slots {
+        let watched = masks.get(fd).copied().unwrap_or(AE_NONE);
+        let registered = event_loop
+            .events
+            .get(fd)
+            .map_or(AE_NONE, |fe| fe.mask & AE_ALL_MASK);
+        let expected = if event_loop.paused_at.is_some() && fd as i32 != wakeup_fd {
+            AE_NONE
+        } else {
+            registered
+        };
+        if watched != expected {
+            return Err(format!(
+                "fd {} registered with mask {} but the backend watches {}",
+                fd, expected, watched
+            ));
+        }
+    }
+    Ok(())
+}
+
+/* Forwards to the real backend, recording the interest it was handed. */
+#[cfg(feature = "debug-invariants")]
+struct ShadowBackend {
+    inner: Box<dyn EventBackend>,
+    masks: Rc<RefCell<Vec<i32>>>,
+}
+
+#[cfg(feature = "debug-invariants")]
+impl ShadowBackend {
+    fn record(&self, fd: i32, set: i32, clear: i32) {
+        if fd < 0 {
+            return;
+        }
+        let mut masks = self.masks.borrow_mut();
+        if masks.len() <= fd as usize {
+            masks.resize(fd as usize + 1, AE_NONE);
+        }
+        let mask = &mut masks[fd as usize];
+        *mask = (*mask | set) & !clear & AE_ALL_MASK;
+    }
+}
+
+#[cfg(feature = "debug-invariants")]
+impl EventBackend for ShadowBackend {
+    fn create() -> Result<Box<Self>, i32> {
+        Err(libc::ENOTSUP)
+    }
+
+    fn free(self: Box<Self>) {
+        self.inner.free();
+    }
+
+    fn resize(&mut self, setsize: i32) -> i32 {
+        self.inner.resize(setsize)
+    }
+
+    fn add_event(&mut self, fd: i32, mask: i32) -> i32 {
+        let retval = self.inner.add_event(fd, mask);
+        if retval != -1 {
+            self.record(fd, mask, AE_NONE);
+        }
+        retval
+    }
+
+    fn add_event_exclusive(&mut self, fd: i32, mask: i32) -> i32 {
+        let retval = self.inner.add_event_exclusive(fd, mask);
+        if retval != -1 {
+            self.record(fd, mask, AE_NONE);
+        }
+        retval
+    }
+
+    fn del_event(&mut self, fd: i32, mask: i32) {
+        self.inner.del_event(fd, mask);
+        self.record(fd, AE_NONE, mask);
+    }
+
+    fn poll(
+        &mut self,
+        events: &[crate::ae::AeFileEvent],
+        fired: &mut [FiredEvent],
+        maxfd: i32,
+        timeout: Option<Duration>,
+    ) -> Result<i32, i32> {
+        self.inner.poll(events, fired, maxfd, timeout)
+    }
+
+    fn poll_nowait(
+        &mut self,
+        events: &[crate::ae::AeFileEvent],
+        fired: &mut [FiredEvent],
+        maxfd: i32,
+    ) -> Result<i32, i32> {
+        self.inner.poll_nowait(events, fired, maxfd)
+    }
+
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn fd(&self) -> i32 {
+        self.inner.fd()
+    }
+
+    fn supports_errqueue(&self) -> bool {
+        self.inner.supports_errqueue()
+    }
+
+    fn reinit(&mut self) -> i32 {
+        let retval = self.inner.reinit();
+        if retval != -1 {
+            self.masks.borrow_mut().clear();
+        }
+        retval
+    }
+
+    fn stats(&self) -> crate::ae::stats::AeBackendStats {
+        self.inner.stats()
+    }
+
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats();
+    }
+}
@@ -0,0 +1,136 @@
+/* Incremental jobs.
+ *
+ * A job is background work done a slice at a time: every iteration its
+ * proc runs with a context whose deadline is the job's time slice (or the
+ * iteration deadline, see ae_set_iteration_budget(), if that comes first)
+ * and returns whether work is left. The proc is expected to check
+ * should_yield() and return in time. Slices that run past their budget
+ * anyway are counted in AeStats::job_overruns and logged in the job
+ * slowlog, like Redis logs slow commands, so the job that stalls the loop
+ * can be found.
+ */
+
+use crate::ae::context::ae_set_dispatch_label;
+use crate::ae::dispatch::ae_dispatch_ctx;
+use crate::ae::{AeEventLoop, ae_create_time_event_owned};
+use crate::constants::AE_NOMORE;
+use crate::traits::JobProc;
+use std::collections::VecDeque;
+
+/* Entries kept in the slowlog, oldest dropped first (slowlog-max-len). */
+pub const AE_SLOWLOG_MAX_LEN: usize = 128;
+
+/* A job slice, or a budgeted timer callback, that ran past its budget. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AeSlowlogEntry {
+    /* Unique and increasing, like the SLOWLOG GET entry id. */
+    pub id: u64,
+    /* Timer id of the job (see ae_create_job()) or of the timer. */
+    pub job_id: i64,
+    pub name: &'static str,
+    /* Loop clock time the slice or callback started at. */
+    pub started_us: u64,
+    pub budget_us: u64,
+    pub took_us: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Slowlog {
+    entries: VecDeque<AeSlowlogEntry>,
+    next_id: u64,
+}
+
+struct Job<T> {
+    name: &'static str,
+    slice_us: u64,
+    proc: JobProc<T>,
+    data: Box<T>,
+}
+
+fn job_timer<T>(event_loop: &mut AeEventLoop, id: i64, job: &mut Job<T>) -> i32 {
+    ae_set_dispatch_label(event_loop, job.name);
+    let started_us = event_loop.now_us();
+    let mut ctx = ae_dispatch_ctx(event_loop);
+    ctx.shorten_deadline(started_us.saturating_add(job.slice_us));
+    let more = (job.proc)(event_loop, &ctx, &mut job.data);
+    let took_us = event_loop.now_us().saturating_sub(started_us);
+
+    event_loop.stats.stats.job_slices += 1;
+    if took_us > job.slice_us {
+        event_loop.stats.stats.job_overruns += 1;
+        log_slow(event_loop, id, job.name, started_us, job.slice_us, took_us);
+    }
+    if more { 0 } else { AE_NOMORE }
+}
+
+/* Log a job slice or a timer callback that ran past its budget, see
+ * ae_set_time_event_budget(). The slowlog stays empty without the
+ * `instrumentation` feature. */
+pub(crate) fn log_slow(
+    event_loop: &mut AeEventLoop,
+    job_id: i64,
+    name: &'static str,
+    started_us: u64,
+    budget_us: u64,
+    took_us: u64,
+) {
+    if !cfg!(feature = "instrumentation") {
+        return;
+    }
+    let slowlog = &mut event_loop.slowlog;
+    if slowlog.entries.len() == AE_SLOWLOG_MAX_LEN {
+        slowlog.entries.pop_back();
+    }
+    slowlog.entries.push_front(AeSlowlogEntry {
+        id: slowlog.next_id,
+        job_id,
+        name,
+        started_us,
+        budget_us,
+        took_us,
+    });
+    slowlog.next_id += 1;
+}
+
+/* Run `proc` on `data` once per iteration, in slices of `slice_us`
+ * microseconds, until it returns false. `data` is then dropped. Returns
+ * the job id, a timer id: ae_delete_time_event() cancels the job. */
+pub fn ae_create_job<T: 'static>(
+    event_loop: &mut AeEventLoop,
+    name: &'static str,
+    slice_us: u64,
+    proc: JobProc<T>,
+    data: Box<T>,
+) -> i64 {
+    ae_create_time_event_owned(
+        event_loop,
+        0,
+        job_timer::<T>,
+        Box::new(Job {
+            name,
+            slice_us,
+            proc,
+            data,
+        }),
+    )
+}
+
+/* Up to `count` slowlog entries, newest first, like SLOWLOG GET. */
+pub fn ae_slowlog_get(event_loop: &AeEventLoop, count: usize) -> Vec<AeSlowlogEntry> {
+    event_loop
+        .slowlog
+        .entries
+        .iter()
+        .take(count)
+        .cloned()
+        .collect()
+}
+
+pub fn ae_slowlog_len(event_loop: &AeEventLoop) -> usize {
+    event_loop.slowlog.entries.len()
+}
+
+/* Drop every entry. Ids keep increasing. */
+pub fn ae_slowlog_reset(event_loop: &mut AeEventLoop) {
+    event_loop.slowlog.entries.clear();
+}
@@ -0,0 +1,181 @@
+/* Keepalive probes on buffered connections.
+ *
+ * A peer that vanished without a FIN (crashed host, dropped NAT entry)
+ * leaves a connection that looks open forever. Watched connections that
+ * received nothing during an interval get a protocol level ping written
+ * to them (PING for RESP, an empty frame, whatever the protocol answers);
+ * any input counts as the answer. One that stays silent for `max_missed`
+ * pings in a row is closed with ETIMEDOUT.
+ *
+ * A single timer serves every watched connection of the loop, and the
+ * pings go through the connection output queue, after pending replies.
+ */
+
+use crate::ae::conn::{ae_conn_close_with_error, ae_conn_write, conn_bytes_in};
+use crate::ae::{
+    AeEventLoop, ae_create_time_event, ae_delete_time_event, duration_ms, ms_duration,
+};
+use crate::constants::{AE_ERR, AE_NOMORE, AE_OK};
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+use std::time::Duration;
+
+pub(crate) struct KeepaliveState {
+    interval_ms: i64,
+    max_missed: u32,
+    timer_id: i64,
+    conns: BTreeMap<i32, Watched>,
+}
+
+struct Watched {
+    ping: Vec<u8>,
+    /* Pings sent since the last input. */
+    missed: u32,
+    /* Input counter of the connection at the previous check. */
+    bytes_in: u64,
+}
+
+/* Check watched connections every `interval_ms` milliseconds and close
+ * those that missed `max_missed` pings. Enabling it again changes the
+ * settings and keeps the connections watched. Returns AE_ERR for a
+ * non-positive interval or a zero `max_missed`. */
+pub fn ae_keepalive_enable(event_loop: &mut AeEventLoop, interval_ms: i64, max_missed: u32) -> i32 {
+    ae_keepalive_enable_duration(event_loop, ms_duration(interval_ms), max_missed)
+}
+
+/* ae_keepalive_enable() taking the interval as a Duration, in whole
+ * milliseconds rounded up. Returns AE_ERR for a zero interval. */
+pub fn ae_keepalive_enable_duration(
+    event_loop: &mut AeEventLoop,
+    interval: Duration,
+    max_missed: u32,
+) -> i32 {
+    let interval_ms = duration_ms(interval);
+    if interval_ms == 0 || max_missed == 0 {
+        return AE_ERR;
+    }
+    let conns = match event_loop.keepalive.take() {
+        Some(state) => {
+            ae_delete_time_event(event_loop, state.timer_id);
+            state.conns
+        }
+        None => BTreeMap::new(),
+    };
+    let timer_id = ae_create_time_event(
+        event_loop,
+        interval_ms,
+        keepalive_timer,
+        std::ptr::null_mut(),
+        None,
+    );
+    event_loop.keepalive = Some(KeepaliveState {
+        interval_ms,
+        max_missed,
+        timer_id,
+        conns,
+    });
+    AE_OK
+}
+
+/* Stop probing and forget every watched connection. */
+pub fn ae_keepalive_disable(event_loop: &mut AeEventLoop) {
+    if let Some(state) = event_loop.keepalive.take() {
+        ae_delete_time_event(event_loop, state.timer_id);
+    }
+}
+
+/* Probe the connection `fd` (see ae_conn_create()) with `ping` when it is
+ * idle. Watching it again replaces the ping. Returns AE_ERR if keepalive
+ * is not enabled or `fd` is not an open connection. The connection is
+ * forgotten when it is closed. */
+pub fn ae_keepalive_watch(event_loop: &mut AeEventLoop, fd: i32, ping: &[u8]) -> i32 {
+    let bytes_in = match conn_bytes_in(event_loop, fd) {
+        Some(bytes_in) => bytes_in,
+        None => return AE_ERR,
+    };
+    match event_loop.keepalive.as_mut() {
+        Some(state) => {
+            state.conns.insert(
+                fd,
+                Watched {
+                    ping: ping.to_vec(),
+                    missed: 0,
+                    bytes_in,
+                },
+            );
+            AE_OK
+        }
+        None => AE_ERR,
+    }
+}
+
+/* Stop probing `fd`. */
+pub fn ae_keepalive_unwatch(event_loop: &mut AeEventLoop, fd: i32) {
+    if let Some(state) = event_loop.keepalive.as_mut() {
+        state.conns.remove(&fd);
+    }
+}
+
+/* Pings `fd` has left unanswered so far, None if it is not watched. */
+pub fn ae_keepalive_missed(event_loop: &AeEventLoop, fd: i32) -> Option<u32> {
+    let state = event_loop.keepalive.as_ref()?;
+    state.conns.get(&fd).map(|watched| watched.missed)
+}
+
+/* Called by the connection layer when `fd` is closed. */
+pub(crate) fn forget(event_loop: &mut AeEventLoop, fd: i32) {
+    ae_keepalive_unwatch(event_loop, fd);
+}
+
+/* Called when the connection `old_fd` was moved to `new_fd`, see
+ * ae_resize_set_size_compact(). */
+pub(crate) fn relocate(event_loop: &mut AeEventLoop, old_fd: i32, new_fd: i32) {
+    if let Some(state) = event_loop.keepalive.as_mut()
+        && let Some(watched) = state.conns.remove(&old_fd)
+    {
+        state.conns.insert(new_fd, watched);
+    }
+}
+
+fn keepalive_timer(event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    let (interval_ms, max_missed, fds) = match &event_loop.keepalive {
+        Some(state) => (
+            state.interval_ms,
+            state.max_missed,
+            state.conns.keys().copied().collect::<Vec<i32>>(),
+        ),
+        None => return AE_NOMORE,
+    };
+
+    for fd in fds {
+        let bytes_in = conn_bytes_in(event_loop, fd);
+        /* Closing or pinging a connection may close others, which
+         * forget() removes: look each one up again. */
+        let watched = match event_loop.keepalive.as_mut() {
+            Some(state) => match state.conns.get_mut(&fd) {
+                Some(watched) => watched,
+                None => continue,
+            },
+            None => break,
+        };
+        let bytes_in = match bytes_in {
+            Some(bytes_in) => bytes_in,
+            None => {
+                ae_keepalive_unwatch(event_loop, fd);
+                continue;
+            }
+        };
+
+        if bytes_in != watched.bytes_in {
+            watched.bytes_in = bytes_in;
+            watched.missed = 0;
+        } else if watched.missed >= max_missed {
+            ae_conn_close_with_error(event_loop, fd, libc::ETIMEDOUT);
+        } else {
+            watched.missed += 1;
+            let ping = watched.ping.clone();
+            ae_conn_write(event_loop, fd, &ping);
+        }
+    }
+    interval_ms.min(i32::MAX as i64) as i32
+}
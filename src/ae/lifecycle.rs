@@ -0,0 +1,35 @@
+/* Loop lifecycle notifications.
+ *
+ * A single optional subscriber receives typed events as the loop runs, so
+ * supervisors can track liveness (e.g. "no IterationBegin for 5s") without
+ * polling ae_get_stats().
+ */
+
+use crate::ae::AeEventLoop;
+use crate::traits::LifecycleProc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeLifecycleEvent {
+    /* ae_main() is about to start iterating. */
+    LoopStarted,
+    /* An ae_process_events() iteration begins, `n` counts from 1 (and
+     * restarts after ae_reset_stats()). */
+    IterationBegin { n: u64 },
+    /* The backend poll returned `nfired` events after blocking for
+     * `waited_us` microseconds. */
+    PollReturned { nfired: i32, waited_us: u64 },
+    /* ae_main() returned because ae_stop() was called. */
+    LoopStopped,
+}
+
+/* Set (or clear with None) the lifecycle subscriber. */
+pub fn ae_set_lifecycle_proc(event_loop: &mut AeEventLoop, lifecycle: Option<LifecycleProc>) {
+    event_loop.lifecycle = lifecycle;
+}
+
+#[inline]
+pub(crate) fn emit(event_loop: &mut AeEventLoop, event: AeLifecycleEvent) {
+    if let Some(lifecycle) = event_loop.lifecycle {
+        lifecycle(event_loop, &event);
+    }
+}
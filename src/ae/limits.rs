@@ -0,0 +1,152 @@
+/* Soft limits on registrations.
+ *
+ * The hard limits of a loop show up as failures: ae_create_file_event()
+ * returns AE_ERR (ERANGE) for an fd past the setsize, and a loop with
+ * hundreds of thousands of timers spends its iterations walking them.
+ * Operators want to hear about it before, while there is still room to
+ * shed load or resize the loop.
+ *
+ * ae_set_soft_limits() sets thresholds below those limits. When a
+ * registration brings the loop to one of them, an AeFindingKind::SoftLimit
+ * finding is raised through the diagnostic proc (see
+ * ae_set_diagnostic_proc()), once per crossing: it is raised again only
+ * after the usage went back under the limit, as seen by a later
+ * registration. ae_doctor() lists the limits reached at the time.
+ */
+
+use crate::ae::doctor::{self, AeFinding, AeFindingKind, AeFindingSeverity};
+use crate::ae::{AeEventLoop, ae_get_set_size, ae_pending_time_events};
+use crate::constants::{AE_ERR, AE_OK};
+
+/* Thresholds of ae_set_soft_limits(), 0 disables one. */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AeSoftLimits {
+    /* Fd slots in use (the highest registered fd plus one), in percent of
+     * the setsize. */
+    pub fd_pct: u32,
+    /* Pending timers. */
+    pub timers: usize,
+}
+
+/* What a soft limit is about. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeSoftLimitResource {
+    Fds,
+    Timers,
+}
+
+#[derive(Default)]
+pub(crate) struct SoftLimits {
+    limits: AeSoftLimits,
+    /* The limit was reached and raised, not to be raised again until the
+     * usage goes back under it. */
+    fds_raised: bool,
+    timers_raised: bool,
+}
+
+/* Raise a finding when a registration reaches one of `limits`. Returns
+ * AE_ERR if `fd_pct` is over 100. */
+pub fn ae_set_soft_limits(event_loop: &mut AeEventLoop, limits: AeSoftLimits) -> i32 {
+    if limits.fd_pct > 100 {
+        return AE_ERR;
+    }
+    event_loop.soft_limits = SoftLimits {
+        limits,
+        ..SoftLimits::default()
+    };
+    AE_OK
+}
+
+pub fn ae_get_soft_limits(event_loop: &AeEventLoop) -> AeSoftLimits {
+    event_loop.soft_limits.limits
+}
+
+/* Usage and threshold of each limit set. */
+fn usage(event_loop: &AeEventLoop, resource: AeSoftLimitResource) -> Option<(usize, usize)> {
+    let limits = &event_loop.soft_limits.limits;
+    match resource {
+        AeSoftLimitResource::Fds if limits.fd_pct > 0 => {
+            let setsize = ae_get_set_size(event_loop).max(0) as usize;
+            let limit = (setsize * limits.fd_pct as usize).div_ceil(100);
+            Some(((event_loop.maxfd + 1) as usize, limit))
+        }
+        AeSoftLimitResource::Timers if limits.timers > 0 => {
+            Some((ae_pending_time_events(event_loop), limits.timers))
+        }
+        _ => None,
+    }
+}
+
+/* An fd was registered. */
+pub(crate) fn check_fds(event_loop: &mut AeEventLoop) {
+    if event_loop.soft_limits.limits.fd_pct != 0 {
+        check(event_loop, AeSoftLimitResource::Fds);
+    }
+}
+
+/* A timer was created. */
+pub(crate) fn check_timers(event_loop: &mut AeEventLoop) {
+    if event_loop.soft_limits.limits.timers != 0 {
+        check(event_loop, AeSoftLimitResource::Timers);
+    }
+}
+
+fn check(event_loop: &mut AeEventLoop, resource: AeSoftLimitResource) {
+    let Some((used, limit)) = usage(event_loop, resource) else {
+        return;
+    };
+    let raised = match resource {
+        AeSoftLimitResource::Fds => &mut event_loop.soft_limits.fds_raised,
+        AeSoftLimitResource::Timers => &mut event_loop.soft_limits.timers_raised,
+    };
+    if used < limit {
+        *raised = false;
+        return;
+    }
+    if std::mem::replace(raised, true) {
+        return;
+    }
+    let finding = finding(event_loop, resource, used, limit);
+    doctor::raise(event_loop, finding);
+}
+
+/* The limits reached, for ae_doctor(). */
+pub(crate) fn findings(event_loop: &AeEventLoop) -> Vec<AeFinding> {
+    [AeSoftLimitResource::Fds, AeSoftLimitResource::Timers]
+        .into_iter()
+        .filter_map(|resource| {
+            let (used, limit) = usage(event_loop, resource)?;
+            (used >= limit).then(|| finding(event_loop, resource, used, limit))
+        })
+        .collect()
+}
+
+fn finding(
+    event_loop: &AeEventLoop,
+    resource: AeSoftLimitResource,
+    used: usize,
+    limit: usize,
+) -> AeFinding {
+    let message = match resource {
+        AeSoftLimitResource::Fds => format!(
+            "Fds up to {} are registered, the soft limit is {limit} fds out of a setsize of \
+             {}: fds past the setsize cannot be registered, shed connections or resize the \
+             loop",
+            used - 1,
+            ae_get_set_size(event_loop)
+        ),
+        AeSoftLimitResource::Timers => format!(
+            "{used} timers are pending, the soft limit is {limit}: every iteration walks \
+             them, look for timers that are never deleted"
+        ),
+    };
+    AeFinding {
+        severity: AeFindingSeverity::Warning,
+        kind: AeFindingKind::SoftLimit {
+            resource,
+            used,
+            limit,
+        },
+        message,
+    }
+}
@@ -0,0 +1,142 @@
+/* Memory accounting.
+ *
+ * Redis routes every allocation through zmalloc so that INFO can report
+ * used_memory. The loop structures here (event table, fired buffer, timer
+ * nodes, connection buffers) are std collections, which on stable Rust
+ * can only allocate from the global allocator, so that is where the hook
+ * goes: AeCountingAlloc wraps the allocator a server installs with
+ * #[global_allocator] (System, jemalloc, mimalloc, ...) and keeps the
+ * totals, and ae_memory_usage() tells how much of it a loop holds.
+ *
+ *     #[global_allocator]
+ *     static ALLOC: AeCountingAlloc<System> = AeCountingAlloc::new(System);
+ */
+
+use crate::ae::{AeEventLoop, AeFileEvent, DispatchEntry, TimeEventNode};
+use crate::ae_select::FiredEvent;
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/* A global allocator forwarding to `A` and counting what goes through
+ * it. */
+pub struct AeCountingAlloc<A> {
+    inner: A,
+    used: AtomicUsize,
+    peak: AtomicUsize,
+    allocations: AtomicU64,
+}
+
+impl<A> AeCountingAlloc<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            used: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            allocations: AtomicU64::new(0),
+        }
+    }
+
+    /* Bytes currently allocated, like zmalloc_used_memory(). */
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /* Highest value used() reached. */
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /* Allocations made so far, reallocations included. */
+    pub fn allocations(&self) -> u64 {
+        self.allocations.load(Ordering::Relaxed)
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    fn add(&self, size: usize) {
+        let used = self.used.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak.fetch_max(used, Ordering::Relaxed);
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn sub(&self, size: usize) {
+        self.used.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for AeCountingAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() {
+            self.add(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            self.add(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) };
+        self.sub(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            self.sub(layout.size());
+            self.add(new_size);
+        }
+        new_ptr
+    }
+}
+
+/* Heap memory held by a loop, in bytes, see ae_memory_usage(). */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AeMemoryUsage {
+    /* Per-fd registration table. */
+    pub file_events: usize,
+    /* Buffers the backend fills on each poll. */
+    pub fired: usize,
+    /* Timer nodes, pending and not yet freed. */
+    pub timers: usize,
+    /* Buffered connections: state, input and queued output. */
+    pub connections: usize,
+    /* Free buffers of the read buffer pool. */
+    pub buffer_pool: usize,
+    pub total: usize,
+}
+
+/* What the loop structures take right now. Client data, the backend's
+ * own state and the kernel side are not included. */
+pub fn ae_memory_usage(event_loop: &AeEventLoop) -> AeMemoryUsage {
+    let file_events = event_loop.events.capacity() * std::mem::size_of::<AeFileEvent>();
+    let fired = event_loop.fired.capacity() * std::mem::size_of::<FiredEvent>()
+        + (event_loop.dispatch_list.capacity() + event_loop.injected.capacity())
+            * std::mem::size_of::<DispatchEntry>();
+
+    let mut timers = 0;
+    let mut node = event_loop.time_event_head.as_deref();
+    while let Some(current) = node {
+        timers += std::mem::size_of::<TimeEventNode>();
+        node = current.next.as_deref();
+    }
+
+    let connections = event_loop.conn_memory;
+    let buffer_pool = event_loop.buf_pool.cached_bytes();
+    AeMemoryUsage {
+        file_events,
+        fired,
+        timers,
+        connections,
+        buffer_pool,
+        total: file_events + fired + timers + connections + buffer_pool,
+    }
+}
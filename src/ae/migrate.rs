@@ -0,0 +1,172 @@
+/* Moving a connection to another loop.
+ *
+ * With one loop per core, the kernel spreads connections when they are
+ * accepted and never again: a few long lived, busy connections can end up
+ * on the same loop while the others idle. ae_conn_migrate() rebalances by
+ * moving a connection, from its own loop, to the loop of an AeHandle.
+ *
+ * The source stops reading and flushes the queued output, as for
+ * ae_conn_handover(), then forgets the connection and posts it to the
+ * target: the fd, the input not consumed yet, the procs, client data and
+ * counters, and the timers of the session (a timer group, see
+ * ae_export_timers()). The target registers it again, imports the timers
+ * and calls the arrived proc with their new ids before the read proc sees
+ * the input. Everything runs on the loop owning the connection at the
+ * time, so the procs never run on two threads at once.
+ *
+ * What the loop keeps about the fd rather than the connection does not
+ * move: keepalive probes, idle timeouts and write backoff are dropped, the
+ * arrived proc may set them again. A connection closed before its output
+ * is flushed (peer hangup, write error) is closed on the source as usual.
+ * If the target is deleted before the connection gets there, it lands
+ * back on the source, or is closed if the source is gone too.
+ */
+
+use crate::ae::conn::{self, AeConnHalf, ConnProcs, ae_conn_half_state};
+use crate::ae::handle::{AeHandle, ae_get_handle};
+use crate::ae::timer_group::{AeExportedTimer, ae_export_timers, ae_import_timers};
+use crate::ae::{AeEventLoop, ae_delete_time_event};
+use crate::anet::{errno, set_errno};
+use crate::constants::AE_ERR;
+use crate::traits::ConnMigratedProc;
+
+/* A connection on its way, all of it owned by the loop running it. */
+struct Arrival {
+    fd: i32,
+    input: Vec<u8>,
+    procs: ConnProcs,
+    timers: Vec<AeExportedTimer>,
+    arrived: Option<ConnMigratedProc>,
+}
+
+/* Only the loop it lands on touches the client data, as with the tasks
+ * posted to a loop. */
+unsafe impl Send for Arrival {}
+
+/* The task posted to the target. Dropped without running, because the
+ * target was deleted first, it sends the connection back home. */
+struct InFlight {
+    arrival: Option<Arrival>,
+    home: Option<AeHandle>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let Some(arrival) = self.arrival.take() else {
+            return;
+        };
+        match &self.home {
+            Some(home) => {
+                home.post(move |event_loop| arrival.land(event_loop));
+            }
+            None => drop(arrival),
+        }
+    }
+}
+
+/* Nowhere left to go: at least the fd does not leak. */
+impl Drop for Arrival {
+    fn drop(&mut self) {
+        if self.fd != -1 {
+            unsafe { libc::close(self.fd) };
+        }
+    }
+}
+
+/* Move the connection `fd` to the loop of `target`, along with the timers
+ * of `timer_group` (0 for none). Once its queued output is written, the
+ * connection is gone from this loop, without its close proc being called,
+ * and is a connection of the target, where `arrived` is called first. From
+ * the read proc, the migration starts once it returns. Returns AE_ERR,
+ * leaving the connection where it is, if `fd` is not an open connection
+ * (see ae_conn_half_state()), is closing or being handed over, or if
+ * `target` is this loop (errno EINVAL) or deleted (errno ESHUTDOWN). */
+pub fn ae_conn_migrate(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    target: &AeHandle,
+    timer_group: u64,
+    arrived: Option<ConnMigratedProc>,
+) -> i32 {
+    if ae_conn_half_state(event_loop, fd) != Some(AeConnHalf::Open) {
+        return AE_ERR;
+    }
+    if target.is_closed() {
+        set_errno(libc::ESHUTDOWN);
+        return AE_ERR;
+    }
+    match ae_get_handle(event_loop) {
+        Some(own) if !own.same_loop(target) => {}
+        _ => {
+            set_errno(libc::EINVAL);
+            return AE_ERR;
+        }
+    }
+
+    let target = target.clone();
+    conn::migrate_out(
+        event_loop,
+        fd,
+        Box::new(move |event_loop, fd, input, procs| {
+            depart(event_loop, fd, input, procs, &target, timer_group, arrived)
+        }),
+    )
+}
+
+/* The connection is off the source loop: post it to the target. */
+fn depart(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    input: Vec<u8>,
+    procs: ConnProcs,
+    target: &AeHandle,
+    timer_group: u64,
+    arrived: Option<ConnMigratedProc>,
+) {
+    /* A group timer running right now stays, with the rest of its group:
+     * it cannot be taken out of its own callback. */
+    let timers = ae_export_timers(event_loop, timer_group).unwrap_or_default();
+    let mut in_flight = InFlight {
+        arrival: Some(Arrival {
+            fd,
+            input,
+            procs,
+            timers,
+            arrived,
+        }),
+        home: ae_get_handle(event_loop),
+    };
+    /* Should the post fail, the task is dropped right away and lands
+     * back here. */
+    target.post(move |event_loop| {
+        if let Some(arrival) = in_flight.arrival.take() {
+            arrival.land(event_loop);
+        }
+    });
+}
+
+impl Arrival {
+    fn land(mut self, event_loop: &mut AeEventLoop) {
+        let fd = std::mem::replace(&mut self.fd, -1);
+        let procs = self.procs;
+        let timers = std::mem::take(&mut self.timers);
+        let ids = ae_import_timers(event_loop, &timers);
+        if conn::adopt(event_loop, fd, &procs, std::mem::take(&mut self.input)) == AE_ERR {
+            /* Closed here as it would have been at home. */
+            let err = errno();
+            for id in ids {
+                ae_delete_time_event(event_loop, id);
+            }
+            unsafe { libc::close(fd) };
+            if let Some(close_proc) = procs.close_proc {
+                close_proc(event_loop, fd, err, procs.client_data);
+            }
+            return;
+        }
+        if let Some(arrived) = self.arrived {
+            let moved: Vec<(i64, i64)> = timers.iter().map(|t| t.id).zip(ids).collect();
+            arrived(event_loop, fd, &moved, procs.client_data);
+        }
+        conn::resume(event_loop, fd);
+    }
+}
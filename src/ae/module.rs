@@ -0,0 +1,47 @@
+/* Process-wide module registry.
+ *
+ * Modules registered here are attached to every loop created afterwards
+ * (loops that already exist are not affected), which lets observability
+ * and policy layers hook the loop without touching every call site.
+ */
+
+use crate::ae::AeEventLoop;
+use crate::traits::AeModule;
+use std::sync::{Arc, Mutex};
+
+static MODULES: Mutex<Vec<Arc<dyn AeModule>>> = Mutex::new(Vec::new());
+
+/* Register a module for all loops created from now on. */
+pub fn ae_register_module(module: Box<dyn AeModule>) {
+    MODULES.lock().unwrap().push(Arc::from(module));
+}
+
+/* Names of the registered modules, in registration order. */
+pub fn ae_registered_modules() -> Vec<String> {
+    MODULES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|m| m.name().to_string())
+        .collect()
+}
+
+/* Snapshot the registered modules into a freshly created loop and run
+ * their on_loop_create hooks. */
+pub(crate) fn attach_modules(mut event_loop: Box<AeEventLoop>) -> Box<AeEventLoop> {
+    event_loop.modules = MODULES.lock().unwrap().clone();
+    run_hooks(&mut event_loop, |m, el| m.on_loop_create(el));
+    event_loop
+}
+
+/* Invoke `hook` on every module attached to the loop. */
+#[inline]
+pub(crate) fn run_hooks(
+    event_loop: &mut AeEventLoop,
+    hook: impl Fn(&dyn AeModule, &mut AeEventLoop),
+) {
+    for i in 0..event_loop.modules.len() {
+        let module = event_loop.modules[i].clone();
+        hook(module.as_ref(), event_loop);
+    }
+}
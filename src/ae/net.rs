@@ -0,0 +1,204 @@
+/* Loop-aware networking helpers.
+ *
+ * These wrap the multi-step socket state machines (non-blocking connect,
+ * accept at the fd limit, ...) that every user of the loop otherwise re-implements on top of raw
+ * file and time events.
+ */
+
+use crate::ae::builder::open_reserved_fd;
+use crate::ae::{
+    AeEventLoop, ae_create_file_event, ae_delete_file_event, ae_delete_time_event,
+    create_time_event_us, duration_us, ms_duration,
+};
+use crate::anet::{anet_accept, anet_get_socket_error, anet_tcp_nonblock_connect};
+use crate::constants::{AE_ERR, AE_NOMORE, AE_WRITABLE};
+use crate::traits::{ConnectProc, PeerFilterProc};
+use std::ffi::c_void;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/* State shared by the writable handler and the timeout timer of a pending
+ * connect. Whichever fires first tears down the other and frees it. */
+struct ConnectState {
+    fd: i32,
+    timer_id: i64,
+    on_connect: ConnectProc,
+    client_data: *mut c_void,
+}
+
+/* Start a non-blocking TCP connect to `addr` and call `on_connect` once it
+ * completes, fails, or `timeout_ms` expires (a timeout <= 0 disables it).
+ *
+ * Returns the fd of the connecting socket, or AE_ERR if the connect could
+ * not even be started. The fd is owned by the callback on success; on
+ * failure it is closed once the callback returns. */
+pub fn ae_tcp_connect(
+    event_loop: &mut AeEventLoop,
+    addr: SocketAddr,
+    timeout_ms: i64,
+    on_connect: ConnectProc,
+    client_data: *mut c_void,
+) -> i32 {
+    ae_tcp_connect_duration(
+        event_loop,
+        addr,
+        ms_duration(timeout_ms),
+        on_connect,
+        client_data,
+    )
+}
+
+/* ae_tcp_connect() taking the timeout as a Duration, Duration::ZERO
+ * for none. */
+pub fn ae_tcp_connect_duration(
+    event_loop: &mut AeEventLoop,
+    addr: SocketAddr,
+    timeout: Duration,
+    on_connect: ConnectProc,
+    client_data: *mut c_void,
+) -> i32 {
+    let fd = match anet_tcp_nonblock_connect(&addr) {
+        Ok(fd) => fd,
+        Err(_) => return AE_ERR,
+    };
+
+    let state = Box::into_raw(Box::new(ConnectState {
+        fd,
+        timer_id: -1,
+        on_connect,
+        client_data,
+    }));
+
+    /* Completion (successful or not) is signalled by the socket becoming
+     * writable, even if connect() already succeeded synchronously. */
+    if ae_create_file_event(
+        event_loop,
+        fd,
+        AE_WRITABLE,
+        connect_writable_handler,
+        state as *mut c_void,
+    ) == AE_ERR
+    {
+        drop(unsafe { Box::from_raw(state) });
+        unsafe { libc::close(fd) };
+        return AE_ERR;
+    }
+
+    let timeout_us = duration_us(timeout);
+    if timeout_us > 0 {
+        let timer_id = create_time_event_us(
+            event_loop,
+            timeout_us,
+            connect_timeout_handler,
+            state as *mut c_void,
+            None,
+        );
+        unsafe { (*state).timer_id = timer_id };
+    }
+
+    fd
+}
+
+fn connect_writable_handler(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    client_data: *mut c_void,
+    _mask: i32,
+) {
+    let state = unsafe { Box::from_raw(client_data as *mut ConnectState) };
+    ae_delete_file_event(event_loop, fd, AE_WRITABLE);
+    if state.timer_id != -1 {
+        ae_delete_time_event(event_loop, state.timer_id);
+    }
+
+    let err = anet_get_socket_error(fd);
+    finish_connect(event_loop, *state, err);
+}
+
+fn connect_timeout_handler(
+    event_loop: &mut AeEventLoop,
+    _id: i64,
+    client_data: *mut c_void,
+) -> i32 {
+    let state = unsafe { Box::from_raw(client_data as *mut ConnectState) };
+    ae_delete_file_event(event_loop, state.fd, AE_WRITABLE);
+    finish_connect(event_loop, *state, libc::ETIMEDOUT);
+    AE_NOMORE
+}
+
+fn finish_connect(event_loop: &mut AeEventLoop, state: ConnectState, err: i32) {
+    (state.on_connect)(event_loop, state.fd, err, state.client_data);
+    if err != 0 {
+        unsafe { libc::close(state.fd) };
+    }
+}
+
+/* Accept a connection on `listen_fd`, like anet_accept().
+ *
+ * Connections the peer filter refuses (see ae_set_peer_filter()) are
+ * closed and the next one is accepted instead: Err(EAGAIN) means none
+ * is left that the filter let through.
+ *
+ * When the process is out of fds (EMFILE/ENFILE) and the loop has reserved
+ * fds (see AeEventLoopBuilder::reserved_fds()), one of them is closed to
+ * make room, the pending client is accepted, sent `reject_msg` and closed
+ * right away, and the reserved fd is opened again. Otherwise the client
+ * would sit in the backlog and the listening socket would keep firing
+ * readable events. The original errno is still returned in that case,
+ * and ae_backoff() keeps the listener out of the poll for a while. */
+pub fn ae_accept(
+    event_loop: &mut AeEventLoop,
+    listen_fd: i32,
+    reject_msg: &[u8],
+) -> Result<(i32, Option<SocketAddr>), i32> {
+    let err = loop {
+        match anet_accept(listen_fd) {
+            Ok((cfd, addr)) => {
+                if peer_allowed(event_loop, listen_fd, addr) {
+                    return Ok((cfd, addr));
+                }
+                unsafe { libc::close(cfd) };
+            }
+            Err(err) => break err,
+        }
+    };
+    if err != libc::EMFILE && err != libc::ENFILE {
+        return Err(err);
+    }
+
+    if let Some(spare) = event_loop.reserved_fds.pop() {
+        unsafe { libc::close(spare) };
+        if let Ok((cfd, _)) = anet_accept(listen_fd) {
+            /* Best effort: the client may not even read it. */
+            unsafe {
+                libc::write(cfd, reject_msg.as_ptr() as *const c_void, reject_msg.len());
+                libc::close(cfd);
+            }
+        }
+        if let Some(fd) = open_reserved_fd() {
+            event_loop.reserved_fds.push(fd);
+        }
+    }
+    Err(err)
+}
+
+/* Have `filter` decide, for every connection ae_accept() accepts on this
+ * loop, whether it is kept, before it is handed to the caller and
+ * registered anywhere: allowlists, or turning away a flood of clients at
+ * the cost of one accept() and close() each. None (the default) keeps
+ * them all. Refused connections are counted in
+ * AeStats::peers_filtered. */
+pub fn ae_set_peer_filter(event_loop: &mut AeEventLoop, filter: Option<PeerFilterProc>) {
+    event_loop.peer_filter = filter;
+}
+
+fn peer_allowed(event_loop: &mut AeEventLoop, listen_fd: i32, addr: Option<SocketAddr>) -> bool {
+    let Some(filter) = event_loop.peer_filter else {
+        return true;
+    };
+    if filter(event_loop, listen_fd, addr) {
+        return true;
+    }
+    event_loop.stats.stats.peers_filtered += 1;
+    false
+}
@@ -0,0 +1,284 @@
+/* Netlink sockets (Linux).
+ *
+ * A daemon reacting to interface and address changes listens on a
+ * NETLINK_ROUTE socket bound to the RTMGRP_* groups it cares about, and
+ * one watching devices on a NETLINK_KOBJECT_UEVENT socket. Both are datagram
+ * sockets, but a route datagram packs several messages, each with its own
+ * nlmsghdr, and the kernel drops datagrams when the socket buffer is full.
+ *
+ * ae_netlink_register() watches such a socket (see anet_netlink_socket())
+ * and calls the proc once per message: a route datagram is split at its
+ * headers, a uevent datagram (no header, see ae_uevent_parse()) is one
+ * message. A datagram is never cut: its size is peeked first and the
+ * buffer grown to fit. Dropped datagrams are reported as an NLMSG_OVERRUN
+ * message, after which a daemon dumps the state again to resync.
+ */
+
+use crate::ae::{AeEventLoop, ae_create_file_event, ae_delete_file_event, ae_get_file_client_data};
+use crate::anet::errno;
+use crate::constants::{AE_ERR, AE_OK, AE_READABLE};
+use crate::traits::NetlinkProc;
+use std::ffi::c_void;
+
+/* Datagrams read per readable event. */
+pub const AE_NETLINK_MAX_READS: usize = 16;
+
+/* Initial receive buffer, what the kernel recommends for route dumps. */
+const NETLINK_BUF_SIZE: usize = 32 * 1024;
+
+const NLMSG_HDRLEN: usize = std::mem::size_of::<libc::nlmsghdr>();
+
+/* A message handed to a NetlinkProc, borrowed from the receive buffer. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AeNetlinkMessage<'a> {
+    /* From the nlmsghdr, e.g. RTM_NEWLINK or NLMSG_DONE; all 0 for a
+     * uevent. */
+    pub msg_type: u16,
+    pub flags: u16,
+    pub seq: u32,
+    pub pid: u32,
+    /* What follows the header, or the whole uevent datagram. */
+    pub payload: &'a [u8],
+}
+
+/* A kernel uevent, see ae_uevent_parse(). */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AeUevent<'a> {
+    /* "add", "remove", "change", "bind", ... */
+    pub action: &'a str,
+    pub devpath: &'a str,
+    /* KEY=VALUE pairs, in order, ACTION and DEVPATH included. */
+    pub vars: Vec<(&'a str, &'a str)>,
+}
+
+struct NetlinkState {
+    proc: NetlinkProc,
+    client_data: *mut c_void,
+    /* Datagrams are uevents, not nlmsghdr framed. */
+    uevent: bool,
+    buf: Vec<u8>,
+    in_proc: bool,
+    unregistered: bool,
+}
+
+/* Watch the netlink socket `fd` and call `proc` with each message it
+ * receives. The fd stays owned by the caller. Returns AE_ERR if `fd` is
+ * not a netlink socket or cannot be registered. */
+pub fn ae_netlink_register(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    proc: NetlinkProc,
+    client_data: *mut c_void,
+) -> i32 {
+    if socket_option(fd, libc::SO_DOMAIN) != Some(libc::AF_NETLINK) {
+        return AE_ERR;
+    }
+    let protocol = socket_option(fd, libc::SO_PROTOCOL);
+    let state = Box::into_raw(Box::new(NetlinkState {
+        proc,
+        client_data,
+        uevent: protocol == Some(libc::NETLINK_KOBJECT_UEVENT),
+        buf: vec![0; NETLINK_BUF_SIZE],
+        in_proc: false,
+        unregistered: false,
+    }));
+    if ae_create_file_event(
+        event_loop,
+        fd,
+        AE_READABLE,
+        netlink_readable_handler,
+        state as *mut c_void,
+    ) == AE_ERR
+    {
+        drop(unsafe { Box::from_raw(state) });
+        return AE_ERR;
+    }
+    AE_OK
+}
+
+/* Stop watching the netlink socket `fd`, which is not closed. From the
+ * proc it takes effect once the proc returns. Returns AE_ERR if `fd` was
+ * not registered with ae_netlink_register(). */
+pub fn ae_netlink_unregister(event_loop: &mut AeEventLoop, fd: i32) -> i32 {
+    let client_data = ae_get_file_client_data(event_loop, fd);
+    if client_data.is_null() {
+        return AE_ERR;
+    }
+    let state = client_data as *mut NetlinkState;
+    ae_delete_file_event(event_loop, fd, AE_READABLE);
+    unsafe {
+        if (*state).in_proc {
+            (*state).unregistered = true;
+        } else {
+            drop(Box::from_raw(state));
+        }
+    }
+    AE_OK
+}
+
+/* Split a uevent datagram, "ACTION@DEVPATH" followed by NUL separated
+ * KEY=VALUE pairs. None for anything else, such as the messages udevd
+ * multicasts with a "libudev" header. */
+pub fn ae_uevent_parse(payload: &[u8]) -> Option<AeUevent<'_>> {
+    let mut fields = payload
+        .split(|&byte| byte == 0)
+        .filter(|field| !field.is_empty());
+    let head = std::str::from_utf8(fields.next()?).ok()?;
+    let (action, devpath) = head.split_once('@')?;
+    let vars = fields
+        .filter_map(|field| std::str::from_utf8(field).ok()?.split_once('='))
+        .collect();
+    Some(AeUevent {
+        action,
+        devpath,
+        vars,
+    })
+}
+
+fn netlink_readable_handler(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    client_data: *mut c_void,
+    _mask: i32,
+) {
+    let state = client_data as *mut NetlinkState;
+    for _ in 0..AE_NETLINK_MAX_READS {
+        let len = match receive(fd, unsafe { &mut (*state).buf }) {
+            Ok(len) => len,
+            Err(libc::ENOBUFS) => {
+                let overrun = AeNetlinkMessage {
+                    msg_type: libc::NLMSG_OVERRUN as u16,
+                    flags: 0,
+                    seq: 0,
+                    pid: 0,
+                    payload: &[],
+                };
+                if !deliver(event_loop, fd, state, &[overrun]) {
+                    return;
+                }
+                continue;
+            }
+            /* EAGAIN, or the socket is broken and stays readable: the
+             * owner finds out on its next request. */
+            Err(_) => return,
+        };
+        let buf = unsafe { &(&(*state).buf)[..len] };
+        let messages = if unsafe { (*state).uevent } {
+            vec![AeNetlinkMessage {
+                msg_type: 0,
+                flags: 0,
+                seq: 0,
+                pid: 0,
+                payload: buf,
+            }]
+        } else {
+            split(buf)
+        };
+        if !deliver(event_loop, fd, state, &messages) {
+            return;
+        }
+    }
+}
+
+/* Call the proc with each message. False once it unregistered. */
+fn deliver(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    state: *mut NetlinkState,
+    messages: &[AeNetlinkMessage<'_>],
+) -> bool {
+    let (proc, client_data) = unsafe { ((*state).proc, (*state).client_data) };
+    unsafe { (*state).in_proc = true };
+    for message in messages {
+        proc(event_loop, fd, message, client_data);
+        if unsafe { (*state).unregistered } {
+            break;
+        }
+    }
+    unsafe {
+        (*state).in_proc = false;
+        if (*state).unregistered {
+            drop(Box::from_raw(state));
+            return false;
+        }
+    }
+    true
+}
+
+fn socket_option(fd: i32, name: libc::c_int) -> Option<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let retval = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            name,
+            &mut value as *mut libc::c_int as *mut c_void,
+            &mut len,
+        )
+    };
+    (retval == 0).then_some(value)
+}
+
+/* Read one whole datagram, growing `buf` to its size first. */
+fn receive(fd: i32, buf: &mut Vec<u8>) -> Result<usize, i32> {
+    loop {
+        let len = unsafe {
+            libc::recv(
+                fd,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len(),
+                libc::MSG_PEEK | libc::MSG_TRUNC | libc::MSG_DONTWAIT,
+            )
+        };
+        if len < 0 {
+            let err = errno();
+            if err == libc::EINTR {
+                continue;
+            }
+            return Err(err);
+        }
+        if len as usize > buf.len() {
+            buf.resize(len as usize, 0);
+        }
+        let len = unsafe {
+            libc::recv(
+                fd,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len(),
+                libc::MSG_DONTWAIT,
+            )
+        };
+        if len < 0 {
+            let err = errno();
+            if err == libc::EINTR {
+                continue;
+            }
+            return Err(err);
+        }
+        return Ok(len as usize);
+    }
+}
+
+/* The nlmsghdr framed messages of a datagram, up to the first malformed
+ * header. */
+fn split(mut buf: &[u8]) -> Vec<AeNetlinkMessage<'_>> {
+    let mut messages = Vec::new();
+    while buf.len() >= NLMSG_HDRLEN {
+        let header = unsafe { std::ptr::read_unaligned(buf.as_ptr() as *const libc::nlmsghdr) };
+        let len = header.nlmsg_len as usize;
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            break;
+        }
+        messages.push(AeNetlinkMessage {
+            msg_type: header.nlmsg_type,
+            flags: header.nlmsg_flags,
+            seq: header.nlmsg_seq,
+            pid: header.nlmsg_pid,
+            payload: &buf[NLMSG_HDRLEN..len],
+        });
+        /* Messages are 4 byte aligned (NLMSG_ALIGN). */
+        buf = &buf[len.next_multiple_of(4).min(buf.len())..];
+    }
+    messages
+}
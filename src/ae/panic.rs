@@ -0,0 +1,119 @@
+/* Callback panics.
+ *
+ * By default a panic in a callback unwinds through ae_process_events()
+ * like any other panic. With a crash reporter or a panic policy set, the
+ * loop catches it first: the reporter is called with the dispatch stack
+ * (see ae_dispatch_stack()) and the panic message, so the crash report
+ * says which fd or timer was being served, then the policy decides
+ * whether to go on unwinding, abort the process, or drop the offending
+ * event and carry on.
+ */
+
+use crate::ae::AeEventLoop;
+use crate::ae::context::{self, AeDispatchRecord};
+use crate::ae::flight;
+use crate::ae::scope;
+use crate::traits::CrashReportProc;
+use std::any::Any;
+use std::panic::{AssertUnwindSafe, catch_unwind, resume_unwind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AePanicPolicy {
+    /* Resume unwinding out of ae_process_events(). */
+    #[default]
+    Propagate,
+    /* Abort the process, e.g. to get a core dump. */
+    Abort,
+    /* Drop the event that panicked and go on with the iteration: a timer
+     * is deleted, the fd is no longer watched. */
+    Continue,
+}
+
+/* What was going on when a callback panicked. */
+#[derive(Debug, Clone)]
+pub struct AeCrashReport {
+    /* The dispatch stack at the time of the panic, outermost first. The
+     * last record is the callback that panicked. */
+    pub stack: Vec<AeDispatchRecord>,
+    /* The panic message, when it is a string. */
+    pub message: Option<String>,
+    pub policy: AePanicPolicy,
+    /* See AeEventLoopBuilder::name(). */
+    pub loop_name: Option<String>,
+}
+
+#[derive(Default)]
+pub(crate) struct PanicState {
+    policy: AePanicPolicy,
+    reporter: Option<CrashReportProc>,
+}
+
+pub fn ae_set_panic_policy(event_loop: &mut AeEventLoop, policy: AePanicPolicy) {
+    event_loop.panic.policy = policy;
+}
+
+pub fn ae_get_panic_policy(event_loop: &AeEventLoop) -> AePanicPolicy {
+    event_loop.panic.policy
+}
+
+/* Call `reporter` when a callback panics, before the panic policy is
+ * applied. A panic in the reporter itself is not caught. */
+pub fn ae_set_crash_reporter(event_loop: &mut AeEventLoop, reporter: Option<CrashReportProc>) {
+    event_loop.panic.reporter = reporter;
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> Option<String> {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        Some(s.to_string())
+    } else {
+        payload.downcast_ref::<String>().cloned()
+    }
+}
+
+/* Run a callback of the dispatch on top of the stack. Returns None if it
+ * panicked and the policy is to carry on. Scopes the callback dropped are
+ * torn down before the next one runs, see scope.rs. */
+pub(crate) fn guard<R>(
+    event_loop: &mut AeEventLoop,
+    callback: impl FnOnce(&mut AeEventLoop) -> R,
+) -> Option<R> {
+    let state = &event_loop.panic;
+    if state.reporter.is_none() && state.policy == AePanicPolicy::Propagate {
+        let result = callback(event_loop);
+        scope::reap(event_loop);
+        return Some(result);
+    }
+
+    let depth = context::depth(event_loop);
+    let payload = match catch_unwind(AssertUnwindSafe(|| callback(&mut *event_loop))) {
+        Ok(result) => {
+            scope::reap(event_loop);
+            return Some(result);
+        }
+        Err(payload) => payload,
+    };
+    /* Nested dispatches the panic unwound through left their records. */
+    context::truncate(event_loop, depth);
+    let label = context::ae_current_dispatch(event_loop).and_then(|record| record.label);
+    flight::panicked(event_loop, label);
+    event_loop.stats.stats.callback_panics += 1;
+
+    let policy = event_loop.panic.policy;
+    if let Some(reporter) = event_loop.panic.reporter {
+        let report = AeCrashReport {
+            stack: context::ae_dispatch_stack(event_loop),
+            message: panic_message(&*payload),
+            policy,
+            loop_name: event_loop.name.clone(),
+        };
+        reporter(event_loop, &report);
+    }
+    match policy {
+        AePanicPolicy::Propagate => resume_unwind(payload),
+        AePanicPolicy::Abort => std::process::abort(),
+        AePanicPolicy::Continue => {
+            scope::reap(event_loop);
+            None
+        }
+    }
+}
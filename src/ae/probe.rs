@@ -0,0 +1,158 @@
+/* Kernel feature probing.
+ *
+ * What the running kernel supports is not known at build time: a binary
+ * built with epoll and timerfd support may run in a container whose
+ * seccomp profile rejects io_uring, or on a kernel too old for pidfds.
+ * ae_kernel_features() tries each facility once (create the object, close
+ * it) and caches the answers for the lifetime of the process, so the
+ * builder and subsystems can pick an implementation without probing
+ * again.
+ */
+
+use crate::ae::create_select_backend;
+use crate::traits::EventBackend;
+use std::sync::OnceLock;
+
+/* Facilities available to this process. Everything is false on systems
+ * that do not have it at all. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AeKernelFeatures {
+    pub epoll: bool,
+    /* EPOLLEXCLUSIVE (Linux 4.5): one waiter woken per event on an fd
+     * shared by several epoll instances. */
+    pub epoll_exclusive: bool,
+    pub io_uring: bool,
+    /* pidfd_open() (Linux 5.3): pollable process handles. */
+    pub pidfd: bool,
+    pub timerfd: bool,
+    pub signalfd: bool,
+    pub kqueue: bool,
+}
+
+static FEATURES: OnceLock<AeKernelFeatures> = OnceLock::new();
+
+/* Probe the kernel on first call, return the cached result after. */
+pub fn ae_kernel_features() -> AeKernelFeatures {
+    *FEATURES.get_or_init(probe)
+}
+
+/* The most capable backend the kernel offers: epoll or kqueue, select
+ * when neither works. */
+pub(crate) fn create_best_backend() -> Result<Box<dyn EventBackend>, i32> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if ae_kernel_features().epoll
+        && let Ok(backend) = crate::ae_epoll::aeApiState::create()
+    {
+        return Ok(backend);
+    }
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    ))]
+    if ae_kernel_features().kqueue
+        && let Ok(backend) = crate::ae_kqueue::aeApiState::create()
+    {
+        return Ok(backend);
+    }
+    create_select_backend()
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn probe() -> AeKernelFeatures {
+    let (epoll, epoll_exclusive) = linux::epoll();
+    AeKernelFeatures {
+        epoll,
+        epoll_exclusive,
+        io_uring: linux::io_uring(),
+        pidfd: linux::pidfd(),
+        timerfd: linux::timerfd(),
+        signalfd: linux::signalfd(),
+        kqueue: false,
+    }
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+fn probe() -> AeKernelFeatures {
+    let kqfd = unsafe { libc::kqueue() };
+    if kqfd != -1 {
+        unsafe { libc::close(kqfd) };
+    }
+    AeKernelFeatures {
+        kqueue: kqfd != -1,
+        ..Default::default()
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+)))]
+fn probe() -> AeKernelFeatures {
+    AeKernelFeatures::default()
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod linux {
+    /* Close `fd` and report whether the call that returned it worked. */
+    fn created(fd: libc::c_long) -> bool {
+        if fd < 0 {
+            return false;
+        }
+        unsafe { libc::close(fd as i32) };
+        true
+    }
+
+    /* Kernels without EPOLLEXCLUSIVE reject it with EINVAL. */
+    pub(super) fn epoll() -> (bool, bool) {
+        let epfd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if epfd == -1 {
+            return (false, false);
+        }
+        let mut fds = [-1; 2];
+        let exclusive = unsafe { libc::pipe(fds.as_mut_ptr()) } == 0 && {
+            let mut ev = libc::epoll_event {
+                events: (libc::EPOLLIN | libc::EPOLLEXCLUSIVE) as u32,
+                u64: 0,
+            };
+            let added = unsafe { libc::epoll_ctl(epfd, libc::EPOLL_CTL_ADD, fds[0], &mut ev) };
+            unsafe {
+                libc::close(fds[0]);
+                libc::close(fds[1]);
+            }
+            added == 0
+        };
+        unsafe { libc::close(epfd) };
+        (true, exclusive)
+    }
+
+    pub(super) fn io_uring() -> bool {
+        /* struct io_uring_params, all zero: default setup. */
+        let mut params = [0u8; 120];
+        created(unsafe { libc::syscall(libc::SYS_io_uring_setup, 1u32, params.as_mut_ptr()) })
+    }
+
+    pub(super) fn pidfd() -> bool {
+        created(unsafe { libc::syscall(libc::SYS_pidfd_open, libc::getpid(), 0u32) })
+    }
+
+    pub(super) fn timerfd() -> bool {
+        created(unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_CLOEXEC) } as _)
+    }
+
+    pub(super) fn signalfd() -> bool {
+        let mut mask: libc::sigset_t = unsafe { std::mem::zeroed() };
+        unsafe { libc::sigemptyset(&mut mask) };
+        created(unsafe { libc::signalfd(-1, &mask, libc::SFD_CLOEXEC) } as _)
+    }
+}
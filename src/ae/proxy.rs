@@ -0,0 +1,366 @@
+/* Byte proxying between two fds.
+ *
+ * A TCP proxy mostly moves bytes from one socket to another, and the
+ * work is in the bookkeeping: stop reading a side whose peer is not
+ * keeping up, watch the peer for writability instead, go back to reading
+ * once it drained, and pass a half close on. ae_proxy_create() does that
+ * for a pair of fds, in both directions.
+ *
+ * On Linux the bytes go through a kernel pipe per direction with
+ * splice(2) and never reach user space. A direction whose fds cannot be
+ * spliced, and every direction elsewhere, goes through a buffer with
+ * read(2) and write(2).
+ *
+ * At most AE_PROXY_CHUNK bytes are in flight per direction: a side is
+ * read again only once everything read from it was written to the other.
+ */
+
+use crate::ae::{AeEventLoop, ae_create_file_event, ae_delete_file_event, ae_get_file_events};
+use crate::anet::{anet_non_block, errno};
+use crate::constants::{AE_ALL_MASK, AE_ERR, AE_NONE, AE_OK, AE_READABLE, AE_WRITABLE};
+use crate::traits::ProxyCloseProc;
+use std::ffi::c_void;
+
+/* Bytes moved from a side per readable event. */
+pub const AE_PROXY_CHUNK: usize = 64 * 1024;
+
+/* Traffic of a proxy, see ae_proxy_stats(). */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AeProxyStats {
+    /* The fds as given to ae_proxy_create(). */
+    pub a: i32,
+    pub b: i32,
+    pub a_to_b: u64,
+    pub b_to_a: u64,
+    /* Both directions go through splice(2). */
+    pub zero_copy: bool,
+}
+
+/* One direction: bytes read from `from`, waiting to be written to `to`. */
+struct Flow {
+    from: i32,
+    to: i32,
+    /* Kernel pipe the bytes are spliced through, and how many it holds.
+     * None for the read/write path, with the bytes in `buf`. */
+    pipe: Option<(i32, i32)>,
+    in_pipe: usize,
+    buf: Vec<u8>,
+    /* `from` reached end of file, and `to` was shut down for writing
+     * once everything before it was written. */
+    eof: bool,
+    shut: bool,
+    bytes: u64,
+}
+
+impl Flow {
+    fn new(from: i32, to: i32) -> Self {
+        #[cfg(target_os = "linux")]
+        let pipe = crate::anet::anet_pipe(true).ok();
+        #[cfg(not(target_os = "linux"))]
+        let pipe = None;
+        Flow {
+            from,
+            to,
+            pipe,
+            in_pipe: 0,
+            buf: Vec::new(),
+            eof: false,
+            shut: false,
+            bytes: 0,
+        }
+    }
+
+    fn pending(&self) -> usize {
+        self.in_pipe + self.buf.len()
+    }
+
+    fn wants_read(&self) -> bool {
+        self.pending() == 0 && !self.eof
+    }
+
+    /* Read up to a chunk from `from`. Ok(0) at end of file. */
+    fn fill(&mut self) -> Result<usize, i32> {
+        #[cfg(target_os = "linux")]
+        if let Some((_, write_end)) = self.pipe {
+            let nread = unsafe {
+                libc::splice(
+                    self.from,
+                    std::ptr::null_mut(),
+                    write_end,
+                    std::ptr::null_mut(),
+                    AE_PROXY_CHUNK,
+                    libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+                )
+            };
+            if nread >= 0 {
+                self.in_pipe += nread as usize;
+                return Ok(nread as usize);
+            }
+            let err = errno();
+            if err != libc::EINVAL {
+                return Err(err);
+            }
+            /* `from` cannot be spliced from, the pipe is still empty. */
+            self.drop_pipe();
+        }
+
+        self.buf.resize(AE_PROXY_CHUNK, 0);
+        let nread = unsafe {
+            libc::read(
+                self.from,
+                self.buf.as_mut_ptr() as *mut c_void,
+                AE_PROXY_CHUNK,
+            )
+        };
+        let err = errno();
+        self.buf.truncate(nread.max(0) as usize);
+        if nread < 0 {
+            Err(err)
+        } else {
+            Ok(nread as usize)
+        }
+    }
+
+    /* Write what is pending to `to`, until done or the socket is full. */
+    fn drain(&mut self) -> Result<(), i32> {
+        #[cfg(target_os = "linux")]
+        if let Some((read_end, _)) = self.pipe {
+            while self.in_pipe > 0 {
+                let written = unsafe {
+                    libc::splice(
+                        read_end,
+                        std::ptr::null_mut(),
+                        self.to,
+                        std::ptr::null_mut(),
+                        self.in_pipe,
+                        libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+                    )
+                };
+                if written < 0 {
+                    let err = errno();
+                    if err != libc::EINVAL {
+                        return Err(err);
+                    }
+                    /* `to` cannot be spliced to: move what the pipe holds
+                     * to the buffer and write it from there. */
+                    self.buf.resize(self.in_pipe, 0);
+                    let nread = unsafe {
+                        libc::read(read_end, self.buf.as_mut_ptr() as *mut c_void, self.in_pipe)
+                    };
+                    if nread != self.in_pipe as isize {
+                        return Err(libc::EIO);
+                    }
+                    self.drop_pipe();
+                    break;
+                }
+                self.in_pipe -= written as usize;
+                self.bytes += written as u64;
+            }
+        }
+
+        while !self.buf.is_empty() {
+            let written =
+                unsafe { libc::write(self.to, self.buf.as_ptr() as *const c_void, self.buf.len()) };
+            if written < 0 {
+                return Err(errno());
+            }
+            self.buf.drain(..written as usize);
+            self.bytes += written as u64;
+        }
+        Ok(())
+    }
+
+    fn drop_pipe(&mut self) {
+        if let Some((read_end, write_end)) = self.pipe.take() {
+            unsafe {
+                libc::close(read_end);
+                libc::close(write_end);
+            }
+        }
+        self.in_pipe = 0;
+    }
+}
+
+impl Drop for Flow {
+    fn drop(&mut self) {
+        self.drop_pipe();
+    }
+}
+
+pub(crate) struct ProxyState {
+    /* a to b, then b to a. */
+    flows: [Flow; 2],
+    close_proc: Option<ProxyCloseProc>,
+    client_data: *mut c_void,
+}
+
+/* Forward everything read from `a` to `b` and from `b` to `a` until both
+ * sides reached end of file, an I/O error, or ae_proxy_close(). End of
+ * file on one side shuts the other down for writing once its pending
+ * bytes are written. The proxy owns both fds from now on, makes them
+ * non-blocking and closes them before calling `close_proc`.
+ *
+ * Returns AE_ERR if the fds are the same, already have file events, or
+ * cannot be registered. */
+pub fn ae_proxy_create(
+    event_loop: &mut AeEventLoop,
+    a: i32,
+    b: i32,
+    close_proc: Option<ProxyCloseProc>,
+    client_data: *mut c_void,
+) -> i32 {
+    if a < 0 || b < 0 || a == b {
+        return AE_ERR;
+    }
+    if ae_get_file_events(event_loop, a) != AE_NONE || ae_get_file_events(event_loop, b) != AE_NONE
+    {
+        return AE_ERR;
+    }
+    if anet_non_block(a) == AE_ERR || anet_non_block(b) == AE_ERR {
+        return AE_ERR;
+    }
+    let state = Box::into_raw(Box::new(ProxyState {
+        flows: [Flow::new(a, b), Flow::new(b, a)],
+        close_proc,
+        client_data,
+    }));
+    if !arm(event_loop, state) {
+        ae_delete_file_event(event_loop, a, AE_ALL_MASK);
+        ae_delete_file_event(event_loop, b, AE_ALL_MASK);
+        drop(unsafe { Box::from_raw(state) });
+        return AE_ERR;
+    }
+    event_loop.proxies.insert(a, state);
+    event_loop.proxies.insert(b, state);
+    AE_OK
+}
+
+/* Close the proxy `fd` is a side of, both fds with it, and call its close
+ * proc with err 0. Bytes not forwarded yet are lost. Returns AE_ERR if
+ * `fd` is not part of a proxy. */
+pub fn ae_proxy_close(event_loop: &mut AeEventLoop, fd: i32) -> i32 {
+    match event_loop.proxies.get(&fd) {
+        Some(&state) => {
+            close_with(event_loop, state, 0);
+            AE_OK
+        }
+        None => AE_ERR,
+    }
+}
+
+/* Traffic of the proxy `fd` is a side of, None if it is not. */
+pub fn ae_proxy_stats(event_loop: &AeEventLoop, fd: i32) -> Option<AeProxyStats> {
+    let state = unsafe { &**event_loop.proxies.get(&fd)? };
+    let [a_to_b, b_to_a] = &state.flows;
+    Some(AeProxyStats {
+        a: a_to_b.from,
+        b: a_to_b.to,
+        a_to_b: a_to_b.bytes,
+        b_to_a: b_to_a.bytes,
+        zero_copy: a_to_b.pipe.is_some() && b_to_a.pipe.is_some(),
+    })
+}
+
+/* Called when the side `old_fd` of a proxy was moved to `new_fd`, see
+ * ae_resize_set_size_compact(). */
+pub(crate) fn relocate(event_loop: &mut AeEventLoop, old_fd: i32, new_fd: i32) {
+    if let Some(state) = event_loop.proxies.remove(&old_fd) {
+        for flow in unsafe { &mut (*state).flows } {
+            if flow.from == old_fd {
+                flow.from = new_fd;
+            }
+            if flow.to == old_fd {
+                flow.to = new_fd;
+            }
+        }
+        event_loop.proxies.insert(new_fd, state);
+    }
+}
+
+fn proxy_handler(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let state = client_data as *mut ProxyState;
+    for flow in unsafe { &mut (*state).flows } {
+        if flow.from != fd && flow.to != fd {
+            continue;
+        }
+        if let Err(err) = pump(flow) {
+            close_with(event_loop, state, err);
+            return;
+        }
+    }
+
+    if unsafe { (*state).flows.iter().all(|flow| flow.shut) } {
+        close_with(event_loop, state, 0);
+    } else if !arm(event_loop, state) {
+        close_with(event_loop, state, libc::ENOMEM);
+    }
+}
+
+/* Read once from the source of `flow` if nothing is pending, then write
+ * as much as the destination takes. */
+fn pump(flow: &mut Flow) -> Result<(), i32> {
+    if flow.wants_read() {
+        match flow.fill() {
+            Ok(0) => flow.eof = true,
+            Ok(_) | Err(libc::EAGAIN) | Err(libc::EINTR) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    match flow.drain() {
+        Ok(()) | Err(libc::EAGAIN) | Err(libc::EINTR) => {}
+        Err(err) => return Err(err),
+    }
+    if flow.eof && flow.pending() == 0 && !flow.shut {
+        /* The destination may be gone already, or not be a socket. */
+        unsafe { libc::shutdown(flow.to, libc::SHUT_WR) };
+        flow.shut = true;
+    }
+    Ok(())
+}
+
+/* Watch each side for what its flows wait for: readable while nothing
+ * read from it is pending, writable while bytes for it are. */
+fn arm(event_loop: &mut AeEventLoop, state: *mut ProxyState) -> bool {
+    let flows = unsafe { &(*state).flows };
+    for (reading, writing) in [(&flows[0], &flows[1]), (&flows[1], &flows[0])] {
+        let fd = reading.from;
+        let mut wanted = AE_NONE;
+        if reading.wants_read() {
+            wanted |= AE_READABLE;
+        }
+        if writing.pending() > 0 {
+            wanted |= AE_WRITABLE;
+        }
+        let current = ae_get_file_events(event_loop, fd) & (AE_READABLE | AE_WRITABLE);
+        if current & !wanted != AE_NONE {
+            ae_delete_file_event(event_loop, fd, current & !wanted);
+        }
+        if wanted & !current != AE_NONE
+            && ae_create_file_event(
+                event_loop,
+                fd,
+                wanted & !current,
+                proxy_handler,
+                state as *mut c_void,
+            ) == AE_ERR
+        {
+            return false;
+        }
+    }
+    true
+}
+
+fn close_with(event_loop: &mut AeEventLoop, state: *mut ProxyState, err: i32) {
+    let state = unsafe { Box::from_raw(state) };
+    let (a, b) = (state.flows[0].from, state.flows[0].to);
+    for fd in [a, b] {
+        ae_delete_file_event(event_loop, fd, AE_ALL_MASK);
+        event_loop.proxies.remove(&fd);
+        unsafe { libc::close(fd) };
+    }
+    let (close_proc, client_data) = (state.close_proc, state.client_data);
+    drop(state);
+    if let Some(close_proc) = close_proc {
+        close_proc(event_loop, a, b, err, client_data);
+    }
+}
@@ -0,0 +1,294 @@
+/* PROXY protocol.
+ *
+ * Behind a load balancer (HAProxy, ELB, ...) every connection comes from
+ * the balancer, which can tell the address of the real client in a header
+ * sent before any data: a text line in version 1
+ *
+ *     PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n
+ *
+ * and a binary block in version 2. ae_proxy_header_accept() takes that
+ * header off an accepted connection, and only then hands the connection
+ * to an AcceptProc, with the address the header carries. Nothing past the
+ * header is consumed: the application protocol starts where it would
+ * without the balancer.
+ *
+ * TLVs of version 2 are skipped, and so are the addresses of families
+ * other than IPv4 and IPv6.
+ */
+
+use crate::ae::{
+    AeEventLoop, ae_create_file_event, ae_create_time_event, ae_delete_file_event,
+    ae_delete_time_event,
+};
+use crate::anet::errno;
+use crate::constants::{AE_ERR, AE_NOMORE, AE_OK, AE_READABLE};
+use crate::traits::AcceptProc;
+use std::ffi::c_void;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/* Longest version 1 header, CRLF included, per the specification. */
+pub const AE_PROXY_HEADER_V1_MAX: usize = 107;
+/* Time a connection gets to send its header in ThreadPerCore. */
+pub const AE_PROXY_HEADER_TIMEOUT_MS: i64 = 3000;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_FIXED_LEN: usize = 16;
+
+/* Bytes peeked from the socket per readable event. */
+const PEEK_LEN: usize = 512;
+
+/* A parsed PROXY header. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AeProxyHeader {
+    /* 1 or 2. */
+    pub version: u8,
+    /* The client and the address it connected to, as the balancer saw
+     * them. None for connections the balancer opened on its own (health
+     * checks: LOCAL in version 2, UNKNOWN in version 1) and for address
+     * families other than IPv4 and IPv6. */
+    pub source: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>,
+}
+
+/* Parse the PROXY header at the start of `buf`. Ok(Some) gives the header
+ * and its length in bytes, Ok(None) means `buf` is a valid start of a
+ * header that goes on, and Err(EPROTO) that it is not a PROXY header. */
+pub fn ae_proxy_header_parse(buf: &[u8]) -> Result<Option<(AeProxyHeader, usize)>, i32> {
+    if buf.starts_with(V2_SIGNATURE) {
+        return parse_v2(buf);
+    }
+    if buf.starts_with(V1_PREFIX) {
+        return parse_v1(buf);
+    }
+    let len = buf.len();
+    if len < V2_SIGNATURE.len() && (V2_SIGNATURE.starts_with(buf) || V1_PREFIX.starts_with(buf)) {
+        return Ok(None);
+    }
+    Err(libc::EPROTO)
+}
+
+fn parse_v1(buf: &[u8]) -> Result<Option<(AeProxyHeader, usize)>, i32> {
+    let window = &buf[..buf.len().min(AE_PROXY_HEADER_V1_MAX)];
+    let Some(end) = window.windows(2).position(|pair| pair == b"\r\n") else {
+        return if buf.len() < AE_PROXY_HEADER_V1_MAX {
+            Ok(None)
+        } else {
+            Err(libc::EPROTO)
+        };
+    };
+    let line = std::str::from_utf8(&buf[..end]).map_err(|_| libc::EPROTO)?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    let mut header = AeProxyHeader {
+        version: 1,
+        source: None,
+        destination: None,
+    };
+    match fields.get(1) {
+        /* The rest of the line is to be ignored. */
+        Some(&"UNKNOWN") => {}
+        Some(&"TCP4") | Some(&"TCP6") if fields.len() == 6 => {
+            let v6 = fields[1] == "TCP6";
+            header.source = Some(v1_address(fields[2], fields[4], v6)?);
+            header.destination = Some(v1_address(fields[3], fields[5], v6)?);
+        }
+        _ => return Err(libc::EPROTO),
+    }
+    Ok(Some((header, end + 2)))
+}
+
+fn v1_address(ip: &str, port: &str, v6: bool) -> Result<SocketAddr, i32> {
+    let ip = if v6 {
+        IpAddr::V6(ip.parse::<Ipv6Addr>().map_err(|_| libc::EPROTO)?)
+    } else {
+        IpAddr::V4(ip.parse::<Ipv4Addr>().map_err(|_| libc::EPROTO)?)
+    };
+    let port = port.parse::<u16>().map_err(|_| libc::EPROTO)?;
+    Ok(SocketAddr::new(ip, port))
+}
+
+fn parse_v2(buf: &[u8]) -> Result<Option<(AeProxyHeader, usize)>, i32> {
+    if buf.len() < V2_FIXED_LEN {
+        return Ok(None);
+    }
+    let version = buf[12] >> 4;
+    let command = buf[12] & 0x0f;
+    if version != 2 || command > 1 {
+        return Err(libc::EPROTO);
+    }
+    let len = V2_FIXED_LEN + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    if buf.len() < len {
+        return Ok(None);
+    }
+    let mut header = AeProxyHeader {
+        version: 2,
+        source: None,
+        destination: None,
+    };
+    /* LOCAL: the addresses, if any, are to be ignored. */
+    if command == 0 {
+        return Ok(Some((header, len)));
+    }
+    let block = &buf[V2_FIXED_LEN..len];
+    match buf[13] >> 4 {
+        /* AF_INET */
+        1 => {
+            if block.len() < 12 {
+                return Err(libc::EPROTO);
+            }
+            let ip = |at: usize| {
+                IpAddr::V4(Ipv4Addr::new(
+                    block[at],
+                    block[at + 1],
+                    block[at + 2],
+                    block[at + 3],
+                ))
+            };
+            header.source = Some(SocketAddr::new(ip(0), v2_port(block, 8)));
+            header.destination = Some(SocketAddr::new(ip(4), v2_port(block, 10)));
+        }
+        /* AF_INET6 */
+        2 => {
+            if block.len() < 36 {
+                return Err(libc::EPROTO);
+            }
+            let ip = |at: usize| {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&block[at..at + 16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            header.source = Some(SocketAddr::new(ip(0), v2_port(block, 32)));
+            header.destination = Some(SocketAddr::new(ip(16), v2_port(block, 34)));
+        }
+        /* AF_UNSPEC, AF_UNIX and future families. */
+        _ => {}
+    }
+    Ok(Some((header, len)))
+}
+
+fn v2_port(block: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([block[at], block[at + 1]])
+}
+
+/* State of a connection waiting for its header, shared by its readable
+ * handler and its timeout timer. */
+struct PendingHeader {
+    fd: i32,
+    timer_id: i64,
+    on_conn: AcceptProc,
+    /* Header bytes already taken off the socket. */
+    buf: Vec<u8>,
+}
+
+/* Wait for the PROXY header of the accepted connection `fd`, then call
+ * `on_conn` with the client address it carries (None for LOCAL and
+ * UNKNOWN headers). The connection is closed instead if what it sends is
+ * not a PROXY header, if it hangs up, or if the header is not complete
+ * within `timeout_ms` (a timeout <= 0 disables it).
+ *
+ * Returns AE_ERR, leaving `fd` to the caller, if it cannot be watched. */
+pub fn ae_proxy_header_accept(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    timeout_ms: i64,
+    on_conn: AcceptProc,
+) -> i32 {
+    let state = Box::into_raw(Box::new(PendingHeader {
+        fd,
+        timer_id: -1,
+        on_conn,
+        buf: Vec::new(),
+    }));
+    if ae_create_file_event(
+        event_loop,
+        fd,
+        AE_READABLE,
+        header_readable_handler,
+        state as *mut c_void,
+    ) == AE_ERR
+    {
+        drop(unsafe { Box::from_raw(state) });
+        return AE_ERR;
+    }
+    if timeout_ms > 0 {
+        let timer_id = ae_create_time_event(
+            event_loop,
+            timeout_ms,
+            header_timeout_handler,
+            state as *mut c_void,
+            None,
+        );
+        unsafe { (*state).timer_id = timer_id };
+    }
+    AE_OK
+}
+
+fn header_readable_handler(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    client_data: *mut c_void,
+    _mask: i32,
+) {
+    let state = unsafe { &mut *(client_data as *mut PendingHeader) };
+
+    /* Peek, so that the bytes following the header stay in the socket. */
+    let mut peeked = [0u8; PEEK_LEN];
+    let n = unsafe {
+        libc::recv(
+            fd,
+            peeked.as_mut_ptr() as *mut c_void,
+            peeked.len(),
+            libc::MSG_PEEK,
+        )
+    };
+    if n == -1 && matches!(errno(), libc::EAGAIN | libc::EINTR) {
+        return;
+    }
+    if n <= 0 {
+        finish(event_loop, client_data, None);
+        return;
+    }
+    let n = n as usize;
+
+    let mut input = state.buf.clone();
+    input.extend_from_slice(&peeked[..n]);
+    let (take, header) = match ae_proxy_header_parse(&input) {
+        Ok(Some((header, len))) => (len - state.buf.len(), Some(header)),
+        /* Everything peeked belongs to the header. */
+        Ok(None) => (n, None),
+        Err(_) => {
+            finish(event_loop, client_data, None);
+            return;
+        }
+    };
+    let read = unsafe { libc::recv(fd, peeked.as_mut_ptr() as *mut c_void, take, 0) };
+    if read != take as isize {
+        finish(event_loop, client_data, None);
+        return;
+    }
+    match header {
+        Some(header) => finish(event_loop, client_data, Some(header)),
+        None => state.buf.extend_from_slice(&peeked[..take]),
+    }
+}
+
+fn header_timeout_handler(event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    unsafe { (*(client_data as *mut PendingHeader)).timer_id = -1 };
+    finish(event_loop, client_data, None);
+    AE_NOMORE
+}
+
+/* Stop waiting, then hand the connection over or close it. */
+fn finish(event_loop: &mut AeEventLoop, client_data: *mut c_void, header: Option<AeProxyHeader>) {
+    let state = unsafe { Box::from_raw(client_data as *mut PendingHeader) };
+    ae_delete_file_event(event_loop, state.fd, AE_READABLE);
+    if state.timer_id != -1 {
+        ae_delete_time_event(event_loop, state.timer_id);
+    }
+    match header {
+        Some(header) => (state.on_conn)(event_loop, state.fd, header.source),
+        None => unsafe {
+            libc::close(state.fd);
+        },
+    }
+}
@@ -0,0 +1,92 @@
+/* Driving a loop from an async-io reactor (smol, async-std).
+ *
+ * Like child loops (see child.rs), but with the executor of the
+ * application as the parent: the backend fd of the loop is registered
+ * with the async-io reactor as an Async<AeBackendFd>, and ae_run_async()
+ * runs one non-blocking iteration whenever it becomes readable or the
+ * earliest timer of the loop is due. The loop is not Send, so the future
+ * runs on a local executor (smol::LocalExecutor, async_io::block_on, ...)
+ * next to the tasks of the application, without a thread of its own.
+ *
+ * This requires a backend that is itself pollable (kqueue, epoll).
+ */
+
+use crate::ae::lifecycle::{self, AeLifecycleEvent};
+use crate::ae::{AeEventLoop, ae_process_events, idle_timeout};
+use crate::anet::set_errno;
+use crate::constants::{
+    AE_ALL_EVENTS, AE_CALL_AFTER_SLEEP, AE_CALL_BEFORE_SLEEP, AE_DONT_WAIT, AE_ERR, AE_OK,
+};
+use async_io::{Async, Timer};
+use std::future::{Future, poll_fn};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::pin::Pin;
+use std::task::Poll;
+
+/* The backend fd of a loop, borrowed: dropping it does not close the fd,
+ * which stays owned by the loop. */
+#[derive(Debug)]
+pub struct AeBackendFd(i32);
+
+impl AeBackendFd {
+    pub fn fd(&self) -> i32 {
+        self.0
+    }
+}
+
+impl AsFd for AeBackendFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        /* Valid as long as the loop, which outlives ae_run_async(). */
+        unsafe { BorrowedFd::borrow_raw(self.0) }
+    }
+}
+
+/* Register the backend fd of the loop with the async-io reactor, for
+ * applications that schedule the iterations themselves. The Async must be
+ * dropped before the loop. Returns the errno on failure, ENOTSUP if the
+ * backend is not pollable. */
+pub fn ae_backend_async(event_loop: &AeEventLoop) -> Result<Async<AeBackendFd>, i32> {
+    let fd = event_loop.apidata.fd();
+    if fd == -1 {
+        return Err(libc::ENOTSUP);
+    }
+    Async::new(AeBackendFd(fd)).map_err(|err| err.raw_os_error().unwrap_or(libc::EIO))
+}
+
+/* Run the loop from the current executor until ae_stop(), like ae_main().
+ * Returns AE_OK once stopped, AE_ERR with errno set if the backend fd
+ * cannot be registered (ENOTSUP for select). */
+pub async fn ae_run_async(event_loop: &mut AeEventLoop) -> i32 {
+    let backend = match ae_backend_async(event_loop) {
+        Ok(backend) => backend,
+        Err(errno) => {
+            set_errno(errno);
+            return AE_ERR;
+        }
+    };
+
+    event_loop.stop = false;
+    lifecycle::emit(event_loop, AeLifecycleEvent::LoopStarted);
+    loop {
+        ae_process_events(
+            event_loop,
+            AE_ALL_EVENTS | AE_DONT_WAIT | AE_CALL_BEFORE_SLEEP | AE_CALL_AFTER_SLEEP,
+        );
+        if event_loop.stop {
+            break;
+        }
+        let mut timer = idle_timeout(event_loop).map(Timer::after);
+        poll_fn(|cx| {
+            if backend.poll_readable(cx).is_ready() {
+                return Poll::Ready(());
+            }
+            match timer.as_mut() {
+                Some(timer) => Pin::new(timer).poll(cx).map(|_| ()),
+                None => Poll::Pending,
+            }
+        })
+        .await;
+    }
+    lifecycle::emit(event_loop, AeLifecycleEvent::LoopStopped);
+    AE_OK
+}
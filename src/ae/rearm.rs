@@ -0,0 +1,63 @@
+/* Re-arming coalesced reads.
+ *
+ * The backends are level-triggered, but an fd registered with
+ * AeReadCoalescing::UntilRearmed behaves like an edge-triggered one: its
+ * read handler is called once, then not again until the fd is re-armed
+ * with ae_rearm(). A handler that reads until EAGAIN re-arms and gets the
+ * next input as soon as it arrives; one that leaves input behind re-arms
+ * once it is ready for more. Forgetting both is the classic edge-triggered
+ * bug: the fd is never read again and the connection hangs.
+ *
+ * With ae_set_rearm_check() on, the loop looks for it each time such a
+ * read handler returns, and panics, like a failed debug assertion, when
+ * the fd is still readable and was not re-armed. It costs a poll() per
+ * read, so it is meant for tests and debug builds.
+ */
+
+use crate::ae::AeEventLoop;
+use crate::constants::{AE_ERR, AE_OK, AE_READABLE, AE_WRITABLE};
+
+/* Re-arm the directions in `mask` of a registered fd: reads held back by
+ * AeReadCoalescing are dispatched again from the next iteration on. From
+ * within the read handler, the fd stays armed after it returns. Writes
+ * are never held back, AE_WRITABLE is accepted for symmetry. Returns
+ * AE_ERR if the fd is not registered for every direction of `mask`. */
+pub fn ae_rearm(event_loop: &mut AeEventLoop, fd: i32, mask: i32) -> i32 {
+    let mask = mask & (AE_READABLE | AE_WRITABLE);
+    if fd < 0 || mask == 0 {
+        return AE_ERR;
+    }
+    let fe = match event_loop.events.get_mut(fd as usize) {
+        Some(fe) if fe.mask & mask == mask => fe,
+        _ => return AE_ERR,
+    };
+    if mask & AE_READABLE != 0 {
+        fe.read_suppressed_until = 0;
+        fe.read_rearmed = true;
+    }
+    AE_OK
+}
+
+/* Check that read handlers of fds coalesced until re-armed either drain
+ * their fd or re-arm it. */
+pub fn ae_set_rearm_check(event_loop: &mut AeEventLoop, enabled: bool) {
+    event_loop.rearm_check = enabled;
+}
+
+/* The read handler of `fd` returned without re-arming it. */
+pub(crate) fn check(event_loop: &AeEventLoop, fd: i32) {
+    if !event_loop.rearm_check {
+        return;
+    }
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let readable = unsafe { libc::poll(&mut pfd, 1, 0) } == 1 && pfd.revents & libc::POLLIN != 0;
+    assert!(
+        !readable,
+        "fd {fd} is still readable after its read handler returned without calling \
+         ae_rearm(): it will not be read again"
+    );
+}
@@ -0,0 +1,100 @@
+/* Live loop parameters.
+ *
+ * Some builder options are worth tuning on a running loop: a smaller
+ * fired buffer to bound the work of an iteration, a poll ceiling while an
+ * incident is investigated, rusage sampling turned on for a while.
+ * ae_reconfigure() queues the change and the loop applies it at the start
+ * of its next iteration, never in the middle of a batch of fired events.
+ *
+ * Options that shape the loop itself (set size, backend, clock) stay
+ * fixed for its lifetime.
+ */
+
+use crate::ae::{AeDispatchOrder, AeEventLoop, AeFdOrder};
+use crate::ae_select::FiredEvent;
+use crate::constants::poll_batch;
+use std::time::Duration;
+
+/* Parameters to change, None for those left as they are. */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AeLoopConfigDelta {
+    /* See AeEventLoopBuilder::fired_capacity(). */
+    pub fired_capacity: Option<usize>,
+    /* See AeEventLoopBuilder::max_poll_timeout(), Some(None) lifts the
+     * ceiling. */
+    pub max_poll_timeout: Option<Option<Duration>>,
+    /* See AeEventLoopBuilder::dispatch_order(). */
+    pub dispatch_order: Option<AeDispatchOrder>,
+    /* See AeEventLoopBuilder::fd_order(). */
+    pub fd_order: Option<AeFdOrder>,
+    /* See AeEventLoopBuilder::rusage_sample_interval(). */
+    pub rusage_sample_interval: Option<u64>,
+}
+
+impl AeLoopConfigDelta {
+    /* Fields set in `later` win. */
+    fn merge(&mut self, later: AeLoopConfigDelta) {
+        self.fired_capacity = later.fired_capacity.or(self.fired_capacity);
+        self.max_poll_timeout = later.max_poll_timeout.or(self.max_poll_timeout);
+        self.dispatch_order = later.dispatch_order.or(self.dispatch_order);
+        self.fd_order = later.fd_order.or(self.fd_order);
+        self.rusage_sample_interval = later.rusage_sample_interval.or(self.rusage_sample_interval);
+    }
+}
+
+/* The parameters a loop runs with, see ae_loop_config(). */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AeLoopConfig {
+    pub fired_capacity: usize,
+    pub max_poll_timeout: Option<Duration>,
+    pub dispatch_order: AeDispatchOrder,
+    pub fd_order: AeFdOrder,
+    pub rusage_sample_interval: u64,
+}
+
+/* Change the parameters of `delta` from the next iteration on. Changes
+ * queued before that are merged, the latest value of a field winning.
+ * AeHandle::reconfigure() does it from another thread. */
+pub fn ae_reconfigure(event_loop: &mut AeEventLoop, delta: AeLoopConfigDelta) {
+    event_loop
+        .pending_reconfig
+        .get_or_insert_with(AeLoopConfigDelta::default)
+        .merge(delta);
+}
+
+/* The parameters in effect, changes still queued left out. */
+pub fn ae_loop_config(event_loop: &AeEventLoop) -> AeLoopConfig {
+    AeLoopConfig {
+        fired_capacity: event_loop.fired_capacity,
+        max_poll_timeout: event_loop.max_poll_timeout,
+        dispatch_order: event_loop.dispatch_order,
+        fd_order: event_loop.fd_order,
+        rusage_sample_interval: event_loop.stats.rusage_interval,
+    }
+}
+
+/* Apply the queued changes, at the start of an iteration. */
+pub(crate) fn apply(event_loop: &mut AeEventLoop) {
+    let Some(delta) = event_loop.pending_reconfig.take() else {
+        return;
+    };
+    if let Some(capacity) = delta.fired_capacity {
+        event_loop.fired_capacity = capacity.max(1);
+        let slots = poll_batch(event_loop.nevents as usize).min(event_loop.fired_capacity);
+        event_loop
+            .fired
+            .resize(slots, FiredEvent { fd: 0, mask: 0 });
+    }
+    if let Some(max) = delta.max_poll_timeout {
+        event_loop.max_poll_timeout = max;
+    }
+    if let Some(order) = delta.dispatch_order {
+        event_loop.dispatch_order = order;
+    }
+    if let Some(order) = delta.fd_order {
+        event_loop.fd_order = order;
+    }
+    if let Some(interval) = delta.rusage_sample_interval {
+        event_loop.stats.rusage_interval = interval;
+    }
+}
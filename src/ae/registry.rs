@@ -0,0 +1,46 @@
+/* Process-wide registry of named loop handles.
+ *
+ * Large applications often have a handful of well-known loops ("main",
+ * "io-0", "admin"). Registering their handles here lets any component look
+ * one up by name and post work to it, instead of threading handles
+ * through every constructor.
+ */
+
+use crate::ae::handle::AeHandle;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+fn registry() -> &'static Mutex<HashMap<String, AeHandle>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<String, AeHandle>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/* Register `handle` under `name`, returning the handle previously
+ * registered under that name, if any. */
+pub fn register(name: &str, handle: AeHandle) -> Option<AeHandle> {
+    registry().lock().unwrap().insert(name.to_string(), handle)
+}
+
+/* Look up the handle registered under `name`. Handles whose loop has been
+ * deleted are dropped from the registry and not returned. */
+pub fn lookup(name: &str) -> Option<AeHandle> {
+    let mut map = registry().lock().unwrap();
+    match map.get(name) {
+        Some(handle) if handle.is_closed() => {
+            map.remove(name);
+            None
+        }
+        Some(handle) => Some(handle.clone()),
+        None => None,
+    }
+}
+
+/* Remove the handle registered under `name`. */
+pub fn unregister(name: &str) -> Option<AeHandle> {
+    registry().lock().unwrap().remove(name)
+}
+
+/* Names currently registered, in no particular order. */
+pub fn names() -> Vec<String> {
+    registry().lock().unwrap().keys().cloned().collect()
+}
@@ -0,0 +1,283 @@
+/* Handing registered fds over to a new process (graceful reload).
+ *
+ * For a zero-downtime restart the old process passes its listening
+ * sockets (and whatever else must survive) to the new one, which picks up
+ * where the old one stopped. Callbacks are function pointers and mean
+ * nothing in another executable, so what travels is the fd, its event mask
+ * and its tag (see AeFileEventOptions): the new process maps each tag back
+ * to a handler when re-registering. Only tagged registrations are
+ * exported, untagged ones are considered private to the process (the loop
+ * own wakeup pipe, timerfds, ...).
+ *
+ * Two transports are provided:
+ *
+ * - across exec(): ae_prepare_exec() clears FD_CLOEXEC on the exported fds
+ *   and returns a string to put in the AE_INHERIT_ENV environment
+ *   variable of the new program, which reads it back with
+ *   ae_inherited_fds().
+ * - over a Unix socket to an unrelated process: ae_send_registrations()
+ *   and ae_recv_registrations(), passing the fds with SCM_RIGHTS.
+ */
+
+use crate::ae::{AeEventLoop, AeFileEventOptions, ae_create_file_event_ex};
+use crate::anet::{anet_cloexec, errno};
+use crate::constants::{AE_BARRIER, AE_ERR, AE_NONE, AE_OK, AE_READABLE, AE_WRITABLE};
+use crate::traits::FileProc;
+use std::ffi::c_void;
+
+/* Environment variable carrying the registrations across exec(). */
+pub const AE_INHERIT_ENV: &str = "RAE_INHERITED_FDS";
+
+/* Most fds passed in a single ae_send_registrations() call (the Linux
+ * SCM_MAX_FD limit). */
+pub const AE_RELOAD_MAX_FDS: usize = 253;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AeInheritedFd {
+    pub fd: i32,
+    pub mask: i32,
+    pub tag: u32,
+}
+
+/* Registrations with a non-zero tag, in fd order. */
+pub fn ae_export_registrations(event_loop: &AeEventLoop) -> Vec<AeInheritedFd> {
+    let mut exported = Vec::new();
+    for fd in 0..=event_loop.maxfd {
+        let fe = &event_loop.events[fd as usize];
+        if fe.mask != AE_NONE && fe.tag != 0 {
+            exported.push(AeInheritedFd {
+                fd,
+                mask: fe.mask & (AE_READABLE | AE_WRITABLE | AE_BARRIER),
+                tag: fe.tag,
+            });
+        }
+    }
+    exported
+}
+
+/* Compact text form: "fd:mask:tag" entries separated by commas. */
+pub fn ae_encode_registrations(fds: &[AeInheritedFd]) -> String {
+    fds.iter()
+        .map(|f| format!("{}:{}:{}", f.fd, f.mask, f.tag))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/* Inverse of ae_encode_registrations(), None if malformed. */
+pub fn ae_decode_registrations(encoded: &str) -> Option<Vec<AeInheritedFd>> {
+    if encoded.is_empty() {
+        return Some(Vec::new());
+    }
+    encoded
+        .split(',')
+        .map(|entry| {
+            let mut parts = entry.split(':');
+            let fd = parts.next()?.parse().ok()?;
+            let mask = parts.next()?.parse().ok()?;
+            let tag = parts.next()?.parse().ok()?;
+            if parts.next().is_some() || fd < 0 {
+                return None;
+            }
+            Some(AeInheritedFd { fd, mask, tag })
+        })
+        .collect()
+}
+
+/* Get the exported registrations ready to survive exec(): FD_CLOEXEC is
+ * cleared on each of them. Returns the value to set AE_INHERIT_ENV to in
+ * the environment of the new program, or the errno of the fcntl() that
+ * failed. */
+pub fn ae_prepare_exec(event_loop: &AeEventLoop) -> Result<String, i32> {
+    let exported = ae_export_registrations(event_loop);
+    for f in &exported {
+        let flags = unsafe { libc::fcntl(f.fd, libc::F_GETFD) };
+        if flags == -1
+            || unsafe { libc::fcntl(f.fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) } == -1
+        {
+            return Err(errno());
+        }
+    }
+    Ok(ae_encode_registrations(&exported))
+}
+
+/* Registrations handed over by the process that exec()ed us, empty if
+ * there are none. Entries whose fd is not actually open are dropped, and
+ * FD_CLOEXEC is set again on the others so they do not leak further. */
+pub fn ae_inherited_fds() -> Vec<AeInheritedFd> {
+    let encoded = match std::env::var(AE_INHERIT_ENV) {
+        Ok(encoded) => encoded,
+        Err(_) => return Vec::new(),
+    };
+    let mut fds = ae_decode_registrations(&encoded).unwrap_or_default();
+    fds.retain(|f| anet_cloexec(f.fd) == AE_OK);
+    fds
+}
+
+/* Register the inherited fds on the loop. `resolve` maps each one (by its
+ * tag, usually) to the handler and client data to use, or None to leave
+ * the fd alone. Tags are kept.
+ *
+ * Returns the number of fds registered, or AE_ERR if a registration
+ * failed (the ones before it stay registered). */
+pub fn ae_restore_registrations<F>(
+    event_loop: &mut AeEventLoop,
+    fds: &[AeInheritedFd],
+    mut resolve: F,
+) -> i32
+where
+    F: FnMut(&AeInheritedFd) -> Option<(FileProc, *mut c_void)>,
+{
+    let mut restored = 0;
+    for f in fds {
+        let (proc, client_data) = match resolve(f) {
+            Some(handler) => handler,
+            None => continue,
+        };
+        let options = AeFileEventOptions {
+            tag: f.tag,
+            ..Default::default()
+        };
+        if ae_create_file_event_ex(event_loop, f.fd, f.mask, proc, client_data, options) == AE_ERR {
+            return AE_ERR;
+        }
+        restored += 1;
+    }
+    restored
+}
+
+/* Send `fds` over the connected Unix socket `sock`, the descriptors
+ * themselves travelling as SCM_RIGHTS. The fds stay open here. On failure
+ * the errno is returned (E2BIG for more than AE_RELOAD_MAX_FDS). */
+pub fn ae_send_registrations(sock: i32, fds: &[AeInheritedFd]) -> Result<(), i32> {
+    if fds.len() > AE_RELOAD_MAX_FDS {
+        return Err(libc::E2BIG);
+    }
+    /* The payload is the encoded list, fd numbers included: they tell the
+     * receiver nothing but keep the format identical to the exec path. An
+     * empty list still sends one byte so the receiver sees a message. */
+    let mut payload = ae_encode_registrations(fds).into_bytes();
+    payload.push(b'\n');
+    let raw: Vec<i32> = fds.iter().map(|f| f.fd).collect();
+
+    let mut control = vec![0u8; cmsg_space(raw.len())];
+    let mut iov = libc::iovec {
+        iov_base: payload.as_mut_ptr() as *mut c_void,
+        iov_len: payload.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    if !raw.is_empty() {
+        msg.msg_control = control.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = control.len() as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of_val(raw.as_slice()) as u32) as _;
+            std::ptr::copy_nonoverlapping(
+                raw.as_ptr() as *const u8,
+                libc::CMSG_DATA(cmsg),
+                std::mem::size_of_val(raw.as_slice()),
+            );
+        }
+    }
+
+    loop {
+        let n = unsafe { libc::sendmsg(sock, &msg, 0) };
+        if n == -1 {
+            let err = errno();
+            if err == libc::EINTR {
+                continue;
+            }
+            return Err(err);
+        }
+        /* The fds went with the first byte: a partial message cannot be
+         * completed later. */
+        return if n as usize == payload.len() {
+            Ok(())
+        } else {
+            Err(libc::EMSGSIZE)
+        };
+    }
+}
+
+/* Receive registrations sent with ae_send_registrations() on `sock`.
+ * The returned fds are the local, close-on-exec copies. On failure the
+ * errno is returned (EBADMSG for a malformed message). */
+pub fn ae_recv_registrations(sock: i32) -> Result<Vec<AeInheritedFd>, i32> {
+    let mut payload = vec![0u8; AE_RELOAD_MAX_FDS * 32];
+    let mut control = vec![0u8; cmsg_space(AE_RELOAD_MAX_FDS)];
+    let mut iov = libc::iovec {
+        iov_base: payload.as_mut_ptr() as *mut c_void,
+        iov_len: payload.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = control.len() as _;
+
+    #[cfg(target_os = "linux")]
+    let flags = libc::MSG_CMSG_CLOEXEC;
+    #[cfg(not(target_os = "linux"))]
+    let flags = 0;
+
+    let n = loop {
+        let n = unsafe { libc::recvmsg(sock, &mut msg, flags) };
+        if n == -1 {
+            let err = errno();
+            if err == libc::EINTR {
+                continue;
+            }
+            return Err(err);
+        }
+        break n as usize;
+    };
+
+    let mut received = Vec::new();
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let hdr = unsafe { &*cmsg };
+        if hdr.cmsg_level == libc::SOL_SOCKET && hdr.cmsg_type == libc::SCM_RIGHTS {
+            let header = unsafe { libc::CMSG_DATA(cmsg) as usize - cmsg as usize };
+            let count = (hdr.cmsg_len as usize - header) / std::mem::size_of::<i32>();
+            let data = unsafe { libc::CMSG_DATA(cmsg) } as *const i32;
+            for i in 0..count {
+                let fd = unsafe { std::ptr::read_unaligned(data.add(i)) };
+                anet_cloexec(fd);
+                received.push(fd);
+            }
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+
+    let close_received = |fds: &[i32]| {
+        for &fd in fds {
+            unsafe { libc::close(fd) };
+        }
+    };
+    let text = match std::str::from_utf8(&payload[..n]) {
+        Ok(text) if msg.msg_flags & libc::MSG_CTRUNC == 0 => text.trim_end_matches('\n'),
+        _ => {
+            close_received(&received);
+            return Err(libc::EBADMSG);
+        }
+    };
+    match ae_decode_registrations(text) {
+        Some(mut fds) if fds.len() == received.len() => {
+            for (f, &fd) in fds.iter_mut().zip(&received) {
+                f.fd = fd;
+            }
+            Ok(fds)
+        }
+        _ => {
+            close_received(&received);
+            Err(libc::EBADMSG)
+        }
+    }
+}
+
+fn cmsg_space(nfds: usize) -> usize {
+    unsafe { libc::CMSG_SPACE((nfds * std::mem::size_of::<i32>()) as u32) as usize }
+}
@@ -0,0 +1,534 @@
+/* Redis protocol (RESP2 and RESP3).
+ *
+ * Two layers. resp_parse() and resp_encode() turn bytes into RespValue
+ * and back, for either protocol version, and can be used by clients as
+ * well as servers. ae_resp_serve() puts a buffered connection (ae::conn)
+ * in server mode: commands sent by clients, as multibulk arrays or inline
+ * lines like redis-cli and telnet send them, are handed to a
+ * RespCommandProc one by one, pipelined ones included, and the replies
+ * written with ae_resp_reply() are encoded for the protocol the
+ * connection speaks (RESP2 until ae_resp_set_protocol(), e.g. on HELLO 3).
+ *
+ * Streamed RESP3 aggregates and strings (the `?` lengths) are not
+ * supported. Attributes are parsed and dropped.
+ */
+
+use crate::ae::AeEventLoop;
+use crate::ae::conn::{
+    ae_conn_client_data, ae_conn_close_after_write, ae_conn_closing, ae_conn_create,
+    ae_conn_write_owned,
+};
+use crate::constants::{AE_ERR, AE_OK};
+use crate::traits::{ConnCloseProc, RespCommandProc};
+use std::ffi::c_void;
+
+/* Largest bulk string accepted, like Redis proto-max-bulk-len. */
+pub const RESP_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/* Longest line (inline command, type header) before the peer is assumed
+ * to be sending garbage, like Redis PROTO_INLINE_MAX_SIZE. */
+pub const RESP_MAX_INLINE: usize = 64 * 1024;
+
+/* Aggregates nested deeper than this are a protocol error, so that
+ * hostile input cannot exhaust the stack. */
+pub const RESP_MAX_DEPTH: usize = 128;
+
+/* Elements preallocated for an aggregate, whatever length it announces. */
+const RESP_PREALLOC: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RespValue {
+    Simple(Vec<u8>),
+    Error(Vec<u8>),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Array(Vec<RespValue>),
+    /* RESP2 null bulk and null array, RESP3 null. */
+    Null,
+    Boolean(bool),
+    Double(f64),
+    BigNumber(Vec<u8>),
+    BlobError(Vec<u8>),
+    Verbatim { format: [u8; 3], text: Vec<u8> },
+    Map(Vec<(RespValue, RespValue)>),
+    Set(Vec<RespValue>),
+    Push(Vec<RespValue>),
+}
+
+impl RespValue {
+    /* +OK */
+    pub fn ok() -> Self {
+        RespValue::Simple(b"OK".to_vec())
+    }
+
+    /* An error reply, e.g. RespValue::error("ERR unknown command"). */
+    pub fn error(msg: &str) -> Self {
+        RespValue::Error(msg.as_bytes().to_vec())
+    }
+
+    pub fn bulk(data: impl Into<Vec<u8>>) -> Self {
+        RespValue::Bulk(data.into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RespProtocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+/* Malformed input. The message is what Redis would put after
+ * "Protocol error: ". */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RespError(pub String);
+
+impl std::fmt::Display for RespError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Protocol error: {}", self.0)
+    }
+}
+
+impl std::error::Error for RespError {}
+
+fn protocol_error<T>(msg: impl Into<String>) -> Result<T, RespError> {
+    Err(RespError(msg.into()))
+}
+
+/* Parse one value from the start of `buf`. Returns the value and the bytes
+ * it took, or None if `buf` holds only part of it. */
+pub fn resp_parse(buf: &[u8]) -> Result<Option<(RespValue, usize)>, RespError> {
+    parse_value(buf, 0, 0)
+}
+
+/* The line starting at `pos`, without its CRLF, and the position after
+ * the CRLF. */
+fn read_line(buf: &[u8], pos: usize) -> Result<Option<(&[u8], usize)>, RespError> {
+    let rest = &buf[pos.min(buf.len())..];
+    match rest.windows(2).position(|w| w == b"\r\n") {
+        Some(len) => Ok(Some((&rest[..len], pos + len + 2))),
+        None if rest.len() > RESP_MAX_INLINE => protocol_error("too big line"),
+        None => Ok(None),
+    }
+}
+
+fn parse_int(line: &[u8]) -> Option<i64> {
+    /* Like Redis string2ll(): no sign other than '-', no spaces. */
+    if line.first() == Some(&b'+') {
+        return None;
+    }
+    std::str::from_utf8(line).ok()?.parse().ok()
+}
+
+/* Length of a bulk string or aggregate, -1 for null. */
+fn parse_len(line: &[u8], what: &str) -> Result<i64, RespError> {
+    match parse_int(line) {
+        Some(len) if len >= -1 => Ok(len),
+        _ => protocol_error(format!("invalid {what} length")),
+    }
+}
+
+fn parse_value(
+    buf: &[u8],
+    pos: usize,
+    depth: usize,
+) -> Result<Option<(RespValue, usize)>, RespError> {
+    let kind = match buf.get(pos) {
+        Some(&kind) => kind,
+        None => return Ok(None),
+    };
+    if depth > RESP_MAX_DEPTH {
+        return protocol_error("nesting too deep");
+    }
+    let (line, next) = match read_line(buf, pos + 1)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    let value = match kind {
+        b'+' => RespValue::Simple(line.to_vec()),
+        b'-' => RespValue::Error(line.to_vec()),
+        b':' => match parse_int(line) {
+            Some(n) => RespValue::Integer(n),
+            None => return protocol_error("invalid integer"),
+        },
+        b'_' if line.is_empty() => RespValue::Null,
+        b'#' => match line {
+            b"t" => RespValue::Boolean(true),
+            b"f" => RespValue::Boolean(false),
+            _ => return protocol_error("invalid boolean"),
+        },
+        b',' => match std::str::from_utf8(line).ok().and_then(|s| s.parse().ok()) {
+            Some(d) => RespValue::Double(d),
+            None => return protocol_error("invalid double"),
+        },
+        b'(' => {
+            let digits = line.strip_prefix(b"-").unwrap_or(line);
+            if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+                return protocol_error("invalid big number");
+            }
+            RespValue::BigNumber(line.to_vec())
+        }
+        b'$' | b'!' | b'=' => {
+            let len = parse_len(line, "bulk")?;
+            if len == -1 && kind == b'$' {
+                return Ok(Some((RespValue::Null, next)));
+            }
+            if len < 0 || len as usize > RESP_MAX_BULK_LEN {
+                return protocol_error("invalid bulk length");
+            }
+            let end = next + len as usize;
+            if buf.len() < end + 2 {
+                return Ok(None);
+            }
+            if &buf[end..end + 2] != b"\r\n" {
+                return protocol_error("bulk string not terminated by CRLF");
+            }
+            let data = buf[next..end].to_vec();
+            let value = match kind {
+                b'$' => RespValue::Bulk(data),
+                b'!' => RespValue::BlobError(data),
+                _ => {
+                    if data.len() < 4 || data[3] != b':' {
+                        return protocol_error("invalid verbatim string");
+                    }
+                    RespValue::Verbatim {
+                        format: [data[0], data[1], data[2]],
+                        text: data[4..].to_vec(),
+                    }
+                }
+            };
+            return Ok(Some((value, end + 2)));
+        }
+        b'*' | b'~' | b'>' => {
+            let len = parse_len(line, "multibulk")?;
+            if len == -1 && kind == b'*' {
+                return Ok(Some((RespValue::Null, next)));
+            }
+            if len < 0 {
+                return protocol_error("invalid multibulk length");
+            }
+            let (items, next) = match parse_items(buf, next, len as usize, depth)? {
+                Some(items) => items,
+                None => return Ok(None),
+            };
+            let value = match kind {
+                b'*' => RespValue::Array(items),
+                b'~' => RespValue::Set(items),
+                _ => RespValue::Push(items),
+            };
+            return Ok(Some((value, next)));
+        }
+        b'%' | b'|' => {
+            let len = parse_len(line, "map")?;
+            if len < 0 {
+                return protocol_error("invalid map length");
+            }
+            let (items, next) = match parse_items(buf, next, 2 * len as usize, depth)? {
+                Some(items) => items,
+                None => return Ok(None),
+            };
+            if kind == b'|' {
+                /* Attributes describe the value that follows them. */
+                return parse_value(buf, next, depth);
+            }
+            let mut pairs = Vec::with_capacity(items.len() / 2);
+            let mut items = items.into_iter();
+            while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                pairs.push((key, value));
+            }
+            return Ok(Some((RespValue::Map(pairs), next)));
+        }
+        _ => {
+            return protocol_error(format!(
+                "unexpected type byte '{}'",
+                (kind as char).escape_default()
+            ));
+        }
+    };
+    Ok(Some((value, next)))
+}
+
+fn parse_items(
+    buf: &[u8],
+    mut pos: usize,
+    count: usize,
+    depth: usize,
+) -> Result<Option<(Vec<RespValue>, usize)>, RespError> {
+    let mut items = Vec::with_capacity(count.min(RESP_PREALLOC));
+    for _ in 0..count {
+        match parse_value(buf, pos, depth + 1)? {
+            Some((item, next)) => {
+                items.push(item);
+                pos = next;
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(Some((items, pos)))
+}
+
+/* Append `value` to `out` in the given protocol. RESP3 only types are
+ * downgraded for RESP2 the way Redis does: maps become flat arrays, sets
+ * and pushes arrays, booleans 1 and 0, doubles, big numbers and verbatim
+ * strings bulk strings. CR and LF in simple strings and errors are
+ * replaced with spaces. */
+pub fn resp_encode(value: &RespValue, protocol: RespProtocol, out: &mut Vec<u8>) {
+    let resp3 = protocol == RespProtocol::Resp3;
+    match value {
+        RespValue::Simple(s) => encode_line(out, b'+', s),
+        RespValue::Error(s) => encode_line(out, b'-', s),
+        RespValue::Integer(n) => encode_header(out, b':', *n),
+        RespValue::Bulk(data) => encode_blob(out, b'$', data),
+        RespValue::Null if resp3 => out.extend_from_slice(b"_\r\n"),
+        RespValue::Null => out.extend_from_slice(b"$-1\r\n"),
+        RespValue::Boolean(b) if resp3 => {
+            out.extend_from_slice(if *b { b"#t\r\n" } else { b"#f\r\n" })
+        }
+        RespValue::Boolean(b) => encode_header(out, b':', *b as i64),
+        RespValue::Double(d) => {
+            let text = if d.is_nan() {
+                "nan".to_string()
+            } else if d.is_infinite() {
+                if *d > 0.0 { "inf" } else { "-inf" }.to_string()
+            } else {
+                d.to_string()
+            };
+            if resp3 {
+                encode_line(out, b',', text.as_bytes());
+            } else {
+                encode_blob(out, b'$', text.as_bytes());
+            }
+        }
+        RespValue::BigNumber(digits) if resp3 => encode_line(out, b'(', digits),
+        RespValue::BigNumber(digits) => encode_blob(out, b'$', digits),
+        RespValue::BlobError(data) if resp3 => encode_blob(out, b'!', data),
+        RespValue::BlobError(data) => encode_line(out, b'-', data),
+        RespValue::Verbatim { format, text } if resp3 => {
+            encode_header(out, b'=', (text.len() + 4) as i64);
+            out.extend_from_slice(format);
+            out.push(b':');
+            out.extend_from_slice(text);
+            out.extend_from_slice(b"\r\n");
+        }
+        RespValue::Verbatim { text, .. } => encode_blob(out, b'$', text),
+        RespValue::Array(items) => encode_items(out, b'*', items, protocol),
+        RespValue::Set(items) => {
+            encode_items(out, if resp3 { b'~' } else { b'*' }, items, protocol)
+        }
+        RespValue::Push(items) => {
+            encode_items(out, if resp3 { b'>' } else { b'*' }, items, protocol)
+        }
+        RespValue::Map(pairs) => {
+            if resp3 {
+                encode_header(out, b'%', pairs.len() as i64);
+            } else {
+                encode_header(out, b'*', 2 * pairs.len() as i64);
+            }
+            for (key, value) in pairs {
+                resp_encode(key, protocol, out);
+                resp_encode(value, protocol, out);
+            }
+        }
+    }
+}
+
+fn encode_header(out: &mut Vec<u8>, kind: u8, n: i64) {
+    out.push(kind);
+    out.extend_from_slice(n.to_string().as_bytes());
+    out.extend_from_slice(b"\r\n");
+}
+
+fn encode_line(out: &mut Vec<u8>, kind: u8, line: &[u8]) {
+    out.push(kind);
+    out.extend(
+        line.iter()
+            .map(|&c| if c == b'\r' || c == b'\n' { b' ' } else { c }),
+    );
+    out.extend_from_slice(b"\r\n");
+}
+
+fn encode_blob(out: &mut Vec<u8>, kind: u8, data: &[u8]) {
+    encode_header(out, kind, data.len() as i64);
+    out.extend_from_slice(data);
+    out.extend_from_slice(b"\r\n");
+}
+
+fn encode_items(out: &mut Vec<u8>, kind: u8, items: &[RespValue], protocol: RespProtocol) {
+    encode_header(out, kind, items.len() as i64);
+    for item in items {
+        resp_encode(item, protocol, out);
+    }
+}
+
+/* Per connection state of a RESP server connection, the client data of
+ * the underlying buffered connection. */
+struct RespConn {
+    proc: RespCommandProc,
+    close_proc: Option<ConnCloseProc>,
+    client_data: *mut c_void,
+    protocol: RespProtocol,
+}
+
+/* Serve RESP on the connected socket `fd`: every command received is
+ * passed to `proc`, which answers with ae_resp_reply(). A malformed
+ * request gets a protocol error reply, after which the connection is
+ * closed, as Redis does. Takes ownership of `fd` like ae_conn_create(),
+ * and the connection is closed the same way, with ae_conn_close(). */
+pub fn ae_resp_serve(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    proc: RespCommandProc,
+    close_proc: Option<ConnCloseProc>,
+    client_data: *mut c_void,
+) -> i32 {
+    let conn = Box::into_raw(Box::new(RespConn {
+        proc,
+        close_proc,
+        client_data,
+        protocol: RespProtocol::Resp2,
+    }));
+    if ae_conn_create(
+        event_loop,
+        fd,
+        resp_read,
+        Some(resp_closed),
+        conn as *mut c_void,
+    ) == AE_ERR
+    {
+        drop(unsafe { Box::from_raw(conn) });
+        return AE_ERR;
+    }
+    AE_OK
+}
+
+fn resp_conn(event_loop: &AeEventLoop, fd: i32) -> Option<*mut RespConn> {
+    let conn = ae_conn_client_data(event_loop, fd);
+    (!conn.is_null()).then_some(conn as *mut RespConn)
+}
+
+/* Send `reply` on the RESP connection `fd`, encoded for the protocol it
+ * speaks. Returns AE_ERR if `fd` is not an open connection. */
+pub fn ae_resp_reply(event_loop: &mut AeEventLoop, fd: i32, reply: &RespValue) -> i32 {
+    let conn = match resp_conn(event_loop, fd) {
+        Some(conn) => conn,
+        None => return AE_ERR,
+    };
+    let mut out = Vec::new();
+    resp_encode(reply, unsafe { (*conn).protocol }, &mut out);
+    ae_conn_write_owned(event_loop, fd, out)
+}
+
+/* Switch the protocol replies are encoded in, for the replies that
+ * follow. */
+pub fn ae_resp_set_protocol(event_loop: &mut AeEventLoop, fd: i32, protocol: RespProtocol) -> i32 {
+    match resp_conn(event_loop, fd) {
+        Some(conn) => {
+            unsafe { (*conn).protocol = protocol };
+            AE_OK
+        }
+        None => AE_ERR,
+    }
+}
+
+fn resp_closed(event_loop: &mut AeEventLoop, fd: i32, err: i32, client_data: *mut c_void) {
+    let conn = unsafe { Box::from_raw(client_data as *mut RespConn) };
+    if let Some(close_proc) = conn.close_proc {
+        close_proc(event_loop, fd, err, conn.client_data);
+    }
+}
+
+fn resp_read(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    input: &[u8],
+    client_data: *mut c_void,
+) -> usize {
+    let conn = client_data as *mut RespConn;
+    let (proc, user_data) = unsafe { ((*conn).proc, (*conn).client_data) };
+    let mut consumed = 0;
+    let mut argv = Vec::new();
+    /* A command may close the connection: what follows it is dropped. */
+    while consumed < input.len() && !ae_conn_closing(event_loop, fd) {
+        argv.clear();
+        match parse_command(&input[consumed..], &mut argv) {
+            Ok(Some(len)) => {
+                if !argv.is_empty() {
+                    proc(event_loop, fd, &argv, user_data);
+                }
+                consumed += len;
+            }
+            Ok(None) => break,
+            Err(err) => {
+                let reply = RespValue::Error(format!("ERR {err}").into_bytes());
+                ae_resp_reply(event_loop, fd, &reply);
+                ae_conn_close_after_write(event_loop, fd);
+                return input.len();
+            }
+        }
+    }
+    consumed
+}
+
+/* Parse the command at the start of `buf` into `argv`, borrowing from
+ * `buf`. Returns the bytes it took (with `argv` left empty for a blank
+ * line or an empty multibulk), or None if it is incomplete. */
+fn parse_command<'a>(buf: &'a [u8], argv: &mut Vec<&'a [u8]>) -> Result<Option<usize>, RespError> {
+    if buf.first() != Some(&b'*') {
+        return parse_inline(buf, argv);
+    }
+
+    let (line, mut pos) = match read_line(buf, 1) {
+        Ok(Some(line)) => line,
+        Ok(None) => return Ok(None),
+        Err(_) => return protocol_error("too big mbulk count string"),
+    };
+    let count = match parse_int(line) {
+        Some(count) if count <= i32::MAX as i64 => count,
+        _ => return protocol_error("invalid multibulk length"),
+    };
+    for _ in 0..count.max(0) {
+        match buf.get(pos) {
+            Some(b'$') => {}
+            Some(&c) => {
+                return protocol_error(format!(
+                    "expected '$', got '{}'",
+                    (c as char).escape_default()
+                ));
+            }
+            None => return Ok(None),
+        }
+        let (line, next) = match read_line(buf, pos + 1) {
+            Ok(Some(line)) => line,
+            Ok(None) => return Ok(None),
+            Err(_) => return protocol_error("too big bulk count string"),
+        };
+        let len = match parse_int(line) {
+            Some(len) if len >= 0 && len as usize <= RESP_MAX_BULK_LEN => len as usize,
+            _ => return protocol_error("invalid bulk length"),
+        };
+        if buf.len() < next + len + 2 {
+            return Ok(None);
+        }
+        argv.push(&buf[next..next + len]);
+        pos = next + len + 2;
+    }
+    Ok(Some(pos))
+}
+
+/* An inline command: a line of arguments separated by spaces, as typed
+ * in telnet. Quoting is not supported. */
+fn parse_inline<'a>(buf: &'a [u8], argv: &mut Vec<&'a [u8]>) -> Result<Option<usize>, RespError> {
+    let newline = match buf.iter().position(|&c| c == b'\n') {
+        Some(newline) => newline,
+        None if buf.len() > RESP_MAX_INLINE => return protocol_error("too big inline request"),
+        None => return Ok(None),
+    };
+    let line = &buf[..newline];
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    argv.extend(
+        line.split(|c| c.is_ascii_whitespace())
+            .filter(|arg| !arg.is_empty()),
+    );
+    Ok(Some(newline + 1))
+}
@@ -0,0 +1,563 @@
+/* Thread-per-core runtime preset.
+ *
+ * The usual way to use all cores with a single threaded loop is to run
+ * one loop per core, each with its own listening socket on the same
+ * address (SO_REUSEPORT), and let the kernel spread the connections among
+ * them: no lock and no hand-off between threads on the request path.
+ * ThreadPerCore sets that up (threads, loops, listeners, accept handlers,
+ * wakeup handles, CPU pinning) and calls an AcceptProc for every
+ * connection on the loop that accepted it.
+ *
+ * The kernel balances connections across SO_REUSEPORT listeners on Linux.
+ * Other systems accept the option but may hand most connections to one of
+ * them.
+ */
+
+use crate::ae::builder::AeEventLoopBuilder;
+use crate::ae::handle::{AeHandle, ae_get_handle};
+use crate::ae::migrate::ae_conn_migrate;
+use crate::ae::net::{ae_accept, ae_set_peer_filter};
+use crate::ae::proxy_header::{AE_PROXY_HEADER_TIMEOUT_MS, ae_proxy_header_accept};
+use crate::ae::{
+    AeEventLoop, AeFileEventOptions, ae_create_file_event_ex, ae_delete_file_event, ae_main,
+};
+use crate::anet::{AnetListenOptions, anet_local_addr, anet_tcp_listen, errno};
+use crate::constants::{AE_ERR, AE_READABLE};
+use crate::traits::{AcceptProc, ConnMigratedProc, LoopInitProc, PeerFilterProc};
+use std::ffi::c_void;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+/* Connections accepted per readable event, like Redis
+ * MAX_ACCEPTS_PER_CALL, so that a connection storm does not starve the
+ * clients already connected. */
+const AE_MAX_ACCEPTS_PER_CALL: usize = 1000;
+
+pub struct ThreadPerCore {
+    threads: usize,
+    pin: bool,
+    setsize: i32,
+    backlog: i32,
+    fastopen_queue: i32,
+    defer_accept_secs: i32,
+    accept_filter: Option<&'static str>,
+    synack_retries: i32,
+    init: Option<LoopInitProc>,
+    peer_filter: Option<PeerFilterProc>,
+    proxy_protocol: bool,
+    name: String,
+    shared_listener: bool,
+}
+
+impl Default for ThreadPerCore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ThreadPerCore {
+    /* One thread per CPU available to the process, pinned. */
+    pub fn new() -> Self {
+        Self {
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            pin: true,
+            setsize: 10_128,
+            backlog: 511,
+            fastopen_queue: 0,
+            defer_accept_secs: 0,
+            accept_filter: None,
+            synack_retries: 0,
+            init: None,
+            peer_filter: None,
+            proxy_protocol: false,
+            name: "rae-core".to_string(),
+            shared_listener: false,
+        }
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /* Pin thread N to the Nth CPU the process may run on (Linux only,
+     * ignored elsewhere or when the thread count exceeds the CPUs). */
+    pub fn pin_threads(mut self, pin: bool) -> Self {
+        self.pin = pin;
+        self
+    }
+
+    /* Set size of every loop, see ae_create_event_loop(). */
+    pub fn setsize(mut self, setsize: i32) -> Self {
+        self.setsize = setsize;
+        self
+    }
+
+    /* listen() backlog of every listener. */
+    pub fn backlog(mut self, backlog: i32) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    /* Accept TCP Fast Open connections, at most `queue_len` pending, see
+     * anet_set_tcp_fastopen(): a connection may reach the AcceptProc with
+     * its first request already readable. 0 (the default) turns it off. */
+    pub fn fastopen(mut self, queue_len: i32) -> Self {
+        self.fastopen_queue = queue_len.max(0);
+        self
+    }
+
+    /* Hand connections to the AcceptProc only once they sent data, or
+     * after about `secs` seconds, see anet_set_defer_accept() (Linux only,
+     * start() fails with ENOPROTOOPT elsewhere). 0 (the default) turns it
+     * off. */
+    pub fn defer_accept(mut self, secs: i32) -> Self {
+        self.defer_accept_secs = secs.max(0);
+        self
+    }
+
+    /* Install the kernel accept filter `name` ("dataready", "httpready")
+     * on the listeners, see anet_set_accept_filter() (FreeBSD and NetBSD
+     * only, start() fails with ENOPROTOOPT elsewhere). */
+    pub fn accept_filter(mut self, name: &'static str) -> Self {
+        self.accept_filter = Some(name);
+        self
+    }
+
+    /* Drop handshakes the client does not complete after `retries`
+     * SYN-ACK retransmissions, see anet_set_synack_retries() (Linux only,
+     * start() fails with ENOPROTOOPT elsewhere). 0 (the default) keeps the
+     * system setting. */
+    pub fn synack_retries(mut self, retries: i32) -> Self {
+        self.synack_retries = retries.max(0);
+        self
+    }
+
+    /* Share one listening socket among all loops instead of giving each
+     * its own SO_REUSEPORT socket: a connection goes to a loop that is
+     * waiting rather than to the one the kernel hashed it to. Every loop
+     * watches the socket with AeFileEventOptions::exclusive, so on epoll
+     * only one of them wakes up per connection. kqueue has no such mode:
+     * every loop wakes up and all but one get EAGAIN from accept(). */
+    pub fn shared_listener(mut self, shared: bool) -> Self {
+        self.shared_listener = shared;
+        self
+    }
+
+    /* Loop N is named "<name>-N", and so is its thread (see
+     * AeEventLoopBuilder::name()). Defaults to "rae-core". */
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /* Install `filter` on every loop, see ae_set_peer_filter(): refused
+     * connections are closed before they reach the AcceptProc. */
+    pub fn peer_filter(mut self, filter: PeerFilterProc) -> Self {
+        self.peer_filter = Some(filter);
+        self
+    }
+
+    /* Expect a PROXY protocol header (version 1 or 2) on every
+     * connection, as sent by HAProxy and other balancers: the AcceptProc
+     * gets the client address it carries, once it arrived, see
+     * ae_proxy_header_accept(). Connections that send no valid header
+     * within AE_PROXY_HEADER_TIMEOUT_MS are closed. The peer filter still
+     * sees the address of the balancer. */
+    pub fn proxy_protocol(mut self, enabled: bool) -> Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+
+    /* Called on each thread once its loop is built, to register timers or
+     * per-core state before the first connection. */
+    pub fn on_loop_start(mut self, init: LoopInitProc) -> Self {
+        self.init = Some(init);
+        self
+    }
+
+    /* Listen on `addr` and start the threads. Returns once every loop is
+     * running, or the errno of the first listener or loop that could not
+     * be created (nothing is left running then). */
+    pub fn start(self, addr: SocketAddr, on_conn: AcceptProc) -> Result<AeRuntime, i32> {
+        let options = AnetListenOptions {
+            backlog: self.backlog,
+            reuse_port: !self.shared_listener,
+            fastopen_queue: self.fastopen_queue,
+            defer_accept_secs: self.defer_accept_secs,
+            accept_filter: self.accept_filter,
+            synack_retries: self.synack_retries,
+        };
+        let listeners = if self.shared_listener {
+            open_shared_listener(addr, self.threads, &options)?
+        } else {
+            open_listeners(addr, self.threads, &options)?
+        };
+        let local_addr = anet_local_addr(listeners[0]);
+        let local_addr = match local_addr {
+            Ok(local_addr) => local_addr,
+            Err(err) => {
+                close_all(&listeners);
+                return Err(err);
+            }
+        };
+
+        let cpus = if self.pin { allowed_cpus() } else { Vec::new() };
+        let mut runtime = AeRuntime {
+            local_addr,
+            handles: Vec::new(),
+            threads: Vec::new(),
+        };
+        let (tx, rx) = mpsc::channel();
+        for (index, &listen_fd) in listeners.iter().enumerate() {
+            let config = CoreConfig {
+                index,
+                listen_fd,
+                cpu: cpus
+                    .get(index)
+                    .copied()
+                    .filter(|_| cpus.len() >= self.threads),
+                setsize: self.setsize,
+                name: format!("{}-{index}", self.name),
+                shared_listener: self.shared_listener,
+                init: self.init,
+                peer_filter: self.peer_filter,
+                proxy_protocol: self.proxy_protocol,
+                on_conn,
+            };
+            let core_tx = tx.clone();
+            let spawned = std::thread::Builder::new()
+                .name(config.name.clone())
+                .spawn(move || run_core(config, core_tx));
+            match spawned {
+                Ok(thread) => runtime.threads.push(thread),
+                Err(err) => {
+                    /* Threads started so far own their listener. */
+                    close_all(&listeners[index..]);
+                    drop(tx);
+                    collect_handles(&mut runtime, &rx);
+                    return Err(err.raw_os_error().unwrap_or(libc::EAGAIN));
+                }
+            }
+        }
+        drop(tx);
+
+        match collect_handles(&mut runtime, &rx) {
+            Some(err) => Err(err),
+            None => Ok(runtime),
+        }
+    }
+
+    /* start(), then wait until every loop has stopped. */
+    pub fn serve(self, addr: SocketAddr, on_conn: AcceptProc) -> Result<(), i32> {
+        self.start(addr, on_conn)?.join();
+        Ok(())
+    }
+}
+
+/* Running ThreadPerCore. Dropping it stops the loops and waits for their
+ * threads. */
+pub struct AeRuntime {
+    local_addr: SocketAddr,
+    handles: Vec<AeHandle>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for AeRuntime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AeRuntime")
+            .field("local_addr", &self.local_addr)
+            .field("threads", &self.threads.len())
+            .finish()
+    }
+}
+
+impl AeRuntime {
+    /* Address the listeners are bound to, with the actual port if port 0
+     * was requested. */
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /* One handle per loop, in thread order, to post work to a core. */
+    pub fn handles(&self) -> &[AeHandle] {
+        &self.handles
+    }
+
+    /* Move the connection `fd` from loop `from` to loop `to`, along with
+     * the timers of `timer_group`, see ae_conn_migrate(). It is posted to
+     * loop `from`, where a connection that cannot move stays. Returns
+     * AE_ERR for a loop index out of range, the same loop twice, or a loop
+     * already stopped. */
+    pub fn migrate(
+        &self,
+        fd: i32,
+        from: usize,
+        to: usize,
+        timer_group: u64,
+        arrived: Option<ConnMigratedProc>,
+    ) -> i32 {
+        let (source, target) = match (self.handles.get(from), self.handles.get(to)) {
+            (Some(source), Some(target)) if from != to => (source, target.clone()),
+            _ => return AE_ERR,
+        };
+        source.post(move |event_loop| {
+            ae_conn_migrate(event_loop, fd, &target, timer_group, arrived);
+        })
+    }
+
+    /* Ask every loop to stop. The listeners are closed as they exit. */
+    pub fn stop(&self) {
+        for handle in &self.handles {
+            handle.stop();
+        }
+    }
+
+    /* Wait for every loop to stop, from stop() or from their callbacks. */
+    pub fn join(mut self) {
+        self.join_threads();
+    }
+
+    fn join_threads(&mut self) {
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for AeRuntime {
+    fn drop(&mut self) {
+        if !self.threads.is_empty() {
+            self.stop();
+            self.join_threads();
+        }
+    }
+}
+
+/* Everything a core thread needs, all of it Send. */
+struct CoreConfig {
+    index: usize,
+    listen_fd: i32,
+    cpu: Option<usize>,
+    setsize: i32,
+    name: String,
+    shared_listener: bool,
+    init: Option<LoopInitProc>,
+    peer_filter: Option<PeerFilterProc>,
+    proxy_protocol: bool,
+    on_conn: AcceptProc,
+}
+
+/* Listener state handed to the accept handler. */
+struct ListenerState {
+    on_conn: AcceptProc,
+    proxy_protocol: bool,
+}
+
+/* Startup result of a core thread, with its index. */
+type CoreStarted = (usize, Result<AeHandle, i32>);
+
+fn run_core(config: CoreConfig, tx: mpsc::Sender<CoreStarted>) {
+    if let Some(cpu) = config.cpu {
+        /* Best effort: a restricted container may refuse it. */
+        pin_current_thread(cpu);
+    }
+
+    /* One reserved fd so that ae_accept() can turn clients away at the fd
+     * limit instead of spinning on the listener. */
+    let mut builder = AeEventLoopBuilder::new(config.setsize)
+        .name(config.name)
+        .reserved_fds(1);
+    if config.shared_listener {
+        /* Exclusive wakeups need epoll. */
+        builder = builder.best_backend();
+    }
+    let event_loop = builder.build();
+    let mut event_loop = match event_loop {
+        Some(event_loop) => event_loop,
+        None => {
+            unsafe { libc::close(config.listen_fd) };
+            let _ = tx.send((config.index, Err(libc::ENOMEM)));
+            return;
+        }
+    };
+
+    ae_set_peer_filter(&mut event_loop, config.peer_filter);
+    let state = ListenerState {
+        on_conn: config.on_conn,
+        proxy_protocol: config.proxy_protocol,
+    };
+    let handle = match ae_get_handle(&mut event_loop) {
+        Some(handle) => handle,
+        None => {
+            let err = errno();
+            unsafe { libc::close(config.listen_fd) };
+            let _ = tx.send((config.index, Err(err)));
+            return;
+        }
+    };
+    let options = AeFileEventOptions {
+        exclusive: config.shared_listener,
+        ..Default::default()
+    };
+    if ae_create_file_event_ex(
+        &mut event_loop,
+        config.listen_fd,
+        AE_READABLE,
+        accept_handler,
+        &state as *const ListenerState as *mut c_void,
+        options,
+    ) == AE_ERR
+    {
+        /* The listener fd is beyond the set size. */
+        unsafe { libc::close(config.listen_fd) };
+        let _ = tx.send((config.index, Err(libc::ERANGE)));
+        return;
+    }
+
+    if let Some(init) = config.init {
+        init(&mut event_loop, config.index);
+    }
+    if tx.send((config.index, Ok(handle))).is_err() {
+        /* start() gave up on another core. */
+        ae_delete_file_event(&mut event_loop, config.listen_fd, AE_READABLE);
+        unsafe { libc::close(config.listen_fd) };
+        return;
+    }
+    drop(tx);
+
+    ae_main(&mut event_loop);
+    ae_delete_file_event(&mut event_loop, config.listen_fd, AE_READABLE);
+    unsafe { libc::close(config.listen_fd) };
+}
+
+fn accept_handler(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let state = unsafe { &*(client_data as *const ListenerState) };
+    let (on_conn, proxy_protocol) = (state.on_conn, state.proxy_protocol);
+    for _ in 0..AE_MAX_ACCEPTS_PER_CALL {
+        match ae_accept(event_loop, fd, b"") {
+            Ok((cfd, _)) if proxy_protocol => {
+                if ae_proxy_header_accept(event_loop, cfd, AE_PROXY_HEADER_TIMEOUT_MS, on_conn)
+                    == AE_ERR
+                {
+                    unsafe { libc::close(cfd) };
+                }
+            }
+            Ok((cfd, addr)) => on_conn(event_loop, cfd, addr),
+            /* EAGAIN: another core took it, or nothing is left. */
+            Err(_) => return,
+        }
+    }
+}
+
+/* Wait for the startup result of every thread that was spawned. On
+ * failure the loops that did start are stopped and joined, and the first
+ * errno is returned. */
+fn collect_handles(runtime: &mut AeRuntime, rx: &mpsc::Receiver<CoreStarted>) -> Option<i32> {
+    let mut first_err = None;
+    let mut handles = Vec::new();
+    for (index, result) in rx.iter() {
+        match result {
+            Ok(handle) => handles.push((index, handle)),
+            Err(err) => {
+                first_err.get_or_insert(err);
+            }
+        }
+    }
+    /* Results arrive in completion order. */
+    handles.sort_by_key(|&(index, _)| index);
+    runtime.handles = handles.into_iter().map(|(_, handle)| handle).collect();
+    if first_err.is_some() {
+        runtime.stop();
+        runtime.join_threads();
+    }
+    first_err
+}
+
+fn open_listeners(
+    addr: SocketAddr,
+    count: usize,
+    options: &AnetListenOptions,
+) -> Result<Vec<i32>, i32> {
+    let mut listeners = Vec::with_capacity(count);
+    let mut addr = addr;
+    for _ in 0..count {
+        match anet_tcp_listen(&addr, options) {
+            Ok(fd) => listeners.push(fd),
+            Err(err) => {
+                close_all(&listeners);
+                return Err(err);
+            }
+        }
+        /* The other listeners must share the port the kernel picked. */
+        if addr.port() == 0 {
+            match anet_local_addr(listeners[0]) {
+                Ok(local) => addr.set_port(local.port()),
+                Err(err) => {
+                    close_all(&listeners);
+                    return Err(err);
+                }
+            }
+        }
+    }
+    Ok(listeners)
+}
+
+/* One listener, and a duplicate of it for every other loop: each loop
+ * owns and closes its own fd, all of them the same socket. */
+fn open_shared_listener(
+    addr: SocketAddr,
+    count: usize,
+    options: &AnetListenOptions,
+) -> Result<Vec<i32>, i32> {
+    let fd = anet_tcp_listen(&addr, options)?;
+    let mut listeners = vec![fd];
+    for _ in 1..count {
+        let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if dup == -1 {
+            let err = errno();
+            close_all(&listeners);
+            return Err(err);
+        }
+        listeners.push(dup);
+    }
+    Ok(listeners)
+}
+
+fn close_all(fds: &[i32]) {
+    for &fd in fds {
+        unsafe { libc::close(fd) };
+    }
+}
+
+/* CPUs the process may run on, in order. */
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn allowed_cpus() -> Vec<usize> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::cpu_set_t>();
+    if unsafe { libc::sched_getaffinity(0, size, &mut set) } == -1 {
+        return Vec::new();
+    }
+    (0..libc::CPU_SETSIZE as usize)
+        .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+        .collect()
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn pin_current_thread(cpu: usize) -> bool {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    unsafe { libc::CPU_SET(cpu, &mut set) };
+    let size = std::mem::size_of::<libc::cpu_set_t>();
+    unsafe { libc::sched_setaffinity(0, size, &set) == 0 }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn allowed_cpus() -> Vec<usize> {
+    Vec::new()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn pin_current_thread(_cpu: usize) -> bool {
+    false
+}
@@ -0,0 +1,196 @@
+/* Scopes of loop registrations.
+ *
+ * A subsystem built on the loop (a client library, a cache refresher)
+ * registers fds and timers whose callbacks point into its own state. When
+ * the subsystem is torn down, every one of them must go with it, or the
+ * loop later calls into freed memory. ae_scope() records what a setup
+ * closure registers through an AeScopeCtx and returns an AeScope guard:
+ * dropping the guard deletes the file events and timers of the scope.
+ *
+ * The guard holds no reference to the loop. Dropping it queues the scope,
+ * and the loop tears queued scopes down as soon as the callback that
+ * dropped it returns (or at the start of the next iteration when it was
+ * dropped outside of a callback), so no other callback of the scope runs
+ * in between. ae_scope_close() tears a scope down on the spot.
+ *
+ * Like groups (see group.rs), a scope remembers the generation of its
+ * fds: an fd deleted by other means and registered again by someone else
+ * is left alone.
+ */
+
+use crate::ae::{
+    AeEventLoop, ae_create_file_event, ae_create_time_event, ae_delete_file_event,
+    ae_delete_time_event, ae_get_file_generation,
+};
+use crate::constants::{AE_ERR, AE_OK};
+use crate::traits::{EventFinalizerProc, FileProc, TimeProc};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct Members {
+    /* fd, generation and the mask registered by the scope. */
+    fds: Vec<(i32, u64, i32)>,
+    timers: Vec<i64>,
+}
+
+#[derive(Default)]
+pub(crate) struct Scopes {
+    members: HashMap<u64, Members>,
+    next_id: u64,
+    /* Scopes whose guard was dropped, waiting for reap(). */
+    dropped: Rc<RefCell<Vec<u64>>>,
+}
+
+/* Guard of a scope, see ae_scope(). Dropping it tears the scope down. */
+#[derive(Debug)]
+pub struct AeScope {
+    id: u64,
+    dropped: Rc<RefCell<Vec<u64>>>,
+}
+
+impl Drop for AeScope {
+    fn drop(&mut self) {
+        self.dropped.borrow_mut().push(self.id);
+    }
+}
+
+/* Registers on behalf of a scope, see ae_scope(). */
+pub struct AeScopeCtx<'a> {
+    event_loop: &'a mut AeEventLoop,
+    members: Members,
+}
+
+impl AeScopeCtx<'_> {
+    /* ae_create_file_event(), with the registration owned by the scope. */
+    pub fn register(
+        &mut self,
+        fd: i32,
+        mask: i32,
+        proc: FileProc,
+        client_data: *mut c_void,
+    ) -> i32 {
+        if ae_create_file_event(self.event_loop, fd, mask, proc, client_data) == AE_ERR {
+            return AE_ERR;
+        }
+        let generation = ae_get_file_generation(self.event_loop, fd);
+        match self
+            .members
+            .fds
+            .iter_mut()
+            .find(|(member, member_generation, _)| {
+                *member == fd && *member_generation == generation
+            }) {
+            Some((_, _, member_mask)) => *member_mask |= mask,
+            None => self.members.fds.push((fd, generation, mask)),
+        }
+        AE_OK
+    }
+
+    /* ae_create_time_event(), with the timer owned by the scope. The
+     * finalizer runs when the scope deletes it, like on any deletion. */
+    pub fn spawn_timer(
+        &mut self,
+        milliseconds: i64,
+        proc: TimeProc,
+        client_data: *mut c_void,
+        finalizer_proc: Option<EventFinalizerProc>,
+    ) -> i64 {
+        let id = ae_create_time_event(
+            self.event_loop,
+            milliseconds,
+            proc,
+            client_data,
+            finalizer_proc,
+        );
+        if id != AE_ERR as i64 {
+            self.members.timers.push(id);
+        }
+        id
+    }
+
+    /* The loop, for setup that is not owned by the scope. */
+    pub fn event_loop(&mut self) -> &mut AeEventLoop {
+        self.event_loop
+    }
+}
+
+/* Run `setup`, which registers file events and timers through the
+ * AeScopeCtx it is given, and return the guard of the scope they belong
+ * to. */
+pub fn ae_scope(event_loop: &mut AeEventLoop, setup: impl FnOnce(&mut AeScopeCtx<'_>)) -> AeScope {
+    let mut ctx = AeScopeCtx {
+        event_loop,
+        members: Members::default(),
+    };
+    setup(&mut ctx);
+    let members = ctx.members;
+    let scopes = &mut event_loop.scopes;
+    let id = scopes.next_id;
+    scopes.next_id += 1;
+    scopes.members.insert(id, members);
+    AeScope {
+        id,
+        dropped: scopes.dropped.clone(),
+    }
+}
+
+/* Run `setup` to register more members in the open scope `scope`.
+ * Returns AE_ERR, without calling `setup`, if the scope belongs to another
+ * loop. */
+pub fn ae_scope_extend(
+    event_loop: &mut AeEventLoop,
+    scope: &AeScope,
+    setup: impl FnOnce(&mut AeScopeCtx<'_>),
+) -> i32 {
+    if !Rc::ptr_eq(&scope.dropped, &event_loop.scopes.dropped) {
+        return AE_ERR;
+    }
+    let Some(members) = event_loop.scopes.members.remove(&scope.id) else {
+        return AE_ERR;
+    };
+    let mut ctx = AeScopeCtx {
+        event_loop,
+        members,
+    };
+    setup(&mut ctx);
+    let members = ctx.members;
+    event_loop.scopes.members.insert(scope.id, members);
+    AE_OK
+}
+
+/* Tear `scope` down now instead of when the loop gets to it. */
+pub fn ae_scope_close(event_loop: &mut AeEventLoop, scope: AeScope) {
+    if Rc::ptr_eq(&scope.dropped, &event_loop.scopes.dropped) {
+        teardown(event_loop, scope.id);
+    }
+}
+
+/* Tear down the scopes dropped since the last call. Called after every
+ * callback and at the start of each iteration. */
+pub(crate) fn reap(event_loop: &mut AeEventLoop) {
+    if event_loop.scopes.dropped.borrow().is_empty() {
+        return;
+    }
+    let dropped = std::mem::take(&mut *event_loop.scopes.dropped.borrow_mut());
+    for id in dropped {
+        teardown(event_loop, id);
+    }
+}
+
+fn teardown(event_loop: &mut AeEventLoop, id: u64) {
+    let Some(members) = event_loop.scopes.members.remove(&id) else {
+        return;
+    };
+    for (fd, generation, mask) in members.fds {
+        if ae_get_file_generation(event_loop, fd) == generation {
+            ae_delete_file_event(event_loop, fd, mask);
+        }
+    }
+    for timer_id in members.timers {
+        /* Timers that returned AE_NOMORE are gone already. */
+        ae_delete_time_event(event_loop, timer_id);
+    }
+}
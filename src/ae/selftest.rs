@@ -0,0 +1,220 @@
+/* Startup self-test.
+ *
+ * ae_self_test() runs every backend built for this platform end to end on
+ * a small loop of its own: a byte written to a pipe must be read by a
+ * file event, a 1ms timer must fire, and a task posted from another
+ * thread must wake the loop up. The report says which of these work on
+ * the running kernel, which installers can check and support bundles can
+ * include. It takes a few milliseconds and leaves nothing behind.
+ */
+
+use crate::ae::handle::ae_get_handle;
+use crate::ae::{
+    AeEventLoop, ae_create_event_loop_with_backend, ae_create_file_event, ae_create_time_event,
+    ae_delete_event_loop, ae_delete_file_event, ae_loop_now, ae_process_events,
+};
+use crate::anet::anet_pipe;
+use crate::constants::{AE_ALL_EVENTS, AE_ERR, AE_NOMORE, AE_READABLE};
+use crate::monotonic::AeClockSource;
+use crate::traits::EventBackend;
+use std::ffi::c_void;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/* How long a backend gets to pass every check. */
+const AE_SELF_TEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/* Result of the checks run on one backend. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AeBackendCheck {
+    pub name: &'static str,
+    /* errno of the failed backend or loop creation, in which case no
+     * check ran. */
+    pub error: Option<i32>,
+    /* A pipe became readable and its file event was called. */
+    pub pipe: bool,
+    /* A 1ms timer fired, this long after it was created. */
+    pub timer_us: Option<u64>,
+    /* A task posted from another thread woke the loop up. */
+    pub wakeup: bool,
+}
+
+impl AeBackendCheck {
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.pipe && self.timer_us.is_some() && self.wakeup
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfTestReport {
+    /* Backend ae_create_event_loop() uses by default. */
+    pub default_backend: &'static str,
+    /* Every backend built for this platform, the default one first. */
+    pub backends: Vec<AeBackendCheck>,
+    /* Clock sources, see AeEventLoopBuilder::clock_source(), and whether
+     * they can be read here. */
+    pub clocks: Vec<(AeClockSource, bool)>,
+}
+
+impl SelfTestReport {
+    /* True if every backend passed every check. */
+    pub fn passed(&self) -> bool {
+        self.backends.iter().all(AeBackendCheck::passed)
+    }
+}
+
+/* Run the self-test. Fails with an errno only when the checks cannot run
+ * at all (no pipe or thread could be created); backends that do not work
+ * are reported, not returned as errors. */
+pub fn ae_self_test() -> Result<SelfTestReport, i32> {
+    let mut backends = vec![check_backend(
+        crate::ae_select::ae_api_name(),
+        crate::ae::create_select_backend(),
+    )?];
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    backends.push(check_backend(
+        "epoll",
+        crate::ae_epoll::aeApiState::create().map(|b| b as Box<dyn EventBackend>),
+    )?);
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    ))]
+    backends.push(check_backend(
+        "kqueue",
+        crate::ae_kqueue::aeApiState::create().map(|b| b as Box<dyn EventBackend>),
+    )?);
+
+    let clocks = [
+        AeClockSource::Instant,
+        AeClockSource::MonotonicRaw,
+        AeClockSource::MonotonicCoarse,
+        AeClockSource::Cycles,
+    ]
+    .into_iter()
+    .map(|clock| (clock, clock.is_supported()))
+    .collect();
+
+    Ok(SelfTestReport {
+        default_backend: crate::ae_select::ae_api_name(),
+        backends,
+        clocks,
+    })
+}
+
+#[derive(Default)]
+struct Probe {
+    pipe: bool,
+    timer_start_us: u64,
+    timer_us: Option<u64>,
+}
+
+fn probe<'a>(client_data: *mut c_void) -> &'a mut Probe {
+    unsafe { &mut *(client_data as *mut Probe) }
+}
+
+fn pipe_readable(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let mut buf = [0u8; 1];
+    unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, 1) };
+    probe(client_data).pipe = true;
+    ae_delete_file_event(event_loop, fd, AE_READABLE);
+}
+
+fn timer_fired(event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    let probe = probe(client_data);
+    probe.timer_us = Some(ae_loop_now(event_loop).saturating_sub(probe.timer_start_us));
+    AE_NOMORE
+}
+
+/* Keeps the loop from blocking forever on a backend that loses events. */
+fn tick(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    10
+}
+
+fn failed(name: &'static str, error: i32) -> AeBackendCheck {
+    AeBackendCheck {
+        name,
+        error: Some(error),
+        pipe: false,
+        timer_us: None,
+        wakeup: false,
+    }
+}
+
+fn check_backend(
+    name: &'static str,
+    backend: Result<Box<dyn EventBackend>, i32>,
+) -> Result<AeBackendCheck, i32> {
+    let backend = match backend {
+        Ok(backend) => backend,
+        Err(err) => return Ok(failed(name, err)),
+    };
+    let mut event_loop = match ae_create_event_loop_with_backend(64, backend) {
+        Some(event_loop) => event_loop,
+        None => return Ok(failed(name, libc::ENOMEM)),
+    };
+    let (rfd, wfd) = anet_pipe(true)?;
+    let result = run_checks(name, &mut event_loop, rfd, wfd);
+    ae_delete_event_loop(event_loop);
+    unsafe {
+        libc::close(rfd);
+        libc::close(wfd);
+    }
+    result
+}
+
+fn run_checks(
+    name: &'static str,
+    event_loop: &mut AeEventLoop,
+    rfd: i32,
+    wfd: i32,
+) -> Result<AeBackendCheck, i32> {
+    let mut state = Probe {
+        timer_start_us: ae_loop_now(event_loop),
+        ..Default::default()
+    };
+    let client_data = &mut state as *mut Probe as *mut c_void;
+    if ae_create_file_event(event_loop, rfd, AE_READABLE, pipe_readable, client_data) == AE_ERR {
+        return Ok(failed(name, crate::anet::errno()));
+    }
+    ae_create_time_event(event_loop, 1, timer_fired, client_data, None);
+    ae_create_time_event(event_loop, 10, tick, std::ptr::null_mut(), None);
+
+    let woken = Arc::new(AtomicBool::new(false));
+    let handle = match ae_get_handle(event_loop) {
+        Some(handle) => handle,
+        None => return Ok(failed(name, crate::anet::errno())),
+    };
+    let poster = {
+        let woken = woken.clone();
+        std::thread::Builder::new()
+            .name("rae-self-test".into())
+            .spawn(move || {
+                handle.post(move |_| woken.store(true, Ordering::Release));
+            })
+            .map_err(|err| err.raw_os_error().unwrap_or(libc::EAGAIN))?
+    };
+    unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+
+    let deadline = Instant::now() + AE_SELF_TEST_TIMEOUT;
+    while Instant::now() < deadline {
+        let done = probe(client_data);
+        if done.pipe && done.timer_us.is_some() && woken.load(Ordering::Acquire) {
+            break;
+        }
+        ae_process_events(event_loop, AE_ALL_EVENTS);
+    }
+    let _ = poster.join();
+    let state = probe(client_data);
+
+    Ok(AeBackendCheck {
+        name,
+        error: None,
+        pipe: state.pipe,
+        timer_us: state.timer_us,
+        wakeup: woken.load(Ordering::Acquire),
+    })
+}
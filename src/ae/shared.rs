@@ -0,0 +1,100 @@
+/* File events on fds shared between loops.
+ *
+ * Registering one fd in several loops directly is fragile: every loop
+ * believes it owns the fd number, and with epoll the registration belongs
+ * to the open file rather than to the number, so the interest outlives a
+ * close() of the fd as long as any duplicate of it is open. A loop that
+ * wants to watch an fd owned by someone else (a listener or a pipe shared
+ * by the loops of a pool) registers it through ae_create_shared_file_event()
+ * instead: the loop dup()s the fd, watches its private copy and closes it
+ * once its last file event on it is deleted, or when the loop is deleted.
+ * The owner keeps its fd and may close it whenever it likes.
+ *
+ * Handlers are called with the loop's copy. ae_shared_fd_origin() maps it
+ * back to the fd it was created from.
+ */
+
+use crate::ae::{
+    AeEventLoop, AeFileEventOptions, ae_create_file_event_ex, ae_delete_file_event,
+    ae_get_file_events,
+};
+use crate::constants::{AE_ERR, AE_NONE};
+use crate::traits::FileProc;
+use std::collections::HashMap;
+use std::ffi::c_void;
+
+#[derive(Default)]
+pub(crate) struct SharedFds {
+    /* Original fd to the loop's copy of it. */
+    copies: HashMap<i32, i32>,
+}
+
+/* Like ae_create_file_event_ex() on a copy of `fd` private to this loop,
+ * made on first use and reused by later registrations of the same fd.
+ * Returns the copy, which the handlers receive, or AE_ERR. */
+pub fn ae_create_shared_file_event(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    mask: i32,
+    proc: FileProc,
+    client_data: *mut c_void,
+    options: AeFileEventOptions,
+) -> i32 {
+    let (local, created) = match event_loop.shared.copies.get(&fd) {
+        Some(&local) => (local, false),
+        None => {
+            let local = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+            if local == -1 {
+                return AE_ERR;
+            }
+            (local, true)
+        }
+    };
+    if ae_create_file_event_ex(event_loop, local, mask, proc, client_data, options) == AE_ERR {
+        if created {
+            unsafe { libc::close(local) };
+        }
+        return AE_ERR;
+    }
+    event_loop.shared.copies.insert(fd, local);
+    local
+}
+
+/* Delete `mask` from the events registered on the copy of `fd`, and close
+ * the copy once none is left. Returns the copy, or AE_ERR if `fd` is not
+ * shared with this loop. */
+pub fn ae_delete_shared_file_event(event_loop: &mut AeEventLoop, fd: i32, mask: i32) -> i32 {
+    let local = match event_loop.shared.copies.get(&fd) {
+        Some(&local) => local,
+        None => return AE_ERR,
+    };
+    ae_delete_file_event(event_loop, local, mask);
+    if ae_get_file_events(event_loop, local) == AE_NONE {
+        event_loop.shared.copies.remove(&fd);
+        unsafe { libc::close(local) };
+    }
+    local
+}
+
+/* The loop's copy of a shared fd, None if `fd` is not shared with it. */
+pub fn ae_shared_fd_local(event_loop: &AeEventLoop, fd: i32) -> Option<i32> {
+    event_loop.shared.copies.get(&fd).copied()
+}
+
+/* The fd a copy given to a handler was made from, None if `local` is not
+ * a copy made by this loop. */
+pub fn ae_shared_fd_origin(event_loop: &AeEventLoop, local: i32) -> Option<i32> {
+    event_loop
+        .shared
+        .copies
+        .iter()
+        .find(|&(_, &copy)| copy == local)
+        .map(|(&fd, _)| fd)
+}
+
+/* Close the copies still open, when the loop is deleted. */
+pub(crate) fn close_all(event_loop: &mut AeEventLoop) {
+    for (_, local) in event_loop.shared.copies.drain() {
+        unsafe { libc::close(local) };
+    }
+}
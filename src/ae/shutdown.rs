@@ -0,0 +1,113 @@
+/* Shutdown token.
+ *
+ * A ShutdownToken is the one switch that tells a loop, and everything
+ * running on it, to shut down. Clones are handed to other threads and
+ * subsystems: any of them may cancel it, and long running work polls
+ * is_cancelled() to bail out early. On the loop side cancellation is an
+ * event like any other, delivered through the wakeup pipe of the loop
+ * handle (see handle.rs): the procs registered with ae_on_shutdown() run
+ * on the loop thread, once, and the loop stops.
+ *
+ * AeHandle::stop() and the stop byte written by signal handlers (see
+ * signal.rs) go through the same path, so whichever way the stop is
+ * requested, the token reads cancelled and the shutdown procs run.
+ */
+
+use crate::ae::handle::{AeHandle, ae_get_handle};
+use crate::ae::{AeEventLoop, ae_stop};
+use crate::constants::{AE_ERR, AE_OK};
+use crate::traits::ShutdownProc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+struct TokenShared {
+    cancelled: AtomicBool,
+    handle: AeHandle,
+}
+
+/* Clonable, Send + Sync cancellation token of a loop. */
+#[derive(Clone)]
+pub struct ShutdownToken {
+    shared: Arc<TokenShared>,
+}
+
+impl std::fmt::Debug for ShutdownToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShutdownToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ShutdownToken {
+    /* Request the shutdown, from any thread. Returns true for the call
+     * that cancelled the token, false if it already was. The loop runs
+     * its shutdown procs and stops at its next iteration. */
+    pub fn cancel(&self) -> bool {
+        if self.shared.cancelled.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        self.shared.handle.post(request);
+        true
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.shared.cancelled.load(Ordering::Acquire)
+    }
+
+    /* True if both tokens belong to the same loop. */
+    pub fn same_loop(&self, other: &ShutdownToken) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct ShutdownState {
+    token: Option<ShutdownToken>,
+    procs: Vec<ShutdownProc>,
+    /* The procs already ran. */
+    done: bool,
+}
+
+/* The shutdown token of the loop, created with the loop handle on first
+ * use. None if the wakeup pipe cannot be created. */
+pub fn ae_shutdown_token(event_loop: &mut AeEventLoop) -> Option<ShutdownToken> {
+    if let Some(token) = &event_loop.shutdown.token {
+        return Some(token.clone());
+    }
+    let handle = ae_get_handle(event_loop)?;
+    let token = ShutdownToken {
+        shared: Arc::new(TokenShared {
+            cancelled: AtomicBool::new(false),
+            handle,
+        }),
+    };
+    event_loop.shutdown.token = Some(token.clone());
+    Some(token)
+}
+
+/* Call `proc` on the loop thread when the shutdown is requested, before
+ * the loop stops. Procs run once, in registration order. Returns AE_ERR
+ * if the shutdown already happened. */
+pub fn ae_on_shutdown(event_loop: &mut AeEventLoop, proc: ShutdownProc) -> i32 {
+    if event_loop.shutdown.done {
+        return AE_ERR;
+    }
+    event_loop.shutdown.procs.push(proc);
+    AE_OK
+}
+
+/* Shut the loop down on its own thread: cancel the token, run the
+ * shutdown procs the first time, and stop. */
+pub(crate) fn request(event_loop: &mut AeEventLoop) {
+    if let Some(token) = &event_loop.shutdown.token {
+        token.shared.cancelled.store(true, Ordering::Release);
+    }
+    if !event_loop.shutdown.done {
+        event_loop.shutdown.done = true;
+        for proc in std::mem::take(&mut event_loop.shutdown.procs) {
+            proc(event_loop);
+        }
+    }
+    ae_stop(event_loop);
+}
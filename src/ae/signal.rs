@@ -0,0 +1,128 @@
+/* Stopping the loop from a signal handler.
+ *
+ * ae_stop() only sets a flag on the loop, which a signal handler cannot
+ * reach safely, and a signal landing between the check of the flag and
+ * the poll would be missed anyway. Instead the handler writes a stop byte
+ * to the wakeup pipe of the loop (see handle.rs): write(2) is
+ * async-signal-safe, the byte wakes the poll up whenever it arrives, and
+ * the loop turns it into a shutdown (see shutdown.rs) on its own thread.
+ *
+ * ae_stop_on_signal() installs such a handler for a signal. Applications
+ * with their own handler call ae_request_stop_from_signal() from it, with
+ * the fd returned by ae_signal_stop_fd().
+ *
+ * SIGPIPE is the other signal a server has to care about: a write to a
+ * socket whose peer is gone raises it, and its default action kills the
+ * process. Rust binaries start with it ignored, C programs embedding rae
+ * and binaries built with -Zon-broken-pipe=kill do not. Building a loop
+ * ignores it (see AeSigpipePolicy), so the write fails with EPIPE and the
+ * connection is closed like on any other error.
+ */
+
+use crate::ae::AeEventLoop;
+use crate::ae::handle::wake_fd;
+use crate::anet::errno_location;
+use crate::constants::{AE_ERR, AE_OK};
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/* What building a loop does with SIGPIPE, see AeEventLoopBuilder::sigpipe(). */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AeSigpipePolicy {
+    /* Ignore it, unless the application installed a handler of its own.
+     * The disposition is process-wide and inherited across exec(2). */
+    #[default]
+    Ignore,
+    /* Leave the disposition alone. */
+    Keep,
+}
+
+/* Wakeup pipe byte asking the loop to stop. Plain wakeups write 1. */
+pub(crate) const AE_WAKE_STOP: u8 = b's';
+
+/* Signals handled by ae_stop_on_signal() are below this number. */
+const AE_MAX_SIGNALS: usize = 65;
+
+/* Wakeup pipe of the loop to stop, per signal number, -1 for none. */
+static STOP_FDS: [AtomicI32; AE_MAX_SIGNALS] = [const { AtomicI32::new(-1) }; AE_MAX_SIGNALS];
+
+/* The fd to pass to ae_request_stop_from_signal() to stop this loop,
+ * creating the wakeup pipe if needed. -1 if it cannot be created. It is
+ * valid until the loop is deleted. */
+pub fn ae_signal_stop_fd(event_loop: &mut AeEventLoop) -> i32 {
+    wake_fd(event_loop)
+}
+
+/* Ask the loop owning `fd` (see ae_signal_stop_fd()) to stop at the end
+ * of its current iteration. Async-signal-safe: it only writes one byte,
+ * and errno is preserved. */
+pub fn ae_request_stop_from_signal(fd: i32) {
+    if fd < 0 {
+        return;
+    }
+    unsafe {
+        let errno = errno_location();
+        let saved = *errno;
+        let byte = AE_WAKE_STOP;
+        libc::write(fd, &byte as *const u8 as *const c_void, 1);
+        *errno = saved;
+    }
+}
+
+/* Stop the loop when `signo` is received (SIGTERM, SIGINT, ...), replacing
+ * the current handler of the signal. Returns AE_OK, or AE_ERR for an
+ * invalid signal or if the wakeup pipe cannot be created. */
+pub fn ae_stop_on_signal(event_loop: &mut AeEventLoop, signo: i32) -> i32 {
+    if signo <= 0 || signo as usize >= AE_MAX_SIGNALS {
+        return AE_ERR;
+    }
+    let fd = ae_signal_stop_fd(event_loop);
+    if fd == -1 {
+        return AE_ERR;
+    }
+    STOP_FDS[signo as usize].store(fd, Ordering::SeqCst);
+
+    let mut sa: libc::sigaction = unsafe { std::mem::zeroed() };
+    sa.sa_sigaction = stop_signal_handler as *const () as usize;
+    sa.sa_flags = libc::SA_RESTART;
+    unsafe { libc::sigemptyset(&mut sa.sa_mask) };
+    if unsafe { libc::sigaction(signo, &sa, std::ptr::null_mut()) } == -1 {
+        STOP_FDS[signo as usize].store(-1, Ordering::SeqCst);
+        return AE_ERR;
+    }
+    AE_OK
+}
+
+/* Ignore SIGPIPE if it still has its default action, which kills the
+ * process. A handler installed by the application is kept. Returns AE_OK,
+ * or AE_ERR if sigaction(2) fails. */
+pub fn ae_ignore_sigpipe() -> i32 {
+    let mut current: libc::sigaction = unsafe { std::mem::zeroed() };
+    if unsafe { libc::sigaction(libc::SIGPIPE, std::ptr::null(), &mut current) } == -1 {
+        return AE_ERR;
+    }
+    if current.sa_sigaction != libc::SIG_DFL {
+        return AE_OK;
+    }
+    let mut sa: libc::sigaction = unsafe { std::mem::zeroed() };
+    sa.sa_sigaction = libc::SIG_IGN;
+    unsafe { libc::sigemptyset(&mut sa.sa_mask) };
+    if unsafe { libc::sigaction(libc::SIGPIPE, &sa, std::ptr::null_mut()) } == -1 {
+        return AE_ERR;
+    }
+    AE_OK
+}
+
+extern "C" fn stop_signal_handler(signo: libc::c_int) {
+    if let Some(fd) = STOP_FDS.get(signo as usize) {
+        ae_request_stop_from_signal(fd.load(Ordering::Relaxed));
+    }
+}
+
+/* The wakeup pipe `fd` is going away: make the signals pointing to it
+ * harmless before the fd number gets reused. */
+pub(crate) fn forget_wake_fd(fd: i32) {
+    for slot in &STOP_FDS {
+        let _ = slot.compare_exchange(fd, -1, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,152 @@
+/* Registration snapshots.
+ *
+ * A leak in an event driven server is usually a registration nobody
+ * removes: an fd left registered after its connection went away, a timer
+ * never deleted. ae_snapshot() records the file and time events of a loop
+ * at one point, and LoopSnapshot::diff() what was registered and removed
+ * between two of them, so a soak test can take a snapshot before the
+ * load, another one once it drained, and assert the diff is empty.
+ *
+ * An fd closed and opened again in between is a different registration:
+ * it shows up as removed and added, not as unchanged.
+ */
+
+use crate::ae::AeEventLoop;
+use crate::constants::AE_NONE;
+
+/* One registered fd. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AeSnapshotFd {
+    pub fd: i32,
+    pub mask: i32,
+    pub tag: u32,
+    /* Releases of the slot before this registration, tells a reused fd
+     * number apart. */
+    pub generation: u64,
+}
+
+/* One pending timer. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AeSnapshotTimer {
+    pub id: i64,
+    /* When it is due, monotonic microseconds. */
+    pub when: u64,
+}
+
+/* Registrations of a loop at one point, see ae_snapshot(). Both lists are
+ * sorted, by fd and by timer id. */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoopSnapshot {
+    /* Iteration of the loop it was taken at. */
+    pub iteration: u64,
+    pub fds: Vec<AeSnapshotFd>,
+    pub timers: Vec<AeSnapshotTimer>,
+}
+
+/* What changed from one snapshot to a later one. */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    pub fds_added: Vec<AeSnapshotFd>,
+    pub fds_removed: Vec<AeSnapshotFd>,
+    /* Same registration with another mask or tag: (before, after). */
+    pub fds_changed: Vec<(AeSnapshotFd, AeSnapshotFd)>,
+    pub timers_added: Vec<AeSnapshotTimer>,
+    pub timers_removed: Vec<AeSnapshotTimer>,
+}
+
+impl LoopSnapshot {
+    /* Registrations added, removed and changed from `self` to `later`.
+     * A timer rescheduled by its callback keeps its id and is not a
+     * change. */
+    pub fn diff(&self, later: &LoopSnapshot) -> SnapshotDiff {
+        let mut diff = SnapshotDiff::default();
+
+        let (mut old, mut new) = (self.fds.iter().peekable(), later.fds.iter().peekable());
+        loop {
+            match (old.peek(), new.peek()) {
+                (Some(a), Some(b)) if a.fd == b.fd => {
+                    if a.generation != b.generation {
+                        diff.fds_removed.push(**a);
+                        diff.fds_added.push(**b);
+                    } else if a != b {
+                        diff.fds_changed.push((**a, **b));
+                    }
+                    old.next();
+                    new.next();
+                }
+                (Some(a), Some(b)) if a.fd < b.fd => diff.fds_removed.push(*old.next().unwrap()),
+                (Some(_), Some(_)) | (None, Some(_)) => diff.fds_added.push(*new.next().unwrap()),
+                (Some(_), None) => diff.fds_removed.push(*old.next().unwrap()),
+                (None, None) => break,
+            }
+        }
+
+        let (mut old, mut new) = (
+            self.timers.iter().peekable(),
+            later.timers.iter().peekable(),
+        );
+        loop {
+            match (old.peek(), new.peek()) {
+                (Some(a), Some(b)) if a.id == b.id => {
+                    old.next();
+                    new.next();
+                }
+                (Some(a), Some(b)) if a.id < b.id => diff.timers_removed.push(*old.next().unwrap()),
+                (Some(_), Some(_)) | (None, Some(_)) => {
+                    diff.timers_added.push(*new.next().unwrap())
+                }
+                (Some(_), None) => diff.timers_removed.push(*old.next().unwrap()),
+                (None, None) => break,
+            }
+        }
+        diff
+    }
+}
+
+impl SnapshotDiff {
+    /* Nothing registered, removed or changed. */
+    pub fn is_empty(&self) -> bool {
+        self.fds_added.is_empty()
+            && self.fds_removed.is_empty()
+            && self.fds_changed.is_empty()
+            && self.timers_added.is_empty()
+            && self.timers_removed.is_empty()
+    }
+}
+
+/* The file events and the timers not deleted yet of `event_loop`. Walks
+ * every fd slot and timer: cheap enough for tests, not for each
+ * iteration. */
+pub fn ae_snapshot(event_loop: &AeEventLoop) -> LoopSnapshot {
+    let fds = event_loop
+        .events
+        .iter()
+        .enumerate()
+        .filter(|(_, fe)| fe.mask != AE_NONE)
+        .map(|(fd, fe)| AeSnapshotFd {
+            fd: fd as i32,
+            mask: fe.mask,
+            tag: fe.tag,
+            generation: fe.generation,
+        })
+        .collect();
+
+    let mut timers = Vec::new();
+    let mut node = event_loop.time_event_head.as_deref();
+    while let Some(current) = node {
+        if !current.event.deleted {
+            timers.push(AeSnapshotTimer {
+                id: current.event.id,
+                when: current.event.when,
+            });
+        }
+        node = current.next.as_deref();
+    }
+    timers.sort_unstable_by_key(|timer| timer.id);
+
+    LoopSnapshot {
+        iteration: event_loop.iteration,
+        fds,
+        timers,
+    }
+}
@@ -0,0 +1,614 @@
+/* Loop statistics.
+ *
+ * Counters are updated at the end of every ae_process_events() call and
+ * read with ae_get_stats(), which returns a copy. Time spent blocked in
+ * the backend poll and time spent in each callback are accumulated in
+ * latency histograms. Resource usage sampling
+ * is optional (see AeEventLoopBuilder::rusage_sample_interval()): every N
+ * iterations getrusage() is called for the loop thread, so operators can
+ * tell a loop busy running callbacks (user time grows) from one starved by
+ * the OS (involuntary context switches grow, CPU time does not).
+ *
+ * Buffered connections (ae::conn) add their traffic to the totals here,
+ * and the loop turns it into a throughput once per rate window, like the
+ * instantaneous_input_kbps of Redis INFO.
+ *
+ * The backend counts the syscalls it issues (AeStats::backend), to tell
+ * whether batching registrations or polling for more events would pay.
+ *
+ * Loop utilization is the share of wall time the loop was not waiting in
+ * poll, over roughly the last AE_STATS_UTILIZATION_WINDOW_US. Close to 1
+ * the loop is saturated: events wait for the callbacks ahead of them and
+ * latency grows with load, whatever the CPU usage of the process says.
+ *
+ * Exporters that push metrics somewhere register a flush proc with
+ * ae_set_stats_flush_proc() instead of running a timer of their own: the
+ * loop calls it at the requested cadence with what the counters moved by
+ * since the previous call.
+ */
+
+use crate::ae::{
+    AeEventLoop, ae_create_time_event, ae_delete_time_event, dispatch, duration_ms, ms_duration,
+};
+use crate::constants::{AE_ERR, AE_NOMORE, AE_OK};
+use crate::traits::StatsFlushProc;
+use std::ffi::c_void;
+use std::time::Duration;
+
+/* Throughput is computed over windows of at least this long. */
+pub const AE_STATS_RATE_WINDOW_US: u64 = 1_000_000;
+
+/* Loop utilization covers about this much wall time, in slots that slide
+ * out one at a time. */
+pub const AE_STATS_UTILIZATION_WINDOW_US: u64 = 1_000_000;
+const UTILIZATION_SLOTS: usize = 10;
+const UTILIZATION_SLOT_US: u64 = AE_STATS_UTILIZATION_WINDOW_US / UTILIZATION_SLOTS as u64;
+
+/* CPU time and context switches of the loop thread. On platforms without
+ * per-thread accounting the values cover the whole process. */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AeRusage {
+    pub user_us: u64,
+    pub sys_us: u64,
+    pub voluntary_switches: u64,
+    pub involuntary_switches: u64,
+}
+
+impl AeRusage {
+    fn delta(&self, older: &AeRusage) -> AeRusage {
+        AeRusage {
+            user_us: self.user_us.saturating_sub(older.user_us),
+            sys_us: self.sys_us.saturating_sub(older.sys_us),
+            voluntary_switches: self
+                .voluntary_switches
+                .saturating_sub(older.voluntary_switches),
+            involuntary_switches: self
+                .involuntary_switches
+                .saturating_sub(older.involuntary_switches),
+        }
+    }
+}
+
+/* Sub-buckets per power of two: values are recorded with a relative
+ * error below 1/16 (~6%), like an HDR histogram with one significant
+ * digit. The `small` feature settles for 1/4 (25%), which takes a
+ * histogram from ~8KB down to ~2KB. */
+#[cfg(not(feature = "small"))]
+const HIST_SUB_BITS: u32 = 4;
+#[cfg(feature = "small")]
+const HIST_SUB_BITS: u32 = 2;
+const HIST_SUB_BUCKETS: usize = 1 << HIST_SUB_BITS;
+const HIST_BUCKETS: usize = (64 - HIST_SUB_BITS as usize + 1) * HIST_SUB_BUCKETS;
+
+/* Log-linear histogram of microsecond durations. */
+#[derive(Debug, Clone, Default)]
+pub struct AeHistogram {
+    /* Allocated on the first record() so idle histograms cost nothing. */
+    counts: Vec<u64>,
+    total: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl AeHistogram {
+    fn bucket_index(value: u64) -> usize {
+        if value < HIST_SUB_BUCKETS as u64 {
+            return value as usize;
+        }
+        let exp = 63 - value.leading_zeros();
+        let sub = (value >> (exp - HIST_SUB_BITS)) as usize & (HIST_SUB_BUCKETS - 1);
+        (exp - HIST_SUB_BITS + 1) as usize * HIST_SUB_BUCKETS + sub
+    }
+
+    /* Highest value that maps to the given bucket. */
+    fn bucket_upper_bound(index: usize) -> u64 {
+        if index < HIST_SUB_BUCKETS {
+            return index as u64;
+        }
+        let exp = (index / HIST_SUB_BUCKETS) as u32 + HIST_SUB_BITS - 1;
+        let sub = (index % HIST_SUB_BUCKETS) as u64;
+        let shift = exp - HIST_SUB_BITS;
+        ((HIST_SUB_BUCKETS as u64 + sub) << shift) + ((1u64 << shift) - 1)
+    }
+
+    pub fn record(&mut self, value: u64) {
+        if self.counts.is_empty() {
+            self.counts = vec![0; HIST_BUCKETS];
+            self.min = value;
+        }
+        self.counts[Self::bucket_index(value)] += 1;
+        self.total += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> u64 {
+        self.sum.checked_div(self.total).unwrap_or(0)
+    }
+
+    /* Value below which `quantile` (0.0 - 1.0) of the recorded values
+     * fall, 0 if nothing has been recorded. */
+    pub fn percentile(&self, quantile: f64) -> u64 {
+        if self.total == 0 {
+            return 0;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Self::bucket_upper_bound(index).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn p50(&self) -> u64 {
+        self.percentile(0.50)
+    }
+
+    pub fn p99(&self) -> u64 {
+        self.percentile(0.99)
+    }
+
+    pub fn p999(&self) -> u64 {
+        self.percentile(0.999)
+    }
+
+    pub fn reset(&mut self) {
+        *self = AeHistogram::default();
+    }
+}
+
+/* System calls issued by the event backend, see EventBackend::stats().
+ * Backends that do not keep count report zeros. */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AeBackendStats {
+    /* Calls waiting for events: epoll_wait(), kevent() with an event
+     * list, select(). */
+    pub poll_calls: u64,
+    /* Calls changing the interest set: epoll_ctl(), kevent() with a
+     * change list only. select() has none. */
+    pub ctl_calls: u64,
+    /* Poll calls that failed with EINTR. */
+    pub eintr: u64,
+    /* Most events returned by one poll call. */
+    pub max_batch: u64,
+}
+
+impl AeBackendStats {
+    /* Account for a poll call that returned `retval`. */
+    #[inline]
+    pub(crate) fn record_poll(&mut self, retval: libc::c_int, err: i32) {
+        self.poll_calls += 1;
+        if retval > 0 {
+            self.max_batch = self.max_batch.max(retval as u64);
+        } else if retval == -1 && err == libc::EINTR {
+            self.eintr += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AeStats {
+    /* Number of ae_process_events() calls. */
+    pub iterations: u64,
+    /* File and time events dispatched. */
+    pub file_events: u64,
+    pub time_events: u64,
+    /* Latest rusage sample, None until the first sample is taken or when
+     * sampling is disabled. */
+    pub rusage: Option<AeRusage>,
+    /* Usage between the two latest samples, and the wall clock time that
+     * elapsed between them. */
+    pub rusage_delta: Option<AeRusage>,
+    pub rusage_wall_us: u64,
+    /* Time spent blocked in the backend poll per iteration. */
+    pub poll_us: AeHistogram,
+    /* Time spent in each file event dispatch (read and write handlers of
+     * one fd) and each timer callback. */
+    pub callback_us: AeHistogram,
+    /* How late timers fired compared to their deadline. */
+    pub timer_lag_us: AeHistogram,
+    /* Polls that returned as many events as the fired buffer holds. */
+    pub fired_saturated: u64,
+    /* Polls interrupted by a signal (EINTR), retries included. */
+    pub interrupted_polls: u64,
+    /* Bytes read from and written to buffered connections. */
+    pub conn_bytes_in: u64,
+    pub conn_bytes_out: u64,
+    /* Buffered connections created, and open right now (not affected by
+     * ae_reset_stats()). */
+    pub conns_created: u64,
+    pub conns_open: u64,
+    /* Connection throughput over the latest rate window, in bytes per
+     * second. */
+    pub conn_input_bps: u64,
+    pub conn_output_bps: u64,
+    /* Slices run by incremental jobs, and those that took longer than
+     * their budget (see ae_slowlog_get()). */
+    pub job_slices: u64,
+    pub job_overruns: u64,
+    /* Timer callbacks that ran past their budget, and the firings skipped
+     * for it (see ae_set_time_event_budget()). */
+    pub timer_overruns: u64,
+    pub timer_firings_skipped: u64,
+    /* File events where AE_BARRIER put the write handler first (the fd
+     * was readable and writable, and the loop policy alone would have
+     * run the read handler first), and those of them where the write
+     * handler did run before the read handler. */
+    pub barrier_inversions: u64,
+    pub barrier_writes_before_reads: u64,
+    /* Callbacks that panicked, counted when the loop catches the panic
+     * (see ae_set_panic_policy()). */
+    pub callback_panics: u64,
+    /* Readable events dropped by AeReadCoalescing. */
+    pub coalesced_reads: u64,
+    /* Delays started by ae_backoff() after a transient error, and fds
+     * given up on after too many failures in a row. */
+    pub backoffs: u64,
+    pub backoffs_exhausted: u64,
+    /* Connections closed by the peer filter, see ae_set_peer_filter(). */
+    pub peers_filtered: u64,
+    /* Disagreements between the backends of a differential loop, see
+     * AeEventLoopBuilder::differential(). */
+    pub backend_divergences: u64,
+    /* Calls of ae_call_soon() and ae_yield_and_continue() waiting to run,
+     * and those the deferred limit pushed to a later iteration (see
+     * ae_set_deferred_limit()). */
+    pub deferred_backlog: u64,
+    pub deferred_postponed: u64,
+    /* Syscalls of the backend since the loop was created or the stats
+     * reset. */
+    pub backend: AeBackendStats,
+    /* See AeEventLoopBuilder::name(). */
+    pub loop_name: Option<String>,
+    /* Time not spent waiting in poll over the wall time of the
+     * utilization window, between 0 and 1. 0 until a second iteration
+     * ran. */
+    pub utilization: f64,
+    /* Wall time the utilization was computed over. */
+    pub utilization_window_us: u64,
+}
+
+/* What the counters of AeStats moved by between two calls of a flush
+ * proc (see ae_set_stats_flush_proc()), or during one iteration of
+ * ae_main_with(). */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AeStatsDelta {
+    /* Time since the previous flush, or since the proc was set. For
+     * ae_main_with(), the time the iteration took. */
+    pub interval_us: u64,
+    pub iterations: u64,
+    pub file_events: u64,
+    pub time_events: u64,
+    pub conn_bytes_in: u64,
+    pub conn_bytes_out: u64,
+    pub conns_created: u64,
+    pub callback_panics: u64,
+}
+
+impl AeStatsDelta {
+    /* The counters of `stats`, with no interval. */
+    pub(crate) fn totals(stats: &AeStats) -> Self {
+        AeStatsDelta {
+            interval_us: 0,
+            iterations: stats.iterations,
+            file_events: stats.file_events,
+            time_events: stats.time_events,
+            conn_bytes_in: stats.conn_bytes_in,
+            conn_bytes_out: stats.conn_bytes_out,
+            conns_created: stats.conns_created,
+            callback_panics: stats.callback_panics,
+        }
+    }
+
+    /* Counters are compared to 0 rather than to `older` after
+     * ae_reset_stats() took them below it. */
+    pub(crate) fn since(&self, older: &AeStatsDelta) -> Self {
+        let delta = |now: u64, then: u64| now.checked_sub(then).unwrap_or(now);
+        AeStatsDelta {
+            interval_us: 0,
+            iterations: delta(self.iterations, older.iterations),
+            file_events: delta(self.file_events, older.file_events),
+            time_events: delta(self.time_events, older.time_events),
+            conn_bytes_in: delta(self.conn_bytes_in, older.conn_bytes_in),
+            conn_bytes_out: delta(self.conn_bytes_out, older.conn_bytes_out),
+            conns_created: delta(self.conns_created, older.conns_created),
+            callback_panics: delta(self.callback_panics, older.callback_panics),
+        }
+    }
+}
+
+struct Flush {
+    proc: StatsFlushProc,
+    client_data: *mut c_void,
+    interval_ms: i64,
+    timer_id: i64,
+    /* Counters and loop time at the previous flush. */
+    base: AeStatsDelta,
+    base_us: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct StatsState {
+    pub(crate) stats: AeStats,
+    /* Take a rusage sample every this many iterations, 0 disables it. */
+    pub(crate) rusage_interval: u64,
+    last_sample_us: u64,
+    open_conns: u64,
+    /* Start of the current rate window, with the byte totals then. */
+    rate_base: Option<(u64, u64, u64)>,
+    utilization: Utilization,
+    flush: Option<Flush>,
+}
+
+/* Wall time and time waited in poll, per slot of the utilization
+ * window. */
+#[derive(Default)]
+struct Utilization {
+    slots: [(u64, u64); UTILIZATION_SLOTS],
+    current: usize,
+    /* End of the previous iteration. */
+    last_us: Option<u64>,
+    /* Waited in poll during the current iteration. */
+    waited_us: u64,
+}
+
+impl Utilization {
+    fn record_iteration(&mut self, now: u64) {
+        let waited = std::mem::take(&mut self.waited_us);
+        let last = match self.last_us.replace(now) {
+            Some(last) => last,
+            None => return,
+        };
+        let wall = now.saturating_sub(last);
+        let slot = &mut self.slots[self.current];
+        slot.0 += wall;
+        slot.1 += waited.min(wall);
+        if slot.0 >= UTILIZATION_SLOT_US {
+            self.current = (self.current + 1) % UTILIZATION_SLOTS;
+            self.slots[self.current] = (0, 0);
+        }
+    }
+
+    /* (utilization, wall time covered) */
+    fn ratio(&self) -> (f64, u64) {
+        let (wall, waited) = self.slots.iter().fold((0, 0), |(wall, waited), slot| {
+            (wall + slot.0, waited + slot.1)
+        });
+        if wall == 0 {
+            return (0.0, 0);
+        }
+        ((wall - waited) as f64 / wall as f64, wall)
+    }
+}
+
+/* Return a snapshot of the loop statistics. */
+pub fn ae_get_stats(event_loop: &AeEventLoop) -> AeStats {
+    let mut stats = event_loop.stats.stats.clone();
+    stats.conns_open = event_loop.stats.open_conns;
+    stats.deferred_backlog = dispatch::backlog(event_loop);
+    stats.backend = event_loop.apidata.stats();
+    stats.loop_name = event_loop.name.clone();
+    (stats.utilization, stats.utilization_window_us) = event_loop.stats.utilization.ratio();
+    stats
+}
+
+/* Zero all counters and histograms. The sampling interval is kept. */
+pub fn ae_reset_stats(event_loop: &mut AeEventLoop) {
+    event_loop.stats.stats = AeStats::default();
+    event_loop.stats.last_sample_us = 0;
+    event_loop.stats.rate_base = None;
+    event_loop.stats.utilization = Utilization::default();
+    event_loop.apidata.reset_stats();
+}
+
+/* Call `proc` every `interval_ms` milliseconds with what the counters
+ * moved by since its previous call. Setting a proc replaces the previous
+ * one, None removes it. Returns AE_ERR for a non-positive interval. */
+pub fn ae_set_stats_flush_proc(
+    event_loop: &mut AeEventLoop,
+    interval_ms: i64,
+    proc: Option<StatsFlushProc>,
+    client_data: *mut c_void,
+) -> i32 {
+    ae_set_stats_flush_proc_duration(event_loop, ms_duration(interval_ms), proc, client_data)
+}
+
+/* ae_set_stats_flush_proc() taking the interval as a Duration, in whole
+ * milliseconds rounded up. Returns AE_ERR for a zero interval. */
+pub fn ae_set_stats_flush_proc_duration(
+    event_loop: &mut AeEventLoop,
+    interval: Duration,
+    proc: Option<StatsFlushProc>,
+    client_data: *mut c_void,
+) -> i32 {
+    let interval_ms = duration_ms(interval);
+    if proc.is_some() && interval_ms == 0 {
+        return AE_ERR;
+    }
+    if let Some(flush) = event_loop.stats.flush.take() {
+        ae_delete_time_event(event_loop, flush.timer_id);
+    }
+    let proc = match proc {
+        Some(proc) => proc,
+        None => return AE_OK,
+    };
+    let timer_id = ae_create_time_event(
+        event_loop,
+        interval_ms,
+        flush_timer,
+        std::ptr::null_mut(),
+        None,
+    );
+    event_loop.stats.flush = Some(Flush {
+        proc,
+        client_data,
+        interval_ms,
+        timer_id,
+        base: AeStatsDelta::totals(&event_loop.stats.stats),
+        base_us: event_loop.cached_now_us,
+    });
+    AE_OK
+}
+
+fn flush_timer(event_loop: &mut AeEventLoop, id: i64, _client_data: *mut c_void) -> i32 {
+    let now = event_loop.cached_now_us;
+    let totals = AeStatsDelta::totals(&event_loop.stats.stats);
+    let (proc, client_data, interval_ms, delta) = match event_loop.stats.flush.as_mut() {
+        Some(flush) if flush.timer_id == id => {
+            let delta = AeStatsDelta {
+                interval_us: now.saturating_sub(flush.base_us),
+                ..totals.since(&flush.base)
+            };
+            flush.base = totals;
+            flush.base_us = now;
+            (flush.proc, flush.client_data, flush.interval_ms, delta)
+        }
+        _ => return AE_NOMORE,
+    };
+    proc(event_loop, &delta, client_data);
+    interval_ms.min(i32::MAX as i64) as i32
+}
+
+/* The hooks below run on the hot path. Without the `instrumentation`
+ * feature the histograms, the utilization and the rate and rusage
+ * sampling they feed are left alone: the hooks return at once and
+ * compile to nothing, the counters are still kept. */
+
+#[inline]
+pub(crate) fn record_poll(event_loop: &mut AeEventLoop, us: u64) {
+    if !cfg!(feature = "instrumentation") {
+        return;
+    }
+    event_loop.stats.stats.poll_us.record(us);
+    event_loop.stats.utilization.waited_us += us;
+}
+
+/* Start of a callback on the latency clock, for record_callback(). 0
+ * without instrumentation, which spares the clock read. */
+#[inline]
+pub(crate) fn callback_start(event_loop: &AeEventLoop) -> u64 {
+    if !cfg!(feature = "instrumentation") {
+        return 0;
+    }
+    event_loop.latency_now_us()
+}
+
+#[inline]
+pub(crate) fn record_callback(event_loop: &mut AeEventLoop, start: u64) {
+    if !cfg!(feature = "instrumentation") {
+        return;
+    }
+    let us = event_loop.latency_now_us().saturating_sub(start);
+    event_loop.stats.stats.callback_us.record(us);
+}
+
+#[inline]
+pub(crate) fn record_timer_lag(event_loop: &mut AeEventLoop, us: u64) {
+    if !cfg!(feature = "instrumentation") {
+        return;
+    }
+    event_loop.stats.stats.timer_lag_us.record(us);
+}
+
+pub(crate) fn record_conn_open(event_loop: &mut AeEventLoop) {
+    event_loop.stats.stats.conns_created += 1;
+    event_loop.stats.open_conns += 1;
+}
+
+pub(crate) fn record_conn_close(event_loop: &mut AeEventLoop) {
+    event_loop.stats.open_conns = event_loop.stats.open_conns.saturating_sub(1);
+}
+
+#[inline]
+pub(crate) fn record_conn_io(event_loop: &mut AeEventLoop, bytes_in: u64, bytes_out: u64) {
+    let stats = &mut event_loop.stats.stats;
+    stats.conn_bytes_in += bytes_in;
+    stats.conn_bytes_out += bytes_out;
+}
+
+pub(crate) fn record_iteration(event_loop: &mut AeEventLoop, file_events: i32, time_events: i32) {
+    let stats = &mut event_loop.stats.stats;
+    stats.iterations += 1;
+    stats.file_events += file_events as u64;
+    stats.time_events += time_events as u64;
+    if !cfg!(feature = "instrumentation") {
+        return;
+    }
+
+    update_rates(event_loop);
+    let now = event_loop.now_us();
+    event_loop.stats.utilization.record_iteration(now);
+    let stats = &mut event_loop.stats.stats;
+    let interval = event_loop.stats.rusage_interval;
+    if interval == 0 || !stats.iterations.is_multiple_of(interval) {
+        return;
+    }
+    let sample = match thread_rusage() {
+        Some(sample) => sample,
+        None => return,
+    };
+    let state = &mut event_loop.stats;
+    if let Some(previous) = state.stats.rusage {
+        state.stats.rusage_delta = Some(sample.delta(&previous));
+        state.stats.rusage_wall_us = now.saturating_sub(state.last_sample_us);
+    }
+    state.stats.rusage = Some(sample);
+    state.last_sample_us = now;
+}
+
+fn update_rates(event_loop: &mut AeEventLoop) {
+    let now = event_loop.cached_now_us;
+    let state = &mut event_loop.stats;
+    let (bytes_in, bytes_out) = (state.stats.conn_bytes_in, state.stats.conn_bytes_out);
+    let (since, base_in, base_out) = match state.rate_base {
+        Some(base) => base,
+        None => {
+            state.rate_base = Some((now, bytes_in, bytes_out));
+            return;
+        }
+    };
+    let elapsed = now.saturating_sub(since);
+    if elapsed < AE_STATS_RATE_WINDOW_US {
+        return;
+    }
+    let rate = |bytes: u64| (bytes as u128 * 1_000_000 / elapsed as u128) as u64;
+    state.stats.conn_input_bps = rate(bytes_in - base_in);
+    state.stats.conn_output_bps = rate(bytes_out - base_out);
+    state.rate_base = Some((now, bytes_in, bytes_out));
+}
+
+fn thread_rusage() -> Option<AeRusage> {
+    #[cfg(target_os = "linux")]
+    let who = libc::RUSAGE_THREAD;
+    #[cfg(not(target_os = "linux"))]
+    let who = libc::RUSAGE_SELF;
+
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(who, &mut usage) } == -1 {
+        return None;
+    }
+    let to_us = |tv: libc::timeval| tv.tv_sec as u64 * 1_000_000 + tv.tv_usec as u64;
+    Some(AeRusage {
+        user_us: to_us(usage.ru_utime),
+        sys_us: to_us(usage.ru_stime),
+        voluntary_switches: usage.ru_nvcsw as u64,
+        involuntary_switches: usage.ru_nivcsw as u64,
+    })
+}
@@ -0,0 +1,190 @@
+/* Line and chunk oriented registration for pipes, FIFOs and stdin.
+ *
+ * CLI tools and REPLs built on the loop want "call me with every line
+ * typed on stdin" rather than raw readability. This puts the fd in
+ * non-blocking mode, buffers partial reads and hands complete lines (or
+ * raw chunks) to a StreamProc, then restores the fd flags once the stream
+ * is unregistered or hits end of file.
+ */
+
+use crate::ae::{AeEventLoop, ae_create_file_event, ae_delete_file_event, ae_get_file_client_data};
+use crate::anet::{anet_block, anet_non_block, errno};
+use crate::constants::{AE_ERR, AE_OK, AE_READABLE};
+use crate::traits::StreamProc;
+use std::ffi::c_void;
+
+/* Bytes requested from the fd on each readable event. */
+const AE_STREAM_READ_LEN: usize = 16 * 1024;
+
+/* A line longer than this is delivered in pieces rather than buffered
+ * forever waiting for its newline. */
+pub const AE_STREAM_MAX_LINE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeStreamMode {
+    /* Deliver one call per newline-terminated line, without the "\n" (and
+     * a trailing "\r" if present). A final unterminated line is delivered
+     * before end of file. */
+    Lines,
+    /* Deliver whatever each read returned. */
+    Chunks,
+}
+
+struct StreamState {
+    mode: AeStreamMode,
+    proc: StreamProc,
+    client_data: *mut c_void,
+    buf: Vec<u8>,
+    /* Whether the fd was blocking before we registered it, so the flags
+     * can be restored (stdin is usually shared with the parent shell). */
+    was_blocking: bool,
+}
+
+/* Watch `fd` and call `proc` with its data as it arrives.
+ *
+ * The fd is switched to non-blocking mode for the lifetime of the
+ * registration. Returns AE_OK or AE_ERR. */
+pub fn ae_register_stream(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    mode: AeStreamMode,
+    proc: StreamProc,
+    client_data: *mut c_void,
+) -> i32 {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 || anet_non_block(fd) == AE_ERR {
+        return AE_ERR;
+    }
+
+    let state = Box::into_raw(Box::new(StreamState {
+        mode,
+        proc,
+        client_data,
+        buf: Vec::new(),
+        was_blocking: flags & libc::O_NONBLOCK == 0,
+    }));
+
+    if ae_create_file_event(
+        event_loop,
+        fd,
+        AE_READABLE,
+        stream_readable_handler,
+        state as *mut c_void,
+    ) == AE_ERR
+    {
+        let state = unsafe { Box::from_raw(state) };
+        if state.was_blocking {
+            anet_block(fd);
+        }
+        return AE_ERR;
+    }
+
+    AE_OK
+}
+
+/* Convenience wrapper registering the process stdin. */
+pub fn ae_register_stdin(
+    event_loop: &mut AeEventLoop,
+    mode: AeStreamMode,
+    proc: StreamProc,
+    client_data: *mut c_void,
+) -> i32 {
+    ae_register_stream(event_loop, libc::STDIN_FILENO, mode, proc, client_data)
+}
+
+/* Stop watching a stream registered with ae_register_stream(), dropping
+ * any buffered partial line and restoring the fd blocking mode. The fd
+ * itself is left open. */
+pub fn ae_unregister_stream(event_loop: &mut AeEventLoop, fd: i32) {
+    let client_data = ae_get_file_client_data(event_loop, fd);
+    if client_data.is_null() {
+        return;
+    }
+    let state = unsafe { Box::from_raw(client_data as *mut StreamState) };
+    release_stream(event_loop, fd, &state);
+}
+
+fn release_stream(event_loop: &mut AeEventLoop, fd: i32, state: &StreamState) {
+    ae_delete_file_event(event_loop, fd, AE_READABLE);
+    if state.was_blocking {
+        anet_block(fd);
+    }
+}
+
+fn stream_readable_handler(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    client_data: *mut c_void,
+    _mask: i32,
+) {
+    let state_ptr = client_data as *mut StreamState;
+    let mut chunk = [0u8; AE_STREAM_READ_LEN];
+
+    let nread = unsafe { libc::read(fd, chunk.as_mut_ptr() as *mut c_void, chunk.len()) };
+    if nread < 0 && errno() == libc::EAGAIN {
+        return;
+    }
+
+    if nread <= 0 {
+        /* End of file or hard error: flush what is left, tell the user and
+         * forget about the fd. */
+        let mut state = unsafe { Box::from_raw(state_ptr) };
+        release_stream(event_loop, fd, &state);
+        let rest = std::mem::take(&mut state.buf);
+        if !rest.is_empty() {
+            (state.proc)(event_loop, fd, Some(trim_line(&rest)), state.client_data);
+        }
+        (state.proc)(event_loop, fd, None, state.client_data);
+        return;
+    }
+
+    let data = &chunk[..nread as usize];
+    let (mode, proc, user_data) = unsafe {
+        (
+            (*state_ptr).mode,
+            (*state_ptr).proc,
+            (*state_ptr).client_data,
+        )
+    };
+
+    if mode == AeStreamMode::Chunks {
+        proc(event_loop, fd, Some(data), user_data);
+        return;
+    }
+
+    /* Take the buffer out of the state while delivering lines: the
+     * callback may unregister the stream, freeing the state. */
+    let mut buf = unsafe { std::mem::take(&mut (*state_ptr).buf) };
+    buf.extend_from_slice(data);
+
+    let mut start = 0;
+    while let Some(pos) = buf[start..].iter().position(|&b| b == b'\n') {
+        let line = &buf[start..start + pos];
+        start += pos + 1;
+        proc(event_loop, fd, Some(trim_line(line)), user_data);
+        if ae_get_file_client_data(event_loop, fd) != client_data {
+            /* Unregistered (and freed) from within the callback. */
+            return;
+        }
+    }
+
+    while buf.len() - start >= AE_STREAM_MAX_LINE {
+        proc(
+            event_loop,
+            fd,
+            Some(&buf[start..start + AE_STREAM_MAX_LINE]),
+            user_data,
+        );
+        start += AE_STREAM_MAX_LINE;
+        if ae_get_file_client_data(event_loop, fd) != client_data {
+            return;
+        }
+    }
+
+    buf.drain(..start);
+    unsafe { (*state_ptr).buf = buf };
+}
+
+fn trim_line(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
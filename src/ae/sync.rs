@@ -0,0 +1,284 @@
+/* Loop-local synchronization.
+ *
+ * Server logic running on one loop still has to coordinate: a request
+ * waits for a connection from a bounded pool, a reply waits for a value
+ * computed by another callback, a group of clients waits for a reload to
+ * finish. Everything runs on the same thread, so no atomics or channels
+ * are needed, only queues of waiters. The primitives here keep such
+ * queues behind a cheap Rc handle (they are not Send), and take a waiter
+ * as a continuation: a proc and the state it gets back, as with
+ * ae_yield_and_continue().
+ *
+ * A waiter is never called from within the call that wakes it up, or the
+ * one that registers it: its continuation is scheduled on the loop and
+ * runs at the next iteration. Callers do not have to worry about being
+ * re-entered while they hold borrowed state.
+ */
+
+use crate::ae::AeEventLoop;
+use crate::ae::dispatch::{AeDispatchCtx, ae_call_soon};
+use crate::traits::{ContinuationProc, OneshotProc};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+type Task = Box<dyn FnOnce(&mut AeEventLoop, &AeDispatchCtx)>;
+
+/* Waiters woken up, run in that order at the next iteration. */
+#[derive(Default)]
+pub(crate) struct ReadyQueue {
+    tasks: VecDeque<Task>,
+    /* A call draining the queue is scheduled. */
+    scheduled: bool,
+}
+
+fn run_ready(event_loop: &mut AeEventLoop, ctx: &AeDispatchCtx, _data: &mut ()) -> bool {
+    /* Waiters woken up from here wait for the next iteration. */
+    let tasks = std::mem::take(&mut event_loop.sync_ready.tasks);
+    event_loop.sync_ready.scheduled = false;
+    for task in tasks {
+        task(event_loop, ctx);
+    }
+    false
+}
+
+/* Run `task` at the next iteration, after the tasks deferred before. */
+fn defer(event_loop: &mut AeEventLoop, task: Task) {
+    let ready = &mut event_loop.sync_ready;
+    ready.tasks.push_back(task);
+    if !std::mem::replace(&mut ready.scheduled, true) {
+        ae_call_soon(event_loop, run_ready, Box::new(()));
+    }
+}
+
+fn continuation<T: 'static>(state: Box<T>, proc: ContinuationProc<T>) -> Task {
+    Box::new(move |event_loop, ctx| proc(event_loop, ctx, state))
+}
+
+#[derive(Default)]
+struct NotifyState {
+    waiters: VecDeque<Task>,
+    /* A notify_one() found nobody waiting. */
+    permit: bool,
+}
+
+/* Wakes up waiters when told to, like a condition variable that
+ * remembers one notification nobody was waiting for. Clones share the
+ * same waiters. */
+#[derive(Clone, Default)]
+pub struct AeNotify {
+    inner: Rc<RefCell<NotifyState>>,
+}
+
+impl AeNotify {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /* Call `proc` with `state` once notified: at the next iteration if a
+     * notification is pending, after the next notify_one() or
+     * notify_all() otherwise. */
+    pub fn wait<T: 'static>(
+        &self,
+        event_loop: &mut AeEventLoop,
+        state: Box<T>,
+        proc: ContinuationProc<T>,
+    ) {
+        let task = continuation(state, proc);
+        let mut inner = self.inner.borrow_mut();
+        if std::mem::take(&mut inner.permit) {
+            drop(inner);
+            defer(event_loop, task);
+        } else {
+            inner.waiters.push_back(task);
+        }
+    }
+
+    /* Wake the oldest waiter up. With nobody waiting, the next wait()
+     * returns at once (notifications do not add up). */
+    pub fn notify_one(&self, event_loop: &mut AeEventLoop) {
+        let task = {
+            let mut inner = self.inner.borrow_mut();
+            match inner.waiters.pop_front() {
+                Some(task) => task,
+                None => {
+                    inner.permit = true;
+                    return;
+                }
+            }
+        };
+        defer(event_loop, task);
+    }
+
+    /* Wake every waiter up. Nothing is remembered for later waiters. */
+    pub fn notify_all(&self, event_loop: &mut AeEventLoop) {
+        let waiters = std::mem::take(&mut self.inner.borrow_mut().waiters);
+        for task in waiters {
+            defer(event_loop, task);
+        }
+    }
+
+    pub fn waiters(&self) -> usize {
+        self.inner.borrow().waiters.len()
+    }
+}
+
+struct SemaphoreState {
+    permits: usize,
+    waiters: VecDeque<Task>,
+}
+
+/* Counts permits handed out to waiters in arrival order, e.g. to bound
+ * the requests in flight to a backend. Clones share the same permits. */
+#[derive(Clone)]
+pub struct AeSemaphore {
+    inner: Rc<RefCell<SemaphoreState>>,
+}
+
+impl AeSemaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(SemaphoreState {
+                permits,
+                waiters: VecDeque::new(),
+            })),
+        }
+    }
+
+    /* Take a permit and call `proc` with `state` once it is granted,
+     * at the next iteration if one is free. The permit is held until
+     * release(). */
+    pub fn acquire<T: 'static>(
+        &self,
+        event_loop: &mut AeEventLoop,
+        state: Box<T>,
+        proc: ContinuationProc<T>,
+    ) {
+        let task = continuation(state, proc);
+        let mut inner = self.inner.borrow_mut();
+        /* Waiters queued before get the free permits first. */
+        if inner.permits > 0 && inner.waiters.is_empty() {
+            inner.permits -= 1;
+            drop(inner);
+            defer(event_loop, task);
+        } else {
+            inner.waiters.push_back(task);
+        }
+    }
+
+    /* Give a permit back, straight to the oldest waiter if any. */
+    pub fn release(&self, event_loop: &mut AeEventLoop) {
+        let task = {
+            let mut inner = self.inner.borrow_mut();
+            match inner.waiters.pop_front() {
+                Some(task) => task,
+                None => {
+                    inner.permits += 1;
+                    return;
+                }
+            }
+        };
+        defer(event_loop, task);
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.inner.borrow().permits
+    }
+
+    pub fn waiters(&self) -> usize {
+        self.inner.borrow().waiters.len()
+    }
+}
+
+type ValueTask<V> = Box<dyn FnOnce(&mut AeEventLoop, &AeDispatchCtx, V)>;
+
+struct OneshotState<V> {
+    value: Option<V>,
+    receiver: Option<ValueTask<V>>,
+    /* The receiver was dropped without waiting. */
+    closed: bool,
+}
+
+/* Sending half of a oneshot, see ae_oneshot(). */
+pub struct AeOneshotSender<V> {
+    inner: Rc<RefCell<OneshotState<V>>>,
+}
+
+/* Receiving half of a oneshot, see ae_oneshot(). */
+pub struct AeOneshotReceiver<V> {
+    inner: Rc<RefCell<OneshotState<V>>>,
+}
+
+/* A channel for a single value, from one callback to another. */
+pub fn ae_oneshot<V: 'static>() -> (AeOneshotSender<V>, AeOneshotReceiver<V>) {
+    let inner = Rc::new(RefCell::new(OneshotState {
+        value: None,
+        receiver: None,
+        closed: false,
+    }));
+    (
+        AeOneshotSender {
+            inner: inner.clone(),
+        },
+        AeOneshotReceiver { inner },
+    )
+}
+
+impl<V: 'static> AeOneshotSender<V> {
+    /* Hand `value` to the receiver, whose proc runs at the next iteration
+     * if it is waiting already. Gives the value back if the receiver was
+     * dropped. */
+    pub fn send(self, event_loop: &mut AeEventLoop, value: V) -> Result<(), V> {
+        let mut inner = self.inner.borrow_mut();
+        if inner.closed {
+            return Err(value);
+        }
+        match inner.receiver.take() {
+            Some(receiver) => {
+                drop(inner);
+                defer(
+                    event_loop,
+                    Box::new(move |event_loop, ctx| receiver(event_loop, ctx, value)),
+                );
+            }
+            None => inner.value = Some(value),
+        }
+        Ok(())
+    }
+}
+
+impl<V: 'static> AeOneshotReceiver<V> {
+    /* Call `proc` with the value and `state` once it is sent, at the next
+     * iteration if it was sent already. If the sender is dropped without
+     * sending, `proc` is never called and `state` is dropped with it. */
+    pub fn recv<T: 'static>(
+        self,
+        event_loop: &mut AeEventLoop,
+        state: Box<T>,
+        proc: OneshotProc<V, T>,
+    ) {
+        let receiver: ValueTask<V> =
+            Box::new(move |event_loop, ctx, value| proc(event_loop, ctx, value, state));
+        let mut inner = self.inner.borrow_mut();
+        match inner.value.take() {
+            Some(value) => {
+                drop(inner);
+                defer(
+                    event_loop,
+                    Box::new(move |event_loop, ctx| receiver(event_loop, ctx, value)),
+                );
+            }
+            None => inner.receiver = Some(receiver),
+        }
+    }
+}
+
+impl<V> Drop for AeOneshotReceiver<V> {
+    fn drop(&mut self) {
+        let mut inner = self.inner.borrow_mut();
+        /* recv() consumes the receiver but keeps waiting. */
+        if inner.receiver.is_none() {
+            inner.closed = true;
+        }
+    }
+}
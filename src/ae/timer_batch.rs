@@ -0,0 +1,103 @@
+/* Batched timers.
+ *
+ * Metrics pipelines arm thousands of tiny timers and pay the dispatch of
+ * each of them (lookup, callback record, stats) on every tick. The timers
+ * of a batch share one TimeBatchProc instead: at each iteration the proc
+ * is called once with the ids of all the members that are due.
+ *
+ * Members are ordinary time events otherwise: they count in
+ * ae_pending_time_events(), are deleted with ae_delete_time_event() and
+ * the loop sleeps until the earliest of them.
+ */
+
+use crate::ae::{AeEventLoop, AeTimeEvent, TimeEventNode};
+use crate::constants::{AE_ERR, AE_OK};
+use crate::traits::TimeBatchProc;
+use std::collections::HashMap;
+use std::ffi::c_void;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AeTimerBatchId(pub(crate) u64);
+
+#[derive(Default)]
+pub(crate) struct TimerBatches {
+    procs: HashMap<u64, (TimeBatchProc, *mut c_void)>,
+    next_id: u64,
+}
+
+impl TimerBatches {
+    pub(crate) fn get(&self, id: u64) -> Option<(TimeBatchProc, *mut c_void)> {
+        self.procs.get(&id).copied()
+    }
+}
+
+/* Create an empty batch calling `proc` with its due members. The proc
+ * returns, like a TimeProc, AE_NOMORE to delete every timer it was given
+ * or the number of milliseconds until they fire again. */
+pub fn ae_create_timer_batch(
+    event_loop: &mut AeEventLoop,
+    proc: TimeBatchProc,
+    client_data: *mut c_void,
+) -> AeTimerBatchId {
+    let batches = &mut event_loop.timer_batches;
+    let id = batches.next_id;
+    batches.next_id += 1;
+    batches.procs.insert(id, (proc, client_data));
+    AeTimerBatchId(id)
+}
+
+/* Add a timer to `batch`, due in `milliseconds`. Returns its id, or
+ * AE_ERR for an unknown batch. */
+pub fn ae_create_batched_time_event(
+    event_loop: &mut AeEventLoop,
+    batch: AeTimerBatchId,
+    milliseconds: i64,
+) -> i64 {
+    if !event_loop.timer_batches.procs.contains_key(&batch.0) {
+        return AE_ERR as i64;
+    }
+    let id = event_loop.time_event_next_id;
+    event_loop.time_event_next_id += 1;
+
+    let when = event_loop
+        .now_us()
+        .saturating_add((milliseconds.max(0) as u64).saturating_mul(1000));
+    let mut time_event = AeTimeEvent::new(id, when, None, None, std::ptr::null_mut());
+    time_event.batch = Some(batch.0);
+
+    let mut new_node = Box::new(TimeEventNode::new(time_event));
+    new_node.next = event_loop.time_event_head.take();
+    event_loop.time_event_head = Some(new_node);
+    event_loop.live_time_events += 1;
+    id
+}
+
+/* Delete the batch and every timer still in it. Returns AE_ERR for an
+ * unknown batch. */
+pub fn ae_delete_timer_batch(event_loop: &mut AeEventLoop, batch: AeTimerBatchId) -> i32 {
+    if event_loop.timer_batches.procs.remove(&batch.0).is_none() {
+        return AE_ERR;
+    }
+    let mut current = &mut event_loop.time_event_head;
+    while let Some(node) = current {
+        let te = &mut node.event;
+        if te.batch == Some(batch.0) && te.mark_deleted() {
+            event_loop.live_time_events -= 1;
+        }
+        current = &mut node.next;
+    }
+    AE_OK
+}
+
+/* Number of live timers in the batch. */
+pub fn ae_timer_batch_len(event_loop: &AeEventLoop, batch: AeTimerBatchId) -> usize {
+    let mut len = 0;
+    let mut current = &event_loop.time_event_head;
+    while let Some(node) = current {
+        if node.event.batch == Some(batch.0) && !node.event.deleted {
+            len += 1;
+        }
+        current = &node.next;
+    }
+    len
+}
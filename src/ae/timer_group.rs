@@ -0,0 +1,150 @@
+/* Timer groups, exported and imported in bulk.
+ *
+ * A session handled by a state machine owns several timers (a request
+ * timeout, a keepalive, a retry), all of which have to follow it when a
+ * pool rebalances it to another loop. Tagging them with the same group
+ * (ae_set_time_event_group()) lets ae_export_timers() take them off the
+ * loop as plain values and ae_import_timers() schedule them on another
+ * one, as they were.
+ *
+ * An export keeps the delay left rather than the deadline, so it means
+ * the same on a loop with another clock. The procs, finalizers and client
+ * data move as is: they are pointers into the process, so an export goes
+ * from one loop of the process to another (through AeHandle::post(), say),
+ * not to another process. Ownership of the client data goes with it, the
+ * source loop neither calls nor finalizes the exported timers.
+ */
+
+use crate::ae::{AeEventLoop, AeOverrunPolicy, create_time_event_us};
+use crate::constants::{AE_ERR, AE_OK};
+use crate::traits::{EventFinalizerProc, TimeProc};
+use std::ffi::c_void;
+
+/* A timer taken off a loop by ae_export_timers(). */
+#[derive(Debug, Clone, Copy)]
+pub struct AeExportedTimer {
+    /* Id on the loop it was exported from. */
+    pub id: i64,
+    pub group: u64,
+    /* Time left until it is due, 0 if it already is. */
+    pub remaining_us: u64,
+    pub proc: TimeProc,
+    pub finalizer_proc: Option<EventFinalizerProc>,
+    pub client_data: *mut c_void,
+    /* See ae_set_time_event_jitter() and ae_set_time_event_budget(). */
+    pub jitter_pct: u32,
+    pub budget_us: u64,
+    pub overrun_policy: AeOverrunPolicy,
+}
+
+/* The client data is handed from one loop to the other and only ever used
+ * by the loop holding the timer, as with a timer created by a task posted
+ * to that loop. */
+unsafe impl Send for AeExportedTimer {}
+
+/* Put a timer in `group`, 0 to take it out of its group. Returns AE_ERR if
+ * there is no such timer, or if it is a member of a timer batch, which
+ * only moves with its batch. */
+pub fn ae_set_time_event_group(event_loop: &mut AeEventLoop, id: i64, group: u64) -> i32 {
+    let mut current = &mut event_loop.time_event_head;
+
+    while let Some(node) = current {
+        if node.event.id == id && !node.event.deleted {
+            if node.event.batch.is_some() {
+                return AE_ERR;
+            }
+            node.event.group = group;
+            return AE_OK;
+        }
+        current = &mut node.next;
+    }
+
+    AE_ERR
+}
+
+/* Ids of the pending timers of `group`. */
+pub fn ae_group_timers(event_loop: &AeEventLoop, group: u64) -> Vec<i64> {
+    let mut ids = Vec::new();
+    let mut current = &event_loop.time_event_head;
+    while let Some(node) = current {
+        if node.event.group == group && group != 0 && !node.event.deleted {
+            ids.push(node.event.id);
+        }
+        current = &node.next;
+    }
+    ids
+}
+
+/* Take the pending timers of `group` off the loop, soonest first. They
+ * are deleted without calling their finalizers, which are exported with
+ * them. Returns None, leaving every timer in place, for group 0 or if the
+ * callback of one of them is running: the export would run it on two
+ * loops. */
+pub fn ae_export_timers(event_loop: &mut AeEventLoop, group: u64) -> Option<Vec<AeExportedTimer>> {
+    if group == 0 {
+        return None;
+    }
+    let mut current = &event_loop.time_event_head;
+    while let Some(node) = current {
+        let te = &node.event;
+        if te.group == group && !te.deleted && te.refcount > 0 {
+            return None;
+        }
+        current = &node.next;
+    }
+
+    let now = event_loop.now_us();
+    let mut exported = Vec::new();
+    let mut current = &mut event_loop.time_event_head;
+    while let Some(node) = current {
+        let te = &mut node.event;
+        if te.group == group
+            && !te.deleted
+            && let Some(proc) = te.time_proc
+        {
+            exported.push(AeExportedTimer {
+                id: te.id,
+                group,
+                remaining_us: te.when.saturating_sub(now),
+                proc,
+                finalizer_proc: te.finalizer_proc.take(),
+                client_data: te.client_data,
+                jitter_pct: te.jitter_pct,
+                budget_us: te.budget_us,
+                overrun_policy: te.overrun_policy,
+            });
+            te.mark_deleted();
+            event_loop.live_time_events -= 1;
+        }
+        current = &mut node.next;
+    }
+    exported.sort_by_key(|timer| (timer.remaining_us, timer.id));
+    Some(exported)
+}
+
+/* Schedule exported timers (see ae_export_timers()) on this loop, each
+ * due once its remaining time has elapsed, in its group and with its
+ * jitter and budget. Returns their new ids, in order. */
+pub fn ae_import_timers(event_loop: &mut AeEventLoop, timers: &[AeExportedTimer]) -> Vec<i64> {
+    let mut ids = Vec::with_capacity(timers.len());
+    for timer in timers {
+        let id = create_time_event_us(
+            event_loop,
+            timer.remaining_us,
+            timer.proc,
+            timer.client_data,
+            timer.finalizer_proc,
+        );
+        let te = &mut event_loop
+            .time_event_head
+            .as_mut()
+            .expect("timer just created")
+            .event;
+        te.group = timer.group;
+        te.jitter_pct = timer.jitter_pct;
+        te.budget_us = timer.budget_us;
+        te.overrun_policy = timer.overrun_policy;
+        ids.push(id);
+    }
+    ids
+}
@@ -0,0 +1,230 @@
+/* Timers-only loop.
+ *
+ * A scheduler that never watches an fd still pays for a regular loop:
+ * the kqueue or epoll fd of its backend, and the wakeup pipe of its
+ * AeHandle, two or three fds per instance. AeTimerLoop is a zero-size
+ * loop (see ae_create_event_loop()) with a backend that holds nothing,
+ * which waits for its next timer parked on a condition variable. Other
+ * threads wake it up, post closures to it or stop it through an
+ * AeTimerLoopHandle, the fd-free counterpart of AeHandle.
+ *
+ * Everything else is the loop itself: the timer API (ae_create_time_event()
+ * and friends, ae_call_soon(), cron events, batches...) takes it as an
+ * AeEventLoop through Deref, callbacks get the usual `&mut AeEventLoop`,
+ * and ae_stop() works from them. File registrations fail with ERANGE.
+ */
+
+use crate::ae::builder::AeEventLoopBuilder;
+use crate::ae::handle::AeTask;
+use crate::ae::{AeEventLoop, ae_main, shutdown};
+use crate::ae_select::FiredEvent;
+use crate::constants::{AE_ERR, AE_OK};
+use crate::traits::EventBackend;
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+struct ParkState {
+    /* Set by wakeup() and post(), cleared by the loop once awake. */
+    woken: bool,
+    tasks: VecDeque<AeTask>,
+    /* The loop is gone. */
+    closed: bool,
+}
+
+/* What the loop parks on, shared with the handles. */
+pub(crate) struct Parker {
+    state: Mutex<ParkState>,
+    wake: Condvar,
+}
+
+impl Parker {
+    fn new() -> Self {
+        Parker {
+            state: Mutex::new(ParkState {
+                woken: false,
+                tasks: VecDeque::new(),
+                closed: false,
+            }),
+            wake: Condvar::new(),
+        }
+    }
+}
+
+/* Wait of a zero-size loop, called instead of polling the backend: park
+ * until `timeout` or a handle wakes the loop up, then run the posted
+ * closures. Loops that are not an AeTimerLoop have nothing to wake them
+ * up and just sleep. */
+pub(crate) fn sleep(event_loop: &mut AeEventLoop, timeout: Option<Duration>) {
+    let Some(parker) = event_loop.parker.clone() else {
+        match timeout {
+            Some(timeout) => std::thread::sleep(timeout),
+            None => std::thread::park(),
+        }
+        return;
+    };
+
+    let mut state = parker.state.lock().unwrap();
+    state = match timeout {
+        Some(timeout) => {
+            parker
+                .wake
+                .wait_timeout_while(state, timeout, |state| !state.woken)
+                .unwrap()
+                .0
+        }
+        None => parker.wake.wait_while(state, |state| !state.woken).unwrap(),
+    };
+    state.woken = false;
+    let tasks = std::mem::take(&mut state.tasks);
+    drop(state);
+
+    for task in tasks {
+        task(event_loop);
+    }
+}
+
+/* Clonable, Send + Sync handle to an AeTimerLoop, see AeHandle for the
+ * loops watching fds. */
+#[derive(Clone)]
+pub struct AeTimerLoopHandle {
+    parker: Arc<Parker>,
+}
+
+impl std::fmt::Debug for AeTimerLoopHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AeTimerLoopHandle")
+            .field("closed", &self.is_closed())
+            .finish_non_exhaustive()
+    }
+}
+
+impl AeTimerLoopHandle {
+    /* Queue `task` to run on the loop thread and wake the loop up.
+     * Returns AE_ERR if the loop has already been dropped. */
+    pub fn post<F>(&self, task: F) -> i32
+    where
+        F: FnOnce(&mut AeEventLoop) + Send + 'static,
+    {
+        let mut state = self.parker.state.lock().unwrap();
+        if state.closed {
+            return AE_ERR;
+        }
+        state.tasks.push_back(Box::new(task));
+        state.woken = true;
+        self.parker.wake.notify_one();
+        AE_OK
+    }
+
+    /* Wake the loop up without queueing anything: its timers are looked
+     * at again. */
+    pub fn wakeup(&self) -> i32 {
+        let mut state = self.parker.state.lock().unwrap();
+        if state.closed {
+            return AE_ERR;
+        }
+        state.woken = true;
+        self.parker.wake.notify_one();
+        AE_OK
+    }
+
+    /* Ask the loop to shut down, as AeHandle::stop() does. */
+    pub fn stop(&self) -> i32 {
+        self.post(shutdown::request)
+    }
+
+    /* True once the loop has been dropped. */
+    pub fn is_closed(&self) -> bool {
+        self.parker.state.lock().unwrap().closed
+    }
+}
+
+pub struct AeTimerLoop {
+    event_loop: Box<AeEventLoop>,
+}
+
+impl AeTimerLoop {
+    /* A timers-only loop with the defaults, see
+     * AeEventLoopBuilder::build_timer_loop() for the others. */
+    pub fn new() -> Option<AeTimerLoop> {
+        AeEventLoopBuilder::new(0).build_timer_loop()
+    }
+
+    pub(crate) fn from_loop(mut event_loop: Box<AeEventLoop>) -> AeTimerLoop {
+        event_loop.parker = Some(Arc::new(Parker::new()));
+        AeTimerLoop { event_loop }
+    }
+
+    pub fn handle(&self) -> AeTimerLoopHandle {
+        AeTimerLoopHandle {
+            parker: self.event_loop.parker.clone().expect("timer loop parker"),
+        }
+    }
+
+    /* ae_main() on the loop: run until ae_stop() or a handle stops it. */
+    pub fn run(&mut self) {
+        ae_main(&mut self.event_loop);
+    }
+}
+
+impl Deref for AeTimerLoop {
+    type Target = AeEventLoop;
+
+    fn deref(&self) -> &AeEventLoop {
+        &self.event_loop
+    }
+}
+
+impl DerefMut for AeTimerLoop {
+    fn deref_mut(&mut self) -> &mut AeEventLoop {
+        &mut self.event_loop
+    }
+}
+
+impl Drop for AeTimerLoop {
+    fn drop(&mut self) {
+        if let Some(parker) = &self.event_loop.parker {
+            let mut state = parker.state.lock().unwrap();
+            state.closed = true;
+            /* Closures that never ran are dropped here, on the loop
+             * thread. */
+            state.tasks.clear();
+        }
+    }
+}
+
+/* Backend of a timer loop: watches nothing, never polled. */
+pub(crate) struct NoBackend;
+
+impl EventBackend for NoBackend {
+    fn create() -> Result<Box<Self>, i32> {
+        Ok(Box::new(NoBackend))
+    }
+
+    fn free(self: Box<Self>) {}
+
+    fn resize(&mut self, setsize: i32) -> i32 {
+        if setsize == 0 { 0 } else { -1 }
+    }
+
+    fn add_event(&mut self, _fd: i32, _mask: i32) -> i32 {
+        -1
+    }
+
+    fn del_event(&mut self, _fd: i32, _mask: i32) {}
+
+    fn poll(
+        &mut self,
+        _events: &[crate::ae::AeFileEvent],
+        _fired: &mut [FiredEvent],
+        _maxfd: i32,
+        _timeout: Option<Duration>,
+    ) -> Result<i32, i32> {
+        Ok(0)
+    }
+
+    fn name(&self) -> &'static str {
+        "none"
+    }
+}
@@ -0,0 +1,80 @@
+/* Weak references to time events.
+ *
+ * A raw timer id stays valid in application code after the timer is gone
+ * (it returned AE_NOMORE, or was deleted elsewhere). A TimeEventRef
+ * observes the deletion, so code holding one can tell whether the timer
+ * still exists and never deletes anything but the timer it was taken
+ * from, even across loops.
+ */
+
+use crate::ae::{AeEventLoop, ae_delete_time_event};
+use crate::constants::AE_ERR;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Debug, Clone)]
+pub struct TimeEventRef {
+    id: i64,
+    alive: Arc<AtomicBool>,
+}
+
+impl TimeEventRef {
+    /* Id of the referenced timer, whether it is still alive or not. */
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /* False once the timer has been deleted (AE_NOMORE,
+     * ae_delete_time_event() or loop deletion). */
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::Acquire)
+    }
+
+    /* The timer id if the timer still exists. */
+    pub fn upgrade(&self) -> Option<i64> {
+        self.is_alive().then_some(self.id)
+    }
+}
+
+/* Return a weak reference to the timer `id`, None if there is no such
+ * timer or it is already deleted. */
+pub fn ae_time_event_ref(event_loop: &mut AeEventLoop, id: i64) -> Option<TimeEventRef> {
+    let mut current = &mut event_loop.time_event_head;
+    while let Some(node) = current {
+        let te = &mut node.event;
+        if te.id == id && !te.deleted {
+            let alive = te
+                .liveness
+                .get_or_insert_with(|| Arc::new(AtomicBool::new(true)))
+                .clone();
+            return Some(TimeEventRef { id, alive });
+        }
+        current = &mut node.next;
+    }
+    None
+}
+
+/* Delete the referenced timer. Returns AE_ERR if it is already gone or
+ * belongs to another loop. */
+pub fn ae_delete_time_event_ref(event_loop: &mut AeEventLoop, timer: &TimeEventRef) -> i32 {
+    if !timer.is_alive() {
+        return AE_ERR;
+    }
+
+    let mut current = &event_loop.time_event_head;
+    while let Some(node) = current {
+        let te = &node.event;
+        if te.id == timer.id {
+            let same_timer = te
+                .liveness
+                .as_ref()
+                .is_some_and(|alive| Arc::ptr_eq(alive, &timer.alive));
+            if !same_timer {
+                return AE_ERR;
+            }
+            return ae_delete_time_event(event_loop, timer.id);
+        }
+        current = &node.next;
+    }
+    AE_ERR
+}
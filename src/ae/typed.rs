@@ -0,0 +1,191 @@
+/* Typed registration identifiers.
+ *
+ * The C-style API identifies timers by an i64 id and file events by the
+ * fd, both plain integers that are easy to mix up with each other (or
+ * with a byte count) in application code. The functions below register
+ * the same events but hand back opaque TimeEventId / FileEventKey values,
+ * and the matching delete and modify functions only accept those.
+ *
+ * A FileEventKey also remembers the generation of the fd slot (see
+ * ae_get_file_generation()), so a key kept after the fd was deleted and
+ * the number reused by another file is rejected instead of acting on the
+ * new registration.
+ *
+ * Both convert to and from the raw values to mix with the C-style API.
+ */
+
+use crate::ae::{
+    AeEventLoop, ae_create_file_event, ae_create_periodic_event, ae_create_time_event,
+    ae_create_time_event_owned, ae_delete_file_event, ae_delete_time_event, ae_get_file_events,
+    ae_get_file_generation, ae_set_time_event_jitter,
+};
+use crate::constants::{AE_ERR, AE_NONE, AE_OK};
+use crate::traits::{EventFinalizerProc, FileProc, OwnedTimeProc, PeriodicTimeProc, TimeProc};
+use std::ffi::c_void;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimeEventId(i64);
+
+impl TimeEventId {
+    /* Wrap an id returned by the C-style API. */
+    pub fn from_raw(id: i64) -> Self {
+        TimeEventId(id)
+    }
+
+    pub fn as_raw(self) -> i64 {
+        self.0
+    }
+}
+
+impl From<TimeEventId> for i64 {
+    fn from(id: TimeEventId) -> i64 {
+        id.0
+    }
+}
+
+impl std::fmt::Display for TimeEventId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "timer#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileEventKey {
+    fd: i32,
+    generation: u64,
+}
+
+impl FileEventKey {
+    /* Key of the current registration of `fd`, None if the fd is not
+     * registered. */
+    pub fn from_raw(event_loop: &AeEventLoop, fd: i32) -> Option<Self> {
+        if fd < 0 || ae_get_file_events(event_loop, fd) == AE_NONE {
+            return None;
+        }
+        Some(FileEventKey {
+            fd,
+            generation: ae_get_file_generation(event_loop, fd),
+        })
+    }
+
+    pub fn fd(self) -> i32 {
+        self.fd
+    }
+
+    pub fn generation(self) -> u64 {
+        self.generation
+    }
+}
+
+impl From<FileEventKey> for i32 {
+    fn from(key: FileEventKey) -> i32 {
+        key.fd
+    }
+}
+
+impl std::fmt::Display for FileEventKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "fd#{}.{}", self.fd, self.generation)
+    }
+}
+
+/* ae_create_time_event() returning a TimeEventId. */
+pub fn ae_add_time_event(
+    event_loop: &mut AeEventLoop,
+    milliseconds: i64,
+    proc: TimeProc,
+    client_data: *mut c_void,
+    finalizer_proc: Option<EventFinalizerProc>,
+) -> TimeEventId {
+    TimeEventId(ae_create_time_event(
+        event_loop,
+        milliseconds,
+        proc,
+        client_data,
+        finalizer_proc,
+    ))
+}
+
+/* ae_create_time_event_owned() returning a TimeEventId. */
+pub fn ae_add_time_event_owned<T: 'static>(
+    event_loop: &mut AeEventLoop,
+    milliseconds: i64,
+    proc: OwnedTimeProc<T>,
+    data: Box<T>,
+) -> TimeEventId {
+    TimeEventId(ae_create_time_event_owned(
+        event_loop,
+        milliseconds,
+        proc,
+        data,
+    ))
+}
+
+/* ae_create_periodic_event() returning a TimeEventId, None if period_ms
+ * is not positive. */
+pub fn ae_add_periodic_event(
+    event_loop: &mut AeEventLoop,
+    period_ms: i64,
+    proc: PeriodicTimeProc,
+    client_data: *mut c_void,
+    finalizer_proc: Option<EventFinalizerProc>,
+) -> Option<TimeEventId> {
+    let id = ae_create_periodic_event(event_loop, period_ms, proc, client_data, finalizer_proc);
+    (id != AE_ERR as i64).then_some(TimeEventId(id))
+}
+
+/* ae_delete_time_event() by TimeEventId. */
+pub fn ae_remove_time_event(event_loop: &mut AeEventLoop, id: TimeEventId) -> i32 {
+    ae_delete_time_event(event_loop, id.0)
+}
+
+/* ae_set_time_event_jitter() by TimeEventId. */
+pub fn ae_set_timer_jitter(event_loop: &mut AeEventLoop, id: TimeEventId, percent: u32) -> i32 {
+    ae_set_time_event_jitter(event_loop, id.0, percent)
+}
+
+/* ae_create_file_event() returning the key of the registration, None on
+ * failure. Adding events to an fd already registered returns the key it
+ * already had. */
+pub fn ae_add_file_event(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    mask: i32,
+    proc: FileProc,
+    client_data: *mut c_void,
+) -> Option<FileEventKey> {
+    if fd < 0 || ae_create_file_event(event_loop, fd, mask, proc, client_data) == AE_ERR {
+        return None;
+    }
+    FileEventKey::from_raw(event_loop, fd)
+}
+
+/* True while the registration the key was taken from is in place. */
+pub fn ae_file_event_key_valid(event_loop: &AeEventLoop, key: FileEventKey) -> bool {
+    FileEventKey::from_raw(event_loop, key.fd) == Some(key)
+}
+
+/* ae_delete_file_event() by key. Returns AE_ERR, leaving the fd alone, if
+ * the registration is gone (the fd may belong to another file now). */
+pub fn ae_remove_file_event(event_loop: &mut AeEventLoop, key: FileEventKey, mask: i32) -> i32 {
+    if !ae_file_event_key_valid(event_loop, key) {
+        return AE_ERR;
+    }
+    ae_delete_file_event(event_loop, key.fd, mask);
+    AE_OK
+}
+
+/* Add events to the registration of the key, with `proc` handling them.
+ * Returns AE_ERR if the registration is gone or the backend fails. */
+pub fn ae_modify_file_event(
+    event_loop: &mut AeEventLoop,
+    key: FileEventKey,
+    mask: i32,
+    proc: FileProc,
+    client_data: *mut c_void,
+) -> i32 {
+    if !ae_file_event_key_valid(event_loop, key) {
+        return AE_ERR;
+    }
+    ae_create_file_event(event_loop, key.fd, mask, proc, client_data)
+}
@@ -0,0 +1,293 @@
+/* Batched UDP receive.
+ *
+ * A QUIC-like server gets many small datagrams per wakeup, and one
+ * recvfrom(2) per datagram makes the syscalls the cost of the wakeup.
+ * ae_udp_register() watches a UDP socket and, when it becomes readable,
+ * reads up to `batch` datagrams with one recvmmsg(2) (one recvmsg(2) per
+ * datagram where recvmmsg is missing) and hands them to the proc
+ * together. Full batches are read again, up to AE_UDP_MAX_BATCHES per
+ * event, so that one busy socket does not starve the others.
+ *
+ * With UDP_GRO on the socket (anet_set_udp_gro()) a buffer may hold
+ * several datagrams of one sender, reported with their segment size. The
+ * send side is anet_udp_send_batch() and anet_udp_send_segments().
+ *
+ * Raw sockets (anet_raw_socket()) and packet sockets (anet_packet_socket())
+ * are read the same way, for a monitoring tool sharing the loop with its
+ * control plane. Their datagrams start with the IP header, or the link
+ * layer header, and a packet socket reports no sender.
+ */
+
+use crate::ae::{AeEventLoop, ae_create_file_event, ae_delete_file_event, ae_get_file_client_data};
+use crate::anet::{errno, raw_to_socket_addr};
+use crate::constants::{AE_ERR, AE_OK, AE_READABLE};
+use crate::traits::UdpBatchProc;
+use std::ffi::c_void;
+use std::net::SocketAddr;
+
+/* Largest batch read at once. */
+pub const AE_UDP_BATCH_MAX: usize = 256;
+
+/* Full batches read per readable event. */
+pub const AE_UDP_MAX_BATCHES: usize = 16;
+
+/* Room for a UDP_GRO message, with slack for others. */
+const CONTROL_LEN: usize = 8;
+
+/* A datagram handed to a UdpBatchProc. */
+#[derive(Debug, Clone, Copy)]
+pub struct AeDatagram<'a> {
+    pub data: &'a [u8],
+    /* The sender, None for a socket family without one. */
+    pub addr: Option<SocketAddr>,
+    /* With UDP_GRO, the size of the datagrams merged into `data` (the last
+     * one may be shorter), 0 for a single datagram. */
+    pub segment_size: usize,
+    /* The datagram was larger than the buffer and cut. */
+    pub truncated: bool,
+}
+
+struct UdpState {
+    proc: UdpBatchProc,
+    client_data: *mut c_void,
+    batch: usize,
+    datagram_size: usize,
+    /* One buffer of `datagram_size` bytes per datagram of the batch. */
+    bufs: Vec<u8>,
+    addrs: Vec<libc::sockaddr_storage>,
+    controls: Vec<[u64; CONTROL_LEN]>,
+    /* The proc is running, and unregistered the socket: the buffers it
+     * borrows are freed once it returns. */
+    in_proc: bool,
+    unregistered: bool,
+}
+
+/* What was received in one slot of the batch. */
+struct Received {
+    len: usize,
+    addr: Option<SocketAddr>,
+    segment_size: usize,
+    truncated: bool,
+}
+
+/* Watch the non-blocking UDP socket `fd` and call `proc` with the
+ * datagrams it receives, at most `batch` (1 to AE_UDP_BATCH_MAX) at a
+ * time, each read into a buffer of `datagram_size` bytes (65535 holds any
+ * datagram, and any GRO merge). The fd stays owned by the caller.
+ * Returns AE_ERR for a bad size or if `fd` cannot be registered. */
+pub fn ae_udp_register(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    batch: usize,
+    datagram_size: usize,
+    proc: UdpBatchProc,
+    client_data: *mut c_void,
+) -> i32 {
+    if batch == 0 || batch > AE_UDP_BATCH_MAX || datagram_size == 0 {
+        return AE_ERR;
+    }
+    let state = Box::into_raw(Box::new(UdpState {
+        proc,
+        client_data,
+        batch,
+        datagram_size,
+        bufs: vec![0; batch * datagram_size],
+        addrs: vec![unsafe { std::mem::zeroed() }; batch],
+        controls: vec![[0; CONTROL_LEN]; batch],
+        in_proc: false,
+        unregistered: false,
+    }));
+    if ae_create_file_event(
+        event_loop,
+        fd,
+        AE_READABLE,
+        udp_readable_handler,
+        state as *mut c_void,
+    ) == AE_ERR
+    {
+        drop(unsafe { Box::from_raw(state) });
+        return AE_ERR;
+    }
+    AE_OK
+}
+
+/* Stop watching the socket `fd`, which is not closed. From the proc it
+ * takes effect once the proc returns. Returns AE_ERR if `fd` was not
+ * registered with ae_udp_register(). */
+pub fn ae_udp_unregister(event_loop: &mut AeEventLoop, fd: i32) -> i32 {
+    let client_data = ae_get_file_client_data(event_loop, fd);
+    if client_data.is_null() {
+        return AE_ERR;
+    }
+    let state = client_data as *mut UdpState;
+    ae_delete_file_event(event_loop, fd, AE_READABLE);
+    unsafe {
+        if (*state).in_proc {
+            (*state).unregistered = true;
+        } else {
+            drop(Box::from_raw(state));
+        }
+    }
+    AE_OK
+}
+
+fn udp_readable_handler(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    client_data: *mut c_void,
+    _mask: i32,
+) {
+    let state = client_data as *mut UdpState;
+    for _ in 0..AE_UDP_MAX_BATCHES {
+        /* EAGAIN, or an error queued by an earlier send (ICMP port
+         * unreachable on a connected socket): nothing to hand over. */
+        let received = match receive(fd, unsafe { &mut *state }) {
+            Ok(received) if !received.is_empty() => received,
+            _ => return,
+        };
+        let (proc, user_data, batch, datagram_size) = unsafe {
+            (
+                (*state).proc,
+                (*state).client_data,
+                (*state).batch,
+                (*state).datagram_size,
+            )
+        };
+        let bufs = unsafe { &(*state).bufs };
+        let datagrams: Vec<AeDatagram<'_>> = received
+            .iter()
+            .enumerate()
+            .map(|(slot, received)| AeDatagram {
+                data: &bufs[slot * datagram_size..slot * datagram_size + received.len],
+                addr: received.addr,
+                segment_size: received.segment_size,
+                truncated: received.truncated,
+            })
+            .collect();
+
+        unsafe { (*state).in_proc = true };
+        proc(event_loop, fd, &datagrams, user_data);
+        unsafe {
+            (*state).in_proc = false;
+            if (*state).unregistered {
+                drop(Box::from_raw(state));
+                return;
+            }
+        }
+        if received.len() < batch {
+            return;
+        }
+    }
+}
+
+/* Read up to a batch of datagrams. */
+fn receive(fd: i32, state: &mut UdpState) -> Result<Vec<Received>, i32> {
+    let size = state.datagram_size;
+    let mut iovs: Vec<libc::iovec> = state
+        .bufs
+        .chunks_mut(size)
+        .map(|buf| libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut c_void,
+            iov_len: buf.len(),
+        })
+        .collect();
+    let mut headers: Vec<libc::msghdr> = Vec::with_capacity(state.batch);
+    for (slot, iov) in iovs.iter_mut().enumerate() {
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_name = &mut state.addrs[slot] as *mut _ as *mut c_void;
+        msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        msg.msg_iov = iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = state.controls[slot].as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = std::mem::size_of::<[u64; CONTROL_LEN]>() as _;
+        headers.push(msg);
+    }
+
+    #[cfg(target_os = "linux")]
+    let lens = {
+        let mut msgs: Vec<libc::mmsghdr> = headers
+            .iter()
+            .map(|&msg_hdr| libc::mmsghdr {
+                msg_hdr,
+                msg_len: 0,
+            })
+            .collect();
+        let count = loop {
+            let count = unsafe {
+                libc::recvmmsg(
+                    fd,
+                    msgs.as_mut_ptr(),
+                    msgs.len() as u32,
+                    libc::MSG_DONTWAIT,
+                    std::ptr::null_mut(),
+                )
+            };
+            if count >= 0 {
+                break count as usize;
+            }
+            let err = errno();
+            if err != libc::EINTR {
+                return Err(err);
+            }
+        };
+        for (msg, header) in msgs.iter().zip(headers.iter_mut()).take(count) {
+            *header = msg.msg_hdr;
+        }
+        msgs.iter()
+            .take(count)
+            .map(|msg| msg.msg_len as usize)
+            .collect::<Vec<usize>>()
+    };
+    #[cfg(not(target_os = "linux"))]
+    let lens = {
+        let mut lens = Vec::new();
+        for header in headers.iter_mut() {
+            let len = unsafe { libc::recvmsg(fd, header, libc::MSG_DONTWAIT) };
+            if len < 0 {
+                let err = errno();
+                if lens.is_empty() {
+                    return Err(err);
+                }
+                break;
+            }
+            lens.push(len as usize);
+        }
+        lens
+    };
+
+    Ok(lens
+        .into_iter()
+        .zip(headers.iter())
+        .enumerate()
+        .map(|(slot, (len, header))| Received {
+            len: len.min(size),
+            addr: if header.msg_namelen > 0 {
+                raw_to_socket_addr(&state.addrs[slot])
+            } else {
+                None
+            },
+            segment_size: gro_segment_size(header),
+            truncated: header.msg_flags & libc::MSG_TRUNC != 0,
+        })
+        .collect())
+}
+
+/* Segment size from the UDP_GRO control message, 0 if there is none. */
+#[cfg(target_os = "linux")]
+fn gro_segment_size(header: &libc::msghdr) -> usize {
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(header) };
+    while !cmsg.is_null() {
+        let hdr = unsafe { &*cmsg };
+        if hdr.cmsg_level == libc::SOL_UDP && hdr.cmsg_type == libc::UDP_GRO {
+            let size = unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const i32) };
+            return size.max(0) as usize;
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(header, cmsg) };
+    }
+    0
+}
+
+#[cfg(not(target_os = "linux"))]
+fn gro_segment_size(_header: &libc::msghdr) -> usize {
+    0
+}
@@ -0,0 +1,278 @@
+/* Wall-clock timers.
+ *
+ * Regular time events run on the monotonic clock: a timer created for
+ * "in 8 hours" keeps firing 8 hours later even if the system clock is
+ * stepped by NTP or the administrator. Jobs that must happen at a given
+ * wall-clock time (log rotation at midnight, ...) use these instead.
+ *
+ * On Linux each timer is a timerfd on CLOCK_REALTIME armed with an
+ * absolute expiration and TFD_TIMER_CANCEL_ON_SET, so the kernel both
+ * fires it on time and wakes us up when the clock is set, at which point
+ * it is simply armed again. Elsewhere, or if no timerfd can be created, a
+ * regular time event polls the wall clock at least every
+ * AE_WALLCLOCK_RECHECK_MS, which bounds how late a clock change is seen.
+ */
+
+use crate::ae::{AeEventLoop, ae_create_time_event, ae_delete_time_event};
+use crate::constants::{AE_ERR, AE_NOMORE, AE_OK};
+use crate::traits::{EventFinalizerProc, TimeProc};
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::time::{Duration, SystemTime};
+
+/* Longest sleep of the fallback timer before looking at the wall clock
+ * again. */
+pub const AE_WALLCLOCK_RECHECK_MS: i64 = 1000;
+
+enum Backing {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    TimerFd(i32),
+    TimeEvent(i64),
+}
+
+struct WallTimer {
+    when: SystemTime,
+    proc: TimeProc,
+    finalizer_proc: Option<EventFinalizerProc>,
+    client_data: *mut c_void,
+    backing: Backing,
+}
+
+#[derive(Default)]
+pub(crate) struct WallClockState {
+    timers: HashMap<i64, WallTimer>,
+    next_id: i64,
+}
+
+/* Call `proc` once the wall clock reaches `when` (right away if it is
+ * already past). Like a time event, the proc returns AE_NOMORE to stop,
+ * or a number of milliseconds: the next firing is then scheduled that
+ * long after the current wall-clock time.
+ *
+ * Returns the timer id, to be passed to ae_delete_wallclock_event(), or
+ * AE_ERR. Ids are distinct from the ids of regular time events. */
+pub fn ae_create_wallclock_event(
+    event_loop: &mut AeEventLoop,
+    when: SystemTime,
+    proc: TimeProc,
+    client_data: *mut c_void,
+    finalizer_proc: Option<EventFinalizerProc>,
+) -> i64 {
+    event_loop.wallclock.next_id += 1;
+    let id = event_loop.wallclock.next_id;
+
+    let backing = match arm(event_loop, id, when) {
+        Some(backing) => backing,
+        None => return AE_ERR as i64,
+    };
+    event_loop.wallclock.timers.insert(
+        id,
+        WallTimer {
+            when,
+            proc,
+            finalizer_proc,
+            client_data,
+            backing,
+        },
+    );
+    id
+}
+
+/* Cancel a wall-clock timer, calling its finalizer. Returns AE_OK, or
+ * AE_ERR if there is no such timer. */
+pub fn ae_delete_wallclock_event(event_loop: &mut AeEventLoop, id: i64) -> i32 {
+    match event_loop.wallclock.timers.remove(&id) {
+        Some(timer) => {
+            release(event_loop, timer);
+            AE_OK
+        }
+        None => AE_ERR,
+    }
+}
+
+/* Free every wall-clock timer, called when the loop is dropped. */
+pub(crate) fn free_all(event_loop: &mut AeEventLoop) {
+    while let Some(&id) = event_loop.wallclock.timers.keys().next() {
+        ae_delete_wallclock_event(event_loop, id);
+    }
+}
+
+fn release(event_loop: &mut AeEventLoop, timer: WallTimer) {
+    disarm(event_loop, &timer.backing);
+    if let Some(finalizer) = timer.finalizer_proc {
+        finalizer(event_loop, timer.client_data);
+    }
+}
+
+/* Set up whatever makes the timer with the given id fire at `when`. */
+fn arm(event_loop: &mut AeEventLoop, id: i64, when: SystemTime) -> Option<Backing> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if crate::ae::probe::ae_kernel_features().timerfd
+        && let Some(fd) = timerfd::create(event_loop, id)
+    {
+        if timerfd::set(fd, when) {
+            return Some(Backing::TimerFd(fd));
+        }
+        timerfd::close(event_loop, fd);
+    }
+
+    let timer_id = ae_create_time_event(
+        event_loop,
+        fallback_delay_ms(when),
+        fallback_handler,
+        id as *mut c_void,
+        None,
+    );
+    if timer_id < 0 {
+        return None;
+    }
+    Some(Backing::TimeEvent(timer_id))
+}
+
+fn disarm(event_loop: &mut AeEventLoop, backing: &Backing) {
+    match backing {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Backing::TimerFd(fd) => timerfd::close(event_loop, *fd),
+        Backing::TimeEvent(timer_id) => {
+            ae_delete_time_event(event_loop, *timer_id);
+        }
+    }
+}
+
+fn fallback_delay_ms(when: SystemTime) -> i64 {
+    match when.duration_since(SystemTime::now()) {
+        Ok(left) => (left.as_millis() as i64).min(AE_WALLCLOCK_RECHECK_MS),
+        Err(_) => 0,
+    }
+}
+
+/* Time proc of the fallback timer, client_data is the wall timer id. */
+fn fallback_handler(event_loop: &mut AeEventLoop, _timer_id: i64, client_data: *mut c_void) -> i32 {
+    let id = client_data as i64;
+    let when = match event_loop.wallclock.timers.get(&id) {
+        Some(timer) => timer.when,
+        None => return AE_NOMORE,
+    };
+    if SystemTime::now() < when {
+        return fallback_delay_ms(when).max(1) as i32;
+    }
+
+    match fire(event_loop, id) {
+        Some(next) => fallback_delay_ms(next).max(1) as i32,
+        None => AE_NOMORE,
+    }
+}
+
+/* Call the user proc of a due timer and schedule the next firing. Returns
+ * the new expiration, or None if the timer is gone. The fallback time
+ * event reschedules itself through its return value, so rearming is only
+ * needed for timerfds. */
+fn fire(event_loop: &mut AeEventLoop, id: i64) -> Option<SystemTime> {
+    let (proc, client_data) = match event_loop.wallclock.timers.get(&id) {
+        Some(timer) => (timer.proc, timer.client_data),
+        None => return None,
+    };
+
+    let retval = proc(event_loop, id, client_data);
+
+    /* The proc may have deleted its own timer. */
+    if !event_loop.wallclock.timers.contains_key(&id) {
+        return None;
+    }
+    if retval == AE_NOMORE {
+        ae_delete_wallclock_event(event_loop, id);
+        return None;
+    }
+
+    let next = SystemTime::now() + Duration::from_millis(retval.max(0) as u64);
+    let timer = event_loop.wallclock.timers.get_mut(&id)?;
+    timer.when = next;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if let Backing::TimerFd(fd) = timer.backing {
+        timerfd::set(fd, next);
+    }
+    Some(next)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod timerfd {
+    use super::*;
+    use crate::ae::{ae_create_file_event, ae_delete_file_event};
+    use crate::anet::errno;
+    use crate::constants::AE_READABLE;
+    use std::time::UNIX_EPOCH;
+
+    pub(super) fn create(event_loop: &mut AeEventLoop, id: i64) -> Option<i32> {
+        let fd = unsafe {
+            libc::timerfd_create(libc::CLOCK_REALTIME, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC)
+        };
+        if fd == -1 {
+            return None;
+        }
+        if ae_create_file_event(
+            event_loop,
+            fd,
+            AE_READABLE,
+            readable_handler,
+            id as *mut c_void,
+        ) == AE_ERR
+        {
+            unsafe { libc::close(fd) };
+            return None;
+        }
+        Some(fd)
+    }
+
+    /* Arm the timerfd for the absolute time `when`. A time in the past
+     * (or the epoch itself, which would disarm it) fires right away. */
+    pub(super) fn set(fd: i32, when: SystemTime) -> bool {
+        let us = unix_us(when).max(1);
+        let spec = libc::itimerspec {
+            it_interval: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            it_value: libc::timespec {
+                tv_sec: (us / 1_000_000).min(libc::time_t::MAX as i128) as libc::time_t,
+                tv_nsec: ((us % 1_000_000) * 1000) as _,
+            },
+        };
+        let flags = libc::TFD_TIMER_ABSTIME | libc::TFD_TIMER_CANCEL_ON_SET;
+        unsafe { libc::timerfd_settime(fd, flags, &spec, std::ptr::null_mut()) == 0 }
+    }
+
+    /* Microseconds since the epoch, negative before it. */
+    fn unix_us(when: SystemTime) -> i128 {
+        match when.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_micros() as i128,
+            Err(e) => -(e.duration().as_micros() as i128),
+        }
+    }
+
+    pub(super) fn close(event_loop: &mut AeEventLoop, fd: i32) {
+        ae_delete_file_event(event_loop, fd, AE_READABLE);
+        unsafe { libc::close(fd) };
+    }
+
+    fn readable_handler(
+        event_loop: &mut AeEventLoop,
+        fd: i32,
+        client_data: *mut c_void,
+        _mask: i32,
+    ) {
+        let id = client_data as i64;
+        let mut expirations: u64 = 0;
+        let n = unsafe { libc::read(fd, &mut expirations as *mut u64 as *mut c_void, 8) };
+        if n == -1 {
+            /* ECANCELED: the clock was set. Arm it again, which fires
+             * right away if the new time is already past the deadline. */
+            if errno() == libc::ECANCELED
+                && let Some(timer) = event_loop.wallclock.timers.get(&id)
+            {
+                set(fd, timer.when);
+            }
+            return;
+        }
+        fire(event_loop, id);
+    }
+}
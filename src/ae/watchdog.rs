@@ -0,0 +1,210 @@
+/* Write stall watchdog.
+ *
+ * An fd keeps AE_WRITABLE registered while it has output the kernel did
+ * not take yet. When the peer stops reading, or its connection is wedged
+ * with full buffers, the interest stays there for good: the output piles
+ * up, and nothing in the loop ever notices since the fd simply never
+ * becomes writable.
+ *
+ * Once enabled with ae_set_write_stall_watchdog(), the loop notes when
+ * each fd got AE_WRITABLE, and when bytes were last flushed to it: by the
+ * connection layer on its own, by ae_note_write_progress() for fds
+ * written by hand. A time event checks the fds every half threshold. One
+ * without progress for the threshold is flagged once per stall: raised as
+ * an AeFindingKind::WriteStall finding through the diagnostic proc (see
+ * ae_set_diagnostic_proc()), handed to the stall proc if any, which may
+ * close it, and listed by ae_doctor() until it makes progress.
+ */
+
+use crate::ae::conn::ae_conn_pending_output;
+use crate::ae::doctor::{self, AeFinding, AeFindingKind, AeFindingSeverity};
+use crate::ae::{
+    AeEventLoop, ae_delete_time_event, ae_get_file_events, create_time_event_us, duration_us,
+};
+use crate::constants::{AE_ERR, AE_NOMORE, AE_OK, AE_WRITABLE};
+use crate::traits::WriteStallProc;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::time::Duration;
+
+#[derive(Default)]
+pub(crate) struct WriteWatchdog {
+    /* 0 when disabled. */
+    threshold_us: u64,
+    proc: Option<WriteStallProc>,
+    /* Fds with AE_WRITABLE to the time of their last progress, or of the
+     * registration, and whether the current stall was flagged. */
+    fds: HashMap<i32, (u64, bool)>,
+    timer_id: Option<i64>,
+}
+
+/* Flag the fds that keep AE_WRITABLE registered for `threshold_ms`
+ * milliseconds without any byte flushed, and call `proc` for each, once
+ * per stall. The check runs every half threshold, so a stall is flagged
+ * up to 1.5 times the threshold after the last progress. Fds already
+ * watching writability are watched from now on. 0 disables the watchdog.
+ * Returns AE_ERR for a negative threshold. */
+pub fn ae_set_write_stall_watchdog(
+    event_loop: &mut AeEventLoop,
+    threshold_ms: i64,
+    proc: Option<WriteStallProc>,
+) -> i32 {
+    if threshold_ms < 0 {
+        return AE_ERR;
+    }
+    ae_set_write_stall_watchdog_duration(
+        event_loop,
+        Duration::from_millis(threshold_ms as u64),
+        proc,
+    )
+}
+
+/* ae_set_write_stall_watchdog() taking the threshold as a Duration,
+ * Duration::ZERO to disable the watchdog. */
+pub fn ae_set_write_stall_watchdog_duration(
+    event_loop: &mut AeEventLoop,
+    threshold: Duration,
+    proc: Option<WriteStallProc>,
+) -> i32 {
+    if let Some(timer_id) = event_loop.write_watchdog.timer_id.take() {
+        ae_delete_time_event(event_loop, timer_id);
+    }
+    let watchdog = &mut event_loop.write_watchdog;
+    watchdog.fds.clear();
+    watchdog.threshold_us = duration_us(threshold);
+    watchdog.proc = proc;
+    if watchdog.threshold_us == 0 {
+        return AE_OK;
+    }
+
+    let now = event_loop.cached_now_us;
+    for fd in 0..=event_loop.maxfd {
+        if ae_get_file_events(event_loop, fd) & AE_WRITABLE != 0 {
+            event_loop.write_watchdog.fds.insert(fd, (now, false));
+        }
+    }
+    let timer_id = create_time_event_us(
+        event_loop,
+        check_interval_ms(event_loop.write_watchdog.threshold_us) * 1000,
+        watchdog_timer,
+        std::ptr::null_mut(),
+        None,
+    );
+    event_loop.write_watchdog.timer_id = Some(timer_id);
+    AE_OK
+}
+
+/* Bytes were written to `fd`, for fds written without the connection
+ * layer (see ae_conn_create()), which notes its own progress. Cheap
+ * enough to call after every write. */
+pub fn ae_note_write_progress(event_loop: &mut AeEventLoop, fd: i32) {
+    let now = event_loop.cached_now_us;
+    if let Some(entry) = event_loop.write_watchdog.fds.get_mut(&fd) {
+        *entry = (now, false);
+    }
+}
+
+/* How long `fd` has had AE_WRITABLE registered without progress, None if
+ * it is not watched. */
+pub fn ae_write_stall_us(event_loop: &AeEventLoop, fd: i32) -> Option<u64> {
+    let &(since_us, _) = event_loop.write_watchdog.fds.get(&fd)?;
+    Some(event_loop.cached_now_us.saturating_sub(since_us))
+}
+
+/* AE_WRITABLE was registered on `fd`, which did not have it. */
+pub(crate) fn armed(event_loop: &mut AeEventLoop, fd: i32) {
+    if event_loop.write_watchdog.threshold_us == 0 {
+        return;
+    }
+    let now = event_loop.cached_now_us;
+    event_loop.write_watchdog.fds.insert(fd, (now, false));
+}
+
+/* AE_WRITABLE was deleted from `fd`. */
+pub(crate) fn disarmed(event_loop: &mut AeEventLoop, fd: i32) {
+    if event_loop.write_watchdog.threshold_us == 0 {
+        return;
+    }
+    event_loop.write_watchdog.fds.remove(&fd);
+}
+
+/* Called when `old_fd` was moved to `new_fd`, see
+ * ae_resize_set_size_compact(): the stall goes on. */
+pub(crate) fn relocate(event_loop: &mut AeEventLoop, old_fd: i32, new_fd: i32) {
+    let watchdog = &mut event_loop.write_watchdog;
+    if let Some(entry) = watchdog.fds.remove(&old_fd) {
+        watchdog.fds.insert(new_fd, entry);
+    }
+}
+
+/* The stalled fds, for ae_doctor(). */
+pub(crate) fn findings(event_loop: &AeEventLoop) -> Vec<AeFinding> {
+    let watchdog = &event_loop.write_watchdog;
+    if watchdog.threshold_us == 0 {
+        return Vec::new();
+    }
+    let mut stalled: Vec<(i32, u64)> = watchdog
+        .fds
+        .iter()
+        .map(|(&fd, &(since_us, _))| (fd, event_loop.cached_now_us.saturating_sub(since_us)))
+        .filter(|&(_, stalled_us)| stalled_us >= watchdog.threshold_us)
+        .collect();
+    stalled.sort_unstable();
+    stalled
+        .into_iter()
+        .map(|(fd, stalled_us)| finding(event_loop, fd, stalled_us))
+        .collect()
+}
+
+fn finding(event_loop: &AeEventLoop, fd: i32, stalled_us: u64) -> AeFinding {
+    let pending = ae_conn_pending_output(event_loop, fd);
+    AeFinding {
+        severity: AeFindingSeverity::Warning,
+        kind: AeFindingKind::WriteStall {
+            fd,
+            stalled_us,
+            pending,
+        },
+        message: format!(
+            "fd {fd} has been waiting to be writable for {stalled_us}us without flushing \
+             anything ({pending} bytes pending): the peer stopped reading or the connection \
+             is wedged"
+        ),
+    }
+}
+
+fn watchdog_timer(event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    let watchdog = &mut event_loop.write_watchdog;
+    if watchdog.threshold_us == 0 {
+        return AE_NOMORE;
+    }
+    let now = event_loop.cached_now_us;
+    let threshold_us = watchdog.threshold_us;
+    let mut stalled = Vec::new();
+    for (&fd, (since_us, flagged)) in watchdog.fds.iter_mut() {
+        let stalled_us = now.saturating_sub(*since_us);
+        if !*flagged && stalled_us >= threshold_us {
+            *flagged = true;
+            stalled.push((fd, stalled_us));
+        }
+    }
+    stalled.sort_unstable();
+
+    for (fd, stalled_us) in stalled {
+        /* A proc called before may have closed it. */
+        if !event_loop.write_watchdog.fds.contains_key(&fd) {
+            continue;
+        }
+        let finding = finding(event_loop, fd, stalled_us);
+        doctor::raise(event_loop, finding);
+        if let Some(proc) = event_loop.write_watchdog.proc {
+            proc(event_loop, fd, stalled_us);
+        }
+    }
+    check_interval_ms(threshold_us) as i32
+}
+
+/* Half the threshold, in whole milliseconds. */
+fn check_interval_ms(threshold_us: u64) -> u64 {
+    (threshold_us / 2000).clamp(1, i32::MAX as u64)
+}
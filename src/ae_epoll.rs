@@ -0,0 +1,323 @@
+/* Linux epoll(2) based ae.c module
+ *
+ * Copyright (c) 2009-2012, Salvatore Sanfilippo <antirez at gmail dot com>
+ * All rights reserved.
+ *
+ * Rust port of Redis ae_epoll.c, plus kernel busy-polling configuration
+ * (EPIOCSPARAMS, Linux 6.9+) for latency-critical deployments.
+ */
+
+use crate::ae::stats::AeBackendStats;
+use crate::ae_select::FiredEvent;
+use crate::anet::errno;
+use crate::constants::{AE_ERRQUEUE, AE_NONE, AE_READABLE, AE_WRITABLE, poll_batch};
+use crate::traits::EventBackend;
+use libc::{
+    EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD, EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLOUT, close,
+    epoll_ctl, epoll_event, epoll_wait,
+};
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+/* struct epoll_params from <linux/eventpoll.h>. */
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct EpollParams {
+    busy_poll_usecs: u32,
+    busy_poll_budget: u16,
+    prefer_busy_poll: u8,
+    pad: u8,
+}
+
+/* _IOW(0x8A, 0x01, struct epoll_params) and _IOR(0x8A, 0x02, ...). */
+const EPIOCSPARAMS: libc::c_ulong = 0x4008_8a01;
+const EPIOCGPARAMS: libc::c_ulong = 0x8008_8a02;
+
+/* Busy-polling parameters of an epoll instance. While waiting, the kernel
+ * spins on the device queues of the sockets in the set for up to
+ * `usecs` microseconds before sleeping, trading CPU for latency. */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AeEpollBusyPoll {
+    pub usecs: u32,
+    /* Packets processed per busy-poll attempt, 0 for the kernel default.
+     * Values above the net.core.busy_poll budget need CAP_NET_ADMIN. */
+    pub budget: u16,
+    /* Keep device interrupts deferred while busy polling. */
+    pub prefer_busy_poll: bool,
+}
+
+#[allow(non_camel_case_types)]
+pub struct aeApiState {
+    epfd: RawFd,
+    events: Vec<epoll_event>,
+    /* Registered mask of every fd. epoll_ctl() takes the full interest
+     * set, while add_event()/del_event() only get the bits to change. */
+    masks: Vec<u8>,
+    stats: AeBackendStats,
+}
+
+impl aeApiState {
+    fn create_epoll() -> Result<RawFd, i32> {
+        let epfd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if epfd == -1 {
+            return Err(-1);
+        }
+        Ok(epfd)
+    }
+
+    #[inline]
+    fn get_mask(&self, fd: i32) -> i32 {
+        self.masks.get(fd as usize).map_or(AE_NONE, |&m| m as i32)
+    }
+
+    fn ctl(&mut self, op: i32, fd: i32, mask: i32) -> i32 {
+        self.ctl_flags(op, fd, mask, 0)
+    }
+
+    fn ctl_flags(&mut self, op: i32, fd: i32, mask: i32, flags: u32) -> i32 {
+        let mut ee = epoll_event {
+            events: flags,
+            u64: 0,
+        };
+        if mask & AE_READABLE != 0 {
+            ee.events |= EPOLLIN as u32;
+        }
+        if mask & AE_WRITABLE != 0 {
+            ee.events |= EPOLLOUT as u32;
+        }
+        ee.u64 = fd as u64;
+        self.stats.ctl_calls += 1;
+        unsafe { epoll_ctl(self.epfd, op, fd, &mut ee) }
+    }
+
+    /* epoll_wait() for at most `timeout_ms`, -1 for no limit. */
+    fn wait(&mut self, fired: &mut [FiredEvent], timeout_ms: libc::c_int) -> Result<i32, i32> {
+        if self.events.is_empty() {
+            self.events.push(epoll_event { events: 0, u64: 0 });
+        }
+        let maxevents = self.events.len().min(fired.len().max(1));
+        let retval = unsafe {
+            epoll_wait(
+                self.epfd,
+                self.events.as_mut_ptr(),
+                maxevents as libc::c_int,
+                timeout_ms,
+            )
+        };
+        let err = if retval == -1 { errno() } else { 0 };
+        self.stats.record_poll(retval, err);
+
+        if retval > 0 {
+            let mut numevents = 0;
+            for e in &self.events[..retval as usize] {
+                if numevents >= fired.len() {
+                    break;
+                }
+                let mut mask = 0;
+                if e.events & EPOLLIN as u32 != 0 {
+                    mask |= AE_READABLE;
+                }
+                if e.events & EPOLLOUT as u32 != 0 {
+                    mask |= AE_WRITABLE;
+                }
+                /* EPOLLERR is how epoll reports a non-empty error queue:
+                 * fds registered for it get AE_ERRQUEUE instead of a
+                 * wakeup of their read and write handlers. */
+                let fd = e.u64 as i32;
+                let errqueue = self.get_mask(fd) & AE_ERRQUEUE != 0;
+                if errqueue && e.events & EPOLLERR as u32 != 0 {
+                    mask |= AE_ERRQUEUE;
+                }
+                if e.events & EPOLLHUP as u32 != 0 || (!errqueue && e.events & EPOLLERR as u32 != 0)
+                {
+                    mask |= AE_READABLE | AE_WRITABLE;
+                }
+                fired[numevents] = FiredEvent { fd, mask };
+                numevents += 1;
+            }
+            Ok(numevents as i32)
+        } else if retval == -1 {
+            /* EINTR included, see AeEintrPolicy. */
+            Err(err)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /* Enable busy polling on this epoll instance. Returns 0 on success,
+     * -1 with errno set (ENOTTY on kernels before 6.9, EPERM for a budget
+     * above the system limit). */
+    pub fn set_busy_poll(&mut self, params: AeEpollBusyPoll) -> i32 {
+        let mut raw = EpollParams {
+            busy_poll_usecs: params.usecs,
+            busy_poll_budget: params.budget,
+            prefer_busy_poll: params.prefer_busy_poll as u8,
+            pad: 0,
+        };
+        unsafe { libc::ioctl(self.epfd, EPIOCSPARAMS as _, &mut raw) }
+    }
+
+    /* Current busy-polling parameters, or the errno of the failed ioctl. */
+    pub fn busy_poll(&self) -> Result<AeEpollBusyPoll, i32> {
+        let mut raw = EpollParams::default();
+        if unsafe { libc::ioctl(self.epfd, EPIOCGPARAMS as _, &mut raw) } == -1 {
+            return Err(errno());
+        }
+        Ok(AeEpollBusyPoll {
+            usecs: raw.busy_poll_usecs,
+            budget: raw.busy_poll_budget,
+            prefer_busy_poll: raw.prefer_busy_poll != 0,
+        })
+    }
+}
+
+impl EventBackend for aeApiState {
+    fn create() -> Result<Box<Self>, i32> {
+        Ok(Box::new(aeApiState {
+            epfd: Self::create_epoll()?,
+            events: Vec::new(),
+            masks: Vec::new(),
+            stats: AeBackendStats::default(),
+        }))
+    }
+
+    fn free(self: Box<Self>) {
+        /* Dropping closes the epoll fd. */
+    }
+
+    fn resize(&mut self, setsize: i32) -> i32 {
+        let setsize = setsize.max(0) as usize;
+        self.events
+            .resize(poll_batch(setsize), epoll_event { events: 0, u64: 0 });
+        self.masks.resize(setsize, AE_NONE as u8);
+        0
+    }
+
+    fn add_event(&mut self, fd: i32, mask: i32) -> i32 {
+        if fd < 0 {
+            return -1;
+        }
+        /* If the fd was already monitored for some event, we need a MOD
+         * operation. Otherwise we need an ADD operation. */
+        let old = self.get_mask(fd);
+        let op = if old == AE_NONE {
+            EPOLL_CTL_ADD
+        } else {
+            EPOLL_CTL_MOD
+        };
+        let mask = (mask | old) & (AE_READABLE | AE_WRITABLE | AE_ERRQUEUE);
+        if self.ctl(op, fd, mask) == -1 {
+            return -1;
+        }
+        if fd as usize >= self.masks.len() {
+            self.masks.resize(fd as usize + 1, AE_NONE as u8);
+        }
+        self.masks[fd as usize] = mask as u8;
+        0
+    }
+
+    /* EPOLLEXCLUSIVE is only accepted by EPOLL_CTL_ADD: the fd must not
+     * be registered yet, and cannot be modified afterwards (only
+     * deleted). */
+    fn add_event_exclusive(&mut self, fd: i32, mask: i32) -> i32 {
+        if fd < 0 || self.get_mask(fd) != AE_NONE {
+            return -1;
+        }
+        let mask = mask & (AE_READABLE | AE_WRITABLE);
+        if self.ctl_flags(EPOLL_CTL_ADD, fd, mask, libc::EPOLLEXCLUSIVE as u32) == -1 {
+            return -1;
+        }
+        if fd as usize >= self.masks.len() {
+            self.masks.resize(fd as usize + 1, AE_NONE as u8);
+        }
+        self.masks[fd as usize] = mask as u8;
+        0
+    }
+
+    fn del_event(&mut self, fd: i32, delmask: i32) {
+        if fd < 0 {
+            return;
+        }
+        let old = self.get_mask(fd);
+        if old == AE_NONE {
+            return;
+        }
+        let mask = old & !delmask;
+        if mask != AE_NONE {
+            self.ctl(EPOLL_CTL_MOD, fd, mask);
+        } else {
+            /* Note, Kernel < 2.6.9 requires a non null event pointer even
+             * for EPOLL_CTL_DEL. */
+            self.ctl(EPOLL_CTL_DEL, fd, mask);
+        }
+        self.masks[fd as usize] = mask as u8;
+    }
+
+    fn poll(
+        &mut self,
+        _events: &[crate::ae::AeFileEvent],
+        fired: &mut [FiredEvent],
+        _maxfd: i32,
+        timeout: Option<Duration>,
+    ) -> Result<i32, i32> {
+        /* Round up so that a timer due in 300us is not polled for with a
+         * zero timeout, which would spin. */
+        let timeout_ms = match timeout {
+            Some(t) => t.as_micros().div_ceil(1000).min(libc::c_int::MAX as u128) as libc::c_int,
+            None => -1,
+        };
+        self.wait(fired, timeout_ms)
+    }
+
+    fn poll_nowait(
+        &mut self,
+        _events: &[crate::ae::AeFileEvent],
+        fired: &mut [FiredEvent],
+        _maxfd: i32,
+    ) -> Result<i32, i32> {
+        self.wait(fired, 0)
+    }
+
+    fn name(&self) -> &'static str {
+        "epoll"
+    }
+
+    fn reinit(&mut self) -> i32 {
+        /* A forked child shares the epoll instance with its parent, any
+         * change would show up there too: get a private one. */
+        let epfd = match Self::create_epoll() {
+            Ok(epfd) => epfd,
+            Err(_) => return -1,
+        };
+        unsafe { close(self.epfd) };
+        self.epfd = epfd;
+        self.masks.fill(AE_NONE as u8);
+        0
+    }
+
+    fn fd(&self) -> i32 {
+        self.epfd
+    }
+
+    fn supports_errqueue(&self) -> bool {
+        true
+    }
+
+    fn stats(&self) -> AeBackendStats {
+        self.stats
+    }
+
+    fn reset_stats(&mut self) {
+        self.stats = AeBackendStats::default();
+    }
+}
+
+impl Drop for aeApiState {
+    fn drop(&mut self) {
+        unsafe { close(self.epfd) };
+    }
+}
+
+pub fn ae_api_name() -> &'static str {
+    "epoll"
+}
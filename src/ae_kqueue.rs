@@ -6,10 +6,11 @@
  * Rust port of Redis ae_kqueue.c
  */
 
+use crate::ae::stats::AeBackendStats;
 use crate::ae_select::FiredEvent;
-use crate::constants::{AE_READABLE, AE_WRITABLE};
+use crate::constants::{AE_READABLE, AE_WRITABLE, poll_batch};
 use crate::traits::EventBackend;
-use libc::{EINTR, EV_ADD, EV_DELETE, EVFILT_READ, EVFILT_WRITE, close, kevent, kqueue, timespec};
+use libc::{EV_ADD, EV_DELETE, EVFILT_READ, EVFILT_WRITE, close, kevent, kqueue, timespec};
 use std::os::unix::io::RawFd;
 use std::time::Duration;
 
@@ -32,6 +33,11 @@ unsafe fn ev_set(
     kev.udata = udata;
 }
 
+const ZERO_TIMEOUT: timespec = timespec {
+    tv_sec: 0,
+    tv_nsec: 0,
+};
+
 #[allow(non_camel_case_types)]
 pub struct aeApiState {
     kqfd: RawFd,
@@ -39,7 +45,8 @@ pub struct aeApiState {
     /* Events mask for merge read and write event.
      * To reduce memory consumption, we use 2 bits to store the mask
      * of an event, so that 1 byte will store the mask of 4 events. */
-    eventsMask: Vec<u8>,
+    events_mask: Vec<u8>,
+    stats: AeBackendStats,
 }
 
 impl aeApiState {
@@ -65,18 +72,18 @@ impl aeApiState {
     #[inline]
     fn get_event_mask(&self, fd: i32) -> i32 {
         let byte_index = (fd as usize) / 4;
-        if byte_index >= self.eventsMask.len() {
+        if byte_index >= self.events_mask.len() {
             return 0;
         }
-        ((self.eventsMask[byte_index] >> Self::event_mask_offset(fd)) & 0x3) as i32
+        ((self.events_mask[byte_index] >> Self::event_mask_offset(fd)) & 0x3) as i32
     }
 
     /* Add mask bits to fd's event mask */
     #[inline]
     fn add_event_mask(&mut self, fd: i32, mask: i32) {
         let byte_index = (fd as usize) / 4;
-        if byte_index < self.eventsMask.len() {
-            self.eventsMask[byte_index] |= Self::event_mask_encode(fd, mask);
+        if byte_index < self.events_mask.len() {
+            self.events_mask[byte_index] |= Self::event_mask_encode(fd, mask);
         }
     }
 
@@ -84,14 +91,14 @@ impl aeApiState {
     #[inline]
     fn reset_event_mask(&mut self, fd: i32) {
         let byte_index = (fd as usize) / 4;
-        if byte_index < self.eventsMask.len() {
-            self.eventsMask[byte_index] &= !Self::event_mask_encode(fd, 0x3);
+        if byte_index < self.events_mask.len() {
+            self.events_mask[byte_index] &= !Self::event_mask_encode(fd, 0x3);
         }
     }
 
     /* Safe wrapper for kevent registration calls */
     #[inline]
-    fn register_kevent(&self, fd: i32, filter: libc::c_short, flags: libc::c_ushort) -> i32 {
+    fn register_kevent(&mut self, fd: i32, filter: libc::c_short, flags: libc::c_ushort) -> i32 {
         let mut ke = unsafe { std::mem::zeroed::<libc::kevent>() };
         unsafe {
             ev_set(
@@ -103,6 +110,7 @@ impl aeApiState {
                 0,
                 std::ptr::null_mut(),
             );
+            self.stats.ctl_calls += 1;
             if kevent(self.kqfd, &ke, 1, std::ptr::null_mut(), 0, std::ptr::null()) == -1 {
                 -1
             } else {
@@ -110,6 +118,73 @@ impl aeApiState {
             }
         }
     }
+
+    /* kevent() for at most `timeout`, null for no limit. */
+    fn wait(&mut self, fired: &mut [FiredEvent], timeout: *const timespec) -> Result<i32, i32> {
+        let retval = unsafe {
+            kevent(
+                self.kqfd,
+                std::ptr::null(),
+                0,
+                self.events.as_mut_ptr(),
+                self.events.len() as libc::c_int,
+                timeout,
+            )
+        };
+        let err = if retval == -1 {
+            unsafe { *libc::__error() }
+        } else {
+            0
+        };
+        self.stats.record_poll(retval, err);
+
+        if retval > 0 {
+            /* Normally we execute the read event first and then the write event.
+             * When the barrier is set, we will do it reverse.
+             *
+             * However, under kqueue, read and write events would be separate
+             * events, which would make it impossible to control the order of
+             * reads and writes. So we store the event's mask we've got and merge
+             * the same fd events later. */
+            for j in 0..retval {
+                let e = &self.events[j as usize];
+                let fd = e.ident as i32;
+                let mask = if e.filter == EVFILT_READ {
+                    AE_READABLE
+                } else if e.filter == EVFILT_WRITE {
+                    AE_WRITABLE
+                } else {
+                    0
+                };
+
+                if mask != 0 {
+                    self.add_event_mask(fd, mask);
+                }
+            }
+
+            /* Re-traversal to merge read and write events, and set the fd's mask to
+             * 0 so that events are not added again when the fd is encountered again. */
+            let mut numevents = 0;
+            for j in 0..retval {
+                let e = &self.events[j as usize];
+                let fd = e.ident as i32;
+                let mask = self.get_event_mask(fd);
+
+                if mask != 0 && (numevents as usize) < fired.len() {
+                    fired[numevents as usize] = FiredEvent { fd, mask };
+                    self.reset_event_mask(fd);
+                    numevents += 1;
+                }
+            }
+
+            Ok(numevents)
+        } else if retval == -1 {
+            /* EINTR included, see AeEintrPolicy. */
+            Err(err)
+        } else {
+            Ok(0)
+        }
+    }
 }
 
 impl EventBackend for aeApiState {
@@ -128,7 +203,8 @@ impl EventBackend for aeApiState {
         Ok(Box::new(aeApiState {
             kqfd,
             events: Vec::new(),
-            eventsMask: Vec::new(),
+            events_mask: Vec::new(),
+            stats: AeBackendStats::default(),
         }))
     }
 
@@ -137,7 +213,7 @@ impl EventBackend for aeApiState {
     }
 
     fn resize(&mut self, setsize: i32) -> i32 {
-        let new_size = setsize as usize;
+        let new_size = poll_batch(setsize as usize);
 
         // Only grow, don't shrink and reallocate unnecessarily
         if new_size > self.events.capacity() {
@@ -146,11 +222,11 @@ impl EventBackend for aeApiState {
         self.events.resize(new_size, unsafe { std::mem::zeroed() });
 
         let mask_size = Self::event_mask_malloc_size(setsize);
-        if mask_size > self.eventsMask.len() {
-            self.eventsMask.resize(mask_size, 0);
+        if mask_size > self.events_mask.len() {
+            self.events_mask.resize(mask_size, 0);
         } else {
             // Clear existing mask data for smaller sizes
-            for byte in &mut self.eventsMask[..mask_size] {
+            for byte in &mut self.events_mask[..mask_size] {
                 *byte = 0;
             }
         }
@@ -186,84 +262,58 @@ impl EventBackend for aeApiState {
         _maxfd: i32,
         timeout: Option<Duration>,
     ) -> Result<i32, i32> {
-        let retval = if let Some(timeout_dur) = timeout {
-            let timeout_spec = timespec {
-                tv_sec: timeout_dur.as_secs() as libc::time_t,
-                tv_nsec: timeout_dur.subsec_nanos() as libc::c_long,
-            };
-            unsafe {
-                kevent(
-                    self.kqfd,
-                    std::ptr::null(),
-                    0,
-                    self.events.as_mut_ptr(),
-                    self.events.len() as libc::c_int,
-                    &timeout_spec,
-                )
-            }
-        } else {
-            unsafe {
-                kevent(
-                    self.kqfd,
-                    std::ptr::null(),
-                    0,
-                    self.events.as_mut_ptr(),
-                    self.events.len() as libc::c_int,
-                    std::ptr::null(),
-                )
-            }
-        };
-
-        if retval > 0 {
-            /* Normally we execute the read event first and then the write event.
-             * When the barrier is set, we will do it reverse.
-             *
-             * However, under kqueue, read and write events would be separate
-             * events, which would make it impossible to control the order of
-             * reads and writes. So we store the event's mask we've got and merge
-             * the same fd events later. */
-            for j in 0..retval {
-                let e = &self.events[j as usize];
-                let fd = e.ident as i32;
-                let mask = if e.filter == EVFILT_READ {
-                    AE_READABLE
-                } else if e.filter == EVFILT_WRITE {
-                    AE_WRITABLE
-                } else {
-                    0
+        match timeout {
+            Some(timeout) => {
+                let timeout_spec = timespec {
+                    tv_sec: timeout.as_secs() as libc::time_t,
+                    tv_nsec: timeout.subsec_nanos() as libc::c_long,
                 };
-
-                if mask != 0 {
-                    self.add_event_mask(fd, mask);
-                }
+                self.wait(fired, &timeout_spec)
             }
+            None => self.wait(fired, std::ptr::null()),
+        }
+    }
 
-            /* Re-traversal to merge read and write events, and set the fd's mask to
-             * 0 so that events are not added again when the fd is encountered again. */
-            let mut numevents = 0;
-            for j in 0..retval {
-                let e = &self.events[j as usize];
-                let fd = e.ident as i32;
-                let mask = self.get_event_mask(fd);
+    fn poll_nowait(
+        &mut self,
+        _events: &[crate::ae::AeFileEvent],
+        fired: &mut [FiredEvent],
+        _maxfd: i32,
+    ) -> Result<i32, i32> {
+        self.wait(fired, &ZERO_TIMEOUT)
+    }
 
-                if mask != 0 && (numevents as usize) < fired.len() {
-                    fired[numevents as usize] = FiredEvent { fd, mask };
-                    self.reset_event_mask(fd);
-                    numevents += 1;
-                }
-            }
+    fn name(&self) -> &'static str {
+        "kqueue"
+    }
 
-            Ok(numevents)
-        } else if retval == -1 {
-            let errno = unsafe { *libc::__error() };
-            if errno == EINTR { Ok(0) } else { Err(errno) }
-        } else {
-            Ok(0)
+    fn reinit(&mut self) -> i32 {
+        /* The kqueue is not inherited by fork(), the descriptor we hold is
+         * unusable in the child: get a fresh one. */
+        unsafe { close(self.kqfd) };
+        let kqfd = unsafe { kqueue() };
+        if kqfd == -1 {
+            return -1;
+        }
+        if unsafe { libc::fcntl(kqfd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            unsafe { close(kqfd) };
+            return -1;
         }
+        self.kqfd = kqfd;
+        self.events_mask.fill(0);
+        0
     }
 
-    fn name(&self) -> &'static str {
-        "kqueue"
+    fn fd(&self) -> i32 {
+        self.kqfd
+    }
+
+    fn stats(&self) -> AeBackendStats {
+        self.stats
+    }
+
+    fn reset_stats(&mut self) {
+        self.stats = AeBackendStats::default();
     }
 }
 
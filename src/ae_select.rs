@@ -8,6 +8,7 @@
  * GNU Affero General Public License v3 (AGPLv3).
  */
 
+use crate::anet::errno;
 use crate::constants::{AE_NONE, AE_READABLE, AE_WRITABLE};
 use crate::fd_set::FdSet;
 use libc::{FD_SETSIZE, select, timeval};
@@ -93,17 +94,22 @@ pub fn ae_api_poll(
     state._rfds = state.rfds.clone();
     state._wfds = state.wfds.clone();
 
-    let timeout_ptr = tvp
-        .map(|d| timeval {
-            tv_sec: d.as_secs() as libc::time_t,
-            tv_usec: d.subsec_micros() as libc::suseconds_t,
-        })
+    /* Kept in a local: select() writes through the pointer, which must
+     * outlive the call. */
+    let mut tv = tvp.map(|d| timeval {
+        tv_sec: d.as_secs() as libc::time_t,
+        tv_usec: d.subsec_micros() as libc::suseconds_t,
+    });
+    let timeout_ptr = tv
         .as_mut()
         .map_or(std::ptr::null_mut(), |t| t as *mut timeval);
 
+    /* The sets hold FD_SETSIZE fds: the kernel would read past them for
+     * a higher maxfd, and none of those fds is in them anyway. */
+    let nfds = (maxfd + 1).min(FD_SETSIZE as i32);
     let retval = unsafe {
         select(
-            maxfd + 1,
+            nfds,
             state._rfds.as_mut_ptr(),
             state._wfds.as_mut_ptr(),
             std::ptr::null_mut(), // no exceptfds
@@ -112,11 +118,9 @@ pub fn ae_api_poll(
     };
 
     if retval < 0 {
-        let errno = unsafe { *libc::__error() };
-        /* Ignore EINTR (interrupted system call) like the C version */
-        if errno == libc::EINTR {
-            return Ok(0);
-        }
+        let errno = errno();
+        /* EINTR included: the loop decides what an interrupted wait means,
+         * see AeEintrPolicy. */
         return Err(errno);
     }
     if retval == 0 {
@@ -124,7 +128,7 @@ pub fn ae_api_poll(
     }
 
     let mut numevents = 0;
-    for fd in 0..=maxfd {
+    for fd in 0..nfds {
         if numevents >= fired.len() {
             break;
         }
@@ -0,0 +1,1117 @@
+/* Basic socket and I/O helpers built around the event loop.
+ *
+ * Loosely follows Redis anet.c: thin wrappers over the raw syscalls that
+ * report failures as errno values instead of panicking, so they are safe
+ * to call from file event callbacks.
+ */
+
+use crate::constants::{AE_ERR, AE_OK};
+use std::collections::VecDeque;
+use std::io::{IoSlice, IoSliceMut};
+use std::net::SocketAddr;
+
+/* Upper bound on the number of iovecs handed to a single readv/writev call.
+ * POSIX guarantees at least 16, every platform we support allows 1024. */
+pub const ANET_IOV_MAX: usize = 1024;
+
+#[inline]
+pub(crate) fn errno() -> i32 {
+    std::io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+/* Set errno, for the functions reporting their errors through it. */
+#[inline]
+pub(crate) fn set_errno(err: i32) {
+    unsafe { *errno_location() = err };
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) unsafe fn errno_location() -> *mut libc::c_int {
+    unsafe { libc::__errno_location() }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) unsafe fn errno_location() -> *mut libc::c_int {
+    unsafe { libc::__error() }
+}
+
+/* Put the fd in non-blocking mode. Returns AE_OK or AE_ERR. */
+pub fn anet_non_block(fd: i32) -> i32 {
+    anet_set_block(fd, true)
+}
+
+/* Put the fd back in blocking mode. Returns AE_OK or AE_ERR. */
+pub fn anet_block(fd: i32) -> i32 {
+    anet_set_block(fd, false)
+}
+
+fn anet_set_block(fd: i32, non_block: bool) -> i32 {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 {
+        return AE_ERR;
+    }
+
+    /* Check if this flag has been set or unset, if so,
+     * then there is no need to call fcntl to set/unset it again. */
+    if ((flags & libc::O_NONBLOCK) != 0) == non_block {
+        return AE_OK;
+    }
+
+    let flags = if non_block {
+        flags | libc::O_NONBLOCK
+    } else {
+        flags & !libc::O_NONBLOCK
+    };
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } == -1 {
+        return AE_ERR;
+    }
+    AE_OK
+}
+
+/* Enable the FD_CLOEXEC on the given fd to avoid fd leaks.
+ * This function should be invoked for fd's on specific places
+ * where fork + execve system calls are called. */
+pub fn anet_cloexec(fd: i32) -> i32 {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags == -1 {
+        return AE_ERR;
+    }
+    if flags & libc::FD_CLOEXEC != 0 {
+        return AE_OK;
+    }
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } == -1 {
+        return AE_ERR;
+    }
+    AE_OK
+}
+
+/* Create a pipe whose both ends are close-on-exec and, if requested,
+ * non-blocking. Returns (read end, write end). */
+pub fn anet_pipe(non_block: bool) -> Result<(i32, i32), i32> {
+    let mut fds = [-1; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(errno());
+    }
+    for &fd in &fds {
+        if anet_cloexec(fd) == AE_ERR || (non_block && anet_non_block(fd) == AE_ERR) {
+            let err = errno();
+            unsafe {
+                libc::close(fds[0]);
+                libc::close(fds[1]);
+            }
+            return Err(err);
+        }
+    }
+    Ok((fds[0], fds[1]))
+}
+
+/* Return the pending error on the socket (SO_ERROR), 0 if there is none.
+ * If the option itself cannot be read, its errno is returned instead. */
+pub fn anet_get_socket_error(fd: i32) -> i32 {
+    let mut sockerr: libc::c_int = 0;
+    let mut errlen = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let retval = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ERROR,
+            &mut sockerr as *mut libc::c_int as *mut libc::c_void,
+            &mut errlen,
+        )
+    };
+    if retval == -1 {
+        return errno();
+    }
+    sockerr
+}
+
+/* Let reads and polls on this socket busy-wait up to `usecs` microseconds
+ * on the device queue for new packets (SO_BUSY_POLL), 0 to disable.
+ * Values above net.core.busy_read need CAP_NET_ADMIN. Returns AE_OK or
+ * AE_ERR. */
+#[cfg(target_os = "linux")]
+pub fn anet_set_busy_poll(fd: i32, usecs: u32) -> i32 {
+    let value = usecs.min(libc::c_int::MAX as u32) as libc::c_int;
+    set_int_sockopt(fd, libc::SOL_SOCKET, libc::SO_BUSY_POLL, value)
+}
+
+/* Create a non-blocking, close-on-exec TCP socket and start connecting it
+ * to `addr`. The connection is usually still in progress when this returns:
+ * wait for the fd to become writable and check anet_get_socket_error(). */
+pub fn anet_tcp_nonblock_connect(addr: &SocketAddr) -> Result<i32, i32> {
+    let family = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(family, libc::SOCK_STREAM, 0) };
+    if fd == -1 {
+        return Err(errno());
+    }
+    if anet_non_block(fd) == AE_ERR || anet_cloexec(fd) == AE_ERR {
+        let err = errno();
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+
+    let (storage, len) = socket_addr_to_raw(addr);
+    let retval = unsafe { libc::connect(fd, &storage as *const _ as *const libc::sockaddr, len) };
+    if retval == -1 {
+        let err = errno();
+        /* If the socket is non-blocking, it is ok for connect() to
+         * return an EINPROGRESS error here. */
+        if err != libc::EINPROGRESS {
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+    }
+    Ok(fd)
+}
+
+/* Options of a listening socket, see anet_tcp_listen(). */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnetListenOptions {
+    pub backlog: i32,
+    /* SO_REUSEPORT: several sockets can listen on the same address, the
+     * kernel spreading connections among them. */
+    pub reuse_port: bool,
+    /* TCP Fast Open queue length, 0 to leave it off. See
+     * anet_set_tcp_fastopen(). */
+    pub fastopen_queue: i32,
+    /* Seconds to wait for data before a connection is accepted, 0 to
+     * accept on the handshake. See anet_set_defer_accept(). */
+    pub defer_accept_secs: i32,
+    /* Kernel accept filter ("dataready", "httpready", ...) holding
+     * connections back until their request arrived, None for none. See
+     * anet_set_accept_filter(). */
+    pub accept_filter: Option<&'static str>,
+    /* SYN-ACK retransmissions before a handshake the client does not
+     * complete is dropped, 0 for the system default. See
+     * anet_set_synack_retries(). */
+    pub synack_retries: i32,
+}
+
+impl Default for AnetListenOptions {
+    fn default() -> Self {
+        Self {
+            backlog: 511,
+            reuse_port: false,
+            fastopen_queue: 0,
+            defer_accept_secs: 0,
+            accept_filter: None,
+            synack_retries: 0,
+        }
+    }
+}
+
+/* Create a non-blocking, close-on-exec TCP socket listening on `addr`,
+ * like Redis anetTcpServer(): SO_REUSEADDR is always set and IPv6 sockets
+ * are IPv6 only. With `reuse_port` (SO_REUSEPORT) several sockets can
+ * listen on the same address, the kernel spreading connections among
+ * them. */
+pub fn anet_tcp_server(addr: &SocketAddr, backlog: i32, reuse_port: bool) -> Result<i32, i32> {
+    let options = AnetListenOptions {
+        backlog,
+        reuse_port,
+        ..Default::default()
+    };
+    anet_tcp_listen(addr, &options)
+}
+
+/* anet_tcp_server() with the options of `options`, which fails with the
+ * errno of the first option the system refuses. */
+pub fn anet_tcp_listen(addr: &SocketAddr, options: &AnetListenOptions) -> Result<i32, i32> {
+    let family = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(family, libc::SOCK_STREAM, 0) };
+    if fd == -1 {
+        return Err(errno());
+    }
+    let fail = |fd: i32| {
+        let err = errno();
+        unsafe { libc::close(fd) };
+        Err(err)
+    };
+
+    if anet_non_block(fd) == AE_ERR
+        || anet_cloexec(fd) == AE_ERR
+        || set_int_sockopt(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, 1) == AE_ERR
+        || (options.reuse_port
+            && set_int_sockopt(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, 1) == AE_ERR)
+        || (family == libc::AF_INET6
+            && set_int_sockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, 1) == AE_ERR)
+    {
+        return fail(fd);
+    }
+
+    let (storage, len) = socket_addr_to_raw(addr);
+    if unsafe { libc::bind(fd, &storage as *const _ as *const libc::sockaddr, len) } == -1
+        || (options.fastopen_queue > 0
+            && anet_set_tcp_fastopen(fd, options.fastopen_queue) == AE_ERR)
+        || (options.defer_accept_secs > 0
+            && anet_set_defer_accept(fd, options.defer_accept_secs) == AE_ERR)
+        || (options.synack_retries > 0
+            && anet_set_synack_retries(fd, options.synack_retries) == AE_ERR)
+        || unsafe { libc::listen(fd, options.backlog) } == -1
+        /* Only accepted on a socket already listening. */
+        || options
+            .accept_filter
+            .is_some_and(|name| anet_set_accept_filter(fd, name, "") == AE_ERR)
+    {
+        return fail(fd);
+    }
+    Ok(fd)
+}
+
+/* Accept TCP Fast Open connections on the listening socket `fd`, with at
+ * most `queue_len` of them pending before the handshake completes. Such a
+ * client sends its first request in the SYN: the accepted socket may
+ * already hold it, and the request may be a replay of one the server
+ * already got, so only idempotent requests should be answered before the
+ * handshake is known to be complete. Linux also needs the server bit of
+ * net.ipv4.tcp_fastopen. Returns AE_OK or AE_ERR (ENOPROTOOPT where
+ * unsupported). */
+pub fn anet_set_tcp_fastopen(fd: i32, queue_len: i32) -> i32 {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        set_int_sockopt(fd, libc::IPPROTO_TCP, libc::TCP_FASTOPEN, queue_len)
+    }
+    /* Darwin takes an on/off flag, the queue is sized by the kernel. */
+    #[cfg(target_os = "macos")]
+    {
+        set_int_sockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_FASTOPEN,
+            (queue_len > 0) as i32,
+        )
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+    {
+        let _ = (fd, queue_len);
+        set_errno(libc::ENOPROTOOPT);
+        AE_ERR
+    }
+}
+
+/* Accept connections on the listening socket `fd` only once the client
+ * sent data, waiting up to about `secs` seconds for it (TCP_DEFER_ACCEPT,
+ * Linux only), 0 to accept on the handshake again. The listener then stays
+ * quiet for connections that send nothing, and the accepted socket
+ * usually has its request waiting: reading it right away saves a trip
+ * through the poll. A client still silent at the deadline is accepted
+ * anyway on its next packet. Returns AE_OK or AE_ERR (ENOPROTOOPT where
+ * unsupported). */
+pub fn anet_set_defer_accept(fd: i32, secs: i32) -> i32 {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        set_int_sockopt(fd, libc::IPPROTO_TCP, libc::TCP_DEFER_ACCEPT, secs)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = (fd, secs);
+        set_errno(libc::ENOPROTOOPT);
+        AE_ERR
+    }
+}
+
+/* Install the accept filter `name` with its argument `arg` on the
+ * listening socket `fd` (SO_ACCEPTFILTER, FreeBSD and NetBSD): connections
+ * are only reported once the filter is satisfied, "dataready" when data
+ * arrived, "httpready" when a whole HTTP request did, so a client that
+ * connects and says nothing costs the server no wakeup. The filter module
+ * must be loaded (accf_data, accf_http). An empty `name` removes the
+ * filter. Linux has TCP_DEFER_ACCEPT instead, see anet_set_defer_accept().
+ * Returns AE_OK or AE_ERR (ENOPROTOOPT where unsupported, EINVAL for a
+ * name or argument too long). */
+pub fn anet_set_accept_filter(fd: i32, name: &str, arg: &str) -> i32 {
+    #[cfg(any(target_os = "freebsd", target_os = "netbsd"))]
+    {
+        /* struct accept_filter_arg of <sys/socket.h>. */
+        #[repr(C)]
+        struct AcceptFilterArg {
+            af_name: [u8; 16],
+            af_arg: [u8; 256 - 16],
+        }
+        const SO_ACCEPTFILTER: libc::c_int = 0x1000;
+
+        if name.is_empty() {
+            let retval = unsafe {
+                libc::setsockopt(fd, libc::SOL_SOCKET, SO_ACCEPTFILTER, std::ptr::null(), 0)
+            };
+            return if retval == -1 { AE_ERR } else { AE_OK };
+        }
+        let mut filter: AcceptFilterArg = unsafe { std::mem::zeroed() };
+        if name.len() >= filter.af_name.len() || arg.len() >= filter.af_arg.len() {
+            set_errno(libc::EINVAL);
+            return AE_ERR;
+        }
+        filter.af_name[..name.len()].copy_from_slice(name.as_bytes());
+        filter.af_arg[..arg.len()].copy_from_slice(arg.as_bytes());
+        let retval = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                SO_ACCEPTFILTER,
+                &filter as *const AcceptFilterArg as *const libc::c_void,
+                std::mem::size_of::<AcceptFilterArg>() as libc::socklen_t,
+            )
+        };
+        if retval == -1 { AE_ERR } else { AE_OK }
+    }
+    #[cfg(not(any(target_os = "freebsd", target_os = "netbsd")))]
+    {
+        let _ = (fd, name, arg);
+        set_errno(libc::ENOPROTOOPT);
+        AE_ERR
+    }
+}
+
+/* Retransmit the SYN-ACK of a pending connection at most `retries` times
+ * (1 to 255) on the listening socket `fd` before dropping it (TCP_SYNCNT,
+ * Linux only), instead of net.ipv4.tcp_synack_retries. Lower values free
+ * the SYN queue sooner under a SYN flood, at the cost of clients on lossy
+ * links. Returns AE_OK or AE_ERR (ENOPROTOOPT where unsupported). */
+pub fn anet_set_synack_retries(fd: i32, retries: i32) -> i32 {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        set_int_sockopt(fd, libc::IPPROTO_TCP, libc::TCP_SYNCNT, retries)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = (fd, retries);
+        set_errno(libc::ENOPROTOOPT);
+        AE_ERR
+    }
+}
+
+/* SYN cookie mode of the system (net.ipv4.tcp_syncookies on Linux): 0
+ * off, 1 once the SYN queue of a listener overflows, 2 always. While
+ * cookies are in use the handshake drops the TCP options the client sent
+ * without timestamps (window scaling, SACK), and TCP Fast Open data is
+ * not accepted. None where it cannot be read. */
+pub fn anet_syn_cookies() -> Option<i32> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        std::fs::read_to_string("/proc/sys/net/ipv4/tcp_syncookies")
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        None
+    }
+}
+
+/* Local address of the socket (getsockname), e.g. to learn the port the
+ * kernel picked for a socket bound to port 0. */
+pub fn anet_local_addr(fd: i32) -> Result<SocketAddr, i32> {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    if unsafe { libc::getsockname(fd, &mut storage as *mut _ as *mut libc::sockaddr, &mut len) }
+        == -1
+    {
+        return Err(errno());
+    }
+    raw_to_socket_addr(&storage).ok_or(libc::EAFNOSUPPORT)
+}
+
+/* Accept a connection on the listening socket `fd`. The new fd is
+ * non-blocking and close-on-exec. The peer address is None for non IP
+ * sockets (Unix domain). On failure the errno is returned (EAGAIN when
+ * there is nothing left to accept). */
+pub fn anet_accept(fd: i32) -> Result<(i32, Option<SocketAddr>), i32> {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let cfd = loop {
+        let cfd =
+            unsafe { libc::accept(fd, &mut storage as *mut _ as *mut libc::sockaddr, &mut len) };
+        if cfd == -1 {
+            let err = errno();
+            if err == libc::EINTR {
+                continue;
+            }
+            return Err(err);
+        }
+        break cfd;
+    };
+
+    if anet_non_block(cfd) == AE_ERR || anet_cloexec(cfd) == AE_ERR {
+        let err = errno();
+        unsafe { libc::close(cfd) };
+        return Err(err);
+    }
+    Ok((cfd, raw_to_socket_addr(&storage)))
+}
+
+/* Ask the kernel to timestamp incoming packets on `fd`, to be read back
+ * with anet_recv_timestamped(). Software timestamps use SO_TIMESTAMPNS on
+ * Linux (SO_TIMESTAMP elsewhere). On Linux SO_TIMESTAMPING is enabled as
+ * well, on a best effort basis, for hardware receive timestamps on NICs
+ * that support them. It does not replace SO_TIMESTAMPNS: its software
+ * stamps only start once the kernel has enabled timestamping globally,
+ * which happens asynchronously, so the first packets could be missed.
+ * Returns AE_OK or AE_ERR. */
+pub fn anet_enable_rx_timestamps(fd: i32) -> i32 {
+    #[cfg(target_os = "linux")]
+    {
+        if set_int_sockopt(fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, 1) == AE_ERR {
+            return AE_ERR;
+        }
+        let flags = libc::SOF_TIMESTAMPING_RX_HARDWARE | libc::SOF_TIMESTAMPING_RAW_HARDWARE;
+        set_int_sockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            flags as libc::c_int,
+        );
+        AE_OK
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        set_int_sockopt(fd, libc::SOL_SOCKET, libc::SO_TIMESTAMP, 1)
+    }
+}
+
+fn set_int_sockopt(fd: i32, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> i32 {
+    let retval = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if retval == -1 { AE_ERR } else { AE_OK }
+}
+
+/* Receive into `buf` like recv(2), also returning the kernel receive
+ * timestamp of the data when timestamping was enabled with
+ * anet_enable_rx_timestamps() (None otherwise). A hardware timestamp is
+ * preferred over a software one. On failure the errno is returned. */
+pub fn anet_recv_timestamped(
+    fd: i32,
+    buf: &mut [u8],
+) -> Result<(usize, Option<std::time::SystemTime>), i32> {
+    /* Room for SCM_TIMESTAMPING, the largest of the timestamp messages
+     * (three timespecs), with some slack for unrelated messages. */
+    let mut control = [0u64; 32];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    let n = loop {
+        let n = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+        if n == -1 {
+            let err = errno();
+            if err == libc::EINTR {
+                continue;
+            }
+            return Err(err);
+        }
+        break n as usize;
+    };
+
+    let mut software = None;
+    let mut hardware = None;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let hdr = unsafe { &*cmsg };
+        if hdr.cmsg_level == libc::SOL_SOCKET {
+            let data = unsafe { libc::CMSG_DATA(cmsg) };
+            match unsafe { parse_timestamp_cmsg(hdr.cmsg_type, data) } {
+                Some((t, true)) => hardware = Some(t),
+                Some((t, false)) => software = Some(t),
+                None => {}
+            }
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+    Ok((n, hardware.or(software)))
+}
+
+/* Decode one SOL_SOCKET control message if it carries a timestamp. The
+ * flag tells whether it is a hardware timestamp. */
+unsafe fn parse_timestamp_cmsg(
+    cmsg_type: libc::c_int,
+    data: *const libc::c_uchar,
+) -> Option<(std::time::SystemTime, bool)> {
+    let from_parts = |secs: i64, nanos: i64| -> Option<std::time::SystemTime> {
+        if secs == 0 && nanos == 0 {
+            return None;
+        }
+        let d = std::time::Duration::new(secs as u64, nanos as u32);
+        Some(std::time::UNIX_EPOCH + d)
+    };
+
+    #[cfg(target_os = "linux")]
+    {
+        if cmsg_type == libc::SCM_TIMESTAMPING {
+            /* [0] software, [1] deprecated, [2] raw hardware. */
+            let ts = unsafe { std::ptr::read_unaligned(data as *const [libc::timespec; 3]) };
+            return from_parts(ts[2].tv_sec as i64, ts[2].tv_nsec as i64)
+                .map(|t| (t, true))
+                .or_else(|| {
+                    from_parts(ts[0].tv_sec as i64, ts[0].tv_nsec as i64).map(|t| (t, false))
+                });
+        }
+        if cmsg_type == libc::SCM_TIMESTAMPNS {
+            let ts = unsafe { std::ptr::read_unaligned(data as *const libc::timespec) };
+            return from_parts(ts.tv_sec as i64, ts.tv_nsec as i64).map(|t| (t, false));
+        }
+    }
+    if cmsg_type == libc::SCM_TIMESTAMP {
+        let tv = unsafe { std::ptr::read_unaligned(data as *const libc::timeval) };
+        return from_parts(tv.tv_sec as i64, tv.tv_usec as i64 * 1000).map(|t| (t, false));
+    }
+    None
+}
+
+/* Convert a std socket address into the raw representation expected by
+ * bind(2)/connect(2). */
+pub(crate) fn socket_addr_to_raw(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(a) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = a.port().to_be();
+            sin.sin_addr = libc::in_addr {
+                s_addr: u32::from_ne_bytes(a.ip().octets()),
+            };
+            #[cfg(any(
+                target_os = "macos",
+                target_os = "freebsd",
+                target_os = "openbsd",
+                target_os = "netbsd"
+            ))]
+            {
+                sin.sin_len = std::mem::size_of::<libc::sockaddr_in>() as u8;
+            }
+            std::mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(a) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = a.port().to_be();
+            sin6.sin6_flowinfo = a.flowinfo().to_be();
+            sin6.sin6_addr = libc::in6_addr {
+                s6_addr: a.ip().octets(),
+            };
+            sin6.sin6_scope_id = a.scope_id();
+            #[cfg(any(
+                target_os = "macos",
+                target_os = "freebsd",
+                target_os = "openbsd",
+                target_os = "netbsd"
+            ))]
+            {
+                sin6.sin6_len = std::mem::size_of::<libc::sockaddr_in6>() as u8;
+            }
+            std::mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+/* Inverse of socket_addr_to_raw(), None for non IP families. */
+pub(crate) fn raw_to_socket_addr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match storage.ss_family as i32 {
+        libc::AF_INET => {
+            let sin = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+            let ip = std::net::Ipv4Addr::from(sin.sin_addr.s_addr.to_ne_bytes());
+            Some(SocketAddr::from((ip, u16::from_be(sin.sin_port))))
+        }
+        libc::AF_INET6 => {
+            let sin6 = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+            Some(SocketAddr::V6(std::net::SocketAddrV6::new(
+                std::net::Ipv6Addr::from(sin6.sin6_addr.s6_addr),
+                u16::from_be(sin6.sin6_port),
+                u32::from_be(sin6.sin6_flowinfo),
+                sin6.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+/* Write the given slices with a single writev(2) call.
+ *
+ * Returns the number of bytes written, which may be less than the total
+ * length of the slices. On failure the errno is returned (EAGAIN when the
+ * socket buffer is full). */
+pub fn anet_writev(fd: i32, bufs: &[IoSlice<'_>]) -> Result<usize, i32> {
+    let iovcnt = bufs.len().min(ANET_IOV_MAX);
+    /* IoSlice is guaranteed to be ABI compatible with iovec on Unix. */
+    let retval = unsafe { libc::writev(fd, bufs.as_ptr() as *const libc::iovec, iovcnt as i32) };
+    if retval < 0 {
+        return Err(errno());
+    }
+    Ok(retval as usize)
+}
+
+/* Read into the given slices with a single readv(2) call.
+ *
+ * Returns the number of bytes read, 0 meaning end of file. On failure the
+ * errno is returned (EAGAIN when nothing is available yet). */
+pub fn anet_readv(fd: i32, bufs: &mut [IoSliceMut<'_>]) -> Result<usize, i32> {
+    let iovcnt = bufs.len().min(ANET_IOV_MAX);
+    let retval = unsafe { libc::readv(fd, bufs.as_mut_ptr() as *mut libc::iovec, iovcnt as i32) };
+    if retval < 0 {
+        return Err(errno());
+    }
+    Ok(retval as usize)
+}
+
+/* Send each payload as a datagram to its address (the connected peer if
+ * None), with one sendmmsg(2) on Linux and one sendto(2) per datagram
+ * elsewhere. At most ANET_IOV_MAX go out per call.
+ *
+ * Returns how many datagrams were sent, which may be fewer than given. On
+ * failure of the first one its errno is returned (EAGAIN when the socket
+ * buffer is full). */
+pub fn anet_udp_send_batch(
+    fd: i32,
+    datagrams: &[(&[u8], Option<SocketAddr>)],
+) -> Result<usize, i32> {
+    let datagrams = &datagrams[..datagrams.len().min(ANET_IOV_MAX)];
+    let addrs: Vec<Option<(libc::sockaddr_storage, libc::socklen_t)>> = datagrams
+        .iter()
+        .map(|(_, addr)| addr.as_ref().map(socket_addr_to_raw))
+        .collect();
+    let mut iovs: Vec<libc::iovec> = datagrams
+        .iter()
+        .map(|(data, _)| libc::iovec {
+            iov_base: data.as_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        })
+        .collect();
+    let header = |index: usize, iov: *mut libc::iovec| {
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        if let Some((storage, len)) = &addrs[index] {
+            msg.msg_name = storage as *const _ as *mut libc::c_void;
+            msg.msg_namelen = *len;
+        }
+        msg.msg_iov = iov;
+        msg.msg_iovlen = 1;
+        msg
+    };
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut msgs: Vec<libc::mmsghdr> = iovs
+            .iter_mut()
+            .enumerate()
+            .map(|(index, iov)| libc::mmsghdr {
+                msg_hdr: header(index, iov),
+                msg_len: 0,
+            })
+            .collect();
+        loop {
+            let sent = unsafe { libc::sendmmsg(fd, msgs.as_mut_ptr(), msgs.len() as u32, 0) };
+            if sent >= 0 {
+                return Ok(sent as usize);
+            }
+            let err = errno();
+            if err != libc::EINTR {
+                return Err(err);
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let count = iovs.len();
+        for (index, iov) in iovs.iter_mut().enumerate() {
+            let msg = header(index, iov);
+            if unsafe { libc::sendmsg(fd, &msg, 0) } == -1 {
+                let err = errno();
+                return if index == 0 { Err(err) } else { Ok(index) };
+            }
+        }
+        Ok(count)
+    }
+}
+
+/* Send `data` as datagrams of `segment_size` bytes, the last one possibly
+ * shorter, to `addr` (the connected peer if None). On Linux it is handed
+ * to the kernel in one sendmsg(2) with UDP_SEGMENT and split by the stack
+ * or the NIC (GSO); elsewhere, when there are more segments than GSO
+ * takes, or when the device refuses it, the datagrams go out through
+ * anet_udp_send_batch().
+ *
+ * Returns the bytes sent, whole segments only. On failure of the first
+ * segment its errno is returned. */
+pub fn anet_udp_send_segments(
+    fd: i32,
+    data: &[u8],
+    segment_size: usize,
+    addr: Option<SocketAddr>,
+) -> Result<usize, i32> {
+    if segment_size == 0 {
+        return Err(libc::EINVAL);
+    }
+    #[cfg(target_os = "linux")]
+    if data.len() > segment_size
+        && data.len() <= u16::MAX as usize
+        && data.len().div_ceil(segment_size) <= ANET_UDP_MAX_SEGMENTS
+    {
+        match send_gso(fd, data, segment_size as u16, addr) {
+            /* No checksum offload on the device. */
+            Err(libc::EIO) => {}
+            result => return result,
+        }
+    }
+
+    let segments: Vec<(&[u8], Option<SocketAddr>)> = data
+        .chunks(segment_size)
+        .map(|chunk| (chunk, addr))
+        .collect();
+    let mut sent = 0;
+    while sent < segments.len() {
+        match anet_udp_send_batch(fd, &segments[sent..]) {
+            Ok(count) => sent += count,
+            Err(err) if sent == 0 => return Err(err),
+            Err(_) => break,
+        }
+    }
+    Ok(data.len().min(sent * segment_size))
+}
+
+/* Segments Linux takes in one UDP_SEGMENT send (UDP_MAX_SEGMENTS of older
+ * kernels, newer ones take 128). */
+#[cfg(target_os = "linux")]
+pub const ANET_UDP_MAX_SEGMENTS: usize = 64;
+
+#[cfg(target_os = "linux")]
+fn send_gso(
+    fd: i32,
+    data: &[u8],
+    segment_size: u16,
+    addr: Option<SocketAddr>,
+) -> Result<usize, i32> {
+    let raw = addr.as_ref().map(socket_addr_to_raw);
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let mut control = [0u64; 4];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    if let Some((storage, len)) = &raw {
+        msg.msg_name = storage as *const _ as *mut libc::c_void;
+        msg.msg_namelen = *len;
+    }
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = unsafe { libc::CMSG_SPACE(std::mem::size_of::<u16>() as u32) } as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_UDP;
+        (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<u16>() as u32) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment_size);
+    }
+    loop {
+        let sent = unsafe { libc::sendmsg(fd, &msg, 0) };
+        if sent >= 0 {
+            return Ok(sent as usize);
+        }
+        let err = errno();
+        if err != libc::EINTR {
+            return Err(err);
+        }
+    }
+}
+
+/* Let the kernel merge datagrams received back to back from one sender
+ * into a single buffer (UDP_GRO, Linux only): ae_udp_register() then
+ * reports the segment size to split it at. Returns AE_OK or AE_ERR
+ * (ENOPROTOOPT where unsupported). */
+pub fn anet_set_udp_gro(fd: i32, enable: bool) -> i32 {
+    #[cfg(target_os = "linux")]
+    {
+        set_int_sockopt(fd, libc::SOL_UDP, libc::UDP_GRO, enable as i32)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (fd, enable);
+        set_errno(libc::ENOPROTOOPT);
+        AE_ERR
+    }
+}
+
+/* Open a non-blocking raw socket (SOCK_RAW) of `family` for `protocol`,
+ * e.g. AF_INET with IPPROTO_ICMP. Needs CAP_NET_RAW on Linux and root
+ * elsewhere (EPERM otherwise). Its datagrams are read like UDP ones,
+ * with ae_udp_register(). */
+pub fn anet_raw_socket(family: i32, protocol: i32) -> Result<i32, i32> {
+    let fd = unsafe { libc::socket(family, libc::SOCK_RAW, protocol) };
+    if fd == -1 {
+        return Err(errno());
+    }
+    if anet_non_block(fd) == AE_ERR || anet_cloexec(fd) == AE_ERR {
+        let err = errno();
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+    Ok(fd)
+}
+
+/* Open an AF_PACKET socket receiving the frames of EtherType `protocol`
+ * (ETH_P_ALL for every frame), link-layer header included, from the
+ * interface `ifindex` (0 for all of them, see if_nametoindex(3)). Linux
+ * only, needs CAP_NET_RAW. */
+#[cfg(target_os = "linux")]
+pub fn anet_packet_socket(protocol: u16, ifindex: u32) -> Result<i32, i32> {
+    let fd = anet_raw_socket(libc::AF_PACKET, protocol.to_be() as i32)?;
+    let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+    addr.sll_family = libc::AF_PACKET as u16;
+    addr.sll_protocol = protocol.to_be();
+    addr.sll_ifindex = ifindex as i32;
+    let retval = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+        )
+    };
+    if retval == -1 {
+        let err = errno();
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+    Ok(fd)
+}
+
+/* Open a non-blocking netlink socket of `protocol` (NETLINK_ROUTE,
+ * NETLINK_KOBJECT_UEVENT, ...) subscribed to the multicast `groups`
+ * (RTMGRP_LINK | RTMGRP_IPV4_IFADDR, ..., or 0 for requests only), with a
+ * port id chosen by the kernel. Linux only. Read it with
+ * ae_netlink_register(). */
+#[cfg(target_os = "linux")]
+pub fn anet_netlink_socket(protocol: i32, groups: u32) -> Result<i32, i32> {
+    let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW, protocol) };
+    if fd == -1 {
+        return Err(errno());
+    }
+    let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as u16;
+    addr.nl_groups = groups;
+    let retval = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if retval == -1 || anet_non_block(fd) == AE_ERR || anet_cloexec(fd) == AE_ERR {
+        let err = errno();
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+    Ok(fd)
+}
+
+/* Send one netlink message to the kernel: a header with `msg_type`,
+ * `flags` (NLM_F_REQUEST is added) and `seq`, then `payload`, e.g. an
+ * ifinfomsg for RTM_GETLINK with NLM_F_DUMP. Returns the bytes sent or
+ * the errno. */
+#[cfg(target_os = "linux")]
+pub fn anet_netlink_send(
+    fd: i32,
+    msg_type: u16,
+    flags: u16,
+    seq: u32,
+    payload: &[u8],
+) -> Result<usize, i32> {
+    let header_len = std::mem::size_of::<libc::nlmsghdr>();
+    let header = libc::nlmsghdr {
+        nlmsg_len: (header_len + payload.len()) as u32,
+        nlmsg_type: msg_type,
+        nlmsg_flags: flags | libc::NLM_F_REQUEST as u16,
+        nlmsg_seq: seq,
+        nlmsg_pid: 0,
+    };
+    let mut msg = Vec::with_capacity(header_len + payload.len());
+    msg.extend_from_slice(unsafe {
+        std::slice::from_raw_parts(&header as *const libc::nlmsghdr as *const u8, header_len)
+    });
+    msg.extend_from_slice(payload);
+    loop {
+        let sent = unsafe { libc::send(fd, msg.as_ptr() as *const libc::c_void, msg.len(), 0) };
+        if sent >= 0 {
+            return Ok(sent as usize);
+        }
+        let err = errno();
+        if err != libc::EINTR {
+            return Err(err);
+        }
+    }
+}
+
+/* One instruction of a classic BPF program, laid out like struct
+ * sock_filter. */
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnetBpfInsn {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+/* Attach a classic BPF program to the socket `fd` (SO_ATTACH_FILTER), so
+ * that the kernel drops what it rejects before the loop is woken up. It
+ * replaces a program attached earlier. Linux only: BSD and macOS filter
+ * on /dev/bpf devices, not sockets, and fail with ENOPROTOOPT. Returns
+ * AE_OK or AE_ERR (EINVAL for an empty or invalid program). */
+pub fn anet_attach_filter(fd: i32, program: &[AnetBpfInsn]) -> i32 {
+    #[cfg(target_os = "linux")]
+    {
+        if program.is_empty() || program.len() > u16::MAX as usize {
+            set_errno(libc::EINVAL);
+            return AE_ERR;
+        }
+        let fprog = libc::sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_ptr() as *mut libc::sock_filter,
+        };
+        let retval = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_ATTACH_FILTER,
+                &fprog as *const libc::sock_fprog as *const libc::c_void,
+                std::mem::size_of::<libc::sock_fprog>() as libc::socklen_t,
+            )
+        };
+        if retval == -1 { AE_ERR } else { AE_OK }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (fd, program);
+        set_errno(libc::ENOPROTOOPT);
+        AE_ERR
+    }
+}
+
+/* Remove the program attached with anet_attach_filter(). Returns AE_OK or
+ * AE_ERR (ENOENT when there is none). */
+pub fn anet_detach_filter(fd: i32) -> i32 {
+    #[cfg(target_os = "linux")]
+    {
+        set_int_sockopt(fd, libc::SOL_SOCKET, libc::SO_DETACH_FILTER, 0)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = fd;
+        set_errno(libc::ENOPROTOOPT);
+        AE_ERR
+    }
+}
+
+/* A queue of owned buffers waiting to be written.
+ *
+ * Protocol code appends a header and a body as separate buffers and the
+ * chain hands them to writev() as one batch, so nothing has to be copied
+ * into a contiguous reply buffer. Partially written buffers are tracked
+ * with an offset into the front buffer. */
+#[derive(Debug, Default)]
+pub struct BufChain {
+    bufs: VecDeque<Vec<u8>>,
+    /* Bytes of the front buffer already written. */
+    offset: usize,
+    /* Bytes still pending across all buffers. */
+    len: usize,
+}
+
+impl BufChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /* Append an owned buffer. Empty buffers are dropped. */
+    pub fn push(&mut self, buf: Vec<u8>) {
+        if buf.is_empty() {
+            return;
+        }
+        self.len += buf.len();
+        self.bufs.push_back(buf);
+    }
+
+    /* Append a copy of the given bytes. */
+    pub fn push_slice(&mut self, data: &[u8]) {
+        self.push(data.to_vec());
+    }
+
+    /* Number of bytes not yet written. */
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /* Number of buffers still (partially) queued. */
+    pub fn buffers(&self) -> usize {
+        self.bufs.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.bufs.clear();
+        self.offset = 0;
+        self.len = 0;
+    }
+
+    /* Fill `out` with slices covering the pending bytes, at most
+     * ANET_IOV_MAX of them. */
+    pub fn io_slices<'a>(&'a self, out: &mut Vec<IoSlice<'a>>) {
+        out.clear();
+        for (i, buf) in self.bufs.iter().take(ANET_IOV_MAX).enumerate() {
+            let start = if i == 0 { self.offset } else { 0 };
+            out.push(IoSlice::new(&buf[start..]));
+        }
+    }
+
+    /* Drop `n` bytes from the front of the chain, releasing buffers that
+     * have been written entirely. */
+    pub fn consume(&mut self, mut n: usize) {
+        n = n.min(self.len);
+        self.len -= n;
+        while n > 0 {
+            let front_left = self.bufs[0].len() - self.offset;
+            if n < front_left {
+                self.offset += n;
+                return;
+            }
+            n -= front_left;
+            self.bufs.pop_front();
+            self.offset = 0;
+        }
+    }
+
+    /* Write as much of the chain as the fd accepts with one writev() call
+     * and consume what was written. */
+    pub fn write_to(&mut self, fd: i32) -> Result<usize, i32> {
+        if self.is_empty() {
+            return Ok(0);
+        }
+        let mut slices = Vec::with_capacity(self.bufs.len().min(ANET_IOV_MAX));
+        self.io_slices(&mut slices);
+        let written = anet_writev(fd, &slices)?;
+        drop(slices);
+        self.consume(written);
+        Ok(written)
+    }
+}
@@ -0,0 +1,209 @@
+/* rae-demo: echo and chat servers on a single event loop.
+ *
+ *     rae-demo echo [addr]    echo every byte back
+ *     rae-demo chat [addr]    line based chat room
+ *
+ * addr defaults to 127.0.0.1:7711, port 0 picks a free one. The address
+ * actually bound is printed on stdout once listening. Chat clients are
+ * named after their fd until they send "/nick <name>", "/quit" leaves,
+ * any other line goes to everyone else in the room. Every few seconds a
+ * timer logs the traffic on stderr. SIGINT and SIGTERM stop the server.
+ *
+ * Built with the `demo` feature: cargo run --features demo --bin rae-demo
+ */
+
+use rae::{
+    AE_ERR, AE_OK, AE_READABLE, AeEventLoop, AeFraming, ae_accept, ae_conn_close,
+    ae_conn_close_after_write, ae_conn_create, ae_conn_write, ae_create_event_loop,
+    ae_create_file_event, ae_create_time_event, ae_delete_event_loop, ae_delete_file_event,
+    ae_framed_create, ae_framed_write, ae_main, ae_stop_on_signal, anet_local_addr,
+    anet_tcp_server,
+};
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+use std::io::Write;
+use std::net::SocketAddr;
+
+const DEFAULT_ADDR: &str = "127.0.0.1:7711";
+const STATS_PERIOD_MS: i64 = 5000;
+const MAX_LINE: usize = 4096;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Echo,
+    Chat,
+}
+
+/* Everything the callbacks share, passed around as their client data. */
+struct Server {
+    mode: Mode,
+    /* Open connections and, for chat, their nick. */
+    clients: BTreeMap<i32, String>,
+    bytes_echoed: u64,
+    messages: u64,
+    /* Traffic already logged by the stats timer. */
+    logged: (usize, u64, u64),
+}
+
+fn server<'a>(client_data: *mut c_void) -> &'a mut Server {
+    unsafe { &mut *(client_data as *mut Server) }
+}
+
+fn accept_handler(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let srv = server(client_data);
+    while let Ok((cfd, _addr)) = ae_accept(event_loop, fd, b"-ERR max clients reached\r\n") {
+        let result = match srv.mode {
+            Mode::Echo => ae_conn_create(event_loop, cfd, echo_read, Some(on_close), client_data),
+            Mode::Chat => ae_framed_create(
+                event_loop,
+                cfd,
+                AeFraming::Lines { max_len: MAX_LINE },
+                chat_line,
+                Some(on_close),
+                client_data,
+            ),
+        };
+        if result == AE_ERR {
+            unsafe { libc::close(cfd) };
+            continue;
+        }
+        let nick = format!("user{cfd}");
+        srv.clients.insert(cfd, nick.clone());
+        if srv.mode == Mode::Chat {
+            ae_framed_write(event_loop, cfd, format!("* you are {nick}").as_bytes());
+            broadcast(event_loop, srv, cfd, &format!("* {nick} joined"));
+        }
+    }
+}
+
+fn echo_read(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    input: &[u8],
+    client_data: *mut c_void,
+) -> usize {
+    server(client_data).bytes_echoed += input.len() as u64;
+    ae_conn_write(event_loop, fd, input);
+    input.len()
+}
+
+fn chat_line(event_loop: &mut AeEventLoop, fd: i32, line: &[u8], client_data: *mut c_void) {
+    let srv = server(client_data);
+    let line = String::from_utf8_lossy(line);
+    if line == "/quit" {
+        ae_framed_write(event_loop, fd, b"* bye");
+        ae_conn_close_after_write(event_loop, fd);
+    } else if let Some(nick) = line
+        .strip_prefix("/nick")
+        .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+    {
+        let nick = nick.trim().to_string();
+        if nick.is_empty() || nick.contains(' ') {
+            ae_framed_write(event_loop, fd, b"* usage: /nick <name>");
+            return;
+        }
+        let old = srv.clients.insert(fd, nick.clone()).unwrap_or_default();
+        broadcast(event_loop, srv, -1, &format!("* {old} is now {nick}"));
+    } else {
+        let msg = format!("<{}> {line}", srv.clients[&fd]);
+        srv.messages += 1;
+        broadcast(event_loop, srv, fd, &msg);
+    }
+}
+
+/* Send `msg` to every chat client but `except`. A failed write closes
+ * its client, which updates the map: iterate over a copy. */
+fn broadcast(event_loop: &mut AeEventLoop, srv: &Server, except: i32, msg: &str) {
+    let fds: Vec<i32> = srv
+        .clients
+        .keys()
+        .copied()
+        .filter(|&fd| fd != except)
+        .collect();
+    for fd in fds {
+        ae_framed_write(event_loop, fd, msg.as_bytes());
+    }
+}
+
+fn on_close(event_loop: &mut AeEventLoop, fd: i32, _err: i32, client_data: *mut c_void) {
+    let srv = server(client_data);
+    if let Some(nick) = srv.clients.remove(&fd)
+        && srv.mode == Mode::Chat
+    {
+        broadcast(event_loop, srv, fd, &format!("* {nick} left"));
+    }
+}
+
+fn log_stats(_event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    let srv = server(client_data);
+    let now = (srv.clients.len(), srv.bytes_echoed, srv.messages);
+    if now != srv.logged {
+        eprintln!(
+            "{} clients, {} bytes echoed, {} messages",
+            now.0, now.1, now.2
+        );
+        srv.logged = now;
+    }
+    STATS_PERIOD_MS as i32
+}
+
+fn usage() -> ! {
+    eprintln!("usage: rae-demo <echo|chat> [addr]");
+    std::process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mode = match args.first().map(String::as_str) {
+        Some("echo") => Mode::Echo,
+        Some("chat") => Mode::Chat,
+        _ => usage(),
+    };
+    let addr: SocketAddr = match args.get(1).map_or(DEFAULT_ADDR, String::as_str).parse() {
+        Ok(addr) => addr,
+        Err(_) => usage(),
+    };
+
+    let listen_fd = match anet_tcp_server(&addr, 511, false) {
+        Ok(fd) => fd,
+        Err(err) => {
+            eprintln!("rae-demo: cannot listen on {addr}: errno {err}");
+            std::process::exit(1);
+        }
+    };
+    let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+    let mut srv = Server {
+        mode,
+        clients: BTreeMap::new(),
+        bytes_echoed: 0,
+        messages: 0,
+        logged: (0, 0, 0),
+    };
+    let srv_ptr = &mut srv as *mut Server as *mut c_void;
+
+    let registered = ae_create_file_event(
+        &mut event_loop,
+        listen_fd,
+        AE_READABLE,
+        accept_handler,
+        srv_ptr,
+    );
+    assert_eq!(registered, AE_OK);
+    ae_create_time_event(&mut event_loop, STATS_PERIOD_MS, log_stats, srv_ptr, None);
+    ae_stop_on_signal(&mut event_loop, libc::SIGINT);
+    ae_stop_on_signal(&mut event_loop, libc::SIGTERM);
+
+    let local = anet_local_addr(listen_fd).unwrap_or(addr);
+    println!("listening on {local}");
+    let _ = std::io::stdout().flush();
+    ae_main(&mut event_loop);
+
+    /* Connections must be closed before the loop goes away. */
+    let fds: Vec<i32> = srv.clients.keys().copied().collect();
+    for fd in fds {
+        ae_conn_close(&mut event_loop, fd);
+    }
+    ae_delete_file_event(&mut event_loop, listen_fd, AE_READABLE);
+    unsafe { libc::close(listen_fd) };
+    ae_delete_event_loop(event_loop);
+}
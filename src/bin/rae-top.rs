@@ -0,0 +1,334 @@
+/* rae-top: live view of a loop through its admin endpoint.
+ *
+ *     rae-top [--once] <socket> [interval_ms]
+ *
+ * Connects to the Unix socket opened by ae_admin_listen() and, every
+ * interval (1000 ms by default), asks for INFO, FDS and SLOWLOG to render
+ * the loop utilization and latencies, the fds dispatched the most since
+ * the previous refresh, and the job slices that overran their budget.
+ * With --once a single frame is printed, without clearing the screen,
+ * and rae-top exits. SIGINT and SIGTERM stop it, and so does the process
+ * being watched going away.
+ *
+ * rae-top runs on a rae loop itself: a timer sends the commands, a
+ * buffered connection reads the replies.
+ *
+ * Built with the `top` feature: cargo run --features top --bin rae-top
+ */
+
+use rae::{
+    AE_ERR, AeEventLoop, ae_conn_close, ae_conn_create, ae_conn_write, ae_create_event_loop,
+    ae_create_time_event, ae_delete_event_loop, ae_main, ae_stop, ae_stop_on_signal,
+};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::c_void;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixStream;
+
+const DEFAULT_INTERVAL_MS: i64 = 1000;
+/* Rows of the fd and slowlog tables. */
+const TOP_FDS: usize = 10;
+const SLOWLOG_ROWS: usize = 5;
+const BAR_WIDTH: usize = 40;
+
+/* Commands of one refresh, answered in order. */
+const COMMANDS: [&str; 3] = ["INFO", "FDS", "SLOWLOG"];
+
+/* One line of the FDS reply. */
+struct FdRow {
+    fd: i32,
+    mask: String,
+    tag: String,
+    events: u64,
+    /* Dispatches since the previous refresh. */
+    delta: u64,
+    traffic: Option<(String, String)>,
+}
+
+struct Top {
+    fd: i32,
+    once: bool,
+    interval_ms: i64,
+    /* Commands sent and not answered yet. */
+    pending: VecDeque<&'static str>,
+    /* Lines of the reply being read. */
+    lines: Vec<String>,
+    info: Vec<(String, String)>,
+    fds: Vec<FdRow>,
+    slowlog: Vec<String>,
+    /* Dispatch counts of the previous refresh. */
+    last_events: HashMap<i32, u64>,
+    frames: u64,
+}
+
+fn top<'a>(client_data: *mut c_void) -> &'a mut Top {
+    unsafe { &mut *(client_data as *mut Top) }
+}
+
+fn refresh(event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    let t = top(client_data);
+    /* The previous refresh is still unanswered: skip this one rather than
+     * pile commands up behind a busy loop. */
+    if t.pending.is_empty() {
+        let mut request = String::new();
+        for command in COMMANDS {
+            request.push_str(command);
+            if command == "SLOWLOG" {
+                let _ = write!(request, " {}", SLOWLOG_ROWS);
+            }
+            request.push('\n');
+            t.pending.push_back(command);
+        }
+        ae_conn_write(event_loop, t.fd, request.as_bytes());
+    }
+    t.interval_ms as i32
+}
+
+fn read_replies(
+    event_loop: &mut AeEventLoop,
+    _fd: i32,
+    input: &[u8],
+    client_data: *mut c_void,
+) -> usize {
+    let t = top(client_data);
+    let mut consumed = 0;
+    while let Some(newline) = input[consumed..].iter().position(|&c| c == b'\n') {
+        let line = String::from_utf8_lossy(&input[consumed..consumed + newline]).into_owned();
+        consumed += newline + 1;
+        if !line.is_empty() {
+            t.lines.push(line);
+            continue;
+        }
+        let lines = std::mem::take(&mut t.lines);
+        match t.pending.pop_front() {
+            Some("INFO") => t.info = parse_info(lines),
+            Some("FDS") => t.fds = parse_fds(lines, &mut t.last_events),
+            Some("SLOWLOG") => {
+                t.slowlog = lines;
+                render(t);
+                if t.once {
+                    ae_stop(event_loop);
+                }
+            }
+            _ => {}
+        }
+    }
+    consumed
+}
+
+/* The watched process went away, or closed the endpoint. Not called
+ * for the close of main(), which clears the fd first. */
+fn on_close(event_loop: &mut AeEventLoop, _fd: i32, err: i32, client_data: *mut c_void) {
+    let t = top(client_data);
+    if t.fd == -1 {
+        return;
+    }
+    /* A reset too: the loop may go away with a command unread. */
+    if err == 0 || err == libc::ECONNRESET || err == libc::EPIPE {
+        eprintln!("rae-top: connection closed by the loop");
+    } else {
+        eprintln!("rae-top: connection lost: errno {err}");
+    }
+    t.fd = -1;
+    ae_stop(event_loop);
+}
+
+fn parse_info(lines: Vec<String>) -> Vec<(String, String)> {
+    lines
+        .into_iter()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/* "fd mask key=value...", see the FDS command in src/ae/admin.rs. */
+fn parse_fds(lines: Vec<String>, last_events: &mut HashMap<i32, u64>) -> Vec<FdRow> {
+    let mut rows = Vec::new();
+    let mut seen = HashMap::new();
+    for line in lines {
+        let mut words = line.split_whitespace();
+        let (Some(Ok(fd)), Some(mask)) = (words.next().map(str::parse::<i32>), words.next()) else {
+            continue;
+        };
+        let fields: HashMap<&str, &str> = words.filter_map(|w| w.split_once('=')).collect();
+        let events = fields
+            .get("events")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        /* A count lower than before is a new file behind the fd number. */
+        let delta = match last_events.get(&fd) {
+            Some(&last) if last <= events => events - last,
+            _ => events,
+        };
+        seen.insert(fd, events);
+        rows.push(FdRow {
+            fd,
+            mask: mask.to_string(),
+            tag: fields.get("tag").unwrap_or(&"0").to_string(),
+            events,
+            delta,
+            traffic: fields
+                .get("in")
+                .zip(fields.get("out"))
+                .map(|(i, o)| (i.to_string(), o.to_string())),
+        });
+    }
+    *last_events = seen;
+    rows.sort_by_key(|row| std::cmp::Reverse((row.delta, row.events)));
+    rows
+}
+
+fn render(t: &mut Top) {
+    let info: HashMap<&str, &str> = t
+        .info
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    let get = |key: &str| info.get(key).copied().unwrap_or("-");
+    let utilization: f64 = get("utilization").parse().unwrap_or(0.0);
+    let filled = ((utilization.clamp(0.0, 1.0) * BAR_WIDTH as f64).round()) as usize;
+
+    let mut out = String::new();
+    if !t.once {
+        out.push_str("\x1b[H\x1b[2J");
+    }
+    let name = get("name");
+    let _ = writeln!(
+        out,
+        "rae-top  {}  backend {}  iterations {}",
+        if name.is_empty() { "-" } else { name },
+        get("backend"),
+        get("iterations")
+    );
+    let _ = writeln!(
+        out,
+        "utilization {:5.1}% [{}{}]",
+        utilization * 100.0,
+        "#".repeat(filled),
+        ".".repeat(BAR_WIDTH - filled)
+    );
+    let _ = writeln!(
+        out,
+        "callbacks   p50 {}us  p99 {}us  max {}us   poll p99 {}us   timer lag p99 {}us",
+        get("callback_us_p50"),
+        get("callback_us_p99"),
+        get("callback_us_max"),
+        get("poll_us_p99"),
+        get("timer_lag_us_p99")
+    );
+    let _ = writeln!(
+        out,
+        "fds {}  timers {}  conns {}  in {}B/s  out {}B/s  panics {}  backoffs {}  memory {}B",
+        get("registered_fds"),
+        get("pending_timers"),
+        get("conns_open"),
+        get("conn_input_bps"),
+        get("conn_output_bps"),
+        get("callback_panics"),
+        get("backoffs"),
+        get("memory_total")
+    );
+
+    let _ = writeln!(out, "\nTOP FDS");
+    let _ = writeln!(
+        out,
+        "{:>6} {:>4} {:>8} {:>12} {:>6} {:>12} {:>12}",
+        "FD", "MASK", "DELTA", "EVENTS", "TAG", "IN", "OUT"
+    );
+    for row in t.fds.iter().take(TOP_FDS) {
+        let (bytes_in, bytes_out) = row
+            .traffic
+            .clone()
+            .unwrap_or_else(|| ("-".to_string(), "-".to_string()));
+        let _ = writeln!(
+            out,
+            "{:>6} {:>4} {:>8} {:>12} {:>6} {:>12} {:>12}",
+            row.fd, row.mask, row.delta, row.events, row.tag, bytes_in, bytes_out
+        );
+    }
+
+    let _ = writeln!(out, "\nSLOW CALLBACKS");
+    if t.slowlog.is_empty() {
+        let _ = writeln!(out, "  none");
+    }
+    for entry in &t.slowlog {
+        let _ = writeln!(out, "  {}", entry);
+    }
+
+    let mut stdout = std::io::stdout();
+    let _ = stdout.write_all(out.as_bytes());
+    let _ = stdout.flush();
+    t.frames += 1;
+}
+
+fn usage() -> ! {
+    eprintln!("usage: rae-top [--once] <socket> [interval_ms]");
+    std::process::exit(2);
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let once = args.first().is_some_and(|arg| arg == "--once");
+    if once {
+        args.remove(0);
+    }
+    let Some(path) = args.first() else {
+        usage();
+    };
+    let interval_ms = match args.get(1).map(|arg| arg.parse::<i64>()) {
+        None => DEFAULT_INTERVAL_MS,
+        Some(Ok(ms)) if ms > 0 => ms,
+        Some(_) => usage(),
+    };
+
+    let stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(err) => {
+            eprintln!("rae-top: cannot connect to {path}: {err}");
+            std::process::exit(1);
+        }
+    };
+    stream
+        .set_nonblocking(true)
+        .expect("Failed to set O_NONBLOCK");
+    let fd = stream.into_raw_fd();
+
+    let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+    let mut t = Top {
+        fd,
+        once,
+        interval_ms,
+        pending: VecDeque::new(),
+        lines: Vec::new(),
+        info: Vec::new(),
+        fds: Vec::new(),
+        slowlog: Vec::new(),
+        last_events: HashMap::new(),
+        frames: 0,
+    };
+    let t_ptr = &mut t as *mut Top as *mut c_void;
+    if ae_conn_create(&mut event_loop, fd, read_replies, Some(on_close), t_ptr) == AE_ERR {
+        eprintln!("rae-top: cannot watch {path}");
+        std::process::exit(1);
+    }
+    refresh(&mut event_loop, 0, t_ptr);
+    if !once {
+        ae_create_time_event(&mut event_loop, interval_ms, refresh, t_ptr, None);
+    }
+    ae_stop_on_signal(&mut event_loop, libc::SIGINT);
+    ae_stop_on_signal(&mut event_loop, libc::SIGTERM);
+    ae_main(&mut event_loop);
+
+    if t.fd != -1 {
+        let fd = std::mem::replace(&mut t.fd, -1);
+        ae_conn_close(&mut event_loop, fd);
+    }
+    ae_delete_event_loop(event_loop);
+    if t.frames == 0 {
+        std::process::exit(1);
+    }
+}
@@ -12,6 +12,16 @@ pub const AE_WRITABLE: i32 = 2;
  * to disk before sending replies, and want to do that in a group fashion. */
 pub const AE_BARRIER: i32 = 4;
 
+/* The socket error queue has something to read (ICMP errors, zerocopy
+ * completions, TX timestamps), to be drained with recvmsg(MSG_ERRQUEUE).
+ * It has its own handler, called with this mask, apart from the read and
+ * write ones. Linux epoll only: other backends refuse it with ENOTSUP. */
+pub const AE_ERRQUEUE: i32 = 8;
+
+/* Every handler of an fd, to remove them all at once with
+ * ae_delete_file_event(). */
+pub const AE_ALL_MASK: i32 = AE_READABLE | AE_WRITABLE | AE_ERRQUEUE;
+
 pub const AE_FILE_EVENTS: i32 = 1 << 0;
 pub const AE_TIME_EVENTS: i32 = 1 << 1;
 pub const AE_ALL_EVENTS: i32 = AE_FILE_EVENTS | AE_TIME_EVENTS;
@@ -22,4 +32,30 @@ pub const AE_CALL_AFTER_SLEEP: i32 = 1 << 4;
 pub const AE_NOMORE: i32 = -1;
 pub const AE_DELETED_EVENT_ID: i64 = -1;
 
+/* Slots of the per-fd tables allocated up front, grown as higher fds get
+ * registered. */
+#[cfg(not(feature = "small"))]
 pub const INITIAL_EVENT: usize = 1024;
+#[cfg(feature = "small")]
+pub const INITIAL_EVENT: usize = 16;
+
+/* Most events one poll returns, which bounds the fired buffers of the loop
+ * and of the backend. Events left over are reported again by the next poll
+ * (all backends are level triggered). Unbounded unless built with the
+ * `small` feature, where the buffers otherwise grow with the highest fd. */
+#[cfg(not(feature = "small"))]
+pub const AE_POLL_BATCH: usize = usize::MAX;
+#[cfg(feature = "small")]
+pub const AE_POLL_BATCH: usize = 64;
+
+/* Slots of a fired buffer for a set of `n` fds. */
+#[cfg(not(feature = "small"))]
+#[inline]
+pub(crate) fn poll_batch(n: usize) -> usize {
+    n
+}
+#[cfg(feature = "small")]
+#[inline]
+pub(crate) fn poll_batch(n: usize) -> usize {
+    n.min(AE_POLL_BATCH)
+}
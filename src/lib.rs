@@ -30,11 +30,18 @@
 pub mod ae;
 pub mod constants;
 pub mod fd_set;
+pub mod monotonic;
 pub mod traits;
 
 #[cfg(any(unix, target_os = "linux", target_os = "macos", target_os = "freebsd"))]
 pub mod ae_select;
 
+#[cfg(any(unix, target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+pub mod anet;
+
+#[cfg(any(unix, target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+pub mod test_util;
+
 #[cfg(any(
     target_os = "macos",
     target_os = "freebsd",
@@ -43,23 +50,211 @@ pub mod ae_select;
 ))]
 pub mod ae_kqueue;
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod ae_epoll;
+
 pub use constants::{
-    AE_ALL_EVENTS, AE_BARRIER, AE_CALL_AFTER_SLEEP, AE_CALL_BEFORE_SLEEP, AE_DONT_WAIT, AE_ERR,
-    AE_FILE_EVENTS, AE_NOMORE, AE_OK, AE_TIME_EVENTS,
+    AE_ALL_EVENTS, AE_ALL_MASK, AE_BARRIER, AE_CALL_AFTER_SLEEP, AE_CALL_BEFORE_SLEEP,
+    AE_DONT_WAIT, AE_ERR, AE_ERRQUEUE, AE_FILE_EVENTS, AE_NOMORE, AE_OK, AE_POLL_BATCH,
+    AE_TIME_EVENTS,
 };
 
+#[cfg(target_os = "linux")]
+pub use traits::NetlinkProc;
+#[cfg(feature = "resp")]
+pub use traits::RespCommandProc;
 pub use traits::{
-    AfterSleepProc, BeforeSleepProc, EventBackend, EventFinalizerProc, FileProc, TimeProc,
+    AcceptProc, AeModule, AfterSleepProc, BeforeSleepProc, ChildLoopSetupProc, ConnCloseProc,
+    ConnEofProc, ConnHandoverProc, ConnMigratedProc, ConnReadProc, ConnectProc, ContinuationProc,
+    CrashReportProc, CustomEventProc, DiagnosticProc, DrainProc, EintrProc, EventBackend,
+    EventFinalizerProc, ExternalSource, FileProc, FileReadProc, FiredOverflowProc, FrameProc,
+    JobProc, LifecycleProc, LoopDriver, LoopInitProc, OneshotProc, OwnedTimeProc, PeerFilterProc,
+    PeriodicTimeProc, ProxyCloseProc, RelocateProc, ShutdownProc, SoonProc, StatsFlushProc,
+    StreamProc, TimeBatchProc, TimeProc, UdpBatchProc, WriteStallProc,
 };
 
+#[allow(deprecated)]
+pub use ae::ae_get_api_name;
 pub use ae::{
-    AeEventLoop, AeFileEvent, AeTimeEvent, ae_create_event_loop, ae_create_file_event,
-    ae_create_time_event, ae_delete_event_loop, ae_delete_file_event, ae_delete_time_event,
-    ae_get_api_name, ae_get_file_client_data, ae_get_file_events, ae_get_set_size, ae_main,
-    ae_process_events, ae_resize_set_size, ae_set_after_sleep_proc, ae_set_before_sleep_proc,
-    ae_set_dont_wait, ae_stop, ae_wait,
+    AE_FIRED_OVERFLOW_MAX_POLLS, AeDispatchOrder, AeEintrPolicy, AeEventLoop, AeFdOrder,
+    AeFileEvent, AeFileEventOptions, AeFiredOverflowPolicy, AeOverrunPolicy, AeReadCoalescing,
+    AeStdioPolicy, AeTimeEvent, ae_advance_clock, ae_create_event_loop,
+    ae_create_event_loop_with_backend, ae_create_file_event, ae_create_file_event_ex,
+    ae_create_periodic_event, ae_create_periodic_event_duration, ae_create_time_event,
+    ae_create_time_event_duration, ae_create_time_event_owned, ae_delete_event_loop,
+    ae_delete_file_event, ae_delete_time_event, ae_dont_wait_next, ae_fire_event, ae_fired_events,
+    ae_get_dont_wait, ae_get_file_client_data, ae_get_file_dispatches, ae_get_file_events,
+    ae_get_file_generation, ae_get_file_tag, ae_get_file_write_client_data, ae_get_loop_name,
+    ae_get_set_size, ae_get_stdio_policy, ae_is_paused, ae_loop_now, ae_main, ae_main_with,
+    ae_pause, ae_pending_time_events, ae_process_events, ae_process_events_nowait,
+    ae_registered_file_events, ae_reinit_after_fork, ae_resize_set_size,
+    ae_resize_set_size_compact, ae_resume, ae_run_with_driver, ae_set_after_sleep_proc,
+    ae_set_before_sleep_proc, ae_set_dont_wait, ae_set_eintr_policy, ae_set_fired_overflow_policy,
+    ae_set_stdio_policy, ae_set_time_event_budget, ae_set_time_event_budget_duration,
+    ae_set_time_event_jitter, ae_stop, ae_wait, ae_wait_duration,
 };
 
+pub use ae::admin::{
+    AE_ADMIN_MAX_LINE, AE_ADMIN_SLOWLOG_DEFAULT, ae_admin_close, ae_admin_listen, ae_admin_path,
+};
+pub use ae::backoff::{
+    AE_BACKOFF_INITIAL_MS, AE_BACKOFF_MAX_MS, AE_BACKOFF_MAX_RETRIES, ae_backoff,
+    ae_backoff_cancel, ae_backoff_failures, ae_backoff_reset, ae_backoff_suspended,
+    ae_is_transient_error,
+};
+pub use ae::bufpool::{
+    AE_BUF_POOL_CLASSES, AE_BUF_POOL_MAX_PER_CLASS, AeBufPoolStats, ae_buf_acquire,
+    ae_buf_pool_stats, ae_buf_pool_trim, ae_buf_release,
+};
+pub use ae::builder::AeEventLoopBuilder;
+pub use ae::child::{ae_attach_child_loop, ae_detach_child_loop};
+pub use ae::config::{ae_clear_config, ae_config, ae_set_config};
+pub use ae::conn::{
+    AE_CONN_MAX_INPUT, AeConnHalf, AeConnStats, ae_conn_client_data, ae_conn_close,
+    ae_conn_close_after_write, ae_conn_closing, ae_conn_create, ae_conn_half_state,
+    ae_conn_handover, ae_conn_pending_output, ae_conn_replace_handlers, ae_conn_set_eof_proc,
+    ae_conn_shutdown_write, ae_conn_stats, ae_conn_write, ae_conn_write_owned,
+};
+pub use ae::context::{
+    AE_DISPATCH_STACK_MAX, AeDispatchRecord, AeDispatchSource, ae_current_dispatch,
+    ae_dispatch_stack, ae_set_dispatch_label,
+};
+pub use ae::cron::{AeCronExpr, ae_create_cron_event};
+pub use ae::custom::{
+    AeCustomEventId, ae_create_custom_event, ae_delete_custom_event, ae_trigger_custom_event,
+};
+pub use ae::differential::{
+    AE_DIVERGENCE_LOG_LEN, AeDivergence, AeDivergenceKind, ae_backend_divergences,
+    ae_differential_secondary,
+};
+pub use ae::dispatch::{
+    AE_DEFERRED_LIMIT_DEFAULT, AeDispatchCtx, ae_call_soon, ae_dispatch_ctx, ae_get_deferred_limit,
+    ae_get_iteration_budget, ae_set_deferred_limit, ae_set_iteration_budget,
+    ae_set_iteration_budget_duration, ae_yield_and_continue,
+};
+pub use ae::doctor::{
+    AeFinding, AeFindingKind, AeFindingSeverity, ae_doctor, ae_set_diagnostic_proc,
+    ae_set_timer_starvation_alarm, ae_set_timer_starvation_alarm_duration,
+};
+pub use ae::drain::{ae_drain, ae_drain_duration, ae_draining};
+pub use ae::external::{
+    AeExternalSourceId, ae_add_external_source, ae_external_sources, ae_remove_external_source,
+};
+pub use ae::fileio::{AE_IO_THREADS_DEFAULT, ae_file_read, ae_file_reads_pending};
+pub use ae::flags::ProcessFlags;
+pub use ae::flight::{
+    AeFlightEntry, AeFlightOutcome, ae_flight_dump, ae_flight_entries, ae_set_flight_recorder,
+};
+pub use ae::fork::{AeForkedChild, ae_child_status_fd, ae_spawn_child_loop, ae_wait_child_loop};
+pub use ae::framing::{
+    AE_FRAME_HEADER_LEN, AeFraming, ae_framed_create, ae_framed_write, frame_decode, frame_encode,
+};
+#[cfg(feature = "glib")]
+pub use ae::glib::{AeGlibContext, ae_glib_host};
+pub use ae::group::{AeGroupId, ae_delete_group, ae_group_fds, ae_register_group};
+pub use ae::group_commit::{
+    AeGroupCommitStats, ae_group_commit_disable, ae_group_commit_enable, ae_group_commit_pending,
+    ae_group_commit_reply, ae_group_commit_stats,
+};
+pub use ae::handle::{AeHandle, AeTask, ae_get_handle};
+pub use ae::heartbeat::{
+    AeHeartbeat, ae_disable_heartbeat, ae_enable_heartbeat, ae_enable_heartbeat_duration,
+};
+pub use ae::idle::{
+    AE_IDLE_WHEEL_SLOTS, ae_conn_idle_timeout, ae_conn_set_idle_timeout,
+    ae_conn_set_idle_timeout_duration, ae_idle_wheel_len,
+};
+pub use ae::invariants::ae_check_invariants;
+pub use ae::job::{
+    AE_SLOWLOG_MAX_LEN, AeSlowlogEntry, ae_create_job, ae_slowlog_get, ae_slowlog_len,
+    ae_slowlog_reset,
+};
+pub use ae::keepalive::{
+    ae_keepalive_disable, ae_keepalive_enable, ae_keepalive_enable_duration, ae_keepalive_missed,
+    ae_keepalive_unwatch, ae_keepalive_watch,
+};
+pub use ae::lifecycle::{AeLifecycleEvent, ae_set_lifecycle_proc};
+pub use ae::limits::{AeSoftLimitResource, AeSoftLimits, ae_get_soft_limits, ae_set_soft_limits};
+pub use ae::memory::{AeCountingAlloc, AeMemoryUsage, ae_memory_usage};
+pub use ae::migrate::ae_conn_migrate;
+pub use ae::module::{ae_register_module, ae_registered_modules};
+pub use ae::net::{ae_accept, ae_set_peer_filter, ae_tcp_connect, ae_tcp_connect_duration};
+#[cfg(target_os = "linux")]
+pub use ae::netlink::{
+    AE_NETLINK_MAX_READS, AeNetlinkMessage, AeUevent, ae_netlink_register, ae_netlink_unregister,
+    ae_uevent_parse,
+};
+pub use ae::panic::{
+    AeCrashReport, AePanicPolicy, ae_get_panic_policy, ae_set_crash_reporter, ae_set_panic_policy,
+};
+pub use ae::probe::{AeKernelFeatures, ae_kernel_features};
+pub use ae::proxy::{
+    AE_PROXY_CHUNK, AeProxyStats, ae_proxy_close, ae_proxy_create, ae_proxy_stats,
+};
+pub use ae::proxy_header::{
+    AE_PROXY_HEADER_TIMEOUT_MS, AE_PROXY_HEADER_V1_MAX, AeProxyHeader, ae_proxy_header_accept,
+    ae_proxy_header_parse,
+};
+#[cfg(feature = "async-io")]
+pub use ae::reactor::{AeBackendFd, ae_backend_async, ae_run_async};
+pub use ae::rearm::{ae_rearm, ae_set_rearm_check};
+pub use ae::reconfigure::{AeLoopConfig, AeLoopConfigDelta, ae_loop_config, ae_reconfigure};
+pub use ae::reload::{
+    AE_INHERIT_ENV, AE_RELOAD_MAX_FDS, AeInheritedFd, ae_decode_registrations,
+    ae_encode_registrations, ae_export_registrations, ae_inherited_fds, ae_prepare_exec,
+    ae_recv_registrations, ae_restore_registrations, ae_send_registrations,
+};
+#[cfg(feature = "resp")]
+pub use ae::resp::{
+    RespError, RespProtocol, RespValue, ae_resp_reply, ae_resp_serve, ae_resp_set_protocol,
+    resp_encode, resp_parse,
+};
+pub use ae::runtime::{AeRuntime, ThreadPerCore};
+pub use ae::scope::{AeScope, AeScopeCtx, ae_scope, ae_scope_close, ae_scope_extend};
+pub use ae::selftest::{AeBackendCheck, SelfTestReport, ae_self_test};
+pub use ae::shared::{
+    ae_create_shared_file_event, ae_delete_shared_file_event, ae_shared_fd_local,
+    ae_shared_fd_origin,
+};
+pub use ae::shutdown::{ShutdownToken, ae_on_shutdown, ae_shutdown_token};
+pub use ae::signal::{
+    AeSigpipePolicy, ae_ignore_sigpipe, ae_request_stop_from_signal, ae_signal_stop_fd,
+    ae_stop_on_signal,
+};
+pub use ae::snapshot::{AeSnapshotFd, AeSnapshotTimer, LoopSnapshot, SnapshotDiff, ae_snapshot};
+pub use ae::stats::{
+    AE_STATS_RATE_WINDOW_US, AE_STATS_UTILIZATION_WINDOW_US, AeBackendStats, AeHistogram, AeRusage,
+    AeStats, AeStatsDelta, ae_get_stats, ae_reset_stats, ae_set_stats_flush_proc,
+    ae_set_stats_flush_proc_duration,
+};
+pub use ae::stream::{AeStreamMode, ae_register_stdin, ae_register_stream, ae_unregister_stream};
+pub use ae::sync::{AeNotify, AeOneshotReceiver, AeOneshotSender, AeSemaphore, ae_oneshot};
+pub use ae::timer_batch::{
+    AeTimerBatchId, ae_create_batched_time_event, ae_create_timer_batch, ae_delete_timer_batch,
+    ae_timer_batch_len,
+};
+pub use ae::timer_group::{
+    AeExportedTimer, ae_export_timers, ae_group_timers, ae_import_timers, ae_set_time_event_group,
+};
+pub use ae::timer_loop::{AeTimerLoop, AeTimerLoopHandle};
+pub use ae::timer_ref::{TimeEventRef, ae_delete_time_event_ref, ae_time_event_ref};
+pub use ae::typed::{
+    FileEventKey, TimeEventId, ae_add_file_event, ae_add_periodic_event, ae_add_time_event,
+    ae_add_time_event_owned, ae_file_event_key_valid, ae_modify_file_event, ae_remove_file_event,
+    ae_remove_time_event, ae_set_timer_jitter,
+};
+pub use ae::udp::{
+    AE_UDP_BATCH_MAX, AE_UDP_MAX_BATCHES, AeDatagram, ae_udp_register, ae_udp_unregister,
+};
+pub use ae::wallclock::{
+    AE_WALLCLOCK_RECHECK_MS, ae_create_wallclock_event, ae_delete_wallclock_event,
+};
+pub use ae::watchdog::{
+    ae_note_write_progress, ae_set_write_stall_watchdog, ae_set_write_stall_watchdog_duration,
+    ae_write_stall_us,
+};
+pub use monotonic::{AeClockSource, ae_cycle_counter_hz};
+
 #[cfg(any(unix, target_os = "linux", target_os = "macos", target_os = "freebsd"))]
 pub use constants::{AE_NONE, AE_READABLE, AE_WRITABLE};
 
@@ -72,5 +267,15 @@ pub use ae_select::{
     ae_api_resize, aeApiState,
 };
 
+#[cfg(target_os = "linux")]
+pub use anet::{ANET_UDP_MAX_SEGMENTS, anet_netlink_send, anet_netlink_socket, anet_packet_socket};
+#[cfg(any(unix, target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+pub use anet::{
+    AnetBpfInsn, AnetListenOptions, BufChain, anet_attach_filter, anet_detach_filter,
+    anet_local_addr, anet_raw_socket, anet_readv, anet_set_accept_filter, anet_set_defer_accept,
+    anet_set_synack_retries, anet_set_tcp_fastopen, anet_set_udp_gro, anet_syn_cookies,
+    anet_tcp_listen, anet_tcp_server, anet_udp_send_batch, anet_udp_send_segments, anet_writev,
+};
+
 #[cfg(any(unix, target_os = "linux", target_os = "macos", target_os = "freebsd"))]
 pub type ApiState = aeApiState;
@@ -0,0 +1,240 @@
+/* Monotonic clock used for timers and loop statistics.
+ *
+ * Rust counterpart of Redis monotonic.c. The loop reads the clock several
+ * times per iteration, so the source is selectable per loop (see
+ * AeEventLoopBuilder::clock_source()): the coarse clocks are served from
+ * the vDSO without reading the hardware counter and are much cheaper on
+ * some kernels, at the cost of a resolution of a few milliseconds.
+ *
+ * The cycle counter is cheaper still, without giving up resolution:
+ * it is read in user space (rdtsc on x86_64, CNTVCT_EL0 on aarch64) and
+ * scaled with a frequency calibrated once per process, like the
+ * processor clock of Redis monotonic.c. Paired with a coarse clock for
+ * timers (AeEventLoopBuilder::latency_clock()) it times callbacks for the
+ * latency histograms without a clock_gettime() per callback.
+ *
+ * Values are microseconds from an arbitrary origin, only differences
+ * between readings of the same source are meaningful.
+ */
+
+use std::sync::OnceLock;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AeClockSource {
+    /* std::time::Instant, i.e. CLOCK_MONOTONIC (mach_absolute_time on
+     * macOS). */
+    #[default]
+    Instant,
+    /* CLOCK_MONOTONIC_RAW: not slewed by NTP. */
+    MonotonicRaw,
+    /* CLOCK_MONOTONIC_COARSE (CLOCK_MONOTONIC_FAST on FreeBSD,
+     * CLOCK_MONOTONIC_RAW_APPROX on macOS): cheapest, tick resolution. */
+    MonotonicCoarse,
+    /* CPU cycle counter, see the top of the file. Needs an invariant TSC
+     * on x86_64; not available on other architectures. */
+    Cycles,
+    /* Virtual time starting at 0 and only moving when ae_advance_clock()
+     * is called, for tests driving timers deterministically. Each loop
+     * has its own. */
+    Manual,
+}
+
+impl AeClockSource {
+    /* Human readable name, like Redis monotonicInfoString(). */
+    pub fn name(&self) -> &'static str {
+        match self {
+            AeClockSource::Instant => "Instant",
+            AeClockSource::MonotonicRaw => "CLOCK_MONOTONIC_RAW",
+            AeClockSource::MonotonicCoarse => "CLOCK_MONOTONIC_COARSE",
+            AeClockSource::Cycles => "cycles",
+            AeClockSource::Manual => "manual",
+        }
+    }
+
+    fn clock_id(&self) -> libc::clockid_t {
+        match self {
+            AeClockSource::Instant | AeClockSource::Manual | AeClockSource::Cycles => {
+                libc::CLOCK_MONOTONIC
+            }
+            AeClockSource::MonotonicRaw => raw_clock_id(),
+            AeClockSource::MonotonicCoarse => coarse_clock_id(),
+        }
+    }
+
+    /* True if the clock can be read on this system. */
+    pub fn is_supported(&self) -> bool {
+        if matches!(self, AeClockSource::Instant | AeClockSource::Manual) {
+            return true;
+        }
+        if *self == AeClockSource::Cycles {
+            return cycles::supported();
+        }
+        let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+        unsafe { libc::clock_gettime(self.clock_id(), &mut ts) == 0 }
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+))]
+fn raw_clock_id() -> libc::clockid_t {
+    libc::CLOCK_MONOTONIC_RAW
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+fn raw_clock_id() -> libc::clockid_t {
+    libc::CLOCK_MONOTONIC
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn coarse_clock_id() -> libc::clockid_t {
+    libc::CLOCK_MONOTONIC_COARSE
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn coarse_clock_id() -> libc::clockid_t {
+    libc::CLOCK_MONOTONIC_RAW_APPROX
+}
+
+#[cfg(target_os = "freebsd")]
+fn coarse_clock_id() -> libc::clockid_t {
+    libc::CLOCK_MONOTONIC_FAST
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+)))]
+fn coarse_clock_id() -> libc::clockid_t {
+    libc::CLOCK_MONOTONIC
+}
+
+/* Current time of the given source in microseconds. Always 0 for the
+ * manual clock, whose time is kept by the loop. */
+#[inline]
+pub fn get_monotonic_us(source: AeClockSource) -> u64 {
+    match source {
+        AeClockSource::Manual => 0,
+        AeClockSource::Cycles => cycles::now_us(),
+        AeClockSource::Instant => {
+            static START_TIME: OnceLock<Instant> = OnceLock::new();
+            let start = START_TIME.get_or_init(Instant::now);
+            start.elapsed().as_micros() as u64
+        }
+        _ => {
+            let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+            unsafe { libc::clock_gettime(source.clock_id(), &mut ts) };
+            ts.tv_sec as u64 * 1_000_000 + ts.tv_nsec as u64 / 1_000
+        }
+    }
+}
+
+/* Frequency of the cycle counter in Hz, measured on first use. None where
+ * AeClockSource::Cycles is not supported. */
+pub fn ae_cycle_counter_hz() -> Option<u64> {
+    cycles::supported().then(|| (cycles::calibration().per_us * 1e6) as u64)
+}
+
+mod cycles {
+    use std::sync::OnceLock;
+
+    pub(super) struct Calibration {
+        /* A reading of the counter and of CLOCK_MONOTONIC taken together. */
+        origin: u64,
+        origin_us: u64,
+        pub(super) per_us: f64,
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub(super) fn supported() -> bool {
+        /* Invariant TSC: constant rate across P-states and C-states. */
+        static INVARIANT: OnceLock<bool> = OnceLock::new();
+        *INVARIANT.get_or_init(|| {
+            use std::arch::x86_64::__cpuid;
+            let max_extended = __cpuid(0x8000_0000).eax;
+            max_extended >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+        })
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub(super) fn supported() -> bool {
+        true
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub(super) fn supported() -> bool {
+        false
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    fn read() -> u64 {
+        unsafe { std::arch::x86_64::_rdtsc() }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[inline]
+    fn read() -> u64 {
+        let value: u64;
+        unsafe { std::arch::asm!("mrs {}, cntvct_el0", out(reg) value) };
+        value
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    #[inline]
+    fn read() -> u64 {
+        0
+    }
+
+    pub(super) fn calibration() -> &'static Calibration {
+        static CALIBRATION: OnceLock<Calibration> = OnceLock::new();
+        CALIBRATION.get_or_init(|| {
+            let origin_us = super::get_monotonic_us(super::AeClockSource::MonotonicRaw);
+            let origin = read();
+            Calibration {
+                origin,
+                origin_us,
+                per_us: cycles_per_us(origin).max(f64::MIN_POSITIVE),
+            }
+        })
+    }
+
+    /* The architected timer frequency, CNTFRQ_EL0. */
+    #[cfg(target_arch = "aarch64")]
+    fn cycles_per_us(_origin: u64) -> f64 {
+        let hz: u64;
+        unsafe { std::arch::asm!("mrs {}, cntfrq_el0", out(reg) hz) };
+        hz as f64 / 1e6
+    }
+
+    /* Cycles counted from `origin` over 10ms of CLOCK_MONOTONIC. */
+    #[cfg(not(target_arch = "aarch64"))]
+    fn cycles_per_us(origin: u64) -> f64 {
+        let start = std::time::Instant::now();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let elapsed = start.elapsed();
+        read().wrapping_sub(origin) as f64 / (elapsed.as_nanos() as f64 / 1e3)
+    }
+
+    #[inline]
+    pub(super) fn now_us() -> u64 {
+        if !supported() {
+            return super::get_monotonic_us(super::AeClockSource::MonotonicRaw);
+        }
+        let calibration = calibration();
+        let cycles = read().wrapping_sub(calibration.origin);
+        calibration.origin_us + (cycles as f64 / calibration.per_us) as u64
+    }
+}
@@ -0,0 +1,461 @@
+/* Helpers for tests driving a loop end to end.
+ *
+ * Registering an fd only checks the bookkeeping. To see a FileProc
+ * actually called the fd must become ready and the loop must run, without
+ * hanging the test when it does not: this module provides fd pairs that
+ * close themselves, connections over socket pairs, ways to make one end
+ * readable or not writable, and loop runners bounded by a deadline or by
+ * a number of non-blocking iterations. MockBackend goes one step further
+ * and replaces the kernel altogether. Used by the crate's own tests, and
+ * public so applications can test their handlers the same way.
+ */
+
+use crate::ae::builder::AeEventLoopBuilder;
+use crate::ae::conn::ae_conn_create;
+use crate::ae::{AeEventLoop, ae_create_time_event, ae_delete_time_event, ae_process_events};
+use crate::ae_select::FiredEvent;
+use crate::anet::errno;
+use crate::constants::{
+    AE_ALL_EVENTS, AE_CALL_AFTER_SLEEP, AE_CALL_BEFORE_SLEEP, AE_DONT_WAIT, AE_NOMORE, AE_NONE,
+    AE_OK, AE_READABLE, AE_WRITABLE,
+};
+use crate::monotonic::AeClockSource;
+use crate::traits::{ConnCloseProc, ConnReadProc, EventBackend};
+use std::cell::{Cell, RefCell};
+use std::ffi::c_void;
+use std::os::unix::io::IntoRawFd;
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/* Two connected fds, closed on drop. For a pipe `a` is the read end and
+ * `b` the write end; for a socket pair both ways work. Both ends are
+ * non-blocking and close-on-exec. */
+#[derive(Debug)]
+pub struct FdPair {
+    pub a: i32,
+    pub b: i32,
+}
+
+impl Drop for FdPair {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.a);
+            libc::close(self.b);
+        }
+    }
+}
+
+pub fn pipe() -> FdPair {
+    let mut fds = [-1; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0, "pipe() failed");
+    let pair = FdPair {
+        a: fds[0],
+        b: fds[1],
+    };
+    prepare_fd(pair.a);
+    prepare_fd(pair.b);
+    pair
+}
+
+/* AF_UNIX stream socket pair. */
+pub fn socketpair() -> FdPair {
+    let mut fds = [-1; 2];
+    let ret = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) };
+    assert_eq!(ret, 0, "socketpair() failed");
+    let pair = FdPair {
+        a: fds[0],
+        b: fds[1],
+    };
+    prepare_fd(pair.a);
+    prepare_fd(pair.b);
+    pair
+}
+
+fn prepare_fd(fd: i32) {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+}
+
+/* Write one byte to `fd`, making its peer readable. */
+pub fn trigger_readable(fd: i32) {
+    let byte = b'x';
+    let n = unsafe { libc::write(fd, &byte as *const u8 as *const c_void, 1) };
+    assert_eq!(n, 1, "write() failed: errno {}", errno());
+}
+
+/* Read and discard everything pending on `fd`. Returns the byte count. */
+pub fn drain(fd: i32) -> usize {
+    let mut buf = [0u8; 4096];
+    let mut total = 0;
+    loop {
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+        if n <= 0 {
+            return total;
+        }
+        total += n as usize;
+    }
+}
+
+/* Write to the non-blocking `fd` until the kernel buffer is full, so that
+ * it stops being writable until the peer drains it. Returns the byte
+ * count. */
+pub fn fill(fd: i32) -> usize {
+    let buf = [0u8; 4096];
+    let mut total = 0;
+    loop {
+        let n = unsafe { libc::write(fd, buf.as_ptr() as *const c_void, buf.len()) };
+        if n <= 0 {
+            return total;
+        }
+        total += n as usize;
+    }
+}
+
+/* Close both ends of a pipe made with anet_pipe(). */
+pub fn close_pipe(rfd: i32, wfd: i32) {
+    unsafe {
+        libc::close(rfd);
+        libc::close(wfd);
+    }
+}
+
+/* Connection made with ae_conn_create() over one end of a socket pair.
+ * Returns its fd and the other end, left blocking. */
+pub fn conn_pair(
+    event_loop: &mut AeEventLoop,
+    proc: ConnReadProc,
+    close_proc: Option<ConnCloseProc>,
+    client_data: *mut c_void,
+) -> (i32, UnixStream) {
+    let (ours, theirs) = UnixStream::pair().expect("socketpair() failed");
+    ours.set_nonblocking(true).unwrap();
+    let fd = ours.into_raw_fd();
+    let result = ae_conn_create(event_loop, fd, proc, close_proc, client_data);
+    assert_eq!(result, AE_OK, "ae_conn_create() failed");
+    (fd, theirs)
+}
+
+/* ConnReadProc consuming and dropping whatever it is handed. */
+pub fn consume_all(
+    _event_loop: &mut AeEventLoop,
+    _fd: i32,
+    input: &[u8],
+    _client_data: *mut c_void,
+) -> usize {
+    input.len()
+}
+
+fn record_close(_event_loop: &mut AeEventLoop, _fd: i32, err: i32, client_data: *mut c_void) {
+    unsafe { *(client_data as *mut Option<i32>) = Some(err) };
+}
+
+/* Connection dropping all its input, over one end of a socket pair. Once
+ * it is closed `closed` holds the err its ConnCloseProc got, so it must
+ * outlive the connection. */
+pub fn sink_conn(event_loop: &mut AeEventLoop, closed: &mut Option<i32>) -> (i32, UnixStream) {
+    conn_pair(
+        event_loop,
+        consume_all,
+        Some(record_close),
+        closed as *mut Option<i32> as *mut c_void,
+    )
+}
+
+/* Loop on the default backend with the manual clock: fds are real, but
+ * timers only fire once ae_advance_clock() moves time. */
+pub fn manual_loop(setsize: i32) -> Box<AeEventLoop> {
+    AeEventLoopBuilder::new(setsize)
+        .clock_source(AeClockSource::Manual)
+        .build()
+        .expect("Failed to create event loop")
+}
+
+/* Run a single iteration processing all events, without blocking.
+ * Returns the number of events processed. */
+pub fn run_once(event_loop: &mut AeEventLoop) -> i32 {
+    run_iterations(event_loop, AE_ALL_EVENTS, 1)
+}
+
+/* Run `count` iterations processing `flags` (AE_DONT_WAIT is added).
+ * Returns the number of events processed over all of them. */
+pub fn run_iterations(event_loop: &mut AeEventLoop, flags: i32, count: usize) -> i32 {
+    let mut processed = 0;
+    for _ in 0..count {
+        processed += ae_process_events(event_loop, flags | AE_DONT_WAIT);
+    }
+    processed
+}
+
+/* Run a few iterations without blocking, enough for data written to a
+ * socket pair to be read, handled and answered on a local connection. */
+pub fn pump(event_loop: &mut AeEventLoop) {
+    run_iterations(event_loop, AE_ALL_EVENTS, 4);
+}
+
+fn deadline_proc(_event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    unsafe { (*(client_data as *const Cell<bool>)).set(true) };
+    AE_NOMORE
+}
+
+/* Timer firing once `timeout` expires, so that the poll never blocks past
+ * it. A deleted timer is never called, so `expired` only has to outlive
+ * the timer while it is armed. */
+fn arm_deadline(event_loop: &mut AeEventLoop, timeout: Duration, expired: &Cell<bool>) -> i64 {
+    let ms = timeout.as_micros().div_ceil(1000).min(i64::MAX as u128) as i64;
+    ae_create_time_event(
+        event_loop,
+        ms,
+        deadline_proc,
+        expired as *const Cell<bool> as *mut c_void,
+        None,
+    )
+}
+
+/* Run loop iterations until `done` returns true (checked before each
+ * iteration) or `timeout` expires, whichever comes first. This never
+ * blocks past the deadline, even with nothing registered. Returns whether
+ * `done` was satisfied. */
+pub fn run_until<F>(event_loop: &mut AeEventLoop, timeout: Duration, mut done: F) -> bool
+where
+    F: FnMut(&mut AeEventLoop) -> bool,
+{
+    let deadline = Instant::now() + timeout;
+    let expired = Cell::new(false);
+    let id = arm_deadline(event_loop, timeout, &expired);
+
+    let satisfied = loop {
+        if done(event_loop) {
+            break true;
+        }
+        if expired.get() || Instant::now() >= deadline {
+            break false;
+        }
+        ae_process_events(
+            event_loop,
+            AE_ALL_EVENTS | AE_CALL_BEFORE_SLEEP | AE_CALL_AFTER_SLEEP,
+        );
+    };
+    if !expired.get() {
+        ae_delete_time_event(event_loop, id);
+    }
+    satisfied
+}
+
+/* Run a single iteration blocking for at most `timeout`. Returns the
+ * number of events processed, like ae_process_events(). */
+pub fn process_once(event_loop: &mut AeEventLoop, timeout: Duration) -> i32 {
+    let expired = Cell::new(false);
+    let id = arm_deadline(event_loop, timeout, &expired);
+    let processed = ae_process_events(
+        event_loop,
+        AE_ALL_EVENTS | AE_CALL_BEFORE_SLEEP | AE_CALL_AFTER_SLEEP,
+    );
+    if expired.get() {
+        /* The deadline timer is not one of the caller's events. */
+        processed - 1
+    } else {
+        ae_delete_time_event(event_loop, id);
+        processed
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    setsize: i32,
+    /* Interest registered by the loop, per fd. */
+    interest: Vec<i32>,
+    /* Readiness set by the test, per fd. */
+    ready: Vec<i32>,
+    polls: u64,
+    nowait_polls: u64,
+    last_timeout: Option<Option<Duration>>,
+    interrupt_next_poll: bool,
+    fail_next_add: bool,
+    /* Report fired fds from the highest down. */
+    descending: bool,
+}
+
+/* Backend without any fd behind it, driven by the test through its
+ * MockControl: readiness is whatever the test says (level triggered: an
+ * fd stays ready until cleared), and polls never block. fds are plain
+ * numbers, no file needs to exist. Install it with
+ * AeEventLoopBuilder::backend(). */
+pub struct MockBackend {
+    state: Rc<RefCell<MockState>>,
+}
+
+/* Test side of a MockBackend. */
+#[derive(Clone)]
+pub struct MockControl {
+    state: Rc<RefCell<MockState>>,
+}
+
+impl MockBackend {
+    pub fn new() -> (Box<MockBackend>, MockControl) {
+        let state = Rc::new(RefCell::new(MockState::default()));
+        (
+            Box::new(MockBackend {
+                state: state.clone(),
+            }),
+            MockControl { state },
+        )
+    }
+}
+
+/* Loop on a MockBackend with the manual clock: nothing in it reaches the
+ * kernel, timers only fire once ae_advance_clock() moves time, and it runs
+ * under Miri (see tests/ae_miri_tests.rs). */
+pub fn virtual_loop(setsize: i32) -> (Box<AeEventLoop>, MockControl) {
+    let (backend, control) = MockBackend::new();
+    let event_loop = AeEventLoopBuilder::new(setsize)
+        .backend(backend)
+        .clock_source(AeClockSource::Manual)
+        .build()
+        .expect("building a mock loop cannot fail");
+    (event_loop, control)
+}
+
+fn slot(v: &mut Vec<i32>, fd: i32) -> &mut i32 {
+    if v.len() <= fd as usize {
+        v.resize(fd as usize + 1, AE_NONE);
+    }
+    &mut v[fd as usize]
+}
+
+impl MockControl {
+    /* Make `fd` ready for `mask` (AE_READABLE | AE_WRITABLE), in
+     * addition to what it already was. */
+    pub fn set_ready(&self, fd: i32, mask: i32) {
+        *slot(&mut self.state.borrow_mut().ready, fd) |= mask & (AE_READABLE | AE_WRITABLE);
+    }
+
+    pub fn clear_ready(&self, fd: i32, mask: i32) {
+        *slot(&mut self.state.borrow_mut().ready, fd) &= !mask;
+    }
+
+    /* Events the loop asked the backend to watch on `fd`. */
+    pub fn interest(&self, fd: i32) -> i32 {
+        let state = self.state.borrow();
+        state.interest.get(fd as usize).copied().unwrap_or(AE_NONE)
+    }
+
+    /* Set size the loop last gave to resize(). */
+    pub fn setsize(&self) -> i32 {
+        self.state.borrow().setsize
+    }
+
+    pub fn polls(&self) -> u64 {
+        self.state.borrow().polls
+    }
+
+    /* Polls that went through poll_nowait(), counted in polls() too. */
+    pub fn nowait_polls(&self) -> u64 {
+        self.state.borrow().nowait_polls
+    }
+
+    /* Timeout of the last poll: None before the first one, Some(None) for
+     * a poll that would have blocked forever. */
+    pub fn last_timeout(&self) -> Option<Option<Duration>> {
+        self.state.borrow().last_timeout
+    }
+
+    /* Make the next poll fail with EINTR. */
+    pub fn interrupt_next_poll(&self) {
+        self.state.borrow_mut().interrupt_next_poll = true;
+    }
+
+    /* Report fired fds from the highest down instead of the lowest up,
+     * as a kernel handing them out in its own order may. */
+    pub fn report_descending(&self, descending: bool) {
+        self.state.borrow_mut().descending = descending;
+    }
+
+    /* Make the next add_event() fail, as a full kernel table would. */
+    pub fn fail_next_add(&self) {
+        self.state.borrow_mut().fail_next_add = true;
+    }
+}
+
+impl EventBackend for MockBackend {
+    fn create() -> Result<Box<Self>, i32> {
+        Ok(MockBackend::new().0)
+    }
+
+    fn free(self: Box<Self>) {}
+
+    fn resize(&mut self, setsize: i32) -> i32 {
+        let mut state = self.state.borrow_mut();
+        state.setsize = setsize;
+        let len = setsize.max(0) as usize;
+        state.interest.truncate(len);
+        0
+    }
+
+    fn add_event(&mut self, fd: i32, mask: i32) -> i32 {
+        let mut state = self.state.borrow_mut();
+        if std::mem::take(&mut state.fail_next_add) || fd < 0 || fd >= state.setsize {
+            return -1;
+        }
+        *slot(&mut state.interest, fd) |= mask & (AE_READABLE | AE_WRITABLE);
+        0
+    }
+
+    fn del_event(&mut self, fd: i32, mask: i32) {
+        let mut state = self.state.borrow_mut();
+        if fd >= 0 && (fd as usize) < state.interest.len() {
+            state.interest[fd as usize] &= !mask;
+        }
+    }
+
+    fn poll(
+        &mut self,
+        _events: &[crate::ae::AeFileEvent],
+        fired: &mut [FiredEvent],
+        maxfd: i32,
+        timeout: Option<Duration>,
+    ) -> Result<i32, i32> {
+        let mut state = self.state.borrow_mut();
+        state.polls += 1;
+        state.last_timeout = Some(timeout);
+        if std::mem::take(&mut state.interrupt_next_poll) {
+            return Err(libc::EINTR);
+        }
+        let mut numevents = 0;
+        for fd in 0..=maxfd.max(-1) {
+            if numevents >= fired.len() {
+                break;
+            }
+            let interest = state.interest.get(fd as usize).copied().unwrap_or(AE_NONE);
+            let ready = state.ready.get(fd as usize).copied().unwrap_or(AE_NONE);
+            if interest & ready != AE_NONE {
+                fired[numevents] = FiredEvent {
+                    fd,
+                    mask: interest & ready,
+                };
+                numevents += 1;
+            }
+        }
+        if state.descending {
+            fired[..numevents].reverse();
+        }
+        Ok(numevents as i32)
+    }
+
+    fn poll_nowait(
+        &mut self,
+        events: &[crate::ae::AeFileEvent],
+        fired: &mut [FiredEvent],
+        maxfd: i32,
+    ) -> Result<i32, i32> {
+        self.state.borrow_mut().nowait_polls += 1;
+        self.poll(events, fired, maxfd, Some(Duration::ZERO))
+    }
+
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+}
@@ -2,6 +2,7 @@
 
 use crate::ae_select::FiredEvent;
 use std::ffi::c_void;
+use std::ops::ControlFlow;
 use std::time::Duration;
 
 /* Type aliases for callback functions (matching C API) */
@@ -9,9 +10,200 @@ pub type FileProc =
     fn(event_loop: &mut crate::ae::AeEventLoop, fd: i32, client_data: *mut c_void, mask: i32);
 pub type TimeProc =
     fn(event_loop: &mut crate::ae::AeEventLoop, id: i64, client_data: *mut c_void) -> i32;
+/* Time proc of a timer created with ae_create_time_event_owned(), which
+ * gets the timer state by reference instead of a raw client_data. */
+pub type OwnedTimeProc<T> =
+    fn(event_loop: &mut crate::ae::AeEventLoop, id: i64, data: &mut T) -> i32;
+/* Called by ae_call_soon() at each iteration with the iteration deadline,
+ * returns true to be called again at the next one. */
+pub type SoonProc<T> = fn(
+    event_loop: &mut crate::ae::AeEventLoop,
+    ctx: &crate::ae::dispatch::AeDispatchCtx,
+    data: &mut T,
+) -> bool;
+/* Slice of a job created with ae_create_job(), returns true while work
+ * is left. ctx carries the deadline of the slice. */
+pub type JobProc<T> = fn(
+    event_loop: &mut crate::ae::AeEventLoop,
+    ctx: &crate::ae::dispatch::AeDispatchCtx,
+    data: &mut T,
+) -> bool;
+/* Called by ae_yield_and_continue() at the next iteration, with the state
+ * it was given. */
+pub type ContinuationProc<T> = fn(
+    event_loop: &mut crate::ae::AeEventLoop,
+    ctx: &crate::ae::dispatch::AeDispatchCtx,
+    state: Box<T>,
+);
+/* Receiver of an ae_oneshot(), called with the value sent and the state
+ * given to AeOneshotReceiver::recv(). */
+pub type OneshotProc<V, T> = fn(
+    event_loop: &mut crate::ae::AeEventLoop,
+    ctx: &crate::ae::dispatch::AeDispatchCtx,
+    value: V,
+    state: Box<T>,
+);
+/* Time proc of a timer created with ae_create_periodic_event(). overruns
+ * is the number of periods that elapsed without a call because the loop
+ * was busy, 0 when the timer fired on schedule. */
+pub type PeriodicTimeProc = fn(
+    event_loop: &mut crate::ae::AeEventLoop,
+    id: i64,
+    overruns: u64,
+    client_data: *mut c_void,
+) -> i32;
+/* Proc of a timer batch, see ae_create_timer_batch(). ids are the due
+ * members, the return value applies to each of them. */
+pub type TimeBatchProc =
+    fn(event_loop: &mut crate::ae::AeEventLoop, ids: &[i64], client_data: *mut c_void) -> i32;
 pub type EventFinalizerProc = fn(event_loop: &mut crate::ae::AeEventLoop, client_data: *mut c_void);
 pub type BeforeSleepProc = fn(event_loop: &mut crate::ae::AeEventLoop);
 pub type AfterSleepProc = fn(event_loop: &mut crate::ae::AeEventLoop);
+/* Called once when the loop shuts down, see ae_on_shutdown(). */
+pub type ShutdownProc = fn(event_loop: &mut crate::ae::AeEventLoop);
+/* Called when a signal interrupts the backend poll, with
+ * AeEintrPolicy::InvokeHook. Returns true to poll again. */
+pub type EintrProc = fn(event_loop: &mut crate::ae::AeEventLoop) -> bool;
+/* Called when a poll filled the fired buffer, with the number of events
+ * it reported, see AeFiredOverflowPolicy::InvokeHook. */
+pub type FiredOverflowProc = fn(event_loop: &mut crate::ae::AeEventLoop, nfired: i32);
+pub type LifecycleProc =
+    fn(event_loop: &mut crate::ae::AeEventLoop, event: &crate::ae::lifecycle::AeLifecycleEvent);
+/* data is None once the stream reached end of file (or failed), after
+ * which the fd is no longer watched. */
+pub type StreamProc = fn(
+    event_loop: &mut crate::ae::AeEventLoop,
+    fd: i32,
+    data: Option<&[u8]>,
+    client_data: *mut c_void,
+);
+/* Completion of ae_file_read(): the bytes read (empty at end of file) or
+ * the errno of the failed read. */
+pub type FileReadProc = fn(
+    event_loop: &mut crate::ae::AeEventLoop,
+    fd: i32,
+    offset: u64,
+    result: Result<&[u8], i32>,
+    client_data: *mut c_void,
+);
+/* err is 0 on success, otherwise the errno that made the connect fail
+ * (ETIMEDOUT when the connect timeout expired). */
+pub type ConnectProc =
+    fn(event_loop: &mut crate::ae::AeEventLoop, fd: i32, err: i32, client_data: *mut c_void);
+/* Called on the loop that accepted the connection `fd`, which it now owns.
+ * addr is None for non IP sockets. */
+pub type AcceptProc =
+    fn(event_loop: &mut crate::ae::AeEventLoop, fd: i32, addr: Option<std::net::SocketAddr>);
+/* Consulted by ae_accept() for every connection accepted on `listen_fd`,
+ * before the caller sees it: false closes the connection right away.
+ * addr is None for non IP sockets. */
+pub type PeerFilterProc = fn(
+    event_loop: &mut crate::ae::AeEventLoop,
+    listen_fd: i32,
+    addr: Option<std::net::SocketAddr>,
+) -> bool;
+/* Called with the input buffered on a connection (see ae_conn_create()).
+ * Returns how many bytes it consumed: the rest stays buffered and is
+ * handed again, followed by new data, after the next read. */
+pub type ConnReadProc = fn(
+    event_loop: &mut crate::ae::AeEventLoop,
+    fd: i32,
+    input: &[u8],
+    client_data: *mut c_void,
+) -> usize;
+/* Called once a connection is closed, its fd already closed. err is 0
+ * after end of file or ae_conn_close(), else the errno that broke it. */
+pub type ConnCloseProc =
+    fn(event_loop: &mut crate::ae::AeEventLoop, fd: i32, err: i32, client_data: *mut c_void);
+/* Called when the peer of a connection sent end of file, see
+ * ae_conn_set_eof_proc(). */
+pub type ConnEofProc =
+    fn(event_loop: &mut crate::ae::AeEventLoop, fd: i32, client_data: *mut c_void);
+/* Called once a connection was handed over (see ae_conn_handover()) with
+ * the input it had not consumed. The fd, still open and no longer
+ * registered, belongs to the proc. */
+pub type ConnHandoverProc =
+    fn(event_loop: &mut crate::ae::AeEventLoop, fd: i32, input: Vec<u8>, client_data: *mut c_void);
+/* Called on the loop a connection was migrated to (see
+ * ae_conn_migrate()) once it is a connection there, before its read proc
+ * gets the input it came with. `timers` pairs the id each timer of its
+ * group had on the source loop with its id here. */
+pub type ConnMigratedProc = fn(
+    event_loop: &mut crate::ae::AeEventLoop,
+    fd: i32,
+    timers: &[(i64, i64)],
+    client_data: *mut c_void,
+);
+/* Called once a proxy (see ae_proxy_create()) is closed, both fds already
+ * closed. err is 0 after end of file on both sides or ae_proxy_close(),
+ * else the errno that broke it. */
+pub type ProxyCloseProc =
+    fn(event_loop: &mut crate::ae::AeEventLoop, a: i32, b: i32, err: i32, client_data: *mut c_void);
+/* Called with the datagrams of one batch read from a UDP socket (see
+ * ae_udp_register()). They borrow the receive buffers, which are reused for
+ * the next batch. */
+pub type UdpBatchProc = fn(
+    event_loop: &mut crate::ae::AeEventLoop,
+    fd: i32,
+    datagrams: &[crate::ae::udp::AeDatagram<'_>],
+    client_data: *mut c_void,
+);
+/* Called with each message read from a netlink socket (see
+ * ae_netlink_register()), NLMSG_OVERRUN when messages were dropped. */
+#[cfg(target_os = "linux")]
+pub type NetlinkProc = fn(
+    event_loop: &mut crate::ae::AeEventLoop,
+    fd: i32,
+    message: &crate::ae::netlink::AeNetlinkMessage<'_>,
+    client_data: *mut c_void,
+);
+/* Called with each frame read from a framed connection (see
+ * ae_framed_create()), without its framing. */
+pub type FrameProc =
+    fn(event_loop: &mut crate::ae::AeEventLoop, fd: i32, frame: &[u8], client_data: *mut c_void);
+/* Called with each command read from a RESP connection (see
+ * ae_resp_serve()), its arguments borrowed from the input buffer. */
+#[cfg(feature = "resp")]
+pub type RespCommandProc =
+    fn(event_loop: &mut crate::ae::AeEventLoop, fd: i32, argv: &[&[u8]], client_data: *mut c_void);
+/* Called by ae_resize_set_size_compact() after the registration of
+ * `old_fd` was moved to `new_fd` and `old_fd` closed. */
+pub type RelocateProc =
+    fn(event_loop: &mut crate::ae::AeEventLoop, old_fd: i32, new_fd: i32, client_data: *mut c_void);
+/* Called when a callback panics, see ae_set_crash_reporter(). */
+pub type CrashReportProc =
+    fn(event_loop: &mut crate::ae::AeEventLoop, report: &crate::ae::panic::AeCrashReport);
+/* Called for a custom event with the number of times it was triggered
+ * since its last call, see ae_create_custom_event(). */
+pub type CustomEventProc = fn(
+    event_loop: &mut crate::ae::AeEventLoop,
+    id: crate::ae::custom::AeCustomEventId,
+    client_data: *mut c_void,
+    triggers: u64,
+);
+/* Receives the findings the loop raises on its own, see
+ * ae_set_diagnostic_proc(). */
+pub type DiagnosticProc =
+    fn(event_loop: &mut crate::ae::AeEventLoop, finding: &crate::ae::doctor::AeFinding);
+/* Called with an fd whose AE_WRITABLE interest made no progress for
+ * `stalled_us`, see ae_set_write_stall_watchdog(). */
+pub type WriteStallProc = fn(event_loop: &mut crate::ae::AeEventLoop, fd: i32, stalled_us: u64);
+/* Called by the loop with the counters moved since its previous call, see
+ * ae_set_stats_flush_proc(). */
+pub type StatsFlushProc = fn(
+    event_loop: &mut crate::ae::AeEventLoop,
+    delta: &crate::ae::stats::AeStatsDelta,
+    client_data: *mut c_void,
+);
+/* Called once a drain started by ae_drain() is over, with the number of
+ * buffered connections still open: 0 unless the timeout expired. */
+pub type DrainProc =
+    fn(event_loop: &mut crate::ae::AeEventLoop, open: usize, client_data: *mut c_void);
+/* Setup of a forked child loop, see ae_spawn_child_loop(). */
+pub type ChildLoopSetupProc = fn(event_loop: &mut crate::ae::AeEventLoop);
+/* Called on each runtime thread once its loop is built, before it starts
+ * serving. `index` is the thread number, from 0. */
+pub type LoopInitProc = fn(event_loop: &mut crate::ae::AeEventLoop, index: usize);
 
 /* Platform-specific event backend trait */
 pub trait EventBackend {
@@ -21,7 +213,16 @@ pub trait EventBackend {
     fn free(self: Box<Self>);
     fn resize(&mut self, setsize: i32) -> i32;
     fn add_event(&mut self, fd: i32, mask: i32) -> i32;
+    /* add_event() for an fd not registered yet, asking the kernel to
+     * wake only one of the pollers sharing it (see
+     * AeFileEventOptions::exclusive). Backends without such a mode
+     * register it normally. */
+    fn add_event_exclusive(&mut self, fd: i32, mask: i32) -> i32 {
+        self.add_event(fd, mask)
+    }
     fn del_event(&mut self, fd: i32, mask: i32);
+    /* Err(EINTR) when a signal interrupted the wait: the loop applies its
+     * AeEintrPolicy. */
     fn poll(
         &mut self,
         events: &[crate::ae::AeFileEvent],
@@ -29,5 +230,90 @@ pub trait EventBackend {
         maxfd: i32,
         timeout: Option<Duration>,
     ) -> Result<i32, i32>;
+    /* poll() with a zero timeout, used when the loop must not block
+     * (AE_DONT_WAIT, timers already due). Backends with a cheaper way to
+     * check for ready events than building a zero timeout override it. */
+    fn poll_nowait(
+        &mut self,
+        events: &[crate::ae::AeFileEvent],
+        fired: &mut [FiredEvent],
+        maxfd: i32,
+    ) -> Result<i32, i32> {
+        self.poll(events, fired, maxfd, Some(Duration::ZERO))
+    }
     fn name(&self) -> &'static str;
+    /* File descriptor that becomes readable when the backend has events
+     * pending, for backends built on one (kqueue, epoll). -1 otherwise. */
+    fn fd(&self) -> i32 {
+        -1
+    }
+    /* The backend reports AE_ERRQUEUE for the fds registered for it. */
+    fn supports_errqueue(&self) -> bool {
+        false
+    }
+    /* Recreate the kernel side of the backend in a forked child, dropping
+     * every registration. Backends without kernel state (select) have
+     * nothing to do. Returns 0 on success, -1 on error. */
+    fn reinit(&mut self) -> i32 {
+        0
+    }
+    /* Syscalls issued so far, for backends that count them. */
+    fn stats(&self) -> crate::ae::stats::AeBackendStats {
+        crate::ae::stats::AeBackendStats::default()
+    }
+    fn reset_stats(&mut self) {}
+}
+
+/* Per-iteration application logic driven by ae_run_with_driver().
+ *
+ * tick() runs on the loop thread before every iteration (before
+ * beforesleep), returning ControlFlow::Break(()) stops the loop. Closures
+ * taking the loop work as drivers too. */
+pub trait LoopDriver {
+    fn tick(&mut self, event_loop: &mut crate::ae::AeEventLoop) -> ControlFlow<()>;
+}
+
+impl<F> LoopDriver for F
+where
+    F: FnMut(&mut crate::ae::AeEventLoop) -> ControlFlow<()>,
+{
+    fn tick(&mut self, event_loop: &mut crate::ae::AeEventLoop) -> ControlFlow<()> {
+        self(event_loop)
+    }
+}
+
+/* A library doing its own I/O (c-ares, D-Bus, libvirt) driven by the
+ * loop, see ae_add_external_source(). */
+pub trait ExternalSource {
+    /* Called before every poll: push the fds to watch, each with its
+     * AE_READABLE / AE_WRITABLE mask. */
+    fn fds(&mut self, fds: &mut Vec<(i32, i32)>);
+    /* Called before every poll: how long the loop may sleep before
+     * dispatch() is due even with nothing ready, None for no limit. */
+    fn timeout(&mut self) -> Option<Duration> {
+        None
+    }
+    /* The fds in `ready` fired, each with the events it fired for, or
+     * `ready` is empty and the timeout passed. */
+    fn dispatch(&mut self, event_loop: &mut crate::ae::AeEventLoop, ready: &[(i32, i32)]);
+}
+
+/* Extension point for observability and policy layers.
+ *
+ * Modules are registered process-wide with ae_register_module() and
+ * attached to every loop created afterwards. All hooks default to no-ops so
+ * a module only implements the ones it cares about. */
+pub trait AeModule: Send + Sync {
+    fn name(&self) -> &str;
+    /* The loop has been created and the module attached to it. */
+    fn on_loop_create(&self, _event_loop: &mut crate::ae::AeEventLoop) {}
+    /* About to block in the backend poll (after beforesleep). */
+    fn before_poll(&self, _event_loop: &mut crate::ae::AeEventLoop) {}
+    /* One ae_process_events() iteration finished dispatching `processed`
+     * file and time events. */
+    fn after_dispatch(&self, _event_loop: &mut crate::ae::AeEventLoop, _processed: i32) {}
+    /* A file event was successfully registered. */
+    fn on_fd_register(&self, _event_loop: &mut crate::ae::AeEventLoop, _fd: i32, _mask: i32) {}
+    /* The loop is being deleted, registrations are still in place. */
+    fn on_shutdown(&self, _event_loop: &mut crate::ae::AeEventLoop) {}
 }
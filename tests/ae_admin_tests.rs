@@ -0,0 +1,181 @@
+/* Admin Tests
+ *
+ * Tests for ae_admin_listen() (ae/admin.rs): a Unix socket served by the
+ * loop, answering PING, INFO, FDS and friends one line at a time.
+ */
+
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AeEventLoop, ae_admin_close, ae_admin_listen, ae_admin_path,
+    ae_create_event_loop, ae_delete_event_loop, ae_process_events, ae_set_flight_recorder,
+};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/* A socket path of its own for each test. */
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rae-admin-{}-{}.sock", std::process::id(), name))
+}
+
+fn connect(event_loop: &mut AeEventLoop, path: &PathBuf) -> UnixStream {
+    let client = UnixStream::connect(path).expect("Failed to connect");
+    client.set_nonblocking(true).unwrap();
+    ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+    client
+}
+
+/* Send `line` and run the loop until the reply, up to its empty line. */
+fn ask(event_loop: &mut AeEventLoop, client: &mut UnixStream, line: &str) -> String {
+    client.write_all(format!("{}\n", line).as_bytes()).unwrap();
+    let mut reply = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while !reply.ends_with(b"\n\n") && Instant::now() < deadline {
+        ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        let mut buf = [0u8; 4096];
+        match client.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => reply.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => panic!("read failed: {}", e),
+        }
+    }
+    String::from_utf8(reply).unwrap()
+}
+
+mod commands {
+    use super::*;
+
+    #[test]
+    fn test_ping_and_unknown_command() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let path = socket_path("ping");
+        ae_admin_listen(&mut event_loop, &path).expect("Failed to listen");
+        let mut client = connect(&mut event_loop, &path);
+
+        assert_eq!(ask(&mut event_loop, &mut client, "PING"), "PONG\n\n");
+        assert_eq!(ask(&mut event_loop, &mut client, "ping"), "PONG\n\n");
+        assert_eq!(
+            ask(&mut event_loop, &mut client, "FLUSHALL"),
+            "ERR unknown command 'FLUSHALL'\n\n"
+        );
+        assert_eq!(
+            ask(&mut event_loop, &mut client, "SLOWLOG many"),
+            "ERR count is not a number\n\n"
+        );
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_info_and_fds() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let path = socket_path("info");
+        ae_admin_listen(&mut event_loop, &path).expect("Failed to listen");
+        let mut client = connect(&mut event_loop, &path);
+
+        let info = ask(&mut event_loop, &mut client, "INFO");
+        assert!(info.lines().any(|l| l.starts_with("iterations:")));
+        assert!(info.contains(&format!("backend:{}\n", event_loop.api_name())));
+        assert!(info.contains("conns_open:1\n"));
+
+        /* The listener, and the client with its traffic so far. */
+        let fds = ask(&mut event_loop, &mut client, "FDS");
+        let lines: Vec<&str> = fds.lines().filter(|l| !l.is_empty()).collect();
+        assert_eq!(lines.len(), 2, "{}", fds);
+        assert!(
+            lines
+                .iter()
+                .any(|l| l.contains(" r tag=") && l.contains(" in="))
+        );
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_flight() {
+        if !cfg!(feature = "instrumentation") {
+            return;
+        }
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let path = socket_path("flight");
+        ae_admin_listen(&mut event_loop, &path).expect("Failed to listen");
+        let mut client = connect(&mut event_loop, &path);
+        assert_eq!(ask(&mut event_loop, &mut client, "FLIGHT"), "\n");
+
+        ae_set_flight_recorder(&mut event_loop, 16);
+        ask(&mut event_loop, &mut client, "PING");
+        let flight = ask(&mut event_loop, &mut client, "FLIGHT");
+        let lines: Vec<&str> = flight.lines().filter(|l| !l.is_empty()).collect();
+        /* The PING, then the dispatch serving this very command. */
+        assert!(lines.len() >= 2, "{}", flight);
+        assert!(lines[0].contains(" file fd="), "{}", flight);
+        assert!(lines.last().unwrap().ends_with(" running"), "{}", flight);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_pipelined_commands_and_quit() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let path = socket_path("quit");
+        ae_admin_listen(&mut event_loop, &path).expect("Failed to listen");
+        let mut client = connect(&mut event_loop, &path);
+
+        /* Answered in order, then the connection is closed. */
+        client.write_all(b"PING\nPING\nQUIT\n").unwrap();
+        let mut reply = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while Instant::now() < deadline {
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+            let mut buf = [0u8; 64];
+            match client.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => reply.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => panic!("read failed: {}", e),
+            }
+        }
+        assert_eq!(reply, b"PONG\n\nPONG\n\n\n");
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod lifecycle {
+    use super::*;
+
+    #[test]
+    fn test_single_endpoint_per_loop() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let path = socket_path("single");
+        ae_admin_listen(&mut event_loop, &path).expect("Failed to listen");
+        assert_eq!(ae_admin_path(&event_loop), Some(path.as_path()));
+        assert_eq!(
+            ae_admin_listen(&mut event_loop, socket_path("other")),
+            Err(libc::EEXIST)
+        );
+
+        /* The path is taken by another loop. */
+        let mut other = ae_create_event_loop(1024).expect("Failed to create event loop");
+        assert_eq!(ae_admin_listen(&mut other, &path), Err(libc::EADDRINUSE));
+        ae_delete_event_loop(other);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_close_removes_socket() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let path = socket_path("close");
+        ae_admin_listen(&mut event_loop, &path).expect("Failed to listen");
+        let mut client = connect(&mut event_loop, &path);
+
+        ae_admin_close(&mut event_loop);
+        assert!(!path.exists());
+        assert_eq!(ae_admin_path(&event_loop), None);
+        let mut buf = [0u8; 16];
+        client.set_nonblocking(false).unwrap();
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+
+        /* And when the loop goes away. */
+        ae_admin_listen(&mut event_loop, &path).expect("Failed to listen again");
+        ae_delete_event_loop(event_loop);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,195 @@
+/* Backoff Tests
+ *
+ * Tests for ae_backoff() (ae/backoff.rs): an fd failing with a transient
+ * error leaves the poll for a delay doubling with each failure, comes
+ * back with its registration intact, and is given up on after too many
+ * failures in a row.
+ */
+
+use rae::anet::anet_pipe;
+use rae::test_util::close_pipe;
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_ERR, AE_READABLE, AeEventLoop, AeEventLoopBuilder,
+    AeFileEventOptions, ae_backoff, ae_backoff_cancel, ae_backoff_failures, ae_backoff_reset,
+    ae_backoff_suspended, ae_create_event_loop, ae_create_file_event_ex, ae_get_file_events,
+    ae_get_file_tag, ae_get_stats, ae_is_transient_error, ae_process_events,
+};
+use std::ffi::c_void;
+use std::time::{Duration, Instant};
+
+/* A readable fd whose every "accept" fails with ENFILE, and what
+ * ae_backoff() answered each time. */
+fn failing_accept(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let delays = unsafe { &mut *(client_data as *mut Vec<i64>) };
+    delays.push(ae_backoff(event_loop, fd, AE_READABLE, libc::ENFILE));
+}
+
+/* Loop with a small backoff policy watching a readable pipe. */
+fn readable_pipe(delays: &mut Vec<i64>) -> (Box<AeEventLoop>, i32, i32) {
+    let mut event_loop = AeEventLoopBuilder::new(1024)
+        .backoff(5, 20, 3)
+        .build()
+        .expect("Failed to create event loop");
+    let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+    unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+    let options = AeFileEventOptions {
+        tag: 7,
+        ..Default::default()
+    };
+    ae_create_file_event_ex(
+        &mut event_loop,
+        rfd,
+        AE_READABLE,
+        failing_accept,
+        delays as *mut Vec<i64> as *mut c_void,
+        options,
+    );
+    (event_loop, rfd, wfd)
+}
+
+/* Run the loop until `done`, for at most a second. */
+fn run_until(event_loop: &mut AeEventLoop, mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while !done() && Instant::now() < deadline {
+        ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+mod errors {
+    use super::*;
+
+    #[test]
+    fn test_transient_errors() {
+        for err in [
+            libc::EAGAIN,
+            libc::EINTR,
+            libc::ENOBUFS,
+            libc::ENOMEM,
+            libc::ENFILE,
+            libc::EMFILE,
+        ] {
+            assert!(ae_is_transient_error(err), "errno {}", err);
+        }
+        assert!(!ae_is_transient_error(libc::ECONNRESET));
+        assert!(!ae_is_transient_error(libc::EBADF));
+    }
+
+    #[test]
+    fn test_no_delay_needed() {
+        let mut delays = Vec::new();
+        let (mut event_loop, rfd, wfd) = readable_pipe(&mut delays);
+
+        assert_eq!(
+            ae_backoff(&mut event_loop, rfd, AE_READABLE, libc::EAGAIN),
+            0
+        );
+        assert_eq!(
+            ae_backoff(&mut event_loop, rfd, AE_READABLE, libc::EINTR),
+            0
+        );
+        assert_eq!(ae_get_file_events(&event_loop, rfd), AE_READABLE);
+        assert_eq!(ae_backoff_failures(&event_loop, rfd), None);
+
+        /* Not transient, or not registered. */
+        let err = ae_backoff(&mut event_loop, rfd, AE_READABLE, libc::ECONNRESET);
+        assert_eq!(err, AE_ERR as i64);
+        let err = ae_backoff(&mut event_loop, wfd, AE_READABLE, libc::ENOBUFS);
+        assert_eq!(err, AE_ERR as i64);
+        assert_eq!(ae_get_stats(&event_loop).backoffs, 0);
+        close_pipe(rfd, wfd);
+    }
+}
+
+mod delays {
+    use super::*;
+
+    #[test]
+    fn test_delays_double_until_given_up() {
+        let mut delays = Vec::new();
+        let (mut event_loop, rfd, wfd) = readable_pipe(&mut delays);
+
+        let start = Instant::now();
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(delays, [5]);
+        assert_eq!(ae_get_file_events(&event_loop, rfd), 0);
+        assert_eq!(ae_backoff_suspended(&event_loop, rfd), AE_READABLE);
+
+        /* Still readable, but out of the poll until the delay passed. */
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(delays.len(), 1);
+
+        let delays_ptr = &delays as *const Vec<i64>;
+        run_until(&mut event_loop, || unsafe { (*delays_ptr).len() == 4 });
+        assert_eq!(delays, [5, 10, 20, AE_ERR as i64]);
+        assert!(start.elapsed() >= Duration::from_millis(35));
+
+        /* Given up: the fd stays out of the poll. */
+        assert_eq!(ae_get_file_events(&event_loop, rfd), 0);
+        assert_eq!(ae_backoff_failures(&event_loop, rfd), None);
+        let stats = ae_get_stats(&event_loop);
+        assert_eq!(stats.backoffs, 3);
+        assert_eq!(stats.backoffs_exhausted, 1);
+        close_pipe(rfd, wfd);
+    }
+
+    #[test]
+    fn test_registration_is_restored() {
+        let mut delays = Vec::new();
+        let (mut event_loop, rfd, wfd) = readable_pipe(&mut delays);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(ae_get_file_events(&event_loop, rfd), 0);
+
+        std::thread::sleep(Duration::from_millis(10));
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(ae_get_file_events(&event_loop, rfd), AE_READABLE);
+        assert_eq!(ae_get_file_tag(&event_loop, rfd), 7);
+        assert_eq!(ae_backoff_failures(&event_loop, rfd), Some(1));
+        close_pipe(rfd, wfd);
+    }
+}
+
+mod streaks {
+    use super::*;
+
+    #[test]
+    fn test_reset_restores_right_away() {
+        let mut delays = Vec::new();
+        let (mut event_loop, rfd, wfd) = readable_pipe(&mut delays);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(ae_get_file_events(&event_loop, rfd), 0);
+
+        ae_backoff_reset(&mut event_loop, rfd);
+        assert_eq!(ae_get_file_events(&event_loop, rfd), AE_READABLE);
+        assert_eq!(ae_backoff_failures(&event_loop, rfd), None);
+
+        /* A new streak starts from the initial delay. */
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(delays, [5, 5]);
+        close_pipe(rfd, wfd);
+    }
+
+    #[test]
+    fn test_cancel_leaves_fd_unregistered() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut delays = Vec::new();
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        ae_create_file_event_ex(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            failing_accept,
+            &mut delays as *mut Vec<i64> as *mut c_void,
+            AeFileEventOptions::default(),
+        );
+        let delay = ae_backoff(&mut event_loop, rfd, AE_READABLE, libc::EMFILE);
+        assert_eq!(delay, rae::AE_BACKOFF_INITIAL_MS);
+
+        ae_backoff_cancel(&mut event_loop, rfd);
+        std::thread::sleep(Duration::from_millis(delay as u64 + 5));
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(ae_get_file_events(&event_loop, rfd), 0);
+        assert_eq!(ae_backoff_failures(&event_loop, rfd), None);
+        close_pipe(rfd, wfd);
+    }
+}
@@ -0,0 +1,185 @@
+/* Buffer Pool Tests
+ *
+ * Tests for the read buffer pool (ae/bufpool.rs): size classes, the cap on
+ * free buffers, and buffered connections taking their input buffer from
+ * the pool.
+ */
+
+use rae::test_util::{conn_pair, consume_all};
+use rae::{
+    AE_ALL_EVENTS, AE_BUF_POOL_CLASSES, AE_DONT_WAIT, AeBufPoolStats, AeEventLoop,
+    AeEventLoopBuilder, ae_buf_acquire, ae_buf_pool_stats, ae_buf_pool_trim, ae_buf_release,
+    ae_conn_close, ae_create_event_loop, ae_delete_event_loop, ae_memory_usage, ae_process_events,
+};
+use std::ffi::c_void;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+
+/* Consumes input up to the last newline. */
+fn consume_lines(
+    _event_loop: &mut AeEventLoop,
+    _fd: i32,
+    input: &[u8],
+    _data: *mut c_void,
+) -> usize {
+    input
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |at| at + 1)
+}
+
+mod classes {
+    use super::*;
+
+    #[test]
+    fn test_smallest_class_that_fits() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let buf = ae_buf_acquire(&mut event_loop, 100);
+        assert!(buf.is_empty());
+        assert_eq!(buf.capacity(), AE_BUF_POOL_CLASSES[0]);
+        let buf = ae_buf_acquire(&mut event_loop, AE_BUF_POOL_CLASSES[0] + 1);
+        assert_eq!(buf.capacity(), AE_BUF_POOL_CLASSES[1]);
+
+        /* Larger than every class: an exact allocation. */
+        let huge = ae_buf_acquire(&mut event_loop, 1 << 20);
+        assert_eq!(huge.capacity(), 1 << 20);
+        assert_eq!(ae_buf_pool_stats(&event_loop).misses, 3);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_released_buffers_are_reused() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut buf = ae_buf_acquire(&mut event_loop, 10);
+        buf.extend_from_slice(b"leftover");
+        let ptr = buf.as_ptr();
+        ae_buf_release(&mut event_loop, buf);
+
+        let stats = ae_buf_pool_stats(&event_loop);
+        assert_eq!(stats.released, 1);
+        assert_eq!(stats.cached, 1);
+        assert_eq!(stats.cached_bytes, AE_BUF_POOL_CLASSES[0]);
+        assert_eq!(ae_memory_usage(&event_loop).buffer_pool, stats.cached_bytes);
+
+        let buf = ae_buf_acquire(&mut event_loop, 10);
+        assert_eq!(buf.as_ptr(), ptr);
+        assert!(buf.is_empty(), "a pooled buffer comes back cleared");
+        let stats = ae_buf_pool_stats(&event_loop);
+        assert_eq!((stats.hits, stats.misses, stats.cached), (1, 1, 0));
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_release_files_by_capacity() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        /* 20K holds a 16K request, not a 64K one. */
+        ae_buf_release(&mut event_loop, Vec::with_capacity(20 * 1024));
+        let buf = ae_buf_acquire(&mut event_loop, 64 * 1024);
+        assert_eq!(ae_buf_pool_stats(&event_loop).hits, 0);
+        drop(buf);
+        let buf = ae_buf_acquire(&mut event_loop, 16 * 1024);
+        assert!(buf.capacity() >= 20 * 1024);
+        assert_eq!(ae_buf_pool_stats(&event_loop).hits, 1);
+
+        /* Too small for any class. */
+        ae_buf_release(&mut event_loop, Vec::with_capacity(16));
+        assert_eq!(ae_buf_pool_stats(&event_loop).dropped, 1);
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod limits {
+    use super::*;
+
+    #[test]
+    fn test_max_per_class() {
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .buffer_pool(&[1024], 2)
+            .build()
+            .expect("Failed to create event loop");
+        let bufs: Vec<Vec<u8>> = (0..3).map(|_| ae_buf_acquire(&mut event_loop, 1)).collect();
+        for buf in bufs {
+            ae_buf_release(&mut event_loop, buf);
+        }
+        let stats = ae_buf_pool_stats(&event_loop);
+        assert_eq!((stats.released, stats.dropped, stats.cached), (2, 1, 2));
+
+        ae_buf_pool_trim(&mut event_loop);
+        let stats = ae_buf_pool_stats(&event_loop);
+        assert_eq!((stats.cached, stats.cached_bytes), (0, 0));
+        assert_eq!(ae_memory_usage(&event_loop).buffer_pool, 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_no_classes() {
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .buffer_pool(&[], 64)
+            .build()
+            .expect("Failed to create event loop");
+        let buf = ae_buf_acquire(&mut event_loop, 100);
+        assert_eq!(buf.capacity(), 100);
+        ae_buf_release(&mut event_loop, buf);
+        let stats = ae_buf_pool_stats(&event_loop);
+        assert_eq!(
+            stats,
+            AeBufPoolStats {
+                misses: 1,
+                dropped: 1,
+                ..Default::default()
+            }
+        );
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod connections {
+    use super::*;
+
+    #[test]
+    fn test_reads_share_pooled_buffers() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let conns: Vec<(i32, UnixStream)> = (0..8)
+            .map(|_| conn_pair(&mut event_loop, consume_all, None, std::ptr::null_mut()))
+            .collect();
+        let idle = ae_memory_usage(&event_loop).connections;
+
+        for round in 0..4 {
+            for (_, theirs) in &conns {
+                let mut theirs = theirs;
+                theirs.write_all(b"PING\r\n").unwrap();
+            }
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+            /* Every read returned its buffer, the next one takes it. */
+            assert_eq!(ae_memory_usage(&event_loop).connections, idle);
+            let stats = ae_buf_pool_stats(&event_loop);
+            assert_eq!(stats.misses, 1, "round {round}");
+            assert_eq!(stats.hits, 8 * (round + 1) - 1);
+            assert_eq!(stats.cached, 1);
+        }
+
+        for (fd, _) in conns {
+            ae_conn_close(&mut event_loop, fd);
+        }
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_partial_input_keeps_its_buffer() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (fd, mut theirs) =
+            conn_pair(&mut event_loop, consume_lines, None, std::ptr::null_mut());
+
+        theirs.write_all(b"GET a\r\nGET").unwrap();
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(ae_buf_pool_stats(&event_loop).cached, 0);
+
+        theirs.write_all(b" b\r\n").unwrap();
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        let stats = ae_buf_pool_stats(&event_loop);
+        assert_eq!((stats.misses, stats.released, stats.cached), (1, 1, 1));
+
+        ae_conn_close(&mut event_loop, fd);
+        ae_delete_event_loop(event_loop);
+    }
+}
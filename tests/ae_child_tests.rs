@@ -0,0 +1,113 @@
+/* Child Loop Tests
+ *
+ * Tests for child loops pumped by a parent loop (ae/child.rs).
+ */
+
+use rae::{AE_ERR, ae_attach_child_loop, ae_create_event_loop, ae_delete_event_loop};
+
+mod attach {
+    use super::*;
+
+    #[test]
+    fn test_select_child_cannot_be_attached() {
+        let mut parent = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let child = ae_create_event_loop(1024).expect("Failed to create event loop");
+
+        /* The select backend has no fd to watch from the parent. */
+        assert_eq!(ae_attach_child_loop(&mut parent, child), AE_ERR);
+
+        ae_delete_event_loop(parent);
+    }
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+mod kqueue_child {
+    use rae::ae_kqueue::aeApiState;
+    use rae::anet::anet_pipe;
+    use rae::traits::EventBackend;
+    use rae::{
+        AE_ALL_EVENTS, AE_NOMORE, AE_READABLE, AeEventLoop, ae_attach_child_loop,
+        ae_create_event_loop, ae_create_event_loop_with_backend, ae_create_file_event,
+        ae_create_time_event, ae_detach_child_loop, ae_get_file_events, ae_process_events,
+    };
+    use std::ffi::c_void;
+
+    fn kqueue_loop() -> Box<AeEventLoop> {
+        let backend = aeApiState::create().expect("Failed to create kqueue state");
+        ae_create_event_loop_with_backend(1024, backend).expect("Failed to create event loop")
+    }
+
+    fn count_read(_el: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+        let mut buf = [0u8; 16];
+        unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+        unsafe { *(client_data as *mut i32) += 1 };
+    }
+
+    fn count_timer(_el: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+        unsafe { *(client_data as *mut i32) += 1 };
+        AE_NOMORE
+    }
+
+    #[test]
+    fn test_child_file_event_pumped_by_parent() {
+        let mut parent = ae_create_event_loop(1024).unwrap();
+        let mut child = kqueue_loop();
+        let (rfd, wfd) = anet_pipe(true).unwrap();
+        let mut reads = 0i32;
+
+        ae_create_file_event(
+            &mut child,
+            rfd,
+            AE_READABLE,
+            count_read,
+            &mut reads as *mut i32 as *mut c_void,
+        );
+        let child_fd = ae_attach_child_loop(&mut parent, child);
+        assert!(child_fd >= 0);
+        assert_eq!(ae_get_file_events(&parent, child_fd), AE_READABLE);
+
+        unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+        ae_process_events(&mut parent, AE_ALL_EVENTS);
+        assert_eq!(reads, 1, "Child callback should run from the parent loop");
+
+        let child = ae_detach_child_loop(&mut parent, child_fd).expect("Child should detach");
+        assert_eq!(ae_get_file_events(&parent, child_fd), 0);
+        drop(child);
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+
+    #[test]
+    fn test_child_timer_pumped_by_parent() {
+        let mut parent = ae_create_event_loop(1024).unwrap();
+        let mut child = kqueue_loop();
+        let mut fired = 0i32;
+
+        ae_create_time_event(
+            &mut child,
+            10,
+            count_timer,
+            &mut fired as *mut i32 as *mut c_void,
+            None,
+        );
+        let child_fd = ae_attach_child_loop(&mut parent, child);
+        assert!(child_fd >= 0);
+
+        for _ in 0..10 {
+            if fired > 0 {
+                break;
+            }
+            ae_process_events(&mut parent, AE_ALL_EVENTS);
+        }
+        assert_eq!(fired, 1, "Child timer should fire through the parent");
+
+        ae_detach_child_loop(&mut parent, child_fd);
+    }
+}
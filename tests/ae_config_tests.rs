@@ -0,0 +1,118 @@
+/* Config Tests
+ *
+ * Tests for ae_set_config() and ae_config() (ae/config.rs): one snapshot
+ * per type, visible to callbacks, swapped by reloads without changing the
+ * snapshots already handed out, and published from other threads through
+ * AeHandle::set_config().
+ */
+
+use rae::{
+    AE_ALL_EVENTS, AE_NOMORE, AE_OK, AeEventLoop, ae_clear_config, ae_config, ae_create_event_loop,
+    ae_create_time_event, ae_get_handle, ae_main, ae_process_events, ae_set_config, ae_stop,
+};
+use std::ffi::c_void;
+use std::sync::Arc;
+
+#[derive(Debug, PartialEq)]
+struct Limits {
+    max_clients: usize,
+}
+
+#[derive(Debug, PartialEq)]
+struct Greeting(&'static str);
+
+/* Stores the max_clients it sees into client_data. */
+fn read_limits(event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    let seen = ae_config::<Limits>(event_loop).map_or(0, |limits| limits.max_clients);
+    unsafe { *(client_data as *mut usize) = seen };
+    ae_stop(event_loop);
+    AE_NOMORE
+}
+
+mod slots {
+    use super::*;
+
+    #[test]
+    fn test_set_and_get() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        assert!(ae_config::<Limits>(&event_loop).is_none());
+
+        let first = Arc::new(Limits { max_clients: 10 });
+        assert!(ae_set_config(&mut event_loop, first.clone()).is_none());
+        assert!(Arc::ptr_eq(
+            &ae_config::<Limits>(&event_loop).unwrap(),
+            &first
+        ));
+
+        let previous = ae_set_config(&mut event_loop, Arc::new(Limits { max_clients: 20 }));
+        assert!(Arc::ptr_eq(&previous.unwrap(), &first));
+        assert_eq!(ae_config::<Limits>(&event_loop).unwrap().max_clients, 20);
+
+        assert_eq!(
+            ae_clear_config::<Limits>(&mut event_loop)
+                .unwrap()
+                .max_clients,
+            20
+        );
+        assert!(ae_config::<Limits>(&event_loop).is_none());
+    }
+
+    #[test]
+    fn test_one_slot_per_type() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_set_config(&mut event_loop, Arc::new(Limits { max_clients: 1 }));
+        ae_set_config(&mut event_loop, Arc::new(Greeting("hello")));
+        assert_eq!(ae_config::<Limits>(&event_loop).unwrap().max_clients, 1);
+        assert_eq!(
+            *ae_config::<Greeting>(&event_loop).unwrap(),
+            Greeting("hello")
+        );
+    }
+
+    #[test]
+    fn test_snapshot_survives_reload() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_set_config(&mut event_loop, Arc::new(Limits { max_clients: 1 }));
+        let snapshot = ae_config::<Limits>(&event_loop).unwrap();
+        ae_set_config(&mut event_loop, Arc::new(Limits { max_clients: 2 }));
+        assert_eq!(snapshot.max_clients, 1);
+        assert_eq!(ae_config::<Limits>(&event_loop).unwrap().max_clients, 2);
+    }
+}
+
+mod callbacks {
+    use super::*;
+
+    #[test]
+    fn test_visible_from_callbacks() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_set_config(&mut event_loop, Arc::new(Limits { max_clients: 42 }));
+        let mut seen = 0usize;
+        ae_create_time_event(
+            &mut event_loop,
+            0,
+            read_limits,
+            &mut seen as *mut usize as *mut c_void,
+            None,
+        );
+        ae_main(&mut event_loop);
+        assert_eq!(seen, 42);
+    }
+
+    #[test]
+    fn test_published_from_another_thread() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let handle = ae_get_handle(&mut event_loop).expect("Failed to get handle");
+        std::thread::spawn(move || {
+            assert_eq!(
+                handle.set_config(Arc::new(Limits { max_clients: 7 })),
+                AE_OK
+            );
+        })
+        .join()
+        .unwrap();
+
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        assert_eq!(ae_config::<Limits>(&event_loop).unwrap().max_clients, 7);
+    }
+}
@@ -0,0 +1,506 @@
+/* Buffered Connection Tests
+ *
+ * Tests for ae/conn.rs over Unix socket pairs: input handed to the read
+ * proc until consumed, queued output and backpressure, the ways a
+ * connection gets closed, and protocol upgrades.
+ */
+
+use rae::test_util::{conn_pair, pump};
+use rae::{
+    AE_ALL_EVENTS, AE_ERR, AE_OK, AE_READABLE, AE_WRITABLE, AeConnHalf, AeEventLoop,
+    ae_conn_client_data, ae_conn_close, ae_conn_close_after_write, ae_conn_closing, ae_conn_create,
+    ae_conn_half_state, ae_conn_handover, ae_conn_pending_output, ae_conn_replace_handlers,
+    ae_conn_set_eof_proc, ae_conn_shutdown_write, ae_conn_write, ae_create_event_loop,
+    ae_delete_event_loop, ae_get_file_events, ae_process_events,
+};
+use std::ffi::c_void;
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+#[derive(Default)]
+struct Peer {
+    /* Every input the read proc was called with. */
+    inputs: Vec<Vec<u8>>,
+    closed: Option<i32>,
+}
+
+fn peer<'a>(client_data: *mut c_void) -> &'a mut Peer {
+    unsafe { &mut *(client_data as *mut Peer) }
+}
+
+/* Echoes complete lines back, leaving a partial one buffered. A "quit"
+ * line closes the connection after the reply. */
+fn echo_lines(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    input: &[u8],
+    client_data: *mut c_void,
+) -> usize {
+    peer(client_data).inputs.push(input.to_vec());
+    let mut consumed = 0;
+    while !ae_conn_closing(event_loop, fd) {
+        let Some(newline) = input[consumed..].iter().position(|&c| c == b'\n') else {
+            break;
+        };
+        let line = &input[consumed..consumed + newline + 1];
+        consumed += newline + 1;
+        assert_eq!(ae_conn_write(event_loop, fd, line), AE_OK);
+        if line == b"quit\n" {
+            ae_conn_close_after_write(event_loop, fd);
+            assert!(ae_conn_closing(event_loop, fd));
+        }
+    }
+    consumed
+}
+
+fn on_close(_event_loop: &mut AeEventLoop, _fd: i32, err: i32, client_data: *mut c_void) {
+    let peer = peer(client_data);
+    assert!(peer.closed.is_none(), "closed twice");
+    peer.closed = Some(err);
+}
+
+/* A connection over one end of a socket pair, and the other end. */
+fn connect(
+    event_loop: &mut AeEventLoop,
+    proc: rae::ConnReadProc,
+    peer: &mut Peer,
+) -> (i32, UnixStream) {
+    let (fd, theirs) = conn_pair(
+        event_loop,
+        proc,
+        Some(on_close),
+        peer as *mut Peer as *mut c_void,
+    );
+    theirs
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (fd, theirs)
+}
+
+fn read_exact(stream: &mut UnixStream, len: usize) -> Vec<u8> {
+    let mut buf = vec![0; len];
+    stream.read_exact(&mut buf).expect("Failed to read");
+    buf
+}
+
+mod input {
+    use super::*;
+
+    #[test]
+    fn test_partial_input_is_kept() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut peer = Peer::default();
+        let (fd, mut theirs) = connect(&mut event_loop, echo_lines, &mut peer);
+
+        theirs.write_all(b"hel").unwrap();
+        pump(&mut event_loop);
+        theirs.write_all(b"lo\nwor").unwrap();
+        pump(&mut event_loop);
+
+        assert_eq!(read_exact(&mut theirs, 6), b"hello\n");
+        assert_eq!(peer.inputs, [b"hel".to_vec(), b"hello\nwor".to_vec()]);
+
+        ae_conn_close(&mut event_loop, fd);
+        assert_eq!(peer.closed, Some(0));
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_client_data() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut peer = Peer::default();
+        let (fd, _theirs) = connect(&mut event_loop, echo_lines, &mut peer);
+        let peer_ptr = &mut peer as *mut Peer as *mut c_void;
+
+        assert_eq!(ae_conn_client_data(&event_loop, fd), peer_ptr);
+        ae_conn_close(&mut event_loop, fd);
+        assert!(ae_conn_client_data(&event_loop, fd).is_null());
+        assert!(ae_conn_closing(&event_loop, fd));
+        assert_eq!(ae_conn_write(&mut event_loop, fd, b"x"), AE_ERR);
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod output {
+    use super::*;
+
+    #[test]
+    fn test_backpressure() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut peer = Peer::default();
+        let (fd, mut theirs) = connect(&mut event_loop, echo_lines, &mut peer);
+
+        /* More than the socket buffers hold. */
+        let data = vec![b'x'; 8 * 1024 * 1024];
+        assert_eq!(ae_conn_write(&mut event_loop, fd, &data), AE_OK);
+        assert!(ae_conn_pending_output(&event_loop, fd) > 0);
+        assert_ne!(ae_get_file_events(&event_loop, fd) & AE_WRITABLE, 0);
+
+        let reader = std::thread::spawn(move || read_exact(&mut theirs, 8 * 1024 * 1024));
+        while ae_conn_pending_output(&event_loop, fd) > 0 {
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        }
+        assert_eq!(ae_get_file_events(&event_loop, fd) & AE_WRITABLE, 0);
+        assert_eq!(reader.join().unwrap(), data);
+
+        ae_conn_close(&mut event_loop, fd);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_close_after_write() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut peer = Peer::default();
+        let (_fd, mut theirs) = connect(&mut event_loop, echo_lines, &mut peer);
+
+        /* The line after quit is never answered. */
+        theirs.write_all(b"one\nquit\ntwo\n").unwrap();
+        pump(&mut event_loop);
+
+        let mut reply = Vec::new();
+        theirs.read_to_end(&mut reply).unwrap();
+        assert_eq!(reply, b"one\nquit\n");
+        assert_eq!(peer.closed, Some(0));
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod closing {
+    use super::*;
+
+    /* Closes the connection on any input, then tries to keep using it. */
+    fn close_at_once(
+        event_loop: &mut AeEventLoop,
+        fd: i32,
+        input: &[u8],
+        client_data: *mut c_void,
+    ) -> usize {
+        peer(client_data).inputs.push(input.to_vec());
+        ae_conn_close(event_loop, fd);
+        /* Deferred: the state is still there until the proc returns. */
+        assert!(peer(client_data).closed.is_none());
+        assert!(ae_conn_closing(event_loop, fd));
+        assert_eq!(ae_conn_write(event_loop, fd, b"late"), AE_ERR);
+        0
+    }
+
+    #[test]
+    fn test_peer_hangup() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut peer = Peer::default();
+        let (fd, theirs) = connect(&mut event_loop, echo_lines, &mut peer);
+
+        drop(theirs);
+        pump(&mut event_loop);
+        assert_eq!(peer.closed, Some(0));
+        assert_eq!(ae_get_file_events(&event_loop, fd), 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_close_from_read_proc() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut peer = Peer::default();
+        let (fd, mut theirs) = connect(&mut event_loop, close_at_once, &mut peer);
+
+        theirs.write_all(b"bye").unwrap();
+        pump(&mut event_loop);
+        assert_eq!(peer.inputs.len(), 1);
+        assert_eq!(peer.closed, Some(0));
+        assert_eq!(ae_get_file_events(&event_loop, fd), 0);
+
+        let mut rest = Vec::new();
+        theirs.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_write_error_reports_errno() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut peer = Peer::default();
+        let (fd, theirs) = connect(&mut event_loop, echo_lines, &mut peer);
+
+        drop(theirs);
+        /* Writing to a socket whose peer is gone fails with EPIPE (the
+         * test harness ignores SIGPIPE). */
+        ae_conn_write(&mut event_loop, fd, b"anyone?");
+        assert_eq!(peer.closed, Some(libc::EPIPE));
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod half_close {
+    use super::*;
+
+    /* Records input without replying. */
+    fn record(
+        _event_loop: &mut AeEventLoop,
+        _fd: i32,
+        input: &[u8],
+        client_data: *mut c_void,
+    ) -> usize {
+        peer(client_data).inputs.push(input.to_vec());
+        input.len()
+    }
+
+    /* Answers end of file with a goodbye, the connection staying open. */
+    fn say_bye(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void) {
+        peer(client_data).inputs.push(b"EOF".to_vec());
+        assert_eq!(
+            ae_conn_half_state(event_loop, fd),
+            Some(AeConnHalf::ReadClosed)
+        );
+        assert_eq!(ae_conn_write(event_loop, fd, b"bye\n"), AE_OK);
+    }
+
+    #[test]
+    fn test_read_eof_keeps_the_write_side() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut peer = Peer::default();
+        let (fd, mut theirs) = connect(&mut event_loop, record, &mut peer);
+        assert_eq!(
+            ae_conn_set_eof_proc(&mut event_loop, fd, Some(say_bye)),
+            AE_OK
+        );
+
+        theirs.write_all(b"last words").unwrap();
+        theirs.shutdown(Shutdown::Write).unwrap();
+        pump(&mut event_loop);
+        assert_eq!(peer.inputs, vec![b"last words".to_vec(), b"EOF".to_vec()]);
+        assert_eq!(read_exact(&mut theirs, 4), b"bye\n");
+        assert_eq!(peer.closed, None);
+        assert_eq!(ae_get_file_events(&event_loop, fd) & AE_READABLE, 0);
+
+        /* Late output still goes out. */
+        assert_eq!(ae_conn_write(&mut event_loop, fd, b"ps\n"), AE_OK);
+        assert_eq!(read_exact(&mut theirs, 3), b"ps\n");
+
+        /* Nothing left in either direction. */
+        assert_eq!(ae_conn_shutdown_write(&mut event_loop, fd), AE_OK);
+        assert_eq!(peer.closed, Some(0));
+        assert_eq!(ae_conn_half_state(&event_loop, fd), None);
+        let mut rest = Vec::new();
+        theirs.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_shutdown_write_keeps_reading() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut peer = Peer::default();
+        let (fd, mut theirs) = connect(&mut event_loop, record, &mut peer);
+        assert_eq!(ae_conn_half_state(&event_loop, fd), Some(AeConnHalf::Open));
+
+        assert_eq!(ae_conn_write(&mut event_loop, fd, b"request"), AE_OK);
+        assert_eq!(ae_conn_shutdown_write(&mut event_loop, fd), AE_OK);
+        assert_eq!(
+            ae_conn_half_state(&event_loop, fd),
+            Some(AeConnHalf::WriteClosed)
+        );
+        assert_eq!(ae_conn_write(&mut event_loop, fd, b"more"), AE_ERR);
+        let mut request = Vec::new();
+        theirs.read_to_end(&mut request).unwrap();
+        assert_eq!(request, b"request");
+
+        /* The answer is read after our end of file, then the peer's end
+         * of file closes the connection. */
+        theirs.write_all(b"response").unwrap();
+        pump(&mut event_loop);
+        assert_eq!(peer.inputs, vec![b"response".to_vec()]);
+        assert_eq!(peer.closed, None);
+        theirs.shutdown(Shutdown::Write).unwrap();
+        pump(&mut event_loop);
+        assert_eq!(peer.closed, Some(0));
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_shutdown_waits_for_queued_output() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut peer = Peer::default();
+        let (fd, mut theirs) = connect(&mut event_loop, record, &mut peer);
+
+        /* More than the socket buffers hold. */
+        let data = vec![b'x'; 8 * 1024 * 1024];
+        assert_eq!(ae_conn_write(&mut event_loop, fd, &data), AE_OK);
+        assert_eq!(ae_conn_shutdown_write(&mut event_loop, fd), AE_OK);
+        assert_eq!(
+            ae_conn_half_state(&event_loop, fd),
+            Some(AeConnHalf::WriteDraining)
+        );
+
+        let reader = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            theirs.read_to_end(&mut received).unwrap();
+            received
+        });
+        while ae_conn_half_state(&event_loop, fd) == Some(AeConnHalf::WriteDraining) {
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        }
+        assert_eq!(
+            ae_conn_half_state(&event_loop, fd),
+            Some(AeConnHalf::WriteClosed)
+        );
+        assert_eq!(reader.join().unwrap(), data);
+
+        ae_conn_close(&mut event_loop, fd);
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod upgrade {
+    use super::*;
+
+    #[derive(Default)]
+    struct Upgrade {
+        /* Inputs of the first protocol, and of the one it upgraded to. */
+        before: Vec<Vec<u8>>,
+        after: Peer,
+        handed: Option<(i32, Vec<u8>)>,
+    }
+
+    /* Line protocol switching to `record_rest` on UPGRADE and handing
+     * the fd over on STARTTLS, answering first. */
+    fn upgrading(
+        event_loop: &mut AeEventLoop,
+        fd: i32,
+        input: &[u8],
+        client_data: *mut c_void,
+    ) -> usize {
+        let upgrade = unsafe { &mut *(client_data as *mut Upgrade) };
+        upgrade.before.push(input.to_vec());
+        let Some(newline) = input.iter().position(|&c| c == b'\n') else {
+            return 0;
+        };
+        match &input[..newline] {
+            b"UPGRADE" => {
+                assert_eq!(ae_conn_write(event_loop, fd, b"101\n"), AE_OK);
+                let after = &mut upgrade.after as *mut Peer as *mut c_void;
+                let result =
+                    ae_conn_replace_handlers(event_loop, fd, record_rest, Some(on_close), after);
+                assert_eq!(result, AE_OK);
+            }
+            b"STARTTLS" => {
+                assert_eq!(ae_conn_write(event_loop, fd, b"220\n"), AE_OK);
+                assert_eq!(ae_conn_handover(event_loop, fd, handed, client_data), AE_OK);
+                assert_eq!(
+                    ae_conn_handover(event_loop, fd, handed, client_data),
+                    AE_ERR
+                );
+            }
+            line => panic!("unexpected {:?}", line),
+        }
+        newline + 1
+    }
+
+    fn record_rest(
+        _event_loop: &mut AeEventLoop,
+        _fd: i32,
+        input: &[u8],
+        client_data: *mut c_void,
+    ) -> usize {
+        peer(client_data).inputs.push(input.to_vec());
+        input.len()
+    }
+
+    fn handed(_event_loop: &mut AeEventLoop, fd: i32, input: Vec<u8>, client_data: *mut c_void) {
+        let upgrade = unsafe { &mut *(client_data as *mut Upgrade) };
+        assert!(upgrade.handed.is_none(), "handed over twice");
+        upgrade.handed = Some((fd, input));
+    }
+
+    fn connect_upgrading(event_loop: &mut AeEventLoop, upgrade: &mut Upgrade) -> (i32, UnixStream) {
+        let (ours, theirs) = UnixStream::pair().expect("Failed to create socket pair");
+        ours.set_nonblocking(true).unwrap();
+        theirs
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let fd = ours.into_raw_fd();
+        let client_data = upgrade as *mut Upgrade as *mut c_void;
+        assert_eq!(
+            ae_conn_create(event_loop, fd, upgrading, None, client_data),
+            AE_OK
+        );
+        (fd, theirs)
+    }
+
+    #[test]
+    fn test_replaced_proc_gets_the_rest_of_the_input() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut upgrade = Upgrade::default();
+        let (fd, mut theirs) = connect_upgrading(&mut event_loop, &mut upgrade);
+
+        /* The first frame of the new protocol comes with the request. */
+        theirs.write_all(b"UPGRADE\nframe").unwrap();
+        pump(&mut event_loop);
+        assert_eq!(read_exact(&mut theirs, 4), b"101\n");
+        assert_eq!(upgrade.before, [b"UPGRADE\nframe".to_vec()]);
+        assert_eq!(upgrade.after.inputs, [b"frame".to_vec()]);
+
+        theirs.write_all(b"next").unwrap();
+        pump(&mut event_loop);
+        assert_eq!(upgrade.after.inputs, [b"frame".to_vec(), b"next".to_vec()]);
+
+        /* The close proc was replaced too. */
+        drop(theirs);
+        pump(&mut event_loop);
+        assert_eq!(upgrade.after.closed, Some(0));
+        assert!(ae_conn_closing(&event_loop, fd));
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_replace_outside_the_read_proc() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut peer = Peer::default();
+        let (fd, mut theirs) = connect(&mut event_loop, echo_lines, &mut peer);
+        theirs.write_all(b"partial").unwrap();
+        pump(&mut event_loop);
+
+        let mut after = Peer::default();
+        let after_ptr = &mut after as *mut Peer as *mut c_void;
+        let result = ae_conn_replace_handlers(&mut event_loop, fd, record_rest, None, after_ptr);
+        assert_eq!(result, AE_OK);
+        assert_eq!(after.inputs, [b"partial".to_vec()]);
+        assert_eq!(ae_conn_client_data(&event_loop, fd), after_ptr);
+
+        ae_conn_close(&mut event_loop, fd);
+        assert_eq!(peer.closed, None);
+        assert_eq!(
+            ae_conn_replace_handlers(&mut event_loop, fd, record_rest, None, after_ptr),
+            AE_ERR
+        );
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_handover_after_the_reply() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut upgrade = Upgrade::default();
+        let (fd, mut theirs) = connect_upgrading(&mut event_loop, &mut upgrade);
+
+        theirs.write_all(b"STARTTLS\nhello").unwrap();
+        pump(&mut event_loop);
+        assert_eq!(upgrade.handed, Some((fd, b"hello".to_vec())));
+        assert_eq!(read_exact(&mut theirs, 4), b"220\n");
+
+        /* No longer a connection, but the fd is open and ours. */
+        assert!(ae_conn_closing(&event_loop, fd));
+        assert_eq!(ae_get_file_events(&event_loop, fd), 0);
+        theirs.write_all(b"more").unwrap();
+        pump(&mut event_loop);
+        assert_eq!(upgrade.before.len(), 1);
+        let mut buf = [0u8; 4];
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+        assert_eq!(n, 4);
+        assert_eq!(&buf, b"more");
+
+        unsafe { libc::close(fd) };
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -0,0 +1,202 @@
+/* Callback Context Tests
+ *
+ * Tests for the record of the dispatch in progress (ae/context.rs): what
+ * callbacks see while they run, labels, nesting, and the doctor report of
+ * a dispatch that runs for too long.
+ */
+
+use rae::anet::anet_pipe;
+use rae::test_util::{manual_loop, run_once};
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_ERR, AE_NOMORE, AE_OK, AE_READABLE, AeDispatchCtx,
+    AeDispatchRecord, AeDispatchSource, AeEventLoop, AeFindingKind, ae_advance_clock,
+    ae_create_file_event, ae_create_job, ae_create_time_event, ae_current_dispatch,
+    ae_delete_event_loop, ae_delete_time_event, ae_dispatch_stack, ae_doctor, ae_loop_now,
+    ae_process_events, ae_set_dispatch_label,
+};
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::rc::Rc;
+
+/* Stacks seen by the callbacks, through client_data. */
+type Seen = Vec<Vec<AeDispatchRecord>>;
+
+fn seen_ptr(seen: &mut Seen) -> *mut c_void {
+    seen as *mut Seen as *mut c_void
+}
+
+fn record_stack(event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    let seen = unsafe { &mut *(client_data as *mut Seen) };
+    seen.push(ae_dispatch_stack(event_loop));
+    AE_NOMORE
+}
+
+mod stack {
+    use super::*;
+
+    #[test]
+    fn test_empty_outside_callbacks() {
+        let mut event_loop = manual_loop(64);
+        assert_eq!(ae_current_dispatch(&event_loop), None);
+        assert_eq!(ae_set_dispatch_label(&mut event_loop, "idle"), AE_ERR);
+        run_once(&mut event_loop);
+        assert!(ae_dispatch_stack(&event_loop).is_empty());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut event_loop = manual_loop(64);
+        ae_advance_clock(&mut event_loop, 1000);
+        let mut seen = Seen::new();
+        let id = ae_create_time_event(&mut event_loop, 0, record_stack, seen_ptr(&mut seen), None);
+        run_once(&mut event_loop);
+
+        assert_eq!(
+            seen,
+            vec![vec![AeDispatchRecord {
+                source: AeDispatchSource::Timer { id },
+                started_us: ae_loop_now(&event_loop),
+                label: None,
+            }]]
+        );
+        assert_eq!(ae_current_dispatch(&event_loop), None);
+        ae_delete_event_loop(event_loop);
+    }
+
+    fn record_file(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+        let mut buf = [0u8; 16];
+        unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+        assert_eq!(ae_set_dispatch_label(event_loop, "drain"), AE_OK);
+        let seen = unsafe { &mut *(client_data as *mut Seen) };
+        seen.push(ae_dispatch_stack(event_loop));
+    }
+
+    #[test]
+    fn test_file_event_with_label() {
+        let mut event_loop = manual_loop(64);
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        let mut seen = Seen::new();
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            record_file,
+            seen_ptr(&mut seen),
+        );
+        unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+        run_once(&mut event_loop);
+
+        assert_eq!(seen.len(), 1);
+        assert_eq!(
+            seen[0][0].source,
+            AeDispatchSource::File {
+                fd: rfd,
+                mask: AE_READABLE
+            }
+        );
+        assert_eq!(seen[0][0].label, Some("drain"));
+        ae_delete_event_loop(event_loop);
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+
+    fn nested(event_loop: &mut AeEventLoop, id: i64, client_data: *mut c_void) -> i32 {
+        /* Or the nested iterations would fire this timer again. */
+        ae_delete_time_event(event_loop, id);
+        ae_set_dispatch_label(event_loop, "outer");
+        ae_create_time_event(event_loop, 0, record_stack, client_data, None);
+        /* Timers created during an iteration wait for the next one. */
+        ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        AE_NOMORE
+    }
+
+    #[test]
+    fn test_nested_dispatch() {
+        let mut event_loop = manual_loop(64);
+        let mut seen = Seen::new();
+        let outer = ae_create_time_event(&mut event_loop, 0, nested, seen_ptr(&mut seen), None);
+        run_once(&mut event_loop);
+
+        assert_eq!(seen.len(), 1);
+        let stack = &seen[0];
+        assert_eq!(stack.len(), 2, "Outermost first");
+        assert_eq!(stack[0].source, AeDispatchSource::Timer { id: outer });
+        assert_eq!(stack[0].label, Some("outer"));
+        assert_eq!(stack[1].label, None);
+        assert!(ae_dispatch_stack(&event_loop).is_empty());
+        ae_delete_event_loop(event_loop);
+    }
+
+    fn job_step(
+        event_loop: &mut AeEventLoop,
+        _ctx: &AeDispatchCtx,
+        seen: &mut Rc<RefCell<Seen>>,
+    ) -> bool {
+        seen.borrow_mut().push(ae_dispatch_stack(event_loop));
+        false
+    }
+
+    #[test]
+    fn test_job_is_labelled_with_its_name() {
+        let mut event_loop = manual_loop(64);
+        let seen = Rc::new(RefCell::new(Seen::new()));
+        ae_create_job(
+            &mut event_loop,
+            "rehash",
+            1000,
+            job_step,
+            Box::new(seen.clone()),
+        );
+        run_once(&mut event_loop);
+
+        let seen = seen.take();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0][0].label, Some("rehash"));
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod doctor {
+    use super::*;
+
+    fn stall(event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+        ae_set_dispatch_label(event_loop, "keys *");
+        ae_advance_clock(event_loop, 20_000);
+        let kinds = unsafe { &mut *(client_data as *mut Vec<AeFindingKind>) };
+        kinds.extend(ae_doctor(event_loop).into_iter().map(|f| f.kind));
+        AE_NOMORE
+    }
+
+    #[test]
+    fn test_reports_long_dispatch() {
+        let mut event_loop = manual_loop(64);
+        let mut kinds: Vec<AeFindingKind> = Vec::new();
+        let started_us = ae_loop_now(&event_loop);
+        let id = ae_create_time_event(
+            &mut event_loop,
+            0,
+            stall,
+            &mut kinds as *mut Vec<AeFindingKind> as *mut c_void,
+            None,
+        );
+        run_once(&mut event_loop);
+
+        assert_eq!(
+            kinds,
+            vec![AeFindingKind::LongDispatch {
+                record: AeDispatchRecord {
+                    source: AeDispatchSource::Timer { id },
+                    started_us,
+                    label: Some("keys *"),
+                },
+                running_us: 20_000,
+            }]
+        );
+        assert!(ae_doctor(&event_loop).is_empty());
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -4,11 +4,25 @@
  * basic operations, and core API functions.
  */
 
+use rae::test_util::virtual_loop;
 use rae::{
-    AE_ALL_EVENTS, AE_DONT_WAIT, AE_ERR, AE_FILE_EVENTS, AE_OK, AE_TIME_EVENTS,
-    ae_create_event_loop, ae_delete_event_loop, ae_get_api_name, ae_get_set_size,
-    ae_process_events, ae_resize_set_size, ae_set_dont_wait, ae_stop,
+    AE_ALL_EVENTS, AE_CALL_BEFORE_SLEEP, AE_DONT_WAIT, AE_ERR, AE_FILE_EVENTS, AE_NOMORE, AE_OK,
+    AE_READABLE, AE_TIME_EVENTS, AE_WRITABLE, AeEventLoop, ae_create_event_loop,
+    ae_create_file_event, ae_create_time_event, ae_delete_event_loop, ae_delete_file_event,
+    ae_delete_time_event, ae_dont_wait_next, ae_get_dont_wait, ae_get_file_client_data,
+    ae_get_file_events, ae_get_set_size, ae_pending_time_events, ae_process_events,
+    ae_process_events_nowait, ae_registered_file_events, ae_resize_set_size,
+    ae_resize_set_size_compact, ae_set_before_sleep_proc, ae_set_dont_wait, ae_stop,
 };
+use std::time::{Duration, Instant};
+
+fn skip_next_sleep(event_loop: &mut AeEventLoop) {
+    ae_dont_wait_next(event_loop);
+}
+
+fn noop_timer(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut std::ffi::c_void) -> i32 {
+    AE_NOMORE
+}
 
 mod core_functionality {
     use super::*;
@@ -26,9 +40,10 @@ mod core_functionality {
 
     #[test]
     fn test_create_event_loop_invalid_size() {
-        // Test with size 0 - should fail or succeed based on implementation
-        let result = ae_create_event_loop(0);
-        // Our implementation might allow size 0 - either behavior is acceptable
+        // Size 0 is a timers-only loop, see the timers_only tests
+        let result = ae_create_event_loop(0).expect("Failed to create event loop");
+        assert_eq!(ae_get_set_size(&result), 0);
+        ae_delete_event_loop(result);
 
         // Test with negative size - should fail
         let result = ae_create_event_loop(-1);
@@ -59,9 +74,10 @@ mod core_functionality {
     fn test_resize_invalid_size() {
         let mut event_loop = ae_create_event_loop(100).expect("Failed to create event loop");
 
-        // Test resize to 0 - may succeed or fail based on implementation
+        // Resize to 0 turns it into a timers-only loop
         let result = ae_resize_set_size(&mut event_loop, 0);
-        // Our implementation might allow resize to 0 - either AE_OK or AE_ERR is acceptable
+        assert_eq!(result, AE_OK);
+        assert_eq!(ae_get_set_size(&event_loop), 0);
 
         // Test resize to negative - should fail
         let result = ae_resize_set_size(&mut event_loop, -1);
@@ -72,13 +88,34 @@ mod core_functionality {
 
     #[test]
     fn test_api_name() {
-        let api_name = ae_get_api_name();
+        let event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let api_name = event_loop.api_name();
         // Should be either "select" or "kqueue" depending on platform
         assert!(
             api_name == "select" || api_name == "kqueue",
             "API name should be select or kqueue, got: {}",
             api_name
         );
+        #[allow(deprecated)]
+        let default_name = rae::ae_get_api_name();
+        assert_eq!(default_name, api_name);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_api_name_follows_the_backend() {
+        let (event_loop, _control) = virtual_loop(64);
+        assert_eq!(event_loop.api_name(), "mock");
+
+        let event_loop = rae::AeEventLoopBuilder::new(64)
+            .best_backend()
+            .build()
+            .expect("Failed to create event loop");
+        #[cfg(target_os = "linux")]
+        assert_eq!(event_loop.api_name(), "epoll");
+        #[cfg(target_os = "macos")]
+        assert_eq!(event_loop.api_name(), "kqueue");
+        ae_delete_event_loop(event_loop);
     }
 
     #[test]
@@ -129,6 +166,7 @@ mod core_functionality {
 
         // Set dont wait flag
         ae_set_dont_wait(&mut event_loop, true);
+        assert!(ae_get_dont_wait(&event_loop));
 
         // Process events - should not block
         let processed = ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
@@ -136,7 +174,290 @@ mod core_functionality {
 
         // Clear dont wait flag
         ae_set_dont_wait(&mut event_loop, false);
+        assert!(!ae_get_dont_wait(&event_loop));
+
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_dont_wait_next_iteration_only() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_create_time_event(&mut event_loop, 100, noop_timer, std::ptr::null_mut(), None);
+
+        // Requested from beforesleep: the current iteration does not sleep
+        ae_set_before_sleep_proc(&mut event_loop, Some(skip_next_sleep));
+        let start = Instant::now();
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_CALL_BEFORE_SLEEP);
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        // The request was consumed: the next iteration waits for the timer
+        ae_set_before_sleep_proc(&mut event_loop, None);
+        let start = Instant::now();
+        let processed = ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        assert_eq!(processed, 1);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_process_events_nowait() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_create_time_event(
+            &mut event_loop,
+            1000,
+            noop_timer,
+            std::ptr::null_mut(),
+            None,
+        );
+
+        let start = Instant::now();
+        assert_eq!(ae_process_events_nowait(&mut event_loop, AE_ALL_EVENTS), 0);
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_dont_wait_uses_poll_nowait() {
+        fn idle(_: &mut AeEventLoop, _: i32, _: *mut std::ffi::c_void, _: i32) {}
+
+        let (mut event_loop, control) = virtual_loop(64);
+        /* Without any fd, a non-blocking iteration does not poll at all. */
+        ae_create_file_event(&mut event_loop, 5, AE_READABLE, idle, std::ptr::null_mut());
+        ae_create_time_event(&mut event_loop, 100, noop_timer, std::ptr::null_mut(), None);
+
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(control.polls(), 1);
+        assert_eq!(control.nowait_polls(), 1);
+
+        /* A timer ahead: the loop waits for it through poll(). */
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        assert_eq!(control.polls(), 2);
+        assert_eq!(control.nowait_polls(), 1);
+        assert_eq!(
+            control.last_timeout(),
+            Some(Some(Duration::from_millis(100)))
+        );
+    }
+}
+
+mod registration_counts {
+    use super::*;
+
+    fn every_second(
+        _event_loop: &mut AeEventLoop,
+        _id: i64,
+        _client_data: *mut std::ffi::c_void,
+    ) -> i32 {
+        1000
+    }
+
+    fn noop_file(
+        _event_loop: &mut AeEventLoop,
+        _fd: i32,
+        _client_data: *mut std::ffi::c_void,
+        _mask: i32,
+    ) {
+    }
+
+    #[test]
+    fn test_pending_time_events() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        assert_eq!(ae_pending_time_events(&event_loop), 0);
+
+        let once = ae_create_time_event(&mut event_loop, 0, noop_timer, std::ptr::null_mut(), None);
+        ae_create_time_event(&mut event_loop, 0, every_second, std::ptr::null_mut(), None);
+        let later = ae_create_time_event(
+            &mut event_loop,
+            60_000,
+            noop_timer,
+            std::ptr::null_mut(),
+            None,
+        );
+        assert_eq!(ae_pending_time_events(&event_loop), 3);
+
+        ae_delete_time_event(&mut event_loop, later);
+        assert_eq!(ae_pending_time_events(&event_loop), 2);
+        /* Deleting twice does not count twice. */
+        assert_eq!(ae_delete_time_event(&mut event_loop, later), AE_ERR);
+        assert_eq!(ae_pending_time_events(&event_loop), 2);
+
+        /* AE_NOMORE retires the first one, the other is rescheduled. */
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(ae_pending_time_events(&event_loop), 1);
+        assert_eq!(ae_delete_time_event(&mut event_loop, once), AE_ERR);
+        ae_delete_event_loop(event_loop);
+    }
 
+    #[test]
+    fn test_registered_file_events() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let [rfd, wfd] = fds;
+        let data = std::ptr::null_mut();
+        assert_eq!(ae_registered_file_events(&event_loop), 0);
+
+        ae_create_file_event(&mut event_loop, rfd, AE_READABLE, noop_file, data);
+        ae_create_file_event(&mut event_loop, wfd, AE_WRITABLE, noop_file, data);
+        ae_create_file_event(&mut event_loop, wfd, AE_READABLE, noop_file, data);
+        assert_eq!(ae_registered_file_events(&event_loop), 2);
+
+        ae_delete_file_event(&mut event_loop, wfd, AE_WRITABLE);
+        assert_eq!(ae_registered_file_events(&event_loop), 2);
+        ae_delete_file_event(&mut event_loop, wfd, AE_READABLE);
+        assert_eq!(ae_registered_file_events(&event_loop), 1);
+        /* Not registered anymore. */
+        ae_delete_file_event(&mut event_loop, wfd, AE_READABLE);
+        assert_eq!(ae_registered_file_events(&event_loop), 1);
+        ae_delete_file_event(&mut event_loop, rfd, AE_READABLE | AE_WRITABLE);
+        assert_eq!(ae_registered_file_events(&event_loop), 0);
+
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod compaction {
+    use super::*;
+    use std::cell::Cell;
+
+    thread_local! {
+        static RELOCATED: Cell<Option<(i32, i32)>> = const { Cell::new(None) };
+    }
+
+    fn record_relocation(
+        _event_loop: &mut AeEventLoop,
+        old_fd: i32,
+        new_fd: i32,
+        client_data: *mut std::ffi::c_void,
+    ) {
+        assert_eq!(unsafe { *(client_data as *const i32) }, 42);
+        RELOCATED.with(|relocated| relocated.set(Some((old_fd, new_fd))));
+    }
+
+    fn read_ready(
+        _event_loop: &mut AeEventLoop,
+        fd: i32,
+        _client_data: *mut std::ffi::c_void,
+        _mask: i32,
+    ) {
+        let mut buf = [0u8; 16];
+        unsafe { libc::read(fd, buf.as_mut_ptr() as *mut std::ffi::c_void, buf.len()) };
+        RELOCATED.with(|relocated| relocated.set(Some((fd, fd))));
+    }
+
+    /* A pipe whose read end is moved up to `high`. */
+    fn high_pipe(high: i32) -> (i32, i32) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let rfd = unsafe { libc::fcntl(fds[0], libc::F_DUPFD, high) };
+        assert!(rfd >= high);
+        unsafe { libc::close(fds[0]) };
+        (rfd, fds[1])
+    }
+
+    #[test]
+    fn test_high_fd_is_moved_down() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (rfd, wfd) = high_pipe(900);
+        let mut tag = 42;
+        let data = &mut tag as *mut i32 as *mut std::ffi::c_void;
+        ae_create_file_event(&mut event_loop, rfd, AE_READABLE, read_ready, data);
+
+        /* The plain resize refuses. */
+        assert_eq!(ae_resize_set_size(&mut event_loop, 256), AE_ERR);
+        assert_eq!(
+            ae_resize_set_size_compact(&mut event_loop, 256, record_relocation),
+            AE_OK
+        );
+        assert_eq!(ae_get_set_size(&event_loop), 256);
+
+        let (old_fd, new_fd) = RELOCATED.with(Cell::take).expect("not relocated");
+        assert_eq!(old_fd, rfd);
+        assert!(new_fd < 256);
+        assert_eq!(unsafe { libc::fcntl(rfd, libc::F_GETFD) }, -1);
+        assert_eq!(ae_get_file_events(&event_loop, new_fd), AE_READABLE);
+        assert_eq!(ae_get_file_client_data(&event_loop, new_fd), data);
+        assert_eq!(ae_registered_file_events(&event_loop), 1);
+
+        /* Events keep coming, on the new fd. */
+        assert_eq!(unsafe { libc::write(wfd, b"x".as_ptr() as *const _, 1) }, 1);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(RELOCATED.with(Cell::take), Some((new_fd, new_fd)));
+
+        ae_delete_file_event(&mut event_loop, new_fd, AE_READABLE);
+        unsafe {
+            libc::close(new_fd);
+            libc::close(wfd);
+        }
+        ae_delete_event_loop(event_loop);
+    }
+
+    /* Every handler moves, the error queue one included. */
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_error_queue_handler_is_moved() {
+        use rae::{AE_ERRQUEUE, AeEventLoopBuilder};
+
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .best_backend()
+            .build()
+            .expect("Failed to create event loop");
+        if event_loop.api_name() != "epoll" {
+            return;
+        }
+        let (rfd, wfd) = high_pipe(900);
+        let mut tag = 42;
+        let data = &mut tag as *mut i32 as *mut std::ffi::c_void;
+        for mask in [AE_READABLE, AE_ERRQUEUE] {
+            ae_create_file_event(&mut event_loop, rfd, mask, read_ready, data);
+        }
+
+        assert_eq!(
+            ae_resize_set_size_compact(&mut event_loop, 256, record_relocation),
+            AE_OK
+        );
+        let (old_fd, new_fd) = RELOCATED.with(Cell::take).expect("not relocated");
+        assert_eq!(old_fd, rfd);
+        assert_eq!(
+            ae_get_file_events(&event_loop, new_fd),
+            AE_READABLE | AE_ERRQUEUE
+        );
+        assert_eq!(ae_registered_file_events(&event_loop), 1);
+
+        ae_delete_file_event(&mut event_loop, new_fd, AE_READABLE | AE_ERRQUEUE);
+        assert_eq!(ae_registered_file_events(&event_loop), 0);
+        unsafe {
+            libc::close(new_fd);
+            libc::close(wfd);
+        }
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_no_room_below_the_new_size() {
+        /* fd 0 is the only slot of a set size of 1. */
+        if unsafe { libc::fcntl(0, libc::F_GETFD) } == -1 {
+            return;
+        }
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (rfd, wfd) = high_pipe(800);
+        let data = std::ptr::null_mut();
+        ae_create_file_event(&mut event_loop, rfd, AE_READABLE, read_ready, data);
+
+        assert_eq!(
+            ae_resize_set_size_compact(&mut event_loop, 1, record_relocation),
+            AE_ERR
+        );
+        assert_eq!(ae_get_set_size(&event_loop), 1024);
+        assert_eq!(ae_get_file_events(&event_loop, rfd), AE_READABLE);
+
+        ae_delete_file_event(&mut event_loop, rfd, AE_READABLE);
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
         ae_delete_event_loop(event_loop);
     }
 }
@@ -187,7 +508,7 @@ mod platform_specific {
     #[test]
     fn test_backend_consistency() {
         let event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
-        let api_name = ae_get_api_name();
+        let api_name = event_loop.api_name();
 
         // Ensure backend is consistently reported
         assert!(!api_name.is_empty(), "API name should not be empty");
@@ -204,20 +525,252 @@ mod platform_specific {
     ))]
     #[test]
     fn test_kqueue_available() {
-        let api_name = ae_get_api_name();
+        let event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let api_name = event_loop.api_name();
         // On BSD systems, we should prefer kqueue over select
         // Note: This test might need adjustment based on actual priority logic
         assert!(
             api_name == "kqueue" || api_name == "select",
             "On BSD systems, should use kqueue or select"
         );
+        ae_delete_event_loop(event_loop);
     }
 
     #[cfg(target_os = "linux")]
     #[test]
     fn test_select_on_linux() {
-        let api_name = ae_get_api_name();
-        // On Linux, we currently only have select (epoll not implemented yet)
-        assert_eq!(api_name, "select", "On Linux, should use select backend");
+        let event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        // On Linux, ae_create_event_loop() defaults to select
+        assert_eq!(
+            event_loop.api_name(),
+            "select",
+            "On Linux, should use select backend"
+        );
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod timers_only {
+    use super::*;
+    use rae::{ae_get_handle, ae_main};
+
+    fn stop_after_three(
+        event_loop: &mut AeEventLoop,
+        _id: i64,
+        client_data: *mut std::ffi::c_void,
+    ) -> i32 {
+        let count = unsafe { &mut *(client_data as *mut i32) };
+        *count += 1;
+        if *count == 3 {
+            ae_stop(event_loop);
+            return AE_NOMORE;
+        }
+        5
+    }
+
+    fn noop_file(
+        _event_loop: &mut AeEventLoop,
+        _fd: i32,
+        _client_data: *mut std::ffi::c_void,
+        _mask: i32,
+    ) {
+    }
+
+    #[test]
+    fn test_file_registration_is_refused() {
+        let mut event_loop = ae_create_event_loop(0).expect("Failed to create event loop");
+        let (rfd, wfd) = rae::anet::anet_pipe(true).expect("Failed to create pipe");
+        let result = ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            noop_file,
+            std::ptr::null_mut(),
+        );
+        assert_eq!(result, AE_ERR);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ERANGE)
+        );
+        assert_eq!(ae_registered_file_events(&event_loop), 0);
+        assert!(ae_get_handle(&mut event_loop).is_none());
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_poll_sleeps_until_the_timer() {
+        let mut event_loop = ae_create_event_loop(0).expect("Failed to create event loop");
+        ae_create_time_event(&mut event_loop, 20, noop_timer, std::ptr::null_mut(), None);
+        let start = Instant::now();
+        let processed = ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        assert_eq!(processed, 1);
+        assert!(start.elapsed() >= Duration::from_millis(19));
+        assert_eq!(ae_pending_time_events(&event_loop), 0);
+
+        /* Nothing to wait for without blocking. */
+        assert_eq!(
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT),
+            0
+        );
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_main_runs_timers() {
+        let mut event_loop = ae_create_event_loop(0).expect("Failed to create event loop");
+        let mut count = 0;
+        ae_create_time_event(
+            &mut event_loop,
+            5,
+            stop_after_three,
+            &mut count as *mut i32 as *mut std::ffi::c_void,
+            None,
+        );
+        let start = Instant::now();
+        ae_main(&mut event_loop);
+        assert_eq!(count, 3);
+        assert!(start.elapsed() >= Duration::from_millis(14));
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod max_poll_timeout {
+    use super::*;
+    use rae::test_util::MockBackend;
+    use rae::{AeClockSource, AeEventLoopBuilder};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn capped_loop(max: Duration) -> (Box<AeEventLoop>, rae::test_util::MockControl) {
+        let (backend, control) = MockBackend::new();
+        let event_loop = AeEventLoopBuilder::new(64)
+            .backend(backend)
+            .clock_source(AeClockSource::Manual)
+            .max_poll_timeout(max)
+            .build()
+            .expect("Failed to create event loop");
+        (event_loop, control)
+    }
+
+    fn idle(_: &mut AeEventLoop, _: i32, _: *mut std::ffi::c_void, _: i32) {}
+
+    #[test]
+    fn test_caps_the_wait() {
+        let (mut event_loop, control) = capped_loop(Duration::from_millis(50));
+        ae_create_file_event(&mut event_loop, 5, AE_READABLE, idle, std::ptr::null_mut());
+
+        /* No timer: an infinite wait, capped. */
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        assert_eq!(
+            control.last_timeout(),
+            Some(Some(Duration::from_millis(50)))
+        );
+
+        /* A timer further away: capped too. */
+        let id = ae_create_time_event(
+            &mut event_loop,
+            10_000,
+            noop_timer,
+            std::ptr::null_mut(),
+            None,
+        );
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        assert_eq!(
+            control.last_timeout(),
+            Some(Some(Duration::from_millis(50)))
+        );
+        ae_delete_time_event(&mut event_loop, id);
+
+        /* A timer due sooner wins. */
+        ae_create_time_event(&mut event_loop, 10, noop_timer, std::ptr::null_mut(), None);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        assert_eq!(
+            control.last_timeout(),
+            Some(Some(Duration::from_millis(10)))
+        );
+    }
+
+    #[test]
+    fn test_unbounded_by_default() {
+        let (mut event_loop, control) = virtual_loop(64);
+        ae_create_file_event(&mut event_loop, 5, AE_READABLE, idle, std::ptr::null_mut());
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        assert_eq!(control.last_timeout(), Some(None));
+    }
+
+    static EXTERNAL_FLAG: AtomicBool = AtomicBool::new(false);
+
+    fn check_flag(event_loop: &mut AeEventLoop) {
+        if EXTERNAL_FLAG.load(Ordering::Acquire) {
+            ae_stop(event_loop);
+        }
+    }
+
+    /* Nothing registered would wake the loop up: only the ceiling brings
+     * it back to beforesleep, where it sees the flag. */
+    #[test]
+    fn test_beforesleep_sees_external_flag() {
+        let mut event_loop = AeEventLoopBuilder::new(64)
+            .max_poll_timeout(Duration::from_millis(5))
+            .build()
+            .expect("Failed to create event loop");
+        let (rfd, wfd) = rae::anet::anet_pipe(true).expect("Failed to create pipe");
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            idle,
+            std::ptr::null_mut(),
+        );
+        ae_set_before_sleep_proc(&mut event_loop, Some(check_flag));
+
+        let setter = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            EXTERNAL_FLAG.store(true, Ordering::Release);
+        });
+        let start = Instant::now();
+        rae::ae_main(&mut event_loop);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        setter.join().unwrap();
+
+        ae_delete_file_event(&mut event_loop, rfd, AE_READABLE);
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+}
+
+mod wait {
+    use super::*;
+    use rae::{ae_wait, ae_wait_duration};
+    use std::io::Write;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_wait_duration() {
+        let (ours, mut theirs) = UnixStream::pair().expect("Failed to create socket pair");
+        let fd = ours.as_raw_fd();
+        assert_eq!(ae_wait_duration(fd, AE_WRITABLE, None), AE_WRITABLE);
+
+        /* A sub-millisecond timeout still waits rather than polling. */
+        let start = Instant::now();
+        assert_eq!(
+            ae_wait_duration(fd, AE_READABLE, Some(Duration::from_micros(100))),
+            0
+        );
+        assert!(start.elapsed() >= Duration::from_micros(100));
+        assert_eq!(ae_wait(fd, AE_READABLE, 0), 0);
+
+        theirs.write_all(b"x").unwrap();
+        assert_eq!(
+            ae_wait_duration(fd, AE_READABLE, Some(Duration::from_secs(5))),
+            AE_READABLE
+        );
+        assert_eq!(ae_wait(fd, AE_READABLE, -1), AE_READABLE);
     }
 }
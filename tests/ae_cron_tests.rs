@@ -0,0 +1,171 @@
+/* Cron Scheduler Tests
+ *
+ * Tests for crontab expression parsing and next-match computation
+ * (ae/cron.rs), and for registering cron jobs on the loop. Matches are
+ * checked against the local time of the returned instant, so the tests
+ * hold in any time zone.
+ */
+
+use rae::{
+    AE_ERR, AE_NOMORE, AE_OK, AeCronExpr, AeEventLoop, ae_create_cron_event, ae_create_event_loop,
+    ae_delete_wallclock_event,
+};
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn local(t: SystemTime) -> libc::tm {
+    let secs = t.duration_since(UNIX_EPOCH).unwrap().as_secs() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&secs, &mut tm) };
+    tm
+}
+
+fn next(expr: &str) -> SystemTime {
+    AeCronExpr::parse(expr)
+        .expect("valid expression")
+        .next_after(SystemTime::now())
+        .expect("expression should match")
+}
+
+mod parsing {
+    use super::*;
+
+    #[test]
+    fn test_valid_expressions() {
+        for expr in [
+            "* * * * *",
+            "*/5 * * * *",
+            "0 0 * * 0",
+            "0 0 * * 7",
+            "15,45 9-17 * * 1-5",
+            "0-30/10 */6 1,15 1-12/3 *",
+            "5/15 * * * *",
+        ] {
+            assert!(AeCronExpr::parse(expr).is_some(), "{expr} should parse");
+        }
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        for expr in [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+            "1,,2 * * * *",
+        ] {
+            assert!(
+                AeCronExpr::parse(expr).is_none(),
+                "{expr:?} should not parse"
+            );
+        }
+    }
+
+    #[test]
+    fn test_sunday_aliases() {
+        assert_eq!(
+            AeCronExpr::parse("0 0 * * 0"),
+            AeCronExpr::parse("0 0 * * 7")
+        );
+    }
+}
+
+mod next_match {
+    use super::*;
+
+    #[test]
+    fn test_every_minute() {
+        let now = SystemTime::now();
+        let t = AeCronExpr::parse("* * * * *")
+            .unwrap()
+            .next_after(now)
+            .unwrap();
+        assert!(t > now);
+        assert!(t <= now + Duration::from_secs(60));
+        assert_eq!(local(t).tm_sec, 0);
+    }
+
+    #[test]
+    fn test_fixed_time_of_day() {
+        let t = next("30 4 * * *");
+        let tm = local(t);
+        assert_eq!((tm.tm_hour, tm.tm_min, tm.tm_sec), (4, 30, 0));
+        assert!(t <= SystemTime::now() + Duration::from_secs(25 * 3600));
+    }
+
+    #[test]
+    fn test_steps_and_lists() {
+        let tm = local(next("*/20 1,13 * * *"));
+        assert!([0, 20, 40].contains(&tm.tm_min));
+        assert!([1, 13].contains(&tm.tm_hour));
+    }
+
+    #[test]
+    fn test_day_of_month_or_day_of_week() {
+        for _ in 0..3 {
+            let tm = local(next("0 12 13 * 5"));
+            assert!(tm.tm_mday == 13 || tm.tm_wday == 5);
+        }
+        let tm = local(next("0 12 * * 2"));
+        assert_eq!(tm.tm_wday, 2, "Unrestricted day of month does not widen");
+    }
+
+    #[test]
+    fn test_leap_day() {
+        let tm = local(next("0 0 29 2 *"));
+        assert_eq!((tm.tm_mon, tm.tm_mday), (1, 29));
+    }
+
+    #[test]
+    fn test_never_matching() {
+        let expr = AeCronExpr::parse("0 0 30 2 *").unwrap();
+        assert_eq!(expr.next_after(SystemTime::now()), None);
+    }
+}
+
+mod loop_integration {
+    use super::*;
+
+    fn job(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+        AE_NOMORE
+    }
+
+    fn count_finalizer(_event_loop: &mut AeEventLoop, client_data: *mut c_void) {
+        let counter = unsafe { &*(client_data as *const AtomicI32) };
+        counter.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_create_and_delete() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let counter = AtomicI32::new(0);
+
+        let id = ae_create_cron_event(
+            &mut event_loop,
+            "0 3 * * *",
+            job,
+            &counter as *const AtomicI32 as *mut c_void,
+            Some(count_finalizer),
+        );
+        assert!(id > 0);
+        assert_eq!(ae_delete_wallclock_event(&mut event_loop, id), AE_OK);
+        assert_eq!(counter.load(Ordering::SeqCst), 1, "User finalizer ran");
+    }
+
+    #[test]
+    fn test_rejects_bad_expressions() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        for expr in ["not a cron", "0 0 30 2 *"] {
+            let id = ae_create_cron_event(&mut event_loop, expr, job, std::ptr::null_mut(), None);
+            assert_eq!(id, AE_ERR as i64, "{expr:?}");
+        }
+    }
+}
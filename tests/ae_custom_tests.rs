@@ -0,0 +1,102 @@
+/* Custom Event Tests
+ *
+ * Tests for user defined event sources (ae/custom.rs): events triggered by
+ * id from any thread, coalesced, and dispatched on the loop thread.
+ */
+
+use rae::test_util::run_until;
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_ERR, AE_OK, AeCustomEventId, AeDispatchSource, AeEventLoop,
+    ae_create_custom_event, ae_create_event_loop, ae_delete_custom_event, ae_delete_event_loop,
+    ae_dispatch_stack, ae_get_handle, ae_process_events, ae_trigger_custom_event,
+};
+use std::ffi::c_void;
+use std::time::Duration;
+
+/* Calls of the proc and the triggers it was told about. */
+#[derive(Default)]
+struct Seen {
+    calls: u64,
+    triggers: u64,
+    source: Option<AeDispatchSource>,
+}
+
+fn count(event_loop: &mut AeEventLoop, _id: AeCustomEventId, client_data: *mut c_void, n: u64) {
+    let seen = unsafe { &mut *(client_data as *mut Seen) };
+    seen.calls += 1;
+    seen.triggers += n;
+    seen.source = ae_dispatch_stack(event_loop)
+        .last()
+        .map(|record| record.source);
+}
+
+fn seen_ptr(seen: &mut Seen) -> *mut c_void {
+    seen as *mut Seen as *mut c_void
+}
+
+mod custom {
+    use super::*;
+
+    #[test]
+    fn test_triggered_from_threads() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let mut seen = Seen::default();
+        let id = ae_create_custom_event(&mut event_loop, count, seen_ptr(&mut seen)).unwrap();
+        let handle = ae_get_handle(&mut event_loop).unwrap();
+
+        let threads: Vec<_> = (0..3)
+            .map(|_| {
+                let handle = handle.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10 {
+                        assert_eq!(handle.trigger(id), AE_OK);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let seen_ptr = &seen as *const Seen;
+        assert!(run_until(&mut event_loop, Duration::from_secs(5), |_| {
+            unsafe { (*seen_ptr).triggers == 30 }
+        }));
+        assert!(seen.calls >= 1 && seen.calls <= 30);
+        assert_eq!(seen.source, Some(AeDispatchSource::Custom { id }));
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_triggers_coalesce() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let mut seen = Seen::default();
+        let id = ae_create_custom_event(&mut event_loop, count, seen_ptr(&mut seen)).unwrap();
+        for _ in 0..3 {
+            assert_eq!(ae_trigger_custom_event(&mut event_loop, id), AE_OK);
+        }
+        assert_eq!(seen.calls, 0, "Dispatched by the loop, not by the trigger");
+
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!((seen.calls, seen.triggers), (1, 3));
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_deleted_events() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let mut seen = Seen::default();
+        let id = ae_create_custom_event(&mut event_loop, count, seen_ptr(&mut seen)).unwrap();
+        let handle = ae_get_handle(&mut event_loop).unwrap();
+
+        assert_eq!(handle.trigger(id), AE_OK);
+        assert_eq!(ae_delete_custom_event(&mut event_loop, id), AE_OK);
+        assert_eq!(ae_delete_custom_event(&mut event_loop, id), AE_ERR);
+        assert_eq!(ae_trigger_custom_event(&mut event_loop, id), AE_ERR);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(seen.calls, 0, "Pending triggers are dropped");
+
+        ae_delete_event_loop(event_loop);
+        assert_eq!(handle.trigger(id), AE_ERR);
+    }
+}
@@ -0,0 +1,125 @@
+/* Demo Server Tests
+ *
+ * Runs the rae-demo binary (src/bin/rae-demo.rs) and talks to it over
+ * TCP, which exercises the listener, buffered connection, framing,
+ * timer and signal APIs together from the outside. Only built with the
+ * `demo` feature.
+ */
+
+#![cfg(feature = "demo")]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+/* A running rae-demo, stopped with SIGTERM when dropped. */
+struct Demo {
+    child: Child,
+    addr: String,
+}
+
+impl Demo {
+    fn start(mode: &str) -> Demo {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_rae-demo"))
+            .args([mode, "127.0.0.1:0"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start rae-demo");
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .expect("Failed to read address");
+        let addr = line
+            .trim()
+            .strip_prefix("listening on ")
+            .unwrap_or_else(|| panic!("unexpected output {line:?}"))
+            .to_string();
+        Demo { child, addr }
+    }
+
+    fn connect(&self) -> BufReader<TcpStream> {
+        let stream = TcpStream::connect(&self.addr).expect("Failed to connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        BufReader::new(stream)
+    }
+
+    /* SIGTERM, then the exit status. */
+    fn stop(mut self) -> bool {
+        unsafe { libc::kill(self.child.id() as i32, libc::SIGTERM) };
+        self.child.wait().unwrap().success()
+    }
+}
+
+impl Drop for Demo {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn read_line(client: &mut BufReader<TcpStream>) -> String {
+    let mut line = String::new();
+    client.read_line(&mut line).expect("Failed to read");
+    line.trim_end().to_string()
+}
+
+fn send(client: &mut BufReader<TcpStream>, data: &str) {
+    client.get_mut().write_all(data.as_bytes()).unwrap();
+}
+
+mod echo {
+    use super::*;
+
+    #[test]
+    fn test_echoes_bytes() {
+        let demo = Demo::start("echo");
+        let mut client = demo.connect();
+
+        let data: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+        let writer = {
+            let mut stream = client.get_ref().try_clone().unwrap();
+            let data = data.clone();
+            std::thread::spawn(move || stream.write_all(&data).unwrap())
+        };
+        let mut echoed = vec![0; data.len()];
+        client.read_exact(&mut echoed).unwrap();
+        writer.join().unwrap();
+        assert_eq!(echoed, data);
+
+        assert!(demo.stop(), "rae-demo did not exit cleanly");
+    }
+}
+
+mod chat {
+    use super::*;
+
+    #[test]
+    fn test_chat_room() {
+        let demo = Demo::start("chat");
+        let mut alice = demo.connect();
+        assert!(read_line(&mut alice).starts_with("* you are user"));
+        send(&mut alice, "/nick alice\n");
+        assert!(read_line(&mut alice).ends_with(" is now alice"));
+
+        let mut bob = demo.connect();
+        let bob_name = read_line(&mut bob)
+            .strip_prefix("* you are ")
+            .unwrap()
+            .to_string();
+        assert_eq!(read_line(&mut alice), format!("* {bob_name} joined"));
+
+        send(&mut alice, "hello\r\n");
+        assert_eq!(read_line(&mut bob), "<alice> hello");
+        send(&mut bob, "/nick\n/quit\n");
+        assert_eq!(read_line(&mut bob), "* usage: /nick <name>");
+        assert_eq!(read_line(&mut bob), "* bye");
+        assert_eq!(read_line(&mut bob), "", "expected end of file");
+        assert_eq!(read_line(&mut alice), format!("* {bob_name} left"));
+
+        assert!(demo.stop(), "rae-demo did not exit cleanly");
+    }
+}
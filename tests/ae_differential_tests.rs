@@ -0,0 +1,206 @@
+/* Differential Backend Tests
+ *
+ * Tests for AeEventLoopBuilder::differential() (ae/differential.rs): the
+ * loop runs select and the native backend side by side, and both must
+ * agree on the readiness of pipes and sockets going through the usual
+ * states. Loops stay below FD_SETSIZE, past which select gives up.
+ */
+
+use rae::anet::anet_pipe;
+use rae::test_util::{close_pipe, drain, trigger_readable};
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_READABLE, AE_WRITABLE, AeEventLoop, AeEventLoopBuilder,
+    ae_backend_divergences, ae_create_event_loop, ae_create_file_event, ae_delete_file_event,
+    ae_differential_secondary, ae_doctor, ae_get_stats, ae_pause, ae_process_events, ae_resume,
+};
+use std::ffi::c_void;
+
+fn count_fired(_event_loop: &mut AeEventLoop, _fd: i32, client_data: *mut c_void, _mask: i32) {
+    unsafe { *(client_data as *mut i32) += 1 };
+}
+
+/* Take a pipe through readable, drained and writable states. */
+fn exercise(event_loop: &mut AeEventLoop) -> (i32, i32) {
+    let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+    let mut reads = 0;
+    let mut writes = 0;
+    ae_create_file_event(
+        event_loop,
+        rfd,
+        AE_READABLE,
+        count_fired,
+        &mut reads as *mut i32 as *mut c_void,
+    );
+    ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+    assert_eq!(reads, 0);
+
+    trigger_readable(wfd);
+    ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+    assert_eq!(reads, 1);
+    drain(rfd);
+    ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+    assert_eq!(reads, 1);
+
+    ae_create_file_event(
+        event_loop,
+        wfd,
+        AE_WRITABLE,
+        count_fired,
+        &mut writes as *mut i32 as *mut c_void,
+    );
+    trigger_readable(wfd);
+    ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+    assert_eq!((reads, writes), (2, 1));
+
+    ae_delete_file_event(event_loop, wfd, AE_WRITABLE);
+    ae_delete_file_event(event_loop, rfd, AE_READABLE);
+    (rfd, wfd)
+}
+
+mod agreement {
+    use super::*;
+
+    #[test]
+    fn test_select_primary_agrees() {
+        let mut event_loop = AeEventLoopBuilder::new(256)
+            .differential()
+            .build()
+            .expect("Failed to create event loop");
+        assert_eq!(event_loop.api_name(), "select");
+        let secondary = ae_differential_secondary(&event_loop).expect("No secondary backend");
+        #[cfg(target_os = "linux")]
+        assert_eq!(secondary, "epoll");
+        #[cfg(not(target_os = "linux"))]
+        let _ = secondary;
+
+        let (rfd, wfd) = exercise(&mut event_loop);
+        assert_eq!(ae_backend_divergences(&event_loop), vec![]);
+        assert_eq!(ae_get_stats(&event_loop).backend_divergences, 0);
+        close_pipe(rfd, wfd);
+    }
+
+    #[test]
+    fn test_native_primary_agrees() {
+        let mut event_loop = AeEventLoopBuilder::new(256)
+            .best_backend()
+            .differential()
+            .build()
+            .expect("Failed to create event loop");
+        if event_loop.api_name() == "select" {
+            /* No native backend on this kernel: paired with itself. */
+            return;
+        }
+        assert_eq!(ae_differential_secondary(&event_loop), Some("select"));
+
+        let (rfd, wfd) = exercise(&mut event_loop);
+        assert_eq!(ae_backend_divergences(&event_loop), vec![]);
+        assert_eq!(ae_get_stats(&event_loop).backend_divergences, 0);
+        assert!(ae_doctor(&event_loop).is_empty());
+        close_pipe(rfd, wfd);
+    }
+
+    #[test]
+    fn test_agrees_across_pause() {
+        let mut event_loop = AeEventLoopBuilder::new(256)
+            .differential()
+            .build()
+            .expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        let mut reads = 0;
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            count_fired,
+            &mut reads as *mut i32 as *mut c_void,
+        );
+        trigger_readable(wfd);
+
+        ae_pause(&mut event_loop);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(reads, 0);
+        ae_resume(&mut event_loop);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(reads, 1);
+        assert_eq!(ae_backend_divergences(&event_loop), vec![]);
+
+        ae_delete_file_event(&mut event_loop, rfd, AE_READABLE);
+        close_pipe(rfd, wfd);
+    }
+}
+
+mod divergence {
+    use super::*;
+    use rae::{AeDivergenceKind, AeFindingKind};
+
+    /* Select does not watch fds from FD_SETSIZE up, the native backend
+     * does: a readable pipe moved there is missed by select. */
+    #[test]
+    fn test_reports_fd_past_select() {
+        let mut event_loop = AeEventLoopBuilder::new(2048)
+            .best_backend()
+            .differential()
+            .build()
+            .expect("Failed to create event loop");
+        if event_loop.api_name() == "select" {
+            return;
+        }
+        let target = 1100;
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) };
+        if limit.rlim_max <= target as libc::rlim_t {
+            return;
+        }
+        if limit.rlim_cur <= target as libc::rlim_t {
+            limit.rlim_cur = limit.rlim_max.min(4096);
+            unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) };
+        }
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        assert_eq!(unsafe { libc::dup2(rfd, target) }, target);
+
+        let mut reads = 0;
+        ae_create_file_event(
+            &mut event_loop,
+            target,
+            AE_READABLE,
+            count_fired,
+            &mut reads as *mut i32 as *mut c_void,
+        );
+        trigger_readable(wfd);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(reads, 1);
+
+        let divergences = ae_backend_divergences(&event_loop);
+        assert_eq!(divergences.len(), 1);
+        assert_eq!(divergences[0].kind, AeDivergenceKind::Missed);
+        assert_eq!(divergences[0].fd, target);
+        assert_eq!(divergences[0].primary_mask, AE_READABLE);
+        assert_eq!(divergences[0].secondary_mask, 0);
+        assert_eq!(ae_get_stats(&event_loop).backend_divergences, 1);
+        assert!(
+            ae_doctor(&event_loop)
+                .iter()
+                .any(|f| matches!(f.kind, AeFindingKind::BackendDivergence { total: 1, .. }))
+        );
+
+        ae_delete_file_event(&mut event_loop, target, AE_READABLE);
+        unsafe { libc::close(target) };
+        close_pipe(rfd, wfd);
+    }
+}
+
+mod disabled {
+    use super::*;
+
+    #[test]
+    fn test_plain_loop_has_no_secondary() {
+        let mut event_loop = ae_create_event_loop(256).expect("Failed to create event loop");
+        assert_eq!(ae_differential_secondary(&event_loop), None);
+        let (rfd, wfd) = exercise(&mut event_loop);
+        assert_eq!(ae_backend_divergences(&event_loop), vec![]);
+        close_pipe(rfd, wfd);
+    }
+}
@@ -0,0 +1,292 @@
+/* Dispatch Stress Tests
+ *
+ * Callbacks that delete, close, reopen and resize while the loop is
+ * dispatching, over real Unix socket pairs and on every backend built for
+ * the platform. Each registration carries an id in its client data, and a
+ * handler called with an id that is no longer the one registered for its
+ * fd fails the test: a deleted or replaced registration must never see an
+ * event that fired for the previous one.
+ *
+ * They open a few hundred sockets and take a few seconds, so they only
+ * run with the stress-tests feature, as CI does:
+ *
+ *     cargo test --features stress-tests --test ae_dispatch_stress_tests
+ */
+
+#![cfg(feature = "stress-tests")]
+
+use rae::test_util::drain;
+use rae::{
+    AE_DONT_WAIT, AE_ERR, AE_FILE_EVENTS, AE_OK, AE_READABLE, AeEventLoop, ae_create_event_loop,
+    ae_create_event_loop_with_backend, ae_create_file_event, ae_delete_event_loop,
+    ae_delete_file_event, ae_get_set_size, ae_process_events, ae_registered_file_events,
+    ae_resize_set_size,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixStream;
+
+const SETSIZE: i32 = 512;
+
+/* What the handlers do when called, see run(). */
+#[derive(Clone, Copy, PartialEq)]
+enum Scenario {
+    DeleteOthers,
+    ReopenOthers,
+    Resize,
+    Random,
+}
+
+struct Harness {
+    scenario: Scenario,
+    /* Our end of each pair to the registration id and the peer end. */
+    conns: HashMap<i32, (usize, i32)>,
+    next_id: usize,
+    calls: usize,
+    seed: u64,
+}
+
+thread_local! {
+    static HARNESS: RefCell<Harness> = RefCell::new(Harness {
+        scenario: Scenario::Random,
+        conns: HashMap::new(),
+        next_id: 1,
+        calls: 0,
+        seed: 0x9e37_79b9_7f4a_7c15,
+    });
+}
+
+fn random(below: usize) -> usize {
+    HARNESS.with_borrow_mut(|h| {
+        h.seed ^= h.seed << 13;
+        h.seed ^= h.seed >> 7;
+        h.seed ^= h.seed << 17;
+        (h.seed % below as u64) as usize
+    })
+}
+
+/* Open a pair, register our end and make it readable. */
+fn open(event_loop: &mut AeEventLoop) -> i32 {
+    let (ours, theirs) = UnixStream::pair().expect("Failed to create socket pair");
+    ours.set_nonblocking(true).unwrap();
+    let (fd, peer) = (ours.into_raw_fd(), theirs.into_raw_fd());
+    let id = HARNESS.with_borrow_mut(|h| {
+        h.next_id += 1;
+        h.conns.insert(fd, (h.next_id, peer));
+        h.next_id
+    });
+    let result = ae_create_file_event(event_loop, fd, AE_READABLE, handler, id as *mut c_void);
+    assert_eq!(result, AE_OK);
+    poke(fd);
+    fd
+}
+
+/* Make `fd` readable. */
+fn poke(fd: i32) {
+    if let Some(peer) = HARNESS.with_borrow(|h| h.conns.get(&fd).map(|conn| conn.1)) {
+        unsafe { libc::write(peer, b"x".as_ptr() as *const c_void, 1) };
+    }
+}
+
+fn close(event_loop: &mut AeEventLoop, fd: i32) {
+    ae_delete_file_event(event_loop, fd, AE_READABLE);
+    if let Some((_, peer)) = HARNESS.with_borrow_mut(|h| h.conns.remove(&fd)) {
+        unsafe {
+            libc::close(fd);
+            libc::close(peer);
+        }
+    }
+}
+
+fn other_fds(fd: i32) -> Vec<i32> {
+    let mut fds: Vec<i32> = HARNESS.with_borrow(|h| h.conns.keys().copied().collect());
+    fds.retain(|&other| other != fd);
+    fds.sort();
+    fds
+}
+
+fn handler(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let (scenario, registered, first) = HARNESS.with_borrow_mut(|h| {
+        h.calls += 1;
+        (
+            h.scenario,
+            h.conns.get(&fd).map(|conn| conn.0),
+            h.calls == 1,
+        )
+    });
+    assert_eq!(
+        registered,
+        Some(client_data as usize),
+        "fd {fd} dispatched to a stale registration"
+    );
+    drain(fd);
+
+    match scenario {
+        Scenario::DeleteOthers if first => {
+            for other in other_fds(fd) {
+                close(event_loop, other);
+            }
+        }
+        Scenario::ReopenOthers if first => {
+            let count = other_fds(fd).len();
+            for other in other_fds(fd) {
+                close(event_loop, other);
+            }
+            /* The new pairs take the fd numbers just released. */
+            for _ in 0..count {
+                open(event_loop);
+            }
+        }
+        Scenario::Resize => {
+            let setsize = ae_get_set_size(event_loop);
+            assert_eq!(ae_resize_set_size(event_loop, setsize + 16), AE_OK);
+        }
+        Scenario::Random => match random(6) {
+            0 => close(event_loop, fd),
+            1 => {
+                let others = other_fds(fd);
+                if !others.is_empty() {
+                    close(event_loop, others[random(others.len())]);
+                }
+            }
+            2 => {
+                close(event_loop, fd);
+                open(event_loop);
+            }
+            3 => {
+                for other in other_fds(fd).into_iter().take(4) {
+                    poke(other);
+                }
+            }
+            4 => {
+                /* Shrinking below a registered fd fails and changes
+                 * nothing. */
+                let setsize = ae_get_set_size(event_loop);
+                let result = ae_resize_set_size(event_loop, fd);
+                assert_eq!(result, AE_ERR);
+                assert_eq!(ae_get_set_size(event_loop), setsize);
+            }
+            _ => poke(fd),
+        },
+        _ => {}
+    }
+}
+
+/* Every backend built for this platform, select first. */
+fn event_loops() -> Vec<(&'static str, Box<AeEventLoop>)> {
+    #[allow(unused_mut)]
+    let mut loops = vec![(
+        "select",
+        ae_create_event_loop(SETSIZE).expect("Failed to create event loop"),
+    )];
+    #[cfg(target_os = "linux")]
+    {
+        use rae::ae_epoll::aeApiState;
+        use rae::traits::EventBackend;
+        let backend = aeApiState::create().expect("Failed to create epoll state");
+        loops.push((
+            "epoll",
+            ae_create_event_loop_with_backend(SETSIZE, backend).expect("Failed to create loop"),
+        ));
+    }
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    ))]
+    {
+        use rae::ae_kqueue::aeApiState;
+        use rae::traits::EventBackend;
+        let backend = aeApiState::create().expect("Failed to create kqueue state");
+        loops.push((
+            "kqueue",
+            ae_create_event_loop_with_backend(SETSIZE, backend).expect("Failed to create loop"),
+        ));
+    }
+    loops
+}
+
+/* Run `scenario` on `pairs` readable pairs. Returns the handler calls of
+ * the first iteration. */
+fn run(event_loop: &mut AeEventLoop, scenario: Scenario, pairs: usize) -> usize {
+    HARNESS.with_borrow_mut(|h| {
+        h.scenario = scenario;
+        h.calls = 0;
+    });
+    for _ in 0..pairs {
+        open(event_loop);
+    }
+    ae_process_events(event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+    HARNESS.with_borrow(|h| h.calls)
+}
+
+fn close_all(mut event_loop: Box<AeEventLoop>) {
+    for fd in other_fds(-1) {
+        close(&mut event_loop, fd);
+    }
+    assert_eq!(ae_registered_file_events(&event_loop), 0);
+    ae_delete_event_loop(event_loop);
+}
+
+mod dispatch {
+    use super::*;
+
+    #[test]
+    fn test_deleted_during_dispatch() {
+        for (name, mut event_loop) in event_loops() {
+            let calls = run(&mut event_loop, Scenario::DeleteOthers, 16);
+            assert_eq!(calls, 1, "{name}: deleted fds were dispatched");
+            assert_eq!(ae_registered_file_events(&event_loop), 1, "{name}");
+            close_all(event_loop);
+        }
+    }
+
+    #[test]
+    fn test_reopened_during_dispatch() {
+        for (name, mut event_loop) in event_loops() {
+            let calls = run(&mut event_loop, Scenario::ReopenOthers, 16);
+            assert_eq!(calls, 1, "{name}: new registrations got stale events");
+            assert_eq!(ae_registered_file_events(&event_loop), 16, "{name}");
+
+            /* The 15 new pairs fire for themselves on the next iteration,
+             * the one that ran was drained. */
+            HARNESS.with_borrow_mut(|h| {
+                h.scenario = Scenario::Resize;
+                h.calls = 0;
+            });
+            ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+            assert_eq!(HARNESS.with_borrow(|h| h.calls), 15, "{name}");
+            close_all(event_loop);
+        }
+    }
+
+    #[test]
+    fn test_resized_during_dispatch() {
+        for (name, mut event_loop) in event_loops() {
+            let calls = run(&mut event_loop, Scenario::Resize, 16);
+            assert_eq!(calls, 16, "{name}: events lost to a resize");
+            assert_eq!(ae_get_set_size(&event_loop), SETSIZE + 16 * 16, "{name}");
+            close_all(event_loop);
+        }
+    }
+
+    #[test]
+    fn test_random_churn() {
+        for (name, mut event_loop) in event_loops() {
+            run(&mut event_loop, Scenario::Random, 64);
+            for _ in 0..500 {
+                /* Keep a steady population of pairs. */
+                while other_fds(-1).len() < 64 {
+                    open(&mut event_loop);
+                }
+                ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+                let open = other_fds(-1).len();
+                assert_eq!(ae_registered_file_events(&event_loop), open, "{name}");
+            }
+            close_all(event_loop);
+        }
+    }
+}
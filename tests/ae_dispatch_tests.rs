@@ -0,0 +1,336 @@
+/* Dispatch Context Tests
+ *
+ * Tests for the iteration budget, AeDispatchCtx, ae_call_soon() and
+ * ae_yield_and_continue() (ae/dispatch.rs).
+ */
+
+use rae::test_util::{manual_loop, run_once};
+use rae::{
+    AeDispatchCtx, AeEventLoop, ae_advance_clock, ae_call_soon, ae_create_event_loop,
+    ae_delete_event_loop, ae_delete_time_event, ae_dispatch_ctx, ae_get_iteration_budget,
+    ae_loop_now, ae_set_iteration_budget, ae_yield_and_continue,
+};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/* What the recorder saw, shared with the test. */
+#[derive(Default)]
+struct Log {
+    ctx: Cell<Option<AeDispatchCtx>>,
+    calls: Cell<u32>,
+}
+
+/* Records the context it is called with until it was called stop_after
+ * times. */
+struct Recorder {
+    log: Rc<Log>,
+    stop_after: u32,
+}
+
+fn record(_event_loop: &mut AeEventLoop, ctx: &AeDispatchCtx, data: &mut Recorder) -> bool {
+    data.log.ctx.set(Some(*ctx));
+    data.log.calls.set(data.log.calls.get() + 1);
+    data.log.calls.get() < data.stop_after
+}
+
+fn recorder(stop_after: u32) -> (Box<Recorder>, Rc<Log>) {
+    let log = Rc::new(Log::default());
+    let data = Box::new(Recorder {
+        log: log.clone(),
+        stop_after,
+    });
+    (data, log)
+}
+
+mod budget {
+    use super::*;
+
+    #[test]
+    fn test_no_budget_by_default() {
+        let mut event_loop = manual_loop(64);
+        assert_eq!(ae_get_iteration_budget(&event_loop), 0);
+        let (data, log) = recorder(1);
+        ae_call_soon(&mut event_loop, record, data);
+        run_once(&mut event_loop);
+
+        let ctx = log.ctx.get().expect("not called");
+        assert_eq!(ctx.deadline_us(), None);
+        assert_eq!(ctx.remaining_us(), None);
+        assert!(!ctx.should_yield());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_deadline_follows_the_clock() {
+        let mut event_loop = manual_loop(64);
+        ae_set_iteration_budget(&mut event_loop, 1000);
+        assert_eq!(ae_get_iteration_budget(&event_loop), 1000);
+        ae_advance_clock(&mut event_loop, 5000);
+        let (data, log) = recorder(1);
+        ae_call_soon(&mut event_loop, record, data);
+        run_once(&mut event_loop);
+
+        let ctx = log.ctx.get().expect("not called");
+        assert_eq!(ctx.deadline_us(), Some(ae_loop_now(&event_loop) + 1000));
+        assert_eq!(ctx.remaining_us(), Some(1000));
+        assert!(!ctx.should_yield());
+
+        /* The context outside of a callback is the one of the last
+         * iteration, seen with the clock moved on. */
+        ae_advance_clock(&mut event_loop, 2000);
+        let ctx = ae_dispatch_ctx(&event_loop);
+        assert_eq!(ctx.deadline_us(), Some(6000));
+        assert!(ctx.should_yield());
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod call_soon {
+    use super::*;
+
+    #[test]
+    fn test_called_until_done() {
+        let mut event_loop = manual_loop(64);
+        let (data, log) = recorder(3);
+        ae_call_soon(&mut event_loop, record, data);
+        assert_eq!(log.calls.get(), 0);
+        for expected in [1, 2, 3, 3, 3] {
+            run_once(&mut event_loop);
+            assert_eq!(log.calls.get(), expected);
+        }
+        /* The data was dropped with the call. */
+        assert_eq!(Rc::strong_count(&log), 1);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut event_loop = manual_loop(64);
+        let (data, log) = recorder(u32::MAX);
+        let id = ae_call_soon(&mut event_loop, record, data);
+        run_once(&mut event_loop);
+        ae_delete_time_event(&mut event_loop, id);
+        run_once(&mut event_loop);
+        run_once(&mut event_loop);
+        assert_eq!(log.calls.get(), 1);
+        assert_eq!(Rc::strong_count(&log), 1);
+        ae_delete_event_loop(event_loop);
+    }
+
+    /* A job splitting itself into slices of the iteration budget. */
+    #[derive(Default)]
+    struct Job {
+        units_left: Cell<u32>,
+        slices: Cell<u32>,
+        longest_slice: Cell<Duration>,
+    }
+
+    fn work(_event_loop: &mut AeEventLoop, ctx: &AeDispatchCtx, job: &mut Rc<Job>) -> bool {
+        let start = Instant::now();
+        job.slices.set(job.slices.get() + 1);
+        while job.units_left.get() > 0 && !ctx.should_yield() {
+            /* One unit of work, about 50us. */
+            let unit = Instant::now();
+            while unit.elapsed() < Duration::from_micros(50) {}
+            job.units_left.set(job.units_left.get() - 1);
+        }
+        job.longest_slice
+            .set(job.longest_slice.get().max(start.elapsed()));
+        job.units_left.get() > 0
+    }
+
+    #[test]
+    fn test_job_yields_at_the_deadline() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_set_iteration_budget(&mut event_loop, 2000);
+        let job = Rc::new(Job::default());
+        job.units_left.set(200);
+        ae_call_soon(&mut event_loop, work, Box::new(job.clone()));
+
+        let mut iterations = 0;
+        while job.units_left.get() > 0 {
+            run_once(&mut event_loop);
+            iterations += 1;
+            assert!(iterations < 1000, "job never finished");
+        }
+        /* 200 units of 50us are about 10ms of work, at most 2ms at a time. */
+        assert!(job.slices.get() >= 4, "{} slices", job.slices.get());
+        assert!(job.longest_slice.get() < Duration::from_millis(50));
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod yield_and_continue {
+    use super::*;
+
+    /* Moves a table to a new one a few entries per iteration, like an
+     * incremental rehash. */
+    struct Rehash {
+        old: Vec<u32>,
+        new: Rc<Cell<Vec<u32>>>,
+        steps: Rc<Cell<u32>>,
+    }
+
+    const STEP: usize = 10;
+
+    fn rehash_step(event_loop: &mut AeEventLoop, _ctx: &AeDispatchCtx, mut state: Box<Rehash>) {
+        state.steps.set(state.steps.get() + 1);
+        let mut new = state.new.take();
+        let keep = state.old.len().saturating_sub(STEP);
+        new.extend(state.old.drain(keep..));
+        state.new.set(new);
+        if !state.old.is_empty() {
+            ae_yield_and_continue(event_loop, state, rehash_step);
+        }
+    }
+
+    #[test]
+    fn test_one_step_per_iteration() {
+        let mut event_loop = manual_loop(64);
+        let new = Rc::new(Cell::new(Vec::new()));
+        let steps = Rc::new(Cell::new(0));
+        let state = Box::new(Rehash {
+            old: (0..95).collect(),
+            new: new.clone(),
+            steps: steps.clone(),
+        });
+        ae_yield_and_continue(&mut event_loop, state, rehash_step);
+        assert_eq!(steps.get(), 0);
+
+        for step in 1..=10 {
+            run_once(&mut event_loop);
+            assert_eq!(steps.get(), step);
+        }
+        run_once(&mut event_loop);
+        assert_eq!(steps.get(), 10);
+
+        let mut moved = new.take();
+        moved.sort();
+        assert_eq!(moved, (0..95).collect::<Vec<u32>>());
+        /* The last step dropped the state. */
+        assert_eq!(Rc::strong_count(&steps), 1);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_cancel_drops_the_state() {
+        let mut event_loop = manual_loop(64);
+        let steps = Rc::new(Cell::new(0));
+        let state = Box::new(Rehash {
+            old: (0..1000).collect(),
+            new: Rc::new(Cell::new(Vec::new())),
+            steps: steps.clone(),
+        });
+        let id = ae_yield_and_continue(&mut event_loop, state, rehash_step);
+        ae_delete_time_event(&mut event_loop, id);
+        run_once(&mut event_loop);
+        assert_eq!(steps.get(), 0);
+        assert_eq!(Rc::strong_count(&steps), 1);
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod deferred_limit {
+    use super::*;
+    use rae::{
+        AE_DEFERRED_LIMIT_DEFAULT, ae_get_deferred_limit, ae_get_stats, ae_set_deferred_limit,
+    };
+
+    /* Counts its calls in the shared cell, in order of creation. */
+    struct Task {
+        index: u32,
+        order: Rc<std::cell::RefCell<Vec<u32>>>,
+    }
+
+    fn once(_event_loop: &mut AeEventLoop, _ctx: &AeDispatchCtx, task: &mut Task) -> bool {
+        task.order.borrow_mut().push(task.index);
+        false
+    }
+
+    /* Spawns two more of itself every time it runs, forever. */
+    fn fork_bomb(
+        event_loop: &mut AeEventLoop,
+        _ctx: &AeDispatchCtx,
+        calls: &mut Rc<Cell<u32>>,
+    ) -> bool {
+        calls.set(calls.get() + 1);
+        ae_call_soon(event_loop, fork_bomb, Box::new(calls.clone()));
+        ae_call_soon(event_loop, fork_bomb, Box::new(calls.clone()));
+        false
+    }
+
+    #[test]
+    fn test_oldest_first_and_backlog() {
+        let mut event_loop = manual_loop(64);
+        assert_eq!(
+            ae_get_deferred_limit(&event_loop),
+            AE_DEFERRED_LIMIT_DEFAULT
+        );
+        ae_set_deferred_limit(&mut event_loop, 4);
+        let order = Rc::new(std::cell::RefCell::new(Vec::new()));
+        for index in 0..10 {
+            let task = Task {
+                index,
+                order: order.clone(),
+            };
+            ae_call_soon(&mut event_loop, once, Box::new(task));
+        }
+        assert_eq!(ae_get_stats(&event_loop).deferred_backlog, 10);
+
+        /* Calls due together run newest first, as any timers. */
+        run_once(&mut event_loop);
+        let mut first = order.borrow().clone();
+        first.sort_unstable();
+        assert_eq!(first, vec![0, 1, 2, 3]);
+        let stats = ae_get_stats(&event_loop);
+        assert_eq!(stats.deferred_backlog, 6);
+        assert_eq!(stats.deferred_postponed, 6);
+
+        run_once(&mut event_loop);
+        run_once(&mut event_loop);
+        let mut all = order.borrow().clone();
+        all.sort_unstable();
+        assert_eq!(all, (0..10).collect::<Vec<u32>>());
+        let stats = ae_get_stats(&event_loop);
+        assert_eq!(stats.deferred_backlog, 0);
+        assert_eq!(stats.deferred_postponed, 8);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_runaway_is_bounded() {
+        let mut event_loop = manual_loop(64);
+        ae_set_deferred_limit(&mut event_loop, 16);
+        let calls = Rc::new(Cell::new(0));
+        ae_call_soon(&mut event_loop, fork_bomb, Box::new(calls.clone()));
+        let mut last = 0;
+        for _ in 0..20 {
+            run_once(&mut event_loop);
+            assert!(calls.get() - last <= 16);
+            last = calls.get();
+        }
+        let stats = ae_get_stats(&event_loop);
+        assert!(stats.deferred_backlog > 16);
+        assert!(stats.deferred_postponed > 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_no_limit() {
+        let mut event_loop = manual_loop(64);
+        ae_set_deferred_limit(&mut event_loop, 0);
+        let order = Rc::new(std::cell::RefCell::new(Vec::new()));
+        for index in 0..2000 {
+            let task = Task {
+                index,
+                order: order.clone(),
+            };
+            ae_call_soon(&mut event_loop, once, Box::new(task));
+        }
+        run_once(&mut event_loop);
+        assert_eq!(order.borrow().len(), 2000);
+        assert_eq!(ae_get_stats(&event_loop).deferred_postponed, 0);
+        ae_delete_event_loop(event_loop);
+    }
+}
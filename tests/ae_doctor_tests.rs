@@ -0,0 +1,244 @@
+/* Loop Doctor Tests
+ *
+ * Tests for ae_doctor(): a healthy loop reports nothing, and each
+ * condition it knows about is provoked and reported.
+ */
+
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_NOMORE, AE_READABLE, AeEventLoop, AeFindingKind,
+    AeFindingSeverity, ae_create_event_loop, ae_create_file_event, ae_create_time_event, ae_doctor,
+    ae_process_events,
+};
+use std::ffi::c_void;
+use std::thread;
+use std::time::Duration;
+
+fn noop_file(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+fn once_timer(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    AE_NOMORE
+}
+
+fn slow_timer(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    thread::sleep(Duration::from_millis(12));
+    AE_NOMORE
+}
+
+mod doctor {
+    use super::*;
+
+    #[test]
+    fn test_healthy_loop() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        for _ in 0..20 {
+            ae_create_time_event(&mut event_loop, 0, once_timer, std::ptr::null_mut(), None);
+        }
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert!(ae_doctor(&event_loop).is_empty());
+    }
+
+    #[test]
+    fn test_late_timers() {
+        if !cfg!(feature = "instrumentation") {
+            return;
+        }
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        for _ in 0..10 {
+            ae_create_time_event(&mut event_loop, 0, once_timer, std::ptr::null_mut(), None);
+        }
+        thread::sleep(Duration::from_millis(15));
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+
+        let findings = ae_doctor(&event_loop);
+        assert!(
+            findings
+                .iter()
+                .any(|f| matches!(f.kind, AeFindingKind::LateTimers { count: 10, .. }))
+        );
+    }
+
+    #[test]
+    fn test_findings_name_the_loop() {
+        if !cfg!(feature = "instrumentation") {
+            return;
+        }
+        let mut event_loop = rae::AeEventLoopBuilder::new(1024)
+            .name("billing")
+            .build()
+            .expect("Failed to create event loop");
+        for _ in 0..10 {
+            ae_create_time_event(&mut event_loop, 0, once_timer, std::ptr::null_mut(), None);
+        }
+        thread::sleep(Duration::from_millis(15));
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+
+        let findings = ae_doctor(&event_loop);
+        assert!(!findings.is_empty());
+        assert!(
+            findings
+                .iter()
+                .all(|f| f.message.starts_with("Loop billing: "))
+        );
+    }
+
+    #[test]
+    fn test_slow_callbacks() {
+        if !cfg!(feature = "instrumentation") {
+            return;
+        }
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        for _ in 0..10 {
+            ae_create_time_event(&mut event_loop, 0, slow_timer, std::ptr::null_mut(), None);
+        }
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+
+        let findings = ae_doctor(&event_loop);
+        let slow = findings
+            .iter()
+            .find(|f| matches!(f.kind, AeFindingKind::SlowCallbacks { .. }))
+            .expect("Slow callbacks reported");
+        assert_eq!(slow.severity, AeFindingSeverity::Warning);
+        assert!(!slow.message.is_empty());
+    }
+
+    #[test]
+    fn test_select_near_fd_limit() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let fd = unsafe { libc::dup2(0, 950) };
+        assert_eq!(fd, 950);
+        ae_create_file_event(
+            &mut event_loop,
+            fd,
+            AE_READABLE,
+            noop_file,
+            std::ptr::null_mut(),
+        );
+
+        let findings = ae_doctor(&event_loop);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, AeFindingSeverity::Critical);
+        assert_eq!(
+            findings[0].kind,
+            AeFindingKind::SelectNearFdLimit { maxfd: 950 }
+        );
+
+        unsafe { libc::close(fd) };
+    }
+}
+
+mod starvation {
+    use super::*;
+    use rae::test_util::virtual_loop;
+    use rae::{
+        AE_TIME_EVENTS, AeDispatchCtx, AeFinding, ae_advance_clock, ae_create_job,
+        ae_set_diagnostic_proc, ae_set_timer_starvation_alarm,
+    };
+    use std::cell::RefCell;
+
+    thread_local! {
+        static RAISED: RefCell<Vec<AeFinding>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn collect(_event_loop: &mut AeEventLoop, finding: &AeFinding) {
+        RAISED.with(|raised| raised.borrow_mut().push(finding.clone()));
+    }
+
+    fn raised() -> Vec<AeFinding> {
+        RAISED.with(|raised| raised.borrow_mut().drain(..).collect())
+    }
+
+    fn every_10ms(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+        10
+    }
+
+    /* A job slice that takes 70ms of manual time. */
+    fn compact(event_loop: &mut AeEventLoop, _ctx: &AeDispatchCtx, _data: &mut ()) -> bool {
+        ae_advance_clock(event_loop, 70_000);
+        true
+    }
+
+    /* Loop with a 10ms timer and an alarm after 3 misses by over 50ms. */
+    fn alarmed_loop() -> Box<AeEventLoop> {
+        let (mut event_loop, _control) = virtual_loop(64);
+        ae_create_time_event(&mut event_loop, 10, every_10ms, std::ptr::null_mut(), None);
+        ae_set_diagnostic_proc(&mut event_loop, Some(collect));
+        ae_set_timer_starvation_alarm(&mut event_loop, 50_000, 3);
+        raised();
+        event_loop
+    }
+
+    /* Run one iteration `late_us` after the timer deadline. */
+    fn run_late(event_loop: &mut AeEventLoop, late_us: u64) {
+        ae_advance_clock(event_loop, 10_000 + late_us);
+        ae_process_events(event_loop, AE_TIME_EVENTS);
+    }
+
+    #[test]
+    fn test_alarm_after_repeated_misses() {
+        let mut event_loop = alarmed_loop();
+        run_late(&mut event_loop, 60_000);
+        run_late(&mut event_loop, 80_000);
+        assert!(raised().is_empty());
+
+        run_late(&mut event_loop, 60_000);
+        let findings = raised();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, AeFindingSeverity::Critical);
+        assert_eq!(
+            findings[0].kind,
+            AeFindingKind::TimerStarvation {
+                misses: 3,
+                worst_lag_us: 80_000,
+                offender: None
+            }
+        );
+
+        /* The count starts over after an alarm. */
+        run_late(&mut event_loop, 60_000);
+        assert!(raised().is_empty());
+    }
+
+    #[test]
+    fn test_timer_in_time_resets_the_count() {
+        let mut event_loop = alarmed_loop();
+        run_late(&mut event_loop, 60_000);
+        run_late(&mut event_loop, 60_000);
+        run_late(&mut event_loop, 50_000);
+        run_late(&mut event_loop, 60_000);
+        run_late(&mut event_loop, 60_000);
+        assert!(raised().is_empty());
+
+        /* Disabled alarms do not count at all. */
+        ae_set_timer_starvation_alarm(&mut event_loop, 50_000, 0);
+        for _ in 0..5 {
+            run_late(&mut event_loop, 60_000);
+        }
+        assert!(raised().is_empty());
+    }
+
+    #[test]
+    fn test_names_the_slowest_job() {
+        if !cfg!(feature = "instrumentation") {
+            return;
+        }
+        let mut event_loop = alarmed_loop();
+        ae_set_timer_starvation_alarm(&mut event_loop, 50_000, 2);
+        ae_create_job(&mut event_loop, "compactor", 1_000, compact, Box::new(()));
+
+        run_late(&mut event_loop, 60_000);
+        run_late(&mut event_loop, 60_000);
+        let findings = raised();
+        assert_eq!(findings.len(), 1);
+        match &findings[0].kind {
+            AeFindingKind::TimerStarvation {
+                offender: Some(entry),
+                ..
+            } => {
+                assert_eq!(entry.name, "compactor");
+                assert_eq!(entry.took_us, 70_000);
+            }
+            kind => panic!("unexpected finding {kind:?}"),
+        }
+        assert!(findings[0].message.contains("compactor"));
+    }
+}
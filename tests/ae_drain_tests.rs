@@ -0,0 +1,157 @@
+/* Drain Tests
+ *
+ * Tests for ae/drain.rs: listeners closed by ae_drain(), and its proc
+ * called once the buffered connections are gone or the timeout expired.
+ */
+
+use rae::test_util::consume_all;
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_ERR, AE_OK, AeEventLoop, ae_admin_close, ae_admin_listen,
+    ae_conn_close, ae_conn_create, ae_create_event_loop, ae_delete_event_loop, ae_drain,
+    ae_draining, ae_get_file_events, ae_process_events,
+};
+use std::ffi::c_void;
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+/* Open counts the drain proc was called with. */
+type Reports = Vec<usize>;
+
+fn record(_event_loop: &mut AeEventLoop, open: usize, client_data: *mut c_void) {
+    unsafe { (*(client_data as *mut Reports)).push(open) };
+}
+
+/* A buffered connection over a socket pair, and the peer end. */
+fn connection(event_loop: &mut AeEventLoop) -> (i32, UnixStream) {
+    let (local, remote) = UnixStream::pair().unwrap();
+    let fd = local.into_raw_fd();
+    assert_eq!(
+        ae_conn_create(event_loop, fd, consume_all, None, std::ptr::null_mut()),
+        AE_OK
+    );
+    (fd, remote)
+}
+
+fn run(event_loop: &mut AeEventLoop, reports: &Reports, deadline: Duration) {
+    let start = Instant::now();
+    while reports.is_empty() && start.elapsed() < deadline {
+        ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+mod drain {
+    use super::*;
+
+    #[test]
+    fn test_waits_for_the_last_connection() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listen_fd = listener.into_raw_fd();
+        let (first, _first_peer) = connection(&mut event_loop);
+        let (second, second_peer) = connection(&mut event_loop);
+
+        let mut reports = Reports::new();
+        let data = &mut reports as *mut Reports as *mut c_void;
+        assert_eq!(
+            ae_drain(&mut event_loop, &[listen_fd], 0, record, data),
+            AE_OK
+        );
+        assert!(ae_draining(&event_loop));
+        /* The listener is gone: new clients are refused. */
+        assert!(TcpStream::connect(addr).is_err());
+
+        ae_conn_close(&mut event_loop, first);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert!(reports.is_empty());
+
+        /* The peer hanging up closes the last one. */
+        drop(second_peer);
+        run(&mut event_loop, &reports, Duration::from_secs(5));
+        assert_eq!(reports, [0]);
+        assert!(!ae_draining(&event_loop));
+        assert_eq!(ae_get_file_events(&event_loop, second), 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_timeout_reports_what_is_left() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (fd, _peer) = connection(&mut event_loop);
+
+        let mut reports = Reports::new();
+        let data = &mut reports as *mut Reports as *mut c_void;
+        assert_eq!(ae_drain(&mut event_loop, &[], 10, record, data), AE_OK);
+        run(&mut event_loop, &reports, Duration::from_secs(5));
+        assert_eq!(reports, [1]);
+        assert!(!ae_draining(&event_loop));
+
+        /* Closing it now reports nothing more. */
+        ae_conn_close(&mut event_loop, fd);
+        assert_eq!(reports, [1]);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_nothing_open_reports_on_next_iteration() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut reports = Reports::new();
+        let data = &mut reports as *mut Reports as *mut c_void;
+        assert_eq!(ae_drain(&mut event_loop, &[], 5000, record, data), AE_OK);
+        assert!(reports.is_empty());
+        run(&mut event_loop, &reports, Duration::from_secs(5));
+        assert_eq!(reports, [0]);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_one_drain_at_a_time() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (fd, _peer) = connection(&mut event_loop);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap().into_raw_fd();
+
+        let mut reports = Reports::new();
+        let data = &mut reports as *mut Reports as *mut c_void;
+        assert_eq!(ae_drain(&mut event_loop, &[], 0, record, data), AE_OK);
+        assert_eq!(
+            ae_drain(&mut event_loop, &[listener], 0, record, data),
+            AE_ERR
+        );
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EBUSY)
+        );
+        /* Left open. */
+        assert_ne!(unsafe { libc::fcntl(listener, libc::F_GETFD) }, -1);
+
+        ae_conn_close(&mut event_loop, fd);
+        assert_eq!(reports, [0]);
+        unsafe { libc::close(listener) };
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_admin_clients_are_not_waited_for() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let path = std::env::temp_dir().join(format!("rae-drain-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(ae_admin_listen(&mut event_loop, &path), Ok(()));
+        let _admin = UnixStream::connect(&path).unwrap();
+        for _ in 0..10 {
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        }
+
+        let (fd, _peer) = connection(&mut event_loop);
+        let mut reports = Reports::new();
+        let data = &mut reports as *mut Reports as *mut c_void;
+        assert_eq!(ae_drain(&mut event_loop, &[], 0, record, data), AE_OK);
+        ae_conn_close(&mut event_loop, fd);
+        assert_eq!(reports, [0]);
+
+        ae_admin_close(&mut event_loop);
+        ae_delete_event_loop(event_loop);
+    }
+}
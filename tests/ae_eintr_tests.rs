@@ -0,0 +1,129 @@
+/* EINTR Policy Tests
+ *
+ * Tests for the handling of signals interrupting the backend poll
+ * (AeEintrPolicy): ending the wait, retrying it and calling a hook.
+ */
+
+use rae::{
+    AE_ALL_EVENTS, AE_NOMORE, AeEintrPolicy, AeEventLoop, AeEventLoopBuilder, ae_create_event_loop,
+    ae_create_time_event, ae_get_stats, ae_process_events, ae_set_eintr_policy,
+};
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+extern "C" fn ignore_signal(_sig: libc::c_int) {}
+
+/* Send SIGUSR1 to the calling thread after `delay`. The handler is
+ * installed without SA_RESTART so the poll fails with EINTR. */
+fn interrupt_after(delay: Duration) -> std::thread::JoinHandle<()> {
+    unsafe {
+        let mut sa: libc::sigaction = std::mem::zeroed();
+        sa.sa_sigaction = ignore_signal as *const () as usize;
+        sa.sa_flags = 0;
+        libc::sigemptyset(&mut sa.sa_mask);
+        libc::sigaction(libc::SIGUSR1, &sa, std::ptr::null_mut());
+    }
+    let target = unsafe { libc::pthread_self() } as usize;
+    std::thread::spawn(move || {
+        std::thread::sleep(delay);
+        unsafe { libc::pthread_kill(target as libc::pthread_t, libc::SIGUSR1) };
+    })
+}
+
+fn count_fire(_event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    let fired = unsafe { &*(client_data as *const AtomicUsize) };
+    fired.fetch_add(1, Ordering::SeqCst);
+    AE_NOMORE
+}
+
+/* Run one iteration with a timer 300ms away, interrupted after 50ms.
+ * Returns the events processed and how long the iteration took. */
+fn interrupted_iteration(event_loop: &mut AeEventLoop) -> (i32, Duration) {
+    let fired = AtomicUsize::new(0);
+    ae_create_time_event(
+        event_loop,
+        300,
+        count_fire,
+        &fired as *const AtomicUsize as *mut c_void,
+        None,
+    );
+    let sender = interrupt_after(Duration::from_millis(50));
+    let start = Instant::now();
+    let processed = ae_process_events(event_loop, AE_ALL_EVENTS);
+    let elapsed = start.elapsed();
+    sender.join().unwrap();
+    if processed == 0 {
+        /* Let the timer go so it does not outlive `fired`. */
+        while fired.load(Ordering::SeqCst) == 0 {
+            ae_process_events(event_loop, AE_ALL_EVENTS);
+        }
+    }
+    (processed, elapsed)
+}
+
+mod policies {
+    use super::*;
+
+    #[test]
+    fn test_return_early_is_the_default() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        let (processed, elapsed) = interrupted_iteration(&mut event_loop);
+        assert_eq!(processed, 0);
+        assert!(elapsed < Duration::from_millis(250));
+        assert_eq!(ae_get_stats(&event_loop).interrupted_polls, 1);
+    }
+
+    #[test]
+    fn test_retry_waits_for_the_timer() {
+        let mut event_loop = AeEventLoopBuilder::new(64)
+            .eintr_policy(AeEintrPolicy::Retry)
+            .build()
+            .unwrap();
+        let (processed, elapsed) = interrupted_iteration(&mut event_loop);
+        assert_eq!(processed, 1);
+        /* The retried poll sleeps for what is left, not 300ms more. */
+        assert!(elapsed >= Duration::from_millis(290));
+        assert!(elapsed < Duration::from_millis(500));
+        assert_eq!(ae_get_stats(&event_loop).interrupted_polls, 1);
+    }
+}
+
+mod hook {
+    use super::*;
+
+    static RETRY_CALLS: AtomicUsize = AtomicUsize::new(0);
+    static EARLY_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn hook_retry(_event_loop: &mut AeEventLoop) -> bool {
+        RETRY_CALLS.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    fn hook_return_early(_event_loop: &mut AeEventLoop) -> bool {
+        EARLY_CALLS.fetch_add(1, Ordering::SeqCst);
+        false
+    }
+
+    #[test]
+    fn test_hook_asks_for_retry() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        ae_set_eintr_policy(&mut event_loop, AeEintrPolicy::InvokeHook(hook_retry));
+        let (processed, _) = interrupted_iteration(&mut event_loop);
+        assert_eq!(processed, 1);
+        assert_eq!(RETRY_CALLS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_hook_ends_the_wait() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        ae_set_eintr_policy(
+            &mut event_loop,
+            AeEintrPolicy::InvokeHook(hook_return_early),
+        );
+        let (processed, elapsed) = interrupted_iteration(&mut event_loop);
+        assert_eq!(processed, 0);
+        assert!(elapsed < Duration::from_millis(250));
+        assert_eq!(EARLY_CALLS.load(Ordering::SeqCst), 1);
+    }
+}
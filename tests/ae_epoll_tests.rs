@@ -0,0 +1,220 @@
+/* Epoll Backend Tests
+ *
+ * Tests for the Linux epoll backend (ae_epoll.rs): interest mask merging,
+ * polling, driving a whole loop, fork reinitialization and the busy-poll
+ * configuration, which older kernels reject.
+ */
+
+#[cfg(target_os = "linux")]
+mod epoll_tests {
+    use rae::ae_epoll::{AeEpollBusyPoll, aeApiState};
+    use rae::ae_select::FiredEvent;
+    use rae::anet::{anet_pipe, anet_set_busy_poll};
+    use rae::constants::{AE_READABLE, AE_WRITABLE};
+    use rae::traits::EventBackend;
+    use rae::{
+        AE_ALL_EVENTS, AE_DONT_WAIT, AE_OK, AeEventLoop, AeEventLoopBuilder, ae_create_file_event,
+        ae_process_events,
+    };
+    use std::ffi::c_void;
+    use std::time::Duration;
+
+    fn poll_now(state: &mut aeApiState) -> Vec<FiredEvent> {
+        let mut fired = vec![FiredEvent { fd: -1, mask: 0 }; 16];
+        let n = state
+            .poll(&[], &mut fired, -1, Some(Duration::ZERO))
+            .expect("poll failed");
+        fired.truncate(n as usize);
+        fired
+    }
+
+    #[test]
+    fn test_create_and_name() {
+        let state = aeApiState::create().expect("Failed to create epoll API state");
+        assert_eq!(state.name(), "epoll");
+        assert!(state.fd() >= 0);
+    }
+
+    #[test]
+    fn test_masks_are_merged() {
+        let mut state = aeApiState::create().expect("Failed to create epoll API state");
+        assert_eq!(state.resize(1024), 0);
+        let (rfd, wfd) = anet_pipe(true).unwrap();
+
+        /* The write end is always writable: adding and removing interest
+         * bits one at a time must keep the others. */
+        assert_eq!(state.add_event(wfd, AE_READABLE), 0);
+        assert_eq!(state.add_event(wfd, AE_WRITABLE), 0);
+        let fired = poll_now(&mut state);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(
+            (fired[0].fd, fired[0].mask & AE_WRITABLE),
+            (wfd, AE_WRITABLE)
+        );
+
+        state.del_event(wfd, AE_READABLE);
+        assert_eq!(poll_now(&mut state).len(), 1, "Write interest kept");
+        state.del_event(wfd, AE_WRITABLE);
+        assert!(poll_now(&mut state).is_empty());
+
+        /* Fully removed: adding again must be an ADD, not a MOD. */
+        assert_eq!(state.add_event(wfd, AE_WRITABLE), 0);
+        assert_eq!(poll_now(&mut state).len(), 1);
+
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+
+    #[test]
+    fn test_poll_timeout_and_readable() {
+        let mut state = aeApiState::create().expect("Failed to create epoll API state");
+        state.resize(1024);
+        let (rfd, wfd) = anet_pipe(true).unwrap();
+        assert_eq!(state.add_event(rfd, AE_READABLE), 0);
+
+        let mut fired = vec![FiredEvent { fd: -1, mask: 0 }; 16];
+        let start = std::time::Instant::now();
+        let n = state
+            .poll(&[], &mut fired, -1, Some(Duration::from_millis(20)))
+            .unwrap();
+        assert_eq!(n, 0);
+        assert!(start.elapsed() >= Duration::from_millis(15));
+
+        unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+        let fired = poll_now(&mut state);
+        assert_eq!(fired.len(), 1);
+        assert_eq!((fired[0].fd, fired[0].mask), (rfd, AE_READABLE));
+
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+
+    fn count_readable(
+        _event_loop: &mut AeEventLoop,
+        fd: i32,
+        client_data: *mut c_void,
+        _mask: i32,
+    ) {
+        let mut buf = [0u8; 16];
+        unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+        unsafe { *(client_data as *mut i32) += 1 };
+    }
+
+    #[test]
+    fn test_drives_event_loop() {
+        let backend = aeApiState::create().expect("Failed to create epoll API state");
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .backend(backend)
+            .build()
+            .expect("Failed to create event loop");
+        assert_eq!(event_loop.apidata.name(), "epoll");
+
+        let (rfd, wfd) = anet_pipe(true).unwrap();
+        let mut calls = 0i32;
+        assert_eq!(
+            ae_create_file_event(
+                &mut event_loop,
+                rfd,
+                AE_READABLE,
+                count_readable,
+                &mut calls as *mut i32 as *mut c_void,
+            ),
+            AE_OK
+        );
+
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(calls, 0);
+        unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(calls, 1);
+
+        drop(event_loop);
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+
+    #[test]
+    fn test_reinit_drops_registrations() {
+        let mut state = aeApiState::create().expect("Failed to create epoll API state");
+        state.resize(1024);
+        let (rfd, wfd) = anet_pipe(true).unwrap();
+        state.add_event(wfd, AE_WRITABLE);
+
+        assert_eq!(state.reinit(), 0);
+        assert!(poll_now(&mut state).is_empty());
+        assert_eq!(
+            state.add_event(wfd, AE_WRITABLE),
+            0,
+            "Fresh ADD after reinit"
+        );
+        assert_eq!(poll_now(&mut state).len(), 1);
+
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+
+    #[test]
+    fn test_exclusive_registration() {
+        if !rae::ae_kernel_features().epoll_exclusive {
+            return;
+        }
+        let mut state = aeApiState::create().expect("Failed to create epoll API state");
+        state.resize(1024);
+        let (rfd, wfd) = anet_pipe(true).unwrap();
+
+        assert_eq!(state.add_event_exclusive(rfd, AE_READABLE), 0);
+        assert_eq!(state.add_event_exclusive(rfd, AE_READABLE), -1);
+        /* The kernel refuses to modify an exclusive registration. */
+        assert_eq!(state.add_event(rfd, AE_WRITABLE), -1);
+
+        unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+        let fired = poll_now(&mut state);
+        assert_eq!(fired.len(), 1);
+        assert_eq!((fired[0].fd, fired[0].mask), (rfd, AE_READABLE));
+
+        state.del_event(rfd, AE_READABLE);
+        assert_eq!(state.add_event_exclusive(rfd, AE_READABLE), 0);
+
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+
+    #[test]
+    fn test_busy_poll_params() {
+        let mut state = aeApiState::create().expect("Failed to create epoll API state");
+        let params = AeEpollBusyPoll {
+            usecs: 50,
+            budget: 0,
+            prefer_busy_poll: false,
+        };
+
+        if state.set_busy_poll(params) == 0 {
+            assert_eq!(state.busy_poll(), Ok(params));
+        } else {
+            /* Kernels before 6.9 do not know the ioctl. */
+            let err = std::io::Error::last_os_error().raw_os_error().unwrap();
+            assert!(
+                [libc::ENOTTY, libc::EINVAL, libc::EPERM].contains(&err),
+                "unexpected errno {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_socket_busy_poll() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let fd = std::os::unix::io::AsRawFd::as_raw_fd(&listener);
+        /* Raising the value may need CAP_NET_ADMIN, disabling never does. */
+        assert_eq!(anet_set_busy_poll(fd, 0), AE_OK);
+    }
+}
@@ -0,0 +1,179 @@
+/* External Source Tests
+ *
+ * Tests for ae_add_external_source() (ae/external.rs): a fake library over
+ * a socket pair, telling the loop which fds to watch and how long it may
+ * sleep, the way c-ares or libdbus do.
+ */
+
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_ERR, AE_OK, AE_READABLE, AE_WRITABLE, AeEventLoop,
+    ExternalSource, ae_add_external_source, ae_create_event_loop, ae_delete_event_loop,
+    ae_external_sources, ae_get_file_events, ae_process_events, ae_remove_external_source,
+};
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/* What the test tells the source, and what the source saw. */
+#[derive(Default)]
+struct Shared {
+    mask: i32,
+    timeout: Option<Duration>,
+    dispatches: Vec<Vec<(i32, i32)>>,
+    received: Vec<u8>,
+    remove_self: Option<rae::AeExternalSourceId>,
+}
+
+struct FakeLibrary {
+    socket: UnixStream,
+    shared: Rc<RefCell<Shared>>,
+}
+
+impl ExternalSource for FakeLibrary {
+    fn fds(&mut self, fds: &mut Vec<(i32, i32)>) {
+        let mask = self.shared.borrow().mask;
+        if mask != 0 {
+            fds.push((self.socket.as_raw_fd(), mask));
+        }
+    }
+
+    fn timeout(&mut self) -> Option<Duration> {
+        self.shared.borrow().timeout
+    }
+
+    fn dispatch(&mut self, event_loop: &mut AeEventLoop, ready: &[(i32, i32)]) {
+        let mut shared = self.shared.borrow_mut();
+        shared.dispatches.push(ready.to_vec());
+        if ready.iter().any(|&(_, mask)| mask & AE_READABLE != 0) {
+            let mut buf = [0u8; 64];
+            let n = self.socket.read(&mut buf).unwrap();
+            shared.received.extend_from_slice(&buf[..n]);
+        }
+        if let Some(id) = shared.remove_self.take() {
+            assert_eq!(ae_remove_external_source(event_loop, id), AE_OK);
+        }
+    }
+}
+
+fn library(mask: i32) -> (Box<FakeLibrary>, UnixStream, Rc<RefCell<Shared>>) {
+    let (ours, theirs) = UnixStream::pair().expect("Failed to create socket pair");
+    ours.set_nonblocking(true).unwrap();
+    let shared = Rc::new(RefCell::new(Shared {
+        mask,
+        ..Default::default()
+    }));
+    let library = Box::new(FakeLibrary {
+        socket: ours,
+        shared: shared.clone(),
+    });
+    (library, theirs, shared)
+}
+
+mod fds {
+    use super::*;
+
+    #[test]
+    fn test_readable_fd_is_dispatched() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (library, mut theirs, shared) = library(AE_READABLE);
+        let fd = library.socket.as_raw_fd();
+        let id = ae_add_external_source(&mut event_loop, library);
+
+        /* Registered by the first iteration, nothing to dispatch yet. */
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(ae_get_file_events(&event_loop, fd), AE_READABLE);
+        assert!(shared.borrow().dispatches.is_empty());
+
+        theirs.write_all(b"answer").unwrap();
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(shared.borrow().dispatches, [vec![(fd, AE_READABLE)]]);
+        assert_eq!(shared.borrow().received, b"answer");
+
+        assert_eq!(ae_remove_external_source(&mut event_loop, id), AE_OK);
+        assert_eq!(ae_get_file_events(&event_loop, fd), 0);
+        assert_eq!(ae_remove_external_source(&mut event_loop, id), AE_ERR);
+        assert_eq!(ae_external_sources(&event_loop), 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_interest_follows_the_source() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (library, _theirs, shared) = library(AE_READABLE);
+        let fd = library.socket.as_raw_fd();
+        ae_add_external_source(&mut event_loop, library);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+
+        /* A query to send: the library wants to write. */
+        shared.borrow_mut().mask = AE_READABLE | AE_WRITABLE;
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(
+            ae_get_file_events(&event_loop, fd),
+            AE_READABLE | AE_WRITABLE
+        );
+        assert_eq!(shared.borrow().dispatches, [vec![(fd, AE_WRITABLE)]]);
+
+        shared.borrow_mut().mask = 0;
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(ae_get_file_events(&event_loop, fd), 0);
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod timeouts {
+    use super::*;
+
+    #[test]
+    fn test_poll_sleeps_no_longer_than_the_timeout() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (library, _theirs, shared) = library(AE_READABLE);
+        shared.borrow_mut().timeout = Some(Duration::from_millis(20));
+        ae_add_external_source(&mut event_loop, library);
+
+        /* Nothing else would wake the loop up. */
+        let start = Instant::now();
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(shared.borrow().dispatches, [Vec::new()]);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_no_dispatch_before_the_timeout() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (library, _theirs, shared) = library(AE_READABLE);
+        shared.borrow_mut().timeout = Some(Duration::from_secs(60));
+        ae_add_external_source(&mut event_loop, library);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert!(shared.borrow().dispatches.is_empty());
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod removal {
+    use super::*;
+
+    #[test]
+    fn test_remove_from_dispatch() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (library, mut theirs, shared) = library(AE_READABLE);
+        let fd = library.socket.as_raw_fd();
+        let id = ae_add_external_source(&mut event_loop, library);
+        shared.borrow_mut().remove_self = Some(id);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+
+        theirs.write_all(b"bye").unwrap();
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(shared.borrow().dispatches.len(), 1);
+        assert_eq!(ae_get_file_events(&event_loop, fd), 0);
+        assert_eq!(ae_external_sources(&event_loop), 0);
+        /* The source was dropped with its socket. */
+        assert_eq!(Rc::strong_count(&shared), 1);
+        assert_eq!(theirs.read(&mut [0u8; 1]).unwrap(), 0);
+        ae_delete_event_loop(event_loop);
+    }
+}
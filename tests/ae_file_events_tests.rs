@@ -5,9 +5,11 @@
  */
 
 use rae::{
-    AE_DONT_WAIT, AE_FILE_EVENTS, AE_OK, AE_READABLE, AE_WRITABLE, ae_create_event_loop,
-    ae_create_file_event, ae_delete_event_loop, ae_delete_file_event, ae_get_file_client_data,
-    ae_get_file_events, ae_process_events,
+    AE_BARRIER, AE_DONT_WAIT, AE_ERR, AE_FILE_EVENTS, AE_OK, AE_READABLE, AE_WRITABLE,
+    AeDispatchOrder, AeEventLoopBuilder, AeFileEventOptions, ae_create_event_loop,
+    ae_create_file_event, ae_create_file_event_ex, ae_delete_event_loop, ae_delete_file_event,
+    ae_get_file_client_data, ae_get_file_events, ae_get_file_generation, ae_get_file_tag,
+    ae_get_file_write_client_data, ae_process_events, ae_registered_file_events,
 };
 use std::ffi::c_void;
 use std::sync::atomic::{AtomicI32, Ordering};
@@ -291,15 +293,19 @@ mod event_masks {
         let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
 
         // Test negative fd
-        let _result = ae_create_file_event(
+        let result = ae_create_file_event(
             &mut event_loop,
             -1,
             AE_READABLE,
             read_callback,
             std::ptr::null_mut(),
         );
-        // Should either succeed or fail gracefully
-        // Don't assert specific behavior as it's platform dependent
+        assert_eq!(result, AE_ERR);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EBADF)
+        );
+        assert_eq!(ae_registered_file_events(&event_loop), 0);
 
         // Test very large fd
         let _result = ae_create_file_event(
@@ -400,6 +406,85 @@ mod client_data {
         // Manual cleanup since we're using immutable reference
         drop(event_loop);
     }
+
+    /* Each handler stores its own client_data into the slot it points to. */
+    fn mark_read(_el: &mut rae::AeEventLoop, _fd: i32, client_data: *mut c_void, _mask: i32) {
+        unsafe { *(client_data as *mut char) = 'r' };
+    }
+
+    fn mark_write(_el: &mut rae::AeEventLoop, _fd: i32, client_data: *mut c_void, _mask: i32) {
+        unsafe { *(client_data as *mut char) = 'w' };
+    }
+
+    #[test]
+    fn test_split_client_data() {
+        use std::os::unix::io::AsRawFd;
+        use std::os::unix::net::UnixStream;
+
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (a, mut b) = UnixStream::pair().expect("Failed to create socket pair");
+        std::io::Write::write_all(&mut b, b"x").unwrap();
+        let fd = a.as_raw_fd();
+        let (mut reader, mut writer) = (' ', ' ');
+        let reader_data = &mut reader as *mut char as *mut c_void;
+        let writer_data = &mut writer as *mut char as *mut c_void;
+        let split = AeFileEventOptions {
+            split_client_data: true,
+            ..Default::default()
+        };
+
+        ae_create_file_event_ex(
+            &mut event_loop,
+            fd,
+            AE_READABLE,
+            mark_read,
+            reader_data,
+            split,
+        );
+        ae_create_file_event_ex(
+            &mut event_loop,
+            fd,
+            AE_WRITABLE,
+            mark_write,
+            writer_data,
+            split,
+        );
+        assert_eq!(ae_get_file_client_data(&event_loop, fd), reader_data);
+        assert_eq!(ae_get_file_write_client_data(&event_loop, fd), writer_data);
+
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        assert_eq!((reader, writer), ('r', 'w'));
+
+        /* A plain registration shares its data between both directions. */
+        ae_create_file_event(&mut event_loop, fd, AE_READABLE, mark_read, reader_data);
+        assert_eq!(ae_get_file_write_client_data(&event_loop, fd), reader_data);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_split_client_data_of_a_free_slot() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let mut old = 0i32;
+        let old_data = &mut old as *mut i32 as *mut c_void;
+        ae_create_file_event(&mut event_loop, 30, AE_READABLE, read_callback, old_data);
+        ae_delete_file_event(&mut event_loop, 30, AE_READABLE);
+
+        /* The write data of the previous user of the fd is not inherited. */
+        let split = AeFileEventOptions {
+            split_client_data: true,
+            ..Default::default()
+        };
+        ae_create_file_event_ex(
+            &mut event_loop,
+            30,
+            AE_READABLE,
+            read_callback,
+            std::ptr::null_mut(),
+            split,
+        );
+        assert!(ae_get_file_write_client_data(&event_loop, 30).is_null());
+        ae_delete_event_loop(event_loop);
+    }
 }
 
 mod edge_cases {
@@ -417,6 +502,27 @@ mod edge_cases {
         ae_delete_event_loop(event_loop);
     }
 
+    #[test]
+    fn test_fd_beyond_registered_range() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_create_file_event(
+            &mut event_loop,
+            3,
+            AE_READABLE,
+            read_callback,
+            std::ptr::null_mut(),
+        );
+
+        /* Below the set size but above any fd registered so far: nothing
+         * is allocated for it yet. */
+        assert_eq!(ae_get_file_events(&event_loop, 40), 0);
+        assert!(ae_get_file_client_data(&event_loop, 40).is_null());
+        ae_delete_file_event(&mut event_loop, 40, AE_READABLE);
+        assert_eq!(ae_get_file_events(&event_loop, 3), AE_READABLE);
+
+        ae_delete_event_loop(event_loop);
+    }
+
     #[test]
     fn test_file_events_with_small_setsize() {
         let mut event_loop = ae_create_event_loop(5).expect("Failed to create small event loop");
@@ -544,3 +650,1009 @@ mod callback_verification {
         ae_delete_event_loop(event_loop);
     }
 }
+
+mod tags {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    fn route_by_tag(el: &mut rae::AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+        unsafe { *(client_data as *mut u32) = ae_get_file_tag(el, fd) };
+    }
+
+    #[test]
+    fn test_tag_delivered_to_callback() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (a, _b) = UnixStream::pair().expect("Failed to create socket pair");
+        let mut seen = 0u32;
+
+        let result = ae_create_file_event_ex(
+            &mut event_loop,
+            a.as_raw_fd(),
+            AE_WRITABLE,
+            route_by_tag,
+            &mut seen as *mut u32 as *mut c_void,
+            AeFileEventOptions {
+                tag: 7,
+                ..Default::default()
+            },
+        );
+        assert_eq!(result, AE_OK);
+        assert_eq!(event_loop.events[a.as_raw_fd() as usize].tag, 7);
+
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        assert_eq!(seen, 7);
+    }
+
+    #[test]
+    fn test_tag_cleared_on_delete() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_create_file_event_ex(
+            &mut event_loop,
+            10,
+            AE_READABLE | AE_WRITABLE,
+            read_callback,
+            std::ptr::null_mut(),
+            AeFileEventOptions {
+                tag: 3,
+                ..Default::default()
+            },
+        );
+
+        ae_delete_file_event(&mut event_loop, 10, AE_WRITABLE);
+        assert_eq!(ae_get_file_tag(&event_loop, 10), 3, "Still registered");
+
+        ae_delete_file_event(&mut event_loop, 10, AE_READABLE);
+        assert_eq!(ae_get_file_tag(&event_loop, 10), 0);
+
+        /* A plain registration does not inherit the old tag. */
+        ae_create_file_event(
+            &mut event_loop,
+            10,
+            AE_READABLE,
+            read_callback,
+            std::ptr::null_mut(),
+        );
+        assert_eq!(ae_get_file_tag(&event_loop, 10), 0);
+        assert_eq!(ae_get_file_tag(&event_loop, -1), 0);
+        assert_eq!(ae_get_file_tag(&event_loop, 60), 0);
+    }
+}
+
+mod generations {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    /* (fd to replace, fd to put in its place, calls of the new handler) */
+    type Reuse = (i32, i32, i32);
+
+    fn reuse_other_fd(el: &mut rae::AeEventLoop, _fd: i32, client_data: *mut c_void, _mask: i32) {
+        let reuse = unsafe { &mut *(client_data as *mut Reuse) };
+        ae_delete_file_event(el, reuse.0, AE_READABLE);
+        unsafe {
+            libc::close(reuse.0);
+            assert_eq!(libc::dup2(reuse.1, reuse.0), reuse.0);
+        }
+        ae_create_file_event(el, reuse.0, AE_READABLE, count_new_owner, client_data);
+    }
+
+    fn count_new_owner(_el: &mut rae::AeEventLoop, _fd: i32, client_data: *mut c_void, _mask: i32) {
+        unsafe { (*(client_data as *mut Reuse)).2 += 1 };
+    }
+
+    #[test]
+    fn test_generation_bumped_on_release() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let initial = ae_get_file_generation(&event_loop, 5);
+
+        ae_create_file_event(
+            &mut event_loop,
+            5,
+            AE_READABLE | AE_WRITABLE,
+            read_callback,
+            std::ptr::null_mut(),
+        );
+        ae_delete_file_event(&mut event_loop, 5, AE_WRITABLE);
+        assert_eq!(ae_get_file_generation(&event_loop, 5), initial);
+
+        ae_delete_file_event(&mut event_loop, 5, AE_READABLE);
+        assert_eq!(ae_get_file_generation(&event_loop, 5), initial + 1);
+    }
+
+    #[test]
+    fn test_stale_fired_event_skipped() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (first, mut first_peer) = UnixStream::pair().expect("Failed to create socket pair");
+        let (second, mut second_peer) = UnixStream::pair().expect("Failed to create socket pair");
+        let (idle, _idle_peer) = UnixStream::pair().expect("Failed to create socket pair");
+        std::io::Write::write_all(&mut first_peer, b"x").unwrap();
+        std::io::Write::write_all(&mut second_peer, b"x").unwrap();
+
+        /* select reports fds in ascending order: the lower fd handler runs
+         * first and replaces the higher, also ready, fd. */
+        let (low, high) = if first.as_raw_fd() < second.as_raw_fd() {
+            (first.as_raw_fd(), second.as_raw_fd())
+        } else {
+            (second.as_raw_fd(), first.as_raw_fd())
+        };
+        let mut reuse: Reuse = (high, idle.as_raw_fd(), 0);
+        let data = &mut reuse as *mut Reuse as *mut c_void;
+        ae_create_file_event(&mut event_loop, low, AE_READABLE, reuse_other_fd, data);
+        ae_create_file_event(&mut event_loop, high, AE_READABLE, count_new_owner, data);
+        let generation = ae_get_file_generation(&event_loop, high);
+
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        assert_eq!(
+            reuse.2, 0,
+            "The new owner of the fd must not see the old readiness"
+        );
+        assert_eq!(ae_get_file_generation(&event_loop, high), generation + 1);
+    }
+
+    /* Mock control, fds dispatched, and whether fd 3 already nested. */
+    type Nesting = (rae::test_util::MockControl, Vec<i32>, bool);
+
+    fn nest_once(el: &mut rae::AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+        let nesting = unsafe { &mut *(client_data as *mut Nesting) };
+        nesting.1.push(fd);
+        if fd == 3 && !std::mem::replace(&mut nesting.2, true) {
+            nesting.0.clear_ready(4, AE_READABLE);
+            nesting.0.set_ready(6, AE_READABLE);
+            ae_process_events(el, AE_FILE_EVENTS | AE_DONT_WAIT);
+        }
+    }
+
+    #[test]
+    fn test_nested_poll_keeps_outer_events() {
+        let (mut event_loop, control) = rae::test_util::virtual_loop(64);
+        let mut nesting: Nesting = (control.clone(), Vec::new(), false);
+        let data = &mut nesting as *mut Nesting as *mut c_void;
+        for fd in [3, 4, 6] {
+            ae_create_file_event(&mut event_loop, fd, AE_READABLE, nest_once, data);
+        }
+        control.set_ready(3, AE_READABLE);
+        control.set_ready(4, AE_READABLE);
+
+        /* The nested iteration polls [3, 6] over the outer [3, 4]: the
+         * outer one still dispatches what it polled. */
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS);
+        assert_eq!(nesting.1, vec![3, 3, 6, 4]);
+    }
+}
+
+mod dispatch_order {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    fn order_read(_el: &mut rae::AeEventLoop, _fd: i32, client_data: *mut c_void, _mask: i32) {
+        unsafe { (*(client_data as *mut Vec<char>)).push('r') };
+    }
+
+    fn order_write(_el: &mut rae::AeEventLoop, _fd: i32, client_data: *mut c_void, _mask: i32) {
+        unsafe { (*(client_data as *mut Vec<char>)).push('w') };
+    }
+
+    /* Register an fd that is readable and writable at once, in the given
+     * order, and return the handler order of one iteration. */
+    fn dispatch(order: AeDispatchOrder, write_registered_first: bool, extra: i32) -> Vec<char> {
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .dispatch_order(order)
+            .build()
+            .expect("Failed to create event loop");
+        let (a, mut b) = UnixStream::pair().expect("Failed to create socket pair");
+        std::io::Write::write_all(&mut b, b"x").unwrap();
+
+        let fd = a.as_raw_fd();
+        let mut calls: Vec<char> = Vec::new();
+        let data = &mut calls as *mut Vec<char> as *mut c_void;
+        if write_registered_first {
+            ae_create_file_event(&mut event_loop, fd, AE_WRITABLE | extra, order_write, data);
+            ae_create_file_event(&mut event_loop, fd, AE_READABLE, order_read, data);
+        } else {
+            ae_create_file_event(&mut event_loop, fd, AE_READABLE, order_read, data);
+            ae_create_file_event(&mut event_loop, fd, AE_WRITABLE | extra, order_write, data);
+        }
+
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        calls
+    }
+
+    #[test]
+    fn test_reads_first_default() {
+        assert_eq!(
+            dispatch(AeDispatchOrder::ReadsFirst, true, 0),
+            vec!['r', 'w']
+        );
+        assert_eq!(
+            dispatch(AeDispatchOrder::ReadsFirst, false, AE_BARRIER),
+            vec!['w', 'r'],
+            "AE_BARRIER still inverts"
+        );
+    }
+
+    #[test]
+    fn test_writes_first() {
+        assert_eq!(
+            dispatch(AeDispatchOrder::WritesFirst, false, 0),
+            vec!['w', 'r']
+        );
+    }
+
+    #[test]
+    fn test_registration_order() {
+        assert_eq!(
+            dispatch(AeDispatchOrder::RegistrationOrder, true, 0),
+            vec!['w', 'r']
+        );
+        assert_eq!(
+            dispatch(AeDispatchOrder::RegistrationOrder, false, 0),
+            vec!['r', 'w']
+        );
+    }
+}
+
+mod exclusive {
+    use super::*;
+    use rae::{AE_ERR, AE_NONE, ae_pause, ae_resume};
+
+    fn exclusive() -> AeFileEventOptions {
+        AeFileEventOptions {
+            exclusive: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_read_only_fresh_registration() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let cd = std::ptr::null_mut();
+
+        assert_eq!(
+            ae_create_file_event_ex(
+                &mut event_loop,
+                10,
+                AE_WRITABLE,
+                read_callback,
+                cd,
+                exclusive()
+            ),
+            AE_ERR
+        );
+        ae_create_file_event(&mut event_loop, 11, AE_WRITABLE, write_callback, cd);
+        assert_eq!(
+            ae_create_file_event_ex(
+                &mut event_loop,
+                11,
+                AE_READABLE,
+                read_callback,
+                cd,
+                exclusive()
+            ),
+            AE_ERR
+        );
+
+        assert_eq!(
+            ae_create_file_event_ex(
+                &mut event_loop,
+                10,
+                AE_READABLE,
+                read_callback,
+                cd,
+                exclusive()
+            ),
+            AE_OK
+        );
+        /* No other direction afterwards, exclusive or not. */
+        assert_eq!(
+            ae_create_file_event(&mut event_loop, 10, AE_WRITABLE, write_callback, cd),
+            AE_ERR
+        );
+        assert_eq!(ae_get_file_events(&event_loop, 10), AE_READABLE);
+
+        /* Once released the slot is an ordinary one again. */
+        ae_delete_file_event(&mut event_loop, 10, AE_READABLE);
+        assert_eq!(ae_get_file_events(&event_loop, 10), AE_NONE);
+        assert_eq!(
+            ae_create_file_event(
+                &mut event_loop,
+                10,
+                AE_READABLE | AE_WRITABLE,
+                read_callback,
+                cd
+            ),
+            AE_OK
+        );
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_survives_pause() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let (rfd, wfd) = rae::anet::anet_pipe(true).expect("Failed to create pipe");
+        let before = READ_CALLBACK_COUNTER.load(Ordering::SeqCst);
+        assert_eq!(
+            ae_create_file_event_ex(
+                &mut event_loop,
+                rfd,
+                AE_READABLE,
+                read_callback,
+                std::ptr::null_mut(),
+                exclusive()
+            ),
+            AE_OK
+        );
+
+        assert_eq!(ae_pause(&mut event_loop), AE_OK);
+        assert_eq!(ae_resume(&mut event_loop), AE_OK);
+        unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        assert!(READ_CALLBACK_COUNTER.load(Ordering::SeqCst) > before);
+
+        ae_delete_event_loop(event_loop);
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+}
+
+mod priority {
+    use super::*;
+    use rae::test_util::virtual_loop;
+
+    /* Handlers record the fd and the direction they were called for. */
+    fn log_read(_el: &mut rae::AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+        unsafe { (*(client_data as *mut Vec<(i32, char)>)).push((fd, 'r')) };
+    }
+
+    fn log_write(_el: &mut rae::AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+        unsafe { (*(client_data as *mut Vec<(i32, char)>)).push((fd, 'w')) };
+    }
+
+    fn with_priority(priority: u8) -> AeFileEventOptions {
+        AeFileEventOptions {
+            priority,
+            split_client_data: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_higher_priority_first() {
+        let (mut event_loop, control) = virtual_loop(64);
+        let mut calls: Vec<(i32, char)> = Vec::new();
+        let data = &mut calls as *mut Vec<(i32, char)> as *mut c_void;
+        for (fd, priority) in [(3, 0), (4, 1), (5, 2), (6, 1)] {
+            ae_create_file_event_ex(
+                &mut event_loop,
+                fd,
+                AE_READABLE,
+                log_read,
+                data,
+                with_priority(priority),
+            );
+            control.set_ready(fd, AE_READABLE);
+        }
+
+        assert_eq!(ae_process_events(&mut event_loop, AE_FILE_EVENTS), 4);
+        /* Equal priorities by ascending fd. */
+        assert_eq!(calls, vec![(5, 'r'), (4, 'r'), (6, 'r'), (3, 'r')]);
+    }
+
+    #[test]
+    fn test_barrier_wins_within_an_fd() {
+        let (mut event_loop, control) = virtual_loop(64);
+        let mut calls: Vec<(i32, char)> = Vec::new();
+        let data = &mut calls as *mut Vec<(i32, char)> as *mut c_void;
+        ae_create_file_event(&mut event_loop, 3, AE_READABLE, log_read, data);
+        for (mask, proc) in [
+            (AE_READABLE, log_read as rae::FileProc),
+            (AE_WRITABLE | AE_BARRIER, log_write),
+        ] {
+            ae_create_file_event_ex(&mut event_loop, 7, mask, proc, data, with_priority(9));
+        }
+        control.set_ready(3, AE_READABLE);
+        control.set_ready(7, AE_READABLE | AE_WRITABLE);
+
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS);
+        assert_eq!(calls, vec![(7, 'w'), (7, 'r'), (3, 'r')]);
+    }
+
+    #[test]
+    fn test_reset_when_released() {
+        let (mut event_loop, control) = virtual_loop(64);
+        let mut calls: Vec<(i32, char)> = Vec::new();
+        let data = &mut calls as *mut Vec<(i32, char)> as *mut c_void;
+        ae_create_file_event(&mut event_loop, 3, AE_READABLE, log_read, data);
+        ae_create_file_event_ex(
+            &mut event_loop,
+            5,
+            AE_READABLE,
+            log_read,
+            data,
+            with_priority(1),
+        );
+        ae_delete_file_event(&mut event_loop, 5, AE_READABLE);
+        ae_create_file_event(&mut event_loop, 5, AE_READABLE, log_read, data);
+        control.set_ready(3, AE_READABLE);
+        control.set_ready(5, AE_READABLE);
+
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS);
+        assert_eq!(calls, vec![(3, 'r'), (5, 'r')]);
+    }
+}
+
+mod fd_order {
+    use super::*;
+    use rae::anet::anet_pipe;
+    use rae::test_util::{MockBackend, virtual_loop};
+    use rae::{AeClockSource, AeFdOrder};
+
+    fn log_fd(_el: &mut rae::AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+        let mut buf = [0u8; 16];
+        unsafe {
+            libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len());
+            (*(client_data as *mut Vec<i32>)).push(fd);
+        }
+    }
+
+    #[test]
+    fn test_ascending_whatever_the_backend_order() {
+        let (mut event_loop, control) = virtual_loop(64);
+        control.report_descending(true);
+        let mut calls: Vec<i32> = Vec::new();
+        let data = &mut calls as *mut Vec<i32> as *mut c_void;
+        for fd in [9, 3, 6] {
+            ae_create_file_event(&mut event_loop, fd, AE_READABLE, log_fd, data);
+            control.set_ready(fd, AE_READABLE);
+        }
+
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS);
+        assert_eq!(calls, vec![3, 6, 9]);
+    }
+
+    #[test]
+    fn test_backend_order_kept_on_request() {
+        let (backend, control) = MockBackend::new();
+        let mut event_loop = AeEventLoopBuilder::new(64)
+            .backend(backend)
+            .clock_source(AeClockSource::Manual)
+            .fd_order(AeFdOrder::Backend)
+            .build()
+            .expect("Failed to create event loop");
+        control.report_descending(true);
+        let mut calls: Vec<i32> = Vec::new();
+        let data = &mut calls as *mut Vec<i32> as *mut c_void;
+        for fd in [9, 3, 6] {
+            ae_create_file_event(&mut event_loop, fd, AE_READABLE, log_fd, data);
+            control.set_ready(fd, AE_READABLE);
+        }
+
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS);
+        assert_eq!(calls, vec![9, 6, 3]);
+    }
+
+    /* Pipes made readable from the highest fd down: the kernel queues of
+     * epoll and kqueue report them in that order, select in the other
+     * one. Every backend must dispatch them in the same order. */
+    #[test]
+    fn test_same_order_across_backends() {
+        for best in [false, true] {
+            let mut builder = AeEventLoopBuilder::new(1024);
+            if best {
+                builder = builder.best_backend();
+            }
+            let mut event_loop = builder.build().expect("Failed to create event loop");
+            let pipes: Vec<(i32, i32)> = (0..4)
+                .map(|_| anet_pipe(true).expect("Failed to create pipe"))
+                .collect();
+            let mut calls: Vec<i32> = Vec::new();
+            let data = &mut calls as *mut Vec<i32> as *mut c_void;
+            for &(rfd, _) in &pipes {
+                ae_create_file_event(&mut event_loop, rfd, AE_READABLE, log_fd, data);
+            }
+            for &(_, wfd) in pipes.iter().rev() {
+                unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+            }
+
+            ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+            let mut expected: Vec<i32> = pipes.iter().map(|&(rfd, _)| rfd).collect();
+            expected.sort();
+            assert_eq!(calls, expected, "backend {}", event_loop.api_name());
+
+            for (rfd, wfd) in pipes {
+                ae_delete_file_event(&mut event_loop, rfd, AE_READABLE);
+                unsafe {
+                    libc::close(rfd);
+                    libc::close(wfd);
+                }
+            }
+        }
+    }
+}
+
+mod coalescing {
+    use super::*;
+    use rae::test_util::virtual_loop;
+    use rae::{AeReadCoalescing, ae_get_stats};
+
+    fn count(_el: &mut rae::AeEventLoop, _fd: i32, client_data: *mut c_void, mask: i32) {
+        let counts = unsafe { &mut *(client_data as *mut (u32, u32)) };
+        if mask & AE_READABLE != 0 {
+            counts.0 += 1;
+        }
+        if mask & AE_WRITABLE != 0 {
+            counts.1 += 1;
+        }
+    }
+
+    fn coalesced(read_coalescing: AeReadCoalescing) -> AeFileEventOptions {
+        AeFileEventOptions {
+            read_coalescing,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_skip_iterations() {
+        let (mut event_loop, control) = virtual_loop(64);
+        let mut counts = (0u32, 0u32);
+        let data = &mut counts as *mut (u32, u32) as *mut c_void;
+        ae_create_file_event_ex(
+            &mut event_loop,
+            3,
+            AE_READABLE,
+            count,
+            data,
+            coalesced(AeReadCoalescing::Iterations(2)),
+        );
+        control.set_ready(3, AE_READABLE);
+
+        for _ in 0..7 {
+            ae_process_events(&mut event_loop, AE_FILE_EVENTS);
+        }
+        /* Iterations 1, 4 and 7. */
+        assert_eq!(counts.0, 3);
+        assert_eq!(ae_get_stats(&event_loop).coalesced_reads, 4);
+    }
+
+    #[test]
+    fn test_until_rearmed() {
+        let (mut event_loop, control) = virtual_loop(64);
+        let mut counts = (0u32, 0u32);
+        let data = &mut counts as *mut (u32, u32) as *mut c_void;
+        ae_create_file_event_ex(
+            &mut event_loop,
+            3,
+            AE_READABLE,
+            count,
+            data,
+            coalesced(AeReadCoalescing::UntilRearmed),
+        );
+        control.set_ready(3, AE_READABLE);
+
+        for _ in 0..3 {
+            ae_process_events(&mut event_loop, AE_FILE_EVENTS);
+        }
+        assert_eq!(counts.0, 1);
+
+        ae_create_file_event(&mut event_loop, 3, AE_READABLE, count, data);
+        for _ in 0..3 {
+            ae_process_events(&mut event_loop, AE_FILE_EVENTS);
+        }
+        assert_eq!(counts.0, 2, "Re-armed once, coalesced again after");
+    }
+
+    #[test]
+    fn test_writes_not_coalesced() {
+        let (mut event_loop, control) = virtual_loop(64);
+        let mut counts = (0u32, 0u32);
+        let data = &mut counts as *mut (u32, u32) as *mut c_void;
+        ae_create_file_event_ex(
+            &mut event_loop,
+            3,
+            AE_READABLE | AE_WRITABLE,
+            count,
+            data,
+            coalesced(AeReadCoalescing::UntilRearmed),
+        );
+        control.set_ready(3, AE_READABLE | AE_WRITABLE);
+
+        for _ in 0..4 {
+            ae_process_events(&mut event_loop, AE_FILE_EVENTS);
+        }
+        /* One handler for both directions is called once per iteration,
+         * with the readable bit dropped once reads are held back. */
+        assert_eq!(counts, (1, 4));
+    }
+}
+
+mod fired_batch {
+    use super::*;
+    use rae::test_util::virtual_loop;
+    use rae::{FiredEvent, ae_fire_event, ae_fired_events, ae_set_after_sleep_proc};
+    use std::cell::RefCell;
+
+    thread_local! {
+        /* (fd, mask) batches aftersleep saw, and handlers called since. */
+        static SEEN: RefCell<Vec<Vec<(i32, i32)>>> = const { RefCell::new(Vec::new()) };
+        static HANDLED: RefCell<usize> = const { RefCell::new(0) };
+    }
+
+    fn pairs(batch: &[FiredEvent]) -> Vec<(i32, i32)> {
+        batch.iter().map(|fired| (fired.fd, fired.mask)).collect()
+    }
+
+    fn record_batch(event_loop: &mut rae::AeEventLoop) {
+        /* Before any handler of the iteration. */
+        assert_eq!(HANDLED.with(|h| *h.borrow()), 0);
+        let batch = pairs(ae_fired_events(event_loop));
+        SEEN.with(|seen| seen.borrow_mut().push(batch));
+    }
+
+    fn handled(_el: &mut rae::AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {
+        HANDLED.with(|h| *h.borrow_mut() += 1);
+    }
+
+    #[test]
+    fn test_aftersleep_sees_the_batch() {
+        let (mut event_loop, control) = virtual_loop(64);
+        ae_set_after_sleep_proc(&mut event_loop, Some(record_batch));
+        ae_create_file_event(
+            &mut event_loop,
+            3,
+            AE_READABLE,
+            handled,
+            std::ptr::null_mut(),
+        );
+        ae_create_file_event(
+            &mut event_loop,
+            5,
+            AE_READABLE | AE_WRITABLE,
+            handled,
+            std::ptr::null_mut(),
+        );
+        ae_create_file_event(
+            &mut event_loop,
+            7,
+            AE_READABLE,
+            handled,
+            std::ptr::null_mut(),
+        );
+        control.set_ready(5, AE_WRITABLE);
+        control.set_ready(3, AE_READABLE);
+        assert_eq!(ae_fire_event(&mut event_loop, 7, AE_READABLE), AE_OK);
+
+        ae_process_events(
+            &mut event_loop,
+            rae::AE_ALL_EVENTS | rae::AE_CALL_AFTER_SLEEP,
+        );
+        let expected = vec![(3, AE_READABLE), (5, AE_WRITABLE), (7, AE_READABLE)];
+        assert_eq!(
+            SEEN.with(|seen| seen.borrow().clone()),
+            vec![expected.clone()]
+        );
+        assert_eq!(HANDLED.with(|h| *h.borrow()), 3);
+        /* Still there once the iteration is over. */
+        assert_eq!(pairs(ae_fired_events(&event_loop)), expected);
+
+        control.clear_ready(3, AE_READABLE);
+        control.clear_ready(5, AE_WRITABLE);
+        HANDLED.with(|h| *h.borrow_mut() = 0);
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        assert!(ae_fired_events(&event_loop).is_empty());
+    }
+}
+
+mod injection {
+    use super::*;
+    use rae::test_util::virtual_loop;
+    use rae::{AE_ERR, ae_fire_event};
+
+    /* Masks the handler was called with. */
+    fn record(_el: &mut rae::AeEventLoop, _fd: i32, client_data: *mut c_void, mask: i32) {
+        unsafe { (*(client_data as *mut Vec<i32>)).push(mask) };
+    }
+
+    /* Fires itself again, once. */
+    fn fire_again(el: &mut rae::AeEventLoop, fd: i32, client_data: *mut c_void, mask: i32) {
+        let calls = unsafe { &mut *(client_data as *mut Vec<i32>) };
+        calls.push(mask);
+        if calls.len() == 1 {
+            assert_eq!(ae_fire_event(el, fd, AE_READABLE), AE_OK);
+        }
+    }
+
+    #[test]
+    fn test_fired_once_without_readiness() {
+        let (mut event_loop, control) = virtual_loop(64);
+        let mut calls: Vec<i32> = Vec::new();
+        let data = &mut calls as *mut Vec<i32> as *mut c_void;
+        ae_create_file_event(&mut event_loop, 3, AE_READABLE, record, data);
+
+        assert_eq!(ae_fire_event(&mut event_loop, 3, AE_READABLE), AE_OK);
+        assert_eq!(ae_process_events(&mut event_loop, AE_FILE_EVENTS), 1);
+        assert_eq!(calls, vec![AE_READABLE]);
+        assert_eq!(control.nowait_polls(), 1, "The poll must not block");
+
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        assert_eq!(calls.len(), 1);
+    }
+
+    #[test]
+    fn test_merged_with_backend_events() {
+        let (mut event_loop, control) = virtual_loop(64);
+        let mut calls: Vec<i32> = Vec::new();
+        let data = &mut calls as *mut Vec<i32> as *mut c_void;
+        ae_create_file_event(&mut event_loop, 3, AE_READABLE | AE_WRITABLE, record, data);
+        control.set_ready(3, AE_READABLE);
+
+        ae_fire_event(&mut event_loop, 3, AE_READABLE);
+        ae_fire_event(&mut event_loop, 3, AE_WRITABLE);
+        assert_eq!(ae_process_events(&mut event_loop, AE_FILE_EVENTS), 1);
+        /* One handler for both directions runs once. */
+        assert_eq!(calls, vec![AE_READABLE | AE_WRITABLE]);
+    }
+
+    #[test]
+    fn test_fired_from_handler_runs_next_iteration() {
+        let (mut event_loop, control) = virtual_loop(64);
+        let mut calls: Vec<i32> = Vec::new();
+        let data = &mut calls as *mut Vec<i32> as *mut c_void;
+        ae_create_file_event(&mut event_loop, 3, AE_READABLE, fire_again, data);
+        control.set_ready(3, AE_READABLE);
+
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS);
+        assert_eq!(calls.len(), 1);
+        control.clear_ready(3, AE_READABLE);
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS);
+        assert_eq!(calls.len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_or_deleted() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let mut calls: Vec<i32> = Vec::new();
+        let data = &mut calls as *mut Vec<i32> as *mut c_void;
+        ae_create_file_event(&mut event_loop, 3, AE_READABLE, record, data);
+
+        assert_eq!(ae_fire_event(&mut event_loop, 4, AE_READABLE), AE_ERR);
+        assert_eq!(ae_fire_event(&mut event_loop, 3, AE_WRITABLE), AE_ERR);
+        assert_eq!(ae_fire_event(&mut event_loop, -1, AE_READABLE), AE_ERR);
+
+        /* Deleted and registered again: the event was for the old one. */
+        assert_eq!(ae_fire_event(&mut event_loop, 3, AE_READABLE), AE_OK);
+        ae_delete_file_event(&mut event_loop, 3, AE_READABLE);
+        ae_create_file_event(&mut event_loop, 3, AE_READABLE, record, data);
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        assert!(calls.is_empty());
+    }
+}
+
+mod fired_overflow {
+    use super::*;
+    use rae::test_util::{MockBackend, MockControl, run_iterations};
+    use rae::{
+        AE_FIRED_OVERFLOW_MAX_POLLS, AeClockSource, AeEventLoop, AeFiredOverflowPolicy,
+        ae_get_stats, ae_set_fired_overflow_policy,
+    };
+
+    struct Server {
+        control: MockControl,
+        served: Vec<i32>,
+    }
+
+    /* Serves the fd, which is no longer ready afterwards. */
+    fn serve(_el: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+        let server = unsafe { &mut *(client_data as *mut Server) };
+        server.control.clear_ready(fd, AE_READABLE);
+        server.served.push(fd);
+    }
+
+    static OVERFLOWS: AtomicI32 = AtomicI32::new(0);
+
+    fn count_overflow(_el: &mut AeEventLoop, nfired: i32) {
+        assert_eq!(nfired, 2);
+        OVERFLOWS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /* A loop reporting 2 events per poll, with `count` ready fds from 3. */
+    fn crowded_loop(policy: AeFiredOverflowPolicy, count: i32) -> (Box<AeEventLoop>, Box<Server>) {
+        let (backend, control) = MockBackend::new();
+        let mut event_loop = AeEventLoopBuilder::new(64)
+            .backend(backend)
+            .clock_source(AeClockSource::Manual)
+            .fired_capacity(2)
+            .fired_overflow(policy)
+            .build()
+            .expect("Failed to create event loop");
+        let mut server = Box::new(Server {
+            control,
+            served: Vec::new(),
+        });
+        let data = &mut *server as *mut Server as *mut c_void;
+        for fd in 3..3 + count {
+            ae_create_file_event(&mut event_loop, fd, AE_READABLE, serve, data);
+            server.control.set_ready(fd, AE_READABLE);
+        }
+        (event_loop, server)
+    }
+
+    #[test]
+    fn test_defer() {
+        let (mut event_loop, server) = crowded_loop(AeFiredOverflowPolicy::Defer, 5);
+        assert_eq!(run_iterations(&mut event_loop, AE_FILE_EVENTS, 1), 2);
+        assert_eq!(server.served, vec![3, 4]);
+        assert_eq!(run_iterations(&mut event_loop, AE_FILE_EVENTS, 1), 2);
+        assert_eq!(run_iterations(&mut event_loop, AE_FILE_EVENTS, 1), 1);
+        assert_eq!(ae_get_stats(&event_loop).fired_saturated, 2);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_grow() {
+        let (mut event_loop, server) = crowded_loop(AeFiredOverflowPolicy::Grow, 7);
+        assert_eq!(run_iterations(&mut event_loop, AE_FILE_EVENTS, 1), 2);
+        assert_eq!(run_iterations(&mut event_loop, AE_FILE_EVENTS, 1), 4);
+        assert_eq!(run_iterations(&mut event_loop, AE_FILE_EVENTS, 1), 1);
+        assert_eq!(server.served, (3..10).collect::<Vec<i32>>());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_poll_again() {
+        let (mut event_loop, server) = crowded_loop(AeFiredOverflowPolicy::PollAgain, 5);
+        assert_eq!(run_iterations(&mut event_loop, AE_FILE_EVENTS, 1), 5);
+        assert_eq!(server.served, vec![3, 4, 5, 6, 7]);
+        ae_delete_event_loop(event_loop);
+
+        /* Bounded, so that timers still get their turn. */
+        let (mut event_loop, _server) = crowded_loop(AeFiredOverflowPolicy::PollAgain, 20);
+        assert_eq!(
+            run_iterations(&mut event_loop, AE_FILE_EVENTS, 1),
+            2 * AE_FIRED_OVERFLOW_MAX_POLLS as i32
+        );
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_hook() {
+        let (mut event_loop, _server) = crowded_loop(AeFiredOverflowPolicy::Defer, 3);
+        ae_set_fired_overflow_policy(
+            &mut event_loop,
+            AeFiredOverflowPolicy::InvokeHook(count_overflow),
+        );
+        assert_eq!(run_iterations(&mut event_loop, AE_FILE_EVENTS, 1), 2);
+        assert_eq!(OVERFLOWS.load(Ordering::SeqCst), 1);
+        assert_eq!(run_iterations(&mut event_loop, AE_FILE_EVENTS, 1), 1);
+        assert_eq!(OVERFLOWS.load(Ordering::SeqCst), 1);
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod stdio_policy {
+    use super::*;
+    use rae::{
+        AE_ERR, AeFinding, AeFindingKind, AeStdioPolicy, ae_add_file_event, ae_get_stdio_policy,
+        ae_set_diagnostic_proc, ae_set_stdio_policy,
+    };
+    use std::cell::RefCell;
+
+    thread_local! {
+        static RAISED: RefCell<Vec<AeFinding>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn collect(_event_loop: &mut rae::AeEventLoop, finding: &AeFinding) {
+        RAISED.with(|raised| raised.borrow_mut().push(finding.clone()));
+    }
+
+    fn raised() -> Vec<AeFinding> {
+        RAISED.with(|raised| raised.borrow_mut().drain(..).collect())
+    }
+
+    #[test]
+    fn test_allow_by_default() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        assert_eq!(ae_get_stdio_policy(&event_loop), AeStdioPolicy::Allow);
+        ae_set_diagnostic_proc(&mut event_loop, Some(collect));
+        let retval = ae_create_file_event(
+            &mut event_loop,
+            0,
+            AE_READABLE,
+            read_callback,
+            std::ptr::null_mut(),
+        );
+        assert_eq!(retval, AE_OK);
+        assert_eq!(ae_get_file_events(&event_loop, 0), AE_READABLE);
+        assert!(raised().is_empty());
+        ae_delete_file_event(&mut event_loop, 0, AE_READABLE);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_deny() {
+        let mut event_loop = AeEventLoopBuilder::new(64)
+            .stdio_policy(AeStdioPolicy::Deny)
+            .build()
+            .expect("Failed to create event loop");
+        for fd in 0..3 {
+            let retval = ae_create_file_event(
+                &mut event_loop,
+                fd,
+                AE_READABLE,
+                read_callback,
+                std::ptr::null_mut(),
+            );
+            assert_eq!(retval, AE_ERR);
+            assert_eq!(ae_get_file_events(&event_loop, fd), 0);
+        }
+        /* The typed API goes through the same check. */
+        assert!(
+            ae_add_file_event(
+                &mut event_loop,
+                1,
+                AE_WRITABLE,
+                write_callback,
+                std::ptr::null_mut()
+            )
+            .is_none()
+        );
+        assert_eq!(event_loop.maxfd, -1);
+
+        let (rfd, wfd) = rae::anet::anet_pipe(true).expect("Failed to create pipe");
+        assert!(rfd > 2);
+        let retval = ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            read_callback,
+            std::ptr::null_mut(),
+        );
+        assert_eq!(retval, AE_OK);
+        ae_delete_file_event(&mut event_loop, rfd, AE_READABLE);
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_warn() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_set_stdio_policy(&mut event_loop, AeStdioPolicy::Warn);
+        ae_set_diagnostic_proc(&mut event_loop, Some(collect));
+        let retval = ae_create_file_event(
+            &mut event_loop,
+            2,
+            AE_WRITABLE,
+            write_callback,
+            std::ptr::null_mut(),
+        );
+        assert_eq!(retval, AE_OK);
+        let findings = raised();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].kind,
+            AeFindingKind::StdioRegistration {
+                fd: 2,
+                mask: AE_WRITABLE
+            }
+        );
+        assert!(findings[0].message.contains("stderr"));
+
+        /* Once per registration, not per direction added. */
+        ae_create_file_event(
+            &mut event_loop,
+            2,
+            AE_READABLE,
+            read_callback,
+            std::ptr::null_mut(),
+        );
+        assert!(raised().is_empty());
+        ae_delete_file_event(&mut event_loop, 2, AE_READABLE | AE_WRITABLE);
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -0,0 +1,160 @@
+/* File Read Offload Tests
+ *
+ * Tests for regular file reads served by the I/O threads (ae/fileio.rs)
+ * and completed on the loop thread.
+ */
+
+use rae::{
+    AE_ALL_EVENTS, AE_OK, AeEventLoop, AeEventLoopBuilder, ae_create_event_loop, ae_file_read,
+    ae_file_reads_pending, ae_process_events,
+};
+use std::ffi::c_void;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+
+#[derive(Default)]
+struct Completions {
+    reads: Vec<(u64, Result<Vec<u8>, i32>)>,
+    thread: Option<std::thread::ThreadId>,
+}
+
+fn record_read(
+    _event_loop: &mut AeEventLoop,
+    _fd: i32,
+    offset: u64,
+    result: Result<&[u8], i32>,
+    client_data: *mut c_void,
+) {
+    let completions = unsafe { &mut *(client_data as *mut Completions) };
+    completions
+        .reads
+        .push((offset, result.map(|data| data.to_vec())));
+    completions.thread = Some(std::thread::current().id());
+}
+
+fn temp_file(contents: &[u8]) -> std::fs::File {
+    let path = std::env::temp_dir().join(format!(
+        "rae-fileio-{}-{:?}",
+        std::process::id(),
+        std::thread::current().id()
+    ));
+    let mut file = std::fs::File::create(&path).unwrap();
+    file.write_all(contents).unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    file
+}
+
+fn run_until_done(event_loop: &mut AeEventLoop) {
+    while ae_file_reads_pending(event_loop) > 0 {
+        ae_process_events(event_loop, AE_ALL_EVENTS);
+    }
+}
+
+mod reads {
+    use super::*;
+
+    #[test]
+    fn test_read_chunks_and_eof() {
+        let file = temp_file(b"hello, offloaded world");
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut completions = Completions::default();
+        let data = &mut completions as *mut Completions as *mut c_void;
+
+        for offset in [0, 7, 100] {
+            assert_eq!(
+                ae_file_read(
+                    &mut event_loop,
+                    file.as_raw_fd(),
+                    offset,
+                    9,
+                    record_read,
+                    data
+                ),
+                AE_OK
+            );
+        }
+        assert_eq!(ae_file_reads_pending(&event_loop), 3);
+        run_until_done(&mut event_loop);
+
+        completions.reads.sort_by_key(|(offset, _)| *offset);
+        assert_eq!(
+            completions.reads,
+            vec![
+                (0, Ok(b"hello, of".to_vec())),
+                (7, Ok(b"offloaded".to_vec())),
+                (100, Ok(Vec::new())),
+            ]
+        );
+        assert_eq!(
+            completions.thread,
+            Some(std::thread::current().id()),
+            "Completions run on the loop thread"
+        );
+    }
+
+    #[test]
+    fn test_read_error_reported() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut completions = Completions::default();
+
+        ae_file_read(
+            &mut event_loop,
+            -1,
+            0,
+            16,
+            record_read,
+            &mut completions as *mut Completions as *mut c_void,
+        );
+        run_until_done(&mut event_loop);
+        assert_eq!(completions.reads, vec![(0, Err(libc::EBADF))]);
+    }
+}
+
+mod pool {
+    use super::*;
+
+    #[test]
+    fn test_single_io_thread_keeps_order() {
+        let file = temp_file(b"0123456789");
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .io_threads(1)
+            .build()
+            .expect("Failed to create event loop");
+        let mut completions = Completions::default();
+        let data = &mut completions as *mut Completions as *mut c_void;
+
+        for offset in 0..10 {
+            ae_file_read(
+                &mut event_loop,
+                file.as_raw_fd(),
+                offset,
+                1,
+                record_read,
+                data,
+            );
+        }
+        run_until_done(&mut event_loop);
+
+        let offsets: Vec<u64> = completions.reads.iter().map(|(o, _)| *o).collect();
+        assert_eq!(offsets, (0..10).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_drop_loop_with_reads_in_flight() {
+        let file = temp_file(b"data");
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut completions = Completions::default();
+
+        ae_file_read(
+            &mut event_loop,
+            file.as_raw_fd(),
+            0,
+            4,
+            record_read,
+            &mut completions as *mut Completions as *mut c_void,
+        );
+        drop(event_loop);
+        assert!(completions.reads.is_empty(), "Completion dropped unrun");
+    }
+}
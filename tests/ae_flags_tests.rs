@@ -0,0 +1,98 @@
+/* Process Flags Tests
+ *
+ * Tests for ProcessFlags and AeEventLoop::process() (ae/flags.rs).
+ */
+
+use rae::test_util::manual_loop;
+use rae::{
+    AE_ALL_EVENTS, AE_CALL_AFTER_SLEEP, AE_CALL_BEFORE_SLEEP, AE_DONT_WAIT, AE_FILE_EVENTS,
+    AE_NOMORE, AE_TIME_EVENTS, AeEventLoop, ProcessFlags, ae_create_time_event,
+    ae_delete_event_loop, ae_set_before_sleep_proc,
+};
+use std::cell::Cell;
+use std::ffi::c_void;
+
+thread_local! {
+    static BEFORE_SLEEP_CALLS: Cell<u32> = const { Cell::new(0) };
+}
+
+fn count_before_sleep(_event_loop: &mut AeEventLoop) {
+    BEFORE_SLEEP_CALLS.with(|calls| calls.set(calls.get() + 1));
+}
+
+fn once(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    AE_NOMORE
+}
+
+mod flags {
+    use super::*;
+
+    #[test]
+    fn test_constructors_match_raw_flags() {
+        assert_eq!(ProcessFlags::all().bits(), AE_ALL_EVENTS);
+        assert_eq!(ProcessFlags::files_only().bits(), AE_FILE_EVENTS);
+        assert_eq!(ProcessFlags::timers_only().bits(), AE_TIME_EVENTS);
+        assert_eq!(ProcessFlags::nowait().bits(), AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(
+            ProcessFlags::main_loop().bits(),
+            AE_ALL_EVENTS | AE_CALL_BEFORE_SLEEP | AE_CALL_AFTER_SLEEP
+        );
+        assert_eq!(
+            ProcessFlags::files_only().dont_wait().with_sleep_hooks(),
+            ProcessFlags::FILE_EVENTS
+                | ProcessFlags::DONT_WAIT
+                | ProcessFlags::CALL_BEFORE_SLEEP
+                | ProcessFlags::CALL_AFTER_SLEEP
+        );
+        assert!(ProcessFlags::nowait().contains(ProcessFlags::DONT_WAIT));
+        assert!(!ProcessFlags::all().contains(ProcessFlags::DONT_WAIT));
+        assert_eq!(i32::from(ProcessFlags::all()), AE_ALL_EVENTS);
+    }
+
+    #[test]
+    fn test_from_bits() {
+        assert_eq!(
+            ProcessFlags::from_bits(AE_ALL_EVENTS | AE_DONT_WAIT),
+            Some(ProcessFlags::nowait())
+        );
+        assert_eq!(ProcessFlags::from_bits(1 << 10), None);
+        assert_eq!(ProcessFlags::from_bits(AE_ALL_EVENTS | (1 << 10)), None);
+    }
+
+    #[test]
+    fn test_validity() {
+        assert!(ProcessFlags::all().is_valid());
+        assert!(ProcessFlags::timers_only().is_valid());
+        assert!(!ProcessFlags::default().is_valid());
+        assert!(!ProcessFlags::DONT_WAIT.with_sleep_hooks().is_valid());
+    }
+}
+
+mod process {
+    use super::*;
+
+    #[test]
+    fn test_processes_events() {
+        let mut event_loop = manual_loop(64);
+        ae_create_time_event(&mut event_loop, 0, once, std::ptr::null_mut(), None);
+        assert_eq!(event_loop.process(ProcessFlags::nowait()), Ok(1));
+        assert_eq!(event_loop.process(ProcessFlags::nowait()), Ok(0));
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_rejects_flags_without_events() {
+        let mut event_loop = manual_loop(64);
+        ae_set_before_sleep_proc(&mut event_loop, Some(count_before_sleep));
+        let flags = ProcessFlags::DONT_WAIT.with_sleep_hooks();
+        assert_eq!(event_loop.process(flags), Err(libc::EINVAL));
+        /* Nothing ran, not even the hooks. */
+        assert_eq!(BEFORE_SLEEP_CALLS.with(Cell::get), 0);
+
+        ae_create_time_event(&mut event_loop, 0, once, std::ptr::null_mut(), None);
+        let flags = ProcessFlags::timers_only().with_sleep_hooks();
+        assert_eq!(event_loop.process(flags), Ok(1));
+        assert_eq!(BEFORE_SLEEP_CALLS.with(Cell::get), 1);
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -0,0 +1,199 @@
+/* Flight Recorder Tests
+ *
+ * Tests for ae_set_flight_recorder() (ae/flight.rs): the ring of the last
+ * dispatches over the mock backend and manual clock, panicked and nested
+ * dispatches, and the dump written to an fd. The recorder needs the
+ * `instrumentation` feature, see ae_instrumentation_tests.rs without it.
+ */
+
+#![cfg(feature = "instrumentation")]
+
+use rae::test_util::{MockControl, run_once, virtual_loop};
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_ERR, AE_NOMORE, AE_OK, AE_READABLE, AeDispatchSource,
+    AeEventLoop, AeFlightOutcome, AePanicPolicy, ae_advance_clock, ae_create_file_event,
+    ae_create_time_event, ae_delete_event_loop, ae_flight_dump, ae_flight_entries,
+    ae_process_events, ae_set_dispatch_label, ae_set_flight_recorder, ae_set_panic_policy,
+};
+use std::ffi::c_void;
+use std::io::Read;
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+/* Takes 5 ms of loop time, labeled, and fires once. */
+fn slow_timer(event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    ae_set_dispatch_label(event_loop, "refresh");
+    ae_advance_clock(event_loop, 5_000);
+    AE_NOMORE
+}
+
+fn periodic_timer(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    100
+}
+
+fn serve(_event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let control = unsafe { &*(client_data as *const MockControl) };
+    control.clear_ready(fd, AE_READABLE);
+}
+
+fn panicking(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {
+    panic!("handler bug");
+}
+
+/* Runs the loop again from a callback. */
+fn nesting(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, mask: i32) {
+    serve(event_loop, fd, client_data, mask);
+    ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+}
+
+/* Dumps the ring, with this dispatch still running, to the fd it was
+ * given. */
+fn dumping(event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    let fd = client_data as usize as i32;
+    assert_eq!(ae_flight_dump(event_loop, fd), AE_OK);
+    assert_eq!(ae_set_flight_recorder(event_loop, 8), AE_ERR);
+    AE_NOMORE
+}
+
+mod flight {
+    use super::*;
+
+    #[test]
+    fn test_disabled_by_default() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        ae_create_time_event(&mut event_loop, 0, slow_timer, std::ptr::null_mut(), None);
+        run_once(&mut event_loop);
+        assert!(ae_flight_entries(&event_loop).is_empty());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_records_dispatches() {
+        let (mut event_loop, control) = virtual_loop(64);
+        assert_eq!(ae_set_flight_recorder(&mut event_loop, 8), AE_OK);
+        let data = &control as *const MockControl as *mut c_void;
+        ae_create_file_event(&mut event_loop, 5, AE_READABLE, serve, data);
+        let id = ae_create_time_event(&mut event_loop, 0, slow_timer, std::ptr::null_mut(), None);
+
+        control.set_ready(5, AE_READABLE);
+        run_once(&mut event_loop);
+        let entries = ae_flight_entries(&event_loop);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].source,
+            AeDispatchSource::File {
+                fd: 5,
+                mask: AE_READABLE
+            }
+        );
+        assert_eq!(entries[0].outcome, AeFlightOutcome::Returned(AE_OK));
+        assert_eq!(entries[1].source, AeDispatchSource::Timer { id });
+        assert_eq!(entries[1].outcome, AeFlightOutcome::Returned(AE_NOMORE));
+        assert_eq!(entries[1].label, Some("refresh"));
+        assert_eq!(entries[1].took_us, 5_000);
+        assert!(entries[0].seq < entries[1].seq);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_keeps_the_last_dispatches() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        ae_set_flight_recorder(&mut event_loop, 3);
+        let id = ae_create_time_event(
+            &mut event_loop,
+            0,
+            periodic_timer,
+            std::ptr::null_mut(),
+            None,
+        );
+        for _ in 0..5 {
+            run_once(&mut event_loop);
+            ae_advance_clock(&mut event_loop, 100_000);
+        }
+
+        let entries = ae_flight_entries(&event_loop);
+        let seqs: Vec<u64> = entries.iter().map(|entry| entry.seq).collect();
+        assert_eq!(seqs, vec![2, 3, 4]);
+        assert!(entries.iter().all(|entry| {
+            entry.source == AeDispatchSource::Timer { id }
+                && entry.outcome == AeFlightOutcome::Returned(100)
+        }));
+
+        /* Resizing starts over. */
+        ae_set_flight_recorder(&mut event_loop, 4);
+        assert!(ae_flight_entries(&event_loop).is_empty());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_panicked_dispatch() {
+        let (mut event_loop, control) = virtual_loop(64);
+        ae_set_flight_recorder(&mut event_loop, 8);
+        ae_set_panic_policy(&mut event_loop, AePanicPolicy::Continue);
+        ae_create_file_event(
+            &mut event_loop,
+            5,
+            AE_READABLE,
+            panicking,
+            std::ptr::null_mut(),
+        );
+
+        control.set_ready(5, AE_READABLE);
+        run_once(&mut event_loop);
+        let entries = ae_flight_entries(&event_loop);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].outcome, AeFlightOutcome::Panicked);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_nested_dispatches() {
+        let (mut event_loop, control) = virtual_loop(64);
+        ae_set_flight_recorder(&mut event_loop, 8);
+        let data = &control as *const MockControl as *mut c_void;
+        ae_create_file_event(&mut event_loop, 5, AE_READABLE, nesting, data);
+        let id = ae_create_time_event(&mut event_loop, 0, slow_timer, std::ptr::null_mut(), None);
+
+        /* The timer runs inside the file dispatch, which took as long. */
+        control.set_ready(5, AE_READABLE);
+        ae_process_events(&mut event_loop, rae::AE_FILE_EVENTS | AE_DONT_WAIT);
+        let entries = ae_flight_entries(&event_loop);
+        assert_eq!(entries.len(), 2);
+        assert!(matches!(
+            entries[0].source,
+            AeDispatchSource::File { fd: 5, .. }
+        ));
+        assert_eq!(entries[0].took_us, 5_000);
+        assert_eq!(entries[1].source, AeDispatchSource::Timer { id });
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_dump_shows_running_dispatch() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        ae_set_flight_recorder(&mut event_loop, 8);
+        let (mut reader, writer) = UnixStream::pair().unwrap();
+        ae_create_time_event(&mut event_loop, 0, slow_timer, std::ptr::null_mut(), None);
+        run_once(&mut event_loop);
+        let fd = writer.as_raw_fd() as usize as *mut c_void;
+        let id = ae_create_time_event(&mut event_loop, 0, dumping, fd, None);
+        run_once(&mut event_loop);
+        drop(writer);
+
+        let mut dump = String::new();
+        reader.read_to_string(&mut dump).unwrap();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", dump);
+        assert!(lines[0].starts_with("0 timer id="), "{}", dump);
+        assert!(
+            lines[0].ends_with("took_us=5000 returned=-1 refresh"),
+            "{}",
+            dump
+        );
+        assert_eq!(
+            lines[1],
+            format!("1 timer id={} started_us=5000 took_us=0 running", id)
+        );
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -0,0 +1,218 @@
+/* Fork Safety Tests
+ *
+ * Tests for ae_reinit_after_fork(): the child process recreates the
+ * backend and keeps receiving events for fds registered by the parent.
+ * Every check runs in the child, which reports through its exit status.
+ * Also tests for ae_spawn_child_loop() (ae/fork.rs), whose child starts
+ * from an emptied loop and reports through its status pipe.
+ */
+
+use rae::anet::anet_pipe;
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_OK, AE_READABLE, AeEventLoop, ae_create_event_loop,
+    ae_create_file_event, ae_get_handle, ae_process_events, ae_reinit_after_fork,
+};
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static READS: AtomicUsize = AtomicUsize::new(0);
+
+fn count_readable(_event_loop: &mut AeEventLoop, fd: i32, _client_data: *mut c_void, _mask: i32) {
+    let mut buf = [0u8; 64];
+    unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+    READS.fetch_add(1, Ordering::SeqCst);
+}
+
+/* Run `child` in a forked process and return its exit code. */
+fn run_in_child(child: impl FnOnce() -> i32) -> i32 {
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "fork failed");
+    if pid == 0 {
+        let code = child();
+        unsafe { libc::_exit(code) };
+    }
+    let mut status = 0;
+    unsafe { libc::waitpid(pid, &mut status, 0) };
+    assert!(libc::WIFEXITED(status), "Child did not exit normally");
+    libc::WEXITSTATUS(status)
+}
+
+mod reinit {
+    use super::*;
+
+    #[test]
+    fn test_child_keeps_file_events() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            count_readable,
+            std::ptr::null_mut(),
+        );
+        let parent_handle = ae_get_handle(&mut event_loop).expect("Failed to get handle");
+
+        let code = run_in_child(|| {
+            if ae_reinit_after_fork(&mut event_loop) != AE_OK {
+                return 1;
+            }
+            if !parent_handle.is_closed() {
+                return 2;
+            }
+
+            unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+            if READS.load(Ordering::SeqCst) != 1 {
+                return 3;
+            }
+
+            let handle = match ae_get_handle(&mut event_loop) {
+                Some(handle) => handle,
+                None => return 4,
+            };
+            if handle.same_loop(&parent_handle) || handle.stop() != AE_OK {
+                return 5;
+            }
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+            if !event_loop.stop {
+                return 6;
+            }
+            0
+        });
+        assert_eq!(code, 0, "Child check {} failed", code);
+
+        assert!(!parent_handle.is_closed(), "Parent handle is unaffected");
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+}
+
+mod spawn_child {
+    use super::*;
+    use rae::{
+        AE_NOMORE, AeEventLoopBuilder, ae_child_status_fd, ae_create_time_event,
+        ae_delete_file_event, ae_pending_time_events, ae_registered_file_events,
+        ae_spawn_child_loop, ae_stop, ae_wait_child_loop,
+    };
+
+    fn never(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+        AE_NOMORE
+    }
+
+    fn report(event_loop: &mut AeEventLoop, byte: u8) {
+        let fd = ae_child_status_fd(event_loop);
+        unsafe { libc::write(fd, [byte].as_ptr() as *const c_void, 1) };
+    }
+
+    /* Reports 'e' if the loop came empty, 'x' otherwise. */
+    fn check_empty(event_loop: &mut AeEventLoop) {
+        let empty =
+            ae_registered_file_events(event_loop) == 0 && ae_pending_time_events(event_loop) == 0;
+        report(event_loop, if empty { b'e' } else { b'x' });
+        ae_stop(event_loop);
+    }
+
+    fn report_from_timer(event_loop: &mut AeEventLoop, _id: i64, _data: *mut c_void) -> i32 {
+        report(event_loop, b't');
+        ae_stop(event_loop);
+        AE_NOMORE
+    }
+
+    fn start_timer(event_loop: &mut AeEventLoop) {
+        ae_create_time_event(event_loop, 1, report_from_timer, std::ptr::null_mut(), None);
+    }
+
+    fn read_status(fd: i32) -> Vec<u8> {
+        let mut status = Vec::new();
+        let mut buf = [0u8; 16];
+        loop {
+            let mut pfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            unsafe { libc::poll(&mut pfd, 1, 5000) };
+            let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+            if n <= 0 {
+                return status;
+            }
+            status.extend_from_slice(&buf[..n as usize]);
+        }
+    }
+
+    #[test]
+    fn test_child_starts_empty() {
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .best_backend()
+            .build()
+            .expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            count_readable,
+            std::ptr::null_mut(),
+        );
+        ae_create_time_event(&mut event_loop, 10_000, never, std::ptr::null_mut(), None);
+        let handle = ae_get_handle(&mut event_loop).expect("Failed to get handle");
+
+        let child = ae_spawn_child_loop(&mut event_loop, check_empty).expect("Failed to fork");
+        assert_eq!(read_status(child.status_fd), b"e");
+        assert_eq!(ae_wait_child_loop(child), 0);
+
+        /* The parent kept its registrations, the wakeup pipe included. */
+        assert_eq!(handle.stop(), AE_OK);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert!(event_loop.stop);
+        ae_delete_file_event(&mut event_loop, rfd, AE_READABLE);
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_child_drops_error_queue_handlers() {
+        use rae::AE_ERRQUEUE;
+
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .best_backend()
+            .build()
+            .expect("Failed to create event loop");
+        if event_loop.api_name() != "epoll" {
+            return;
+        }
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_ERRQUEUE,
+            count_readable,
+            std::ptr::null_mut(),
+        );
+
+        let child = ae_spawn_child_loop(&mut event_loop, check_empty).expect("Failed to fork");
+        assert_eq!(read_status(child.status_fd), b"e");
+        assert_eq!(ae_wait_child_loop(child), 0);
+
+        ae_delete_file_event(&mut event_loop, rfd, AE_ERRQUEUE);
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+
+    #[test]
+    fn test_child_runs_its_loop() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        assert_eq!(ae_child_status_fd(&event_loop), -1);
+        let child = ae_spawn_child_loop(&mut event_loop, start_timer).expect("Failed to fork");
+        assert_eq!(read_status(child.status_fd), b"t");
+        assert_eq!(ae_wait_child_loop(child), 0);
+    }
+}
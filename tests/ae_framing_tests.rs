@@ -0,0 +1,203 @@
+/* Framing Tests
+ *
+ * Tests for the line and length-prefixed codecs (ae/framing.rs): frames
+ * split at every possible byte, size limits, and framed connections over
+ * Unix socket pairs.
+ */
+
+use rae::{
+    AE_ERR, AE_OK, AeEventLoop, AeFraming, ae_conn_close, ae_create_event_loop,
+    ae_delete_event_loop, ae_framed_create, ae_framed_write, frame_decode, frame_encode,
+};
+use std::ffi::c_void;
+use std::io::{Read, Write};
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+const LINES: AeFraming = AeFraming::Lines { max_len: 16 };
+const PREFIXED: AeFraming = AeFraming::LengthPrefixed { max_len: 16 };
+
+/* Every frame in `buf`, and the bytes left over. */
+fn decode_all(framing: AeFraming, buf: &[u8]) -> (Vec<Vec<u8>>, usize) {
+    let mut frames = Vec::new();
+    let mut pos = 0;
+    while let Some((payload, len)) = frame_decode(framing, &buf[pos..]).expect("Bad frame") {
+        frames.push(buf[pos..][payload].to_vec());
+        pos += len;
+    }
+    (frames, buf.len() - pos)
+}
+
+fn encode(framing: AeFraming, frames: &[&[u8]]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for frame in frames {
+        assert_eq!(frame_encode(framing, frame, &mut out), AE_OK);
+    }
+    out
+}
+
+mod codec {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        let (frames, rest) = decode_all(LINES, b"one\r\ntwo\n\nthr");
+        assert_eq!(frames, [b"one".to_vec(), b"two".to_vec(), b"".to_vec()]);
+        assert_eq!(rest, 3);
+        assert_eq!(encode(LINES, &[b"a", b""]), b"a\n\n");
+    }
+
+    #[test]
+    fn test_length_prefixed() {
+        let bytes = encode(PREFIXED, &[b"hello", b"", b"\n\0"]);
+        assert_eq!(&bytes[..9], b"\0\0\0\x05hello");
+        let (frames, rest) = decode_all(PREFIXED, &bytes);
+        assert_eq!(frames, [b"hello".to_vec(), b"".to_vec(), b"\n\0".to_vec()]);
+        assert_eq!(rest, 0);
+    }
+
+    #[test]
+    fn test_every_split_point() {
+        for framing in [LINES, PREFIXED] {
+            let bytes = encode(framing, &[b"first", b"second frame"]);
+            for split in 0..bytes.len() {
+                /* The frames before the split are whole, the rest waits. */
+                let (head, rest) = decode_all(framing, &bytes[..split]);
+                let mut buf = bytes[split - rest..split].to_vec();
+                buf.extend_from_slice(&bytes[split..]);
+                let (tail, rest) = decode_all(framing, &buf);
+                assert_eq!(rest, 0);
+                assert_eq!(
+                    [head, tail].concat(),
+                    [b"first".to_vec(), b"second frame".to_vec()]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        /* 16 bytes fit, with or without "\r". */
+        assert!(frame_decode(LINES, &[b'x'; 17]).unwrap().is_none());
+        assert!(
+            frame_decode(LINES, b"0123456789abcdef\r\n")
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(frame_decode(LINES, &[b'x'; 18]), Err(libc::EMSGSIZE));
+        assert_eq!(
+            frame_decode(LINES, b"0123456789abcdefg\n"),
+            Err(libc::EMSGSIZE)
+        );
+
+        /* The header alone is enough to refuse a frame. */
+        assert_eq!(frame_decode(PREFIXED, b"\0\0\0\x11"), Err(libc::EMSGSIZE));
+
+        let mut out = Vec::new();
+        assert_eq!(frame_encode(LINES, b"a\nb", &mut out), AE_ERR);
+        assert_eq!(frame_encode(PREFIXED, &[0; 17], &mut out), AE_ERR);
+        assert!(out.is_empty());
+    }
+}
+
+mod connections {
+    use super::*;
+    use rae::test_util::pump;
+
+    #[derive(Default)]
+    struct Peer {
+        frames: Vec<Vec<u8>>,
+        closed: Option<i32>,
+    }
+
+    fn peer<'a>(client_data: *mut c_void) -> &'a mut Peer {
+        unsafe { &mut *(client_data as *mut Peer) }
+    }
+
+    /* Echoes every frame back, upper-cased. */
+    fn shout(event_loop: &mut AeEventLoop, fd: i32, frame: &[u8], client_data: *mut c_void) {
+        peer(client_data).frames.push(frame.to_vec());
+        assert_eq!(
+            ae_framed_write(event_loop, fd, &frame.to_ascii_uppercase()),
+            AE_OK
+        );
+    }
+
+    fn on_close(_event_loop: &mut AeEventLoop, _fd: i32, err: i32, client_data: *mut c_void) {
+        peer(client_data).closed = Some(err);
+    }
+
+    fn framed(
+        event_loop: &mut AeEventLoop,
+        framing: AeFraming,
+        peer: &mut Peer,
+    ) -> (i32, UnixStream) {
+        let (ours, theirs) = UnixStream::pair().expect("Failed to create socket pair");
+        ours.set_nonblocking(true).unwrap();
+        theirs
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let fd = ours.into_raw_fd();
+        let result = ae_framed_create(
+            event_loop,
+            fd,
+            framing,
+            shout,
+            Some(on_close),
+            peer as *mut Peer as *mut c_void,
+        );
+        assert_eq!(result, AE_OK);
+        (fd, theirs)
+    }
+
+    #[test]
+    fn test_frames_across_reads() {
+        for framing in [LINES, PREFIXED] {
+            let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+            let mut peer = Peer::default();
+            let (fd, mut theirs) = framed(&mut event_loop, framing, &mut peer);
+
+            /* Byte by byte, then several frames in one write. */
+            let bytes = encode(framing, &[b"ab", b"cd", b"ef"]);
+            let split = encode(framing, &[b"ab"]).len();
+            for &byte in &bytes[..split] {
+                theirs.write_all(&[byte]).unwrap();
+                pump(&mut event_loop);
+            }
+            theirs.write_all(&bytes[split..]).unwrap();
+            pump(&mut event_loop);
+
+            assert_eq!(
+                peer.frames,
+                [b"ab".to_vec(), b"cd".to_vec(), b"ef".to_vec()]
+            );
+            let mut reply = vec![0; bytes.len()];
+            theirs.read_exact(&mut reply).unwrap();
+            assert_eq!(reply, encode(framing, &[b"AB", b"CD", b"EF"]));
+
+            ae_conn_close(&mut event_loop, fd);
+            assert_eq!(peer.closed, Some(0));
+            ae_delete_event_loop(event_loop);
+        }
+    }
+
+    #[test]
+    fn test_oversized_frame_closes() {
+        for (framing, garbage) in [(LINES, vec![b'x'; 64]), (PREFIXED, b"\0\0\x10\0".to_vec())] {
+            let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+            let mut peer = Peer::default();
+            let (_fd, mut theirs) = framed(&mut event_loop, framing, &mut peer);
+
+            /* The frame before it is still delivered. */
+            let mut bytes = encode(framing, &[b"ok"]);
+            bytes.extend_from_slice(&garbage);
+            theirs.write_all(&bytes).unwrap();
+            pump(&mut event_loop);
+
+            assert_eq!(peer.frames, [b"ok".to_vec()]);
+            assert_eq!(peer.closed, Some(libc::EMSGSIZE));
+            ae_delete_event_loop(event_loop);
+        }
+    }
+}
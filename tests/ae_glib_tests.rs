@@ -0,0 +1,170 @@
+/* GLib Tests
+ *
+ * Tests for ae_glib_host() (ae/glib.rs, `glib` feature): GLib timeout and
+ * fd sources attached to a private GMainContext, run by the loop.
+ */
+
+#![cfg(feature = "glib")]
+
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_OK, AE_READABLE, ae_create_event_loop, ae_delete_event_loop,
+    ae_get_file_events, ae_glib_host, ae_process_events, ae_remove_external_source,
+};
+use std::ffi::c_void;
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+const G_IO_IN: u32 = 1;
+
+type GSourceFunc = unsafe extern "C" fn(data: *mut c_void) -> i32;
+type GUnixFDSourceFunc = unsafe extern "C" fn(fd: i32, condition: u32, data: *mut c_void) -> i32;
+
+#[link(name = "glib-2.0")]
+unsafe extern "C" {
+    fn g_main_context_new() -> *mut c_void;
+    fn g_main_context_unref(context: *mut c_void);
+    fn g_main_context_acquire(context: *mut c_void) -> i32;
+    fn g_main_context_release(context: *mut c_void);
+    fn g_timeout_source_new(interval_ms: u32) -> *mut c_void;
+    fn g_unix_fd_source_new(fd: i32, condition: u32) -> *mut c_void;
+    fn g_source_set_callback(
+        source: *mut c_void,
+        func: *const c_void,
+        data: *mut c_void,
+        notify: *const c_void,
+    );
+    fn g_source_attach(source: *mut c_void, context: *mut c_void) -> u32;
+    fn g_source_unref(source: *mut c_void);
+}
+
+/* Attach `source` to `context` with `func` as its callback. */
+fn attach(context: *mut c_void, source: *mut c_void, func: *const c_void, data: *mut c_void) {
+    unsafe {
+        g_source_set_callback(source, func, data, std::ptr::null());
+        g_source_attach(source, context);
+        g_source_unref(source);
+    }
+}
+
+unsafe extern "C" fn count_once(data: *mut c_void) -> i32 {
+    unsafe { *(data as *mut u32) += 1 };
+    0 /* G_SOURCE_REMOVE */
+}
+
+unsafe extern "C" fn read_fd(fd: i32, condition: u32, data: *mut c_void) -> i32 {
+    let received = unsafe { &mut *(data as *mut Vec<u8>) };
+    assert_ne!(condition & G_IO_IN, 0);
+    let mut buf = [0u8; 64];
+    let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+    received.extend_from_slice(&buf[..n.max(0) as usize]);
+    1 /* G_SOURCE_CONTINUE */
+}
+
+mod sources {
+    use super::*;
+
+    #[test]
+    fn test_timeout_source_fires() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let context = unsafe { g_main_context_new() };
+        let mut fired = 0u32;
+        let timeout = unsafe { g_timeout_source_new(20) };
+        let func = count_once as GSourceFunc as *const c_void;
+        attach(
+            context,
+            timeout,
+            func,
+            &mut fired as *mut u32 as *mut c_void,
+        );
+        let id = ae_glib_host(&mut event_loop, context).expect("Context owned elsewhere");
+
+        /* The loop sleeps until the GLib timeout, nothing else wakes it. */
+        let start = Instant::now();
+        for _ in 0..10 {
+            if fired == 1 {
+                break;
+            }
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        }
+        assert_eq!(fired, 1);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        assert_eq!(ae_remove_external_source(&mut event_loop, id), AE_OK);
+        unsafe { g_main_context_unref(context) };
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_fd_source_is_watched_by_the_loop() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let context = unsafe { g_main_context_new() };
+        let (ours, mut theirs) = UnixStream::pair().expect("Failed to create socket pair");
+        let fd = ours.as_raw_fd();
+        let mut received = Vec::new();
+        let source = unsafe { g_unix_fd_source_new(fd, G_IO_IN) };
+        let func = read_fd as GUnixFDSourceFunc as *const c_void;
+        attach(
+            context,
+            source,
+            func,
+            &mut received as *mut Vec<u8> as *mut c_void,
+        );
+        let id = ae_glib_host(&mut event_loop, context).expect("Context owned elsewhere");
+
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(ae_get_file_events(&event_loop, fd), AE_READABLE);
+        assert!(received.is_empty());
+
+        theirs.write_all(b"signal").unwrap();
+        for _ in 0..10 {
+            if !received.is_empty() {
+                break;
+            }
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        }
+        assert_eq!(received, b"signal");
+
+        assert_eq!(ae_remove_external_source(&mut event_loop, id), AE_OK);
+        assert_eq!(ae_get_file_events(&event_loop, fd), 0);
+        unsafe { g_main_context_unref(context) };
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod ownership {
+    use super::*;
+
+    #[test]
+    fn test_context_owned_by_another_thread() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let context = unsafe { g_main_context_new() } as usize;
+        let (acquired_tx, acquired_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        let owner = std::thread::spawn(move || {
+            assert_ne!(unsafe { g_main_context_acquire(context as *mut c_void) }, 0);
+            acquired_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+            unsafe { g_main_context_release(context as *mut c_void) };
+        });
+        acquired_rx.recv().unwrap();
+        assert!(ae_glib_host(&mut event_loop, context as *mut c_void).is_none());
+        release_tx.send(()).unwrap();
+        owner.join().unwrap();
+
+        /* Free again, and given back once removed. */
+        let id = ae_glib_host(&mut event_loop, context as *mut c_void).expect("Context not free");
+        ae_remove_external_source(&mut event_loop, id);
+        let reacquired = std::thread::spawn(move || {
+            let owned = unsafe { g_main_context_acquire(context as *mut c_void) } != 0;
+            if owned {
+                unsafe { g_main_context_release(context as *mut c_void) };
+            }
+            owned
+        });
+        assert!(reacquired.join().unwrap());
+        unsafe { g_main_context_unref(context as *mut c_void) };
+        ae_delete_event_loop(event_loop);
+    }
+}
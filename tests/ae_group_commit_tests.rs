@@ -0,0 +1,196 @@
+/* Group Commit Tests
+ *
+ * Tests for ae_group_commit_enable() and ae_group_commit_reply()
+ * (ae/group_commit.rs): replies queued during an iteration share one sync
+ * of the log, their write handlers only run after it, and replies for
+ * clients gone in the meantime are dropped.
+ */
+
+use rae::test_util::{FdPair, run_iterations, socketpair};
+use rae::{
+    AE_ALL_EVENTS, AE_CALL_BEFORE_SLEEP, AE_ERR, AE_OK, AE_READABLE, AE_WRITABLE, AeEventLoop,
+    ae_create_event_loop, ae_create_file_event, ae_delete_file_event, ae_get_file_events,
+    ae_group_commit_disable, ae_group_commit_enable, ae_group_commit_pending,
+    ae_group_commit_reply, ae_group_commit_stats,
+};
+use std::ffi::c_void;
+use std::os::unix::io::AsRawFd;
+
+fn log_file(name: &str) -> std::fs::File {
+    let path =
+        std::env::temp_dir().join(format!("rae-group-commit-{}-{}", std::process::id(), name));
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(&path)
+        .expect("Failed to create log");
+    let _ = std::fs::remove_file(&path);
+    file
+}
+
+fn ignore(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+/* Records the commits seen when called, then stops writing. */
+fn reply(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let commits = ae_group_commit_stats(event_loop).map_or(0, |stats| stats.commits);
+    unsafe { (*(client_data as *mut Vec<(i32, u64)>)).push((fd, commits)) };
+    ae_delete_file_event(event_loop, fd, AE_WRITABLE);
+}
+
+fn client(event_loop: &mut AeEventLoop) -> FdPair {
+    let pair = socketpair();
+    ae_create_file_event(
+        event_loop,
+        pair.a,
+        AE_READABLE,
+        ignore,
+        std::ptr::null_mut(),
+    );
+    pair
+}
+
+mod commit {
+    use super::*;
+
+    #[test]
+    fn test_one_sync_per_iteration() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let log = log_file("one-sync");
+        assert_eq!(
+            ae_group_commit_enable(&mut event_loop, log.as_raw_fd()),
+            AE_OK
+        );
+        let clients = [client(&mut event_loop), client(&mut event_loop)];
+        let mut calls: Vec<(i32, u64)> = Vec::new();
+        let data = &mut calls as *mut Vec<(i32, u64)> as *mut c_void;
+
+        for pair in &clients {
+            assert_eq!(
+                ae_group_commit_reply(&mut event_loop, pair.a, reply, data),
+                AE_OK
+            );
+            /* Held until the sync. */
+            assert_eq!(ae_get_file_events(&event_loop, pair.a), AE_READABLE);
+        }
+        assert_eq!(ae_group_commit_pending(&event_loop), 2);
+
+        run_iterations(&mut event_loop, AE_ALL_EVENTS | AE_CALL_BEFORE_SLEEP, 1);
+        let stats = ae_group_commit_stats(&event_loop).unwrap();
+        assert_eq!((stats.commits, stats.replies), (1, 2));
+        assert_eq!(ae_group_commit_pending(&event_loop), 0);
+
+        /* Both handlers ran after the single sync. */
+        let mut expected = vec![(clients[0].a, 1), (clients[1].a, 1)];
+        expected.sort();
+        calls.sort();
+        assert_eq!(calls, expected);
+
+        /* Nothing waiting: no sync. */
+        run_iterations(&mut event_loop, AE_ALL_EVENTS | AE_CALL_BEFORE_SLEEP, 1);
+        assert_eq!(ae_group_commit_stats(&event_loop).unwrap().commits, 1);
+        for pair in &clients {
+            ae_delete_file_event(&mut event_loop, pair.a, AE_READABLE);
+        }
+    }
+
+    #[test]
+    fn test_gone_client_is_dropped() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let log = log_file("gone");
+        ae_group_commit_enable(&mut event_loop, log.as_raw_fd());
+        let pair = client(&mut event_loop);
+        let mut calls: Vec<(i32, u64)> = Vec::new();
+        let data = &mut calls as *mut Vec<(i32, u64)> as *mut c_void;
+        ae_group_commit_reply(&mut event_loop, pair.a, reply, data);
+
+        /* The client went away and its fd number got registered again. */
+        ae_delete_file_event(&mut event_loop, pair.a, AE_READABLE);
+        ae_create_file_event(
+            &mut event_loop,
+            pair.a,
+            AE_READABLE,
+            ignore,
+            std::ptr::null_mut(),
+        );
+
+        run_iterations(&mut event_loop, AE_ALL_EVENTS | AE_CALL_BEFORE_SLEEP, 1);
+        let stats = ae_group_commit_stats(&event_loop).unwrap();
+        assert_eq!((stats.commits, stats.replies, stats.dropped), (1, 0, 1));
+        assert!(calls.is_empty());
+        assert_eq!(ae_get_file_events(&event_loop, pair.a), AE_READABLE);
+        ae_delete_file_event(&mut event_loop, pair.a, AE_READABLE);
+    }
+
+    #[test]
+    fn test_failed_sync_keeps_replies() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        /* A pipe cannot be synced. */
+        let (rfd, wfd) = rae::anet::anet_pipe(true).expect("Failed to create pipe");
+        ae_group_commit_enable(&mut event_loop, wfd);
+        let pair = client(&mut event_loop);
+        let mut calls: Vec<(i32, u64)> = Vec::new();
+        let data = &mut calls as *mut Vec<(i32, u64)> as *mut c_void;
+        ae_group_commit_reply(&mut event_loop, pair.a, reply, data);
+
+        run_iterations(&mut event_loop, AE_ALL_EVENTS | AE_CALL_BEFORE_SLEEP, 1);
+        let stats = ae_group_commit_stats(&event_loop).unwrap();
+        assert_eq!((stats.commits, stats.failures), (0, 1));
+        assert_eq!(ae_group_commit_pending(&event_loop), 1);
+        assert!(calls.is_empty());
+
+        /* A log that can be synced: released on the next iteration. */
+        let log = log_file("failed");
+        ae_group_commit_enable(&mut event_loop, log.as_raw_fd());
+        run_iterations(&mut event_loop, AE_ALL_EVENTS | AE_CALL_BEFORE_SLEEP, 1);
+        assert_eq!(calls, vec![(pair.a, 1)]);
+        ae_delete_file_event(&mut event_loop, pair.a, AE_READABLE);
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+}
+
+mod setup {
+    use super::*;
+
+    #[test]
+    fn test_reply_requires_enable_and_registration() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let pair = client(&mut event_loop);
+        assert_eq!(
+            ae_group_commit_reply(&mut event_loop, pair.a, reply, std::ptr::null_mut()),
+            AE_ERR
+        );
+        assert!(ae_group_commit_stats(&event_loop).is_none());
+
+        let log = log_file("setup");
+        assert_eq!(ae_group_commit_enable(&mut event_loop, -1), AE_ERR);
+        ae_group_commit_enable(&mut event_loop, log.as_raw_fd());
+        /* Not registered. */
+        assert_eq!(
+            ae_group_commit_reply(&mut event_loop, pair.b, reply, std::ptr::null_mut()),
+            AE_ERR
+        );
+        ae_delete_file_event(&mut event_loop, pair.a, AE_READABLE);
+    }
+
+    #[test]
+    fn test_disable_releases_pending() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let log = log_file("disable");
+        ae_group_commit_enable(&mut event_loop, log.as_raw_fd());
+        let pair = client(&mut event_loop);
+        let mut calls: Vec<(i32, u64)> = Vec::new();
+        let data = &mut calls as *mut Vec<(i32, u64)> as *mut c_void;
+        ae_group_commit_reply(&mut event_loop, pair.a, reply, data);
+
+        assert_eq!(ae_group_commit_disable(&mut event_loop), AE_OK);
+        assert!(ae_group_commit_stats(&event_loop).is_none());
+        assert_ne!(ae_get_file_events(&event_loop, pair.a) & AE_WRITABLE, 0);
+        run_iterations(&mut event_loop, AE_ALL_EVENTS | AE_CALL_BEFORE_SLEEP, 1);
+        assert_eq!(calls.len(), 1);
+        ae_delete_file_event(&mut event_loop, pair.a, AE_READABLE);
+    }
+}
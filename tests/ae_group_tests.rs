@@ -0,0 +1,144 @@
+/* File Event Group Tests
+ *
+ * Tests for grouped registration and teardown of file events
+ * (ae/group.rs).
+ */
+
+use rae::anet::anet_pipe;
+use rae::{
+    AE_ERR, AE_NONE, AE_OK, AE_READABLE, AE_WRITABLE, AeEventLoop, FileProc, ae_create_event_loop,
+    ae_create_file_event, ae_delete_event_loop, ae_delete_file_event, ae_delete_group,
+    ae_get_file_events, ae_group_fds, ae_register_group,
+};
+use std::ffi::c_void;
+
+fn noop(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+fn other_noop(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+fn entry(fd: i32, mask: i32) -> (i32, i32, FileProc, *mut c_void) {
+    (fd, mask, noop, std::ptr::null_mut())
+}
+
+struct Pipes(Vec<(i32, i32)>);
+
+impl Pipes {
+    fn new(n: usize) -> Self {
+        Pipes(
+            (0..n)
+                .map(|_| anet_pipe(true).expect("Failed to create pipe"))
+                .collect(),
+        )
+    }
+}
+
+impl Drop for Pipes {
+    fn drop(&mut self) {
+        for &(rfd, wfd) in &self.0 {
+            unsafe {
+                libc::close(rfd);
+                libc::close(wfd);
+            }
+        }
+    }
+}
+
+mod group {
+    use super::*;
+
+    #[test]
+    fn test_register_and_delete() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let pipes = Pipes::new(2);
+        let (r0, w0) = pipes.0[0];
+        let (r1, _) = pipes.0[1];
+        let entries = [
+            entry(r0, AE_READABLE),
+            entry(r1, AE_READABLE),
+            entry(w0, AE_WRITABLE),
+            (r0, AE_WRITABLE, other_noop, std::ptr::null_mut()),
+        ];
+
+        let id = ae_register_group(&mut event_loop, &entries).expect("Failed to register");
+        assert_eq!(
+            ae_get_file_events(&event_loop, r0),
+            AE_READABLE | AE_WRITABLE
+        );
+        let mut fds = ae_group_fds(&event_loop, id);
+        fds.sort();
+        let mut expected = vec![r0, r1, w0];
+        expected.sort();
+        assert_eq!(fds, expected);
+
+        assert_eq!(ae_delete_group(&mut event_loop, id), AE_OK);
+        for fd in [r0, r1, w0] {
+            assert_eq!(ae_get_file_events(&event_loop, fd), AE_NONE);
+        }
+        assert_eq!(ae_delete_group(&mut event_loop, id), AE_ERR);
+        assert!(ae_group_fds(&event_loop, id).is_empty());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_all_or_nothing() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let pipes = Pipes::new(1);
+        let (rfd, _) = pipes.0[0];
+
+        /* The second entry is beyond the set size. */
+        let entries = [entry(rfd, AE_READABLE), entry(100, AE_READABLE)];
+        assert!(ae_register_group(&mut event_loop, &entries).is_none());
+        assert_eq!(ae_get_file_events(&event_loop, rfd), AE_NONE);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_rejects_registered_fds() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let pipes = Pipes::new(2);
+        let (r0, _) = pipes.0[0];
+        let (r1, _) = pipes.0[1];
+        ae_create_file_event(
+            &mut event_loop,
+            r1,
+            AE_READABLE,
+            other_noop,
+            std::ptr::null_mut(),
+        );
+
+        let entries = [entry(r0, AE_READABLE), entry(r1, AE_WRITABLE)];
+        assert!(ae_register_group(&mut event_loop, &entries).is_none());
+        assert_eq!(ae_get_file_events(&event_loop, r0), AE_NONE);
+        assert_eq!(ae_get_file_events(&event_loop, r1), AE_READABLE);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_delete_skips_reused_fds() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let pipes = Pipes::new(2);
+        let (r0, _) = pipes.0[0];
+        let (r1, _) = pipes.0[1];
+        let id = ae_register_group(
+            &mut event_loop,
+            &[entry(r0, AE_READABLE), entry(r1, AE_READABLE)],
+        )
+        .unwrap();
+
+        /* r1 is released and taken over by another component. */
+        ae_delete_file_event(&mut event_loop, r1, AE_READABLE);
+        ae_create_file_event(
+            &mut event_loop,
+            r1,
+            AE_READABLE,
+            other_noop,
+            std::ptr::null_mut(),
+        );
+        assert_eq!(ae_group_fds(&event_loop, id), vec![r0]);
+
+        assert_eq!(ae_delete_group(&mut event_loop, id), AE_OK);
+        assert_eq!(ae_get_file_events(&event_loop, r0), AE_NONE);
+        assert_eq!(ae_get_file_events(&event_loop, r1), AE_READABLE);
+        ae_delete_event_loop(event_loop);
+    }
+}
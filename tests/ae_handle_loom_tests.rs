@@ -0,0 +1,170 @@
+/* Handle Loom Models
+ *
+ * Models of the cross-thread handle (ae/handle.rs) checked with loom,
+ * which runs each of them under every interleaving of the threads at the
+ * queue lock. The pipe itself is real: a lost wakeup shows up as a task
+ * still queued while the pipe is empty. Only built with `--cfg loom`:
+ *
+ *     RUSTFLAGS="--cfg loom" cargo test --release --test ae_handle_loom_tests
+ */
+
+#![cfg(loom)]
+
+use loom::sync::atomic::{AtomicUsize, Ordering};
+use loom::sync::{Arc, Mutex};
+use loom::thread;
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_ERR, AE_OK, ae_create_event_loop, ae_delete_event_loop,
+    ae_get_handle, ae_process_events,
+};
+
+const NOWAIT: i32 = AE_ALL_EVENTS | AE_DONT_WAIT;
+
+mod wakeup {
+    use super::*;
+
+    #[test]
+    fn test_post_is_never_lost() {
+        loom::model(|| {
+            let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+            let handle = ae_get_handle(&mut event_loop).expect("Failed to create handle");
+            let ran = Arc::new(AtomicUsize::new(0));
+
+            let poster = {
+                let ran = ran.clone();
+                thread::spawn(move || {
+                    let ret = handle.post(move |_| {
+                        ran.fetch_add(1, Ordering::SeqCst);
+                    });
+                    assert_eq!(ret, AE_OK);
+                })
+            };
+            /* Races with the post: may or may not see the task. */
+            ae_process_events(&mut event_loop, NOWAIT);
+            poster.join().unwrap();
+
+            /* If the task is still queued, its wakeup byte is in the pipe. */
+            ae_process_events(&mut event_loop, NOWAIT);
+            assert_eq!(ran.load(Ordering::SeqCst), 1);
+            ae_delete_event_loop(event_loop);
+        });
+    }
+
+    #[test]
+    fn test_wakeup_after_drain_is_not_coalesced_away() {
+        loom::model(|| {
+            let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+            let handle = ae_get_handle(&mut event_loop).expect("Failed to create handle");
+            let ran = Arc::new(AtomicUsize::new(0));
+
+            /* One wakeup in flight before the race starts. */
+            let first = ran.clone();
+            handle.post(move |_| {
+                first.fetch_add(1, Ordering::SeqCst);
+            });
+
+            let poster = {
+                let ran = ran.clone();
+                thread::spawn(move || {
+                    handle.post(move |_| {
+                        ran.fetch_add(1, Ordering::SeqCst);
+                    });
+                })
+            };
+            ae_process_events(&mut event_loop, NOWAIT);
+            poster.join().unwrap();
+
+            ae_process_events(&mut event_loop, NOWAIT);
+            assert_eq!(ran.load(Ordering::SeqCst), 2);
+            ae_delete_event_loop(event_loop);
+        });
+    }
+}
+
+mod ordering {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_posters_keep_their_order() {
+        loom::model(|| {
+            let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+            let handle = ae_get_handle(&mut event_loop).expect("Failed to create handle");
+            let log = Arc::new(Mutex::new(Vec::new()));
+
+            let posters: Vec<_> = ["a", "b"]
+                .into_iter()
+                .map(|name| {
+                    let handle = handle.clone();
+                    let log = log.clone();
+                    thread::spawn(move || {
+                        for n in 0..2 {
+                            let log = log.clone();
+                            handle.post(move |_| log.lock().unwrap().push((name, n)));
+                        }
+                    })
+                })
+                .collect();
+            ae_process_events(&mut event_loop, NOWAIT);
+            for poster in posters {
+                poster.join().unwrap();
+            }
+            ae_process_events(&mut event_loop, NOWAIT);
+
+            /* Every task ran once, each poster's in its own order. */
+            let log = log.lock().unwrap();
+            assert_eq!(log.len(), 4);
+            for name in ["a", "b"] {
+                let runs: Vec<i32> = log.iter().filter(|e| e.0 == name).map(|e| e.1).collect();
+                assert_eq!(runs, [0, 1]);
+            }
+            drop(log);
+            ae_delete_event_loop(event_loop);
+        });
+    }
+}
+
+mod shutdown {
+    use super::*;
+
+    /* Counts the drops of the closure owning it. */
+    struct Token(Arc<AtomicUsize>);
+
+    impl Drop for Token {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_post_racing_loop_deletion() {
+        loom::model(|| {
+            let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+            let handle = ae_get_handle(&mut event_loop).expect("Failed to create handle");
+            let ran = Arc::new(AtomicUsize::new(0));
+            let dropped = Arc::new(AtomicUsize::new(0));
+
+            let poster = {
+                let ran = ran.clone();
+                let token = Token(dropped.clone());
+                thread::spawn(move || {
+                    let ret = handle.post(move |_| {
+                        let _token = token;
+                        ran.fetch_add(1, Ordering::SeqCst);
+                    });
+                    /* A closed handle refuses the task instead of writing
+                     * to the closed pipe. */
+                    if ret == AE_ERR {
+                        assert!(handle.is_closed());
+                    }
+                })
+            };
+            ae_delete_event_loop(event_loop);
+            poster.join().unwrap();
+
+            /* Never run, since the loop is gone, and released exactly
+             * once whichever side won. */
+            assert_eq!(ran.load(Ordering::SeqCst), 0);
+            assert_eq!(dropped.load(Ordering::SeqCst), 1);
+        });
+    }
+}
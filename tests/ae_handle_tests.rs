@@ -0,0 +1,164 @@
+/* Loop Handle Tests
+ *
+ * Tests for cross-thread loop handles (ae/handle.rs) and the process-wide
+ * registry of named handles (ae/registry.rs).
+ */
+
+use rae::ae::registry;
+use rae::{
+    AE_ALL_EVENTS, AE_ERR, AE_NOMORE, AE_OK, AeEventLoop, ae_create_event_loop,
+    ae_create_time_event, ae_delete_event_loop, ae_get_dont_wait, ae_get_handle, ae_main,
+    ae_process_events, ae_stop,
+};
+use std::ffi::c_void;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod handle {
+    use super::*;
+
+    #[test]
+    fn test_post_from_other_thread() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let handle = ae_get_handle(&mut event_loop).expect("Failed to create handle");
+        let counter = Arc::new(AtomicI32::new(0));
+
+        let poster = {
+            let counter = counter.clone();
+            thread::spawn(move || {
+                for _ in 0..10 {
+                    let counter = counter.clone();
+                    assert_eq!(
+                        handle.post(move |_el: &mut AeEventLoop| {
+                            counter.fetch_add(1, Ordering::SeqCst);
+                        }),
+                        AE_OK
+                    );
+                }
+                handle.post(ae_stop);
+            })
+        };
+
+        ae_main(&mut event_loop);
+        poster.join().unwrap();
+
+        assert_eq!(
+            counter.load(Ordering::SeqCst),
+            10,
+            "All tasks should run in order before stop"
+        );
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_handle_is_shared() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let first = ae_get_handle(&mut event_loop).unwrap();
+        let second = ae_get_handle(&mut event_loop).unwrap();
+        assert!(first.same_loop(&second));
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_post_after_loop_deleted() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let handle = ae_get_handle(&mut event_loop).unwrap();
+        assert!(!handle.is_closed());
+
+        ae_delete_event_loop(event_loop);
+
+        assert!(handle.is_closed());
+        assert_eq!(handle.post(|_el: &mut AeEventLoop| {}), AE_ERR);
+        assert_eq!(handle.wakeup(), AE_ERR);
+    }
+
+    #[test]
+    fn test_stop_from_other_thread() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let handle = ae_get_handle(&mut event_loop).unwrap();
+
+        let stopper = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(20));
+            assert_eq!(handle.stop(), AE_OK);
+        });
+
+        ae_main(&mut event_loop);
+        stopper.join().unwrap();
+        ae_delete_event_loop(event_loop);
+    }
+
+    fn far_timer(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+        AE_NOMORE
+    }
+
+    #[test]
+    fn test_set_dont_wait_from_other_thread() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let handle = ae_get_handle(&mut event_loop).unwrap();
+        ae_create_time_event(
+            &mut event_loop,
+            10_000,
+            far_timer,
+            std::ptr::null_mut(),
+            None,
+        );
+
+        let setter = {
+            let handle = handle.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                assert_eq!(handle.set_dont_wait(true), AE_OK);
+            })
+        };
+        /* Woken up out of the 10s sleep. */
+        let start = Instant::now();
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        setter.join().unwrap();
+        assert!(ae_get_dont_wait(&event_loop));
+
+        /* And no longer blocking. */
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        assert_eq!(handle.set_dont_wait(false), AE_OK);
+        assert!(!ae_get_dont_wait(&event_loop));
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod named_registry {
+    use super::*;
+
+    #[test]
+    fn test_register_and_lookup() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let handle = ae_get_handle(&mut event_loop).unwrap();
+
+        assert!(registry::register("registry-test-main", handle.clone()).is_none());
+        assert!(registry::names().contains(&"registry-test-main".to_string()));
+
+        let found = thread::spawn(|| registry::lookup("registry-test-main"))
+            .join()
+            .unwrap()
+            .expect("Handle should be found from another thread");
+        assert!(found.same_loop(&handle));
+
+        assert!(registry::unregister("registry-test-main").is_some());
+        assert!(registry::lookup("registry-test-main").is_none());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_lookup_skips_deleted_loops() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let handle = ae_get_handle(&mut event_loop).unwrap();
+        registry::register("registry-test-dead", handle);
+
+        ae_delete_event_loop(event_loop);
+
+        assert!(registry::lookup("registry-test-dead").is_none());
+        assert!(!registry::names().contains(&"registry-test-dead".to_string()));
+    }
+}
@@ -0,0 +1,101 @@
+/* Heartbeat Tests
+ *
+ * Tests for the loop heartbeat pipe (ae/heartbeat.rs) and the monitor
+ * thread alarming when the loop stops beating.
+ */
+
+use rae::{
+    AE_ALL_EVENTS, ae_create_event_loop, ae_disable_heartbeat, ae_enable_heartbeat,
+    ae_process_events,
+};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+mod beats {
+    use super::*;
+
+    #[test]
+    fn test_idle_loop_keeps_beating() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        let heartbeat = ae_enable_heartbeat(&mut event_loop, 20).unwrap();
+        assert_eq!(heartbeat.interval(), Duration::from_millis(20));
+        assert_eq!(heartbeat.wait(Duration::ZERO), Some(true));
+
+        /* Nothing registered but the heartbeat timer: every blocking
+         * iteration must still come back within the interval. */
+        for _ in 0..5 {
+            let start = Instant::now();
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+            assert!(start.elapsed() < Duration::from_millis(500));
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+            assert_eq!(heartbeat.wait(Duration::ZERO), Some(true));
+        }
+    }
+
+    #[test]
+    fn test_no_beat_without_iterations() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        let heartbeat = ae_enable_heartbeat(&mut event_loop, 10).unwrap();
+        assert_eq!(heartbeat.wait(Duration::ZERO), Some(true));
+        assert_eq!(heartbeat.wait(Duration::from_millis(30)), Some(false));
+    }
+
+    #[test]
+    fn test_disable_and_delete_close_the_pipe() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        let heartbeat = ae_enable_heartbeat(&mut event_loop, 10).unwrap();
+        heartbeat.wait(Duration::ZERO);
+        ae_disable_heartbeat(&mut event_loop);
+        assert_eq!(heartbeat.wait(Duration::from_millis(100)), None);
+
+        let heartbeat = ae_enable_heartbeat(&mut event_loop, 10).unwrap();
+        heartbeat.wait(Duration::ZERO);
+        drop(event_loop);
+        assert_eq!(heartbeat.wait(Duration::from_millis(100)), None);
+    }
+
+    #[test]
+    fn test_reenable_replaces_the_pipe() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        let first = ae_enable_heartbeat(&mut event_loop, 10).unwrap();
+        let second = ae_enable_heartbeat(&mut event_loop, 10).unwrap();
+        assert_ne!(first.fd(), second.fd());
+        assert_eq!(first.wait(Duration::ZERO), Some(true));
+        assert_eq!(first.wait(Duration::from_millis(100)), None);
+        assert_eq!(second.wait(Duration::ZERO), Some(true));
+    }
+}
+
+mod monitor {
+    use super::*;
+
+    #[test]
+    fn test_monitor_alarms_on_stall() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        let heartbeat = ae_enable_heartbeat(&mut event_loop, 10).unwrap();
+        let alarms = Arc::new(AtomicUsize::new(0));
+        let counter = alarms.clone();
+        let monitor = heartbeat
+            .spawn_monitor(Duration::from_millis(30), move |stalled| {
+                assert!(stalled >= Duration::from_millis(30));
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+
+        /* A running loop keeps the monitor quiet. */
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(100) {
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        }
+        assert_eq!(alarms.load(Ordering::SeqCst), 0);
+
+        /* Stuck loop thread. */
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(alarms.load(Ordering::SeqCst) >= 1);
+
+        /* Deleting the loop stops the monitor. */
+        drop(event_loop);
+        monitor.join().unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+/* Idle Timeout Tests
+ *
+ * Tests for the idle timeout wheel (ae/idle.rs) on connections over Unix
+ * socket pairs, with the manual clock turning the wheel.
+ */
+
+use rae::test_util::{manual_loop, sink_conn};
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_ERR, AE_OK, AeEventLoop, ae_advance_clock, ae_conn_close,
+    ae_conn_idle_timeout, ae_conn_set_idle_timeout, ae_conn_set_idle_timeout_duration,
+    ae_delete_event_loop, ae_idle_wheel_len, ae_pending_time_events, ae_process_events,
+};
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+/* Let `seconds` pass, one iteration per second. */
+fn wait(event_loop: &mut AeEventLoop, seconds: u64) {
+    for _ in 0..seconds {
+        ae_advance_clock(event_loop, 1_000_000);
+        ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+    }
+}
+
+mod timeouts {
+    use super::*;
+
+    #[test]
+    fn test_idle_connection_is_closed() {
+        let mut event_loop = manual_loop(1024);
+        let mut closed = None;
+        let (fd, _theirs) = sink_conn(&mut event_loop, &mut closed);
+        assert_eq!(ae_conn_set_idle_timeout(&mut event_loop, fd, 3000), AE_OK);
+        assert_eq!(ae_conn_idle_timeout(&event_loop, fd), Some(3000));
+
+        wait(&mut event_loop, 2);
+        assert_eq!(closed, None);
+        wait(&mut event_loop, 2);
+        assert_eq!(closed, Some(libc::ETIMEDOUT));
+        assert_eq!(ae_idle_wheel_len(&event_loop), 0);
+        /* The wheel stops turning once empty. */
+        assert_eq!(ae_pending_time_events(&event_loop), 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_traffic_postpones_the_timeout() {
+        let mut event_loop = manual_loop(1024);
+        let mut closed = None;
+        let (fd, mut theirs) = sink_conn(&mut event_loop, &mut closed);
+        ae_conn_set_idle_timeout(&mut event_loop, fd, 3000);
+
+        for _ in 0..5 {
+            wait(&mut event_loop, 2);
+            theirs.write_all(b"x").unwrap();
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        }
+        assert_eq!(closed, None);
+        wait(&mut event_loop, 5);
+        assert_eq!(closed, Some(libc::ETIMEDOUT));
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_timeout_longer_than_the_wheel() {
+        let mut event_loop = manual_loop(1024);
+        let mut closed = None;
+        let (fd, _theirs) = sink_conn(&mut event_loop, &mut closed);
+        ae_conn_set_idle_timeout(&mut event_loop, fd, 100_000);
+
+        wait(&mut event_loop, 99);
+        assert_eq!(closed, None);
+        wait(&mut event_loop, 2);
+        assert_eq!(closed, Some(libc::ETIMEDOUT));
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_duration_timeout() {
+        let mut event_loop = manual_loop(1024);
+        let mut closed = None;
+        let (fd, _theirs) = sink_conn(&mut event_loop, &mut closed);
+        assert_eq!(
+            ae_conn_set_idle_timeout_duration(&mut event_loop, fd, Duration::from_secs(3)),
+            AE_OK
+        );
+        assert_eq!(ae_conn_idle_timeout(&event_loop, fd), Some(3000));
+
+        /* Zero removes it. */
+        ae_conn_set_idle_timeout_duration(&mut event_loop, fd, Duration::ZERO);
+        assert_eq!(ae_conn_idle_timeout(&event_loop, fd), None);
+        wait(&mut event_loop, 5);
+        assert_eq!(closed, None);
+        ae_conn_close(&mut event_loop, fd);
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod wheel {
+    use super::*;
+
+    #[test]
+    fn test_one_timer_for_every_connection() {
+        let mut event_loop = manual_loop(1024);
+        let mut closed = vec![None; 32];
+        let conns: Vec<(i32, UnixStream)> = closed
+            .iter_mut()
+            .map(|closed| sink_conn(&mut event_loop, closed))
+            .collect();
+        for (n, (fd, _)) in conns.iter().enumerate() {
+            let timeout_ms = 1000 * (n as i64 % 4 + 1);
+            assert_eq!(
+                ae_conn_set_idle_timeout(&mut event_loop, *fd, timeout_ms),
+                AE_OK
+            );
+        }
+        assert_eq!(ae_idle_wheel_len(&event_loop), 32);
+        assert_eq!(ae_pending_time_events(&event_loop), 1);
+
+        wait(&mut event_loop, 2);
+        let expired = closed.iter().filter(|closed| closed.is_some()).count();
+        assert!((8..=16).contains(&expired), "{expired} connections expired");
+        wait(&mut event_loop, 4);
+        assert!(closed.iter().all(|&closed| closed == Some(libc::ETIMEDOUT)));
+        assert_eq!(ae_pending_time_events(&event_loop), 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_remove_and_close() {
+        let mut event_loop = manual_loop(1024);
+        let mut closed = None;
+        let (fd, _theirs) = sink_conn(&mut event_loop, &mut closed);
+
+        assert_eq!(ae_conn_set_idle_timeout(&mut event_loop, fd, -1), AE_ERR);
+        /* Not a connection. */
+        assert_eq!(ae_conn_set_idle_timeout(&mut event_loop, 0, 1000), AE_ERR);
+
+        ae_conn_set_idle_timeout(&mut event_loop, fd, 1000);
+        assert_eq!(ae_conn_set_idle_timeout(&mut event_loop, fd, 0), AE_OK);
+        assert_eq!(ae_conn_idle_timeout(&event_loop, fd), None);
+        wait(&mut event_loop, 3);
+        assert_eq!(closed, None);
+
+        ae_conn_set_idle_timeout(&mut event_loop, fd, 1000);
+        ae_conn_close(&mut event_loop, fd);
+        assert_eq!(closed, Some(0));
+        assert_eq!(ae_idle_wheel_len(&event_loop), 0);
+        ae_delete_event_loop(event_loop);
+    }
+}
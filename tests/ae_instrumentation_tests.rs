@@ -0,0 +1,92 @@
+/* Instrumentation Tests
+ *
+ * What the `instrumentation` feature turns on and off: with it the loop
+ * feeds its histograms, slowlog and flight recorder; without it those
+ * stay empty, the recorder is refused, and the plain counters of
+ * AeStats are still kept. Run both ways:
+ *
+ *     cargo test --test ae_instrumentation_tests
+ *     cargo test --test ae_instrumentation_tests --no-default-features
+ */
+
+use rae::test_util::{MockControl, run_once, virtual_loop};
+use rae::{
+    AE_ERR, AE_NOMORE, AE_OK, AE_READABLE, AeDispatchCtx, AeEventLoop, ae_advance_clock,
+    ae_create_file_event, ae_create_job, ae_create_time_event, ae_delete_event_loop,
+    ae_flight_entries, ae_get_stats, ae_set_flight_recorder, ae_slowlog_len,
+};
+use std::ffi::c_void;
+
+const ENABLED: bool = cfg!(feature = "instrumentation");
+
+fn serve(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let control = unsafe { &*(client_data as *const MockControl) };
+    control.clear_ready(fd, AE_READABLE);
+    ae_advance_clock(event_loop, 2_000);
+}
+
+fn once_timer(event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    ae_advance_clock(event_loop, 1_000);
+    AE_NOMORE
+}
+
+/* Always takes 5 ms for a 1 ms slice, done after one. */
+fn overrunning(event_loop: &mut AeEventLoop, _ctx: &AeDispatchCtx, _state: &mut ()) -> bool {
+    ae_advance_clock(event_loop, 5_000);
+    false
+}
+
+mod instrumentation {
+    use super::*;
+
+    #[test]
+    fn test_counters_are_always_kept() {
+        let (mut event_loop, control) = virtual_loop(64);
+        let data = &control as *const MockControl as *mut c_void;
+        ae_create_file_event(&mut event_loop, 5, AE_READABLE, serve, data);
+        ae_create_time_event(&mut event_loop, 0, once_timer, std::ptr::null_mut(), None);
+
+        control.set_ready(5, AE_READABLE);
+        run_once(&mut event_loop);
+        run_once(&mut event_loop);
+        let stats = ae_get_stats(&event_loop);
+        assert_eq!(stats.iterations, 2);
+        assert_eq!((stats.file_events, stats.time_events), (1, 1));
+        /* The histograms are only fed with the feature. */
+        let expected = if ENABLED { 2 } else { 0 };
+        assert_eq!(stats.callback_us.count(), expected);
+        assert_eq!(stats.poll_us.count(), expected);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_flight_recorder() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let result = ae_set_flight_recorder(&mut event_loop, 8);
+        if ENABLED {
+            assert_eq!(result, AE_OK);
+        } else {
+            assert_eq!(result, AE_ERR);
+            assert_eq!(
+                std::io::Error::last_os_error().raw_os_error(),
+                Some(libc::ENOTSUP)
+            );
+        }
+
+        ae_create_time_event(&mut event_loop, 0, once_timer, std::ptr::null_mut(), None);
+        run_once(&mut event_loop);
+        assert_eq!(ae_flight_entries(&event_loop).len(), ENABLED as usize);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_overruns_without_slowlog() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        ae_create_job(&mut event_loop, "compact", 1_000, overrunning, Box::new(()));
+
+        run_once(&mut event_loop);
+        assert_eq!(ae_get_stats(&event_loop).job_overruns, 1);
+        assert_eq!(ae_slowlog_len(&event_loop), ENABLED as usize);
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -7,10 +7,12 @@
 use rae::{
     AE_ALL_EVENTS, AE_DONT_WAIT, AE_FILE_EVENTS, AE_NOMORE, AE_READABLE, AE_TIME_EVENTS,
     AE_WRITABLE, ae_create_event_loop, ae_create_file_event, ae_create_time_event,
-    ae_delete_event_loop, ae_delete_file_event, ae_delete_time_event, ae_process_events,
-    ae_set_after_sleep_proc, ae_set_before_sleep_proc, ae_stop,
+    ae_delete_event_loop, ae_delete_file_event, ae_delete_time_event, ae_main_with,
+    ae_process_events, ae_run_with_driver, ae_set_after_sleep_proc, ae_set_before_sleep_proc,
+    ae_stop,
 };
 use std::ffi::c_void;
+use std::ops::ControlFlow;
 use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
 use std::time::Duration;
 
@@ -287,6 +289,95 @@ mod event_loop_control {
 
         ae_delete_event_loop(event_loop);
     }
+
+    #[test]
+    fn test_driver_breaks_loop() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_create_time_event(&mut event_loop, 1, |_, _, _| 1, std::ptr::null_mut(), None);
+
+        let mut ticks = 0;
+        ae_run_with_driver(&mut event_loop, &mut |_: &mut rae::AeEventLoop| {
+            ticks += 1;
+            if ticks == 3 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(ticks, 3, "Driver ticks once per iteration");
+
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_driver_stopped_by_callback() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_create_time_event(
+            &mut event_loop,
+            1,
+            stopping_time_callback,
+            std::ptr::null_mut(),
+            None,
+        );
+
+        let mut ticks = 0;
+        ae_run_with_driver(&mut event_loop, &mut |_: &mut rae::AeEventLoop| {
+            ticks += 1;
+            ControlFlow::Continue(())
+        });
+        assert!(ticks >= 1);
+
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_main_with_reports_each_iteration() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_create_time_event(&mut event_loop, 2, |_, _, _| 2, std::ptr::null_mut(), None);
+
+        let mut deltas = Vec::new();
+        let mut fired = 0;
+        ae_main_with(&mut event_loop, |delta| {
+            deltas.push(*delta);
+            fired += delta.time_events;
+            if fired == 3 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert!(deltas.iter().all(|delta| delta.iterations == 1));
+        /* Iterations firing the timer waited for it. */
+        let firing: Vec<_> = deltas
+            .iter()
+            .filter(|delta| delta.time_events > 0)
+            .collect();
+        assert_eq!(firing.len(), 3);
+        assert!(firing.iter().all(|delta| delta.interval_us >= 1000));
+
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_main_with_stopped_by_callback() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_create_time_event(
+            &mut event_loop,
+            1,
+            stopping_time_callback,
+            std::ptr::null_mut(),
+            None,
+        );
+
+        let mut iterations = 0;
+        ae_main_with(&mut event_loop, |delta| {
+            iterations += delta.iterations;
+            ControlFlow::Continue(())
+        });
+        assert!(iterations >= 1);
+
+        ae_delete_event_loop(event_loop);
+    }
 }
 
 mod processing_flags {
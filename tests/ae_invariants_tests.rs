@@ -0,0 +1,105 @@
+/* Invariant Tests
+ *
+ * Tests for ae_check_invariants() (ae/invariants.rs), and for the checks
+ * the loop makes on its own after every mutation with the
+ * `debug-invariants` feature.
+ */
+
+use rae::anet::anet_pipe;
+use rae::test_util::close_pipe;
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_READABLE, AE_WRITABLE, AeEventLoop, ae_check_invariants,
+    ae_create_event_loop, ae_create_file_event, ae_delete_file_event, ae_pause, ae_process_events,
+    ae_resize_set_size, ae_resume,
+};
+use std::ffi::c_void;
+
+fn noop(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+mod checks {
+    use super::*;
+
+    #[test]
+    fn test_consistent_through_mutations() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        assert_eq!(ae_check_invariants(&event_loop), Ok(()));
+
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            noop,
+            std::ptr::null_mut(),
+        );
+        ae_create_file_event(
+            &mut event_loop,
+            wfd,
+            AE_WRITABLE,
+            noop,
+            std::ptr::null_mut(),
+        );
+        assert_eq!(ae_check_invariants(&event_loop), Ok(()));
+
+        ae_pause(&mut event_loop);
+        assert_eq!(ae_check_invariants(&event_loop), Ok(()));
+        ae_resume(&mut event_loop);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        ae_resize_set_size(&mut event_loop, 2048);
+        assert_eq!(ae_check_invariants(&event_loop), Ok(()));
+
+        ae_delete_file_event(&mut event_loop, wfd, AE_WRITABLE);
+        ae_delete_file_event(&mut event_loop, rfd, AE_READABLE);
+        assert_eq!(ae_check_invariants(&event_loop), Ok(()));
+        assert_eq!(event_loop.maxfd, -1);
+        close_pipe(rfd, wfd);
+    }
+
+    #[test]
+    fn test_empty_mask_leaves_maxfd() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_create_file_event(&mut event_loop, 5, 0, noop, std::ptr::null_mut());
+        assert_eq!(event_loop.maxfd, -1);
+        assert_eq!(ae_check_invariants(&event_loop), Ok(()));
+    }
+
+    #[test]
+    fn test_reports_stale_maxfd() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        event_loop.maxfd = 7;
+        let err = ae_check_invariants(&event_loop).unwrap_err();
+        assert_eq!(err, "maxfd is 7 but the highest registered fd is -1");
+        event_loop.maxfd = -1;
+    }
+
+    #[test]
+    fn test_reports_nevents_over_setsize() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let nevents = event_loop.nevents;
+        event_loop.setsize = nevents as i32 - 1;
+        let err = ae_check_invariants(&event_loop).unwrap_err();
+        assert!(err.starts_with("nevents "), "{}", err);
+        event_loop.setsize = 64;
+    }
+}
+
+#[cfg(feature = "debug-invariants")]
+mod debug_invariants {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "loop invariant broken after ae_create_file_event: maxfd is 40")]
+    fn test_mutation_panics_on_corruption() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        event_loop.maxfd = 40;
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            noop,
+            std::ptr::null_mut(),
+        );
+        close_pipe(rfd, wfd);
+    }
+}
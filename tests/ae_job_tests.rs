@@ -0,0 +1,164 @@
+/* Incremental Job Tests
+ *
+ * Tests for time sliced jobs and the job slowlog (ae/job.rs). Jobs run on
+ * the manual clock and simulate their work by moving it forward.
+ */
+
+use rae::test_util::{manual_loop, run_once};
+use rae::{
+    AE_SLOWLOG_MAX_LEN, AeDispatchCtx, AeEventLoop, ae_advance_clock, ae_create_job,
+    ae_delete_event_loop, ae_delete_time_event, ae_get_stats, ae_loop_now, ae_set_iteration_budget,
+    ae_slowlog_get, ae_slowlog_len, ae_slowlog_reset,
+};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/* Work measured in microseconds of (manual) clock time. */
+struct Work {
+    left_us: u64,
+    /* Time each slice takes, whatever the deadline says. */
+    slice_us: u64,
+}
+
+fn do_work(event_loop: &mut AeEventLoop, _ctx: &AeDispatchCtx, work: &mut Work) -> bool {
+    let step = work.slice_us.min(work.left_us);
+    ae_advance_clock(event_loop, step);
+    work.left_us -= step;
+    work.left_us > 0
+}
+
+fn work(left_us: u64, slice_us: u64) -> Box<Work> {
+    Box::new(Work { left_us, slice_us })
+}
+
+mod slices {
+    use super::*;
+
+    #[test]
+    fn test_runs_once_per_iteration_until_done() {
+        let mut event_loop = manual_loop(64);
+        ae_create_job(&mut event_loop, "scan", 1000, do_work, work(3500, 1000));
+        for expected in [1, 2, 3, 4, 4] {
+            run_once(&mut event_loop);
+            assert_eq!(ae_get_stats(&event_loop).job_slices, expected);
+        }
+        assert_eq!(ae_get_stats(&event_loop).job_overruns, 0);
+        assert_eq!(ae_slowlog_len(&event_loop), 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    fn record_deadline(
+        _event_loop: &mut AeEventLoop,
+        ctx: &AeDispatchCtx,
+        seen: &mut Rc<RefCell<Vec<Option<u64>>>>,
+    ) -> bool {
+        seen.borrow_mut().push(ctx.deadline_us());
+        false
+    }
+
+    #[test]
+    fn test_deadline_is_the_slice() {
+        let mut event_loop = manual_loop(64);
+        ae_advance_clock(&mut event_loop, 10_000);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+
+        /* One slice shorter than the iteration budget, one longer. */
+        ae_set_iteration_budget(&mut event_loop, 2000);
+        ae_create_job(
+            &mut event_loop,
+            "short",
+            500,
+            record_deadline,
+            Box::new(seen.clone()),
+        );
+        ae_create_job(
+            &mut event_loop,
+            "long",
+            5000,
+            record_deadline,
+            Box::new(seen.clone()),
+        );
+        run_once(&mut event_loop);
+
+        let now = ae_loop_now(&event_loop);
+        let mut seen = seen.take();
+        seen.sort();
+        assert_eq!(seen, vec![Some(now + 500), Some(now + 2000)]);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut event_loop = manual_loop(64);
+        let id = ae_create_job(&mut event_loop, "scan", 1000, do_work, work(1_000_000, 10));
+        run_once(&mut event_loop);
+        ae_delete_time_event(&mut event_loop, id);
+        run_once(&mut event_loop);
+        assert_eq!(ae_get_stats(&event_loop).job_slices, 1);
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod slowlog {
+    use super::*;
+
+    #[test]
+    fn test_overruns_are_logged() {
+        if !cfg!(feature = "instrumentation") {
+            return;
+        }
+        let mut event_loop = manual_loop(64);
+        /* Every slice takes 1500us against a budget of 1000us. */
+        let id = ae_create_job(&mut event_loop, "expire", 1000, do_work, work(4000, 1500));
+        let start = ae_loop_now(&event_loop);
+        for _ in 0..3 {
+            run_once(&mut event_loop);
+        }
+
+        let stats = ae_get_stats(&event_loop);
+        assert_eq!(stats.job_slices, 3);
+        /* The last slice only had 1000us of work left. */
+        assert_eq!(stats.job_overruns, 2);
+
+        let entries = ae_slowlog_get(&event_loop, 10);
+        assert_eq!(entries.len(), 2);
+        /* Newest first. */
+        assert_eq!(entries[0].id, 1);
+        assert_eq!(entries[0].started_us, start + 1500);
+        assert_eq!(entries[1].id, 0);
+        assert_eq!(entries[1].started_us, start);
+        for entry in &entries {
+            assert_eq!(entry.job_id, id);
+            assert_eq!(entry.name, "expire");
+            assert_eq!(entry.budget_us, 1000);
+            assert_eq!(entry.took_us, 1500);
+        }
+        assert_eq!(ae_slowlog_get(&event_loop, 1).len(), 1);
+
+        ae_slowlog_reset(&mut event_loop);
+        assert_eq!(ae_slowlog_len(&event_loop), 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_length_is_capped() {
+        if !cfg!(feature = "instrumentation") {
+            return;
+        }
+        let mut event_loop = manual_loop(64);
+        let slices = AE_SLOWLOG_MAX_LEN as u64 + 10;
+        ae_create_job(&mut event_loop, "big", 1, do_work, work(slices * 2, 2));
+        for _ in 0..slices {
+            run_once(&mut event_loop);
+        }
+        assert_eq!(ae_get_stats(&event_loop).job_overruns, slices);
+        assert_eq!(ae_slowlog_len(&event_loop), AE_SLOWLOG_MAX_LEN);
+        let entries = ae_slowlog_get(&event_loop, usize::MAX);
+        assert_eq!(entries[0].id, slices - 1);
+        assert_eq!(
+            entries.last().unwrap().id,
+            slices - AE_SLOWLOG_MAX_LEN as u64
+        );
+        ae_delete_event_loop(event_loop);
+    }
+}
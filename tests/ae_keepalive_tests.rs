@@ -0,0 +1,142 @@
+/* Keepalive Tests
+ *
+ * Tests for the keepalive probes (ae/keepalive.rs) on connections over
+ * Unix socket pairs, with the manual clock driving the probe timer.
+ */
+
+use rae::test_util::{manual_loop, sink_conn};
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_ERR, AE_OK, AeEventLoop, ae_advance_clock, ae_conn_close,
+    ae_delete_event_loop, ae_keepalive_disable, ae_keepalive_enable, ae_keepalive_missed,
+    ae_keepalive_unwatch, ae_keepalive_watch, ae_process_events,
+};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+
+const INTERVAL_MS: i64 = 1000;
+
+/* The peer end is non-blocking, for drain() to return. */
+fn connect(event_loop: &mut AeEventLoop, closed: &mut Option<i32>) -> (i32, UnixStream) {
+    let (fd, theirs) = sink_conn(event_loop, closed);
+    theirs.set_nonblocking(true).unwrap();
+    (fd, theirs)
+}
+
+/* Let one probe interval pass. */
+fn tick(event_loop: &mut AeEventLoop) {
+    ae_advance_clock(event_loop, INTERVAL_MS as u64 * 1000);
+    ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+}
+
+/* Everything the peer received so far. */
+fn drain(stream: &mut UnixStream) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut buf = [0u8; 256];
+    while let Ok(n) = stream.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        out.extend_from_slice(&buf[..n]);
+    }
+    out
+}
+
+mod probes {
+    use super::*;
+
+    #[test]
+    fn test_silent_peer_is_closed() {
+        let mut event_loop = manual_loop(1024);
+        let mut closed = None;
+        let (fd, mut theirs) = connect(&mut event_loop, &mut closed);
+        assert_eq!(ae_keepalive_enable(&mut event_loop, INTERVAL_MS, 2), AE_OK);
+        assert_eq!(ae_keepalive_watch(&mut event_loop, fd, b"PING\r\n"), AE_OK);
+
+        tick(&mut event_loop);
+        assert_eq!(ae_keepalive_missed(&event_loop, fd), Some(1));
+        tick(&mut event_loop);
+        assert_eq!(ae_keepalive_missed(&event_loop, fd), Some(2));
+        assert_eq!(drain(&mut theirs), b"PING\r\nPING\r\n");
+        assert_eq!(closed, None);
+
+        tick(&mut event_loop);
+        assert_eq!(closed, Some(libc::ETIMEDOUT));
+        assert_eq!(ae_keepalive_missed(&event_loop, fd), None);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_answer_resets_the_count() {
+        let mut event_loop = manual_loop(1024);
+        let mut closed = None;
+        let (fd, mut theirs) = connect(&mut event_loop, &mut closed);
+        ae_keepalive_enable(&mut event_loop, INTERVAL_MS, 1);
+        ae_keepalive_watch(&mut event_loop, fd, b"ping\n");
+
+        for _ in 0..5 {
+            tick(&mut event_loop);
+            assert_eq!(ae_keepalive_missed(&event_loop, fd), Some(1));
+            assert_eq!(drain(&mut theirs), b"ping\n");
+            theirs.write_all(b"pong\n").unwrap();
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+            /* Any input in the interval counts, and an active peer is not
+             * pinged. */
+            tick(&mut event_loop);
+            assert_eq!(ae_keepalive_missed(&event_loop, fd), Some(0));
+            assert!(drain(&mut theirs).is_empty());
+        }
+        assert_eq!(closed, None);
+
+        ae_conn_close(&mut event_loop, fd);
+        assert_eq!(closed, Some(0));
+        assert_eq!(ae_keepalive_missed(&event_loop, fd), None);
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod registration {
+    use super::*;
+
+    #[test]
+    fn test_invalid_arguments() {
+        let mut event_loop = manual_loop(1024);
+        let mut closed = None;
+        let (fd, _theirs) = connect(&mut event_loop, &mut closed);
+
+        /* Not enabled yet. */
+        assert_eq!(ae_keepalive_watch(&mut event_loop, fd, b"x"), AE_ERR);
+        assert_eq!(ae_keepalive_enable(&mut event_loop, 0, 3), AE_ERR);
+        assert_eq!(ae_keepalive_enable(&mut event_loop, 10, 0), AE_ERR);
+        assert_eq!(ae_keepalive_enable(&mut event_loop, 10, 3), AE_OK);
+        /* Not a connection. */
+        assert_eq!(ae_keepalive_watch(&mut event_loop, 0, b"x"), AE_ERR);
+
+        ae_conn_close(&mut event_loop, fd);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_unwatch_and_disable() {
+        let mut event_loop = manual_loop(1024);
+        let mut closed = None;
+        let (fd, mut theirs) = connect(&mut event_loop, &mut closed);
+        ae_keepalive_enable(&mut event_loop, INTERVAL_MS, 1);
+
+        ae_keepalive_watch(&mut event_loop, fd, b"ping\n");
+        ae_keepalive_unwatch(&mut event_loop, fd);
+        tick(&mut event_loop);
+        assert!(drain(&mut theirs).is_empty());
+
+        ae_keepalive_watch(&mut event_loop, fd, b"ping\n");
+        ae_keepalive_disable(&mut event_loop);
+        assert_eq!(ae_keepalive_missed(&event_loop, fd), None);
+        for _ in 0..3 {
+            tick(&mut event_loop);
+        }
+        assert!(drain(&mut theirs).is_empty());
+        assert_eq!(closed, None);
+
+        ae_conn_close(&mut event_loop, fd);
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -0,0 +1,67 @@
+/* Lifecycle Notification Tests
+ *
+ * Tests for the lifecycle subscriber set with ae_set_lifecycle_proc().
+ */
+
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AeEventLoop, AeLifecycleEvent, ae_create_event_loop,
+    ae_create_time_event, ae_main, ae_process_events, ae_set_lifecycle_proc, ae_stop,
+};
+use std::ffi::c_void;
+use std::sync::Mutex;
+
+/* The subscriber is a plain fn, events are collected per test through the
+ * loop private data. */
+fn record_event(event_loop: &mut AeEventLoop, event: &AeLifecycleEvent) {
+    let events = unsafe { &*(event_loop.privdata[0] as *const Mutex<Vec<AeLifecycleEvent>>) };
+    events.lock().unwrap().push(*event);
+}
+
+fn stop_timer(event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    ae_stop(event_loop);
+    rae::AE_NOMORE
+}
+
+mod lifecycle {
+    use super::*;
+
+    #[test]
+    fn test_iteration_events() {
+        let events = Mutex::new(Vec::new());
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        event_loop.privdata[0] = &events as *const _ as *mut c_void;
+        ae_set_lifecycle_proc(&mut event_loop, Some(record_event));
+
+        ae_create_time_event(&mut event_loop, 0, stop_timer, std::ptr::null_mut(), None);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+
+        let events = events.lock().unwrap();
+        assert_eq!(events[0], AeLifecycleEvent::IterationBegin { n: 1 });
+        assert!(events.contains(&AeLifecycleEvent::IterationBegin { n: 2 }));
+        assert!(!events.contains(&AeLifecycleEvent::LoopStarted));
+    }
+
+    #[test]
+    fn test_main_started_and_stopped() {
+        let events = Mutex::new(Vec::new());
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        event_loop.privdata[0] = &events as *const _ as *mut c_void;
+        ae_set_lifecycle_proc(&mut event_loop, Some(record_event));
+
+        ae_create_time_event(&mut event_loop, 5, stop_timer, std::ptr::null_mut(), None);
+        ae_main(&mut event_loop);
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.first(), Some(&AeLifecycleEvent::LoopStarted));
+        assert_eq!(events.last(), Some(&AeLifecycleEvent::LoopStopped));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            AeLifecycleEvent::PollReturned { nfired: 0, waited_us } if *waited_us > 0
+        )));
+
+        drop(events);
+        ae_set_lifecycle_proc(&mut event_loop, None);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+    }
+}
@@ -0,0 +1,161 @@
+/* Soft Limit Tests
+ *
+ * Tests for ae_set_soft_limits() (ae/limits.rs): registrations on a mock
+ * loop bring it to its fd and timer limits, which are raised once per
+ * crossing through the diagnostic proc and listed by ae_doctor().
+ */
+
+use rae::test_util::virtual_loop;
+use rae::{
+    AE_ERR, AE_NOMORE, AE_OK, AE_READABLE, AeEventLoop, AeFinding, AeFindingKind,
+    AeFindingSeverity, AeSoftLimitResource, AeSoftLimits, ae_create_file_event,
+    ae_create_time_event, ae_delete_event_loop, ae_delete_file_event, ae_delete_time_event,
+    ae_doctor, ae_get_soft_limits, ae_set_diagnostic_proc, ae_set_soft_limits,
+};
+use std::cell::RefCell;
+use std::ffi::c_void;
+
+thread_local! {
+    static RAISED: RefCell<Vec<AeFinding>> = const { RefCell::new(Vec::new()) };
+}
+
+fn collect(_event_loop: &mut AeEventLoop, finding: &AeFinding) {
+    RAISED.with(|raised| raised.borrow_mut().push(finding.clone()));
+}
+
+/* The soft limit findings raised since the last call. */
+fn raised() -> Vec<(AeSoftLimitResource, usize, usize)> {
+    RAISED.with(|raised| {
+        raised
+            .borrow_mut()
+            .drain(..)
+            .filter_map(|finding| match finding.kind {
+                AeFindingKind::SoftLimit {
+                    resource,
+                    used,
+                    limit,
+                } => Some((resource, used, limit)),
+                _ => None,
+            })
+            .collect()
+    })
+}
+
+fn noop_file(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+fn noop_timer(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    AE_NOMORE
+}
+
+fn register(event_loop: &mut AeEventLoop, fd: i32) -> i32 {
+    ae_create_file_event(event_loop, fd, AE_READABLE, noop_file, std::ptr::null_mut())
+}
+
+fn timer(event_loop: &mut AeEventLoop) -> i64 {
+    ae_create_time_event(event_loop, 1000, noop_timer, std::ptr::null_mut(), None)
+}
+
+/* Mock loop with a setsize of 64 and the diagnostic proc collecting. */
+fn limited_loop(limits: AeSoftLimits) -> Box<AeEventLoop> {
+    let (mut event_loop, _control) = virtual_loop(64);
+    ae_set_diagnostic_proc(&mut event_loop, Some(collect));
+    assert_eq!(ae_set_soft_limits(&mut event_loop, limits), AE_OK);
+    raised();
+    event_loop
+}
+
+mod limits {
+    use super::*;
+
+    #[test]
+    fn test_fd_limit_before_the_setsize() {
+        let mut event_loop = limited_loop(AeSoftLimits {
+            fd_pct: 80,
+            ..AeSoftLimits::default()
+        });
+        /* 80% of 64 slots, rounded up: fd 51 is the 52nd. */
+        assert_eq!(register(&mut event_loop, 50), AE_OK);
+        assert!(raised().is_empty());
+        assert_eq!(register(&mut event_loop, 51), AE_OK);
+        assert_eq!(raised(), vec![(AeSoftLimitResource::Fds, 52, 52)]);
+
+        /* Once per crossing. */
+        assert_eq!(register(&mut event_loop, 60), AE_OK);
+        assert!(raised().is_empty());
+        let findings = ae_doctor(&event_loop);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, AeFindingSeverity::Warning);
+        assert_eq!(
+            findings[0].kind,
+            AeFindingKind::SoftLimit {
+                resource: AeSoftLimitResource::Fds,
+                used: 61,
+                limit: 52
+            }
+        );
+
+        /* The hard limit comes later. */
+        assert_eq!(register(&mut event_loop, 64), AE_ERR);
+
+        /* Back under, seen by the next registration, then over again. */
+        ae_delete_file_event(&mut event_loop, 60, AE_READABLE);
+        ae_delete_file_event(&mut event_loop, 51, AE_READABLE);
+        assert!(ae_doctor(&event_loop).is_empty());
+        assert_eq!(register(&mut event_loop, 10), AE_OK);
+        assert_eq!(register(&mut event_loop, 55), AE_OK);
+        assert_eq!(raised(), vec![(AeSoftLimitResource::Fds, 56, 52)]);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_timer_limit() {
+        let mut event_loop = limited_loop(AeSoftLimits {
+            timers: 3,
+            ..AeSoftLimits::default()
+        });
+        let ids = [timer(&mut event_loop), timer(&mut event_loop)];
+        assert!(raised().is_empty());
+        let third = timer(&mut event_loop);
+        assert_eq!(raised(), vec![(AeSoftLimitResource::Timers, 3, 3)]);
+        timer(&mut event_loop);
+        assert!(raised().is_empty());
+
+        for id in ids.into_iter().chain([third]) {
+            ae_delete_time_event(&mut event_loop, id);
+        }
+        timer(&mut event_loop);
+        assert!(raised().is_empty());
+        timer(&mut event_loop);
+        assert_eq!(raised(), vec![(AeSoftLimitResource::Timers, 3, 3)]);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_configuration() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        ae_set_diagnostic_proc(&mut event_loop, Some(collect));
+        /* None by default. */
+        assert_eq!(ae_get_soft_limits(&event_loop), AeSoftLimits::default());
+        for fd in 0..64 {
+            register(&mut event_loop, fd);
+        }
+        assert!(raised().is_empty());
+
+        let limits = AeSoftLimits {
+            fd_pct: 101,
+            timers: 10,
+        };
+        assert_eq!(ae_set_soft_limits(&mut event_loop, limits), AE_ERR);
+        assert_eq!(ae_get_soft_limits(&event_loop), AeSoftLimits::default());
+        let limits = AeSoftLimits {
+            fd_pct: 50,
+            timers: 10,
+        };
+        assert_eq!(ae_set_soft_limits(&mut event_loop, limits), AE_OK);
+        assert_eq!(ae_get_soft_limits(&event_loop), limits);
+        /* Checked on registrations only, listed by the doctor at once. */
+        assert!(raised().is_empty());
+        assert_eq!(ae_doctor(&event_loop).len(), 1);
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -0,0 +1,191 @@
+/* Memory Accounting Tests
+ *
+ * Tests for the counting allocator and ae_memory_usage() (ae/memory.rs).
+ * The allocator is installed as the global allocator of this test binary.
+ * Tests run in parallel: the allocator tests take turns and use margins
+ * well above what the other tests allocate meanwhile.
+ */
+
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_NOMORE, AE_OK, AeCountingAlloc, AeEventLoop, ae_conn_close,
+    ae_conn_create, ae_create_event_loop, ae_create_time_event, ae_delete_event_loop,
+    ae_delete_time_event, ae_memory_usage, ae_process_events,
+};
+use std::alloc::System;
+use std::ffi::c_void;
+use std::io::Write;
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::Mutex;
+
+#[global_allocator]
+static ALLOC: AeCountingAlloc<System> = AeCountingAlloc::new(System);
+
+const MB: usize = 1024 * 1024;
+
+/* Held by the tests making large allocations. */
+static BIG_ALLOCS: Mutex<()> = Mutex::new(());
+
+fn noop_timer(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    AE_NOMORE
+}
+
+fn keep_input(
+    _event_loop: &mut AeEventLoop,
+    _fd: i32,
+    _input: &[u8],
+    _client_data: *mut c_void,
+) -> usize {
+    0
+}
+
+mod allocator {
+    use super::*;
+
+    #[test]
+    fn test_counts_allocations() {
+        let _guard = BIG_ALLOCS.lock().unwrap();
+        let before = ALLOC.used();
+        let allocations = ALLOC.allocations();
+        let buf = vec![1u8; 64 * MB];
+        assert!(ALLOC.used() >= before + 60 * MB);
+        assert!(ALLOC.peak() >= ALLOC.used());
+        assert!(ALLOC.allocations() > allocations);
+
+        drop(buf);
+        assert!(ALLOC.used() < before + 32 * MB);
+        assert!(ALLOC.peak() >= before + 64 * MB);
+    }
+
+    #[test]
+    fn test_counts_reallocations() {
+        let _guard = BIG_ALLOCS.lock().unwrap();
+        let before = ALLOC.used();
+        let mut buf = vec![0u8; 16 * MB];
+        buf.resize(80 * MB, 0);
+        assert!(ALLOC.used() >= before + 76 * MB);
+        buf.truncate(MB);
+        buf.shrink_to_fit();
+        assert!(ALLOC.used() < before + 40 * MB);
+    }
+
+    #[test]
+    fn test_loop_allocates_through_it() {
+        let before = ALLOC.allocations();
+        let event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        assert!(ALLOC.allocations() > before);
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod usage {
+    use super::*;
+
+    #[test]
+    fn test_empty_loop() {
+        let event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let usage = ae_memory_usage(&event_loop);
+        assert!(usage.file_events > 0);
+        assert!(usage.fired > 0);
+        assert_eq!(usage.timers, 0);
+        assert_eq!(usage.connections, 0);
+        assert_eq!(
+            usage.total,
+            usage.file_events + usage.fired + usage.timers + usage.connections
+        );
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_timers() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let ids: Vec<i64> = (0..10)
+            .map(|_| {
+                ae_create_time_event(
+                    &mut event_loop,
+                    60_000,
+                    noop_timer,
+                    std::ptr::null_mut(),
+                    None,
+                )
+            })
+            .collect();
+        let ten = ae_memory_usage(&event_loop).timers;
+        assert!(ten > 0);
+        assert_eq!(ten % 10, 0);
+
+        for id in ids {
+            ae_delete_time_event(&mut event_loop, id);
+        }
+        /* Deleted timers are freed on the next pass. */
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(ae_memory_usage(&event_loop).timers, 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_connections() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (ours, mut theirs) = UnixStream::pair().expect("Failed to create socket pair");
+        ours.set_nonblocking(true).unwrap();
+        let fd = ours.into_raw_fd();
+        let result = ae_conn_create(&mut event_loop, fd, keep_input, None, std::ptr::null_mut());
+        assert_eq!(result, AE_OK);
+        let idle = ae_memory_usage(&event_loop).connections;
+        assert!(idle > 0);
+
+        /* Unconsumed input stays in the connection buffer. */
+        theirs.write_all(&[b'x'; 4096]).unwrap();
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        let usage = ae_memory_usage(&event_loop);
+        assert!(usage.connections >= idle + 4096);
+        assert_eq!(
+            usage.total,
+            usage.file_events + usage.fired + usage.timers + usage.connections
+        );
+
+        ae_conn_close(&mut event_loop, fd);
+        assert_eq!(ae_memory_usage(&event_loop).connections, 0);
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+#[cfg(feature = "small")]
+mod small {
+    use super::*;
+    use rae::{AE_POLL_BATCH, AE_READABLE, FiredEvent, ae_create_file_event, ae_delete_file_event};
+
+    fn noop_file(_event_loop: &mut AeEventLoop, _fd: i32, _data: *mut c_void, _mask: i32) {}
+
+    #[test]
+    fn test_tables_grow_on_demand() {
+        let mut event_loop = ae_create_event_loop(10_000).expect("Failed to create event loop");
+        let empty = ae_memory_usage(&event_loop);
+        assert!(empty.total < 4096, "empty loop takes {empty:?}");
+
+        /* A high fd grows the event table, not the fired buffer. */
+        let fds: Vec<i32> = (0..300)
+            .map(|_| unsafe { libc::dup(0) })
+            .filter(|&fd| fd >= 0)
+            .collect();
+        for &fd in &fds {
+            let result = ae_create_file_event(
+                &mut event_loop,
+                fd,
+                AE_READABLE,
+                noop_file,
+                std::ptr::null_mut(),
+            );
+            assert_eq!(result, AE_OK);
+        }
+        let usage = ae_memory_usage(&event_loop);
+        assert!(usage.file_events > empty.file_events);
+        assert!(usage.fired <= AE_POLL_BATCH * std::mem::size_of::<FiredEvent>());
+
+        for fd in fds {
+            ae_delete_file_event(&mut event_loop, fd, AE_READABLE);
+            unsafe { libc::close(fd) };
+        }
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -0,0 +1,303 @@
+/* Connection Migration Tests
+ *
+ * Tests for ae_conn_migrate() (ae/migrate.rs): a connection over a Unix
+ * socket pair moves between two loops driven from the test thread, with
+ * its unconsumed input, counters and timer group, after its queued
+ * output. Also AeRuntime::migrate() between the loops of a runtime.
+ */
+
+use rae::test_util::pump;
+use rae::{
+    AE_ERR, AE_NOMORE, AE_OK, AeEventLoop, AeHandle, ThreadPerCore, ae_conn_close, ae_conn_create,
+    ae_conn_migrate, ae_conn_pending_output, ae_conn_shutdown_write, ae_conn_stats, ae_conn_write,
+    ae_create_event_loop, ae_create_time_event, ae_delete_event_loop, ae_get_handle,
+    ae_get_loop_name, ae_group_timers, ae_set_time_event_group,
+};
+use std::ffi::c_void;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const SESSION: u64 = 3;
+
+#[derive(Default)]
+struct Session {
+    /* Every input the read proc was called with. */
+    inputs: Vec<Vec<u8>>,
+    /* Timers handed to the arrived proc, and the ids it saw then. */
+    moved: Vec<(i64, i64)>,
+    group_on_arrival: Vec<i64>,
+    fired: u32,
+    closed: Option<i32>,
+}
+
+fn session<'a>(client_data: *mut c_void) -> &'a mut Session {
+    unsafe { &mut *(client_data as *mut Session) }
+}
+
+/* Echoes complete lines back, leaving a partial one buffered. */
+fn echo_lines(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    input: &[u8],
+    client_data: *mut c_void,
+) -> usize {
+    session(client_data).inputs.push(input.to_vec());
+    let consumed = input
+        .iter()
+        .rposition(|&c| c == b'\n')
+        .map_or(0, |newline| newline + 1);
+    ae_conn_write(event_loop, fd, &input[..consumed]);
+    consumed
+}
+
+fn on_close(_event_loop: &mut AeEventLoop, _fd: i32, err: i32, client_data: *mut c_void) {
+    session(client_data).closed = Some(err);
+}
+
+fn on_arrived(
+    event_loop: &mut AeEventLoop,
+    _fd: i32,
+    timers: &[(i64, i64)],
+    client_data: *mut c_void,
+) {
+    let session = session(client_data);
+    /* Before the read proc sees the input the connection came with. */
+    assert!(session.inputs.len() <= 1);
+    session.moved = timers.to_vec();
+    session.group_on_arrival = ae_group_timers(event_loop, SESSION);
+}
+
+fn tick(_event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    session(client_data).fired += 1;
+    AE_NOMORE
+}
+
+fn read_exact(stream: &mut impl Read, len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).unwrap();
+    buf
+}
+
+/* Two loops and a connection of the first one. */
+fn setup(
+    session: &mut Session,
+) -> (
+    Box<AeEventLoop>,
+    Box<AeEventLoop>,
+    AeHandle,
+    i32,
+    UnixStream,
+) {
+    let mut source = ae_create_event_loop(1024).expect("Failed to create event loop");
+    let mut target = ae_create_event_loop(1024).expect("Failed to create event loop");
+    let handle = ae_get_handle(&mut target).expect("Failed to get handle");
+    let (ours, mut theirs) = UnixStream::pair().expect("Failed to create socket pair");
+    theirs
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    ours.set_nonblocking(true).unwrap();
+    let fd = ours.into_raw_fd();
+    let data = session as *mut Session as *mut c_void;
+    assert_eq!(
+        ae_conn_create(&mut source, fd, echo_lines, Some(on_close), data),
+        AE_OK
+    );
+    theirs.write_all(b"one\ntw").unwrap();
+    pump(&mut source);
+    assert_eq!(read_exact(&mut theirs, 4), b"one\n");
+    (source, target, handle, fd, theirs)
+}
+
+/* Loop index and fd of every input the runtime echoed. */
+static RUNTIME_INPUTS: Mutex<Vec<(usize, i32)>> = Mutex::new(Vec::new());
+
+fn runtime_echo(event_loop: &mut AeEventLoop, fd: i32, input: &[u8], _: *mut c_void) -> usize {
+    /* The loop index is kept in the loop name. */
+    let index: usize = ae_get_loop_name(event_loop)
+        .and_then(|name| name.rsplit('-').next()?.parse().ok())
+        .unwrap_or(usize::MAX);
+    RUNTIME_INPUTS.lock().unwrap().push((index, fd));
+    ae_conn_write(event_loop, fd, input);
+    input.len()
+}
+
+mod migrate {
+    use super::*;
+
+    #[test]
+    fn test_moves_input_counters_and_timers() {
+        let mut session = Session::default();
+        let data = &mut session as *mut Session as *mut c_void;
+        let (mut source, mut target, handle, fd, mut theirs) = setup(&mut session);
+        let timer = ae_create_time_event(&mut source, 20, tick, data, None);
+        ae_set_time_event_group(&mut source, timer, SESSION);
+        let before = ae_conn_stats(&source, fd).unwrap();
+
+        assert_eq!(
+            ae_conn_migrate(&mut source, fd, &handle, SESSION, Some(on_arrived)),
+            AE_OK
+        );
+        /* Nothing to flush: off the source right away. */
+        assert!(ae_conn_stats(&source, fd).is_none());
+        assert!(ae_group_timers(&source, SESSION).is_empty());
+        assert_eq!(session.closed, None);
+
+        pump(&mut target);
+        assert_eq!(session.moved.len(), 1);
+        assert_eq!(session.moved[0].0, timer);
+        assert_eq!(session.group_on_arrival, vec![session.moved[0].1]);
+        /* The partial line is handed to the read proc on the target. */
+        assert_eq!(session.inputs.last().unwrap(), b"tw");
+        let after = ae_conn_stats(&target, fd).unwrap();
+        assert_eq!(
+            (after.bytes_in, after.bytes_out),
+            (before.bytes_in, before.bytes_out)
+        );
+
+        theirs.write_all(b"o\n").unwrap();
+        pump(&mut target);
+        assert_eq!(read_exact(&mut theirs, 4), b"two\n");
+        std::thread::sleep(Duration::from_millis(20));
+        pump(&mut target);
+        assert_eq!(session.fired, 1);
+
+        /* Closed on the target like any of its connections. */
+        ae_conn_close(&mut target, fd);
+        assert_eq!(session.closed, Some(0));
+        pump(&mut source);
+        assert_eq!(session.fired, 1);
+        ae_delete_event_loop(source);
+        ae_delete_event_loop(target);
+    }
+
+    #[test]
+    fn test_output_is_flushed_first() {
+        let mut session = Session::default();
+        let (mut source, mut target, handle, fd, mut theirs) = setup(&mut session);
+
+        /* More than the socket buffers hold. */
+        let big: Vec<u8> = (0..4u32 << 20).map(|i| i as u8).collect();
+        ae_conn_write(&mut source, fd, &big);
+        assert!(ae_conn_pending_output(&source, fd) > 0);
+        assert_eq!(ae_conn_migrate(&mut source, fd, &handle, 0, None), AE_OK);
+        /* Still on the source, writing. */
+        assert!(ae_conn_stats(&source, fd).is_some());
+        assert_eq!(ae_conn_write(&mut source, fd, b"late\n"), AE_OK);
+
+        let big_len = big.len() as u64;
+        let reader = std::thread::spawn(move || {
+            let received = read_exact(&mut theirs, big.len());
+            assert!(received == big);
+            theirs
+        });
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while ae_conn_stats(&source, fd).is_some() && Instant::now() < deadline {
+            pump(&mut source);
+        }
+        let mut theirs = reader.join().unwrap();
+        assert!(ae_conn_stats(&source, fd).is_none());
+        assert_eq!(read_exact(&mut theirs, 5), b"late\n");
+
+        pump(&mut target);
+        let stats = ae_conn_stats(&target, fd).unwrap();
+        assert_eq!(stats.bytes_out, 4 + big_len + 5);
+        theirs.write_all(b"o\n").unwrap();
+        pump(&mut target);
+        assert_eq!(read_exact(&mut theirs, 4), b"two\n");
+
+        ae_conn_close(&mut target, fd);
+        ae_delete_event_loop(source);
+        ae_delete_event_loop(target);
+    }
+
+    #[test]
+    fn test_refused() {
+        let mut session = Session::default();
+        let (mut source, target, handle, fd, _theirs) = setup(&mut session);
+        let own = ae_get_handle(&mut source).unwrap();
+
+        assert_eq!(ae_conn_migrate(&mut source, fd, &own, 0, None), AE_ERR);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+        assert_eq!(ae_conn_migrate(&mut source, 1000, &handle, 0, None), AE_ERR);
+
+        /* Half closed connections stay. */
+        ae_conn_shutdown_write(&mut source, fd);
+        assert_eq!(ae_conn_migrate(&mut source, fd, &handle, 0, None), AE_ERR);
+        assert!(ae_conn_stats(&source, fd).is_some());
+
+        ae_delete_event_loop(target);
+        assert!(handle.is_closed());
+        ae_conn_close(&mut source, fd);
+        ae_delete_event_loop(source);
+    }
+
+    #[test]
+    fn test_dead_target_sends_it_back() {
+        let mut session = Session::default();
+        let (mut source, target, handle, fd, mut theirs) = setup(&mut session);
+        assert_eq!(
+            ae_conn_migrate(&mut source, fd, &handle, SESSION, Some(on_arrived)),
+            AE_OK
+        );
+        assert!(ae_conn_stats(&source, fd).is_none());
+
+        /* Deleted before it ran the task carrying the connection. */
+        ae_delete_event_loop(target);
+        pump(&mut source);
+        assert!(ae_conn_stats(&source, fd).is_some());
+        assert_eq!(session.closed, None);
+        assert_eq!(session.inputs.last().unwrap(), b"tw");
+        theirs.write_all(b"o\n").unwrap();
+        pump(&mut source);
+        assert_eq!(read_exact(&mut theirs, 4), b"two\n");
+
+        ae_conn_close(&mut source, fd);
+        ae_delete_event_loop(source);
+    }
+
+    #[test]
+    fn test_runtime_migrate() {
+        fn accept(event_loop: &mut AeEventLoop, fd: i32, _addr: Option<SocketAddr>) {
+            ae_conn_create(event_loop, fd, runtime_echo, None, std::ptr::null_mut());
+        }
+
+        let runtime = ThreadPerCore::new()
+            .threads(2)
+            .name("migrate")
+            .start("127.0.0.1:0".parse().unwrap(), accept)
+            .expect("Failed to start runtime");
+        assert_eq!(runtime.migrate(0, 0, 0, 0, None), AE_ERR);
+        assert_eq!(runtime.migrate(0, 0, 2, 0, None), AE_ERR);
+
+        let mut client = TcpStream::connect(runtime.local_addr()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.write_all(b"a").unwrap();
+        assert_eq!(read_exact(&mut client, 1), b"a");
+        let (from, fd) = RUNTIME_INPUTS.lock().unwrap()[0];
+        let to = 1 - from;
+        assert_eq!(runtime.migrate(fd, from, to, 0, None), AE_OK);
+
+        /* Answered from the other loop from now on. */
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            client.write_all(b"b").unwrap();
+            assert_eq!(read_exact(&mut client, 1), b"b");
+            if RUNTIME_INPUTS.lock().unwrap().last().unwrap().0 == to {
+                break;
+            }
+            assert!(Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        runtime.stop();
+        runtime.join();
+    }
+}
@@ -0,0 +1,202 @@
+/* Miri Tests
+ *
+ * Scenarios exercising the unsafe parts of the loop (client_data pointers
+ * handed back to callbacks, owned timer state, finalizers, fd_set
+ * bitmaps) on test_util::virtual_loop(), which never makes a system call,
+ * so that Miri can check them for undefined behavior:
+ *
+ *     cargo +nightly miri test --test ae_miri_tests
+ *
+ * They also run as plain tests.
+ */
+
+use rae::fd_set::FdSet;
+use rae::test_util::virtual_loop;
+use rae::{
+    AE_ALL_EVENTS, AE_BARRIER, AE_DONT_WAIT, AE_ERR, AE_FILE_EVENTS, AE_NOMORE, AE_OK, AE_READABLE,
+    AE_TIME_EVENTS, AE_WRITABLE, AeEventLoop, ae_advance_clock, ae_create_file_event,
+    ae_create_time_event, ae_create_time_event_owned, ae_delete_event_loop, ae_delete_file_event,
+    ae_delete_time_event, ae_get_file_client_data, ae_process_events, ae_resize_set_size,
+};
+use std::ffi::c_void;
+
+const NOWAIT: i32 = AE_ALL_EVENTS | AE_DONT_WAIT;
+
+type Calls = Vec<(&'static str, i32)>;
+
+fn calls<'a>(client_data: *mut c_void) -> &'a mut Calls {
+    unsafe { &mut *(client_data as *mut Calls) }
+}
+
+mod client_data {
+    use super::*;
+
+    fn on_read(_event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+        calls(client_data).push(("read", fd));
+    }
+
+    fn on_write(_event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+        calls(client_data).push(("write", fd));
+    }
+
+    /* Deletes its own fd and the next one, which fired in the same poll. */
+    fn delete_pair(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+        calls(client_data).push(("delete", fd));
+        ae_delete_file_event(event_loop, fd, AE_READABLE | AE_WRITABLE);
+        ae_delete_file_event(event_loop, fd + 1, AE_READABLE | AE_WRITABLE);
+    }
+
+    #[test]
+    fn test_client_data_reaches_callbacks() {
+        let (mut event_loop, control) = virtual_loop(64);
+        let mut log = Calls::new();
+        let log_ptr = &mut log as *mut Calls as *mut c_void;
+
+        ae_create_file_event(&mut event_loop, 3, AE_READABLE, on_read, log_ptr);
+        ae_create_file_event(&mut event_loop, 3, AE_WRITABLE, on_write, log_ptr);
+        assert_eq!(ae_get_file_client_data(&event_loop, 3), log_ptr);
+
+        control.set_ready(3, AE_READABLE | AE_WRITABLE);
+        assert_eq!(ae_process_events(&mut event_loop, NOWAIT), 1);
+        ae_create_file_event(&mut event_loop, 3, AE_BARRIER, on_read, log_ptr);
+        assert_eq!(ae_process_events(&mut event_loop, NOWAIT), 1);
+        ae_delete_event_loop(event_loop);
+
+        assert_eq!(log, [("read", 3), ("write", 3), ("write", 3), ("read", 3)]);
+    }
+
+    #[test]
+    fn test_delete_from_callback() {
+        let (mut event_loop, control) = virtual_loop(64);
+        let mut log = Calls::new();
+        let log_ptr = &mut log as *mut Calls as *mut c_void;
+
+        ae_create_file_event(&mut event_loop, 5, AE_READABLE, delete_pair, log_ptr);
+        ae_create_file_event(&mut event_loop, 6, AE_READABLE, on_read, log_ptr);
+        control.set_ready(5, AE_READABLE);
+        control.set_ready(6, AE_READABLE);
+
+        ae_process_events(&mut event_loop, NOWAIT);
+        assert_eq!(ae_process_events(&mut event_loop, NOWAIT), 0);
+        ae_delete_event_loop(event_loop);
+        assert_eq!(log, [("delete", 5)]);
+    }
+
+    #[test]
+    fn test_resize_keeps_registrations() {
+        let (mut event_loop, control) = virtual_loop(8);
+        let mut log = Calls::new();
+        let log_ptr = &mut log as *mut Calls as *mut c_void;
+
+        ae_create_file_event(&mut event_loop, 2, AE_READABLE, on_read, log_ptr);
+        assert_eq!(ae_resize_set_size(&mut event_loop, 128), AE_OK);
+        ae_create_file_event(&mut event_loop, 100, AE_READABLE, on_read, log_ptr);
+        assert_eq!(ae_resize_set_size(&mut event_loop, 50), AE_ERR);
+        ae_delete_file_event(&mut event_loop, 100, AE_READABLE);
+        assert_eq!(ae_resize_set_size(&mut event_loop, 4), AE_OK);
+
+        control.set_ready(2, AE_READABLE);
+        assert_eq!(ae_process_events(&mut event_loop, AE_FILE_EVENTS), 1);
+        assert_eq!(ae_get_file_client_data(&event_loop, 2), log_ptr);
+        ae_delete_event_loop(event_loop);
+        assert_eq!(log, [("read", 2)]);
+    }
+}
+
+mod time_events {
+    use super::*;
+
+    fn count_down(_event_loop: &mut AeEventLoop, _id: i64, counter: &mut Vec<u32>) -> i32 {
+        counter.push(counter.len() as u32);
+        if counter.len() < 3 { 10 } else { AE_NOMORE }
+    }
+
+    fn free_box(_event_loop: &mut AeEventLoop, client_data: *mut c_void) {
+        drop(unsafe { Box::from_raw(client_data as *mut u64) });
+    }
+
+    fn bump(_event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+        unsafe { *(client_data as *mut u64) += 1 };
+        AE_NOMORE
+    }
+
+    /* Deletes the timer whose id is stored in its client data. */
+    fn delete_other(event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+        let other = unsafe { *(client_data as *const i64) };
+        ae_delete_time_event(event_loop, other);
+        AE_NOMORE
+    }
+
+    #[test]
+    fn test_owned_timer_state() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        ae_create_time_event_owned(&mut event_loop, 10, count_down, Box::new(Vec::new()));
+        for _ in 0..3 {
+            ae_advance_clock(&mut event_loop, 10_000);
+            assert_eq!(
+                ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT),
+                1
+            );
+        }
+        ae_advance_clock(&mut event_loop, 10_000);
+        assert_eq!(
+            ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT),
+            0
+        );
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_finalizers_free_client_data() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let fired = Box::into_raw(Box::new(0u64)) as *mut c_void;
+        let pending = Box::into_raw(Box::new(0u64)) as *mut c_void;
+        let deleted = Box::into_raw(Box::new(0u64)) as *mut c_void;
+
+        ae_create_time_event(&mut event_loop, 0, bump, fired, Some(free_box));
+        ae_create_time_event(&mut event_loop, 1000, bump, pending, Some(free_box));
+        let id = ae_create_time_event(&mut event_loop, 5, bump, deleted, Some(free_box));
+        ae_delete_time_event(&mut event_loop, id);
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+
+        /* The loop finalizes the timer still pending. */
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_delete_due_timer_from_callback() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let mut hits = 0u64;
+        let hits_ptr = &mut hits as *mut u64 as *mut c_void;
+
+        /* Both are due in the same iteration, whichever runs first. */
+        let victim = ae_create_time_event(&mut event_loop, 0, bump, hits_ptr, None);
+        let victim_ptr = &victim as *const i64 as *mut c_void;
+        ae_create_time_event(&mut event_loop, 0, delete_other, victim_ptr, None);
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        ae_delete_event_loop(event_loop);
+        assert!(hits <= 1);
+    }
+}
+
+mod fd_set {
+    use super::*;
+
+    #[test]
+    fn test_set_clear_and_bounds() {
+        let mut set = FdSet::zero();
+        for fd in (0..libc::FD_SETSIZE as i32).step_by(7) {
+            set.set(fd);
+        }
+        set.set(-1);
+        set.set(libc::FD_SETSIZE as i32);
+        set.clr(14);
+
+        let copy = set.clone();
+        for fd in -1..=libc::FD_SETSIZE as i32 {
+            let expected = fd >= 0 && fd < libc::FD_SETSIZE as i32 && fd % 7 == 0 && fd != 14;
+            assert_eq!(copy.isset(fd), expected, "fd {fd}");
+        }
+    }
+}
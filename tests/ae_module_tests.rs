@@ -0,0 +1,90 @@
+/* Module Hook Tests
+ *
+ * Tests for the process-wide AeModule registry (ae/module.rs). Modules are
+ * global, so everything runs from a single test to keep counts exact.
+ */
+
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_READABLE, AeEventLoop, AeModule, ae_create_event_loop,
+    ae_create_file_event, ae_delete_event_loop, ae_process_events, ae_register_module,
+    ae_registered_modules,
+};
+use std::ffi::c_void;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+#[derive(Default)]
+struct HookCounts {
+    created: AtomicI32,
+    before_poll: AtomicI32,
+    after_dispatch: AtomicI32,
+    fd_register: AtomicI32,
+    shutdown: AtomicI32,
+}
+
+struct CountingModule {
+    counts: Arc<HookCounts>,
+}
+
+impl AeModule for CountingModule {
+    fn name(&self) -> &str {
+        "counting"
+    }
+
+    fn on_loop_create(&self, _event_loop: &mut AeEventLoop) {
+        self.counts.created.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn before_poll(&self, _event_loop: &mut AeEventLoop) {
+        self.counts.before_poll.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn after_dispatch(&self, _event_loop: &mut AeEventLoop, _processed: i32) {
+        self.counts.after_dispatch.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_fd_register(&self, _event_loop: &mut AeEventLoop, fd: i32, mask: i32) {
+        assert_eq!((fd, mask), (3, AE_READABLE));
+        self.counts.fd_register.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_shutdown(&self, _event_loop: &mut AeEventLoop) {
+        self.counts.shutdown.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn noop_file_proc(_el: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+#[test]
+fn test_module_hooks() {
+    let before = ae_create_event_loop(64).expect("Failed to create event loop");
+
+    let counts = Arc::new(HookCounts::default());
+    ae_register_module(Box::new(CountingModule {
+        counts: counts.clone(),
+    }));
+    assert_eq!(ae_registered_modules(), vec!["counting".to_string()]);
+
+    let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+    assert_eq!(counts.created.load(Ordering::SeqCst), 1);
+
+    ae_create_file_event(
+        &mut event_loop,
+        3,
+        AE_READABLE,
+        noop_file_proc,
+        std::ptr::null_mut(),
+    );
+    assert_eq!(counts.fd_register.load(Ordering::SeqCst), 1);
+
+    ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+    assert_eq!(counts.before_poll.load(Ordering::SeqCst), 1);
+    assert_eq!(counts.after_dispatch.load(Ordering::SeqCst), 1);
+
+    ae_delete_event_loop(event_loop);
+    assert_eq!(counts.shutdown.load(Ordering::SeqCst), 1);
+
+    /* Loops created before registration are not affected. */
+    ae_delete_event_loop(before);
+    assert_eq!(counts.shutdown.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,251 @@
+/* Networking Helper Tests
+ *
+ * Tests for the loop-aware networking helpers in ae/net.rs, driving the
+ * event loop against real local sockets, and for the peer filter of
+ * ae_accept().
+ */
+
+use rae::{
+    AE_ALL_EVENTS, AE_ERR, AeEventLoop, AeEventLoopBuilder, ae_accept, ae_create_event_loop,
+    ae_process_events, ae_tcp_connect,
+};
+use std::ffi::c_void;
+use std::io::Read;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::AsRawFd;
+
+/* (called, fd, err) */
+type ConnectRecord = (bool, i32, i32);
+
+fn record_connect(_event_loop: &mut AeEventLoop, fd: i32, err: i32, client_data: *mut c_void) {
+    let record = unsafe { &mut *(client_data as *mut ConnectRecord) };
+    record.0 = true;
+    record.1 = fd;
+    record.2 = err;
+}
+
+fn run_until_connected(event_loop: &mut AeEventLoop, record: &ConnectRecord) {
+    for _ in 0..100 {
+        if record.0 {
+            return;
+        }
+        ae_process_events(event_loop, AE_ALL_EVENTS);
+    }
+}
+
+mod tcp_connect {
+    use super::*;
+
+    #[test]
+    fn test_connect_success() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+
+        let mut record: ConnectRecord = (false, -1, -1);
+        let fd = ae_tcp_connect(
+            &mut event_loop,
+            addr,
+            1000,
+            record_connect,
+            &mut record as *mut ConnectRecord as *mut c_void,
+        );
+        assert!(fd >= 0, "Connect should start");
+
+        run_until_connected(&mut event_loop, &record);
+        assert!(record.0, "Connect callback should fire");
+        assert_eq!(record.1, fd);
+        assert_eq!(record.2, 0, "Connect should succeed");
+
+        let (_accepted, _) = listener.accept().unwrap();
+        unsafe { libc::close(fd) };
+    }
+
+    #[test]
+    fn test_connect_refused() {
+        let addr: SocketAddr = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+
+        let mut record: ConnectRecord = (false, -1, -1);
+        let fd = ae_tcp_connect(
+            &mut event_loop,
+            addr,
+            1000,
+            record_connect,
+            &mut record as *mut ConnectRecord as *mut c_void,
+        );
+
+        if fd != AE_ERR {
+            run_until_connected(&mut event_loop, &record);
+            assert!(record.0, "Connect callback should fire");
+            assert_eq!(record.2, libc::ECONNREFUSED);
+        }
+    }
+
+    #[test]
+    fn test_connect_timeout() {
+        /* TEST-NET-1 is not routable: the connect either hangs until the
+         * timeout or fails right away when there is no route at all. */
+        let addr: SocketAddr = "192.0.2.1:9".parse().unwrap();
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+
+        let mut record: ConnectRecord = (false, -1, -1);
+        let fd = ae_tcp_connect(
+            &mut event_loop,
+            addr,
+            50,
+            record_connect,
+            &mut record as *mut ConnectRecord as *mut c_void,
+        );
+
+        if fd != AE_ERR {
+            run_until_connected(&mut event_loop, &record);
+            assert!(record.0, "Connect callback should fire");
+            assert_ne!(record.2, 0, "Connect should not succeed");
+        }
+    }
+}
+
+mod accept {
+    use super::*;
+
+    #[test]
+    fn test_accept_returns_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+
+        assert_eq!(
+            ae_accept(&mut event_loop, listener.as_raw_fd(), b""),
+            Err(libc::EAGAIN)
+        );
+
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (fd, peer) =
+            ae_accept(&mut event_loop, listener.as_raw_fd(), b"").expect("accept should succeed");
+        assert_eq!(peer, Some(client.local_addr().unwrap()));
+
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        assert_ne!(flags & libc::O_NONBLOCK, 0, "Accepted fd is non-blocking");
+        unsafe { libc::close(fd) };
+    }
+
+    #[test]
+    fn test_reject_at_fd_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .reserved_fds(1)
+            .build()
+            .expect("Failed to create event loop");
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        /* Lowering the fd limit would break the other tests running in
+         * this process, so the EMFILE part runs in a child. */
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            let code = unsafe {
+                /* Every fd below the lowest free one is in use. */
+                let free = libc::dup(0);
+                libc::close(free);
+                let limit = libc::rlimit {
+                    rlim_cur: free as libc::rlim_t,
+                    rlim_max: free as libc::rlim_t,
+                };
+                libc::setrlimit(libc::RLIMIT_NOFILE, &limit);
+
+                match ae_accept(
+                    &mut event_loop,
+                    listener.as_raw_fd(),
+                    b"-ERR max clients\r\n",
+                ) {
+                    Err(libc::EMFILE) => 0,
+                    _ => 1,
+                }
+            };
+            unsafe { libc::_exit(code) };
+        }
+
+        let mut status = 0;
+        unsafe { libc::waitpid(pid, &mut status, 0) };
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(
+            libc::WEXITSTATUS(status),
+            0,
+            "accept should fail with EMFILE"
+        );
+
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "-ERR max clients\r\n");
+    }
+}
+
+mod peer_filter {
+    use super::*;
+    use rae::{ae_get_stats, ae_set_peer_filter};
+    use std::sync::atomic::{AtomicU16, Ordering};
+
+    /* Client port refused by refuse_port(). */
+    static REFUSED_PORT: AtomicU16 = AtomicU16::new(0);
+
+    fn refuse_port(
+        _event_loop: &mut AeEventLoop,
+        _listen_fd: i32,
+        addr: Option<SocketAddr>,
+    ) -> bool {
+        addr.map(|addr| addr.port()) != Some(REFUSED_PORT.load(Ordering::SeqCst))
+    }
+
+    fn refuse_all(
+        _event_loop: &mut AeEventLoop,
+        _listen_fd: i32,
+        _addr: Option<SocketAddr>,
+    ) -> bool {
+        false
+    }
+
+    #[test]
+    fn test_refused_peer_is_skipped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        ae_set_peer_filter(&mut event_loop, Some(refuse_port));
+
+        let mut refused = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        REFUSED_PORT.store(refused.local_addr().unwrap().port(), Ordering::SeqCst);
+        let allowed = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        let (fd, peer) =
+            ae_accept(&mut event_loop, listener.as_raw_fd(), b"").expect("accept should succeed");
+        assert_eq!(peer, Some(allowed.local_addr().unwrap()));
+        assert_eq!(
+            ae_accept(&mut event_loop, listener.as_raw_fd(), b""),
+            Err(libc::EAGAIN)
+        );
+        assert_eq!(ae_get_stats(&event_loop).peers_filtered, 1);
+
+        /* Closed without a byte. */
+        let mut reply = Vec::new();
+        assert_eq!(refused.read_to_end(&mut reply).unwrap(), 0);
+        unsafe { libc::close(fd) };
+    }
+
+    #[test]
+    fn test_no_filter_keeps_everyone() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        ae_set_peer_filter(&mut event_loop, Some(refuse_all));
+        ae_set_peer_filter(&mut event_loop, None);
+
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (fd, _) =
+            ae_accept(&mut event_loop, listener.as_raw_fd(), b"").expect("accept should succeed");
+        assert_eq!(ae_get_stats(&event_loop).peers_filtered, 0);
+        unsafe { libc::close(fd) };
+    }
+}
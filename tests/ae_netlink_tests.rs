@@ -0,0 +1,180 @@
+/* Netlink Tests
+ *
+ * Tests for netlink sockets (ae/netlink.rs, Linux only): a link dump over
+ * NETLINK_ROUTE read message by message, and uevent parsing.
+ */
+
+#![cfg(target_os = "linux")]
+
+use rae::{
+    AE_DONT_WAIT, AE_ERR, AE_FILE_EVENTS, AE_OK, AeEventLoop, AeNetlinkMessage,
+    ae_create_event_loop, ae_delete_event_loop, ae_get_file_events, ae_netlink_register,
+    ae_netlink_unregister, ae_process_events, ae_uevent_parse, anet_netlink_send,
+    anet_netlink_socket,
+};
+use std::ffi::c_void;
+
+/* Type, flags, sequence number and payload of each message seen. */
+#[derive(Default)]
+struct Messages {
+    seen: Vec<(u16, u16, u32, Vec<u8>)>,
+    unregister_on: Option<u16>,
+}
+
+fn record(
+    event_loop: &mut AeEventLoop,
+    fd: i32,
+    message: &AeNetlinkMessage<'_>,
+    data: *mut c_void,
+) {
+    let messages = unsafe { &mut *(data as *mut Messages) };
+    messages.seen.push((
+        message.msg_type,
+        message.flags,
+        message.seq,
+        message.payload.to_vec(),
+    ));
+    if messages.unregister_on == Some(message.msg_type) {
+        assert_eq!(ae_netlink_unregister(event_loop, fd), AE_OK);
+    }
+}
+
+/* Ask for every link: an RTM_GETLINK dump with an empty ifinfomsg. */
+fn dump_links(fd: i32, seq: u32) {
+    let ifinfomsg = [0u8; 16];
+    let sent = anet_netlink_send(
+        fd,
+        libc::RTM_GETLINK,
+        libc::NLM_F_DUMP as u16,
+        seq,
+        &ifinfomsg,
+    );
+    assert_eq!(sent, Ok(16 + ifinfomsg.len()));
+}
+
+fn run_until_done(event_loop: &mut AeEventLoop, messages: &Messages) {
+    for _ in 0..100 {
+        if messages.seen.iter().any(|m| m.0 == libc::NLMSG_DONE as u16) {
+            return;
+        }
+        ae_process_events(event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+    }
+}
+
+mod route {
+    use super::*;
+
+    #[test]
+    fn test_link_dump_message_by_message() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let fd = anet_netlink_socket(libc::NETLINK_ROUTE, 0).expect("Failed to open netlink");
+        let mut messages = Messages::default();
+        let data = &mut messages as *mut Messages as *mut c_void;
+        assert_eq!(
+            ae_netlink_register(&mut event_loop, fd, record, data),
+            AE_OK
+        );
+
+        dump_links(fd, 42);
+        run_until_done(&mut event_loop, &messages);
+        let (done, links) = messages.seen.split_last().expect("No messages");
+        assert_eq!(done.0, libc::NLMSG_DONE as u16);
+        /* At least the loopback interface, each link its own message. */
+        assert!(!links.is_empty());
+        for (msg_type, flags, seq, payload) in links {
+            assert_eq!(*msg_type, libc::RTM_NEWLINK);
+            assert_ne!(*flags & libc::NLM_F_MULTI as u16, 0);
+            assert_eq!(*seq, 42);
+            assert!(payload.len() >= 16);
+        }
+
+        assert_eq!(ae_netlink_unregister(&mut event_loop, fd), AE_OK);
+        assert_eq!(ae_get_file_events(&event_loop, fd), 0);
+        unsafe { libc::close(fd) };
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_unregister_from_the_proc() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let fd = anet_netlink_socket(libc::NETLINK_ROUTE, 0).expect("Failed to open netlink");
+        let mut messages = Messages {
+            unregister_on: Some(libc::RTM_NEWLINK),
+            ..Default::default()
+        };
+        let data = &mut messages as *mut Messages as *mut c_void;
+        assert_eq!(
+            ae_netlink_register(&mut event_loop, fd, record, data),
+            AE_OK
+        );
+
+        dump_links(fd, 1);
+        for _ in 0..10 {
+            ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        }
+        /* Nothing after the message that unregistered. */
+        assert_eq!(messages.seen.len(), 1);
+        assert_eq!(ae_netlink_unregister(&mut event_loop, fd), AE_ERR);
+        unsafe { libc::close(fd) };
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_not_a_netlink_socket() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let fd = std::os::fd::AsRawFd::as_raw_fd(&socket);
+        let data = std::ptr::null_mut();
+        assert_eq!(
+            ae_netlink_register(&mut event_loop, fd, record, data),
+            AE_ERR
+        );
+        assert_eq!(ae_get_file_events(&event_loop, fd), 0);
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod uevent {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let payload = b"add@/devices/virtual/net/veth0\0ACTION=add\0DEVPATH=/devices/virtual/net/veth0\0SUBSYSTEM=net\0INTERFACE=veth0\0SEQNUM=1234\0";
+        let uevent = ae_uevent_parse(payload).expect("Not a uevent");
+        assert_eq!(uevent.action, "add");
+        assert_eq!(uevent.devpath, "/devices/virtual/net/veth0");
+        assert_eq!(uevent.vars.len(), 5);
+        assert_eq!(uevent.vars[2], ("SUBSYSTEM", "net"));
+        assert_eq!(uevent.vars[4], ("SEQNUM", "1234"));
+    }
+
+    #[test]
+    fn test_parse_rejects_udev_messages() {
+        assert!(ae_uevent_parse(b"libudev\0\xfe\xed\xca\xfe").is_none());
+        assert!(ae_uevent_parse(b"").is_none());
+    }
+
+    #[test]
+    fn test_register_uevent_socket() {
+        /* Multicast groups need privileges in some containers. */
+        let Ok(fd) = anet_netlink_socket(libc::NETLINK_KOBJECT_UEVENT, 1) else {
+            return;
+        };
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut messages = Messages::default();
+        let data = &mut messages as *mut Messages as *mut c_void;
+        assert_eq!(
+            ae_netlink_register(&mut event_loop, fd, record, data),
+            AE_OK
+        );
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        /* Whatever the host emitted, each message is a whole uevent. */
+        for (msg_type, _, _, payload) in &messages.seen {
+            assert_eq!(*msg_type, 0);
+            assert!(ae_uevent_parse(payload).is_some());
+        }
+        ae_netlink_unregister(&mut event_loop, fd);
+        unsafe { libc::close(fd) };
+        ae_delete_event_loop(event_loop);
+    }
+}
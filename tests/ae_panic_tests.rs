@@ -0,0 +1,184 @@
+/* Callback Panic Tests
+ *
+ * Tests for the panic policy and crash reporter (ae/panic.rs). The panics
+ * are deliberate, their messages show up in the test output.
+ */
+
+use rae::anet::anet_pipe;
+use rae::test_util::run_once;
+use rae::{
+    AE_NONE, AE_READABLE, AeCrashReport, AeDispatchSource, AeEventLoop, AePanicPolicy,
+    ae_create_event_loop, ae_create_file_event, ae_create_time_event, ae_delete_event_loop,
+    ae_dispatch_stack, ae_get_file_events, ae_get_panic_policy, ae_get_stats,
+    ae_pending_time_events, ae_set_crash_reporter, ae_set_panic_policy,
+};
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+thread_local! {
+    static REPORTS: RefCell<Vec<AeCrashReport>> = const { RefCell::new(Vec::new()) };
+}
+
+fn report(_event_loop: &mut AeEventLoop, report: &AeCrashReport) {
+    REPORTS.with(|reports| reports.borrow_mut().push(report.clone()));
+}
+
+fn take_reports() -> Vec<AeCrashReport> {
+    REPORTS.with(|reports| reports.take())
+}
+
+fn panicking_timer(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    panic!("timer exploded");
+}
+
+fn panicking_reader(_event_loop: &mut AeEventLoop, fd: i32, _client_data: *mut c_void, _mask: i32) {
+    panic!("reader of fd {fd} exploded");
+}
+
+mod propagate {
+    use super::*;
+
+    #[test]
+    fn test_default_unwinds_out_of_the_loop() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        assert_eq!(ae_get_panic_policy(&event_loop), AePanicPolicy::Propagate);
+        ae_create_time_event(
+            &mut event_loop,
+            0,
+            panicking_timer,
+            std::ptr::null_mut(),
+            None,
+        );
+        let result = catch_unwind(AssertUnwindSafe(|| run_once(&mut event_loop)));
+        assert!(result.is_err());
+        assert!(take_reports().is_empty());
+    }
+
+    #[test]
+    fn test_reporter_runs_before_unwinding() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_set_crash_reporter(&mut event_loop, Some(report));
+        let id = ae_create_time_event(
+            &mut event_loop,
+            0,
+            panicking_timer,
+            std::ptr::null_mut(),
+            None,
+        );
+        let result = catch_unwind(AssertUnwindSafe(|| run_once(&mut event_loop)));
+        assert!(result.is_err());
+
+        let reports = take_reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].message.as_deref(), Some("timer exploded"));
+        assert_eq!(reports[0].policy, AePanicPolicy::Propagate);
+        assert_eq!(reports[0].stack.len(), 1);
+        assert_eq!(reports[0].stack[0].source, AeDispatchSource::Timer { id });
+    }
+}
+
+mod carry_on {
+    use super::*;
+
+    #[test]
+    fn test_panicking_timer_is_dropped() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_set_panic_policy(&mut event_loop, AePanicPolicy::Continue);
+        ae_set_crash_reporter(&mut event_loop, Some(report));
+        ae_create_time_event(
+            &mut event_loop,
+            0,
+            panicking_timer,
+            std::ptr::null_mut(),
+            None,
+        );
+
+        run_once(&mut event_loop);
+        assert_eq!(ae_pending_time_events(&event_loop), 0);
+        assert_eq!(ae_get_stats(&event_loop).callback_panics, 1);
+        assert!(ae_dispatch_stack(&event_loop).is_empty());
+        assert_eq!(take_reports().len(), 1);
+
+        run_once(&mut event_loop);
+        assert_eq!(ae_get_stats(&event_loop).callback_panics, 1);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_panicking_fd_is_unwatched() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_set_panic_policy(&mut event_loop, AePanicPolicy::Continue);
+        ae_set_crash_reporter(&mut event_loop, Some(report));
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            panicking_reader,
+            std::ptr::null_mut(),
+        );
+        unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+
+        run_once(&mut event_loop);
+        assert_eq!(ae_get_file_events(&event_loop, rfd), AE_NONE);
+        let reports = take_reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(
+            reports[0].message,
+            Some(format!("reader of fd {rfd} exploded"))
+        );
+        assert_eq!(
+            reports[0].stack[0].source,
+            AeDispatchSource::File {
+                fd: rfd,
+                mask: AE_READABLE
+            }
+        );
+
+        /* Still readable, but nobody is called any more. */
+        run_once(&mut event_loop);
+        assert_eq!(ae_get_stats(&event_loop).callback_panics, 1);
+        ae_delete_event_loop(event_loop);
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+
+    /* The error queue handler goes with the others. */
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_error_queue_handler_is_unwatched_too() {
+        use rae::{AE_ERRQUEUE, AeEventLoopBuilder};
+
+        let mut event_loop = AeEventLoopBuilder::new(64)
+            .best_backend()
+            .build()
+            .expect("Failed to create event loop");
+        if event_loop.api_name() != "epoll" {
+            return;
+        }
+        ae_set_panic_policy(&mut event_loop, AePanicPolicy::Continue);
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        for mask in [AE_READABLE, AE_ERRQUEUE] {
+            ae_create_file_event(
+                &mut event_loop,
+                rfd,
+                mask,
+                panicking_reader,
+                std::ptr::null_mut(),
+            );
+        }
+        unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+
+        run_once(&mut event_loop);
+        assert_eq!(ae_get_file_events(&event_loop, rfd), AE_NONE);
+        take_reports();
+        ae_delete_event_loop(event_loop);
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+/* Pause / Resume Tests
+ *
+ * Tests for ae_pause() and ae_resume(): while paused neither file events
+ * nor timers may fire, and both pick up again once the loop is resumed.
+ */
+
+use rae::anet::anet_pipe;
+use rae::test_util::trigger_readable;
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_NOMORE, AE_OK, AE_READABLE, AeEventLoop, ae_create_event_loop,
+    ae_create_file_event, ae_create_time_event, ae_get_handle, ae_is_paused, ae_pause,
+    ae_process_events, ae_resume,
+};
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+fn count_readable(_event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let mut buf = [0u8; 64];
+    unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+    let counter = unsafe { &*(client_data as *const AtomicUsize) };
+    counter.fetch_add(1, Ordering::SeqCst);
+}
+
+fn count_timer(_event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    let counter = unsafe { &*(client_data as *const AtomicUsize) };
+    counter.fetch_add(1, Ordering::SeqCst);
+    AE_NOMORE
+}
+
+mod pause {
+    use super::*;
+
+    #[test]
+    fn test_paused_loop_dispatches_nothing() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        let reads = AtomicUsize::new(0);
+        let fires = AtomicUsize::new(0);
+
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            count_readable,
+            &reads as *const AtomicUsize as *mut c_void,
+        );
+        ae_create_time_event(
+            &mut event_loop,
+            10,
+            count_timer,
+            &fires as *const AtomicUsize as *mut c_void,
+            None,
+        );
+
+        assert_eq!(ae_pause(&mut event_loop), AE_OK);
+        assert!(ae_is_paused(&event_loop));
+
+        trigger_readable(wfd);
+        thread::sleep(Duration::from_millis(20));
+        for _ in 0..3 {
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        }
+        assert_eq!(reads.load(Ordering::SeqCst), 0, "No reads while paused");
+        assert_eq!(fires.load(Ordering::SeqCst), 0, "No timers while paused");
+
+        assert_eq!(ae_resume(&mut event_loop), AE_OK);
+        assert!(!ae_is_paused(&event_loop));
+
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(reads.load(Ordering::SeqCst), 1, "Pending read after resume");
+        assert_eq!(
+            fires.load(Ordering::SeqCst),
+            0,
+            "Timer shifted by the paused duration"
+        );
+
+        thread::sleep(Duration::from_millis(20));
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(fires.load(Ordering::SeqCst), 1, "Timer fires after resume");
+
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+
+    #[test]
+    fn test_registration_while_paused() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        let reads = AtomicUsize::new(0);
+
+        ae_pause(&mut event_loop);
+        let result = ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            count_readable,
+            &reads as *const AtomicUsize as *mut c_void,
+        );
+        assert_eq!(result, AE_OK);
+
+        trigger_readable(wfd);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(reads.load(Ordering::SeqCst), 0);
+
+        ae_resume(&mut event_loop);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+
+    #[test]
+    fn test_resume_from_another_thread() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let handle = ae_get_handle(&mut event_loop).expect("Failed to get handle");
+        ae_pause(&mut event_loop);
+
+        let poster = thread::spawn(move || {
+            handle.post(|el| {
+                ae_resume(el);
+            })
+        });
+        assert_eq!(poster.join().unwrap(), AE_OK);
+
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        assert!(!ae_is_paused(&event_loop), "Handle task should resume loop");
+    }
+}
@@ -0,0 +1,59 @@
+/* Kernel Feature Probe Tests
+ *
+ * Tests for ae_kernel_features() and the backend choice built on it
+ * (ae/probe.rs).
+ */
+
+use rae::{
+    AE_ERR, AeEventLoopBuilder, ae_attach_child_loop, ae_create_event_loop, ae_detach_child_loop,
+    ae_kernel_features,
+};
+
+mod features {
+    use super::*;
+
+    #[test]
+    fn test_results_are_cached() {
+        assert_eq!(ae_kernel_features(), ae_kernel_features());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_linux_basics() {
+        let features = ae_kernel_features();
+        assert!(features.epoll);
+        assert!(features.timerfd);
+        assert!(features.signalfd);
+        assert!(!features.kqueue);
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_macos_basics() {
+        let features = ae_kernel_features();
+        assert!(features.kqueue);
+        assert!(!features.epoll);
+        assert!(!features.io_uring);
+    }
+}
+
+mod best_backend {
+    use super::*;
+
+    #[test]
+    fn test_best_backend_is_pollable() {
+        let features = ae_kernel_features();
+        let mut parent = ae_create_event_loop(64).expect("Failed to create event loop");
+        let child = AeEventLoopBuilder::new(64)
+            .best_backend()
+            .build()
+            .expect("Failed to create event loop");
+
+        /* Only epoll and kqueue loops can be attached to a parent. */
+        let fd = ae_attach_child_loop(&mut parent, child);
+        assert_eq!(fd != AE_ERR, features.epoll || features.kqueue);
+        if fd != AE_ERR {
+            assert!(ae_detach_child_loop(&mut parent, fd).is_some());
+        }
+    }
+}
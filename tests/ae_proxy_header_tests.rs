@@ -0,0 +1,322 @@
+/* PROXY Protocol Tests
+ *
+ * Tests for ae/proxy_header.rs: parsing of version 1 and 2 headers, and
+ * ae_proxy_header_accept() taking the header off real local connections.
+ */
+
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_ERR, AE_OK, AE_PROXY_HEADER_V1_MAX, AeEventLoop, AeProxyHeader,
+    ae_create_event_loop, ae_delete_event_loop, ae_pending_time_events, ae_process_events,
+    ae_proxy_header_accept, ae_proxy_header_parse, ae_registered_file_events,
+};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::IntoRawFd;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/* A version 2 header: command, family and address block. */
+fn v2(command: u8, family: u8, block: &[u8]) -> Vec<u8> {
+    let mut header = V2_SIGNATURE.to_vec();
+    header.push(0x20 | command);
+    header.push(family);
+    header.extend_from_slice(&(block.len() as u16).to_be_bytes());
+    header.extend_from_slice(block);
+    header
+}
+
+fn addr(s: &str) -> Option<SocketAddr> {
+    Some(s.parse().unwrap())
+}
+
+mod parse_v1 {
+    use super::*;
+
+    #[test]
+    fn test_tcp4() {
+        let input = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /";
+        let (header, len) = ae_proxy_header_parse(input).unwrap().unwrap();
+        assert_eq!(
+            header,
+            AeProxyHeader {
+                version: 1,
+                source: addr("192.0.2.1:56324"),
+                destination: addr("198.51.100.1:443"),
+            }
+        );
+        assert_eq!(&input[len..], b"GET /");
+    }
+
+    #[test]
+    fn test_tcp6() {
+        let input = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 80\r\n";
+        let (header, len) = ae_proxy_header_parse(input).unwrap().unwrap();
+        assert_eq!(header.source, addr("[2001:db8::1]:4000"));
+        assert_eq!(header.destination, addr("[2001:db8::2]:80"));
+        assert_eq!(len, input.len());
+    }
+
+    #[test]
+    fn test_unknown_has_no_address() {
+        let input = b"PROXY UNKNOWN whatever follows\r\n";
+        let (header, len) = ae_proxy_header_parse(input).unwrap().unwrap();
+        assert_eq!((header.source, header.destination), (None, None));
+        assert_eq!(len, input.len());
+    }
+
+    #[test]
+    fn test_incomplete() {
+        let input = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n";
+        for end in 0..input.len() - 1 {
+            assert_eq!(ae_proxy_header_parse(&input[..end]), Ok(None), "{end}");
+        }
+    }
+
+    #[test]
+    fn test_malformed() {
+        for input in [
+            &b"GET / HTTP/1.1\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n",
+            b"PROXY TCP4 2001:db8::1 198.51.100.1 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 65536\r\n",
+            b"PROXY UDP4 192.0.2.1 198.51.100.1 56324 443\r\n",
+        ] {
+            assert_eq!(ae_proxy_header_parse(input), Err(libc::EPROTO));
+        }
+    }
+
+    #[test]
+    fn test_too_long() {
+        let mut input = b"PROXY UNKNOWN ".to_vec();
+        input.resize(AE_PROXY_HEADER_V1_MAX, b'x');
+        assert_eq!(ae_proxy_header_parse(&input), Err(libc::EPROTO));
+    }
+}
+
+mod parse_v2 {
+    use super::*;
+
+    #[test]
+    fn test_inet() {
+        let block = [192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb];
+        let mut input = v2(1, 0x11, &block);
+        let header_len = input.len();
+        input.extend_from_slice(b"payload");
+        let (header, len) = ae_proxy_header_parse(&input).unwrap().unwrap();
+        assert_eq!(
+            header,
+            AeProxyHeader {
+                version: 2,
+                source: addr("192.0.2.1:56324"),
+                destination: addr("198.51.100.1:443"),
+            }
+        );
+        assert_eq!(len, header_len);
+    }
+
+    #[test]
+    fn test_inet6_with_tlvs() {
+        let mut block = Vec::new();
+        block.extend_from_slice(
+            &"2001:db8::1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets(),
+        );
+        block.extend_from_slice(
+            &"2001:db8::2"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets(),
+        );
+        block.extend_from_slice(&4000u16.to_be_bytes());
+        block.extend_from_slice(&80u16.to_be_bytes());
+        /* PP2_TYPE_AUTHORITY TLV, skipped. */
+        block.extend_from_slice(&[0x02, 0x00, 0x03, b'a', b'.', b'b']);
+        let input = v2(1, 0x21, &block);
+        let (header, len) = ae_proxy_header_parse(&input).unwrap().unwrap();
+        assert_eq!(header.source, addr("[2001:db8::1]:4000"));
+        assert_eq!(header.destination, addr("[2001:db8::2]:80"));
+        assert_eq!(len, input.len());
+    }
+
+    #[test]
+    fn test_local_and_unix_have_no_address() {
+        let local = v2(0, 0x11, &[0; 12]);
+        let (header, _) = ae_proxy_header_parse(&local).unwrap().unwrap();
+        assert_eq!((header.source, header.destination), (None, None));
+
+        let unix = v2(1, 0x31, &[0; 216]);
+        let (header, len) = ae_proxy_header_parse(&unix).unwrap().unwrap();
+        assert_eq!((header.source, header.destination), (None, None));
+        assert_eq!(len, unix.len());
+    }
+
+    #[test]
+    fn test_incomplete() {
+        let input = v2(1, 0x11, &[0; 12]);
+        for end in 0..input.len() {
+            assert_eq!(ae_proxy_header_parse(&input[..end]), Ok(None), "{end}");
+        }
+    }
+
+    #[test]
+    fn test_malformed() {
+        let mut bad_version = v2(1, 0x11, &[0; 12]);
+        bad_version[12] = 0x11;
+        let bad_command = v2(2, 0x11, &[0; 12]);
+        let short_block = v2(1, 0x11, &[0; 8]);
+        for input in [bad_version, bad_command, short_block] {
+            assert_eq!(ae_proxy_header_parse(&input), Err(libc::EPROTO));
+        }
+    }
+}
+
+mod accept {
+    use super::*;
+
+    /* Address and payload each accepted connection arrived with. */
+    static ACCEPTED: Mutex<Vec<(Option<SocketAddr>, Vec<u8>)>> = Mutex::new(Vec::new());
+
+    fn record(_event_loop: &mut AeEventLoop, fd: i32, addr: Option<SocketAddr>) {
+        let mut buf = [0u8; 64];
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        let payload = buf[..n.max(0) as usize].to_vec();
+        unsafe { libc::close(fd) };
+        ACCEPTED.lock().unwrap().push((addr, payload));
+    }
+
+    /* A connected pair: the client, and the accepted fd. */
+    fn pair() -> (TcpStream, i32) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        server.set_nonblocking(true).unwrap();
+        (client, server.into_raw_fd())
+    }
+
+    fn run(event_loop: &mut AeEventLoop, until: impl Fn(&AeEventLoop) -> bool) {
+        for _ in 0..200 {
+            if until(event_loop) {
+                return;
+            }
+            ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /* Closed by the loop, as seen from the client. */
+    fn hung_up(client: &mut TcpStream) -> bool {
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buf = [0u8; 16];
+        matches!(client.read(&mut buf), Ok(0) | Err(_))
+    }
+
+    #[test]
+    fn test_header_in_pieces() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (mut client, fd) = pair();
+        assert_eq!(
+            ae_proxy_header_accept(&mut event_loop, fd, 5000, record),
+            AE_OK
+        );
+
+        let header = b"PROXY TCP4 203.0.113.7 198.51.100.1 40000 443\r\n";
+        client.write_all(&header[..10]).unwrap();
+        run(&mut event_loop, |_| false);
+        /* Still waiting for the rest. */
+        assert_eq!(ae_registered_file_events(&event_loop), 1);
+
+        /* The rest of the header, and the first request right behind it. */
+        client.write_all(&header[10..]).unwrap();
+        client.write_all(b"PING\r\n").unwrap();
+        let expected = (addr("203.0.113.7:40000"), b"PING\r\n".to_vec());
+        run(&mut event_loop, |_| {
+            ACCEPTED.lock().unwrap().contains(&expected)
+        });
+        assert!(ACCEPTED.lock().unwrap().contains(&expected));
+
+        /* Nothing is left behind: no file event, no timer. */
+        assert_eq!(ae_registered_file_events(&event_loop), 0);
+        assert_eq!(ae_pending_time_events(&event_loop), 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_v2_header() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (mut client, fd) = pair();
+        assert_eq!(
+            ae_proxy_header_accept(&mut event_loop, fd, 0, record),
+            AE_OK
+        );
+
+        let mut input = v2(
+            1,
+            0x11,
+            &[203, 0, 113, 8, 198, 51, 100, 1, 0x9c, 0x41, 0x01, 0xbb],
+        );
+        input.extend_from_slice(b"v2");
+        client.write_all(&input).unwrap();
+        let expected = (addr("203.0.113.8:40001"), b"v2".to_vec());
+        run(&mut event_loop, |_| {
+            ACCEPTED.lock().unwrap().contains(&expected)
+        });
+        assert!(ACCEPTED.lock().unwrap().contains(&expected));
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_garbage_is_closed() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (mut client, fd) = pair();
+        assert_eq!(
+            ae_proxy_header_accept(&mut event_loop, fd, 5000, record),
+            AE_OK
+        );
+
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        run(&mut event_loop, |event_loop| {
+            ae_registered_file_events(event_loop) == 0
+        });
+        assert!(hung_up(&mut client));
+        assert_eq!(ae_registered_file_events(&event_loop), 0);
+        assert_eq!(ae_pending_time_events(&event_loop), 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_silent_client_times_out() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (mut client, fd) = pair();
+        assert_eq!(
+            ae_proxy_header_accept(&mut event_loop, fd, 10, record),
+            AE_OK
+        );
+
+        run(&mut event_loop, |event_loop| {
+            ae_registered_file_events(event_loop) == 0
+        });
+        assert!(hung_up(&mut client));
+        assert_eq!(ae_pending_time_events(&event_loop), 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_fd_beyond_setsize() {
+        let mut event_loop = ae_create_event_loop(1).expect("Failed to create event loop");
+        let (_client, fd) = pair();
+        assert_eq!(
+            ae_proxy_header_accept(&mut event_loop, fd, 5000, record),
+            AE_ERR
+        );
+        /* Still open, and the caller's. */
+        assert_ne!(unsafe { libc::fcntl(fd, libc::F_GETFD) }, -1);
+        unsafe { libc::close(fd) };
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -0,0 +1,274 @@
+/* Proxy Tests
+ *
+ * Tests for ae_proxy_create() (ae/proxy.rs): a client and a server socket
+ * pair joined by a proxy, with the test writing and reading at both ends.
+ */
+
+use rae::{
+    AE_DONT_WAIT, AE_ERR, AE_FILE_EVENTS, AE_OK, AE_READABLE, AE_WRITABLE, AeEventLoop,
+    ae_create_event_loop, ae_create_file_event, ae_delete_event_loop, ae_delete_file_event,
+    ae_get_file_events, ae_process_events, ae_proxy_close, ae_proxy_create, ae_proxy_stats,
+};
+use std::ffi::c_void;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixStream;
+
+/* Where the close proc records its call. */
+type Closed = Option<(i32, i32, i32)>;
+
+fn record_close(_event_loop: &mut AeEventLoop, a: i32, b: i32, err: i32, client_data: *mut c_void) {
+    unsafe { *(client_data as *mut Closed) = Some((a, b, err)) };
+}
+
+fn noop(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+/* A proxy between two socket pairs: returns the client end, the server
+ * end and the proxy fds. */
+fn proxy(event_loop: &mut AeEventLoop, closed: &mut Closed) -> (UnixStream, UnixStream, i32, i32) {
+    let (client, a) = UnixStream::pair().expect("Failed to create socket pair");
+    let (b, server) = UnixStream::pair().expect("Failed to create socket pair");
+    let (a, b) = (a.into_raw_fd(), b.into_raw_fd());
+    let result = ae_proxy_create(
+        event_loop,
+        a,
+        b,
+        Some(record_close),
+        closed as *mut Closed as *mut c_void,
+    );
+    assert_eq!(result, AE_OK);
+    client.set_nonblocking(true).unwrap();
+    server.set_nonblocking(true).unwrap();
+    (client, server, a, b)
+}
+
+fn run(event_loop: &mut AeEventLoop) {
+    for _ in 0..8 {
+        ae_process_events(event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+    }
+}
+
+/* Read what `stream` has right now. */
+fn read_available(mut stream: &UnixStream) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => return data,
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => return data,
+            Err(err) => panic!("read failed: {err}"),
+        }
+    }
+}
+
+fn is_eof(mut stream: &UnixStream) -> bool {
+    matches!(stream.read(&mut [0u8; 1]), Ok(0))
+}
+
+mod forwarding {
+    use super::*;
+
+    #[test]
+    fn test_both_directions() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut closed = None;
+        let (mut client, mut server, a, b) = proxy(&mut event_loop, &mut closed);
+
+        client.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        run(&mut event_loop);
+        assert_eq!(read_available(&server), b"GET / HTTP/1.0\r\n\r\n");
+        server.write_all(b"HTTP/1.0 200 OK\r\n\r\n").unwrap();
+        run(&mut event_loop);
+        assert_eq!(read_available(&client), b"HTTP/1.0 200 OK\r\n\r\n");
+
+        let stats = ae_proxy_stats(&event_loop, b).expect("Not a proxy");
+        assert_eq!((stats.a, stats.b), (a, b));
+        assert_eq!((stats.a_to_b, stats.b_to_a), (18, 19));
+        assert_eq!(stats.zero_copy, cfg!(target_os = "linux"));
+        assert_eq!(closed, None);
+
+        assert_eq!(ae_proxy_close(&mut event_loop, a), AE_OK);
+        assert_eq!(closed, Some((a, b, 0)));
+        assert_eq!(ae_get_file_events(&event_loop, a), 0);
+        assert_eq!(ae_get_file_events(&event_loop, b), 0);
+        assert!(ae_proxy_stats(&event_loop, a).is_none());
+        assert!(is_eof(&client) && is_eof(&server));
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_tcp() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).unwrap();
+        let a = listener.accept().unwrap().0;
+        let b = TcpStream::connect(addr).unwrap();
+        let mut server = listener.accept().unwrap().0;
+
+        let mut closed = None;
+        let (a, b) = (a.into_raw_fd(), b.into_raw_fd());
+        let result = ae_proxy_create(
+            &mut event_loop,
+            a,
+            b,
+            Some(record_close),
+            &mut closed as *mut Closed as *mut c_void,
+        );
+        assert_eq!(result, AE_OK);
+
+        let data: Vec<u8> = (0..200_000u32).map(|n| n as u8).collect();
+        client.write_all(&data).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        server.set_nonblocking(true).unwrap();
+        let mut received = Vec::new();
+        for _ in 0..1000 {
+            ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+            let mut buf = [0u8; 65536];
+            match server.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => received.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => panic!("read failed: {err}"),
+            }
+        }
+        assert!(received == data, "received {} bytes", received.len());
+        let stats = ae_proxy_stats(&event_loop, a).expect("Not a proxy");
+        assert_eq!(stats.a_to_b, data.len() as u64);
+        ae_proxy_close(&mut event_loop, a);
+        assert_eq!(closed, Some((a, b, 0)));
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod backpressure {
+    use super::*;
+
+    #[test]
+    fn test_slow_reader_stops_the_source() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut closed = None;
+        let (mut client, server, a, b) = proxy(&mut event_loop, &mut closed);
+
+        /* Write until the client socket, the proxy and the server socket
+         * are all full. */
+        let pattern = |n: usize| (n % 251) as u8;
+        let mut sent = 0;
+        let mut stalled = 0;
+        while stalled < 3 {
+            let chunk: Vec<u8> = (sent..sent + 4096).map(pattern).collect();
+            match client.write(&chunk) {
+                Ok(n) => {
+                    sent += n;
+                    stalled = 0;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => stalled += 1,
+                Err(err) => panic!("write failed: {err}"),
+            }
+            run(&mut event_loop);
+        }
+        /* The proxy waits for the server side, not for more input. */
+        assert_eq!(ae_get_file_events(&event_loop, a) & AE_READABLE, 0);
+        assert_ne!(ae_get_file_events(&event_loop, b) & AE_WRITABLE, 0);
+
+        let mut received = Vec::new();
+        for _ in 0..10_000 {
+            received.extend(read_available(&server));
+            if received.len() >= sent {
+                break;
+            }
+            run(&mut event_loop);
+        }
+        assert_eq!(received.len(), sent);
+        assert!(
+            received
+                .iter()
+                .enumerate()
+                .all(|(n, &byte)| byte == pattern(n))
+        );
+        /* Drained: reading the client side again. */
+        assert_ne!(ae_get_file_events(&event_loop, a) & AE_READABLE, 0);
+        assert_eq!(ae_get_file_events(&event_loop, b) & AE_WRITABLE, 0);
+
+        ae_proxy_close(&mut event_loop, b);
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod closing {
+    use super::*;
+
+    #[test]
+    fn test_half_close_is_passed_on() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut closed = None;
+        let (mut client, mut server, a, b) = proxy(&mut event_loop, &mut closed);
+
+        client.write_all(b"request").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        run(&mut event_loop);
+        assert_eq!(read_available(&server), b"request");
+        assert!(is_eof(&server));
+
+        /* The other direction still works. */
+        server.write_all(b"response").unwrap();
+        run(&mut event_loop);
+        assert_eq!(read_available(&client), b"response");
+        assert_eq!(closed, None);
+
+        server.shutdown(Shutdown::Write).unwrap();
+        run(&mut event_loop);
+        assert_eq!(closed, Some((a, b, 0)));
+        assert!(ae_proxy_stats(&event_loop, a).is_none());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_peer_reset() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut closed = None;
+        let (client, server, a, b) = proxy(&mut event_loop, &mut closed);
+
+        /* Data the server will never read, then the server goes away. */
+        drop(server);
+        (&client).write_all(b"lost").unwrap();
+        run(&mut event_loop);
+        let (_, _, err) = closed.expect("Proxy not closed");
+        assert_eq!(closed, Some((a, b, err)));
+        assert!(err == libc::EPIPE || err == libc::ECONNRESET, "err {err}");
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_invalid_fds() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (a, b) = UnixStream::pair().expect("Failed to create socket pair");
+        let (a, b) = (a.into_raw_fd(), b.into_raw_fd());
+
+        assert_eq!(
+            ae_proxy_create(&mut event_loop, a, a, None, std::ptr::null_mut()),
+            AE_ERR
+        );
+        assert_eq!(
+            ae_proxy_create(&mut event_loop, -1, b, None, std::ptr::null_mut()),
+            AE_ERR
+        );
+        /* The fds must be free for the proxy to watch. */
+        ae_create_file_event(&mut event_loop, a, AE_READABLE, noop, std::ptr::null_mut());
+        assert_eq!(
+            ae_proxy_create(&mut event_loop, a, b, None, std::ptr::null_mut()),
+            AE_ERR
+        );
+        ae_delete_file_event(&mut event_loop, a, AE_READABLE);
+
+        assert_eq!(ae_proxy_close(&mut event_loop, a), AE_ERR);
+        assert_eq!(
+            ae_proxy_create(&mut event_loop, a, b, None, std::ptr::null_mut()),
+            AE_OK
+        );
+        assert_eq!(ae_proxy_close(&mut event_loop, b), AE_OK);
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -0,0 +1,128 @@
+/* Reactor Tests
+ *
+ * Tests for ae_run_async() (ae/reactor.rs, `async-io` feature): a loop
+ * over a pollable backend run by async_io::block_on(), woken up by its
+ * file events, its timers and posts from other threads.
+ */
+
+#![cfg(feature = "async-io")]
+
+use rae::traits::EventBackend;
+use rae::{
+    AE_ERR, AE_NOMORE, AE_OK, AE_READABLE, AeEventLoop, ae_backend_async, ae_create_event_loop,
+    ae_create_event_loop_with_backend, ae_create_file_event, ae_create_time_event,
+    ae_delete_event_loop, ae_get_handle, ae_run_async, ae_stop,
+};
+use std::ffi::c_void;
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "linux")]
+use rae::ae_epoll::aeApiState;
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+use rae::ae_kqueue::aeApiState;
+
+fn pollable_loop() -> Box<AeEventLoop> {
+    let backend = aeApiState::create().expect("Failed to create backend state");
+    ae_create_event_loop_with_backend(64, backend).expect("Failed to create event loop")
+}
+
+fn stop_timer(event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    unsafe { *(client_data as *mut usize) += 1 };
+    ae_stop(event_loop);
+    AE_NOMORE
+}
+
+fn read_and_stop(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let mut buf = [0u8; 16];
+    let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+    unsafe { *(client_data as *mut usize) += n as usize };
+    ae_stop(event_loop);
+}
+
+mod reactor {
+    use super::*;
+
+    #[test]
+    fn test_timer_wakes_the_loop() {
+        let mut event_loop = pollable_loop();
+        let mut fired = 0usize;
+        let data = &mut fired as *mut usize as *mut c_void;
+        ae_create_time_event(&mut event_loop, 30, stop_timer, data, None);
+
+        let start = Instant::now();
+        assert_eq!(async_io::block_on(ae_run_async(&mut event_loop)), AE_OK);
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert_eq!(fired, 1);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_file_event_wakes_the_loop() {
+        let mut event_loop = pollable_loop();
+        let (reader, mut writer) = UnixStream::pair().unwrap();
+        let mut read = 0usize;
+        let data = &mut read as *mut usize as *mut c_void;
+        ae_create_file_event(
+            &mut event_loop,
+            reader.as_raw_fd(),
+            AE_READABLE,
+            read_and_stop,
+            data,
+        );
+
+        let writer_thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            writer.write_all(b"ping").unwrap();
+            writer
+        });
+        assert_eq!(async_io::block_on(ae_run_async(&mut event_loop)), AE_OK);
+        assert_eq!(read, 4);
+        drop(writer_thread.join().unwrap());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_post_from_another_thread() {
+        let mut event_loop = pollable_loop();
+        let handle = ae_get_handle(&mut event_loop).expect("Failed to get handle");
+        let poster = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            handle.post(ae_stop);
+        });
+
+        assert_eq!(async_io::block_on(ae_run_async(&mut event_loop)), AE_OK);
+        poster.join().unwrap();
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_backend_fd_is_borrowed() {
+        let event_loop = pollable_loop();
+        let backend = ae_backend_async(&event_loop).expect("Failed to register backend fd");
+        let fd = backend.get_ref().fd();
+        drop(backend);
+        /* Still open, owned by the loop. */
+        assert_ne!(unsafe { libc::fcntl(fd, libc::F_GETFD) }, -1);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_select_is_refused() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        assert_eq!(ae_backend_async(&event_loop).err(), Some(libc::ENOTSUP));
+        assert_eq!(async_io::block_on(ae_run_async(&mut event_loop)), AE_ERR);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ENOTSUP)
+        );
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -0,0 +1,137 @@
+/* Re-arm Tests
+ *
+ * Tests for fds coalesced until re-armed (ae/rearm.rs): the read handler
+ * runs once per arm, and the re-arm check catches handlers that neither
+ * drain nor re-arm their fd.
+ */
+
+use rae::anet::anet_pipe;
+use rae::test_util::{close_pipe, run_iterations};
+use rae::{
+    AE_ERR, AE_FILE_EVENTS, AE_OK, AE_READABLE, AE_WRITABLE, AeEventLoop, AeFileEventOptions,
+    AeReadCoalescing, ae_create_event_loop, ae_create_file_event_ex, ae_rearm, ae_set_rearm_check,
+};
+use std::ffi::c_void;
+
+/* Reads of the handlers, and whether they re-arm. */
+struct Reader {
+    calls: u32,
+    rearm: bool,
+}
+
+fn read_one(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let reader = unsafe { &mut *(client_data as *mut Reader) };
+    let mut buf = [0u8; 1];
+    unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, 1) };
+    reader.calls += 1;
+    if reader.rearm {
+        ae_rearm(event_loop, fd, AE_READABLE);
+    }
+}
+
+fn read_all(_event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let reader = unsafe { &mut *(client_data as *mut Reader) };
+    let mut buf = [0u8; 64];
+    while unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len()) } > 0 {}
+    reader.calls += 1;
+}
+
+/* Loop watching a pipe holding `input`, coalesced until re-armed. */
+fn armed_pipe(
+    proc: rae::FileProc,
+    reader: &mut Reader,
+    input: &[u8],
+) -> (Box<AeEventLoop>, i32, i32) {
+    let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+    let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+    let options = AeFileEventOptions {
+        read_coalescing: AeReadCoalescing::UntilRearmed,
+        ..Default::default()
+    };
+    assert_eq!(
+        ae_create_file_event_ex(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            proc,
+            reader as *mut Reader as *mut c_void,
+            options
+        ),
+        AE_OK
+    );
+    unsafe { libc::write(wfd, input.as_ptr() as *const c_void, input.len()) };
+    (event_loop, rfd, wfd)
+}
+
+mod rearm {
+    use super::*;
+
+    #[test]
+    fn test_rearm_from_handler() {
+        let mut reader = Reader {
+            calls: 0,
+            rearm: true,
+        };
+        let (mut event_loop, rfd, wfd) = armed_pipe(read_one, &mut reader, b"abc");
+        run_iterations(&mut event_loop, AE_FILE_EVENTS, 5);
+        assert_eq!(reader.calls, 3);
+        close_pipe(rfd, wfd);
+    }
+
+    #[test]
+    fn test_not_read_until_rearmed() {
+        let mut reader = Reader {
+            calls: 0,
+            rearm: false,
+        };
+        let (mut event_loop, rfd, wfd) = armed_pipe(read_one, &mut reader, b"abc");
+        run_iterations(&mut event_loop, AE_FILE_EVENTS, 3);
+        assert_eq!(reader.calls, 1);
+
+        assert_eq!(ae_rearm(&mut event_loop, rfd, AE_READABLE), AE_OK);
+        run_iterations(&mut event_loop, AE_FILE_EVENTS, 3);
+        assert_eq!(reader.calls, 2);
+        close_pipe(rfd, wfd);
+    }
+
+    #[test]
+    fn test_rearm_unregistered() {
+        let mut reader = Reader {
+            calls: 0,
+            rearm: false,
+        };
+        let (mut event_loop, rfd, wfd) = armed_pipe(read_one, &mut reader, b"");
+        assert_eq!(ae_rearm(&mut event_loop, wfd, AE_READABLE), AE_ERR);
+        assert_eq!(ae_rearm(&mut event_loop, rfd, AE_WRITABLE), AE_ERR);
+        assert_eq!(ae_rearm(&mut event_loop, -1, AE_READABLE), AE_ERR);
+        close_pipe(rfd, wfd);
+    }
+}
+
+mod check {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "still readable")]
+    fn test_input_left_without_rearm() {
+        let mut reader = Reader {
+            calls: 0,
+            rearm: false,
+        };
+        let (mut event_loop, _rfd, _wfd) = armed_pipe(read_one, &mut reader, b"abc");
+        ae_set_rearm_check(&mut event_loop, true);
+        run_iterations(&mut event_loop, AE_FILE_EVENTS, 1);
+    }
+
+    #[test]
+    fn test_drained_or_rearmed() {
+        for (proc, rearm) in [(read_all as rae::FileProc, false), (read_one, true)] {
+            let mut reader = Reader { calls: 0, rearm };
+            let (mut event_loop, rfd, wfd) = armed_pipe(proc, &mut reader, b"abc");
+            ae_set_rearm_check(&mut event_loop, true);
+            run_iterations(&mut event_loop, AE_FILE_EVENTS, 3);
+            assert!(reader.calls > 0);
+            close_pipe(rfd, wfd);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+/* Reconfiguration Tests
+ *
+ * Tests for ae/reconfigure.rs: parameters changed on a live loop, applied
+ * at the start of the next iteration, over the mock backend, and changes
+ * posted through a handle from another thread.
+ */
+
+use rae::test_util::{MockBackend, MockControl, run_iterations};
+use rae::{
+    AE_FILE_EVENTS, AE_READABLE, AeClockSource, AeDispatchOrder, AeEventLoop, AeEventLoopBuilder,
+    AeFdOrder, AeLoopConfigDelta, ae_create_event_loop, ae_create_file_event, ae_delete_event_loop,
+    ae_get_handle, ae_loop_config, ae_reconfigure,
+};
+use std::ffi::c_void;
+use std::time::Duration;
+
+struct Server {
+    control: MockControl,
+    served: Vec<i32>,
+}
+
+/* Serves the fd, which is no longer ready afterwards. */
+fn serve(_event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let server = unsafe { &mut *(client_data as *mut Server) };
+    server.control.clear_ready(fd, AE_READABLE);
+    server.served.push(fd);
+}
+
+fn mock_loop() -> (Box<AeEventLoop>, MockControl) {
+    let (backend, control) = MockBackend::new();
+    let event_loop = AeEventLoopBuilder::new(64)
+        .backend(backend)
+        .clock_source(AeClockSource::Manual)
+        .build()
+        .expect("Failed to create event loop");
+    (event_loop, control)
+}
+
+mod reconfigure {
+    use super::*;
+
+    #[test]
+    fn test_applied_at_next_iteration() {
+        let (mut event_loop, _control) = mock_loop();
+        let before = ae_loop_config(&event_loop);
+        assert_eq!(before.max_poll_timeout, None);
+        assert_eq!(before.fd_order, AeFdOrder::Ascending);
+
+        ae_reconfigure(
+            &mut event_loop,
+            AeLoopConfigDelta {
+                max_poll_timeout: Some(Some(Duration::from_millis(20))),
+                fd_order: Some(AeFdOrder::Backend),
+                ..Default::default()
+            },
+        );
+        assert_eq!(ae_loop_config(&event_loop), before);
+
+        run_iterations(&mut event_loop, AE_FILE_EVENTS, 1);
+        let after = ae_loop_config(&event_loop);
+        assert_eq!(after.max_poll_timeout, Some(Duration::from_millis(20)));
+        assert_eq!(after.fd_order, AeFdOrder::Backend);
+        /* Fields left out are kept. */
+        assert_eq!(after.dispatch_order, before.dispatch_order);
+        assert_eq!(after.fired_capacity, before.fired_capacity);
+
+        /* Some(None) lifts the ceiling. */
+        ae_reconfigure(
+            &mut event_loop,
+            AeLoopConfigDelta {
+                max_poll_timeout: Some(None),
+                ..Default::default()
+            },
+        );
+        run_iterations(&mut event_loop, AE_FILE_EVENTS, 1);
+        assert_eq!(ae_loop_config(&event_loop).max_poll_timeout, None);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_queued_changes_merge() {
+        let (mut event_loop, _control) = mock_loop();
+        ae_reconfigure(
+            &mut event_loop,
+            AeLoopConfigDelta {
+                dispatch_order: Some(AeDispatchOrder::WritesFirst),
+                rusage_sample_interval: Some(10),
+                ..Default::default()
+            },
+        );
+        ae_reconfigure(
+            &mut event_loop,
+            AeLoopConfigDelta {
+                rusage_sample_interval: Some(5),
+                ..Default::default()
+            },
+        );
+        run_iterations(&mut event_loop, AE_FILE_EVENTS, 1);
+        let config = ae_loop_config(&event_loop);
+        assert_eq!(config.dispatch_order, AeDispatchOrder::WritesFirst);
+        assert_eq!(config.rusage_sample_interval, 5);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_fired_capacity_bounds_the_batch() {
+        let (mut event_loop, control) = mock_loop();
+        let mut server = Box::new(Server {
+            control,
+            served: Vec::new(),
+        });
+        let data = &mut *server as *mut Server as *mut c_void;
+        for fd in 3..8 {
+            ae_create_file_event(&mut event_loop, fd, AE_READABLE, serve, data);
+        }
+
+        ae_reconfigure(
+            &mut event_loop,
+            AeLoopConfigDelta {
+                fired_capacity: Some(2),
+                ..Default::default()
+            },
+        );
+        for fd in 3..8 {
+            server.control.set_ready(fd, AE_READABLE);
+        }
+        assert_eq!(run_iterations(&mut event_loop, AE_FILE_EVENTS, 1), 2);
+        assert_eq!(server.served, vec![3, 4]);
+        assert_eq!(ae_loop_config(&event_loop).fired_capacity, 2);
+
+        /* Back up: the rest in one go. */
+        ae_reconfigure(
+            &mut event_loop,
+            AeLoopConfigDelta {
+                fired_capacity: Some(64),
+                ..Default::default()
+            },
+        );
+        assert_eq!(run_iterations(&mut event_loop, AE_FILE_EVENTS, 1), 3);
+        assert_eq!(server.served, vec![3, 4, 5, 6, 7]);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_from_another_thread() {
+        /* A real backend, for the wakeup pipe of the handle. */
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let handle = ae_get_handle(&mut event_loop).expect("Failed to get handle");
+        std::thread::spawn(move || {
+            handle.reconfigure(AeLoopConfigDelta {
+                fd_order: Some(AeFdOrder::Backend),
+                ..Default::default()
+            });
+        })
+        .join()
+        .unwrap();
+
+        /* The post runs, then the change applies. */
+        run_iterations(&mut event_loop, AE_FILE_EVENTS, 1);
+        run_iterations(&mut event_loop, AE_FILE_EVENTS, 1);
+        assert_eq!(ae_loop_config(&event_loop).fd_order, AeFdOrder::Backend);
+        ae_delete_event_loop(event_loop);
+    }
+}
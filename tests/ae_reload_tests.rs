@@ -0,0 +1,246 @@
+/* Graceful Reload Tests
+ *
+ * Tests for exporting tagged registrations and restoring them in another
+ * process (ae/reload.rs), both across exec() (this test binary re-runs
+ * itself) and over a Unix socket with SCM_RIGHTS.
+ */
+
+use rae::anet::anet_pipe;
+use rae::test_util::{close_pipe, trigger_readable};
+use rae::{
+    AE_ERR, AE_INHERIT_ENV, AE_OK, AE_READABLE, AE_WRITABLE, AeEventLoop, AeFileEventOptions,
+    AeInheritedFd, ae_create_event_loop, ae_create_file_event, ae_create_file_event_ex,
+    ae_decode_registrations, ae_encode_registrations, ae_export_registrations, ae_get_file_events,
+    ae_get_file_tag, ae_inherited_fds, ae_prepare_exec, ae_recv_registrations,
+    ae_restore_registrations, ae_send_registrations,
+};
+use std::ffi::c_void;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+const LISTENER_TAG: u32 = 7;
+
+fn noop_handler(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+fn tagged(event_loop: &mut AeEventLoop, fd: i32, mask: i32, tag: u32) {
+    let result = ae_create_file_event_ex(
+        event_loop,
+        fd,
+        mask,
+        noop_handler,
+        std::ptr::null_mut(),
+        AeFileEventOptions {
+            tag,
+            ..Default::default()
+        },
+    );
+    assert_eq!(result, AE_OK);
+}
+
+mod export {
+    use super::*;
+
+    #[test]
+    fn test_only_tagged_registrations_exported() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (a, b) = (anet_pipe(true).unwrap(), anet_pipe(true).unwrap());
+
+        tagged(&mut event_loop, a.0, AE_READABLE, LISTENER_TAG);
+        tagged(&mut event_loop, b.1, AE_WRITABLE, 9);
+        ae_create_file_event(
+            &mut event_loop,
+            b.0,
+            AE_READABLE,
+            noop_handler,
+            std::ptr::null_mut(),
+        );
+
+        let mut expected = vec![
+            AeInheritedFd {
+                fd: a.0,
+                mask: AE_READABLE,
+                tag: LISTENER_TAG,
+            },
+            AeInheritedFd {
+                fd: b.1,
+                mask: AE_WRITABLE,
+                tag: 9,
+            },
+        ];
+        expected.sort_by_key(|f| f.fd);
+        assert_eq!(ae_export_registrations(&event_loop), expected);
+
+        drop(event_loop);
+        close_pipe(a.0, a.1);
+        close_pipe(b.0, b.1);
+    }
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let fds = vec![
+            AeInheritedFd {
+                fd: 3,
+                mask: AE_READABLE,
+                tag: 1,
+            },
+            AeInheritedFd {
+                fd: 12,
+                mask: AE_READABLE | AE_WRITABLE,
+                tag: 4_000_000_000,
+            },
+        ];
+        let encoded = ae_encode_registrations(&fds);
+        assert_eq!(encoded, "3:1:1,12:3:4000000000");
+        assert_eq!(ae_decode_registrations(&encoded), Some(fds));
+        assert_eq!(ae_decode_registrations(""), Some(Vec::new()));
+
+        for bad in ["3:1", "3:1:1:1", "x:1:1", "-1:1:1", "3:1:1,"] {
+            assert_eq!(ae_decode_registrations(bad), None, "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn test_restore_uses_resolver() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (a, b) = (anet_pipe(true).unwrap(), anet_pipe(true).unwrap());
+        let fds = [
+            AeInheritedFd {
+                fd: a.0,
+                mask: AE_READABLE,
+                tag: LISTENER_TAG,
+            },
+            AeInheritedFd {
+                fd: b.0,
+                mask: AE_READABLE,
+                tag: 99,
+            },
+        ];
+
+        let restored = ae_restore_registrations(&mut event_loop, &fds, |f| {
+            (f.tag == LISTENER_TAG).then_some((noop_handler as rae::FileProc, std::ptr::null_mut()))
+        });
+        assert_eq!(restored, 1);
+        assert_eq!(ae_get_file_events(&event_loop, a.0), AE_READABLE);
+        assert_eq!(ae_get_file_tag(&event_loop, a.0), LISTENER_TAG);
+        assert_eq!(
+            ae_get_file_events(&event_loop, b.0),
+            0,
+            "Unresolved fd left alone"
+        );
+
+        let bad = [AeInheritedFd {
+            fd: 100_000,
+            mask: AE_READABLE,
+            tag: LISTENER_TAG,
+        }];
+        let restored = ae_restore_registrations(&mut event_loop, &bad, |_| {
+            Some((noop_handler as rae::FileProc, std::ptr::null_mut()))
+        });
+        assert_eq!(restored, AE_ERR);
+
+        drop(event_loop);
+        close_pipe(a.0, a.1);
+        close_pipe(b.0, b.1);
+    }
+}
+
+mod exec {
+    use super::*;
+
+    /* Runs in the re-executed test binary only: write to the inherited
+     * pipe so that the parent can tell it made it through exec(). */
+    #[test]
+    fn child_entry() {
+        if std::env::var(AE_INHERIT_ENV).is_err() {
+            return;
+        }
+        let fds = ae_inherited_fds();
+        assert_eq!(fds.len(), 1);
+        assert_eq!(fds[0].tag, LISTENER_TAG);
+        let flags = unsafe { libc::fcntl(fds[0].fd, libc::F_GETFD) };
+        assert_ne!(flags & libc::FD_CLOEXEC, 0, "cloexec set again");
+        trigger_readable(fds[0].fd);
+    }
+
+    #[test]
+    fn test_registrations_survive_exec() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(false).unwrap();
+        unsafe { libc::fcntl(wfd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        tagged(&mut event_loop, wfd, AE_WRITABLE, LISTENER_TAG);
+
+        let encoded = ae_prepare_exec(&event_loop).expect("prepare should succeed");
+        let flags = unsafe { libc::fcntl(wfd, libc::F_GETFD) };
+        assert_eq!(flags & libc::FD_CLOEXEC, 0, "cloexec cleared");
+
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "exec::child_entry", "--test-threads=1"])
+            .env(AE_INHERIT_ENV, &encoded)
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "child failed");
+
+        let mut byte = 0u8;
+        let n = unsafe { libc::read(rfd, &mut byte as *mut u8 as *mut c_void, 1) };
+        assert_eq!(n, 1, "child wrote through the inherited fd");
+
+        drop(event_loop);
+        close_pipe(rfd, wfd);
+    }
+}
+
+mod unix_socket {
+    use super::*;
+
+    #[test]
+    fn test_send_and_receive() {
+        let (left, right) = UnixStream::pair().unwrap();
+        let (rfd, wfd) = anet_pipe(false).unwrap();
+        let sent = [AeInheritedFd {
+            fd: wfd,
+            mask: AE_WRITABLE,
+            tag: LISTENER_TAG,
+        }];
+
+        ae_send_registrations(left.as_raw_fd(), &sent).expect("send should succeed");
+        let received = ae_recv_registrations(right.as_raw_fd()).expect("recv should succeed");
+
+        assert_eq!(received.len(), 1);
+        assert_ne!(received[0].fd, wfd, "A new descriptor is received");
+        assert_eq!(
+            (received[0].mask, received[0].tag),
+            (AE_WRITABLE, LISTENER_TAG)
+        );
+
+        trigger_readable(received[0].fd);
+        let mut byte = 0u8;
+        assert_eq!(
+            unsafe { libc::read(rfd, &mut byte as *mut u8 as *mut c_void, 1) },
+            1
+        );
+
+        unsafe { libc::close(received[0].fd) };
+        close_pipe(rfd, wfd);
+    }
+
+    #[test]
+    fn test_empty_and_oversized() {
+        let (left, right) = UnixStream::pair().unwrap();
+        ae_send_registrations(left.as_raw_fd(), &[]).unwrap();
+        assert_eq!(ae_recv_registrations(right.as_raw_fd()), Ok(Vec::new()));
+
+        let too_many = vec![
+            AeInheritedFd {
+                fd: 0,
+                mask: AE_READABLE,
+                tag: 1,
+            };
+            rae::AE_RELOAD_MAX_FDS + 1
+        ];
+        assert_eq!(
+            ae_send_registrations(left.as_raw_fd(), &too_many),
+            Err(libc::E2BIG)
+        );
+    }
+}
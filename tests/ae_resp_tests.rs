@@ -0,0 +1,380 @@
+/* RESP Tests
+ *
+ * Tests for the Redis protocol codec (ae/resp.rs): parsing and encoding of
+ * every RESP2 and RESP3 type, incomplete and malformed input, and a small
+ * server answering commands over a Unix socket pair. Only built with the
+ * `resp` feature.
+ */
+
+#![cfg(feature = "resp")]
+
+use rae::ae::resp::RESP_MAX_DEPTH;
+use rae::{
+    AE_OK, AeEventLoop, RespError, RespProtocol, RespValue, ae_conn_close,
+    ae_conn_close_after_write, ae_create_event_loop, ae_delete_event_loop, ae_resp_reply,
+    ae_resp_serve, ae_resp_set_protocol, resp_encode, resp_parse,
+};
+use std::ffi::c_void;
+use std::io::{Read, Write};
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+fn encode(value: &RespValue, protocol: RespProtocol) -> Vec<u8> {
+    let mut out = Vec::new();
+    resp_encode(value, protocol, &mut out);
+    out
+}
+
+fn parse_all(buf: &[u8]) -> RespValue {
+    let (value, len) = resp_parse(buf)
+        .expect("Failed to parse")
+        .expect("Incomplete");
+    assert_eq!(len, buf.len());
+    value
+}
+
+fn bulk(s: &str) -> RespValue {
+    RespValue::bulk(s)
+}
+
+mod parse {
+    use super::*;
+
+    #[test]
+    fn test_resp2_types() {
+        assert_eq!(parse_all(b"+OK\r\n"), RespValue::ok());
+        assert_eq!(parse_all(b"-ERR no\r\n"), RespValue::error("ERR no"));
+        assert_eq!(parse_all(b":-42\r\n"), RespValue::Integer(-42));
+        assert_eq!(parse_all(b"$5\r\nhe\r\no\r\n"), bulk("he\r\no"));
+        assert_eq!(parse_all(b"$0\r\n\r\n"), bulk(""));
+        assert_eq!(parse_all(b"$-1\r\n"), RespValue::Null);
+        assert_eq!(parse_all(b"*-1\r\n"), RespValue::Null);
+        assert_eq!(
+            parse_all(b"*2\r\n$3\r\nfoo\r\n*1\r\n:1\r\n"),
+            RespValue::Array(vec![
+                bulk("foo"),
+                RespValue::Array(vec![RespValue::Integer(1)])
+            ])
+        );
+    }
+
+    #[test]
+    fn test_resp3_types() {
+        assert_eq!(parse_all(b"_\r\n"), RespValue::Null);
+        assert_eq!(parse_all(b"#t\r\n"), RespValue::Boolean(true));
+        assert_eq!(parse_all(b",1.5\r\n"), RespValue::Double(1.5));
+        assert_eq!(
+            parse_all(b",-inf\r\n"),
+            RespValue::Double(f64::NEG_INFINITY)
+        );
+        assert_eq!(
+            parse_all(b"(3492890328409238509324850943850943825024385\r\n"),
+            RespValue::BigNumber(b"3492890328409238509324850943850943825024385".to_vec())
+        );
+        assert_eq!(
+            parse_all(b"!8\r\nSYNTAX x\r\n"),
+            RespValue::BlobError(b"SYNTAX x".to_vec())
+        );
+        assert_eq!(
+            parse_all(b"=8\r\ntxt:text\r\n"),
+            RespValue::Verbatim {
+                format: *b"txt",
+                text: b"text".to_vec()
+            }
+        );
+        assert_eq!(
+            parse_all(b"%1\r\n+key\r\n:7\r\n"),
+            RespValue::Map(vec![(
+                RespValue::Simple(b"key".to_vec()),
+                RespValue::Integer(7)
+            )])
+        );
+        assert_eq!(
+            parse_all(b"~1\r\n#f\r\n"),
+            RespValue::Set(vec![RespValue::Boolean(false)])
+        );
+        assert_eq!(
+            parse_all(b">2\r\n+message\r\n$2\r\nhi\r\n"),
+            RespValue::Push(vec![RespValue::Simple(b"message".to_vec()), bulk("hi")])
+        );
+    }
+
+    #[test]
+    fn test_attributes_are_skipped() {
+        assert_eq!(
+            parse_all(b"|1\r\n+ttl\r\n:3600\r\n$3\r\nval\r\n"),
+            bulk("val")
+        );
+    }
+
+    #[test]
+    fn test_incomplete_input() {
+        let full = b"*3\r\n$3\r\nSET\r\n%1\r\n+k\r\n,2.5\r\n$-1\r\n";
+        for len in 0..full.len() {
+            assert_eq!(resp_parse(&full[..len]), Ok(None), "prefix of {len} bytes");
+        }
+        /* Trailing input is left for the next value. */
+        let (_, len) = resp_parse(b"+a\r\n+b\r\n").unwrap().unwrap();
+        assert_eq!(len, 4);
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        let cases: [(&[u8], &str); 7] = [
+            (b"?\r\n", "unexpected type byte '?'"),
+            (b":12a\r\n", "invalid integer"),
+            (b"#x\r\n", "invalid boolean"),
+            (b"$-2\r\n", "invalid bulk length"),
+            (b"$3\r\nabcd\r\n", "bulk string not terminated by CRLF"),
+            (b"=3\r\ntxt\r\n", "invalid verbatim string"),
+            (b"*+1\r\n", "invalid multibulk length"),
+        ];
+        for (input, msg) in cases {
+            assert_eq!(resp_parse(input), Err(RespError(msg.to_string())));
+        }
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let mut deep = b"*1\r\n".repeat(RESP_MAX_DEPTH + 2);
+        deep.extend_from_slice(b":1\r\n");
+        assert_eq!(
+            resp_parse(&deep),
+            Err(RespError("nesting too deep".to_string()))
+        );
+
+        let mut ok = b"*1\r\n".repeat(RESP_MAX_DEPTH);
+        ok.extend_from_slice(b":1\r\n");
+        assert!(resp_parse(&ok).unwrap().is_some());
+    }
+}
+
+mod encode {
+    use super::*;
+
+    fn sample() -> RespValue {
+        RespValue::Array(vec![
+            RespValue::Map(vec![(bulk("a"), RespValue::Boolean(true))]),
+            RespValue::Set(vec![RespValue::Double(0.5)]),
+            RespValue::Null,
+            RespValue::BigNumber(b"-12".to_vec()),
+            RespValue::Verbatim {
+                format: *b"mkd",
+                text: b"# hi".to_vec(),
+            },
+            RespValue::BlobError(b"ERR x".to_vec()),
+        ])
+    }
+
+    #[test]
+    fn test_resp3_round_trip() {
+        let value = sample();
+        let bytes = encode(&value, RespProtocol::Resp3);
+        assert_eq!(parse_all(&bytes), value);
+    }
+
+    #[test]
+    fn test_resp2_downgrade() {
+        let bytes = encode(&sample(), RespProtocol::Resp2);
+        assert_eq!(
+            bytes,
+            b"*6\r\n*2\r\n$1\r\na\r\n:1\r\n*1\r\n$3\r\n0.5\r\n$-1\r\n$3\r\n-12\r\n\
+              $4\r\n# hi\r\n-ERR x\r\n"
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_lines_are_sanitized() {
+        let bytes = encode(&RespValue::error("ERR a\r\nb"), RespProtocol::Resp2);
+        assert_eq!(bytes, b"-ERR a  b\r\n");
+    }
+
+    #[test]
+    fn test_special_doubles() {
+        let bytes = encode(&RespValue::Double(f64::INFINITY), RespProtocol::Resp3);
+        assert_eq!(bytes, b",inf\r\n");
+        let bytes = encode(&RespValue::Double(f64::NAN), RespProtocol::Resp3);
+        assert_eq!(bytes, b",nan\r\n");
+    }
+}
+
+mod server {
+    use super::*;
+    use rae::test_util::pump;
+
+    #[derive(Default)]
+    struct Server {
+        commands: Vec<Vec<Vec<u8>>>,
+        closed: Option<i32>,
+    }
+
+    fn server<'a>(client_data: *mut c_void) -> &'a mut Server {
+        unsafe { &mut *(client_data as *mut Server) }
+    }
+
+    /* PING, ECHO, HELLO <protover>, QUIT, anything else is an error. */
+    fn handle(event_loop: &mut AeEventLoop, fd: i32, argv: &[&[u8]], client_data: *mut c_void) {
+        server(client_data)
+            .commands
+            .push(argv.iter().map(|arg| arg.to_vec()).collect());
+        let reply = match argv[0].to_ascii_uppercase().as_slice() {
+            b"PING" => RespValue::Simple(b"PONG".to_vec()),
+            b"ECHO" if argv.len() == 2 => RespValue::bulk(argv[1]),
+            b"HELLO" => {
+                let protocol = if argv.get(1) == Some(&&b"3"[..]) {
+                    RespProtocol::Resp3
+                } else {
+                    RespProtocol::Resp2
+                };
+                ae_resp_set_protocol(event_loop, fd, protocol);
+                RespValue::Map(vec![(bulk("proto"), RespValue::Integer(argv.len() as i64))])
+            }
+            b"QUIT" => {
+                ae_resp_reply(event_loop, fd, &RespValue::ok());
+                ae_conn_close_after_write(event_loop, fd);
+                return;
+            }
+            _ => RespValue::error("ERR unknown command"),
+        };
+        assert_eq!(ae_resp_reply(event_loop, fd, &reply), AE_OK);
+    }
+
+    fn on_close(_event_loop: &mut AeEventLoop, _fd: i32, err: i32, client_data: *mut c_void) {
+        server(client_data).closed = Some(err);
+    }
+
+    fn serve(event_loop: &mut AeEventLoop, state: &mut Server) -> (i32, UnixStream) {
+        let (ours, theirs) = UnixStream::pair().expect("Failed to create socket pair");
+        ours.set_nonblocking(true).unwrap();
+        theirs
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let fd = ours.into_raw_fd();
+        let result = ae_resp_serve(
+            event_loop,
+            fd,
+            handle,
+            Some(on_close),
+            state as *mut Server as *mut c_void,
+        );
+        assert_eq!(result, AE_OK);
+        (fd, theirs)
+    }
+
+    /* Read replies until `count` of them are complete. */
+    fn replies(stream: &mut UnixStream, count: usize) -> Vec<RespValue> {
+        let mut buf = Vec::new();
+        let mut values = Vec::new();
+        let mut chunk = [0u8; 4096];
+        while values.len() < count {
+            match resp_parse(&buf).expect("Bad reply") {
+                Some((value, len)) => {
+                    values.push(value);
+                    buf.drain(..len);
+                }
+                None => {
+                    let n = stream.read(&mut chunk).expect("Failed to read");
+                    assert!(n > 0, "connection closed");
+                    buf.extend_from_slice(&chunk[..n]);
+                }
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn test_pipelined_commands() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut state = Server::default();
+        let (fd, mut client) = serve(&mut event_loop, &mut state);
+
+        /* Multibulk, split mid argument, then inline. */
+        client.write_all(b"*2\r\n$4\r\nECHO\r\n$5\r\nhe").unwrap();
+        pump(&mut event_loop);
+        client
+            .write_all(b"llo\r\n*1\r\n$4\r\nPING\r\nping\r\n\r\nNOPE x\n")
+            .unwrap();
+        pump(&mut event_loop);
+
+        assert_eq!(
+            replies(&mut client, 4),
+            [
+                bulk("hello"),
+                RespValue::Simple(b"PONG".to_vec()),
+                RespValue::Simple(b"PONG".to_vec()),
+                RespValue::error("ERR unknown command"),
+            ]
+        );
+        assert_eq!(state.commands.len(), 4);
+        assert_eq!(state.commands[3], [b"NOPE".to_vec(), b"x".to_vec()]);
+
+        ae_conn_close(&mut event_loop, fd);
+        assert_eq!(state.closed, Some(0));
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_hello_switches_protocol() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut state = Server::default();
+        let (fd, mut client) = serve(&mut event_loop, &mut state);
+
+        client.write_all(b"HELLO 3\r\n").unwrap();
+        pump(&mut event_loop);
+        let mut reply = [0u8; 4];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"%1\r\n");
+        assert_eq!(
+            replies(&mut client, 2),
+            [bulk("proto"), RespValue::Integer(2)]
+        );
+
+        client.write_all(b"HELLO 2\r\n").unwrap();
+        pump(&mut event_loop);
+        let mut reply = [0u8; 4];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"*2\r\n");
+
+        ae_conn_close(&mut event_loop, fd);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_quit_drops_the_rest() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut state = Server::default();
+        let (_fd, mut client) = serve(&mut event_loop, &mut state);
+
+        client.write_all(b"PING\r\nQUIT\r\nPING\r\n").unwrap();
+        pump(&mut event_loop);
+
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).unwrap();
+        assert_eq!(reply, b"+PONG\r\n+OK\r\n");
+        assert_eq!(state.commands.len(), 2);
+        assert_eq!(state.closed, Some(0));
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_protocol_error_closes() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut state = Server::default();
+        let (_fd, mut client) = serve(&mut event_loop, &mut state);
+
+        client.write_all(b"PING\r\n*1\r\n:1\r\n").unwrap();
+        pump(&mut event_loop);
+
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).unwrap();
+        assert_eq!(
+            reply,
+            b"+PONG\r\n-ERR Protocol error: expected '$', got ':'\r\n"
+        );
+        assert_eq!(state.commands.len(), 1);
+        assert_eq!(state.closed, Some(0));
+        ae_delete_event_loop(event_loop);
+    }
+}
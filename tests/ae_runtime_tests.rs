@@ -0,0 +1,319 @@
+/* Runtime Tests
+ *
+ * Tests for the thread-per-core preset (ae/runtime.rs) and the listening
+ * socket helpers it is built on, over real TCP connections on localhost.
+ */
+
+use rae::{
+    AeEventLoop, ThreadPerCore, ae_get_handle, ae_get_loop_name, anet_local_addr, anet_tcp_server,
+};
+use std::io::Read;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+fn localhost() -> SocketAddr {
+    "127.0.0.1:0".parse().unwrap()
+}
+
+/* Greets the client with the name of the accepting thread, then hangs up. */
+fn greet(_event_loop: &mut AeEventLoop, fd: i32, addr: Option<SocketAddr>) {
+    assert!(addr.is_some());
+    let name = std::thread::current().name().unwrap_or("").to_string();
+    unsafe {
+        libc::write(fd, name.as_ptr() as *const libc::c_void, name.len());
+        libc::close(fd);
+    }
+}
+
+fn fetch(addr: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(addr).expect("Failed to connect");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).expect("Failed to read");
+    reply
+}
+
+mod listeners {
+    use super::*;
+
+    #[test]
+    fn test_port_zero_resolves() {
+        let fd = anet_tcp_server(&localhost(), 16, false).expect("Failed to listen");
+        let addr = anet_local_addr(fd).expect("Failed to get address");
+        assert_ne!(addr.port(), 0);
+        unsafe { libc::close(fd) };
+    }
+
+    #[test]
+    fn test_reuse_port_shares_the_address() {
+        let first = anet_tcp_server(&localhost(), 16, true).expect("Failed to listen");
+        let addr = anet_local_addr(first).unwrap();
+        let second = anet_tcp_server(&addr, 16, true).expect("SO_REUSEPORT should allow it");
+        assert_eq!(anet_local_addr(second).unwrap(), addr);
+
+        /* Without the option the address is taken. */
+        assert_eq!(anet_tcp_server(&addr, 16, false), Err(libc::EADDRINUSE));
+        unsafe {
+            libc::close(first);
+            libc::close(second);
+        }
+    }
+}
+
+mod thread_per_core {
+    use super::*;
+
+    static STARTED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+    fn record_start(event_loop: &mut AeEventLoop, index: usize) {
+        assert!(ae_get_handle(event_loop).is_some());
+        STARTED.lock().unwrap().push(index);
+    }
+
+    #[test]
+    fn test_connections_are_served() {
+        let runtime = ThreadPerCore::new()
+            .threads(3)
+            .pin_threads(false)
+            .setsize(256)
+            .on_loop_start(record_start)
+            .start(localhost(), greet)
+            .expect("Failed to start runtime");
+        let addr = runtime.local_addr();
+        assert_ne!(addr.port(), 0);
+        assert_eq!(runtime.handles().len(), 3);
+
+        let mut started = STARTED.lock().unwrap().clone();
+        started.sort();
+        assert_eq!(started, [0, 1, 2]);
+
+        for _ in 0..20 {
+            let reply = fetch(addr);
+            assert!(reply.starts_with("rae-core-"), "unexpected reply {reply:?}");
+        }
+        runtime.stop();
+        runtime.join();
+
+        /* Listeners are closed once the loops are gone. */
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    /* Echoes the request the connection was accepted with, which deferred
+     * accept guarantees is already readable. */
+    #[cfg(target_os = "linux")]
+    fn echo_first(_event_loop: &mut AeEventLoop, fd: i32, _addr: Option<SocketAddr>) {
+        let mut buf = [0u8; 64];
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        assert!(n > 0, "accepted before the request arrived");
+        unsafe {
+            libc::write(fd, buf.as_ptr() as *const libc::c_void, n as usize);
+            libc::close(fd);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_fastopen_and_deferred_accept() {
+        use std::io::Write;
+
+        let runtime = ThreadPerCore::new()
+            .threads(2)
+            .pin_threads(false)
+            .setsize(256)
+            .fastopen(16)
+            .defer_accept(10)
+            .start(localhost(), echo_first)
+            .expect("Failed to start runtime");
+
+        let mut stream = TcpStream::connect(runtime.local_addr()).expect("Failed to connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        stream.write_all(b"hello").unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).expect("Failed to read");
+        assert_eq!(reply, "hello");
+
+        runtime.stop();
+        runtime.join();
+    }
+
+    #[test]
+    fn test_peer_filter_runs_before_accept_proc() {
+        static CHECKED: AtomicUsize = AtomicUsize::new(0);
+
+        fn refuse(
+            _event_loop: &mut AeEventLoop,
+            _listen_fd: i32,
+            addr: Option<SocketAddr>,
+        ) -> bool {
+            assert!(addr.is_some());
+            CHECKED.fetch_add(1, Ordering::SeqCst);
+            false
+        }
+
+        let runtime = ThreadPerCore::new()
+            .threads(2)
+            .pin_threads(false)
+            .setsize(256)
+            .peer_filter(refuse)
+            .start(localhost(), greet)
+            .expect("Failed to start runtime");
+        /* Hung up on without the greeting. */
+        assert_eq!(fetch(runtime.local_addr()), "");
+        assert_eq!(CHECKED.load(Ordering::SeqCst), 1);
+        runtime.stop();
+        runtime.join();
+    }
+
+    #[test]
+    fn test_proxy_protocol_gives_the_client_address() {
+        use std::io::Write;
+
+        static CLIENTS: Mutex<Vec<Option<SocketAddr>>> = Mutex::new(Vec::new());
+
+        fn record_client(event_loop: &mut AeEventLoop, fd: i32, addr: Option<SocketAddr>) {
+            CLIENTS.lock().unwrap().push(addr);
+            greet(event_loop, fd, addr);
+        }
+
+        let runtime = ThreadPerCore::new()
+            .threads(2)
+            .pin_threads(false)
+            .setsize(256)
+            .proxy_protocol(true)
+            .start(localhost(), record_client)
+            .expect("Failed to start runtime");
+
+        let mut stream = TcpStream::connect(runtime.local_addr()).expect("Failed to connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+            .write_all(b"PROXY TCP4 203.0.113.9 198.51.100.1 50000 443\r\n")
+            .unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).expect("Failed to read");
+        assert!(reply.starts_with("rae-core-"), "unexpected reply {reply:?}");
+        assert_eq!(
+            *CLIENTS.lock().unwrap(),
+            [Some("203.0.113.9:50000".parse().unwrap())]
+        );
+
+        runtime.stop();
+        runtime.join();
+    }
+
+    #[test]
+    fn test_handles_reach_each_core() {
+        static RAN: AtomicUsize = AtomicUsize::new(0);
+        static NAMES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let runtime = ThreadPerCore::new()
+            .threads(2)
+            .start(localhost(), greet)
+            .expect("Failed to start runtime");
+        for handle in runtime.handles() {
+            handle.post(|_| {
+                let name = std::thread::current().name().unwrap().to_string();
+                NAMES.lock().unwrap().push(name);
+                RAN.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        /* Dropping the runtime stops and joins, after the queued tasks. */
+        drop(runtime);
+
+        assert_eq!(RAN.load(Ordering::SeqCst), 2);
+        let mut names = NAMES.lock().unwrap().clone();
+        names.sort();
+        assert_eq!(names, ["rae-core-0", "rae-core-1"]);
+    }
+
+    #[test]
+    fn test_named_loops_and_threads() {
+        static NAMES: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+        fn record_names(event_loop: &mut AeEventLoop, _index: usize) {
+            let thread = std::thread::current().name().unwrap().to_string();
+            let name = ae_get_loop_name(event_loop).unwrap().to_string();
+            NAMES.lock().unwrap().push((thread, name));
+        }
+
+        let runtime = ThreadPerCore::new()
+            .threads(2)
+            .name("web")
+            .on_loop_start(record_names)
+            .start(localhost(), greet)
+            .expect("Failed to start runtime");
+        let handle_names: Vec<_> = runtime
+            .handles()
+            .iter()
+            .map(|handle| handle.loop_name().unwrap().to_string())
+            .collect();
+        assert_eq!(handle_names, ["web-0", "web-1"]);
+        drop(runtime);
+
+        let mut names = NAMES.lock().unwrap().clone();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                ("web-0".to_string(), "web-0".to_string()),
+                ("web-1".to_string(), "web-1".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shared_listener() {
+        let runtime = ThreadPerCore::new()
+            .threads(2)
+            .name("shared")
+            .shared_listener(true)
+            .start(localhost(), greet)
+            .expect("Failed to start runtime");
+        let addr = runtime.local_addr();
+        for _ in 0..8 {
+            assert!(fetch(addr).starts_with("shared-"));
+        }
+        drop(runtime);
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_threads_are_pinned() {
+        static PINNED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+        fn record_cpus(_event_loop: &mut AeEventLoop, _index: usize) {
+            let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            let size = std::mem::size_of::<libc::cpu_set_t>();
+            assert_eq!(unsafe { libc::sched_getaffinity(0, size, &mut set) }, 0);
+            let count = unsafe { libc::CPU_COUNT(&set) } as usize;
+            PINNED.lock().unwrap().push(count);
+        }
+
+        let runtime = ThreadPerCore::new()
+            .threads(1)
+            .on_loop_start(record_cpus)
+            .start(localhost(), greet)
+            .expect("Failed to start runtime");
+        drop(runtime);
+        assert_eq!(*PINNED.lock().unwrap(), [1]);
+    }
+
+    #[test]
+    fn test_address_in_use() {
+        let taken = anet_tcp_server(&localhost(), 16, false).expect("Failed to listen");
+        let addr = anet_local_addr(taken).unwrap();
+
+        let result = ThreadPerCore::new().threads(2).start(addr, greet);
+        assert_eq!(result.err(), Some(libc::EADDRINUSE));
+        unsafe { libc::close(taken) };
+    }
+}
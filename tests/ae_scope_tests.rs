@@ -0,0 +1,169 @@
+/* Scope Tests
+ *
+ * Tests for ae/scope.rs: file events and timers registered through a
+ * scope, deleted when its guard is dropped (between iterations or from a
+ * callback of the scope itself) or closed.
+ */
+
+use rae::test_util::{close_pipe, run_once};
+use rae::{
+    AE_ERR, AE_NONE, AE_OK, AE_READABLE, AE_WRITABLE, AeEventLoop, AeScope, ae_create_event_loop,
+    ae_create_file_event, ae_delete_event_loop, ae_delete_file_event, ae_get_file_events,
+    ae_pending_time_events, ae_scope, ae_scope_close, ae_scope_extend,
+};
+use std::ffi::c_void;
+
+/* A subsystem: the guard of its scope, and the fds its handlers saw. */
+#[derive(Default)]
+struct Subsystem {
+    scope: Option<AeScope>,
+    calls: Vec<i32>,
+    finalized: usize,
+}
+
+fn subsystem<'a>(client_data: *mut c_void) -> &'a mut Subsystem {
+    unsafe { &mut *(client_data as *mut Subsystem) }
+}
+
+fn record(_event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    subsystem(client_data).calls.push(fd);
+}
+
+/* Tears the whole subsystem down from its own callback. */
+fn tear_down(_event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let subsystem = subsystem(client_data);
+    subsystem.calls.push(fd);
+    subsystem.scope = None;
+}
+
+fn never(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    panic!("timer of a dropped scope fired");
+}
+
+fn finalize(_event_loop: &mut AeEventLoop, client_data: *mut c_void) {
+    subsystem(client_data).finalized += 1;
+}
+
+/* A pipe with data waiting, (read end, write end). */
+fn readable_pipe() -> (i32, i32) {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    assert_eq!(
+        unsafe { libc::write(fds[1], b"x".as_ptr() as *const c_void, 1) },
+        1
+    );
+    (fds[0], fds[1])
+}
+
+mod scope {
+    use super::*;
+
+    #[test]
+    fn test_drop_between_iterations() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let pipe = readable_pipe();
+        let mut sub = Box::new(Subsystem::default());
+        let data = &mut *sub as *mut Subsystem as *mut c_void;
+
+        let scope = ae_scope(&mut event_loop, |scope| {
+            assert_eq!(scope.register(pipe.0, AE_READABLE, record, data), AE_OK);
+            assert_ne!(scope.spawn_timer(1, never, data, Some(finalize)), -1);
+        });
+        assert_eq!(ae_get_file_events(&event_loop, pipe.0), AE_READABLE);
+        assert_eq!(ae_pending_time_events(&event_loop), 1);
+
+        drop(scope);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        run_once(&mut event_loop);
+        assert!(sub.calls.is_empty());
+        assert_eq!(ae_get_file_events(&event_loop, pipe.0), AE_NONE);
+        assert_eq!(ae_pending_time_events(&event_loop), 0);
+        assert_eq!(sub.finalized, 1);
+
+        close_pipe(pipe.0, pipe.1);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_drop_from_own_callback() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let first = readable_pipe();
+        let second = readable_pipe();
+        /* Fds are dispatched in ascending order. */
+        let (low, high) = (first.0.min(second.0), first.0.max(second.0));
+        let mut sub = Box::new(Subsystem::default());
+        let data = &mut *sub as *mut Subsystem as *mut c_void;
+
+        sub.scope = Some(ae_scope(&mut event_loop, |scope| {
+            scope.register(low, AE_READABLE, tear_down, data);
+            scope.register(high, AE_READABLE, record, data);
+            /* Both directions of one fd, torn down together. */
+            scope.register(high, AE_WRITABLE, record, data);
+        }));
+
+        run_once(&mut event_loop);
+        assert_eq!(sub.calls, vec![low]);
+        assert_eq!(ae_get_file_events(&event_loop, low), AE_NONE);
+        assert_eq!(ae_get_file_events(&event_loop, high), AE_NONE);
+
+        close_pipe(first.0, first.1);
+        close_pipe(second.0, second.1);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_reused_fd_is_left_alone() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let pipe = readable_pipe();
+        let mut sub = Box::new(Subsystem::default());
+        let data = &mut *sub as *mut Subsystem as *mut c_void;
+
+        let scope = ae_scope(&mut event_loop, |scope| {
+            scope.register(pipe.0, AE_READABLE, record, data);
+        });
+        /* Deleted behind the scope's back and registered by someone else. */
+        ae_delete_file_event(&mut event_loop, pipe.0, AE_READABLE);
+        ae_create_file_event(&mut event_loop, pipe.0, AE_READABLE, record, data);
+
+        ae_scope_close(&mut event_loop, scope);
+        assert_eq!(ae_get_file_events(&event_loop, pipe.0), AE_READABLE);
+
+        ae_delete_file_event(&mut event_loop, pipe.0, AE_READABLE);
+        close_pipe(pipe.0, pipe.1);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_close_and_extend() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut other = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let pipe = readable_pipe();
+        let mut sub = Box::new(Subsystem::default());
+        let data = &mut *sub as *mut Subsystem as *mut c_void;
+
+        let scope = ae_scope(&mut event_loop, |_| {});
+        assert_eq!(
+            ae_scope_extend(&mut event_loop, &scope, |scope| {
+                scope.register(pipe.0, AE_READABLE, record, data);
+                scope.spawn_timer(10_000, never, data, Some(finalize));
+            }),
+            AE_OK
+        );
+        assert_eq!(
+            ae_scope_extend(&mut other, &scope, |_| unreachable!()),
+            AE_ERR
+        );
+
+        /* Torn down on the spot, no iteration needed. */
+        ae_scope_close(&mut event_loop, scope);
+        assert_eq!(ae_get_file_events(&event_loop, pipe.0), AE_NONE);
+        assert_eq!(ae_pending_time_events(&event_loop), 0);
+        /* Deleted timers are finalized by the next pass over the timers. */
+        run_once(&mut event_loop);
+        assert_eq!(sub.finalized, 1);
+
+        close_pipe(pipe.0, pipe.1);
+        ae_delete_event_loop(other);
+        ae_delete_event_loop(event_loop);
+    }
+}
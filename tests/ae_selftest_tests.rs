@@ -0,0 +1,42 @@
+/* Self-Test Tests
+ *
+ * Tests for ae_self_test() (ae/selftest.rs), which must pass on any
+ * kernel the test suite runs on.
+ */
+
+use rae::{AeClockSource, ae_create_event_loop, ae_delete_event_loop, ae_self_test};
+
+mod self_test {
+    use super::*;
+
+    #[test]
+    fn test_every_backend_passes() {
+        let report = ae_self_test().expect("Self-test could not run");
+        assert!(report.passed(), "{report:?}");
+        let event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        assert_eq!(report.default_backend, event_loop.api_name());
+        assert_eq!(report.backends[0].name, event_loop.api_name());
+        ae_delete_event_loop(event_loop);
+
+        #[cfg(target_os = "linux")]
+        assert!(report.backends.iter().any(|check| check.name == "epoll"));
+        #[cfg(target_os = "macos")]
+        assert!(report.backends.iter().any(|check| check.name == "kqueue"));
+
+        for check in &report.backends {
+            assert!(check.timer_us.unwrap() >= 1000, "{check:?}");
+        }
+    }
+
+    #[test]
+    fn test_clocks() {
+        let report = ae_self_test().unwrap();
+        assert!(report.clocks.contains(&(AeClockSource::Instant, true)));
+        assert!(
+            !report
+                .clocks
+                .iter()
+                .any(|&(clock, _)| clock == AeClockSource::Manual)
+        );
+    }
+}
@@ -0,0 +1,51 @@
+/* Send/Sync Tests
+ *
+ * Which types may cross threads. The loop is neither Send nor Sync: other
+ * threads reach it through an AeHandle, which is both. The compile-fail
+ * cases live in tests/send_sync/, each with the compiler error it must
+ * produce (regenerate with TRYBUILD=overwrite after a toolchain upgrade).
+ */
+
+use rae::{
+    AeHandle, AeHeartbeat, AeStats, AeTimerLoopHandle, FileEventKey, TimeEventId, TimeEventRef,
+};
+
+fn assert_send<T: Send>() {}
+fn assert_sync<T: Sync>() {}
+
+mod shareable {
+    use super::*;
+
+    #[test]
+    fn test_handles_cross_threads() {
+        assert_send::<AeHandle>();
+        assert_sync::<AeHandle>();
+        assert_send::<AeTimerLoopHandle>();
+        assert_sync::<AeTimerLoopHandle>();
+        assert_send::<TimeEventRef>();
+        assert_sync::<TimeEventRef>();
+        assert_send::<AeHeartbeat>();
+    }
+
+    #[test]
+    fn test_plain_values_cross_threads() {
+        assert_send::<TimeEventId>();
+        assert_sync::<TimeEventId>();
+        assert_send::<FileEventKey>();
+        assert_sync::<FileEventKey>();
+        assert_send::<AeStats>();
+    }
+}
+
+mod thread_bound {
+    #[test]
+    fn test_compile_fail() {
+        /* The shadow of the backend adds its own Rc to the errors of the
+         * loop, which the expected output leaves out. */
+        if cfg!(feature = "debug-invariants") {
+            return;
+        }
+        let cases = trybuild::TestCases::new();
+        cases.compile_fail("tests/send_sync/*.rs");
+    }
+}
@@ -0,0 +1,141 @@
+/* Shared File Event Tests
+ *
+ * Tests for fds watched by several loops through private copies
+ * (ae/shared.rs).
+ */
+
+use rae::anet::anet_pipe;
+use rae::{
+    AE_DONT_WAIT, AE_ERR, AE_FILE_EVENTS, AE_NONE, AE_READABLE, AE_WRITABLE, AeEventLoop,
+    AeFileEventOptions, ae_create_event_loop, ae_create_shared_file_event, ae_delete_event_loop,
+    ae_delete_shared_file_event, ae_get_file_events, ae_process_events, ae_shared_fd_local,
+    ae_shared_fd_origin,
+};
+use std::ffi::c_void;
+
+/* Records the origin of the fd it is called with. */
+fn record_origin(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, _mask: i32) {
+    let origin = ae_shared_fd_origin(event_loop, fd).unwrap_or(-1);
+    unsafe { (*(client_data as *mut Vec<i32>)).push(origin) };
+}
+
+fn noop(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+fn share(event_loop: &mut AeEventLoop, fd: i32, mask: i32, client_data: *mut c_void) -> i32 {
+    ae_create_shared_file_event(
+        event_loop,
+        fd,
+        mask,
+        record_origin,
+        client_data,
+        AeFileEventOptions::default(),
+    )
+}
+
+/* True once every write end of the pipe is closed. */
+fn at_eof(rfd: i32) -> bool {
+    let mut buf = [0u8; 16];
+    loop {
+        match unsafe { libc::read(rfd, buf.as_mut_ptr() as *mut c_void, buf.len()) } {
+            0 => return true,
+            -1 => return false,
+            _ => continue,
+        }
+    }
+}
+
+mod shared {
+    use super::*;
+
+    #[test]
+    fn test_two_loops_watch_one_pipe() {
+        let mut a = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut b = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        let mut seen_a: Vec<i32> = Vec::new();
+        let mut seen_b: Vec<i32> = Vec::new();
+
+        let local_a = share(
+            &mut a,
+            rfd,
+            AE_READABLE,
+            &mut seen_a as *mut _ as *mut c_void,
+        );
+        let local_b = share(
+            &mut b,
+            rfd,
+            AE_READABLE,
+            &mut seen_b as *mut _ as *mut c_void,
+        );
+        assert!(local_a != AE_ERR && local_b != AE_ERR);
+        assert_ne!(local_a, rfd);
+        assert_ne!(local_a, local_b);
+        assert_eq!(ae_shared_fd_local(&a, rfd), Some(local_a));
+        assert_eq!(ae_get_file_events(&a, rfd), AE_NONE);
+
+        /* The owner closes its fd, the loops keep theirs. */
+        unsafe { libc::close(rfd) };
+        unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+        ae_process_events(&mut a, AE_FILE_EVENTS | AE_DONT_WAIT);
+        ae_process_events(&mut b, AE_FILE_EVENTS | AE_DONT_WAIT);
+        assert_eq!(seen_a, vec![rfd]);
+        assert_eq!(seen_b, vec![rfd]);
+
+        ae_delete_event_loop(a);
+        ae_delete_event_loop(b);
+        unsafe { libc::close(wfd) };
+    }
+
+    #[test]
+    fn test_copy_reused_and_closed_with_last_event() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        let local = share(&mut event_loop, wfd, AE_WRITABLE, std::ptr::null_mut());
+        assert_eq!(
+            ae_create_shared_file_event(
+                &mut event_loop,
+                wfd,
+                AE_READABLE,
+                noop,
+                std::ptr::null_mut(),
+                AeFileEventOptions::default()
+            ),
+            local
+        );
+        unsafe { libc::close(wfd) };
+
+        assert_eq!(
+            ae_delete_shared_file_event(&mut event_loop, wfd, AE_WRITABLE),
+            local
+        );
+        assert_eq!(ae_get_file_events(&event_loop, local), AE_READABLE);
+        assert!(!at_eof(rfd), "The copy is still open");
+
+        ae_delete_shared_file_event(&mut event_loop, wfd, AE_READABLE);
+        assert!(at_eof(rfd));
+        assert_eq!(ae_shared_fd_local(&event_loop, wfd), None);
+        assert_eq!(
+            ae_delete_shared_file_event(&mut event_loop, wfd, AE_READABLE),
+            AE_ERR
+        );
+
+        ae_delete_event_loop(event_loop);
+        unsafe { libc::close(rfd) };
+    }
+
+    #[test]
+    fn test_loop_deletion_closes_copies() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        assert_ne!(
+            share(&mut event_loop, wfd, AE_WRITABLE, std::ptr::null_mut()),
+            AE_ERR
+        );
+        unsafe { libc::close(wfd) };
+        assert!(!at_eof(rfd));
+
+        ae_delete_event_loop(event_loop);
+        assert!(at_eof(rfd));
+        unsafe { libc::close(rfd) };
+    }
+}
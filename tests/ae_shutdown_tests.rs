@@ -0,0 +1,105 @@
+/* Shutdown Token Tests
+ *
+ * Tests for ShutdownToken and the shutdown procs (ae/shutdown.rs),
+ * cancelled from other threads, through the loop handle and from the
+ * signal stop path.
+ */
+
+use rae::{
+    AE_ERR, AE_OK, AeEventLoop, ae_create_event_loop, ae_delete_event_loop, ae_get_handle, ae_main,
+    ae_on_shutdown, ae_request_stop_from_signal, ae_shutdown_token, ae_signal_stop_fd,
+};
+use std::cell::RefCell;
+use std::thread;
+use std::time::Duration;
+
+thread_local! {
+    static CALLS: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+fn close_listeners(_event_loop: &mut AeEventLoop) {
+    CALLS.with(|calls| calls.borrow_mut().push("listeners"));
+}
+
+fn flush_clients(_event_loop: &mut AeEventLoop) {
+    CALLS.with(|calls| calls.borrow_mut().push("clients"));
+}
+
+fn take_calls() -> Vec<&'static str> {
+    CALLS.with(|calls| calls.take())
+}
+
+mod token {
+    use super::*;
+
+    #[test]
+    fn test_cancel_from_other_thread() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let token = ae_shutdown_token(&mut event_loop).expect("Failed to create token");
+        assert!(token.same_loop(&ae_shutdown_token(&mut event_loop).unwrap()));
+        ae_on_shutdown(&mut event_loop, close_listeners);
+        ae_on_shutdown(&mut event_loop, flush_clients);
+        assert!(!token.is_cancelled());
+
+        let canceller = {
+            let token = token.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                assert!(token.cancel());
+                assert!(!token.cancel(), "Already cancelled");
+            })
+        };
+        ae_main(&mut event_loop);
+        canceller.join().unwrap();
+
+        assert!(token.is_cancelled());
+        assert_eq!(take_calls(), vec!["listeners", "clients"]);
+        assert_eq!(ae_on_shutdown(&mut event_loop, flush_clients), AE_ERR);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_handle_stop_cancels() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let token = ae_shutdown_token(&mut event_loop).unwrap();
+        assert_eq!(ae_on_shutdown(&mut event_loop, close_listeners), AE_OK);
+        let handle = ae_get_handle(&mut event_loop).unwrap();
+
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            handle.stop();
+        });
+        ae_main(&mut event_loop);
+        stopper.join().unwrap();
+
+        assert!(token.is_cancelled());
+        assert_eq!(take_calls(), vec!["listeners"]);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_signal_stop_cancels() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let token = ae_shutdown_token(&mut event_loop).unwrap();
+        ae_on_shutdown(&mut event_loop, flush_clients);
+        let fd = ae_signal_stop_fd(&mut event_loop);
+
+        /* What a signal handler would do. */
+        ae_request_stop_from_signal(fd);
+        ae_main(&mut event_loop);
+
+        assert!(token.is_cancelled());
+        assert_eq!(take_calls(), vec!["clients"]);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_cancelled_after_loop_deleted() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let token = ae_shutdown_token(&mut event_loop).unwrap();
+        ae_delete_event_loop(event_loop);
+        /* Nobody left to run the procs, the token still reads cancelled. */
+        assert!(token.cancel());
+        assert!(token.is_cancelled());
+    }
+}
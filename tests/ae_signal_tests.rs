@@ -0,0 +1,112 @@
+/* Signal Stop Tests
+ *
+ * Tests for stopping the loop from a signal handler through the wakeup
+ * pipe (ae/signal.rs).
+ */
+
+use rae::{
+    AE_ERR, AE_OK, AeEventLoop, ae_create_event_loop, ae_create_time_event, ae_main,
+    ae_request_stop_from_signal, ae_signal_stop_fd, ae_stop_on_signal,
+};
+use std::ffi::c_void;
+use std::time::{Duration, Instant};
+
+fn far_timer(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    10_000
+}
+
+/* A loop that would otherwise block for ten seconds. */
+fn idle_loop() -> Box<AeEventLoop> {
+    let mut event_loop = ae_create_event_loop(64).unwrap();
+    ae_create_time_event(
+        &mut event_loop,
+        10_000,
+        far_timer,
+        std::ptr::null_mut(),
+        None,
+    );
+    event_loop
+}
+
+fn signal_thread_after(signo: i32, delay: Duration) -> std::thread::JoinHandle<()> {
+    let target = unsafe { libc::pthread_self() } as usize;
+    std::thread::spawn(move || {
+        std::thread::sleep(delay);
+        unsafe { libc::pthread_kill(target as libc::pthread_t, signo) };
+    })
+}
+
+mod stop_on_signal {
+    use super::*;
+
+    #[test]
+    fn test_signal_stops_running_loop() {
+        let mut event_loop = idle_loop();
+        assert_eq!(ae_stop_on_signal(&mut event_loop, libc::SIGUSR1), AE_OK);
+
+        let sender = signal_thread_after(libc::SIGUSR1, Duration::from_millis(50));
+        let start = Instant::now();
+        ae_main(&mut event_loop);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn test_signal_before_main_is_not_lost() {
+        let mut event_loop = idle_loop();
+        assert_eq!(ae_stop_on_signal(&mut event_loop, libc::SIGUSR2), AE_OK);
+        unsafe { libc::pthread_kill(libc::pthread_self(), libc::SIGUSR2) };
+
+        let start = Instant::now();
+        ae_main(&mut event_loop);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_signal_after_delete_is_harmless() {
+        let mut event_loop = idle_loop();
+        assert_eq!(ae_stop_on_signal(&mut event_loop, libc::SIGWINCH), AE_OK);
+        drop(event_loop);
+        unsafe { libc::pthread_kill(libc::pthread_self(), libc::SIGWINCH) };
+    }
+
+    #[test]
+    fn test_invalid_signal() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        assert_eq!(ae_stop_on_signal(&mut event_loop, 0), AE_ERR);
+        assert_eq!(ae_stop_on_signal(&mut event_loop, 1000), AE_ERR);
+    }
+}
+
+mod request_stop {
+    use super::*;
+
+    #[test]
+    fn test_request_from_another_thread() {
+        let mut event_loop = idle_loop();
+        let fd = ae_signal_stop_fd(&mut event_loop);
+        assert!(fd >= 0);
+        assert_eq!(ae_signal_stop_fd(&mut event_loop), fd);
+
+        let requester = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            ae_request_stop_from_signal(fd);
+        });
+        let start = Instant::now();
+        ae_main(&mut event_loop);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        requester.join().unwrap();
+    }
+
+    #[test]
+    fn test_request_preserves_errno() {
+        /* Leave ENOENT in errno, then fail a write with EBADF. */
+        let missing = c"/nonexistent/rae-signal-test";
+        assert_eq!(unsafe { libc::access(missing.as_ptr(), libc::F_OK) }, -1);
+        ae_request_stop_from_signal(1_000_000);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ENOENT)
+        );
+    }
+}
@@ -0,0 +1,79 @@
+/* SIGPIPE Tests
+ *
+ * Tests for AeSigpipePolicy (ae/signal.rs). The disposition of SIGPIPE
+ * is process-wide and every loop built may change it, so this binary
+ * holds a single test that walks through the cases in order.
+ */
+
+use rae::test_util::consume_all;
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_OK, AeEventLoop, AeEventLoopBuilder, AeSigpipePolicy,
+    ae_conn_create, ae_conn_write, ae_create_event_loop, ae_delete_event_loop, ae_process_events,
+};
+use std::ffi::c_void;
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixStream;
+
+fn disposition() -> libc::sighandler_t {
+    let mut current: libc::sigaction = unsafe { std::mem::zeroed() };
+    assert_eq!(
+        unsafe { libc::sigaction(libc::SIGPIPE, std::ptr::null(), &mut current) },
+        0
+    );
+    current.sa_sigaction
+}
+
+fn set_disposition(handler: libc::sighandler_t) {
+    unsafe { libc::signal(libc::SIGPIPE, handler) };
+}
+
+extern "C" fn app_handler(_signo: libc::c_int) {}
+
+fn record_err(_event_loop: &mut AeEventLoop, _fd: i32, err: i32, client_data: *mut c_void) {
+    unsafe { *(client_data as *mut i32) = err };
+}
+
+mod sigpipe {
+    use super::*;
+
+    #[test]
+    fn test_policies() {
+        /* Rust binaries start with SIGPIPE ignored: put the C default
+         * back. */
+        set_disposition(libc::SIG_DFL);
+        let event_loop = AeEventLoopBuilder::new(64)
+            .sigpipe(AeSigpipePolicy::Keep)
+            .build()
+            .expect("Failed to create event loop");
+        assert_eq!(disposition(), libc::SIG_DFL);
+        ae_delete_event_loop(event_loop);
+
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        assert_eq!(disposition(), libc::SIG_IGN);
+
+        /* Writing to a connection whose peer is gone closes it with
+         * EPIPE instead of killing the process. */
+        let (ours, theirs) = UnixStream::pair().expect("Failed to create socket pair");
+        ours.set_nonblocking(true).unwrap();
+        let fd = ours.into_raw_fd();
+        let mut err = 0i32;
+        let data = &mut err as *mut i32 as *mut c_void;
+        assert_eq!(
+            ae_conn_create(&mut event_loop, fd, consume_all, Some(record_err), data),
+            AE_OK
+        );
+        drop(theirs);
+        ae_conn_write(&mut event_loop, fd, b"hello");
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(err, libc::EPIPE);
+        ae_delete_event_loop(event_loop);
+
+        /* A handler of the application is kept. */
+        let handler = app_handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        set_disposition(handler);
+        let event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        assert_eq!(disposition(), handler);
+        ae_delete_event_loop(event_loop);
+        set_disposition(libc::SIG_IGN);
+    }
+}
@@ -0,0 +1,192 @@
+/* Snapshot Tests
+ *
+ * Tests for ae_snapshot() and LoopSnapshot::diff() (ae/snapshot.rs): the
+ * registrations found between two snapshots, fd reuse, and the "back to
+ * baseline" check of a soak test.
+ */
+
+use rae::anet::anet_pipe;
+use rae::test_util::close_pipe;
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_NOMORE, AE_READABLE, AE_WRITABLE, AeEventLoop,
+    ae_create_event_loop, ae_create_file_event, ae_create_time_event, ae_delete_file_event,
+    ae_delete_time_event, ae_process_events, ae_snapshot,
+};
+use std::ffi::c_void;
+
+fn noop(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+fn repeat(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    1
+}
+
+fn once(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    AE_NOMORE
+}
+
+mod snapshot {
+    use super::*;
+
+    #[test]
+    fn test_records_fds_and_timers() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        assert!(ae_snapshot(&event_loop).fds.is_empty());
+
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        ae_create_file_event(
+            &mut event_loop,
+            wfd,
+            AE_WRITABLE,
+            noop,
+            std::ptr::null_mut(),
+        );
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            noop,
+            std::ptr::null_mut(),
+        );
+        let id = ae_create_time_event(&mut event_loop, 1000, repeat, std::ptr::null_mut(), None);
+
+        let snapshot = ae_snapshot(&event_loop);
+        let fds: Vec<(i32, i32)> = snapshot.fds.iter().map(|f| (f.fd, f.mask)).collect();
+        let mut expected = vec![(rfd, AE_READABLE), (wfd, AE_WRITABLE)];
+        expected.sort();
+        assert_eq!(fds, expected);
+        assert_eq!(snapshot.timers.len(), 1);
+        assert_eq!(snapshot.timers[0].id, id);
+
+        ae_delete_time_event(&mut event_loop, id);
+        assert!(ae_snapshot(&event_loop).timers.is_empty());
+        ae_delete_file_event(&mut event_loop, rfd, AE_READABLE);
+        ae_delete_file_event(&mut event_loop, wfd, AE_WRITABLE);
+        close_pipe(rfd, wfd);
+    }
+}
+
+mod diff {
+    use super::*;
+
+    #[test]
+    fn test_added_removed_changed() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            noop,
+            std::ptr::null_mut(),
+        );
+        let old_timer =
+            ae_create_time_event(&mut event_loop, 1000, repeat, std::ptr::null_mut(), None);
+        let before = ae_snapshot(&event_loop);
+
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_WRITABLE,
+            noop,
+            std::ptr::null_mut(),
+        );
+        ae_create_file_event(
+            &mut event_loop,
+            wfd,
+            AE_WRITABLE,
+            noop,
+            std::ptr::null_mut(),
+        );
+        ae_delete_time_event(&mut event_loop, old_timer);
+        let new_timer =
+            ae_create_time_event(&mut event_loop, 1000, repeat, std::ptr::null_mut(), None);
+
+        let diff = before.diff(&ae_snapshot(&event_loop));
+        assert!(!diff.is_empty());
+        assert_eq!(diff.fds_added.len(), 1);
+        assert_eq!(diff.fds_added[0].fd, wfd);
+        assert!(diff.fds_removed.is_empty());
+        assert_eq!(diff.fds_changed.len(), 1);
+        assert_eq!(diff.fds_changed[0].0.mask, AE_READABLE);
+        assert_eq!(diff.fds_changed[0].1.mask, AE_READABLE | AE_WRITABLE);
+        assert_eq!(
+            diff.timers_removed.iter().map(|t| t.id).collect::<Vec<_>>(),
+            vec![old_timer]
+        );
+        assert_eq!(
+            diff.timers_added.iter().map(|t| t.id).collect::<Vec<_>>(),
+            vec![new_timer]
+        );
+
+        ae_delete_time_event(&mut event_loop, new_timer);
+        ae_delete_file_event(&mut event_loop, rfd, AE_READABLE | AE_WRITABLE);
+        ae_delete_file_event(&mut event_loop, wfd, AE_WRITABLE);
+        close_pipe(rfd, wfd);
+    }
+
+    #[test]
+    fn test_reused_fd_is_a_new_registration() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            noop,
+            std::ptr::null_mut(),
+        );
+        let before = ae_snapshot(&event_loop);
+
+        ae_delete_file_event(&mut event_loop, rfd, AE_READABLE);
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            noop,
+            std::ptr::null_mut(),
+        );
+        let diff = before.diff(&ae_snapshot(&event_loop));
+        assert_eq!(diff.fds_removed.len(), 1);
+        assert_eq!(diff.fds_added.len(), 1);
+        assert_eq!(diff.fds_removed[0].fd, diff.fds_added[0].fd);
+        assert!(diff.fds_added[0].generation > diff.fds_removed[0].generation);
+        assert!(diff.fds_changed.is_empty());
+
+        ae_delete_file_event(&mut event_loop, rfd, AE_READABLE);
+        close_pipe(rfd, wfd);
+    }
+
+    /* The soak test pattern: registrations come and go under load, and
+     * are back to the baseline once it drained. */
+    #[test]
+    fn test_back_to_baseline() {
+        let mut event_loop = ae_create_event_loop(256).expect("Failed to create event loop");
+        let heartbeat =
+            ae_create_time_event(&mut event_loop, 1, repeat, std::ptr::null_mut(), None);
+        let baseline = ae_snapshot(&event_loop);
+
+        for _ in 0..100 {
+            let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+            ae_create_file_event(
+                &mut event_loop,
+                rfd,
+                AE_READABLE,
+                noop,
+                std::ptr::null_mut(),
+            );
+            ae_create_time_event(&mut event_loop, 0, once, std::ptr::null_mut(), None);
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+            ae_delete_file_event(&mut event_loop, rfd, AE_READABLE);
+            close_pipe(rfd, wfd);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+
+        let after = ae_snapshot(&event_loop);
+        assert!(after.iteration > baseline.iteration);
+        let diff = baseline.diff(&after);
+        assert!(diff.is_empty(), "{:?}", diff);
+
+        ae_delete_time_event(&mut event_loop, heartbeat);
+    }
+}
@@ -0,0 +1,574 @@
+/* Loop Statistics Tests
+ *
+ * Tests for the counters, latency histograms, rusage sampling and
+ * connection traffic exposed by ae_get_stats().
+ */
+
+use rae::anet::anet_pipe;
+use rae::monotonic::get_monotonic_us;
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_NOMORE, AE_READABLE, AeBackendStats, AeClockSource,
+    AeEventLoop, AeEventLoopBuilder, AeHistogram, ae_create_event_loop, ae_create_file_event,
+    ae_create_time_event, ae_cycle_counter_hz, ae_get_stats, ae_process_events, ae_reset_stats,
+};
+use std::ffi::c_void;
+use std::time::{Duration, Instant};
+
+fn drain_readable(_event_loop: &mut AeEventLoop, fd: i32, _client_data: *mut c_void, _mask: i32) {
+    let mut buf = [0u8; 64];
+    unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+}
+
+fn once_timer(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    AE_NOMORE
+}
+
+fn slow_timer(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    AE_NOMORE
+}
+
+mod counters {
+    use super::*;
+
+    #[test]
+    fn test_counts_iterations_and_events() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        ae_create_file_event(
+            &mut event_loop,
+            rfd,
+            AE_READABLE,
+            drain_readable,
+            std::ptr::null_mut(),
+        );
+        ae_create_time_event(&mut event_loop, 0, once_timer, std::ptr::null_mut(), None);
+
+        unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+
+        let stats = ae_get_stats(&event_loop);
+        assert_eq!(stats.iterations, 2);
+        assert_eq!(stats.file_events, 1);
+        assert_eq!(stats.time_events, 1);
+        assert!(stats.rusage.is_none(), "Sampling is off by default");
+
+        ae_reset_stats(&mut event_loop);
+        let stats = ae_get_stats(&event_loop);
+        assert_eq!(stats.iterations, 0);
+        assert_eq!(stats.file_events, 0);
+
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+}
+
+mod backend {
+    use super::*;
+
+    #[test]
+    fn test_counts_syscalls() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        ae_reset_stats(&mut event_loop);
+        let pipes: Vec<(i32, i32)> = (0..3)
+            .map(|_| anet_pipe(true).expect("Failed to create pipe"))
+            .collect();
+        for &(rfd, wfd) in &pipes {
+            ae_create_file_event(
+                &mut event_loop,
+                rfd,
+                AE_READABLE,
+                drain_readable,
+                std::ptr::null_mut(),
+            );
+            unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+        }
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+
+        let backend = ae_get_stats(&event_loop).backend;
+        assert_eq!(backend.poll_calls, 2);
+        assert_eq!(backend.max_batch, 3);
+        assert_eq!(backend.eintr, 0);
+        if event_loop.api_name() != "select" {
+            assert_eq!(backend.ctl_calls, 3, "One registration per fd");
+        }
+
+        ae_reset_stats(&mut event_loop);
+        assert_eq!(ae_get_stats(&event_loop).backend, AeBackendStats::default());
+
+        for (rfd, wfd) in pipes {
+            unsafe {
+                libc::close(rfd);
+                libc::close(wfd);
+            }
+        }
+    }
+}
+
+mod barrier {
+    use super::*;
+    use rae::{AE_BARRIER, AE_WRITABLE, AeDispatchOrder};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    /* Distinct from on_read: a handler registered for both is called once. */
+    fn noop(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+    fn on_read(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+    fn drop_read(event_loop: &mut AeEventLoop, fd: i32, _client_data: *mut c_void, _mask: i32) {
+        rae::ae_delete_file_event(event_loop, fd, AE_READABLE);
+    }
+
+    /* Dispatch one iteration of a socket that is readable (if `input`)
+     * and writable, return (barrier_inversions, barrier_writes_before_reads). */
+    fn barrier_stats(order: AeDispatchOrder, input: bool, write_proc: rae::FileProc) -> (u64, u64) {
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .dispatch_order(order)
+            .build()
+            .expect("Failed to create event loop");
+        let (a, mut b) = UnixStream::pair().expect("Failed to create socket pair");
+        if input {
+            std::io::Write::write_all(&mut b, b"x").unwrap();
+        }
+        let fd = a.as_raw_fd();
+        ae_create_file_event(
+            &mut event_loop,
+            fd,
+            AE_READABLE,
+            on_read,
+            std::ptr::null_mut(),
+        );
+        ae_create_file_event(
+            &mut event_loop,
+            fd,
+            AE_WRITABLE | AE_BARRIER,
+            write_proc,
+            std::ptr::null_mut(),
+        );
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        let stats = ae_get_stats(&event_loop);
+        (stats.barrier_inversions, stats.barrier_writes_before_reads)
+    }
+
+    #[test]
+    fn test_counts_inverted_dispatches() {
+        assert_eq!(
+            barrier_stats(AeDispatchOrder::ReadsFirst, true, noop),
+            (1, 1)
+        );
+        /* The write handler ran first, the read handler not at all. */
+        assert_eq!(
+            barrier_stats(AeDispatchOrder::ReadsFirst, true, drop_read),
+            (1, 0)
+        );
+    }
+
+    #[test]
+    fn test_ignores_dispatches_it_did_not_change() {
+        /* Only writable: there was no order to invert. */
+        assert_eq!(
+            barrier_stats(AeDispatchOrder::ReadsFirst, false, noop),
+            (0, 0)
+        );
+        /* The policy already runs writes first. */
+        assert_eq!(
+            barrier_stats(AeDispatchOrder::WritesFirst, true, noop),
+            (0, 0)
+        );
+    }
+}
+
+mod rusage {
+    use super::*;
+
+    #[test]
+    fn test_sampled_every_n_iterations() {
+        if !cfg!(feature = "instrumentation") {
+            return;
+        }
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .rusage_sample_interval(2)
+            .build()
+            .expect("Failed to create event loop");
+
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert!(ae_get_stats(&event_loop).rusage.is_none());
+
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        let stats = ae_get_stats(&event_loop);
+        assert!(stats.rusage.is_some(), "Sample taken on iteration 2");
+        assert!(stats.rusage_delta.is_none(), "A delta needs two samples");
+
+        /* Burn some CPU so the delta has something to show. */
+        let mut x = 0u64;
+        for i in 0..5_000_000u64 {
+            x = std::hint::black_box(x.wrapping_add(i * i));
+        }
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+
+        let stats = ae_get_stats(&event_loop);
+        let first = stats.rusage.unwrap();
+        let delta = stats.rusage_delta.expect("Delta after the second sample");
+        assert!(first.user_us + first.sys_us > 0);
+        assert!(stats.rusage_wall_us > 0);
+        assert!(delta.user_us + delta.sys_us <= first.user_us + first.sys_us);
+    }
+}
+
+mod histograms {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let mut hist = AeHistogram::default();
+        assert_eq!(hist.p99(), 0, "Empty histogram");
+
+        for v in 1..=1000u64 {
+            hist.record(v);
+        }
+        assert_eq!(hist.count(), 1000);
+        assert_eq!(hist.min(), 1);
+        assert_eq!(hist.max(), 1000);
+        assert_eq!(hist.mean(), 500);
+
+        /* Buckets are within 1/16 of the recorded value. */
+        let p50 = hist.p50();
+        assert!((500..=532).contains(&p50), "p50 = {}", p50);
+        let p99 = hist.p99();
+        assert!((990..=1000).contains(&p99), "p99 = {}", p99);
+        assert_eq!(hist.p999(), 1000);
+        assert_eq!(hist.percentile(0.0), 1);
+
+        hist.reset();
+        assert_eq!(hist.count(), 0);
+        assert_eq!(hist.p50(), 0);
+    }
+
+    #[test]
+    fn test_large_values() {
+        let mut hist = AeHistogram::default();
+        hist.record(u64::MAX);
+        hist.record(0);
+        assert_eq!(hist.p50(), 0);
+        assert_eq!(hist.p999(), u64::MAX);
+    }
+
+    #[test]
+    fn test_loop_records_poll_and_callbacks() {
+        if !cfg!(feature = "instrumentation") {
+            return;
+        }
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        ae_create_time_event(&mut event_loop, 0, slow_timer, std::ptr::null_mut(), None);
+
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+
+        let stats = ae_get_stats(&event_loop);
+        assert_eq!(stats.callback_us.count(), 1);
+        assert!(stats.callback_us.max() >= 5000, "Timer slept 5ms");
+
+        ae_reset_stats(&mut event_loop);
+        assert_eq!(ae_get_stats(&event_loop).callback_us.count(), 0);
+    }
+
+    #[test]
+    fn test_latency_clock_times_callbacks() {
+        if !cfg!(feature = "instrumentation") {
+            return;
+        }
+        if !AeClockSource::Cycles.is_supported() {
+            assert!(ae_cycle_counter_hz().is_none());
+            assert!(
+                AeEventLoopBuilder::new(1024)
+                    .latency_clock(AeClockSource::Cycles)
+                    .build()
+                    .is_none()
+            );
+            return;
+        }
+        assert!(ae_cycle_counter_hz().is_some_and(|hz| hz > 0));
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .clock_source(AeClockSource::MonotonicCoarse)
+            .latency_clock(AeClockSource::Cycles)
+            .build()
+            .expect("Failed to create event loop");
+        ae_create_time_event(&mut event_loop, 0, slow_timer, std::ptr::null_mut(), None);
+
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+
+        let stats = ae_get_stats(&event_loop);
+        assert_eq!(stats.callback_us.count(), 1);
+        let max = stats.callback_us.max();
+        assert!(
+            (4500..1_000_000).contains(&max),
+            "Timer slept 5ms, got {}",
+            max
+        );
+    }
+
+    #[test]
+    fn test_cycle_counter_follows_monotonic_time() {
+        if !AeClockSource::Cycles.is_supported() {
+            return;
+        }
+        let cycles_start = get_monotonic_us(AeClockSource::Cycles);
+        let start = Instant::now();
+        std::thread::sleep(Duration::from_millis(50));
+        let cycles = get_monotonic_us(AeClockSource::Cycles) - cycles_start;
+        let elapsed = start.elapsed().as_micros() as u64;
+        /* Within 10% of the time measured by Instant. */
+        assert!(
+            cycles.abs_diff(elapsed) < elapsed / 10,
+            "{} vs {}",
+            cycles,
+            elapsed
+        );
+    }
+}
+
+mod connections {
+    use super::*;
+    use rae::test_util::consume_all;
+    use rae::{
+        AE_OK, AeClockSource, ae_advance_clock, ae_conn_close, ae_conn_create, ae_conn_stats,
+        ae_conn_write, ae_loop_now,
+    };
+    use std::io::{Read, Write};
+    use std::os::fd::IntoRawFd;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_traffic_is_accounted() {
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .clock_source(AeClockSource::Manual)
+            .build()
+            .expect("Failed to create event loop");
+        let (ours, mut theirs) = UnixStream::pair().expect("Failed to create socket pair");
+        ours.set_nonblocking(true).unwrap();
+        let fd = ours.into_raw_fd();
+        let result = ae_conn_create(&mut event_loop, fd, consume_all, None, std::ptr::null_mut());
+        assert_eq!(result, AE_OK);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        let opened_at = ae_loop_now(&event_loop);
+
+        ae_advance_clock(&mut event_loop, 500_000);
+        theirs.write_all(&[0; 3000]).unwrap();
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(ae_conn_write(&mut event_loop, fd, &[0; 1000]), AE_OK);
+        let mut reply = [0u8; 1000];
+        theirs.read_exact(&mut reply).unwrap();
+
+        let conn = ae_conn_stats(&event_loop, fd).expect("Missing connection stats");
+        assert_eq!((conn.bytes_in, conn.bytes_out), (3000, 1000));
+        assert_eq!(conn.created_us, opened_at);
+        assert_eq!(conn.last_interaction_us, opened_at + 500_000);
+
+        /* The rate window closes on the first iteration a second later. */
+        ae_advance_clock(&mut event_loop, 500_000);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        let stats = ae_get_stats(&event_loop);
+        assert_eq!((stats.conn_bytes_in, stats.conn_bytes_out), (3000, 1000));
+        if cfg!(feature = "instrumentation") {
+            assert_eq!((stats.conn_input_bps, stats.conn_output_bps), (3000, 1000));
+        }
+        assert_eq!((stats.conns_created, stats.conns_open), (1, 1));
+
+        ae_reset_stats(&mut event_loop);
+        ae_conn_close(&mut event_loop, fd);
+        assert!(ae_conn_stats(&event_loop, fd).is_none());
+        let stats = ae_get_stats(&event_loop);
+        assert_eq!(
+            (stats.conn_bytes_in, stats.conns_created, stats.conns_open),
+            (0, 0, 0)
+        );
+    }
+}
+
+mod naming {
+    use super::*;
+    use rae::{ae_get_handle, ae_get_loop_name};
+
+    #[test]
+    fn test_name_in_stats() {
+        let mut event_loop = AeEventLoopBuilder::new(64)
+            .name("ingest")
+            .build()
+            .expect("Failed to create event loop");
+        assert_eq!(ae_get_loop_name(&event_loop), Some("ingest"));
+        assert_eq!(
+            ae_get_stats(&event_loop).loop_name.as_deref(),
+            Some("ingest")
+        );
+
+        /* The name is not a counter, a reset keeps it. */
+        ae_reset_stats(&mut event_loop);
+        assert_eq!(
+            ae_get_stats(&event_loop).loop_name.as_deref(),
+            Some("ingest")
+        );
+
+        let handle = ae_get_handle(&mut event_loop).expect("Failed to get handle");
+        assert_eq!(handle.loop_name(), Some("ingest"));
+        assert!(format!("{handle:?}").contains("ingest"));
+    }
+
+    #[test]
+    fn test_unnamed_loop() {
+        let event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        assert_eq!(ae_get_loop_name(&event_loop), None);
+        assert_eq!(ae_get_stats(&event_loop).loop_name, None);
+    }
+}
+
+mod utilization {
+    use super::*;
+    use rae::test_util::virtual_loop;
+    use rae::{AE_FILE_EVENTS, AE_STATS_UTILIZATION_WINDOW_US, ae_advance_clock};
+
+    /* A callback that keeps the loop busy for 50ms of manual time. */
+    fn busy_50ms(event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {
+        ae_advance_clock(event_loop, 50_000);
+    }
+
+    fn sleep_20ms(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        20
+    }
+
+    #[test]
+    fn test_never_waiting_is_saturated() {
+        if !cfg!(feature = "instrumentation") {
+            return;
+        }
+        let (mut event_loop, control) = virtual_loop(64);
+        ae_create_file_event(
+            &mut event_loop,
+            3,
+            AE_READABLE,
+            busy_50ms,
+            std::ptr::null_mut(),
+        );
+        control.set_ready(3, AE_READABLE);
+        assert_eq!(ae_get_stats(&event_loop).utilization, 0.0);
+
+        for _ in 0..5 {
+            ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        }
+        let stats = ae_get_stats(&event_loop);
+        assert_eq!(stats.utilization, 1.0);
+        /* Measured from the end of the first iteration. */
+        assert_eq!(stats.utilization_window_us, 200_000);
+
+        /* Older slots slide out of the window. */
+        for _ in 0..100 {
+            ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        }
+        assert!(ae_get_stats(&event_loop).utilization_window_us <= AE_STATS_UTILIZATION_WINDOW_US);
+
+        ae_reset_stats(&mut event_loop);
+        let stats = ae_get_stats(&event_loop);
+        assert_eq!((stats.utilization, stats.utilization_window_us), (0.0, 0));
+    }
+
+    #[test]
+    fn test_half_busy_loop() {
+        if !cfg!(feature = "instrumentation") {
+            return;
+        }
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_create_time_event(&mut event_loop, 20, sleep_20ms, std::ptr::null_mut(), None);
+
+        /* Each timer waits 20ms in poll, then runs for 20ms. */
+        for _ in 0..6 {
+            ae_process_events(&mut event_loop, AE_ALL_EVENTS);
+        }
+        let stats = ae_get_stats(&event_loop);
+        assert!(
+            stats.utilization > 0.2 && stats.utilization < 0.8,
+            "utilization {}",
+            stats.utilization
+        );
+        assert!(stats.utilization_window_us >= 100_000);
+    }
+}
+
+mod flush {
+    use super::*;
+    use rae::test_util::{run_iterations, virtual_loop};
+    use rae::{
+        AE_ERR, AE_OK, AE_TIME_EVENTS, AeStatsDelta, ae_advance_clock, ae_pending_time_events,
+        ae_set_stats_flush_proc,
+    };
+
+    fn collect(_event_loop: &mut AeEventLoop, delta: &AeStatsDelta, client_data: *mut c_void) {
+        unsafe { (*(client_data as *mut Vec<AeStatsDelta>)).push(*delta) };
+    }
+
+    #[test]
+    fn test_deltas_at_the_requested_cadence() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let mut flushed: Vec<AeStatsDelta> = Vec::new();
+        let client_data = &mut flushed as *mut Vec<AeStatsDelta> as *mut c_void;
+        assert_eq!(
+            ae_set_stats_flush_proc(&mut event_loop, 1000, Some(collect), client_data),
+            AE_OK
+        );
+
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 3);
+        ae_advance_clock(&mut event_loop, 1_000_000);
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(flushed[0].interval_us, 1_000_000);
+        /* The iteration running the flush is not over yet. */
+        assert_eq!(flushed[0].iterations, 3);
+        assert_eq!(flushed[0].time_events, 0);
+
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 4);
+        ae_advance_clock(&mut event_loop, 1_000_000);
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert_eq!(flushed.len(), 2);
+        assert_eq!(flushed[1].iterations, 5);
+        /* The previous flush itself. */
+        assert_eq!(flushed[1].time_events, 1);
+
+        /* A reset does not show up as a huge delta. */
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 2);
+        ae_reset_stats(&mut event_loop);
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        ae_advance_clock(&mut event_loop, 1_000_000);
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert_eq!(flushed[2].iterations, 1);
+    }
+
+    #[test]
+    fn test_replace_and_remove() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let mut flushed: Vec<AeStatsDelta> = Vec::new();
+        let client_data = &mut flushed as *mut Vec<AeStatsDelta> as *mut c_void;
+        assert_eq!(
+            ae_set_stats_flush_proc(&mut event_loop, 0, Some(collect), client_data),
+            AE_ERR
+        );
+
+        ae_set_stats_flush_proc(&mut event_loop, 1000, Some(collect), client_data);
+        ae_set_stats_flush_proc(&mut event_loop, 500, Some(collect), client_data);
+        assert_eq!(ae_pending_time_events(&event_loop), 1);
+        ae_advance_clock(&mut event_loop, 500_000);
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert_eq!(flushed.len(), 1);
+
+        ae_set_stats_flush_proc(&mut event_loop, 0, None, std::ptr::null_mut());
+        assert_eq!(ae_pending_time_events(&event_loop), 0);
+        ae_advance_clock(&mut event_loop, 5_000_000);
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert_eq!(flushed.len(), 1);
+    }
+}
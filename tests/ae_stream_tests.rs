@@ -0,0 +1,145 @@
+/* Stream Registration Tests
+ *
+ * Tests for line and chunk oriented registration of pipes (ae/stream.rs),
+ * including partial lines, end of file handling and fd flag restoration.
+ */
+
+use rae::anet::anet_pipe;
+use rae::test_util::pump;
+use rae::{
+    AE_OK, AeEventLoop, AeStreamMode, ae_create_event_loop, ae_get_file_events, ae_register_stream,
+    ae_unregister_stream,
+};
+use std::ffi::c_void;
+
+#[derive(Default)]
+struct Received {
+    items: Vec<Vec<u8>>,
+    eof: bool,
+}
+
+fn record_stream(
+    _event_loop: &mut AeEventLoop,
+    _fd: i32,
+    data: Option<&[u8]>,
+    client_data: *mut c_void,
+) {
+    let received = unsafe { &mut *(client_data as *mut Received) };
+    match data {
+        Some(bytes) => received.items.push(bytes.to_vec()),
+        None => received.eof = true,
+    }
+}
+
+fn write_all(fd: i32, data: &[u8]) {
+    let n = unsafe { libc::write(fd, data.as_ptr() as *const c_void, data.len()) };
+    assert_eq!(n as usize, data.len());
+}
+
+mod lines {
+    use super::*;
+
+    #[test]
+    fn test_lines_and_partial_line() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(false).expect("Failed to create pipe");
+        let mut received = Received::default();
+
+        let result = ae_register_stream(
+            &mut event_loop,
+            rfd,
+            AeStreamMode::Lines,
+            record_stream,
+            &mut received as *mut Received as *mut c_void,
+        );
+        assert_eq!(result, AE_OK);
+
+        write_all(wfd, b"first\nsecond\r\nthi");
+        pump(&mut event_loop);
+        assert_eq!(received.items, vec![b"first".to_vec(), b"second".to_vec()]);
+
+        write_all(wfd, b"rd\nlast");
+        unsafe { libc::close(wfd) };
+        pump(&mut event_loop);
+
+        assert_eq!(received.items.len(), 4);
+        assert_eq!(received.items[2], b"third");
+        assert_eq!(
+            received.items[3], b"last",
+            "Unterminated line flushed at EOF"
+        );
+        assert!(received.eof);
+        assert_eq!(
+            ae_get_file_events(&event_loop, rfd),
+            0,
+            "Stream unregistered at EOF"
+        );
+
+        unsafe { libc::close(rfd) };
+    }
+
+    #[test]
+    fn test_unregister_restores_blocking_mode() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(false).expect("Failed to create pipe");
+        let mut received = Received::default();
+
+        ae_register_stream(
+            &mut event_loop,
+            rfd,
+            AeStreamMode::Lines,
+            record_stream,
+            &mut received as *mut Received as *mut c_void,
+        );
+        let flags = unsafe { libc::fcntl(rfd, libc::F_GETFL) };
+        assert_ne!(
+            flags & libc::O_NONBLOCK,
+            0,
+            "Registered fd should be non-blocking"
+        );
+
+        ae_unregister_stream(&mut event_loop, rfd);
+        let flags = unsafe { libc::fcntl(rfd, libc::F_GETFL) };
+        assert_eq!(
+            flags & libc::O_NONBLOCK,
+            0,
+            "Blocking mode should be restored"
+        );
+        assert_eq!(ae_get_file_events(&event_loop, rfd), 0);
+        assert!(!received.eof);
+
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+}
+
+mod chunks {
+    use super::*;
+
+    #[test]
+    fn test_chunks_delivered_verbatim() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (rfd, wfd) = anet_pipe(true).expect("Failed to create pipe");
+        let mut received = Received::default();
+
+        ae_register_stream(
+            &mut event_loop,
+            rfd,
+            AeStreamMode::Chunks,
+            record_stream,
+            &mut received as *mut Received as *mut c_void,
+        );
+
+        write_all(wfd, b"no newline\nhere");
+        pump(&mut event_loop);
+        assert_eq!(received.items, vec![b"no newline\nhere".to_vec()]);
+
+        unsafe { libc::close(wfd) };
+        pump(&mut event_loop);
+        assert!(received.eof);
+
+        unsafe { libc::close(rfd) };
+    }
+}
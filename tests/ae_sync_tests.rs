@@ -0,0 +1,125 @@
+/* Loop Synchronization Tests
+ *
+ * Tests for the loop-local notify, semaphore and oneshot (ae/sync.rs):
+ * waiters are woken in order, always at the next iteration.
+ */
+
+use rae::test_util::{run_iterations, virtual_loop};
+use rae::{AE_TIME_EVENTS, AeDispatchCtx, AeEventLoop, AeNotify, AeSemaphore, ae_oneshot};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+thread_local! {
+    /* States handed back to the procs, in the order they were called. */
+    static WOKEN: RefCell<VecDeque<Box<u32>>> = const { RefCell::new(VecDeque::new()) };
+}
+
+/* Keeps the waiter number it was given. */
+fn woken(_event_loop: &mut AeEventLoop, _ctx: &AeDispatchCtx, state: Box<u32>) {
+    WOKEN.with_borrow_mut(|woken| woken.push_back(state));
+}
+
+/* Keeps the value received, in the slot it was given. */
+fn received(_event_loop: &mut AeEventLoop, _ctx: &AeDispatchCtx, value: u32, mut slot: Box<u32>) {
+    *slot = value;
+    WOKEN.with_borrow_mut(|woken| woken.push_back(slot));
+}
+
+fn woken_order() -> Vec<u32> {
+    WOKEN.with_borrow(|woken| woken.iter().map(|n| **n).collect())
+}
+
+mod notify {
+    use super::*;
+
+    #[test]
+    fn test_notify_one_then_all() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let notify = AeNotify::new();
+        for n in 1..=3 {
+            notify.wait(&mut event_loop, Box::new(n), woken);
+        }
+
+        notify.notify_one(&mut event_loop);
+        assert!(woken_order().is_empty(), "Woken at the next iteration");
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert_eq!(woken_order(), vec![1]);
+
+        notify.clone().notify_all(&mut event_loop);
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert_eq!(woken_order(), vec![1, 2, 3]);
+        assert_eq!(notify.waiters(), 0);
+    }
+
+    #[test]
+    fn test_pending_notification() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let notify = AeNotify::new();
+        notify.notify_one(&mut event_loop);
+        notify.notify_one(&mut event_loop);
+
+        notify.wait(&mut event_loop, Box::new(1), woken);
+        notify.wait(&mut event_loop, Box::new(2), woken);
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        /* Notifications do not add up. */
+        assert_eq!(woken_order(), vec![1]);
+        assert_eq!(notify.waiters(), 1);
+    }
+}
+
+mod semaphore {
+    use super::*;
+
+    #[test]
+    fn test_permits_in_arrival_order() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let semaphore = AeSemaphore::new(1);
+        for n in 1..=3 {
+            semaphore.acquire(&mut event_loop, Box::new(n), woken);
+        }
+        assert_eq!(semaphore.available_permits(), 0);
+        assert_eq!(semaphore.waiters(), 2);
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert_eq!(woken_order(), vec![1]);
+
+        semaphore.release(&mut event_loop);
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert_eq!(woken_order(), vec![1, 2]);
+
+        semaphore.release(&mut event_loop);
+        semaphore.release(&mut event_loop);
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert_eq!(woken_order(), vec![1, 2, 3]);
+        assert_eq!(semaphore.available_permits(), 1);
+    }
+}
+
+mod oneshot {
+    use super::*;
+
+    #[test]
+    fn test_recv_before_and_after_send() {
+        let (mut event_loop, _control) = virtual_loop(64);
+
+        let (tx, rx) = ae_oneshot::<u32>();
+        rx.recv(&mut event_loop, Box::new(0), received);
+        assert_eq!(tx.send(&mut event_loop, 7), Ok(()));
+        assert!(woken_order().is_empty());
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert_eq!(woken_order(), vec![7]);
+
+        let (tx, rx) = ae_oneshot::<u32>();
+        assert_eq!(tx.send(&mut event_loop, 8), Ok(()));
+        rx.recv(&mut event_loop, Box::new(0), received);
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert_eq!(woken_order(), vec![7, 8]);
+    }
+
+    #[test]
+    fn test_receiver_dropped() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let (tx, rx) = ae_oneshot::<u32>();
+        drop(rx);
+        assert_eq!(tx.send(&mut event_loop, 9), Err(9));
+    }
+}
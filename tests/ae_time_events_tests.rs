@@ -356,3 +356,840 @@ mod callback_data {
         ae_delete_event_loop(event_loop);
     }
 }
+
+mod reentrancy {
+    use super::*;
+    use rae::test_util::run_iterations;
+    use rae::{AE_ERR, AE_OK};
+
+    /* Shared by the callbacks below through client_data. */
+    #[derive(Default)]
+    struct TimerState {
+        calls: i32,
+        finalized: i32,
+        other_id: i64,
+        created_id: i64,
+        retval: i32,
+    }
+
+    fn state<'a>(client_data: *mut c_void) -> &'a mut TimerState {
+        unsafe { &mut *(client_data as *mut TimerState) }
+    }
+
+    fn count_finalize(_event_loop: &mut rae::AeEventLoop, client_data: *mut c_void) {
+        state(client_data).finalized += 1;
+    }
+
+    fn delete_self(event_loop: &mut rae::AeEventLoop, id: i64, client_data: *mut c_void) -> i32 {
+        let st = state(client_data);
+        st.calls += 1;
+        assert_eq!(ae_delete_time_event(event_loop, id), AE_OK);
+        assert_eq!(
+            ae_delete_time_event(event_loop, id),
+            AE_ERR,
+            "Already deleted"
+        );
+        st.retval
+    }
+
+    fn delete_self_and_create(
+        event_loop: &mut rae::AeEventLoop,
+        id: i64,
+        client_data: *mut c_void,
+    ) -> i32 {
+        let st = state(client_data);
+        st.calls += 1;
+        ae_delete_time_event(event_loop, id);
+        st.created_id = ae_create_time_event(event_loop, 0, count_call, client_data, None);
+        10
+    }
+
+    fn delete_other(event_loop: &mut rae::AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+        let st = state(client_data);
+        st.calls += 1;
+        ae_delete_time_event(event_loop, st.other_id);
+        AE_NOMORE
+    }
+
+    fn count_call(_event_loop: &mut rae::AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+        state(client_data).calls += 100;
+        AE_NOMORE
+    }
+
+    #[test]
+    fn test_delete_self() {
+        for retval in [AE_NOMORE, 0, 10] {
+            let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+            let mut st = TimerState {
+                retval,
+                ..Default::default()
+            };
+            let data = &mut st as *mut TimerState as *mut c_void;
+            let id =
+                ae_create_time_event(&mut event_loop, 0, delete_self, data, Some(count_finalize));
+
+            run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+            assert_eq!(st.calls, 1);
+            assert_eq!(st.finalized, 1, "Timer released when the callback returns");
+
+            std::thread::sleep(Duration::from_millis(15));
+            run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+            assert_eq!(st.calls, 1, "Deleted timer must not be rescheduled");
+            assert_eq!(ae_delete_time_event(&mut event_loop, id), AE_ERR);
+
+            ae_delete_event_loop(event_loop);
+            assert_eq!(st.finalized, 1);
+        }
+    }
+
+    #[test]
+    fn test_delete_self_then_create() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let mut st = TimerState::default();
+        let data = &mut st as *mut TimerState as *mut c_void;
+        let id = ae_create_time_event(
+            &mut event_loop,
+            0,
+            delete_self_and_create,
+            data,
+            Some(count_finalize),
+        );
+
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert_eq!(st.calls, 1, "New timer does not run in the same iteration");
+        assert_ne!(st.created_id, id, "Deleted id is never reused");
+        assert_eq!(st.finalized, 1);
+
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert_eq!(st.calls, 101, "Only the new timer fires");
+        assert_eq!(ae_delete_time_event(&mut event_loop, id), AE_ERR);
+    }
+
+    #[test]
+    fn test_delete_other() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let mut st = TimerState::default();
+        let data = &mut st as *mut TimerState as *mut c_void;
+        /* Both are due: whichever runs first, the other must not. */
+        let first = ae_create_time_event(&mut event_loop, 0, delete_other, data, None);
+        let second =
+            ae_create_time_event(&mut event_loop, 0, count_call, data, Some(count_finalize));
+        st.other_id = second;
+        assert!(first > 0 && second > first);
+
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        run_iterations(&mut event_loop, AE_TIME_EVENTS, 1);
+        assert!(
+            st.calls == 1 || st.calls == 101,
+            "The deleted timer ran after its deletion"
+        );
+        assert_eq!(st.finalized, 1);
+    }
+}
+
+mod finalizers {
+    use super::*;
+
+    /* (finalizer calls, id of another timer to delete or create from the
+     * finalizer) */
+    type FinalizeState = (i32, i64);
+
+    fn finalize_state<'a>(client_data: *mut c_void) -> &'a mut FinalizeState {
+        unsafe { &mut *(client_data as *mut FinalizeState) }
+    }
+
+    fn count_finalize(_event_loop: &mut rae::AeEventLoop, client_data: *mut c_void) {
+        finalize_state(client_data).0 += 1;
+    }
+
+    fn finalize_and_delete_other(event_loop: &mut rae::AeEventLoop, client_data: *mut c_void) {
+        let st = finalize_state(client_data);
+        st.0 += 1;
+        ae_delete_time_event(event_loop, st.1);
+    }
+
+    fn finalize_and_create(event_loop: &mut rae::AeEventLoop, client_data: *mut c_void) {
+        let st = finalize_state(client_data);
+        st.0 += 1;
+        if st.1 == 0 {
+            st.1 = ae_create_time_event(
+                event_loop,
+                1000,
+                test_time_callback,
+                client_data,
+                Some(count_finalize),
+            );
+        }
+    }
+
+    fn noop(_event_loop: &mut rae::AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+        AE_NOMORE
+    }
+
+    #[test]
+    fn test_once_when_processed_then_dropped() {
+        let mut st: FinalizeState = (0, 0);
+        let data = &mut st as *mut FinalizeState as *mut c_void;
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_create_time_event(&mut event_loop, 0, noop, data, Some(count_finalize));
+
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        assert_eq!(st.0, 1);
+        ae_delete_event_loop(event_loop);
+        assert_eq!(st.0, 1);
+    }
+
+    #[test]
+    fn test_once_when_deleted_then_dropped() {
+        let mut st: FinalizeState = (0, 0);
+        let data = &mut st as *mut FinalizeState as *mut c_void;
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let id = ae_create_time_event(&mut event_loop, 1000, noop, data, Some(count_finalize));
+
+        /* Deleted but never cleaned up by an iteration. */
+        ae_delete_time_event(&mut event_loop, id);
+        ae_delete_event_loop(event_loop);
+        assert_eq!(st.0, 1);
+    }
+
+    #[test]
+    fn test_finalizer_deleting_another_timer() {
+        let mut st: FinalizeState = (0, 0);
+        let data = &mut st as *mut FinalizeState as *mut c_void;
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let other = ae_create_time_event(&mut event_loop, 1000, noop, data, Some(count_finalize));
+        st.1 = other;
+        ae_create_time_event(
+            &mut event_loop,
+            0,
+            noop,
+            data,
+            Some(finalize_and_delete_other),
+        );
+
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        assert_eq!(st.0, 2, "Both finalizers ran");
+        ae_delete_event_loop(event_loop);
+        assert_eq!(st.0, 2);
+    }
+
+    #[test]
+    fn test_finalizer_creating_timer_during_drop() {
+        let mut st: FinalizeState = (0, 0);
+        let data = &mut st as *mut FinalizeState as *mut c_void;
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        ae_create_time_event(&mut event_loop, 1000, noop, data, Some(finalize_and_create));
+
+        ae_delete_event_loop(event_loop);
+        assert!(st.1 > 0, "Timer created while dropping");
+        assert_eq!(st.0, 2, "The new timer is finalized too");
+    }
+}
+
+mod owned_data {
+    use super::*;
+    use rae::ae_create_time_event_owned;
+    use std::rc::Rc;
+
+    /* Counts its runs, and tells through the Rc when it was dropped. */
+    struct Job {
+        runs: i32,
+        max_runs: i32,
+        _alive: Rc<()>,
+    }
+
+    fn run_job(_event_loop: &mut rae::AeEventLoop, _id: i64, job: &mut Job) -> i32 {
+        job.runs += 1;
+        if job.runs == job.max_runs {
+            AE_NOMORE
+        } else {
+            0
+        }
+    }
+
+    #[test]
+    fn test_data_dropped_after_nomore() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let alive = Rc::new(());
+        let job = Box::new(Job {
+            runs: 0,
+            max_runs: 3,
+            _alive: alive.clone(),
+        });
+        ae_create_time_event_owned(&mut event_loop, 0, run_job, job);
+        assert_eq!(Rc::strong_count(&alive), 2);
+
+        for _ in 0..3 {
+            ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        }
+        assert_eq!(Rc::strong_count(&alive), 1, "Job dropped after AE_NOMORE");
+
+        ae_delete_event_loop(event_loop);
+        assert_eq!(Rc::strong_count(&alive), 1);
+    }
+
+    #[test]
+    fn test_data_dropped_on_delete_and_loop_drop() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let alive = Rc::new(());
+        let new_job = || {
+            Box::new(Job {
+                runs: 0,
+                max_runs: 100,
+                _alive: alive.clone(),
+            })
+        };
+        let deleted = ae_create_time_event_owned(&mut event_loop, 1000, run_job, new_job());
+        ae_create_time_event_owned(&mut event_loop, 1000, run_job, new_job());
+        assert_eq!(Rc::strong_count(&alive), 3);
+
+        ae_delete_time_event(&mut event_loop, deleted);
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        assert_eq!(Rc::strong_count(&alive), 2);
+
+        ae_delete_event_loop(event_loop);
+        assert_eq!(Rc::strong_count(&alive), 1);
+    }
+}
+
+mod weak_refs {
+    use super::*;
+    use rae::{AE_ERR, AE_OK, ae_delete_time_event_ref, ae_time_event_ref};
+
+    fn once(_event_loop: &mut rae::AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+        AE_NOMORE
+    }
+
+    #[test]
+    fn test_ref_observes_nomore() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let id = ae_create_time_event(&mut event_loop, 0, once, std::ptr::null_mut(), None);
+        let timer = ae_time_event_ref(&mut event_loop, id).expect("Timer exists");
+        assert_eq!(timer.upgrade(), Some(id));
+
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        assert!(!timer.is_alive());
+        assert_eq!(timer.upgrade(), None);
+        assert_eq!(timer.id(), id);
+        assert_eq!(ae_delete_time_event_ref(&mut event_loop, &timer), AE_ERR);
+        assert!(ae_time_event_ref(&mut event_loop, id).is_none());
+    }
+
+    #[test]
+    fn test_delete_through_ref() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        let id = ae_create_time_event(&mut event_loop, 1000, once, std::ptr::null_mut(), None);
+        let timer = ae_time_event_ref(&mut event_loop, id).unwrap();
+        let clone = ae_time_event_ref(&mut event_loop, id).unwrap();
+
+        assert_eq!(ae_delete_time_event_ref(&mut event_loop, &timer), AE_OK);
+        assert!(!clone.is_alive(), "All refs share the timer state");
+        assert_eq!(ae_delete_time_event_ref(&mut event_loop, &clone), AE_ERR);
+    }
+
+    #[test]
+    fn test_ref_from_other_loop_rejected() {
+        let mut first = ae_create_event_loop(64).expect("Failed to create event loop");
+        let mut second = ae_create_event_loop(64).expect("Failed to create event loop");
+        let id = ae_create_time_event(&mut first, 1000, once, std::ptr::null_mut(), None);
+        let other_id = ae_create_time_event(&mut second, 1000, once, std::ptr::null_mut(), None);
+        assert_eq!(id, other_id, "Both loops hand out the same first id");
+
+        let timer = ae_time_event_ref(&mut first, id).unwrap();
+        assert_eq!(ae_delete_time_event_ref(&mut second, &timer), AE_ERR);
+        assert!(ae_time_event_ref(&mut second, other_id).is_some());
+
+        ae_delete_event_loop(first);
+        assert!(!timer.is_alive(), "Loop deletion kills its timers");
+    }
+}
+
+mod clock_sources {
+    use super::*;
+    use rae::{AeClockSource, AeEventLoopBuilder};
+
+    fn count_callback(
+        _event_loop: &mut rae::AeEventLoop,
+        _id: i64,
+        client_data: *mut c_void,
+    ) -> i32 {
+        let counter = unsafe { &*(client_data as *const AtomicI32) };
+        counter.fetch_add(1, Ordering::SeqCst);
+        AE_NOMORE
+    }
+
+    #[test]
+    fn test_timers_fire_with_each_clock_source() {
+        for clock in [
+            AeClockSource::Instant,
+            AeClockSource::MonotonicRaw,
+            AeClockSource::MonotonicCoarse,
+            AeClockSource::Cycles,
+        ] {
+            if !clock.is_supported() {
+                assert!(
+                    AeEventLoopBuilder::new(1024)
+                        .clock_source(clock)
+                        .build()
+                        .is_none(),
+                    "Unsupported clock {} should fail the build",
+                    clock.name()
+                );
+                continue;
+            }
+            let mut event_loop = AeEventLoopBuilder::new(1024)
+                .clock_source(clock)
+                .build()
+                .expect("Failed to create event loop");
+            let counter = AtomicI32::new(0);
+
+            ae_create_time_event(
+                &mut event_loop,
+                20,
+                count_callback,
+                &counter as *const AtomicI32 as *mut c_void,
+                None,
+            );
+            ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+            assert_eq!(
+                counter.load(Ordering::SeqCst),
+                0,
+                "{}: timer fired early",
+                clock.name()
+            );
+
+            std::thread::sleep(Duration::from_millis(40));
+            ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+            assert_eq!(
+                counter.load(Ordering::SeqCst),
+                1,
+                "{}: timer did not fire",
+                clock.name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_manual_clock_only_moves_when_advanced() {
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .clock_source(AeClockSource::Manual)
+            .build()
+            .expect("Failed to create event loop");
+        let counter = AtomicI32::new(0);
+
+        ae_create_time_event(
+            &mut event_loop,
+            20,
+            count_callback,
+            &counter as *const AtomicI32 as *mut c_void,
+            None,
+        );
+        std::thread::sleep(Duration::from_millis(30));
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+
+        assert_eq!(rae::ae_advance_clock(&mut event_loop, 19_999), rae::AE_OK);
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+        assert_eq!(rae::ae_loop_now(&event_loop), 19_999);
+
+        rae::ae_advance_clock(&mut event_loop, 1);
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_advance_clock_needs_manual_clock() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        assert_eq!(rae::ae_advance_clock(&mut event_loop, 1000), rae::AE_ERR);
+    }
+
+    #[test]
+    fn test_negative_delay_fires_immediately() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let counter = AtomicI32::new(0);
+
+        ae_create_time_event(
+            &mut event_loop,
+            -1000,
+            count_callback,
+            &counter as *const AtomicI32 as *mut c_void,
+            None,
+        );
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+}
+
+mod loop_now {
+    use super::*;
+    use rae::ae_loop_now;
+
+    fn record_now(event_loop: &mut rae::AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+        let seen = unsafe { &mut *(client_data as *mut Vec<u64>) };
+        seen.push(ae_loop_now(event_loop));
+        std::thread::sleep(Duration::from_millis(2));
+        AE_NOMORE
+    }
+
+    #[test]
+    fn test_now_is_stable_within_an_iteration() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut seen: Vec<u64> = Vec::new();
+        let seen_ptr = &mut seen as *mut Vec<u64> as *mut c_void;
+
+        ae_create_time_event(&mut event_loop, 0, record_now, seen_ptr, None);
+        ae_create_time_event(&mut event_loop, 0, record_now, seen_ptr, None);
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+
+        assert_eq!(seen.len(), 2);
+        assert_eq!(
+            seen[0], seen[1],
+            "Callbacks of one iteration see the same time"
+        );
+        assert_eq!(ae_loop_now(&event_loop), seen[0]);
+    }
+
+    #[test]
+    fn test_now_advances_between_iterations() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        let first = ae_loop_now(&event_loop);
+
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(ae_loop_now(&event_loop), first, "Only the loop updates it");
+
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        assert!(ae_loop_now(&event_loop) >= first + 5000);
+    }
+}
+
+mod jitter {
+    use super::*;
+    use rae::{AE_ERR, AE_OK, ae_loop_now, ae_set_time_event_jitter};
+
+    fn every_second(
+        _event_loop: &mut rae::AeEventLoop,
+        _id: i64,
+        _client_data: *mut c_void,
+    ) -> i32 {
+        1000
+    }
+
+    /* Delay until the next firing of the only timer of a loop, after it
+     * ran once. */
+    fn next_delay_us(jitter: u32) -> u64 {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let id = ae_create_time_event(&mut event_loop, 0, every_second, std::ptr::null_mut(), None);
+        assert_eq!(ae_set_time_event_jitter(&mut event_loop, id, jitter), AE_OK);
+        assert_eq!(
+            ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT),
+            1
+        );
+        let when = event_loop.time_event_head.as_ref().unwrap().event.when;
+        when - ae_loop_now(&event_loop)
+    }
+
+    #[test]
+    fn test_no_jitter_by_default() {
+        let delay = next_delay_us(0);
+        assert!((1_000_000..1_050_000).contains(&delay), "delay {delay}");
+    }
+
+    #[test]
+    fn test_jitter_spreads_firings() {
+        let delays: Vec<u64> = (0..20).map(|_| next_delay_us(50)).collect();
+        for &delay in &delays {
+            assert!((500_000..1_550_000).contains(&delay), "delay {delay}");
+        }
+        let spread = delays.iter().max().unwrap() - delays.iter().min().unwrap();
+        assert!(spread > 100_000, "Jitter should vary, got {delays:?}");
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let id = ae_create_time_event(
+            &mut event_loop,
+            10,
+            every_second,
+            std::ptr::null_mut(),
+            None,
+        );
+        assert_eq!(ae_set_time_event_jitter(&mut event_loop, id, 101), AE_ERR);
+        assert_eq!(
+            ae_set_time_event_jitter(&mut event_loop, id + 1, 10),
+            AE_ERR
+        );
+        ae_delete_time_event(&mut event_loop, id);
+        assert_eq!(ae_set_time_event_jitter(&mut event_loop, id, 10), AE_ERR);
+    }
+}
+
+mod overruns {
+    use super::*;
+    use rae::{AE_ERR, ae_create_periodic_event};
+
+    #[derive(Default)]
+    struct Calls {
+        overruns: Vec<u64>,
+        finalized: bool,
+    }
+
+    fn record_overruns(
+        _event_loop: &mut rae::AeEventLoop,
+        _id: i64,
+        overruns: u64,
+        client_data: *mut c_void,
+    ) -> i32 {
+        let calls = unsafe { &mut *(client_data as *mut Calls) };
+        calls.overruns.push(overruns);
+        if calls.overruns.len() == 3 {
+            AE_NOMORE
+        } else {
+            0
+        }
+    }
+
+    fn mark_finalized(_event_loop: &mut rae::AeEventLoop, client_data: *mut c_void) {
+        let calls = unsafe { &mut *(client_data as *mut Calls) };
+        calls.finalized = true;
+    }
+
+    #[test]
+    fn test_reports_missed_periods() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let mut calls = Calls::default();
+
+        let id = ae_create_periodic_event(
+            &mut event_loop,
+            50,
+            record_overruns,
+            &mut calls as *mut Calls as *mut c_void,
+            Some(mark_finalized),
+        );
+        assert!(id > 0);
+
+        /* Busy for five periods and a half: the first call is four
+         * periods late. */
+        std::thread::sleep(Duration::from_millis(275));
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        assert_eq!(calls.overruns, vec![4]);
+
+        /* Then back on schedule. */
+        while calls.overruns.len() < 3 {
+            ae_process_events(&mut event_loop, AE_TIME_EVENTS);
+        }
+        assert_eq!(calls.overruns, vec![4, 0, 0]);
+        assert!(calls.finalized, "AE_NOMORE finalizes the timer");
+    }
+
+    #[test]
+    fn test_rejects_non_positive_period() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        for period in [0, -5] {
+            let id = ae_create_periodic_event(
+                &mut event_loop,
+                period,
+                record_overruns,
+                std::ptr::null_mut(),
+                None,
+            );
+            assert_eq!(id, AE_ERR as i64);
+        }
+    }
+}
+
+mod budget {
+    use super::*;
+    use rae::test_util::virtual_loop;
+    use rae::{
+        AE_ERR, AE_OK, AeOverrunPolicy, ae_advance_clock, ae_get_stats, ae_set_dispatch_label,
+        ae_set_time_event_budget, ae_slowlog_get,
+    };
+    use std::cell::Cell;
+
+    /* Costs what client_data says in loop time, then asks for 10 ms. */
+    fn costly(event_loop: &mut rae::AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+        let cost_us = unsafe { &*(client_data as *const Cell<u64>) }.get();
+        ae_set_dispatch_label(event_loop, "refresh");
+        ae_advance_clock(event_loop, cost_us);
+        10
+    }
+
+    fn run_timers(event_loop: &mut rae::AeEventLoop) -> i32 {
+        ae_process_events(event_loop, AE_TIME_EVENTS | AE_DONT_WAIT)
+    }
+
+    #[test]
+    fn test_overrun_is_recorded() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let cost_us = Cell::new(500u64);
+        let data = &cost_us as *const Cell<u64> as *mut c_void;
+        let id = ae_create_time_event(&mut event_loop, 0, costly, data, None);
+        assert_eq!(
+            ae_set_time_event_budget(&mut event_loop, id, 1_000, AeOverrunPolicy::Record),
+            AE_OK
+        );
+
+        /* Within budget. */
+        assert_eq!(run_timers(&mut event_loop), 1);
+        assert_eq!(ae_get_stats(&event_loop).timer_overruns, 0);
+
+        cost_us.set(3_000);
+        ae_advance_clock(&mut event_loop, 10_000);
+        assert_eq!(run_timers(&mut event_loop), 1);
+        assert_eq!(ae_get_stats(&event_loop).timer_overruns, 1);
+        /* Logged only with the `instrumentation` feature. */
+        if cfg!(feature = "instrumentation") {
+            let slowlog = ae_slowlog_get(&event_loop, 10);
+            assert_eq!(slowlog.len(), 1);
+            assert_eq!(slowlog[0].job_id, id);
+            assert_eq!(slowlog[0].name, "refresh");
+            assert_eq!(slowlog[0].budget_us, 1_000);
+            assert_eq!(slowlog[0].took_us, 3_000);
+        }
+
+        /* The schedule is kept. */
+        ae_advance_clock(&mut event_loop, 10_000);
+        assert_eq!(run_timers(&mut event_loop), 1);
+        assert_eq!(ae_get_stats(&event_loop).timer_firings_skipped, 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_skip_firings_eaten_by_overrun() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let cost_us = Cell::new(35_000u64);
+        let data = &cost_us as *const Cell<u64> as *mut c_void;
+        let id = ae_create_time_event(&mut event_loop, 0, costly, data, None);
+        ae_set_time_event_budget(&mut event_loop, id, 5_000, AeOverrunPolicy::Skip);
+
+        /* 35 ms for a 10 ms period: the next three firings are skipped. */
+        assert_eq!(run_timers(&mut event_loop), 1);
+        assert_eq!(ae_get_stats(&event_loop).timer_firings_skipped, 3);
+        cost_us.set(0);
+        ae_advance_clock(&mut event_loop, 39_000);
+        assert_eq!(run_timers(&mut event_loop), 0);
+        ae_advance_clock(&mut event_loop, 1_000);
+        assert_eq!(run_timers(&mut event_loop), 1);
+
+        /* Fast again: back to every 10 ms. */
+        ae_advance_clock(&mut event_loop, 10_000);
+        assert_eq!(run_timers(&mut event_loop), 1);
+        assert_eq!(ae_get_stats(&event_loop).timer_overruns, 1);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_unknown_timer() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let cost_us = Cell::new(0u64);
+        let data = &cost_us as *const Cell<u64> as *mut c_void;
+        let id = ae_create_time_event(&mut event_loop, 10, costly, data, None);
+        assert_eq!(
+            ae_set_time_event_budget(&mut event_loop, id + 1, 1_000, AeOverrunPolicy::Record),
+            AE_ERR
+        );
+        ae_delete_time_event(&mut event_loop, id);
+        assert_eq!(
+            ae_set_time_event_budget(&mut event_loop, id, 1_000, AeOverrunPolicy::Record),
+            AE_ERR
+        );
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod durations {
+    use super::*;
+    use rae::test_util::virtual_loop;
+    use rae::{ae_advance_clock, ae_create_periodic_event_duration, ae_create_time_event_duration};
+
+    fn count(_event_loop: &mut rae::AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+        unsafe { *(client_data as *mut u32) += 1 };
+        AE_NOMORE
+    }
+
+    #[test]
+    fn test_sub_millisecond_delay() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let mut fired = 0u32;
+        let data = &mut fired as *mut u32 as *mut c_void;
+        ae_create_time_event_duration(
+            &mut event_loop,
+            Duration::from_micros(1500),
+            count,
+            data,
+            None,
+        );
+        ae_advance_clock(&mut event_loop, 1499);
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        assert_eq!(fired, 0);
+        ae_advance_clock(&mut event_loop, 1);
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        assert_eq!(fired, 1);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_integer_version_matches() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let mut fired = 0u32;
+        let data = &mut fired as *mut u32 as *mut c_void;
+        ae_create_time_event(&mut event_loop, 2, count, data, None);
+        ae_create_time_event_duration(&mut event_loop, Duration::from_millis(2), count, data, None);
+        /* Negative delays are due right away, huge ones never. */
+        ae_create_time_event(&mut event_loop, -5, count, data, None);
+        ae_create_time_event_duration(&mut event_loop, Duration::MAX, count, data, None);
+
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        assert_eq!(fired, 1);
+        ae_advance_clock(&mut event_loop, 2000);
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        assert_eq!(fired, 3);
+        ae_delete_event_loop(event_loop);
+    }
+
+    fn count_periods(
+        _event_loop: &mut rae::AeEventLoop,
+        _id: i64,
+        overruns: u64,
+        client_data: *mut c_void,
+    ) -> i32 {
+        unsafe { *(client_data as *mut u32) += 1 + overruns as u32 };
+        0
+    }
+
+    #[test]
+    fn test_sub_millisecond_period() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let mut periods = 0u32;
+        let data = &mut periods as *mut u32 as *mut c_void;
+        let id = ae_create_periodic_event_duration(
+            &mut event_loop,
+            Duration::from_micros(250),
+            count_periods,
+            data,
+            None,
+        );
+        assert!(id > 0);
+        for _ in 0..8 {
+            ae_advance_clock(&mut event_loop, 250);
+            ae_process_events(&mut event_loop, AE_TIME_EVENTS | AE_DONT_WAIT);
+        }
+        assert_eq!(periods, 8);
+
+        assert_eq!(
+            ae_create_periodic_event_duration(
+                &mut event_loop,
+                Duration::ZERO,
+                count_periods,
+                data,
+                None
+            ),
+            rae::AE_ERR as i64
+        );
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -0,0 +1,123 @@
+/* Timer Batch Tests
+ *
+ * Tests for batched timers (ae/timer_batch.rs): due members are handed to
+ * one call of the batch proc, which reschedules or deletes them all.
+ */
+
+use rae::test_util::virtual_loop;
+use rae::{
+    AE_ERR, AE_NOMORE, AE_OK, AE_TIME_EVENTS, AeEventLoop, ae_advance_clock,
+    ae_create_batched_time_event, ae_create_time_event, ae_create_timer_batch,
+    ae_delete_time_event, ae_delete_timer_batch, ae_pending_time_events, ae_process_events,
+    ae_timer_batch_len,
+};
+use std::ffi::c_void;
+
+/* Each call of a batch proc, as the ids it was given. */
+type Calls = Vec<Vec<i64>>;
+
+fn calls<'a>(client_data: *mut c_void) -> &'a mut Calls {
+    unsafe { &mut *(client_data as *mut Calls) }
+}
+
+fn record_once(_event_loop: &mut AeEventLoop, ids: &[i64], client_data: *mut c_void) -> i32 {
+    let mut ids = ids.to_vec();
+    ids.sort();
+    calls(client_data).push(ids);
+    AE_NOMORE
+}
+
+fn record_every_10ms(_event_loop: &mut AeEventLoop, ids: &[i64], client_data: *mut c_void) -> i32 {
+    let mut ids = ids.to_vec();
+    ids.sort();
+    calls(client_data).push(ids);
+    10
+}
+
+fn single(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    AE_NOMORE
+}
+
+mod batch {
+    use super::*;
+
+    #[test]
+    fn test_due_members_in_one_call() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let mut seen: Calls = Vec::new();
+        let batch = ae_create_timer_batch(
+            &mut event_loop,
+            record_once,
+            &mut seen as *mut Calls as *mut c_void,
+        );
+        let ids: Vec<i64> = (0..3)
+            .map(|_| ae_create_batched_time_event(&mut event_loop, batch, 10))
+            .collect();
+        let late = ae_create_batched_time_event(&mut event_loop, batch, 50);
+        ae_create_time_event(&mut event_loop, 10, single, std::ptr::null_mut(), None);
+        assert_eq!(ae_timer_batch_len(&event_loop, batch), 4);
+        assert_eq!(ae_pending_time_events(&event_loop), 5);
+
+        assert_eq!(ae_advance_clock(&mut event_loop, 10_000), AE_OK);
+        /* Every timer that fired counts, batched or not. */
+        assert_eq!(ae_process_events(&mut event_loop, AE_TIME_EVENTS), 4);
+        assert_eq!(seen, vec![ids]);
+        assert_eq!(ae_timer_batch_len(&event_loop, batch), 1);
+
+        assert_eq!(ae_advance_clock(&mut event_loop, 40_000), AE_OK);
+        assert_eq!(ae_process_events(&mut event_loop, AE_TIME_EVENTS), 1);
+        assert_eq!(seen[1], vec![late]);
+        assert_eq!(ae_pending_time_events(&event_loop), 0);
+    }
+
+    #[test]
+    fn test_return_value_reschedules_members() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let mut seen: Calls = Vec::new();
+        let batch = ae_create_timer_batch(
+            &mut event_loop,
+            record_every_10ms,
+            &mut seen as *mut Calls as *mut c_void,
+        );
+        let a = ae_create_batched_time_event(&mut event_loop, batch, 10);
+        let b = ae_create_batched_time_event(&mut event_loop, batch, 10);
+
+        for _ in 0..3 {
+            assert_eq!(ae_advance_clock(&mut event_loop, 10_000), AE_OK);
+            assert_eq!(ae_process_events(&mut event_loop, AE_TIME_EVENTS), 2);
+        }
+        assert_eq!(seen, vec![vec![a, b]; 3]);
+
+        /* A deleted member is left out of the next call. */
+        assert_eq!(ae_delete_time_event(&mut event_loop, a), AE_OK);
+        assert_eq!(ae_advance_clock(&mut event_loop, 10_000), AE_OK);
+        assert_eq!(ae_process_events(&mut event_loop, AE_TIME_EVENTS), 1);
+        assert_eq!(seen[3], vec![b]);
+    }
+
+    #[test]
+    fn test_delete_batch() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let mut seen: Calls = Vec::new();
+        let batch = ae_create_timer_batch(
+            &mut event_loop,
+            record_once,
+            &mut seen as *mut Calls as *mut c_void,
+        );
+        for _ in 0..2 {
+            ae_create_batched_time_event(&mut event_loop, batch, 10);
+        }
+
+        assert_eq!(ae_delete_timer_batch(&mut event_loop, batch), AE_OK);
+        assert_eq!(ae_pending_time_events(&event_loop), 0);
+        assert_eq!(ae_delete_timer_batch(&mut event_loop, batch), AE_ERR);
+        assert_eq!(
+            ae_create_batched_time_event(&mut event_loop, batch, 10),
+            AE_ERR as i64
+        );
+
+        assert_eq!(ae_advance_clock(&mut event_loop, 10_000), AE_OK);
+        assert_eq!(ae_process_events(&mut event_loop, AE_TIME_EVENTS), 0);
+        assert!(seen.is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+/* Timer Group Tests
+ *
+ * Tests for ae_export_timers() and ae_import_timers() (ae/timer_group.rs):
+ * the timers of a session move from one mock loop to another, with the
+ * time they had left, and only the loop holding them calls or finalizes
+ * them.
+ */
+
+use rae::test_util::virtual_loop;
+use rae::{
+    AE_DONT_WAIT, AE_ERR, AE_NOMORE, AE_OK, AE_TIME_EVENTS, AeEventLoop, AeExportedTimer,
+    AeOverrunPolicy, ae_advance_clock, ae_create_batched_time_event, ae_create_time_event,
+    ae_create_time_event_owned, ae_create_timer_batch, ae_delete_event_loop, ae_export_timers,
+    ae_group_timers, ae_import_timers, ae_pending_time_events, ae_process_events,
+    ae_set_time_event_budget, ae_set_time_event_group,
+};
+use std::cell::Cell;
+use std::ffi::c_void;
+
+const SESSION: u64 = 7;
+
+fn count<'a>(client_data: *mut c_void) -> &'a Cell<u32> {
+    unsafe { &*(client_data as *const Cell<u32>) }
+}
+
+fn fire_once(_event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    count(client_data).set(count(client_data).get() + 1);
+    AE_NOMORE
+}
+
+fn finalize(_event_loop: &mut AeEventLoop, client_data: *mut c_void) {
+    count(client_data).set(count(client_data).get() + 100);
+}
+
+/* Exports its own group while running. */
+fn export_self(event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    let exported = unsafe { &mut *(client_data as *mut Option<Vec<AeExportedTimer>>) };
+    *exported = ae_export_timers(event_loop, SESSION);
+    AE_NOMORE
+}
+
+fn batch_proc(_event_loop: &mut AeEventLoop, _ids: &[i64], _client_data: *mut c_void) -> i32 {
+    AE_NOMORE
+}
+
+fn run_timers(event_loop: &mut AeEventLoop) -> i32 {
+    ae_process_events(event_loop, AE_TIME_EVENTS | AE_DONT_WAIT)
+}
+
+fn assert_send<T: Send>(_: &T) {}
+
+mod timer_group {
+    use super::*;
+
+    #[test]
+    fn test_migrate_keeps_remaining_time() {
+        let (mut source, _source_control) = virtual_loop(64);
+        let (mut target, _target_control) = virtual_loop(64);
+        /* Another clock altogether. */
+        ae_advance_clock(&mut target, 5_000_000);
+
+        let fired = Cell::new(0u32);
+        let data = &fired as *const Cell<u32> as *mut c_void;
+        let timeout = ae_create_time_event(&mut source, 100, fire_once, data, Some(finalize));
+        let retry = ae_create_time_event(&mut source, 30, fire_once, data, Some(finalize));
+        /* Not in the group, stays. */
+        ae_create_time_event(&mut source, 30, fire_once, data, None);
+        assert_eq!(
+            ae_set_time_event_group(&mut source, timeout, SESSION),
+            AE_OK
+        );
+        assert_eq!(ae_set_time_event_group(&mut source, retry, SESSION), AE_OK);
+        ae_set_time_event_budget(&mut source, timeout, 500, AeOverrunPolicy::Skip);
+        assert_eq!(ae_group_timers(&source, SESSION).len(), 2);
+
+        ae_advance_clock(&mut source, 10_000);
+        let exported = ae_export_timers(&mut source, SESSION).expect("Nothing is running");
+        assert_send(&exported);
+        let remaining: Vec<(i64, u64)> = exported
+            .iter()
+            .map(|timer| (timer.id, timer.remaining_us))
+            .collect();
+        assert_eq!(remaining, vec![(retry, 20_000), (timeout, 90_000)]);
+        assert_eq!(exported[1].budget_us, 500);
+        assert!(ae_group_timers(&source, SESSION).is_empty());
+
+        /* Gone from the source, without finalizers: they go along. */
+        ae_advance_clock(&mut source, 200_000);
+        assert_eq!(run_timers(&mut source), 1);
+        assert_eq!(fired.get(), 1);
+        assert_eq!(ae_pending_time_events(&source), 0);
+
+        let ids = ae_import_timers(&mut target, &exported);
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ae_group_timers(&target, SESSION).len(), 2);
+        ae_advance_clock(&mut target, 19_000);
+        assert_eq!(run_timers(&mut target), 0);
+        ae_advance_clock(&mut target, 1_000);
+        assert_eq!(run_timers(&mut target), 1);
+        ae_advance_clock(&mut target, 70_000);
+        assert_eq!(run_timers(&mut target), 1);
+        run_timers(&mut target);
+        /* Three calls, two finalizers, all once. */
+        assert_eq!(fired.get(), 203);
+
+        ae_delete_event_loop(source);
+        ae_delete_event_loop(target);
+    }
+
+    #[test]
+    fn test_owned_timer_moves_its_data() {
+        fn tick(_event_loop: &mut AeEventLoop, _id: i64, data: &mut Vec<u8>) -> i32 {
+            data.push(1);
+            if data.len() == 3 { AE_NOMORE } else { 10 }
+        }
+
+        let (mut source, _source_control) = virtual_loop(64);
+        let (mut target, _target_control) = virtual_loop(64);
+        let id = ae_create_time_event_owned(&mut source, 10, tick, Box::new(vec![1u8]));
+        ae_set_time_event_group(&mut source, id, SESSION);
+        ae_advance_clock(&mut source, 10_000);
+        assert_eq!(run_timers(&mut source), 1);
+
+        let exported = ae_export_timers(&mut source, SESSION).unwrap();
+        ae_delete_event_loop(source);
+        ae_import_timers(&mut target, &exported);
+        ae_advance_clock(&mut target, 10_000);
+        assert_eq!(run_timers(&mut target), 1);
+        assert_eq!(ae_pending_time_events(&target), 0);
+        ae_delete_event_loop(target);
+    }
+
+    #[test]
+    fn test_running_timer_is_not_exported() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        let mut exported: Option<Vec<AeExportedTimer>> = Some(Vec::new());
+        let data = &mut exported as *mut Option<Vec<AeExportedTimer>> as *mut c_void;
+        let id = ae_create_time_event(&mut event_loop, 0, export_self, data, None);
+        ae_set_time_event_group(&mut event_loop, id, SESSION);
+
+        run_timers(&mut event_loop);
+        assert!(exported.is_none());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_invalid_groups() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        assert_eq!(
+            ae_set_time_event_group(&mut event_loop, 42, SESSION),
+            AE_ERR
+        );
+        assert!(ae_export_timers(&mut event_loop, 0).is_none());
+        assert_eq!(ae_export_timers(&mut event_loop, SESSION).unwrap().len(), 0);
+
+        /* Members of a batch move with their batch. */
+        let batch = ae_create_timer_batch(&mut event_loop, batch_proc, std::ptr::null_mut());
+        let member = ae_create_batched_time_event(&mut event_loop, batch, 10);
+        assert_eq!(
+            ae_set_time_event_group(&mut event_loop, member, SESSION),
+            AE_ERR
+        );
+        ae_delete_event_loop(event_loop);
+    }
+}
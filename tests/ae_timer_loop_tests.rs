@@ -0,0 +1,154 @@
+/* Timer Loop Tests
+ *
+ * Tests for AeTimerLoop (ae/timer_loop.rs): timers run as on any loop, the
+ * loop holds no fd, and AeTimerLoopHandle wakes it up, posts closures to
+ * it and stops it from other threads.
+ */
+
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_ERR, AE_NOMORE, AE_OK, AE_READABLE, AeEventLoop,
+    AeEventLoopBuilder, AeTimerLoop, ae_create_file_event, ae_create_time_event, ae_process_events,
+    ae_stop,
+};
+use std::ffi::c_void;
+use std::time::{Duration, Instant};
+
+fn noop(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+fn count_and_stop(event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    let count = unsafe { &mut *(client_data as *mut i32) };
+    *count += 1;
+    if *count == 3 {
+        ae_stop(event_loop);
+        return AE_NOMORE;
+    }
+    1
+}
+
+fn never(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    AE_NOMORE
+}
+
+mod timers {
+    use super::*;
+
+    #[test]
+    fn test_runs_timers() {
+        let mut timers = AeTimerLoop::new().expect("Failed to create timer loop");
+        assert_eq!(timers.api_name(), "none");
+        let mut count = 0;
+        ae_create_time_event(
+            &mut timers,
+            1,
+            count_and_stop,
+            &mut count as *mut i32 as *mut c_void,
+            None,
+        );
+        timers.run();
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_refuses_file_events() {
+        let mut timers = AeTimerLoop::new().expect("Failed to create timer loop");
+        assert_eq!(
+            ae_create_file_event(&mut timers, 0, AE_READABLE, noop, std::ptr::null_mut()),
+            AE_ERR
+        );
+    }
+
+    #[test]
+    fn test_builder_options_apply() {
+        let timers = AeEventLoopBuilder::new(1024)
+            .name("scheduler")
+            .best_backend()
+            .build_timer_loop()
+            .expect("Failed to create timer loop");
+        assert_eq!(timers.api_name(), "none");
+        assert_eq!(timers.setsize, 0);
+    }
+
+    /* Ten loops, not a single fd. Linux only, where /proc lists them. */
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_holds_no_fd() {
+        let open_fds = || std::fs::read_dir("/proc/self/fd").unwrap().count();
+        let before = open_fds();
+        let loops: Vec<AeTimerLoop> = (0..10)
+            .map(|_| AeTimerLoop::new().expect("Failed to create timer loop"))
+            .collect();
+        let handles: Vec<_> = loops.iter().map(|timers| timers.handle()).collect();
+        assert_eq!(open_fds(), before);
+        drop(handles);
+    }
+}
+
+mod handle {
+    use super::*;
+
+    #[test]
+    fn test_wakeup_ends_the_wait() {
+        let mut timers = AeTimerLoop::new().expect("Failed to create timer loop");
+        ae_create_time_event(&mut timers, 10_000, never, std::ptr::null_mut(), None);
+        let handle = timers.handle();
+        let waker = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            assert_eq!(handle.wakeup(), AE_OK);
+        });
+
+        let start = Instant::now();
+        ae_process_events(&mut timers, AE_ALL_EVENTS);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        waker.join().unwrap();
+    }
+
+    #[test]
+    fn test_post_runs_on_the_loop() {
+        let mut timers = AeTimerLoop::new().expect("Failed to create timer loop");
+        let handle = timers.handle();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            handle.post(move |event_loop| {
+                tx.send(event_loop.setsize).unwrap();
+                ae_stop(event_loop);
+            });
+        });
+        timers.run();
+        assert_eq!(rx.try_recv(), Ok(0));
+    }
+
+    #[test]
+    fn test_post_runs_without_waiting() {
+        let mut timers = AeTimerLoop::new().expect("Failed to create timer loop");
+        let ran = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let flag = ran.clone();
+        timers.handle().post(move |_| {
+            flag.store(true, std::sync::atomic::Ordering::Release);
+        });
+        ae_process_events(&mut timers, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert!(ran.load(std::sync::atomic::Ordering::Acquire));
+    }
+
+    #[test]
+    fn test_stop_from_another_thread() {
+        let mut timers = AeTimerLoop::new().expect("Failed to create timer loop");
+        let handle = timers.handle();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            assert_eq!(handle.stop(), AE_OK);
+        });
+        timers.run();
+        stopper.join().unwrap();
+    }
+
+    #[test]
+    fn test_closed_after_drop() {
+        let timers = AeTimerLoop::new().expect("Failed to create timer loop");
+        let handle = timers.handle();
+        assert!(!handle.is_closed());
+        drop(timers);
+        assert!(handle.is_closed());
+        assert_eq!(handle.wakeup(), AE_ERR);
+        assert_eq!(handle.post(|_| {}), AE_ERR);
+    }
+}
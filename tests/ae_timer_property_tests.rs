@@ -0,0 +1,208 @@
+/* Timer Property Tests
+ *
+ * Random timer sets run under virtual time (AeClockSource::Manual with
+ * test_util::MockBackend): each poll the loop would have slept in is
+ * replaced by advancing the clock by its timeout, so firings land on
+ * exact microseconds. Checks that no timer is lost, fires early or late,
+ * or out of order, and that due timers all get their turn. These hold for
+ * any correct timer store, whatever its data structure.
+ */
+
+use proptest::prelude::*;
+use rae::test_util::{MockControl, virtual_loop};
+use rae::{
+    AE_ALL_EVENTS, AE_NOMORE, AE_OK, AE_TIME_EVENTS, AeEventLoop, ae_advance_clock,
+    ae_create_time_event, ae_delete_time_event, ae_loop_now, ae_process_events,
+};
+use std::ffi::c_void;
+
+/* Firings as (timer id, loop time in microseconds). */
+type Firings = Vec<(i64, u64)>;
+
+fn firings<'a>(client_data: *mut c_void) -> &'a mut Firings {
+    unsafe { &mut *(client_data as *mut Firings) }
+}
+
+/* Run iterations, sleeping in virtual time, until no timer is left or the
+ * next one is due after `until_us`. */
+fn run_virtual(event_loop: &mut AeEventLoop, control: &MockControl, until_us: u64) {
+    loop {
+        ae_process_events(event_loop, AE_ALL_EVENTS);
+        let sleep_us = match control.last_timeout() {
+            Some(Some(timeout)) => timeout.as_micros() as u64,
+            _ => return,
+        };
+        if ae_loop_now(event_loop) + sleep_us > until_us {
+            return;
+        }
+        assert_eq!(ae_advance_clock(event_loop, sleep_us), AE_OK);
+    }
+}
+
+fn record_once(event_loop: &mut AeEventLoop, id: i64, client_data: *mut c_void) -> i32 {
+    firings(client_data).push((id, ae_loop_now(event_loop)));
+    AE_NOMORE
+}
+
+/* Client data of record_periodic(): a timer rearming itself every
+ * `period_ms`. */
+struct Periodic {
+    period_ms: i32,
+    log: *mut Firings,
+}
+
+fn record_periodic(event_loop: &mut AeEventLoop, id: i64, client_data: *mut c_void) -> i32 {
+    let timer = unsafe { &*(client_data as *const Periodic) };
+    firings(timer.log as *mut c_void).push((id, ae_loop_now(event_loop)));
+    timer.period_ms
+}
+
+fn rearm_now(_event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    unsafe { *(client_data as *mut u32) += 1 };
+    0
+}
+
+mod one_shot {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn test_every_timer_fires_once_on_time(
+            timers in prop::collection::vec((0i64..5_000, any::<bool>()), 1..40),
+        ) {
+            let (mut event_loop, control) = virtual_loop(64);
+            let mut log = Firings::new();
+            let log_ptr = &mut log as *mut Firings as *mut c_void;
+
+            let mut expected = Vec::new();
+            for &(delay_ms, keep) in &timers {
+                let id = ae_create_time_event(&mut event_loop, delay_ms, record_once, log_ptr, None);
+                if keep {
+                    expected.push((id, delay_ms as u64 * 1000));
+                } else {
+                    ae_delete_time_event(&mut event_loop, id);
+                }
+            }
+            run_virtual(&mut event_loop, &control, u64::MAX);
+
+            /* Exactly the kept timers, each once, at its due time. */
+            let mut fired = log.clone();
+            fired.sort();
+            prop_assert_eq!(fired, expected);
+
+            /* In the order of their due times. */
+            prop_assert!(log.windows(2).all(|w| w[0].1 <= w[1].1), "out of order: {:?}", log);
+        }
+
+        #[test]
+        fn test_timers_added_while_running_fire_on_time(
+            first in prop::collection::vec(0i64..2_000, 1..20),
+            later in prop::collection::vec(0i64..2_000, 1..20),
+            at_ms in 0u64..2_000,
+        ) {
+            let (mut event_loop, control) = virtual_loop(64);
+            let mut log = Firings::new();
+            let log_ptr = &mut log as *mut Firings as *mut c_void;
+
+            let mut expected = Vec::new();
+            for &delay_ms in &first {
+                let id = ae_create_time_event(&mut event_loop, delay_ms, record_once, log_ptr, None);
+                expected.push((id, delay_ms as u64 * 1000));
+            }
+            run_virtual(&mut event_loop, &control, at_ms * 1000);
+            let now = ae_loop_now(&event_loop);
+            for &delay_ms in &later {
+                let id = ae_create_time_event(&mut event_loop, delay_ms, record_once, log_ptr, None);
+                expected.push((id, now + delay_ms as u64 * 1000));
+            }
+            run_virtual(&mut event_loop, &control, u64::MAX);
+
+            let mut fired = log.clone();
+            fired.sort();
+            expected.sort();
+            prop_assert_eq!(fired, expected);
+            prop_assert!(log.windows(2).all(|w| w[0].1 <= w[1].1), "out of order: {:?}", log);
+        }
+    }
+}
+
+mod periodic {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn test_periodic_timers_keep_their_rate(
+            periods in prop::collection::vec(1i32..500, 1..10),
+            horizon_ms in 1u64..5_000,
+        ) {
+            let (mut event_loop, control) = virtual_loop(64);
+            let mut log = Firings::new();
+            let log_ptr = &mut log as *mut Firings;
+            let timers: Vec<Periodic> = periods
+                .iter()
+                .map(|&period_ms| Periodic { period_ms, log: log_ptr })
+                .collect();
+
+            let mut ids = Vec::new();
+            for timer in &timers {
+                let data = timer as *const Periodic as *mut c_void;
+                ids.push(ae_create_time_event(
+                    &mut event_loop,
+                    timer.period_ms as i64,
+                    record_periodic,
+                    data,
+                    None,
+                ));
+            }
+            run_virtual(&mut event_loop, &control, horizon_ms * 1000);
+
+            /* Fast timers do not starve slow ones: every timer fired at
+             * each multiple of its period, and only then. */
+            for (timer, &id) in timers.iter().zip(&ids) {
+                let times: Vec<u64> = log.iter().filter(|f| f.0 == id).map(|f| f.1).collect();
+                let period_us = timer.period_ms as u64 * 1000;
+                let expected: Vec<u64> =
+                    (1..=horizon_ms * 1000 / period_us).map(|k| k * period_us).collect();
+                prop_assert_eq!(times, expected, "timer {} every {}ms", id, timer.period_ms);
+            }
+        }
+
+        #[test]
+        fn test_rearming_timer_does_not_starve_others(
+            delays in prop::collection::vec(0i64..100, 1..30),
+        ) {
+            let (mut event_loop, _control) = virtual_loop(64);
+            let mut log = Firings::new();
+            let log_ptr = &mut log as *mut Firings as *mut c_void;
+            let mut rearms = 0u32;
+
+            for &delay_ms in &delays {
+                ae_create_time_event(&mut event_loop, delay_ms, record_once, log_ptr, None);
+            }
+            ae_create_time_event(
+                &mut event_loop,
+                0,
+                rearm_now,
+                &mut rearms as *mut u32 as *mut c_void,
+                None,
+            );
+
+            /* The rearming timer is always due, so time only moves when
+             * the test moves it. It runs once per iteration and the timers
+             * due alongside it run in that same iteration. */
+            let due_ms = *delays.iter().max().unwrap() as u64;
+            let mut iterations = 0;
+            while ae_loop_now(&event_loop) < due_ms * 1000 {
+                ae_process_events(&mut event_loop, AE_TIME_EVENTS);
+                iterations += 1;
+                prop_assert_eq!(rearms, iterations);
+                let now = ae_loop_now(&event_loop);
+                let due = delays.iter().filter(|&&d| d as u64 * 1000 <= now).count();
+                prop_assert_eq!(log.len(), due);
+                ae_advance_clock(&mut event_loop, 1000);
+            }
+            ae_process_events(&mut event_loop, AE_TIME_EVENTS);
+            prop_assert_eq!(log.len(), delays.len());
+        }
+    }
+}
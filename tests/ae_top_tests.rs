@@ -0,0 +1,141 @@
+/* rae-top Tests
+ *
+ * Runs the rae-top binary (src/bin/rae-top.rs) against a loop serving
+ * its admin endpoint from a thread of the test, which exercises
+ * ae_admin_listen() and the stats behind it from the outside. Only built
+ * with the `top` feature.
+ */
+
+#![cfg(feature = "top")]
+
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, ae_admin_listen, ae_create_event_loop, ae_delete_event_loop,
+    ae_process_events,
+};
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/* A loop serving its admin endpoint until dropped. */
+struct Watched {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watched {
+    fn start(name: &str) -> Watched {
+        let path =
+            std::env::temp_dir().join(format!("rae-top-{}-{}.sock", std::process::id(), name));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let thread = {
+            let (path, stop) = (path.clone(), stop.clone());
+            std::thread::spawn(move || {
+                let mut event_loop =
+                    ae_create_event_loop(1024).expect("Failed to create event loop");
+                ae_admin_listen(&mut event_loop, &path).expect("Failed to listen");
+                ready_tx.send(()).unwrap();
+                while !stop.load(Ordering::Relaxed) {
+                    ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                ae_delete_event_loop(event_loop);
+            })
+        };
+        ready_rx.recv().unwrap();
+        Watched {
+            path,
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Watched {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn rae_top(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rae-top"));
+    command
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    command
+}
+
+fn run(args: &[&str]) -> Output {
+    rae_top(args).output().expect("Failed to run rae-top")
+}
+
+mod frames {
+    use super::*;
+
+    #[test]
+    fn test_once_prints_a_frame() {
+        let watched = Watched::start("once");
+        let output = run(&["--once", watched.path.to_str().unwrap()]);
+        assert!(output.status.success());
+
+        let frame = String::from_utf8(output.stdout).unwrap();
+        assert!(frame.starts_with("rae-top "), "{}", frame);
+        assert!(!frame.contains('\x1b'));
+        assert!(frame.contains("utilization "));
+        assert!(frame.contains("\nTOP FDS\n"));
+        assert!(frame.contains("\nSLOW CALLBACKS\n  none\n"));
+        /* The admin listener and rae-top's own connection. */
+        let fd_rows = frame
+            .lines()
+            .skip_while(|line| *line != "TOP FDS")
+            .skip(2)
+            .take_while(|line| !line.is_empty())
+            .count();
+        assert_eq!(fd_rows, 2, "{}", frame);
+    }
+
+    #[test]
+    fn test_stops_when_the_loop_goes_away() {
+        let watched = Watched::start("gone");
+        let child = rae_top(&[watched.path.to_str().unwrap(), "20"])
+            .spawn()
+            .expect("Failed to start rae-top");
+        std::thread::sleep(Duration::from_millis(100));
+        drop(watched);
+
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.matches("\x1b[H\x1b[2J").count() >= 2);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("connection closed"), "{}", stderr);
+    }
+}
+
+mod usage {
+    use super::*;
+
+    #[test]
+    fn test_bad_arguments() {
+        assert_eq!(run(&[]).status.code(), Some(2));
+        assert_eq!(run(&["/tmp/x.sock", "soon"]).status.code(), Some(2));
+
+        let missing = std::env::temp_dir().join("rae-top-missing.sock");
+        let output = run(&["--once", missing.to_str().unwrap()]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(
+            String::from_utf8(output.stderr)
+                .unwrap()
+                .contains("cannot connect")
+        );
+    }
+}
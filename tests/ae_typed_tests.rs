@@ -0,0 +1,254 @@
+/* Typed Identifier Tests
+ *
+ * Tests for the TimeEventId / FileEventKey registration functions
+ * (ae/typed.rs) and their interplay with the raw C-style API.
+ */
+
+use rae::{
+    AE_ERR, AE_NOMORE, AE_OK, AE_READABLE, AE_TIME_EVENTS, AE_WRITABLE, AeEventLoop, FileEventKey,
+    TimeEventId, ae_add_file_event, ae_add_periodic_event, ae_add_time_event,
+    ae_add_time_event_owned, ae_create_event_loop, ae_delete_file_event, ae_delete_time_event,
+    ae_file_event_key_valid, ae_get_file_events, ae_modify_file_event, ae_process_events,
+    ae_remove_file_event, ae_remove_time_event, ae_set_timer_jitter,
+};
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn noop_timer(_event_loop: &mut AeEventLoop, _id: i64, _client_data: *mut c_void) -> i32 {
+    AE_NOMORE
+}
+
+fn noop_periodic(
+    _event_loop: &mut AeEventLoop,
+    _id: i64,
+    _overruns: u64,
+    _client_data: *mut c_void,
+) -> i32 {
+    0
+}
+
+fn noop_file(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+fn pipe() -> (i32, i32) {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    (fds[0], fds[1])
+}
+
+fn close(fds: (i32, i32)) {
+    unsafe {
+        libc::close(fds.0);
+        libc::close(fds.1);
+    }
+}
+
+mod time_event_id {
+    use super::*;
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        let id = ae_add_time_event(
+            &mut event_loop,
+            1000,
+            noop_timer,
+            std::ptr::null_mut(),
+            None,
+        );
+        assert_eq!(ae_set_timer_jitter(&mut event_loop, id, 10), AE_OK);
+        assert_eq!(ae_remove_time_event(&mut event_loop, id), AE_OK);
+        assert_eq!(ae_remove_time_event(&mut event_loop, id), AE_ERR);
+        assert_eq!(ae_set_timer_jitter(&mut event_loop, id, 10), AE_ERR);
+    }
+
+    #[test]
+    fn test_raw_round_trip() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        let id = ae_add_time_event(
+            &mut event_loop,
+            1000,
+            noop_timer,
+            std::ptr::null_mut(),
+            None,
+        );
+        let raw: i64 = id.into();
+        assert_eq!(raw, id.as_raw());
+        assert_eq!(TimeEventId::from_raw(raw), id);
+        assert_eq!(id.to_string(), format!("timer#{raw}"));
+
+        /* Deleted through the C-style API, the typed id sees it. */
+        assert_eq!(ae_delete_time_event(&mut event_loop, raw), AE_OK);
+        assert_eq!(ae_remove_time_event(&mut event_loop, id), AE_ERR);
+    }
+
+    #[test]
+    fn test_owned_and_periodic() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Counted;
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        fn owned_proc(_event_loop: &mut AeEventLoop, _id: i64, _data: &mut Counted) -> i32 {
+            AE_NOMORE
+        }
+
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        let owned = ae_add_time_event_owned(&mut event_loop, 1000, owned_proc, Box::new(Counted));
+        let periodic = ae_add_periodic_event(
+            &mut event_loop,
+            50,
+            noop_periodic,
+            std::ptr::null_mut(),
+            None,
+        )
+        .unwrap();
+        assert_ne!(owned, periodic);
+        assert!(
+            ae_add_periodic_event(
+                &mut event_loop,
+                0,
+                noop_periodic,
+                std::ptr::null_mut(),
+                None
+            )
+            .is_none()
+        );
+
+        assert_eq!(ae_remove_time_event(&mut event_loop, owned), AE_OK);
+        ae_process_events(&mut event_loop, AE_TIME_EVENTS | rae::AE_DONT_WAIT);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+    }
+}
+
+mod file_event_key {
+    use super::*;
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        let fds = pipe();
+        let key = ae_add_file_event(
+            &mut event_loop,
+            fds.0,
+            AE_READABLE,
+            noop_file,
+            std::ptr::null_mut(),
+        )
+        .unwrap();
+        assert_eq!(key.fd(), fds.0);
+        assert_eq!(i32::from(key), fds.0);
+        assert!(ae_file_event_key_valid(&event_loop, key));
+
+        assert_eq!(
+            ae_remove_file_event(&mut event_loop, key, AE_READABLE),
+            AE_OK
+        );
+        assert!(!ae_file_event_key_valid(&event_loop, key));
+        assert_eq!(
+            ae_remove_file_event(&mut event_loop, key, AE_READABLE),
+            AE_ERR
+        );
+        close(fds);
+    }
+
+    #[test]
+    fn test_adding_events_keeps_the_key() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        let fds = pipe();
+        let key = ae_add_file_event(
+            &mut event_loop,
+            fds.1,
+            AE_READABLE,
+            noop_file,
+            std::ptr::null_mut(),
+        )
+        .unwrap();
+        assert_eq!(
+            ae_modify_file_event(
+                &mut event_loop,
+                key,
+                AE_WRITABLE,
+                noop_file,
+                std::ptr::null_mut()
+            ),
+            AE_OK
+        );
+        assert_eq!(
+            ae_get_file_events(&event_loop, fds.1),
+            AE_READABLE | AE_WRITABLE
+        );
+        assert_eq!(FileEventKey::from_raw(&event_loop, fds.1), Some(key));
+
+        /* Removing part of the events keeps the registration. */
+        assert_eq!(
+            ae_remove_file_event(&mut event_loop, key, AE_READABLE),
+            AE_OK
+        );
+        assert!(ae_file_event_key_valid(&event_loop, key));
+        close(fds);
+    }
+
+    #[test]
+    fn test_stale_key_after_fd_reuse() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        let fds = pipe();
+        let old = ae_add_file_event(
+            &mut event_loop,
+            fds.0,
+            AE_READABLE,
+            noop_file,
+            std::ptr::null_mut(),
+        )
+        .unwrap();
+
+        /* The fd goes away through the raw API and the number is
+         * registered again. */
+        ae_delete_file_event(&mut event_loop, fds.0, AE_READABLE);
+        let new = ae_add_file_event(
+            &mut event_loop,
+            fds.0,
+            AE_READABLE,
+            noop_file,
+            std::ptr::null_mut(),
+        )
+        .unwrap();
+        assert_ne!(old, new);
+        assert_eq!(old.fd(), new.fd());
+
+        assert_eq!(
+            ae_remove_file_event(&mut event_loop, old, AE_READABLE),
+            AE_ERR
+        );
+        assert_eq!(
+            ae_modify_file_event(
+                &mut event_loop,
+                old,
+                AE_WRITABLE,
+                noop_file,
+                std::ptr::null_mut()
+            ),
+            AE_ERR
+        );
+        assert_eq!(ae_get_file_events(&event_loop, fds.0), AE_READABLE);
+        assert!(ae_file_event_key_valid(&event_loop, new));
+        close(fds);
+    }
+
+    #[test]
+    fn test_invalid_fd() {
+        let mut event_loop = ae_create_event_loop(64).unwrap();
+        assert!(
+            ae_add_file_event(
+                &mut event_loop,
+                -1,
+                AE_READABLE,
+                noop_file,
+                std::ptr::null_mut()
+            )
+            .is_none()
+        );
+        assert!(FileEventKey::from_raw(&event_loop, 5).is_none());
+    }
+}
@@ -0,0 +1,587 @@
+/* UDP Tests
+ *
+ * Tests for batched UDP receive (ae/udp.rs) and the send helpers
+ * anet_udp_send_batch() and anet_udp_send_segments(), over loopback
+ * sockets. Also AE_ERRQUEUE registrations, fed by ICMP errors.
+ */
+
+use rae::{
+    AE_DONT_WAIT, AE_ERR, AE_FILE_EVENTS, AE_OK, AE_UDP_BATCH_MAX, AeDatagram, AeEventLoop,
+    ae_create_event_loop, ae_delete_event_loop, ae_get_file_events, ae_process_events,
+    ae_udp_register, ae_udp_unregister, anet_udp_send_batch, anet_udp_send_segments,
+};
+use std::ffi::c_void;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::fd::AsRawFd;
+
+/* Data, sender, segment size and truncation of a received datagram. */
+type Datagram = (Vec<u8>, Option<SocketAddr>, usize, bool);
+
+/* What the proc saw: one entry per batch. */
+#[derive(Default)]
+struct Received {
+    batches: Vec<Vec<Datagram>>,
+    unregister: bool,
+}
+
+fn record(event_loop: &mut AeEventLoop, fd: i32, datagrams: &[AeDatagram<'_>], data: *mut c_void) {
+    let received = unsafe { &mut *(data as *mut Received) };
+    received.batches.push(
+        datagrams
+            .iter()
+            .map(|d| (d.data.to_vec(), d.addr, d.segment_size, d.truncated))
+            .collect(),
+    );
+    if received.unregister {
+        assert_eq!(ae_udp_unregister(event_loop, fd), AE_OK);
+    }
+}
+
+fn sockets() -> (UdpSocket, UdpSocket) {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver.set_nonblocking(true).unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.connect(receiver.local_addr().unwrap()).unwrap();
+    (receiver, sender)
+}
+
+fn register(
+    event_loop: &mut AeEventLoop,
+    receiver: &UdpSocket,
+    batch: usize,
+    size: usize,
+    received: &mut Received,
+) {
+    let result = ae_udp_register(
+        event_loop,
+        receiver.as_raw_fd(),
+        batch,
+        size,
+        record,
+        received as *mut Received as *mut c_void,
+    );
+    assert_eq!(result, AE_OK);
+}
+
+mod receive {
+    use super::*;
+
+    #[test]
+    fn test_datagrams_arrive_in_batches() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (receiver, sender) = sockets();
+        let mut received = Received::default();
+        register(&mut event_loop, &receiver, 8, 1500, &mut received);
+
+        for n in 0..20u8 {
+            sender.send(&[n; 10]).unwrap();
+        }
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        let sizes: Vec<usize> = received.batches.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [8, 8, 4]);
+        let datagrams: Vec<_> = received.batches.concat();
+        for (n, (data, addr, segment_size, truncated)) in datagrams.into_iter().enumerate() {
+            assert_eq!(data, [n as u8; 10]);
+            assert_eq!(addr, Some(sender.local_addr().unwrap()));
+            assert_eq!((segment_size, truncated), (0, false));
+        }
+
+        assert_eq!(
+            ae_udp_unregister(&mut event_loop, receiver.as_raw_fd()),
+            AE_OK
+        );
+        assert_eq!(ae_get_file_events(&event_loop, receiver.as_raw_fd()), 0);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (receiver, sender) = sockets();
+        let mut received = Received::default();
+        register(&mut event_loop, &receiver, 4, 16, &mut received);
+
+        sender.send(&[7; 100]).unwrap();
+        sender.send(b"short").unwrap();
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        let datagrams = received.batches.concat();
+        assert_eq!(datagrams.len(), 2);
+        assert_eq!((datagrams[0].0.len(), datagrams[0].3), (16, true));
+        assert_eq!(
+            (&datagrams[1].0[..], datagrams[1].3),
+            (&b"short"[..], false)
+        );
+
+        ae_udp_unregister(&mut event_loop, receiver.as_raw_fd());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_unregister_from_the_proc() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (receiver, sender) = sockets();
+        let mut received = Received {
+            unregister: true,
+            ..Default::default()
+        };
+        register(&mut event_loop, &receiver, 2, 1500, &mut received);
+
+        for _ in 0..6 {
+            sender.send(b"ping").unwrap();
+        }
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        /* No batch after the one that unregistered. */
+        assert_eq!(received.batches.len(), 1);
+        assert_eq!(ae_get_file_events(&event_loop, receiver.as_raw_fd()), 0);
+        assert_eq!(
+            ae_udp_unregister(&mut event_loop, receiver.as_raw_fd()),
+            AE_ERR
+        );
+        /* The fd is still the caller's. */
+        let mut buf = [0u8; 16];
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 4);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_invalid_sizes() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (receiver, _sender) = sockets();
+        let fd = receiver.as_raw_fd();
+        let data = std::ptr::null_mut();
+        assert_eq!(
+            ae_udp_register(&mut event_loop, fd, 0, 1500, record, data),
+            AE_ERR
+        );
+        assert_eq!(
+            ae_udp_register(
+                &mut event_loop,
+                fd,
+                AE_UDP_BATCH_MAX + 1,
+                1500,
+                record,
+                data
+            ),
+            AE_ERR
+        );
+        assert_eq!(
+            ae_udp_register(&mut event_loop, fd, 8, 0, record, data),
+            AE_ERR
+        );
+        assert_eq!(ae_get_file_events(&event_loop, fd), 0);
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+mod send {
+    use super::*;
+
+    #[test]
+    fn test_send_batch() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (to_receiver, to_other) = (receiver.local_addr().ok(), other.local_addr().ok());
+        let datagrams: [(&[u8], Option<SocketAddr>); 3] = [
+            (b"one", to_receiver),
+            (b"two", to_other),
+            (b"three", to_receiver),
+        ];
+        assert_eq!(anet_udp_send_batch(sender.as_raw_fd(), &datagrams), Ok(3));
+
+        let mut buf = [0u8; 16];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"one");
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"three");
+        let n = other.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"two");
+        assert_eq!(anet_udp_send_batch(sender.as_raw_fd(), &[]), Ok(0));
+    }
+
+    #[test]
+    fn test_send_segments() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (receiver, sender) = sockets();
+        let mut received = Received::default();
+        register(&mut event_loop, &receiver, 16, 1500, &mut received);
+
+        let data: Vec<u8> = (0..1000u32).map(|n| n as u8).collect();
+        assert_eq!(
+            anet_udp_send_segments(sender.as_raw_fd(), &data, 100, None),
+            Ok(1000)
+        );
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        let datagrams = received.batches.concat();
+        assert_eq!(datagrams.len(), 10);
+        for (n, (segment, ..)) in datagrams.iter().enumerate() {
+            assert_eq!(segment[..], data[n * 100..(n + 1) * 100]);
+        }
+
+        assert_eq!(
+            anet_udp_send_segments(sender.as_raw_fd(), &data, 0, None),
+            Err(libc::EINVAL)
+        );
+        ae_udp_unregister(&mut event_loop, receiver.as_raw_fd());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_gro_merges_segments() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let (receiver, sender) = sockets();
+        if rae::anet_set_udp_gro(receiver.as_raw_fd(), true) != AE_OK {
+            return;
+        }
+        let mut received = Received::default();
+        register(&mut event_loop, &receiver, 16, 65535, &mut received);
+
+        let data: Vec<u8> = (0..1000u32).map(|n| n as u8).collect();
+        assert_eq!(
+            anet_udp_send_segments(sender.as_raw_fd(), &data, 100, None),
+            Ok(1000)
+        );
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        /* Merged or not, splitting at the segment size gives the segments
+         * back. */
+        let mut segments = Vec::new();
+        for (buf, _, segment_size, _) in received.batches.concat() {
+            match segment_size {
+                0 => segments.push(buf),
+                size => segments.extend(buf.chunks(size).map(<[u8]>::to_vec)),
+            }
+        }
+        assert_eq!(segments.concat(), data);
+        assert!(segments.iter().all(|segment| segment.len() == 100));
+
+        ae_udp_unregister(&mut event_loop, receiver.as_raw_fd());
+        ae_delete_event_loop(event_loop);
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod raw_sockets {
+    use super::*;
+    use rae::{
+        AnetBpfInsn, anet_attach_filter, anet_detach_filter, anet_packet_socket, anet_raw_socket,
+    };
+
+    const MARKER: &[u8] = b"rae raw socket marker";
+
+    /* Accept packets of `len` bytes, drop the others. */
+    fn length_filter(len: u32) -> [AnetBpfInsn; 4] {
+        let insn = |code, jt, jf, k| AnetBpfInsn { code, jt, jf, k };
+        [
+            /* ld len; jeq #len, 0, 1; ret #0xffff; ret #0 */
+            insn(0x80, 0, 0, 0),
+            insn(0x15, 0, 1, len),
+            insn(0x06, 0, 0, 0xffff),
+            insn(0x06, 0, 0, 0),
+        ]
+    }
+
+    /* Without CAP_NET_RAW there is nothing to test. */
+    fn raw_socket(family: i32, protocol: i32) -> Option<i32> {
+        match anet_raw_socket(family, protocol) {
+            Ok(fd) => Some(fd),
+            Err(libc::EPERM) | Err(libc::EACCES) => None,
+            Err(err) => panic!("raw socket failed: {err}"),
+        }
+    }
+
+    fn register_fd(event_loop: &mut AeEventLoop, fd: i32, received: &mut Received) {
+        let result = ae_udp_register(
+            event_loop,
+            fd,
+            64,
+            65535,
+            record,
+            received as *mut Received as *mut c_void,
+        );
+        assert_eq!(result, AE_OK);
+    }
+
+    #[test]
+    fn test_filter_drops_in_the_kernel() {
+        let Some(fd) = raw_socket(libc::AF_INET, libc::IPPROTO_UDP) else {
+            return;
+        };
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        /* IPv4 header, UDP header, marker. */
+        let wanted = 20 + 8 + MARKER.len();
+        assert_eq!(anet_attach_filter(fd, &length_filter(wanted as u32)), AE_OK);
+        let mut received = Received::default();
+        register_fd(&mut event_loop, fd, &mut received);
+
+        let (_receiver, sender) = sockets();
+        sender.send(b"filtered out").unwrap();
+        sender.send(MARKER).unwrap();
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        let datagrams = received.batches.concat();
+        assert!(datagrams.iter().all(|(data, ..)| data.len() == wanted));
+        assert!(datagrams.iter().any(|(data, ..)| data.ends_with(MARKER)));
+
+        assert_eq!(anet_detach_filter(fd), AE_OK);
+        assert_eq!(anet_detach_filter(fd), AE_ERR);
+        ae_udp_unregister(&mut event_loop, fd);
+        unsafe { libc::close(fd) };
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_packet_socket_on_loopback() {
+        let lo = unsafe { libc::if_nametoindex(c"lo".as_ptr()) };
+        let fd = match anet_packet_socket(libc::ETH_P_IP as u16, lo) {
+            Ok(fd) => fd,
+            Err(libc::EPERM) | Err(libc::EACCES) => return,
+            Err(err) => panic!("packet socket failed: {err}"),
+        };
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        /* Ethernet header, IPv4 header, UDP header, marker. */
+        let wanted = 14 + 20 + 8 + MARKER.len();
+        assert_eq!(anet_attach_filter(fd, &length_filter(wanted as u32)), AE_OK);
+        let mut received = Received::default();
+        register_fd(&mut event_loop, fd, &mut received);
+
+        let (_receiver, sender) = sockets();
+        sender.send(MARKER).unwrap();
+        ae_process_events(&mut event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+        let datagrams = received.batches.concat();
+        let (data, addr, ..) = datagrams
+            .iter()
+            .find(|(data, ..)| data.ends_with(MARKER))
+            .expect("Frame not received");
+        assert_eq!(data.len(), wanted);
+        assert_eq!(*addr, None);
+
+        ae_udp_unregister(&mut event_loop, fd);
+        unsafe { libc::close(fd) };
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_invalid_filter() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(anet_attach_filter(socket.as_raw_fd(), &[]), AE_ERR);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+        /* Jumps past the end. */
+        let bad = [AnetBpfInsn {
+            code: 0x15,
+            jt: 5,
+            jf: 5,
+            k: 0,
+        }];
+        assert_eq!(anet_attach_filter(socket.as_raw_fd(), &bad), AE_ERR);
+        assert_eq!(anet_detach_filter(socket.as_raw_fd()), AE_ERR);
+    }
+}
+
+mod error_queue {
+    use super::*;
+    use rae::{AE_ERRQUEUE, ae_create_file_event};
+    #[cfg(target_os = "linux")]
+    use rae::{
+        AE_READABLE, AeEventLoopBuilder, ae_delete_file_event, ae_get_file_client_data,
+        ae_resize_set_size_compact,
+    };
+    #[cfg(target_os = "linux")]
+    use std::cell::Cell;
+
+    /* Calls of each handler, read and error queue. */
+    #[derive(Default)]
+    struct Calls {
+        reads: usize,
+        #[cfg(target_os = "linux")]
+        errors: Vec<i32>,
+    }
+
+    fn on_read(_event_loop: &mut AeEventLoop, _fd: i32, data: *mut c_void, _mask: i32) {
+        unsafe { (*(data as *mut Calls)).reads += 1 };
+    }
+
+    /* Drain the error queue, recording the errno of each entry. */
+    #[cfg(target_os = "linux")]
+    fn on_error(_event_loop: &mut AeEventLoop, fd: i32, data: *mut c_void, mask: i32) {
+        assert_eq!(mask, AE_ERRQUEUE);
+        assert!(!data.is_null());
+        let calls = unsafe { &mut *(data as *mut Calls) };
+        loop {
+            let mut buf = [0u8; 64];
+            let mut control = [0u64; 64];
+            let mut iov = libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut c_void,
+                iov_len: buf.len(),
+            };
+            let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr() as *mut c_void;
+            msg.msg_controllen = std::mem::size_of_val(&control) as _;
+            if unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_ERRQUEUE) } == -1 {
+                return;
+            }
+            let cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+            assert!(!cmsg.is_null());
+            let err = unsafe { &*(libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err) };
+            calls.errors.push(err.ee_errno as i32);
+        }
+    }
+
+    /* A socket with IP_RECVERR connected to a port nobody listens on. */
+    #[cfg(target_os = "linux")]
+    fn refused_socket() -> UdpSocket {
+        let closed = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = closed.local_addr().unwrap();
+        drop(closed);
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_nonblocking(true).unwrap();
+        socket.connect(target).unwrap();
+        let fd = socket.as_raw_fd();
+        let on: libc::c_int = 1;
+        let set = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::IPPROTO_IP,
+                libc::IP_RECVERR,
+                &on as *const libc::c_int as *const c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(set, 0);
+        socket
+    }
+
+    /* Run the loop until the error queue handler was called. */
+    #[cfg(target_os = "linux")]
+    fn wait_for_error(event_loop: &mut AeEventLoop, calls: &Calls) {
+        for _ in 0..100 {
+            ae_process_events(event_loop, AE_FILE_EVENTS | AE_DONT_WAIT);
+            if !calls.errors.is_empty() {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn epoll_loop() -> Option<Box<AeEventLoop>> {
+        let event_loop = AeEventLoopBuilder::new(1024)
+            .best_backend()
+            .build()
+            .expect("Failed to create event loop");
+        (event_loop.api_name() == "epoll").then_some(event_loop)
+    }
+
+    /* An ICMP port unreachable lands in the error queue of a socket with
+     * IP_RECVERR, and only the error queue handler hears about it. */
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_icmp_error_reaches_its_handler() {
+        let Some(mut event_loop) = epoll_loop() else {
+            return;
+        };
+        let socket = refused_socket();
+        let fd = socket.as_raw_fd();
+        let mut calls = Calls::default();
+        let data = &mut calls as *mut Calls as *mut c_void;
+        assert_eq!(
+            ae_create_file_event(&mut event_loop, fd, AE_READABLE, on_read, data),
+            AE_OK
+        );
+        assert_eq!(
+            ae_create_file_event(&mut event_loop, fd, AE_ERRQUEUE, on_error, data),
+            AE_OK
+        );
+        assert_eq!(
+            ae_get_file_events(&event_loop, fd),
+            AE_READABLE | AE_ERRQUEUE
+        );
+
+        socket.send(b"anyone?").unwrap();
+        wait_for_error(&mut event_loop, &calls);
+        assert_eq!(calls.errors, vec![libc::ECONNREFUSED]);
+        assert_eq!(calls.reads, 0);
+
+        ae_delete_file_event(&mut event_loop, fd, AE_ERRQUEUE);
+        assert_eq!(ae_get_file_events(&event_loop, fd), AE_READABLE);
+        ae_delete_file_event(&mut event_loop, fd, AE_READABLE);
+    }
+
+    #[cfg(target_os = "linux")]
+    thread_local! {
+        static MOVED_TO: Cell<i32> = const { Cell::new(-1) };
+    }
+
+    #[cfg(target_os = "linux")]
+    fn record_move(_event_loop: &mut AeEventLoop, _old_fd: i32, new_fd: i32, _data: *mut c_void) {
+        MOVED_TO.with(|moved| moved.set(new_fd));
+    }
+
+    /* An fd with only an error queue handler keeps its data when it is
+     * moved down by a compacting resize. */
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_error_queue_only_fd_is_relocated() {
+        let Some(mut event_loop) = epoll_loop() else {
+            return;
+        };
+        let socket = refused_socket();
+        let fd = unsafe { libc::fcntl(socket.as_raw_fd(), libc::F_DUPFD, 900) };
+        assert!(fd >= 900);
+        drop(socket);
+        let mut calls = Calls::default();
+        let data = &mut calls as *mut Calls as *mut c_void;
+        assert_eq!(
+            ae_create_file_event(&mut event_loop, fd, AE_ERRQUEUE, on_error, data),
+            AE_OK
+        );
+
+        assert_eq!(
+            ae_resize_set_size_compact(&mut event_loop, 256, record_move),
+            AE_OK
+        );
+        let new_fd = MOVED_TO.with(Cell::get);
+        assert!((0..256).contains(&new_fd));
+        assert_eq!(ae_get_file_events(&event_loop, new_fd), AE_ERRQUEUE);
+        assert_eq!(ae_get_file_client_data(&event_loop, new_fd), data);
+
+        let sent = unsafe { libc::send(new_fd, b"anyone?".as_ptr() as *const c_void, 7, 0) };
+        assert_eq!(sent, 7);
+        wait_for_error(&mut event_loop, &calls);
+        assert_eq!(calls.errors, vec![libc::ECONNREFUSED]);
+
+        ae_delete_file_event(&mut event_loop, new_fd, AE_ERRQUEUE);
+        unsafe { libc::close(new_fd) };
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_refused_without_backend_support() {
+        let mut event_loop = ae_create_event_loop(64).expect("Failed to create event loop");
+        assert_eq!(event_loop.api_name(), "select");
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut calls = Calls::default();
+        let data = &mut calls as *mut Calls as *mut c_void;
+        assert_eq!(
+            ae_create_file_event(
+                &mut event_loop,
+                socket.as_raw_fd(),
+                AE_ERRQUEUE,
+                on_read,
+                data
+            ),
+            AE_ERR
+        );
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ENOTSUP)
+        );
+        assert_eq!(ae_get_file_events(&event_loop, socket.as_raw_fd()), 0);
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -0,0 +1,163 @@
+/* Wall-clock Timer Tests
+ *
+ * Tests for timers firing at absolute system times (ae/wallclock.rs):
+ * scheduling, rescheduling from the callback, deletion and finalizers.
+ * Clock changes themselves need privileges and are not exercised here.
+ */
+
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_ERR, AE_NOMORE, AE_OK, AeEventLoop, ae_create_event_loop,
+    ae_create_wallclock_event, ae_delete_event_loop, ae_delete_wallclock_event, ae_process_events,
+};
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, SystemTime};
+
+fn fire_once(_event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    let counter = unsafe { &*(client_data as *const AtomicI32) };
+    counter.fetch_add(1, Ordering::SeqCst);
+    AE_NOMORE
+}
+
+fn fire_three_times(_event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    let counter = unsafe { &*(client_data as *const AtomicI32) };
+    if counter.fetch_add(1, Ordering::SeqCst) + 1 == 3 {
+        AE_NOMORE
+    } else {
+        5
+    }
+}
+
+fn delete_self(event_loop: &mut AeEventLoop, id: i64, client_data: *mut c_void) -> i32 {
+    let counter = unsafe { &*(client_data as *const AtomicI32) };
+    counter.fetch_add(1, Ordering::SeqCst);
+    assert_eq!(ae_delete_wallclock_event(event_loop, id), AE_OK);
+    10
+}
+
+fn count_finalizer(_event_loop: &mut AeEventLoop, client_data: *mut c_void) {
+    let counter = unsafe { &*(client_data as *const AtomicI32) };
+    counter.fetch_add(100, Ordering::SeqCst);
+}
+
+fn ptr(counter: &AtomicI32) -> *mut c_void {
+    counter as *const AtomicI32 as *mut c_void
+}
+
+fn run_for(event_loop: &mut AeEventLoop, duration: Duration) {
+    let deadline = std::time::Instant::now() + duration;
+    while std::time::Instant::now() < deadline {
+        ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+mod scheduling {
+    use super::*;
+
+    #[test]
+    fn test_fires_at_wall_clock_time() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let counter = AtomicI32::new(0);
+
+        let when = SystemTime::now() + Duration::from_millis(30);
+        let id = ae_create_wallclock_event(&mut event_loop, when, fire_once, ptr(&counter), None);
+        assert!(id > 0);
+
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        assert_eq!(counter.load(Ordering::SeqCst), 0, "Fired early");
+
+        run_for(&mut event_loop, Duration::from_millis(80));
+        assert!(SystemTime::now() >= when);
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            ae_delete_wallclock_event(&mut event_loop, id),
+            AE_ERR,
+            "AE_NOMORE removes the timer"
+        );
+    }
+
+    #[test]
+    fn test_past_time_fires_right_away() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let counter = AtomicI32::new(0);
+
+        let when = SystemTime::now() - Duration::from_secs(3600);
+        ae_create_wallclock_event(&mut event_loop, when, fire_once, ptr(&counter), None);
+        run_for(&mut event_loop, Duration::from_millis(10));
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_reschedule_from_callback() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let counter = AtomicI32::new(0);
+
+        ae_create_wallclock_event(
+            &mut event_loop,
+            SystemTime::now(),
+            fire_three_times,
+            ptr(&counter),
+            Some(count_finalizer),
+        );
+        run_for(&mut event_loop, Duration::from_millis(100));
+        assert_eq!(counter.load(Ordering::SeqCst), 103);
+    }
+}
+
+mod deletion {
+    use super::*;
+
+    #[test]
+    fn test_delete_before_firing() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let counter = AtomicI32::new(0);
+
+        let when = SystemTime::now() + Duration::from_millis(10);
+        let id = ae_create_wallclock_event(
+            &mut event_loop,
+            when,
+            fire_once,
+            ptr(&counter),
+            Some(count_finalizer),
+        );
+        assert_eq!(ae_delete_wallclock_event(&mut event_loop, id), AE_OK);
+        assert_eq!(counter.load(Ordering::SeqCst), 100, "Finalizer ran");
+
+        run_for(&mut event_loop, Duration::from_millis(30));
+        assert_eq!(counter.load(Ordering::SeqCst), 100, "Deleted timer fired");
+    }
+
+    #[test]
+    fn test_delete_from_own_callback() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let counter = AtomicI32::new(0);
+
+        ae_create_wallclock_event(
+            &mut event_loop,
+            SystemTime::now(),
+            delete_self,
+            ptr(&counter),
+            Some(count_finalizer),
+        );
+        run_for(&mut event_loop, Duration::from_millis(40));
+        assert_eq!(counter.load(Ordering::SeqCst), 101);
+    }
+
+    #[test]
+    fn test_loop_drop_runs_finalizers() {
+        let mut event_loop = ae_create_event_loop(1024).expect("Failed to create event loop");
+        let counter = AtomicI32::new(0);
+
+        let when = SystemTime::now() + Duration::from_secs(3600);
+        ae_create_wallclock_event(
+            &mut event_loop,
+            when,
+            fire_once,
+            ptr(&counter),
+            Some(count_finalizer),
+        );
+        ae_delete_event_loop(event_loop);
+        assert_eq!(counter.load(Ordering::SeqCst), 100);
+    }
+}
@@ -0,0 +1,240 @@
+/* Write Stall Watchdog Tests
+ *
+ * Tests for ae_set_write_stall_watchdog() (ae/watchdog.rs): fds keeping
+ * AE_WRITABLE registered without progress on the manual clock, and a
+ * connection whose peer stops reading.
+ */
+
+use rae::test_util::{consume_all, virtual_loop};
+use rae::{
+    AE_ALL_EVENTS, AE_DONT_WAIT, AE_ERR, AE_OK, AE_READABLE, AE_WRITABLE, AeClockSource,
+    AeEventLoop, AeEventLoopBuilder, AeFinding, AeFindingKind, ae_advance_clock, ae_conn_close,
+    ae_conn_create, ae_conn_pending_output, ae_conn_stats, ae_conn_write, ae_create_file_event,
+    ae_delete_event_loop, ae_delete_file_event, ae_doctor, ae_note_write_progress,
+    ae_process_events, ae_set_diagnostic_proc, ae_set_write_stall_watchdog,
+    ae_set_write_stall_watchdog_duration, ae_write_stall_us,
+};
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+thread_local! {
+    static RAISED: RefCell<Vec<AeFinding>> = const { RefCell::new(Vec::new()) };
+    static STALLED: RefCell<Vec<(i32, u64)>> = const { RefCell::new(Vec::new()) };
+}
+
+fn collect(_event_loop: &mut AeEventLoop, finding: &AeFinding) {
+    RAISED.with(|raised| raised.borrow_mut().push(finding.clone()));
+}
+
+fn raised() -> Vec<AeFinding> {
+    RAISED.with(|raised| raised.borrow_mut().drain(..).collect())
+}
+
+fn on_stall(_event_loop: &mut AeEventLoop, fd: i32, stalled_us: u64) {
+    STALLED.with(|stalled| stalled.borrow_mut().push((fd, stalled_us)));
+}
+
+fn close_stalled(event_loop: &mut AeEventLoop, fd: i32, stalled_us: u64) {
+    on_stall(event_loop, fd, stalled_us);
+    ae_conn_close(event_loop, fd);
+}
+
+fn stalled() -> Vec<(i32, u64)> {
+    STALLED.with(|stalled| stalled.borrow_mut().drain(..).collect())
+}
+
+fn noop_file(_event_loop: &mut AeEventLoop, _fd: i32, _client_data: *mut c_void, _mask: i32) {}
+
+/* Move the manual clock and run the timers due. */
+fn advance(event_loop: &mut AeEventLoop, us: u64) {
+    ae_advance_clock(event_loop, us);
+    ae_process_events(event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+}
+
+/* Mock loop with fd 5 waiting to be writable and a 100ms watchdog. */
+fn watched_loop() -> Box<AeEventLoop> {
+    let (mut event_loop, _control) = virtual_loop(64);
+    ae_set_diagnostic_proc(&mut event_loop, Some(collect));
+    assert_eq!(
+        ae_set_write_stall_watchdog(&mut event_loop, 100, Some(on_stall)),
+        AE_OK
+    );
+    ae_create_file_event(
+        &mut event_loop,
+        5,
+        AE_WRITABLE,
+        noop_file,
+        std::ptr::null_mut(),
+    );
+    raised();
+    stalled();
+    event_loop
+}
+
+mod watchdog {
+    use super::*;
+
+    #[test]
+    fn test_stall_is_flagged_once() {
+        let mut event_loop = watched_loop();
+        advance(&mut event_loop, 50_000);
+        assert!(stalled().is_empty());
+
+        advance(&mut event_loop, 50_000);
+        assert_eq!(stalled(), vec![(5, 100_000)]);
+        let findings = raised();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].kind,
+            AeFindingKind::WriteStall {
+                fd: 5,
+                stalled_us: 100_000,
+                pending: 0
+            }
+        );
+
+        /* Flagged once, listed by the doctor until it makes progress. */
+        advance(&mut event_loop, 200_000);
+        assert!(stalled().is_empty());
+        assert!(raised().is_empty());
+        assert_eq!(ae_write_stall_us(&event_loop, 5), Some(300_000));
+        assert!(
+            ae_doctor(&event_loop)
+                .iter()
+                .any(|f| matches!(f.kind, AeFindingKind::WriteStall { fd: 5, .. }))
+        );
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_progress_resets_the_stall() {
+        let mut event_loop = watched_loop();
+        advance(&mut event_loop, 80_000);
+        ae_note_write_progress(&mut event_loop, 5);
+        advance(&mut event_loop, 80_000);
+        assert!(stalled().is_empty());
+        assert_eq!(ae_write_stall_us(&event_loop, 5), Some(80_000));
+
+        /* Checked every 50ms, the next check is at 210ms. */
+        advance(&mut event_loop, 40_000);
+        assert!(stalled().is_empty());
+        advance(&mut event_loop, 10_000);
+        assert_eq!(stalled(), vec![(5, 130_000)]);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_deleting_writable_disarms() {
+        let mut event_loop = watched_loop();
+        /* Other directions do not count. */
+        ae_create_file_event(
+            &mut event_loop,
+            5,
+            AE_READABLE,
+            noop_file,
+            std::ptr::null_mut(),
+        );
+        ae_delete_file_event(&mut event_loop, 5, AE_READABLE);
+        assert_eq!(ae_write_stall_us(&event_loop, 5), Some(0));
+
+        ae_delete_file_event(&mut event_loop, 5, AE_WRITABLE);
+        assert_eq!(ae_write_stall_us(&event_loop, 5), None);
+        advance(&mut event_loop, 500_000);
+        assert!(stalled().is_empty());
+        assert!(ae_doctor(&event_loop).is_empty());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_enable_and_disable() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        ae_create_file_event(
+            &mut event_loop,
+            7,
+            AE_WRITABLE,
+            noop_file,
+            std::ptr::null_mut(),
+        );
+        assert_eq!(
+            ae_set_write_stall_watchdog(&mut event_loop, -1, None),
+            AE_ERR
+        );
+        assert_eq!(ae_write_stall_us(&event_loop, 7), None);
+
+        /* Watched from the moment the watchdog is enabled. */
+        ae_advance_clock(&mut event_loop, 1_000_000);
+        ae_process_events(&mut event_loop, AE_ALL_EVENTS | AE_DONT_WAIT);
+        ae_set_write_stall_watchdog(&mut event_loop, 100, Some(on_stall));
+        assert_eq!(ae_write_stall_us(&event_loop, 7), Some(0));
+        advance(&mut event_loop, 100_000);
+        assert_eq!(stalled(), vec![(7, 100_000)]);
+
+        ae_set_write_stall_watchdog(&mut event_loop, 0, None);
+        assert_eq!(ae_write_stall_us(&event_loop, 7), None);
+        advance(&mut event_loop, 500_000);
+        assert!(stalled().is_empty());
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_sub_millisecond_threshold() {
+        let (mut event_loop, _control) = virtual_loop(64);
+        ae_create_file_event(
+            &mut event_loop,
+            7,
+            AE_WRITABLE,
+            noop_file,
+            std::ptr::null_mut(),
+        );
+        assert_eq!(
+            ae_set_write_stall_watchdog_duration(
+                &mut event_loop,
+                Duration::from_micros(1500),
+                Some(on_stall)
+            ),
+            AE_OK
+        );
+        stalled();
+        /* Checked every millisecond at least. */
+        advance(&mut event_loop, 1000);
+        assert!(stalled().is_empty());
+        advance(&mut event_loop, 1000);
+        assert_eq!(stalled(), vec![(7, 2000)]);
+
+        ae_set_write_stall_watchdog_duration(&mut event_loop, Duration::ZERO, None);
+        assert_eq!(ae_write_stall_us(&event_loop, 7), None);
+        ae_delete_event_loop(event_loop);
+    }
+
+    #[test]
+    fn test_peer_stops_reading() {
+        let mut event_loop = AeEventLoopBuilder::new(1024)
+            .clock_source(AeClockSource::Manual)
+            .build()
+            .expect("Failed to create event loop");
+        let (ours, theirs) = UnixStream::pair().expect("Failed to create socket pair");
+        ours.set_nonblocking(true).unwrap();
+        let fd = ours.into_raw_fd();
+        assert_eq!(
+            ae_conn_create(&mut event_loop, fd, consume_all, None, std::ptr::null_mut()),
+            AE_OK
+        );
+        ae_set_write_stall_watchdog(&mut event_loop, 100, Some(close_stalled));
+
+        /* More than the socket buffers hold, the peer never reads. */
+        ae_conn_write(&mut event_loop, fd, &vec![0u8; 4 << 20]);
+        let pending = ae_conn_pending_output(&event_loop, fd);
+        assert!(pending > 0);
+        advance(&mut event_loop, 50_000);
+        assert!(stalled().is_empty());
+
+        advance(&mut event_loop, 50_000);
+        assert_eq!(stalled(), vec![(fd, 100_000)]);
+        assert!(ae_conn_stats(&event_loop, fd).is_none());
+        drop(theirs);
+        ae_delete_event_loop(event_loop);
+    }
+}
@@ -0,0 +1,325 @@
+/* Anet Helper Tests
+ *
+ * Tests for the socket and I/O helpers in anet.rs, exercised over real
+ * Unix socket pairs.
+ */
+
+use rae::{BufChain, anet_readv, anet_writev};
+use std::io::{IoSlice, IoSliceMut, Read};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+mod scatter_gather {
+    use super::*;
+
+    #[test]
+    fn test_writev_header_and_body() {
+        let (a, mut b) = UnixStream::pair().expect("Failed to create socket pair");
+
+        let header = b"$5\r\n";
+        let body = b"hello\r\n";
+        let written = anet_writev(a.as_raw_fd(), &[IoSlice::new(header), IoSlice::new(body)])
+            .expect("writev should succeed");
+        assert_eq!(written, header.len() + body.len());
+
+        let mut out = vec![0u8; written];
+        b.read_exact(&mut out).unwrap();
+        assert_eq!(&out, b"$5\r\nhello\r\n");
+    }
+
+    #[test]
+    fn test_readv_fills_slices_in_order() {
+        let (a, b) = UnixStream::pair().expect("Failed to create socket pair");
+        anet_writev(a.as_raw_fd(), &[IoSlice::new(b"abcdefgh")]).unwrap();
+
+        let mut first = [0u8; 3];
+        let mut second = [0u8; 8];
+        let n = anet_readv(
+            b.as_raw_fd(),
+            &mut [IoSliceMut::new(&mut first), IoSliceMut::new(&mut second)],
+        )
+        .expect("readv should succeed");
+
+        assert_eq!(n, 8);
+        assert_eq!(&first, b"abc");
+        assert_eq!(&second[..5], b"defgh");
+    }
+
+    #[test]
+    fn test_readv_would_block() {
+        let (_a, b) = UnixStream::pair().expect("Failed to create socket pair");
+        b.set_nonblocking(true).unwrap();
+
+        let mut buf = [0u8; 4];
+        let result = anet_readv(b.as_raw_fd(), &mut [IoSliceMut::new(&mut buf)]);
+        assert_eq!(result, Err(libc::EAGAIN));
+    }
+}
+
+mod buf_chain {
+    use super::*;
+
+    #[test]
+    fn test_push_and_len() {
+        let mut chain = BufChain::new();
+        assert!(chain.is_empty());
+
+        chain.push(b"abc".to_vec());
+        chain.push(Vec::new());
+        chain.push_slice(b"de");
+
+        assert_eq!(chain.len(), 5);
+        assert_eq!(chain.buffers(), 2, "Empty buffers should not be queued");
+    }
+
+    #[test]
+    fn test_consume_partial_buffers() {
+        let mut chain = BufChain::new();
+        chain.push(b"abc".to_vec());
+        chain.push(b"defg".to_vec());
+
+        chain.consume(2);
+        assert_eq!(chain.len(), 5);
+        assert_eq!(chain.buffers(), 2);
+
+        let mut slices = Vec::new();
+        chain.io_slices(&mut slices);
+        assert_eq!(&*slices[0], b"c");
+        assert_eq!(&*slices[1], b"defg");
+        drop(slices);
+
+        chain.consume(3);
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain.buffers(), 1);
+
+        chain.consume(100);
+        assert!(chain.is_empty());
+        assert_eq!(chain.buffers(), 0);
+    }
+
+    #[test]
+    fn test_write_to_socket() {
+        let (a, mut b) = UnixStream::pair().expect("Failed to create socket pair");
+
+        let mut chain = BufChain::new();
+        chain.push(b"*1\r\n".to_vec());
+        chain.push(b"$4\r\nPING\r\n".to_vec());
+
+        let written = chain.write_to(a.as_raw_fd()).expect("write should succeed");
+        assert_eq!(written, 14);
+        assert!(chain.is_empty());
+
+        let mut out = vec![0u8; written];
+        b.read_exact(&mut out).unwrap();
+        assert_eq!(&out, b"*1\r\n$4\r\nPING\r\n");
+
+        assert_eq!(chain.write_to(a.as_raw_fd()), Ok(0));
+    }
+}
+
+mod rx_timestamps {
+    use super::*;
+    use rae::AE_OK;
+    use rae::anet::{anet_enable_rx_timestamps, anet_recv_timestamped};
+    use std::net::UdpSocket;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_udp_receive_timestamp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(anet_enable_rx_timestamps(receiver.as_raw_fd()), AE_OK);
+
+        let before = SystemTime::now();
+        sender
+            .send_to(b"ping", receiver.local_addr().unwrap())
+            .unwrap();
+
+        let mut buf = [0u8; 16];
+        let (n, timestamp) =
+            anet_recv_timestamped(receiver.as_raw_fd(), &mut buf).expect("recv should succeed");
+        assert_eq!(&buf[..n], b"ping");
+
+        let timestamp = timestamp.expect("Kernel timestamp expected");
+        let slack = Duration::from_millis(100);
+        assert!(timestamp + slack >= before);
+        assert!(timestamp <= SystemTime::now() + slack);
+    }
+
+    #[test]
+    fn test_no_timestamp_when_disabled() {
+        let (a, b) = UnixStream::pair().expect("Failed to create socket pair");
+        std::io::Write::write_all(&mut &a, b"data").unwrap();
+
+        let mut buf = [0u8; 16];
+        let (n, timestamp) = anet_recv_timestamped(b.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(&buf[..n], b"data");
+        assert_eq!(timestamp, None);
+    }
+
+    #[test]
+    fn test_recv_error() {
+        let mut buf = [0u8; 16];
+        assert_eq!(anet_recv_timestamped(-1, &mut buf), Err(libc::EBADF));
+    }
+}
+
+mod listen_options {
+    use rae::anet::anet_accept;
+    use rae::{
+        AnetListenOptions, anet_local_addr, anet_set_accept_filter, anet_syn_cookies,
+        anet_tcp_listen,
+    };
+    #[cfg(target_os = "linux")]
+    use std::io::Write;
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::{Duration, Instant};
+
+    fn localhost() -> SocketAddr {
+        "127.0.0.1:0".parse().unwrap()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn tcp_sockopt(fd: i32, name: i32) -> i32 {
+        let mut value: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let retval = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::IPPROTO_TCP,
+                name,
+                &mut value as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(retval, 0, "getsockopt failed");
+        value
+    }
+
+    #[test]
+    fn test_plain_listener() {
+        let fd = anet_tcp_listen(&localhost(), &AnetListenOptions::default()).unwrap();
+        let addr = anet_local_addr(fd).unwrap();
+        let _client = TcpStream::connect(addr).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let accepted = loop {
+            match anet_accept(fd) {
+                Err(libc::EAGAIN) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(1))
+                }
+                result => break result,
+            }
+        };
+        let (cfd, _) = accepted.expect("accept should succeed");
+        unsafe {
+            libc::close(cfd);
+            libc::close(fd);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_fastopen_queue() {
+        let options = AnetListenOptions {
+            fastopen_queue: 16,
+            ..Default::default()
+        };
+        let fd = anet_tcp_listen(&localhost(), &options).expect("Failed to listen");
+        assert_eq!(tcp_sockopt(fd, libc::TCP_FASTOPEN), 16);
+        unsafe { libc::close(fd) };
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_deferred_accept_waits_for_data() {
+        let options = AnetListenOptions {
+            defer_accept_secs: 30,
+            ..Default::default()
+        };
+        let fd = anet_tcp_listen(&localhost(), &options).expect("Failed to listen");
+        assert!(tcp_sockopt(fd, libc::TCP_DEFER_ACCEPT) > 0);
+
+        /* Connected as far as the client knows, not accepted yet. */
+        let mut client = TcpStream::connect(anet_local_addr(fd).unwrap()).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(anet_accept(fd).map(|(cfd, _)| cfd), Err(libc::EAGAIN));
+
+        /* The request arrives with the connection. */
+        client.write_all(b"PING\r\n").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let (cfd, _) = loop {
+            match anet_accept(fd) {
+                Err(libc::EAGAIN) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(1))
+                }
+                result => break result.expect("accept should succeed"),
+            }
+        };
+        let mut buf = [0u8; 16];
+        let n = unsafe { libc::read(cfd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        assert_eq!(&buf[..n as usize], b"PING\r\n");
+        unsafe {
+            libc::close(cfd);
+            libc::close(fd);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_synack_retries() {
+        let options = AnetListenOptions {
+            synack_retries: 2,
+            ..Default::default()
+        };
+        let fd = anet_tcp_listen(&localhost(), &options).expect("Failed to listen");
+        assert_eq!(tcp_sockopt(fd, libc::TCP_SYNCNT), 2);
+        unsafe { libc::close(fd) };
+
+        /* Out of the range TCP_SYNCNT takes. */
+        let options = AnetListenOptions {
+            synack_retries: 1000,
+            ..Default::default()
+        };
+        assert_eq!(anet_tcp_listen(&localhost(), &options), Err(libc::EINVAL));
+    }
+
+    #[test]
+    fn test_syn_cookies_mode() {
+        match anet_syn_cookies() {
+            Some(mode) => assert!((0..=2).contains(&mode), "mode {}", mode),
+            None => assert!(
+                !cfg!(target_os = "linux")
+                    || !std::path::Path::new("/proc/sys/net/ipv4/tcp_syncookies").exists()
+            ),
+        }
+    }
+
+    #[cfg(not(any(target_os = "freebsd", target_os = "netbsd")))]
+    #[test]
+    fn test_accept_filter_unsupported() {
+        let options = AnetListenOptions {
+            accept_filter: Some("dataready"),
+            ..Default::default()
+        };
+        assert_eq!(
+            anet_tcp_listen(&localhost(), &options),
+            Err(libc::ENOPROTOOPT)
+        );
+        let fd = anet_tcp_listen(&localhost(), &AnetListenOptions::default()).unwrap();
+        assert_eq!(anet_set_accept_filter(fd, "dataready", ""), rae::AE_ERR);
+        unsafe { libc::close(fd) };
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    #[test]
+    fn test_deferred_accept_unsupported() {
+        let options = AnetListenOptions {
+            defer_accept_secs: 30,
+            ..Default::default()
+        };
+        assert_eq!(
+            anet_tcp_listen(&localhost(), &options),
+            Err(libc::ENOPROTOOPT)
+        );
+    }
+}
@@ -0,0 +1,4 @@
+/* anet.h shim: the select backend does not use anet. */
+#ifndef RAE_SHIM_ANET_H
+#define RAE_SHIM_ANET_H
+#endif
@@ -0,0 +1,5 @@
+/* config.h shim: no HAVE_EPOLL / HAVE_KQUEUE / HAVE_EVPORT, so ae.c falls
+ * back to ae_select.c on every platform, like the rae select backend. */
+#ifndef RAE_SHIM_CONFIG_H
+#define RAE_SHIM_CONFIG_H
+#endif
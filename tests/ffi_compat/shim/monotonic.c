@@ -0,0 +1,10 @@
+/* getMonotonicUs() for the ffi-compat-tests build of Redis ae.c. */
+#include <time.h>
+
+#include "monotonic.h"
+
+monotime getMonotonicUs(void) {
+    struct timespec ts;
+    clock_gettime(CLOCK_MONOTONIC, &ts);
+    return ((uint64_t)ts.tv_sec) * 1000000 + ts.tv_nsec / 1000;
+}
@@ -0,0 +1,11 @@
+/* monotonic.h shim: CLOCK_MONOTONIC, without the TSC based clock. */
+#ifndef RAE_SHIM_MONOTONIC_H
+#define RAE_SHIM_MONOTONIC_H
+
+#include <stdint.h>
+
+typedef uint64_t monotime;
+
+monotime getMonotonicUs(void);
+
+#endif
@@ -0,0 +1,7 @@
+/* redisassert.h shim. */
+#ifndef RAE_SHIM_REDISASSERT_H
+#define RAE_SHIM_REDISASSERT_H
+
+#include <assert.h>
+
+#endif
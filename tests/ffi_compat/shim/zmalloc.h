@@ -0,0 +1,11 @@
+/* zmalloc.h shim for the ffi-compat-tests build of Redis ae.c. */
+#ifndef RAE_SHIM_ZMALLOC_H
+#define RAE_SHIM_ZMALLOC_H
+
+#include <stdlib.h>
+
+#define zmalloc malloc
+#define zrealloc realloc
+#define zfree free
+
+#endif
@@ -0,0 +1,536 @@
+/* FFI Compatibility Tests
+ *
+ * Run the same scenarios against rae and against the original Redis ae.c
+ * (built by build.rs with the ffi-compat-tests feature, see
+ * RAE_REDIS_SRC) and check that both produce the same observable trace:
+ * timer ordering, file event dispatch and AE_BARRIER semantics, set size
+ * handling. Both sides use the select backend.
+ *
+ *     RAE_REDIS_SRC=~/redis/src cargo test --features ffi-compat-tests \
+ *         --test ffi_compat_tests
+ */
+
+#![cfg(feature = "ffi-compat-tests")]
+
+use rae::{
+    AE_ALL_EVENTS, AE_BARRIER, AE_DONT_WAIT, AE_NOMORE, AE_READABLE, AE_TIME_EVENTS, AE_WRITABLE,
+    AeEventLoop,
+};
+use std::cell::{Cell, RefCell};
+use std::ffi::{c_int, c_longlong, c_void};
+use std::rc::Rc;
+use std::time::Duration;
+
+#[allow(non_camel_case_types)]
+mod redis {
+    use super::*;
+
+    #[repr(C)]
+    pub struct aeEventLoop {
+        _private: [u8; 0],
+    }
+
+    pub type aeFileProc = extern "C" fn(*mut aeEventLoop, c_int, *mut c_void, c_int);
+    pub type aeTimeProc = extern "C" fn(*mut aeEventLoop, c_longlong, *mut c_void) -> c_int;
+    pub type aeEventFinalizerProc = extern "C" fn(*mut aeEventLoop, *mut c_void);
+
+    unsafe extern "C" {
+        pub fn aeCreateEventLoop(setsize: c_int) -> *mut aeEventLoop;
+        pub fn aeDeleteEventLoop(event_loop: *mut aeEventLoop);
+        pub fn aeCreateFileEvent(
+            event_loop: *mut aeEventLoop,
+            fd: c_int,
+            mask: c_int,
+            proc_: aeFileProc,
+            client_data: *mut c_void,
+        ) -> c_int;
+        pub fn aeDeleteFileEvent(event_loop: *mut aeEventLoop, fd: c_int, mask: c_int);
+        pub fn aeGetFileEvents(event_loop: *mut aeEventLoop, fd: c_int) -> c_int;
+        pub fn aeCreateTimeEvent(
+            event_loop: *mut aeEventLoop,
+            milliseconds: c_longlong,
+            proc_: aeTimeProc,
+            client_data: *mut c_void,
+            finalizer_proc: Option<aeEventFinalizerProc>,
+        ) -> c_longlong;
+        pub fn aeDeleteTimeEvent(event_loop: *mut aeEventLoop, id: c_longlong) -> c_int;
+        pub fn aeProcessEvents(event_loop: *mut aeEventLoop, flags: c_int) -> c_int;
+        pub fn aeGetSetSize(event_loop: *mut aeEventLoop) -> c_int;
+        pub fn aeResizeSetSize(event_loop: *mut aeEventLoop, setsize: c_int) -> c_int;
+    }
+}
+
+type Trace = Rc<RefCell<Vec<String>>>;
+
+enum Action {
+    /* Timers: fire once. */
+    Once,
+    /* Timers: fire `left` more times, `ms` apart. */
+    Repeat { left: Cell<u32>, ms: i32 },
+    /* Timers: create `child` as a 0ms timer, then stop. */
+    Spawn { child: Box<Probe> },
+    /* File events: record the call. */
+    Record,
+    /* File events: record the call and delete the writable event. */
+    DropWritable,
+}
+
+/* client_data of every event: what to record, and what to do. */
+struct Probe {
+    name: &'static str,
+    trace: Trace,
+    action: Action,
+}
+
+impl Probe {
+    fn new(name: &'static str, trace: &Trace, action: Action) -> Box<Probe> {
+        Box::new(Probe {
+            name,
+            trace: trace.clone(),
+            action,
+        })
+    }
+
+    fn ptr(&self) -> *mut c_void {
+        self as *const Probe as *mut c_void
+    }
+
+    fn timer_fired(&self) -> i32 {
+        self.trace.borrow_mut().push(format!("timer {}", self.name));
+        match &self.action {
+            Action::Repeat { left, ms } if left.get() > 0 => {
+                left.set(left.get() - 1);
+                *ms
+            }
+            _ => AE_NOMORE,
+        }
+    }
+
+    fn file_fired(&self, side: &str, fd: i32, mask: i32) {
+        self.trace
+            .borrow_mut()
+            .push(format!("{side} {} mask={mask} fd={fd}", self.name));
+    }
+}
+
+fn probe<'a>(client_data: *mut c_void) -> &'a Probe {
+    unsafe { &*(client_data as *const Probe) }
+}
+
+/* The operations the scenarios need, implemented by both loops. Every
+ * read (write) registration uses the read (write) proc of the
+ * implementation, or the shared one with `shared`. */
+trait Loop {
+    const NAME: &'static str;
+    fn create(setsize: i32) -> Self;
+    fn add_timer(&mut self, ms: i64, probe: &Probe) -> i64;
+    fn delete_timer(&mut self, id: i64) -> i32;
+    fn add_file(&mut self, fd: i32, mask: i32, probe: &Probe, shared: bool) -> i32;
+    fn delete_file(&mut self, fd: i32, mask: i32);
+    fn file_events(&mut self, fd: i32) -> i32;
+    fn set_size(&mut self) -> i32;
+    fn resize(&mut self, setsize: i32) -> i32;
+    fn process(&mut self, flags: i32) -> i32;
+}
+
+struct Rae(Box<AeEventLoop>);
+
+fn rae_time_proc(event_loop: &mut AeEventLoop, _id: i64, client_data: *mut c_void) -> i32 {
+    let p = probe(client_data);
+    if let Action::Spawn { child } = &p.action {
+        rae::ae_create_time_event(event_loop, 0, rae_time_proc, child.ptr(), None);
+    }
+    p.timer_fired()
+}
+
+fn rae_read_proc(event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, mask: i32) {
+    let p = probe(client_data);
+    p.file_fired("read", fd, mask);
+    if let Action::DropWritable = p.action {
+        rae::ae_delete_file_event(event_loop, fd, AE_WRITABLE);
+    }
+}
+
+fn rae_write_proc(_event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, mask: i32) {
+    probe(client_data).file_fired("write", fd, mask);
+}
+
+fn rae_shared_proc(_event_loop: &mut AeEventLoop, fd: i32, client_data: *mut c_void, mask: i32) {
+    probe(client_data).file_fired("shared", fd, mask);
+}
+
+impl Loop for Rae {
+    const NAME: &'static str = "rae";
+
+    fn create(setsize: i32) -> Self {
+        Rae(rae::ae_create_event_loop(setsize).unwrap())
+    }
+
+    fn add_timer(&mut self, ms: i64, probe: &Probe) -> i64 {
+        rae::ae_create_time_event(&mut self.0, ms, rae_time_proc, probe.ptr(), None)
+    }
+
+    fn delete_timer(&mut self, id: i64) -> i32 {
+        rae::ae_delete_time_event(&mut self.0, id)
+    }
+
+    fn add_file(&mut self, fd: i32, mask: i32, probe: &Probe, shared: bool) -> i32 {
+        let proc = if shared {
+            rae_shared_proc
+        } else if mask & AE_READABLE != 0 {
+            rae_read_proc
+        } else {
+            rae_write_proc
+        };
+        rae::ae_create_file_event(&mut self.0, fd, mask, proc, probe.ptr())
+    }
+
+    fn delete_file(&mut self, fd: i32, mask: i32) {
+        rae::ae_delete_file_event(&mut self.0, fd, mask)
+    }
+
+    fn file_events(&mut self, fd: i32) -> i32 {
+        rae::ae_get_file_events(&self.0, fd)
+    }
+
+    fn set_size(&mut self) -> i32 {
+        rae::ae_get_set_size(&self.0)
+    }
+
+    fn resize(&mut self, setsize: i32) -> i32 {
+        rae::ae_resize_set_size(&mut self.0, setsize)
+    }
+
+    fn process(&mut self, flags: i32) -> i32 {
+        rae::ae_process_events(&mut self.0, flags)
+    }
+}
+
+struct Redis(*mut redis::aeEventLoop);
+
+extern "C" fn redis_time_proc(
+    event_loop: *mut redis::aeEventLoop,
+    _id: c_longlong,
+    client_data: *mut c_void,
+) -> c_int {
+    let p = probe(client_data);
+    if let Action::Spawn { child } = &p.action {
+        unsafe { redis::aeCreateTimeEvent(event_loop, 0, redis_time_proc, child.ptr(), None) };
+    }
+    p.timer_fired()
+}
+
+extern "C" fn redis_read_proc(
+    event_loop: *mut redis::aeEventLoop,
+    fd: c_int,
+    client_data: *mut c_void,
+    mask: c_int,
+) {
+    let p = probe(client_data);
+    p.file_fired("read", fd, mask);
+    if let Action::DropWritable = p.action {
+        unsafe { redis::aeDeleteFileEvent(event_loop, fd, AE_WRITABLE) };
+    }
+}
+
+extern "C" fn redis_write_proc(
+    _event_loop: *mut redis::aeEventLoop,
+    fd: c_int,
+    client_data: *mut c_void,
+    mask: c_int,
+) {
+    probe(client_data).file_fired("write", fd, mask);
+}
+
+extern "C" fn redis_shared_proc(
+    _event_loop: *mut redis::aeEventLoop,
+    fd: c_int,
+    client_data: *mut c_void,
+    mask: c_int,
+) {
+    probe(client_data).file_fired("shared", fd, mask);
+}
+
+impl Loop for Redis {
+    const NAME: &'static str = "redis";
+
+    fn create(setsize: i32) -> Self {
+        let event_loop = unsafe { redis::aeCreateEventLoop(setsize) };
+        assert!(!event_loop.is_null());
+        Redis(event_loop)
+    }
+
+    fn add_timer(&mut self, ms: i64, probe: &Probe) -> i64 {
+        unsafe { redis::aeCreateTimeEvent(self.0, ms, redis_time_proc, probe.ptr(), None) }
+    }
+
+    fn delete_timer(&mut self, id: i64) -> i32 {
+        unsafe { redis::aeDeleteTimeEvent(self.0, id) }
+    }
+
+    fn add_file(&mut self, fd: i32, mask: i32, probe: &Probe, shared: bool) -> i32 {
+        let proc = if shared {
+            redis_shared_proc
+        } else if mask & AE_READABLE != 0 {
+            redis_read_proc
+        } else {
+            redis_write_proc
+        };
+        unsafe { redis::aeCreateFileEvent(self.0, fd, mask, proc, probe.ptr()) }
+    }
+
+    fn delete_file(&mut self, fd: i32, mask: i32) {
+        unsafe { redis::aeDeleteFileEvent(self.0, fd, mask) }
+    }
+
+    fn file_events(&mut self, fd: i32) -> i32 {
+        unsafe { redis::aeGetFileEvents(self.0, fd) }
+    }
+
+    fn set_size(&mut self) -> i32 {
+        unsafe { redis::aeGetSetSize(self.0) }
+    }
+
+    fn resize(&mut self, setsize: i32) -> i32 {
+        unsafe { redis::aeResizeSetSize(self.0, setsize) }
+    }
+
+    fn process(&mut self, flags: i32) -> i32 {
+        unsafe { redis::aeProcessEvents(self.0, flags) }
+    }
+}
+
+impl Drop for Redis {
+    fn drop(&mut self) {
+        unsafe { redis::aeDeleteEventLoop(self.0) };
+    }
+}
+
+fn new_trace() -> Trace {
+    Rc::new(RefCell::new(Vec::new()))
+}
+
+fn note(trace: &Trace, line: String) {
+    trace.borrow_mut().push(line);
+}
+
+/* A socket pair whose first end is both readable and writable. */
+fn ready_socketpair() -> (i32, i32) {
+    let mut fds = [0; 2];
+    let ret = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) };
+    assert_eq!(ret, 0);
+    assert_eq!(
+        unsafe { libc::write(fds[1], b"x".as_ptr() as *const c_void, 1) },
+        1
+    );
+    (fds[0], fds[1])
+}
+
+fn close_pair(fds: (i32, i32)) {
+    unsafe {
+        libc::close(fds.0);
+        libc::close(fds.1);
+    }
+}
+
+mod scenarios {
+    use super::*;
+
+    /* Timers with deadlines 20ms apart fire in deadline order; timers already
+     * expired together fire in list order; a repeating timer is
+     * rescheduled; a timer created by a timer callback waits for the next
+     * iteration; a deleted timer never fires. */
+    pub fn timer_ordering<L: Loop>() -> Vec<String> {
+        let trace = new_trace();
+        let mut event_loop = L::create(64);
+
+        let probes = [
+            Probe::new("t120", &trace, Action::Once),
+            Probe::new("t40", &trace, Action::Once),
+            Probe::new("t80", &trace, Action::Once),
+            Probe::new("t0", &trace, Action::Once),
+            Probe::new(
+                "every40",
+                &trace,
+                Action::Repeat {
+                    left: Cell::new(2),
+                    ms: 40,
+                },
+            ),
+            Probe::new("deleted", &trace, Action::Once),
+        ];
+        for (probe, ms) in probes.iter().zip([120, 40, 80, 0, 20, 5]) {
+            let id = event_loop.add_timer(ms, probe);
+            if probe.name == "deleted" {
+                note(&trace, format!("delete {}", event_loop.delete_timer(id)));
+                note(
+                    &trace,
+                    format!("delete again {}", event_loop.delete_timer(id)),
+                );
+            }
+        }
+        while trace
+            .borrow()
+            .iter()
+            .filter(|l| l.starts_with("timer"))
+            .count()
+            < 7
+        {
+            event_loop.process(AE_ALL_EVENTS);
+        }
+
+        /* Same deadline reached before the loop looks at them. */
+        let batch = [
+            Probe::new("batch-a", &trace, Action::Once),
+            Probe::new("batch-b", &trace, Action::Once),
+            Probe::new(
+                "spawner",
+                &trace,
+                Action::Spawn {
+                    child: Probe::new("spawned", &trace, Action::Once),
+                },
+            ),
+            Probe::new("batch-c", &trace, Action::Once),
+        ];
+        for probe in &batch {
+            event_loop.add_timer(1, probe);
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        let processed = event_loop.process(AE_TIME_EVENTS | AE_DONT_WAIT);
+        note(&trace, format!("processed {processed}"));
+        let processed = event_loop.process(AE_TIME_EVENTS | AE_DONT_WAIT);
+        note(&trace, format!("processed {processed}"));
+
+        drop(event_loop);
+        trace.take()
+    }
+
+    /* Read before write by default, write before read with AE_BARRIER, a
+     * proc registered for both is called once, and a read handler
+     * deleting the writable event prevents the write call. */
+    pub fn barrier_semantics<L: Loop>() -> Vec<String> {
+        let trace = new_trace();
+        let mut event_loop = L::create(64);
+        let fds = ready_socketpair();
+        let fd = fds.0;
+        let (record, drop_writable) = (
+            Probe::new("p", &trace, Action::Record),
+            Probe::new("d", &trace, Action::DropWritable),
+        );
+
+        let cases: [(&str, i32, &Probe, bool); 4] = [
+            ("plain", 0, &record, false),
+            ("barrier", AE_BARRIER, &record, false),
+            ("shared", 0, &record, true),
+            ("drop-writable", 0, &drop_writable, false),
+        ];
+        for (name, extra, probe, shared) in cases {
+            note(&trace, format!("case {name}"));
+            if shared {
+                event_loop.add_file(fd, AE_READABLE | AE_WRITABLE | extra, probe, true);
+            } else {
+                event_loop.add_file(fd, AE_READABLE, probe, false);
+                event_loop.add_file(fd, AE_WRITABLE | extra, probe, false);
+            }
+            let processed = event_loop.process(AE_ALL_EVENTS | AE_DONT_WAIT);
+            note(
+                &trace,
+                format!("processed {processed} mask {}", event_loop.file_events(fd)),
+            );
+            event_loop.delete_file(fd, AE_READABLE | AE_WRITABLE);
+            note(
+                &trace,
+                format!("after delete {}", event_loop.file_events(fd)),
+            );
+        }
+
+        drop(event_loop);
+        close_pair(fds);
+        trace.take()
+    }
+
+    /* Registrations beyond the set size fail, shrinking below the highest
+     * registered fd fails, growing and shrinking around it works. */
+    pub fn resize_behavior<L: Loop>() -> Vec<String> {
+        let trace = new_trace();
+        let fds = ready_socketpair();
+        let record = Probe::new("p", &trace, Action::Record);
+        /* Size the loop just above the fd so the limits are reachable. */
+        let setsize = fds.0 + 2;
+        let mut event_loop = L::create(setsize);
+
+        let step = |name: &str, value: i32| note(&trace, format!("{name} {value}"));
+        step("setsize", event_loop.set_size());
+        step(
+            "add beyond",
+            event_loop.add_file(setsize, AE_READABLE, &record, false),
+        );
+        step(
+            "add",
+            event_loop.add_file(fds.0, AE_READABLE, &record, false),
+        );
+        step("shrink below fd", event_loop.resize(fds.0));
+        step("setsize", event_loop.set_size());
+        step("shrink above fd", event_loop.resize(fds.0 + 1));
+        step("same size", event_loop.resize(fds.0 + 1));
+        step("grow", event_loop.resize(setsize * 4));
+        step("setsize", event_loop.set_size());
+        step(
+            "add after grow",
+            event_loop.add_file(setsize + 1, AE_READABLE, &record, false),
+        );
+        event_loop.delete_file(setsize + 1, AE_READABLE);
+        step(
+            "processed",
+            event_loop.process(AE_ALL_EVENTS | AE_DONT_WAIT),
+        );
+        event_loop.delete_file(fds.0, AE_READABLE);
+        step("shrink after delete", event_loop.resize(1));
+
+        drop(event_loop);
+        close_pair(fds);
+        trace.take()
+    }
+}
+
+fn check(name: &str, rae_trace: Vec<String>, redis_trace: Vec<String>) {
+    assert!(!redis_trace.is_empty(), "{name}: empty trace");
+    assert_eq!(
+        rae_trace,
+        redis_trace,
+        "{name}: {} and {} disagree",
+        Rae::NAME,
+        Redis::NAME
+    );
+}
+
+mod compat {
+    use super::*;
+
+    #[test]
+    fn test_timer_ordering() {
+        check(
+            "timer_ordering",
+            scenarios::timer_ordering::<Rae>(),
+            scenarios::timer_ordering::<Redis>(),
+        );
+    }
+
+    #[test]
+    fn test_barrier_semantics() {
+        check(
+            "barrier_semantics",
+            scenarios::barrier_semantics::<Rae>(),
+            scenarios::barrier_semantics::<Redis>(),
+        );
+    }
+
+    #[test]
+    fn test_resize_behavior() {
+        check(
+            "resize_behavior",
+            scenarios::resize_behavior::<Rae>(),
+            scenarios::resize_behavior::<Redis>(),
+        );
+    }
+}
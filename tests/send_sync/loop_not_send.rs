@@ -0,0 +1,8 @@
+/* The loop hands raw client_data pointers to its callbacks and may own
+ * thread-bound backends: it must stay on the thread that created it. */
+
+fn assert_send<T: Send>() {}
+
+fn main() {
+    assert_send::<rae::AeEventLoop>();
+}
@@ -0,0 +1,7 @@
+/* Only the loop thread touches the loop, other threads use an AeHandle. */
+
+fn assert_sync<T: Sync>() {}
+
+fn main() {
+    assert_sync::<rae::AeEventLoop>();
+}
@@ -0,0 +1,7 @@
+/* A MockBackend and its control share unsynchronized state. */
+
+fn assert_send<T: Send>() {}
+
+fn main() {
+    assert_send::<rae::test_util::MockControl>();
+}
@@ -0,0 +1,10 @@
+/* Tasks posted through a handle run on the loop thread. */
+
+use std::rc::Rc;
+
+fn main() {
+    let mut event_loop = rae::ae_create_event_loop(64).unwrap();
+    let handle = rae::ae_get_handle(&mut event_loop).unwrap();
+    let shared = Rc::new(0);
+    handle.post(move |_| drop(shared));
+}